@@ -0,0 +1,32 @@
+// Share verification of a VSS round, one by one against the batch verification (run with "cargo bench").
+// Timings depend on the machine, so this is a benchmark and not a test.
+use std::time::{Duration, Instant};
+
+use core_fpi::{rnd_scalar, G};
+use core_fpi::shares::{Polynomial, RistrettoShare};
+
+const ROUNDS: u32 = 20;
+
+fn measure<F: Fn() -> bool>(run: F) -> Duration {
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        assert!(run());
+    }
+
+    start.elapsed() / ROUNDS
+}
+
+#[allow(non_snake_case)]
+fn main() {
+    for threshold in [1, 10, 33].iter() {
+        let parties = 3*threshold + 1;
+
+        let poly = Polynomial::rnd(rnd_scalar(), *threshold);
+        let S_poly = &poly * &G;
+        let S_shares: Vec<RistrettoShare> = poly.shares(parties).0.iter().map(|s| s * &G).collect();
+
+        let single = measure(|| S_shares.iter().all(|share| S_poly.verify(share)));
+        let batch = measure(|| S_poly.batch_verify(&S_shares));
+        println!("VERIFY (n = {}) - one by one: {:?}, batch: {:?}", parties, single, batch);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,90 @@
+use sha2::{Sha512, Digest};
+use chacha20poly1305::{XChaCha20Poly1305, Key, XNonce};
+use chacha20poly1305::aead::{Aead, KeyInit};
+use rand_os::OsRng;
+use rand_os::rand_core::RngCore;
+
+use crate::{Result, RistrettoPoint};
+
+const NONCE_SIZE: usize = 24;
+
+//-----------------------------------------------------------------------------------------------------------
+// Record data encryption (Ek[data] where H(y.Pe) = H(e.Y) = k)
+//-----------------------------------------------------------------------------------------------------------
+// Key of the record data from the shared point, SHA-512 truncated to 32 bytes. The point is y.Pe for the profile server
+// (with the encryption master-key share y) and e.Y for the recipient of the disclosed encryption key.
+pub fn derive_record_key(shared_point: &RistrettoPoint) -> [u8; 32] {
+    let hash = Sha512::new()
+        .chain("fpi:record-key")
+        .chain(shared_point.compress().as_bytes())
+        .result();
+
+    let mut key = [0u8; 32];
+    key.copy_from_slice(&hash[..32]);
+    key
+}
+
+// sealed format: <nonce><ciphertext + tag>, with a fresh random nonce for every record
+pub fn encrypt_record(key: &[u8; 32], plaintext: &[u8]) -> Result<Vec<u8>> {
+    let mut nonce = [0u8; NONCE_SIZE];
+    let mut csprng: OsRng = OsRng::new().map_err(|_| "Unable to initialize the random generator!")?;
+    csprng.fill_bytes(&mut nonce);
+
+    let cipher = XChaCha20Poly1305::new(Key::from_slice(key));
+    let encrypted = cipher.encrypt(XNonce::from_slice(&nonce), plaintext).map_err(|_| "Unable to encrypt the record data!")?;
+
+    let mut sealed = Vec::with_capacity(NONCE_SIZE + encrypted.len());
+    sealed.extend_from_slice(&nonce);
+    sealed.extend_from_slice(&encrypted);
+
+    Ok(sealed)
+}
+
+pub fn decrypt_record(key: &[u8; 32], sealed: &[u8]) -> Result<Vec<u8>> {
+    if sealed.len() < NONCE_SIZE {
+        return Err("Unable to decrypt the record data, not enough data!".into())
+    }
+
+    // the AEAD tag fails for an incorrect key or tampered data
+    let (nonce, encrypted) = sealed.split_at(NONCE_SIZE);
+    let cipher = XChaCha20Poly1305::new(Key::from_slice(key));
+    let plaintext = cipher.decrypt(XNonce::from_slice(nonce), encrypted).map_err(|_| "Unable to decrypt the record data, incorrect key?")?;
+
+    Ok(plaintext)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{G, rnd_scalar};
+
+    #[allow(non_snake_case)]
+    #[test]
+    fn test_record_key() {
+        // encryption master-key (y, Y) and the ephemeral key (e, Pe) of the record
+        let y = rnd_scalar();
+        let Y = y * G;
+        let e = rnd_scalar();
+        let Pe = e * G;
+
+        let k_server = derive_record_key(&(y * Pe));
+        let k_recipient = derive_record_key(&(e * Y));
+        assert!(k_server == k_recipient);
+        assert!(k_server != derive_record_key(&(rnd_scalar() * Y)));
+
+        let sealed = encrypt_record(&k_server, b"record data").unwrap();
+        assert!(sealed.len() == NONCE_SIZE + b"record data".len() + 16);
+        assert!(decrypt_record(&k_recipient, &sealed) == Ok(b"record data".to_vec()));
+
+        // fresh nonce for each encryption
+        assert!(encrypt_record(&k_server, b"record data").unwrap() != sealed);
+
+        let other = derive_record_key(&(rnd_scalar() * G));
+        assert!(decrypt_record(&other, &sealed) == Err("Unable to decrypt the record data, incorrect key?".into()));
+
+        let mut tampered = sealed.clone();
+        tampered[NONCE_SIZE] ^= 1;
+        assert!(decrypt_record(&k_recipient, &tampered) == Err("Unable to decrypt the record data, incorrect key?".into()));
+        assert!(decrypt_record(&k_recipient, &sealed[..10]) == Err("Unable to decrypt the record data, not enough data!".into()));
+    }
+}
\ No newline at end of file
@@ -1,2 +1,3 @@
+pub mod aead;
 pub mod shares;
 pub mod signatures;
\ No newline at end of file
@@ -1,12 +1,20 @@
 use std::fmt::{Debug, Formatter};
+use std::collections::HashSet;
 
 use core::ops::{Add, Mul, Sub};
 use rand_os::OsRng;
+use curve25519_dalek::traits::VartimeMultiscalarMul;
 use clear_on_drop::clear::Clear;
 
 use serde::{Serialize, Deserialize};
+use serde::de::{Deserializer, Error};
 
-use crate::{Scalar, RistrettoPoint, KeyEncoder};
+use crate::{G, Result, Scalar, RistrettoPoint, KeyEncoder};
+
+// Second generator for Pedersen's commitments, a hash-to-point with no known discrete log relative to G
+pub fn pedersen_h() -> RistrettoPoint {
+    RistrettoPoint::hash_from_bytes::<sha2::Sha512>(b"fpi:pedersen-h")
+}
 
 //-----------------------------------------------------------------------------------------------------------
 // Share
@@ -32,7 +40,7 @@ impl Debug for Share {
     }
 }
 
-impl<'a, 'b> Add<&'b Share> for &'a Share {
+impl<'b> Add<&'b Share> for &Share {
     type Output = Share;
     fn add(self, rhs: &'b Share) -> Share {
         assert!(self.i == rhs.i);
@@ -40,14 +48,14 @@ impl<'a, 'b> Add<&'b Share> for &'a Share {
     }
 }
 
-impl<'a, 'b> Add<&'b Scalar> for &'a Share {
+impl<'b> Add<&'b Scalar> for &Share {
     type Output = Share;
     fn add(self, rhs: &'b Scalar) -> Share {
         Share { i: self.i, yi: self.yi + rhs }
     }
 }
 
-impl<'a, 'b> Sub<&'b Share> for &'a Share {
+impl<'b> Sub<&'b Share> for &Share {
     type Output = Share;
     fn sub(self, rhs: &'b Share) -> Share {
         assert!(self.i == rhs.i);
@@ -55,21 +63,21 @@ impl<'a, 'b> Sub<&'b Share> for &'a Share {
     }
 }
 
-impl<'a, 'b> Sub<&'b Scalar> for &'a Share {
+impl<'b> Sub<&'b Scalar> for &Share {
     type Output = Share;
     fn sub(self, rhs: &'b Scalar) -> Share {
         Share { i: self.i, yi: self.yi - rhs }
     }
 }
 
-impl<'a, 'b> Mul<&'b Scalar> for &'a Share {
+impl<'b> Mul<&'b Scalar> for &Share {
     type Output = Share;
     fn mul(self, rhs: &'b Scalar) -> Share {
         Share { i: self.i, yi: self.yi * rhs }
     }
 }
 
-impl<'a, 'b> Mul<&'b RistrettoPoint> for &'a Share {
+impl<'b> Mul<&'b RistrettoPoint> for &Share {
     type Output = RistrettoShare;
     fn mul(self, rhs: &'b RistrettoPoint) -> RistrettoShare {
         RistrettoShare { i: self.i, Yi: self.yi * rhs }
@@ -109,21 +117,21 @@ impl Debug for RistrettoShare {
     }
 }
 
-impl<'a, 'b> Add<&'b RistrettoPoint> for &'a RistrettoShare {
+impl<'b> Add<&'b RistrettoPoint> for &RistrettoShare {
     type Output = RistrettoShare;
     fn add(self, rhs: &'b RistrettoPoint) -> RistrettoShare {
         RistrettoShare { i: self.i, Yi: self.Yi + rhs }
     }
 }
 
-impl<'a, 'b> Sub<&'b RistrettoPoint> for &'a RistrettoShare {
+impl<'b> Sub<&'b RistrettoPoint> for &RistrettoShare {
     type Output = RistrettoShare;
     fn sub(self, rhs: &'b RistrettoPoint) -> RistrettoShare {
         RistrettoShare { i: self.i, Yi: self.Yi - rhs }
     }
 }
 
-impl<'a, 'b> Mul<&'b Scalar> for &'a RistrettoShare {
+impl<'b> Mul<&'b Scalar> for &RistrettoShare {
     type Output = RistrettoShare;
     fn mul(self, rhs: &'b Scalar) -> RistrettoShare {
         RistrettoShare { i: self.i, Yi: self.Yi * rhs }
@@ -135,8 +143,9 @@ impl<'a, 'b> Mul<&'b Scalar> for &'a RistrettoShare {
 // Shared traits and functions for Polynomial and RistrettoPolynomial
 //-----------------------------------------------------------------------------------------------------------
 fn cut_tail<Z>(v: &mut Vec::<Z>, elm: Z) where Z: Eq {
-    if let Some(i) = v.iter().rev().rposition(|x| *x == elm) {
-        v.truncate(i);
+    // remove trailing elements, keeping at least the constant term
+    while v.len() > 1 && v.last() == Some(&elm) {
+        v.pop();
     }
 }
 
@@ -151,17 +160,67 @@ fn short_mul(a: &mut Vec::<Scalar>, b: Scalar) {
     a.push(Scalar::one());
 }
 
-fn lx_num_bar(range: &[Scalar], i: usize) -> (Vec<Scalar>, Scalar) {
-    let mut num = vec![Scalar::one()];
-    let mut denum = Scalar::one();
-    for j in 0..range.len() {
-        if j != i {
-            short_mul(&mut num, -range[j]);
-            denum *= range[i] - range[j];
+// Barycentric weights 1 / prod(x_i - x_j) of all the Lagrange basis, with a single (Montgomery batch) inversion of the denominators
+fn barycentrics(range: &[Scalar]) -> Vec<Scalar> {
+    let mut denums = Vec::<Scalar>::with_capacity(range.len());
+    for i in 0..range.len() {
+        let mut denum = Scalar::one();
+        for j in 0..range.len() {
+            if j != i {
+                denum *= range[i] - range[j];
+            }
+        }
+
+        denums.push(denum);
+    }
+
+    Scalar::batch_invert(&mut denums);
+    denums
+}
+
+// Numerators and barycentric weights of all the Lagrange basis
+fn lx_num_bars(range: &[Scalar]) -> Vec<(Vec<Scalar>, Scalar)> {
+    let mut nums = Vec::<Vec<Scalar>>::with_capacity(range.len());
+    for i in 0..range.len() {
+        let mut num = vec![Scalar::one()];
+        for j in 0..range.len() {
+            if j != i {
+                short_mul(&mut num, -range[j]);
+            }
         }
+
+        nums.push(num);
     }
 
-    (num, denum.invert())
+    nums.into_iter().zip(barycentrics(range)).collect()
+}
+
+// All the Lagrange basis l_i(x) at a single x, in the direct product form
+fn lx_weights(range: &[Scalar], x: &Scalar) -> Vec<Scalar> {
+    let mut nums = Vec::<Scalar>::with_capacity(range.len());
+    for i in 0..range.len() {
+        let mut num = Scalar::one();
+        for j in 0..range.len() {
+            if j != i {
+                num *= x - range[j];
+            }
+        }
+
+        nums.push(num);
+    }
+
+    nums.iter().zip(barycentrics(range).iter()).map(|(num, bar)| num * bar).collect()
+}
+
+// The secret is at x = 0, a share with index 0 would collide with it and corrupt the Lagrange weights.
+// Duplicated indices have no inverse for the Lagrange denominators.
+fn range(indices: impl Iterator<Item=u32>) -> Result<Vec<Scalar>> {
+    let mut unique = HashSet::<u32>::new();
+    indices.map(|i| match i {
+        0 => Err("Invalid share index, 0 is reserved for the secret!".into()),
+        _ if !unique.insert(i) => Err("Duplicated share index!".into()),
+        _ => Ok(Scalar::from(i))
+    }).collect()
 }
 
 pub trait Interpolate<S> {
@@ -186,11 +245,17 @@ pub trait Degree {
 //-----------------------------------------------------------------------------------------------------------
 // Polynomial
 //-----------------------------------------------------------------------------------------------------------
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct Polynomial {
+#[derive(Deserialize)]
+struct SerializedPolynomial {
     pub a: Vec<Scalar>
 }
 
+// The coefficients are private, a polynomial is only built with at least one coefficient.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Polynomial {
+    a: Vec<Scalar>
+}
+
 impl Drop for Polynomial {
     fn drop(&mut self) {
         for item in self.a.iter_mut() {
@@ -199,7 +264,29 @@ impl Drop for Polynomial {
     }
 }
 
-impl<'a, 'b> Mul<&'b Scalar> for &'a Polynomial {
+// degree expects at least one coefficient
+impl<'de> Deserialize<'de> for Polynomial {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error> where D: Deserializer<'de> {
+        let sp = SerializedPolynomial::deserialize(deserializer)?;
+        Self::from_coefficients(sp.a).map_err(Error::custom)
+    }
+}
+
+impl Add<&Polynomial> for &Polynomial {
+    type Output = Polynomial;
+    fn add(self, rhs: &Polynomial) -> Polynomial {
+        Polynomial::combine(self, rhs, |x, y| x + y)
+    }
+}
+
+impl Sub<&Polynomial> for &Polynomial {
+    type Output = Polynomial;
+    fn sub(self, rhs: &Polynomial) -> Polynomial {
+        Polynomial::combine(self, rhs, |x, y| x - y)
+    }
+}
+
+impl<'b> Mul<&'b Scalar> for &Polynomial {
     type Output = Polynomial;
     fn mul(self, rhs: &'b Scalar) -> Polynomial {
         Polynomial {
@@ -208,7 +295,7 @@ impl<'a, 'b> Mul<&'b Scalar> for &'a Polynomial {
     }
 }
 
-impl<'a, 'b> Mul<&'b RistrettoPoint> for &'a Polynomial {
+impl<'b> Mul<&'b RistrettoPoint> for &Polynomial {
     type Output = RistrettoPolynomial;
     fn mul(self, rhs: &'b RistrettoPoint) -> RistrettoPolynomial {
         RistrettoPolynomial {
@@ -218,6 +305,23 @@ impl<'a, 'b> Mul<&'b RistrettoPoint> for &'a Polynomial {
 }
 
 impl Polynomial {
+    // coefficients in ascending order (a_0 is the secret)
+    pub fn from_coefficients(a: Vec<Scalar>) -> Result<Self> {
+        if a.is_empty() {
+            return Err("Polynomial without coefficients!".into())
+        }
+
+        Ok(Self { a })
+    }
+
+    pub fn coefficients(&self) -> &[Scalar] {
+        &self.a
+    }
+
+    pub fn zero() -> Self {
+        Polynomial { a: vec![Scalar::zero()] }
+    }
+
     pub fn rnd(mut secret: Scalar, degree: usize) -> Self {
         let mut coefs = vec![secret];
 
@@ -244,6 +348,11 @@ impl Polynomial {
         num * denum.invert()
     }
 
+    // all the weights l_i(0) of the range, with a single (Montgomery batch) inversion
+    pub fn l_weights(range: &[Scalar]) -> Vec<Scalar> {
+        lx_weights(range, &Scalar::zero())
+    }
+
     pub fn shares(&self, n: usize) -> ShareVector {
         let mut shares = Vec::<Share>::with_capacity(n);
         for j in 1..=n {
@@ -254,57 +363,75 @@ impl Polynomial {
 
         ShareVector(shares)
     }
+
+    // interpolated value at any x, without reconstructing all the coefficients
+    pub fn interpolate_at(shares: &[Share], x: &Scalar) -> Result<Scalar> {
+        let range = range(shares.iter().map(|s| s.i))?;
+
+        let mut acc = Scalar::zero();
+        for (l_i, item) in lx_weights(&range, x).iter().zip(shares.iter()) {
+            acc += l_i * item.yi;
+        }
+
+        Ok(acc)
+    }
+
+    // coefficient-wise operation, zero-padding the shorter polynomial
+    fn combine(left: &Polynomial, right: &Polynomial, op: impl Fn(Scalar, Scalar) -> Scalar) -> Polynomial {
+        let size = left.a.len().max(right.a.len());
+        let coef = |p: &Polynomial, k: usize| p.a.get(k).cloned().unwrap_or_else(Scalar::zero);
+
+        let mut a: Vec<Scalar> = (0..size).map(|k| op(coef(left, k), coef(right, k))).collect();
+        cut_tail(&mut a, Scalar::zero());
+        Polynomial { a }
+    }
 }
 
 impl Evaluate for Polynomial {
     type Output = Scalar;
     
     fn evaluate(&self, x: &Scalar) -> Scalar {
-        // evaluate using Horner's rule
-        let mut rev = self.a.iter().rev();
-        let head = *rev.next().unwrap();
-            
-        rev.fold(head, |partial, coef| partial * x + coef)
+        // evaluate using Horner's rule, an empty polynomial evaluates to zero
+        self.a.iter().rev().fold(Scalar::zero(), |partial, coef| partial * x + coef)
     }
 }
 
 impl Interpolate<Share> for Polynomial {
-    type Output = Scalar;
+    type Output = Result<Scalar>;
     
-    fn interpolate(shares: &[Share]) -> Scalar {
-        let range = shares.iter().map(|s| Scalar::from(s.i)).collect::<Vec<_>>();
+    fn interpolate(shares: &[Share]) -> Result<Scalar> {
+        let range = range(shares.iter().map(|s| s.i))?;
 
         let mut acc = Scalar::zero();
-        for (i, item) in shares.iter().enumerate() {
-            acc += Polynomial::l_i(&range, i) * item.yi;
+        for (l_i, item) in Polynomial::l_weights(&range).iter().zip(shares.iter()) {
+            acc += l_i * item.yi;
         }
 
-        acc
+        Ok(acc)
     }
 }
 
 impl Reconstruct<Share> for Polynomial {
-    type Output = Polynomial;
+    type Output = Result<Polynomial>;
 
-    fn reconstruct(shares: &[Share]) -> Polynomial {
-        let range = shares.iter().map(|s| Scalar::from(s.i)).collect::<Vec<_>>();
+    fn reconstruct(shares: &[Share]) -> Result<Polynomial> {
+        let range = range(shares.iter().map(|s| s.i))?;
 
         let mut acc = vec![Scalar::zero(); range.len()];
-        for (i, item) in shares.iter().enumerate() {
-            let (num, barycentric) = lx_num_bar(&range, i);
+        for ((num, barycentric), item) in lx_num_bars(&range).iter().zip(shares.iter()) {
             for j in 0..num.len() {
                 acc[j] += num[j] * barycentric * item.yi;
             }
         }
 
         cut_tail(&mut acc, Scalar::zero());
-        Polynomial { a: acc }
+        Ok(Polynomial { a: acc })
     }
 }
 
 impl Degree for Polynomial {
     fn degree(&self) -> usize {
-        self.a.len() - 1
+        self.a.len().saturating_sub(1)
     }
 }
 
@@ -312,11 +439,25 @@ impl Degree for Polynomial {
 // RistrettoPolynomial
 //-----------------------------------------------------------------------------------------------------------
 #[allow(non_snake_case)]
-#[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
-pub struct RistrettoPolynomial {
+#[derive(Deserialize)]
+struct SerializedRistrettoPolynomial {
     pub A: Vec<RistrettoPoint>
 }
 
+#[allow(non_snake_case)]
+#[derive(Serialize, Clone, PartialEq, Eq)]
+pub struct RistrettoPolynomial {
+    A: Vec<RistrettoPoint>
+}
+
+// degree expects at least one coefficient, as produced by Polynomial::rnd
+impl<'de> Deserialize<'de> for RistrettoPolynomial {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error> where D: Deserializer<'de> {
+        let sp = SerializedRistrettoPolynomial::deserialize(deserializer)?;
+        Self::from_coefficients(sp.A).map_err(Error::custom)
+    }
+}
+
 impl Debug for RistrettoPolynomial {
     fn fmt(&self, fmt: &mut Formatter<'_>) -> std::fmt::Result {
         let poly: Vec<String> = self.A.iter().map(|p| p.compress().encode()).collect();
@@ -326,7 +467,7 @@ impl Debug for RistrettoPolynomial {
     }
 }
 
-impl<'a, 'b> Mul<&'b Scalar> for &'a RistrettoPolynomial {
+impl<'b> Mul<&'b Scalar> for &RistrettoPolynomial {
     type Output = RistrettoPolynomial;
 
     #[allow(non_snake_case)]
@@ -338,63 +479,134 @@ impl<'a, 'b> Mul<&'b Scalar> for &'a RistrettoPolynomial {
 }
 
 impl RistrettoPolynomial {
+    // commitments in ascending order (A_0 is the public secret)
+    #[allow(non_snake_case)]
+    pub fn from_coefficients(A: Vec<RistrettoPoint>) -> Result<Self> {
+        if A.is_empty() {
+            return Err("Polynomial without coefficients!".into())
+        }
+
+        Ok(Self { A })
+    }
+
+    pub fn coefficients(&self) -> &[RistrettoPoint] {
+        &self.A
+    }
+
+    // interpolated value at any x, without reconstructing all the coefficients
+    #[allow(non_snake_case)]
+    pub fn interpolate_at(shares: &[RistrettoShare], x: &Scalar) -> Result<RistrettoPoint> {
+        let range = range(shares.iter().map(|s| s.i))?;
+
+        let mut acc = RistrettoPoint::default();
+        for (l_i, item) in lx_weights(&range, x).iter().zip(shares.iter()) {
+            acc += l_i * item.Yi;
+        }
+
+        Ok(acc)
+    }
+
     pub fn verify(&self, share: &RistrettoShare) -> bool {
         let x = Scalar::from(u64::from(share.i));
         share.Yi == self.evaluate(&x)
     }
+
+    // Verify all shares with a random linear combination (r_i): sum(r_i * Y_i) == sum_k(sum_i(r_i * x_i^k) * A_k).
+    // A single multi-scalar multiplication instead of one polynomial evaluation per share, an invalid share passes with negligible probability.
+    pub fn batch_verify(&self, shares: &[RistrettoShare]) -> bool {
+        let mut csprng: OsRng = OsRng::new().unwrap();
+
+        let mut coefs = vec![Scalar::zero(); self.A.len()];
+        let mut weights = Vec::<Scalar>::with_capacity(shares.len());
+        for share in shares.iter() {
+            let r = Scalar::random(&mut csprng);
+            let x = Scalar::from(u64::from(share.i));
+
+            let mut rxk = r;
+            for coef in coefs.iter_mut() {
+                *coef += rxk;
+                rxk *= x;
+            }
+
+            weights.push(-r);
+        }
+
+        // shares and commitments are public, variable time is fine
+        let scalars = coefs.iter().chain(weights.iter());
+        let points = self.A.iter().chain(shares.iter().map(|share| &share.Yi));
+        RistrettoPoint::vartime_multiscalar_mul(scalars, points) == RistrettoPoint::default()
+    }
+
+    // Pedersen's commitments (a_k * G + b_k * H) of the secret polynomial (ak) and the companion blinding polynomial (bk)
+    #[allow(non_snake_case)]
+    pub fn pedersen(ak: &Polynomial, bk: &Polynomial) -> Result<Self> {
+        if ak.a.len() != bk.a.len() {
+            return Err("Blinding polynomial with a different degree!".into())
+        }
+
+        let H = pedersen_h();
+        let A = ak.a.iter().zip(bk.a.iter()).map(|(a_k, b_k)| a_k * G + b_k * H).collect();
+        Ok(Self { A })
+    }
+
+    // the share (y_i * G) and the blinding share (z_i * H) must sum to the committed point
+    pub fn verify_pedersen(&self, share: &RistrettoShare, blinding: &RistrettoShare) -> bool {
+        if share.i != blinding.i {
+            return false
+        }
+
+        let x = Scalar::from(u64::from(share.i));
+        share.Yi + blinding.Yi == self.evaluate(&x)
+    }
 }
 
 impl Evaluate for RistrettoPolynomial {
     type Output = RistrettoPoint;
     
     fn evaluate(&self, x: &Scalar) -> RistrettoPoint {
-        // evaluate using Horner's rule
-        let mut rev = self.A.iter().rev();
-        let head = *rev.next().unwrap();
-            
-        rev.fold(head, |partial, coef| partial * x + coef)
+        // evaluate using Horner's rule, an empty polynomial evaluates to the identity
+        self.A.iter().rev().fold(RistrettoPoint::default(), |partial, coef| partial * x + coef)
     }
 }
 
 impl Interpolate<RistrettoShare> for RistrettoPolynomial {
-    type Output = RistrettoPoint;
+    type Output = Result<RistrettoPoint>;
 
     #[allow(non_snake_case)]
-    fn interpolate(shares: &[RistrettoShare]) -> RistrettoPoint {
-        let range = shares.iter().map(|s| Scalar::from(s.i)).collect::<Vec<_>>();
+    fn interpolate(shares: &[RistrettoShare]) -> Result<RistrettoPoint> {
+        let range = range(shares.iter().map(|s| s.i))?;
 
         let mut acc = RistrettoPoint::default();
-        for (i, item) in shares.iter().enumerate() {
-            acc += Polynomial::l_i(&range, i) * item.Yi;
+        for (l_i, item) in Polynomial::l_weights(&range).iter().zip(shares.iter()) {
+            acc += l_i * item.Yi;
         }
 
-        acc
+        Ok(acc)
     }
 }
 
 impl Reconstruct<RistrettoShare> for RistrettoPolynomial {
-    type Output = RistrettoPolynomial;
+    type Output = Result<RistrettoPolynomial>;
 
     #[allow(non_snake_case)]
-    fn reconstruct(shares: &[RistrettoShare]) -> RistrettoPolynomial {
-        let range = shares.iter().map(|s| Scalar::from(s.i)).collect::<Vec<_>>();
+    fn reconstruct(shares: &[RistrettoShare]) -> Result<RistrettoPolynomial> {
+        let range = range(shares.iter().map(|s| s.i))?;
 
         let mut acc = vec![RistrettoPoint::default(); range.len()];
-        for (i, item) in shares.iter().enumerate() {
-            let (num, barycentric) = lx_num_bar(&range, i);
+        for ((num, barycentric), item) in lx_num_bars(&range).iter().zip(shares.iter()) {
             for j in 0..num.len() {
                 acc[j] += num[j] * barycentric * item.Yi;
             }
         }
 
         cut_tail(&mut acc, RistrettoPoint::default());
-        RistrettoPolynomial { A: acc }
+        Ok(RistrettoPolynomial { A: acc })
     }
 }
 
 impl Degree for RistrettoPolynomial {
     fn degree(&self) -> usize {
-        self.A.len() - 1
+        self.A.len().saturating_sub(1)
     }
 }
 
@@ -420,10 +632,239 @@ mod tests {
         let shares = poly.shares(parties);
         let S_shares = shares.0.iter().map(|s| s * &G).collect::<Vec<_>>();
 
-        let r_poly = Polynomial::reconstruct(&shares.0[0..2*threshold + 1]);
+        let r_poly = Polynomial::reconstruct(&shares.0[0..2*threshold + 1]).unwrap();
         assert!(poly == r_poly);
 
-        let S_r_poly = RistrettoPolynomial::reconstruct(&S_shares[0..2*threshold + 1]);
+        let S_r_poly = RistrettoPolynomial::reconstruct(&S_shares[0..2*threshold + 1]).unwrap();
         assert!(S_poly == S_r_poly);
     }
+
+    #[allow(non_snake_case)]
+    #[test]
+    fn test_empty_commit() {
+        let empty = RistrettoPolynomial { A: Vec::new() };
+        let data = bincode::serialize(&empty).unwrap();
+        assert!(bincode::deserialize::<RistrettoPolynomial>(&data).is_err());
+
+        let S_poly = &Polynomial::rnd(rnd_scalar(), 2) * &G;
+        let data = bincode::serialize(&S_poly).unwrap();
+        assert!(bincode::deserialize::<RistrettoPolynomial>(&data).unwrap() == S_poly);
+    }
+
+    #[allow(non_snake_case)]
+    #[test]
+    fn test_from_coefficients() {
+        assert!(Polynomial::from_coefficients(Vec::new()) == Err("Polynomial without coefficients!".into()));
+        assert!(RistrettoPolynomial::from_coefficients(Vec::new()) == Err("Polynomial without coefficients!".into()));
+
+        let poly = Polynomial::rnd(rnd_scalar(), 2);
+        let built = Polynomial::from_coefficients(poly.a.clone()).unwrap();
+        assert!(built == poly && built.degree() == 2);
+
+        let S_poly = RistrettoPolynomial::from_coefficients(poly.a.iter().map(|ak| ak * G).collect()).unwrap();
+        assert!(S_poly == &poly * &G);
+
+        let shares = poly.shares(3);
+        for share in shares.0.iter() {
+            assert!(S_poly.verify(&(share * &G)));
+        }
+
+        // no panic on the (module private) struct literals without coefficients
+        assert!(Polynomial { a: Vec::new() }.evaluate(&rnd_scalar()) == Scalar::zero());
+        assert!(RistrettoPolynomial { A: Vec::new() }.evaluate(&rnd_scalar()) == RistrettoPoint::default());
+        assert!(Polynomial { a: Vec::new() }.degree() == 0 && RistrettoPolynomial { A: Vec::new() }.degree() == 0);
+    }
+
+    #[allow(non_snake_case)]
+    #[test]
+    fn test_zero_index() {
+        let threshold = 2;
+        let parties = 3*threshold + 1;
+
+        let poly = Polynomial::rnd(rnd_scalar(), threshold);
+        let mut shares = poly.shares(parties).0[0..threshold + 1].to_vec();
+        let mut S_shares = shares.iter().map(|s| s * &G).collect::<Vec<_>>();
+        assert!(Polynomial::interpolate(&shares) == Ok(poly.a[0]));
+
+        // a share injected at the evaluation point of the secret
+        shares[0].i = 0;
+        S_shares[0].i = 0;
+
+        let err = Some("Invalid share index, 0 is reserved for the secret!".to_string());
+        assert!(Polynomial::interpolate(&shares).err() == err);
+        assert!(Polynomial::reconstruct(&shares).err() == err);
+        assert!(RistrettoPolynomial::interpolate(&S_shares).err() == err);
+        assert!(RistrettoPolynomial::reconstruct(&S_shares).err() == err);
+    }
+
+    #[test]
+    fn test_batch_inversion() {
+        let range: Vec<Scalar> = [1u32, 3, 4, 7, 9, 12, 16].iter().map(|i| Scalar::from(*i)).collect();
+
+        let weights = Polynomial::l_weights(&range);
+        for (i, l_i) in weights.iter().enumerate() {
+            assert!(*l_i == Polynomial::l_i(&range, i));
+        }
+
+        for (i, (_, barycentric)) in lx_num_bars(&range).iter().enumerate() {
+            let denum = range.iter().enumerate().filter(|(j, _)| *j != i).fold(Scalar::one(), |acc, (_, x_j)| acc * (range[i] - x_j));
+            assert!(*barycentric == denum.invert());
+        }
+
+        // l_i(x_k) is 1 only for i = k
+        for (k, x_k) in range.iter().enumerate() {
+            for (i, l_i) in lx_weights(&range, x_k).iter().enumerate() {
+                assert!(*l_i == if i == k { Scalar::one() } else { Scalar::zero() });
+            }
+        }
+
+        // duplicated indices have no inverse
+        let poly = Polynomial::rnd(rnd_scalar(), 2);
+        let mut shares = poly.shares(3).0.to_vec();
+        shares[2].i = 1;
+        assert!(Polynomial::interpolate(&shares).err() == Some("Duplicated share index!".into()));
+        assert!(Polynomial::reconstruct(&shares).err() == Some("Duplicated share index!".into()));
+    }
+
+    #[allow(non_snake_case)]
+    #[test]
+    fn test_share_sub() {
+        let a = Share { i: 3, yi: rnd_scalar() };
+        let b = Share { i: 3, yi: rnd_scalar() };
+        let scalar = rnd_scalar();
+
+        let a_b = &a - &b;
+        assert!(a_b.i == 3 && a_b.yi == a.yi - b.yi);
+        assert!((&a_b + &b).yi == a.yi);
+
+        let a_s = &a - &scalar;
+        assert!(a_s.i == 3 && a_s.yi == a.yi - scalar);
+        assert!((&a_s + &scalar).yi == a.yi);
+
+        // consistent with the RistrettoShare subtraction
+        let A_s = &(&a * &G) - &(scalar * G);
+        assert!(A_s.i == 3 && A_s.Yi == (&a_s * &G).Yi);
+        assert!((&(&a * &G) - &(b.yi * G)).Yi == (&a_b * &G).Yi);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_share_sub_index() {
+        let a = Share { i: 1, yi: rnd_scalar() };
+        let b = Share { i: 2, yi: rnd_scalar() };
+        let _ = &a - &b;
+    }
+
+    #[test]
+    fn test_polynomial_ops() {
+        let degree = || 1 + (rnd_scalar().to_bytes()[0] % 8) as usize;
+        let p1 = Polynomial::rnd(rnd_scalar(), degree());
+        let p2 = Polynomial::rnd(rnd_scalar(), degree());
+
+        let sum = &p1 + &p2;
+        let diff = &p1 - &p2;
+        assert!(sum.degree() <= p1.degree().max(p2.degree()));
+        for _ in 0..5 {
+            let x = rnd_scalar();
+            assert!(sum.evaluate(&x) == p1.evaluate(&x) + p2.evaluate(&x));
+            assert!(diff.evaluate(&x) == p1.evaluate(&x) - p2.evaluate(&x));
+        }
+
+        // combine the secrets of reconstructed polynomials
+        let r1 = Polynomial::reconstruct(&p1.shares(p1.degree() + 1).0).unwrap();
+        let r2 = Polynomial::reconstruct(&p2.shares(p2.degree() + 1).0).unwrap();
+        assert!((&r1 + &r2).a[0] == p1.a[0] + p2.a[0]);
+
+        // the tail is removed
+        assert!(&p1 - &p1 == Polynomial::zero());
+        assert!(&p1 + &Polynomial::zero() == p1);
+        assert!(Polynomial::zero().degree() == 0);
+
+        // serialization round-trip
+        let data = bincode::serialize(&sum).unwrap();
+        assert!(bincode::deserialize::<Polynomial>(&data).unwrap() == sum);
+
+        let empty = bincode::serialize(&Vec::<Scalar>::new()).unwrap();
+        assert!(bincode::deserialize::<Polynomial>(&empty).is_err());
+    }
+
+    #[allow(non_snake_case)]
+    #[test]
+    fn test_interpolate_at() {
+        let threshold = 3;
+        let parties = 3*threshold + 1;
+
+        let poly = Polynomial::rnd(rnd_scalar(), threshold);
+        let S_poly = &poly * &G;
+        let shares = poly.shares(parties).0.to_vec();
+        let S_shares = shares.iter().map(|s| s * &G).collect::<Vec<_>>();
+
+        let x = Scalar::from(5u64);
+        assert!(Polynomial::interpolate_at(&shares[0..threshold + 1], &x) == Ok(poly.evaluate(&x)));
+        assert!(RistrettoPolynomial::interpolate_at(&S_shares[2..threshold + 3], &x) == Ok(S_poly.evaluate(&x)));
+
+        // at zero is the same as interpolate, and at a random point with all shares
+        assert!(Polynomial::interpolate_at(&shares, &Scalar::zero()) == Polynomial::interpolate(&shares));
+        assert!(RistrettoPolynomial::interpolate_at(&S_shares, &Scalar::zero()) == RistrettoPolynomial::interpolate(&S_shares));
+
+        let x = rnd_scalar();
+        assert!(Polynomial::interpolate_at(&shares[1..], &x) == Ok(poly.evaluate(&x)));
+        assert!(RistrettoPolynomial::interpolate_at(&S_shares[1..], &x) == Ok(S_poly.evaluate(&x)));
+
+        // not enough shares
+        assert!(Polynomial::interpolate_at(&shares[0..threshold], &x) != Ok(poly.evaluate(&x)));
+
+        let mut shares = shares;
+        shares[0].i = 0;
+        assert!(Polynomial::interpolate_at(&shares, &x).err() == Some("Invalid share index, 0 is reserved for the secret!".into()));
+    }
+
+    #[allow(non_snake_case)]
+    #[test]
+    fn test_pedersen() {
+        let threshold = 2;
+        let parties = 3*threshold + 1;
+        let H = pedersen_h();
+
+        let poly = Polynomial::rnd(rnd_scalar(), threshold);
+        let blinding = Polynomial::rnd(rnd_scalar(), threshold);
+        let C_poly = RistrettoPolynomial::pedersen(&poly, &blinding).unwrap();
+
+        let shares = poly.shares(parties).0.to_vec();
+        let b_shares = blinding.shares(parties).0.to_vec();
+        for (share, b_share) in shares.iter().zip(b_shares.iter()) {
+            assert!(C_poly.verify_pedersen(&(share * &G), &(b_share * &H)));
+            assert!(!C_poly.verify(&(share * &G)));
+        }
+
+        // mismatched index and blinding share
+        assert!(!C_poly.verify_pedersen(&(&shares[0] * &G), &(&b_shares[1] * &H)));
+        assert!(!C_poly.verify_pedersen(&(&shares[0] * &G), &(&b_shares[0] * &G)));
+
+        let other = Polynomial::rnd(rnd_scalar(), threshold + 1);
+        assert!(RistrettoPolynomial::pedersen(&poly, &other).err() == Some("Blinding polynomial with a different degree!".into()));
+    }
+
+    #[allow(non_snake_case)]
+    #[test]
+    fn test_batch_verify() {
+        let threshold = 4;
+        let parties = 3*threshold + 1;
+
+        let poly = Polynomial::rnd(rnd_scalar(), threshold);
+        let S_poly = &poly * &G;
+        let mut S_shares = poly.shares(parties).0.iter().map(|s| s * &G).collect::<Vec<_>>();
+        assert!(S_poly.batch_verify(&S_shares));
+        assert!(S_poly.batch_verify(&S_shares[3..5]));
+        assert!(S_poly.batch_verify(&[]));
+
+        // a single invalid share fails the batch
+        S_shares[7].Yi += G;
+        assert!(!S_poly.batch_verify(&S_shares));
+        assert!(S_poly.batch_verify(&S_shares[..7]));
+
+        S_shares[7].Yi -= G;
+        S_shares[2].i += 1;
+        assert!(!S_poly.batch_verify(&S_shares));
+    }
 }
\ No newline at end of file
@@ -7,25 +7,39 @@ use serde::ser::Serializer;
 use serde::de::{Deserializer, Error};
 
 use sha2::{Sha512, Digest};
+use curve25519_dalek::traits::VartimeMultiscalarMul;
 
-use crate::{G, Scalar, RistrettoPoint, KeyEncoder};
+use crate::{G, Result, Scalar, RistrettoPoint, CompressedRistretto, RistrettoBasepointTable, KeyEncoder, rnd_scalar};
 
 //-----------------------------------------------------------------------------------------------------------
 // Schnorr's signature
 //-----------------------------------------------------------------------------------------------------------
+// Version of the domain-separation scheme, signatures of other versions never verify.
+const SIG_VERSION: u8 = 1;
+
+// Hasher tagged with the version and the signature domain (ex: "fpi:subject-key"), before any data bytes.
+fn tagged(domain: &str) -> Sha512 {
+    Sha512::new()
+        .chain([SIG_VERSION])
+        .chain((domain.len() as u64).to_le_bytes())
+        .chain(domain.as_bytes())
+}
+
 #[derive(Serialize, Deserialize)]
 struct SerializedSignature {
     pub sig: String,
     pub timestamp: i64,
 }
 
+#[allow(non_snake_case)]
 #[derive(Clone)]
 pub struct Signature {
     pub encoded: String,
     pub timestamp: i64,
 
     pub c: Scalar,
-    pub p: Scalar
+    pub p: Scalar,
+    pub M: Option<RistrettoPoint>       // nonce commitment, only encoded by batchable signatures
 }
 
 impl Debug for Signature {
@@ -35,20 +49,20 @@ impl Debug for Signature {
 }
 
 impl Serialize for Signature {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> where S: Serializer {
         let ss = SerializedSignature { sig: self.encoded.clone(), timestamp: self.timestamp };
         ss.serialize(serializer)
     }
 }
 
 impl<'de> Deserialize<'de> for Signature {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: Deserializer<'de> {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error> where D: Deserializer<'de> {
         let ss = SerializedSignature::deserialize(deserializer)?;
 
         let data = bs58::decode(&ss.sig).into_vec()
             .map_err(|_| Error::custom("Invalid base58 signature string!"))?;
         
-        if data.len() != 64 {
+        if data.len() != 64 && data.len() != 96 {
             return Err(Error::custom("Incorrect signature lenght!"))
         }
 
@@ -64,27 +78,56 @@ impl<'de> Deserialize<'de> for Signature {
         let p_scalar = Scalar::from_canonical_bytes(p_bytes)
             .ok_or_else(|| Error::custom("Invalid p scalar!"))?;
 
-        let obj = Signature { encoded: ss.sig, timestamp: ss.timestamp, c: c_scalar, p: p_scalar };
+        #[allow(non_snake_case)]
+        let M_point = match data.len() {
+            64 => None,
+            _ => {
+                let M_point = CompressedRistretto::from_slice(&data[64..96]).decompress()
+                    .ok_or_else(|| Error::custom("Invalid M point!"))?;
+                Some(M_point)
+            }
+        };
+
+        let obj = Signature { encoded: ss.sig, timestamp: ss.timestamp, c: c_scalar, p: p_scalar, M: M_point };
         Ok(obj)
     }
 }
 
 impl Signature {
     #[allow(non_snake_case)]
-    pub fn sign(s: &Scalar, P: &RistrettoPoint, BasePoint: &RistrettoPoint, data: &[Vec<u8>]) -> Self {
-        let timestamp = Utc::now().timestamp();
+    pub fn sign(s: &Scalar, P: &RistrettoPoint, BasePoint: &RistrettoPoint, domain: &str, data: &[Vec<u8>]) -> Self {
+        Self::sign_at(Utc::now().timestamp(), s, P, BasePoint, domain, data)
+    }
+
+    // sign with an explicit timestamp, for messages prepared ahead of the submission
+    #[allow(non_snake_case)]
+    pub fn sign_at(timestamp: i64, s: &Scalar, P: &RistrettoPoint, BasePoint: &RistrettoPoint, domain: &str, data: &[Vec<u8>]) -> Self {
+        Self::create(timestamp, s, P, BasePoint, domain, data, false)
+    }
+
+    // Signature also encoding the nonce commitment (c, p, M). It verifies with the same equation,
+    // but a set of these can be verified in a single multiscalar (see verify_batch).
+    #[allow(non_snake_case)]
+    pub fn sign_batchable(s: &Scalar, P: &RistrettoPoint, BasePoint: &RistrettoPoint, domain: &str, data: &[Vec<u8>]) -> Self {
+        Self::create(Utc::now().timestamp(), s, P, BasePoint, domain, data, true)
+    }
 
-        let mut hasher = Sha512::new()
-            .chain(s.as_bytes());
+    #[allow(non_snake_case)]
+    fn create(timestamp: i64, s: &Scalar, P: &RistrettoPoint, BasePoint: &RistrettoPoint, domain: &str, data: &[Vec<u8>], batchable: bool) -> Self {
+        // hedged nonce, bound to the secret and message but with fresh entropy (no reuse on faults or identical data)
+        let mut hasher = tagged(domain)
+            .chain(s.as_bytes())
+            .chain(rnd_scalar().as_bytes());
         
         for d in data {
             hasher.input(d);
         }
 
         let m = Scalar::from_hash(hasher); 
-        let M = (m * BasePoint).compress();
+        let M_point = m * BasePoint;
+        let M = M_point.compress();
 
-        let mut hasher = Sha512::new()
+        let mut hasher = tagged(domain)
             .chain(P.compress().as_bytes())
             .chain(M.as_bytes())
             .chain(timestamp.to_le_bytes());
@@ -96,18 +139,85 @@ impl Signature {
         let c = Scalar::from_hash(hasher);
         let p = m - c * s;
 
-        let data: &[&[u8]] = &[c.as_bytes(), p.as_bytes()];
+        let (data, M_point): (&[&[u8]], _) = match batchable {
+            false => (&[c.as_bytes(), p.as_bytes()], None),
+            true => (&[c.as_bytes(), p.as_bytes(), M.as_bytes()], Some(M_point))
+        };
         let data = data.concat();
-        let as_string = bs58::encode(&data).into_string();
+        let as_string = data.encode();
 
-        Self { encoded: as_string, timestamp, c, p: m - c * s }
+        Self { encoded: as_string, timestamp, c, p, M: M_point }
     }
 
     #[allow(non_snake_case)]
-    pub fn verify(&self, P: &RistrettoPoint, BasePoint: &RistrettoPoint, data: &[Vec<u8>]) -> bool {
+    pub fn verify(&self, P: &RistrettoPoint, BasePoint: &RistrettoPoint, domain: &str, data: &[Vec<u8>]) -> bool {
         let M = self.c * P + self.p * BasePoint;
+        self.matches(&M) && self.challenge(P, &M, domain, data) == self.c
+    }
+
+    // Verify a set of (signature, data) of the same key and base-point. Batchable signatures check the challenge hash with the encoded M,
+    // and all equations with a random linear combination (r_i): sum(r_i * c_i) * P + sum(r_i * p_i) * Base - sum(r_i * M_i) == 0.
+    // Signatures without M are verified one by one. Doesn't locate the invalid signature, verify each one for that.
+    #[allow(non_snake_case)]
+    pub fn verify_batch(sigs: &[(&Signature, &[Vec<u8>])], P: &RistrettoPoint, BasePoint: &RistrettoPoint, domain: &str) -> bool {
+        let (mut key, mut base) = (Scalar::zero(), Scalar::zero());
+        let mut scalars = Vec::<Scalar>::with_capacity(sigs.len() + 2);
+        let mut points = Vec::<RistrettoPoint>::with_capacity(sigs.len() + 2);
+        for (sig, data) in sigs.iter() {
+            let M = match sig.M {
+                Some(M) => M,
+                None => match sig.verify(P, BasePoint, domain, data) {
+                    true => continue,
+                    false => return false
+                }
+            };
+
+            if sig.challenge(P, &M, domain, data) != sig.c {
+                return false
+            }
+
+            let r = rnd_scalar();
+            key += r * sig.c;
+            base += r * sig.p;
+            scalars.push(-r);
+            points.push(M);
+        }
+
+        // signatures and keys are public, variable time is fine
+        scalars.push(key);
+        points.push(*P);
+        scalars.push(base);
+        points.push(*BasePoint);
+        RistrettoPoint::vartime_multiscalar_mul(scalars.iter(), points.iter()) == RistrettoPoint::default()
+    }
+
+    // an encoded commitment must be the one of the verification equation
+    #[allow(non_snake_case)]
+    fn matches(&self, M: &RistrettoPoint) -> bool {
+        match self.M {
+            None => true,
+            Some(encoded) => encoded == *M
+        }
+    }
+
+    // Same as verify, with precomputed tables of the key and base-point. Amortizes the verification of many signatures
+    // with the same pair. The commitment M is always required to recompute the challenge, only batchable signatures encode it.
+    #[allow(non_snake_case)]
+    pub fn verify_with(&self, P: &RistrettoPoint, tP: &RistrettoBasepointTable, tBase: &RistrettoBasepointTable, domain: &str, data: &[Vec<u8>]) -> bool {
+        let M = tP * &self.c + tBase * &self.p;
+        self.matches(&M) && self.challenge(P, &M, domain, data) == self.c
+    }
 
-        let mut hasher = Sha512::new()
+    // Same as verify with the G base-point, but in variable time (only for public inputs).
+    #[allow(non_snake_case)]
+    fn verify_vartime(&self, P: &RistrettoPoint, domain: &str, data: &[Vec<u8>]) -> bool {
+        let M = RistrettoPoint::vartime_double_scalar_mul_basepoint(&self.c, P, &self.p);
+        self.matches(&M) && self.challenge(P, &M, domain, data) == self.c
+    }
+
+    #[allow(non_snake_case)]
+    fn challenge(&self, P: &RistrettoPoint, M: &RistrettoPoint, domain: &str, data: &[Vec<u8>]) -> Scalar {
+        let mut hasher = tagged(domain)
             .chain(P.compress().as_bytes())
             .chain(M.compress().as_bytes())
             .chain(self.timestamp.to_le_bytes());
@@ -116,17 +226,21 @@ impl Signature {
             hasher.input(d);
         }
         
-        let c = Scalar::from_hash(hasher);
-
-        c == self.c
+        Scalar::from_hash(hasher)
     }
 
     pub fn check_timestamp(&self, threshold: Duration) -> bool {
         let now = Utc::now().timestamp();
-        let thr = threshold.as_secs() as i64;
+        self.check_timestamp_at(now, threshold)
+    }
+
+    // Accepts signatures up to "threshold" in the past, and up to "threshold" in the future to tolerate clock skew.
+    fn check_timestamp_at(&self, now: i64, threshold: Duration) -> bool {
+        // the timestamp is not trusted (deserialized), so avoid overflows
+        let thr = if threshold.as_secs() > i64::MAX as u64 { i64::MAX } else { threshold.as_secs() as i64 };
 
-        let upper = self.timestamp + thr;
-        let lower = self.timestamp - thr;
+        let upper = self.timestamp.saturating_add(thr);
+        let lower = self.timestamp.saturating_sub(thr);
 
         now >= lower && now <= upper
     }
@@ -156,14 +270,14 @@ impl ExtSignature {
     }
 
     #[allow(non_snake_case)]
-    pub fn sign(s: &Scalar, key: RistrettoPoint, data: &[Vec<u8>]) -> Self {
-        let sig = Signature::sign(s, &key, &G, data);
+    pub fn sign(s: &Scalar, key: RistrettoPoint, domain: &str, data: &[Vec<u8>]) -> Self {
+        let sig = Signature::sign(s, &key, &G, domain, data);
         Self { sig, key }
     }
 
     #[allow(non_snake_case)]
-    pub fn verify(&self, data: &[Vec<u8>]) -> bool {
-        self.sig.verify(&self.key, &G, data)
+    pub fn verify(&self, domain: &str, data: &[Vec<u8>]) -> bool {
+        self.sig.verify(&self.key, &G, domain, data)
     }
 }
 
@@ -181,14 +295,81 @@ impl IndSignature {
         &self.sig.encoded
     }
 
-    pub fn sign(index: usize, s: &Scalar, key: &RistrettoPoint, data: &[Vec<u8>]) -> Self {
-        let sig = Signature::sign(s, key, &G, data);
+    pub fn sign(index: usize, s: &Scalar, key: &RistrettoPoint, domain: &str, data: &[Vec<u8>]) -> Self {
+        let sig = Signature::sign(s, key, &G, domain, data);
+        Self { index, sig }
+    }
+
+    pub fn sign_at(timestamp: i64, index: usize, s: &Scalar, key: &RistrettoPoint, domain: &str, data: &[Vec<u8>]) -> Self {
+        let sig = Signature::sign_at(timestamp, s, key, &G, domain, data);
         Self { index, sig }
     }
 
     #[allow(non_snake_case)]
-    pub fn verify(&self, key: &RistrettoPoint, data: &[Vec<u8>]) -> bool {
-        self.sig.verify(&key, &G, data)
+    pub fn verify(&self, key: &RistrettoPoint, domain: &str, data: &[Vec<u8>]) -> bool {
+        self.sig.verify(key, &G, domain, data)
+    }
+
+    // Key selected by the signature index (ex: in the federation peer keys).
+    pub fn key<'a>(&self, keys: &'a [RistrettoPoint]) -> Result<&'a RistrettoPoint> {
+        keys.get(self.index).ok_or_else(|| "Unexpected peer index!".into())
+    }
+
+    // Verify against the key selected by the signature index. An index out of range is an error, not an invalid signature.
+    pub fn verify_against(&self, keys: &[RistrettoPoint], domain: &str, data: &[Vec<u8>]) -> Result<bool> {
+        let key = self.key(keys)?;
+        Ok(self.verify(key, domain, data))
+    }
+
+    pub fn sign_batchable(index: usize, s: &Scalar, key: &RistrettoPoint, domain: &str, data: &[Vec<u8>]) -> Self {
+        let sig = Signature::sign_batchable(s, key, &G, domain, data);
+        Self { index, sig }
+    }
+
+    // Verify a set of (signature, key, data) of the same domain, failing with the position of the first invalid signature.
+    // Batchable signatures check the challenge hash with the encoded M, and all equations with a random linear combination (r_i):
+    // sum(r_i * c_i * P_i) + sum(r_i * p_i) * G - sum(r_i * M_i) == 0, in a single multiscalar. An invalid signature passes with negligible probability.
+    // Signatures without M (c, p) can't be combined, these are verified one by one.
+    pub fn verify_batch(domain: &str, sigs: &[(&IndSignature, &RistrettoPoint, &[Vec<u8>])]) -> Result<()> {
+        if Self::batch(domain, sigs) {
+            return Ok(())
+        }
+
+        // locate the first invalid signature
+        let pos = sigs.iter().position(|(sig, key, data)| !sig.sig.verify_vartime(key, domain, data)).unwrap_or(0);
+        Err(format!("Invalid signature at batch position {}!", pos))
+    }
+
+    #[allow(non_snake_case)]
+    fn batch(domain: &str, sigs: &[(&IndSignature, &RistrettoPoint, &[Vec<u8>])]) -> bool {
+        let mut base = Scalar::zero();
+        let mut scalars = Vec::<Scalar>::with_capacity(2 * sigs.len() + 1);
+        let mut points = Vec::<RistrettoPoint>::with_capacity(2 * sigs.len() + 1);
+        for (sig, key, data) in sigs.iter() {
+            let M = match sig.sig.M {
+                Some(M) => M,
+                None => match sig.sig.verify_vartime(key, domain, data) {
+                    true => continue,
+                    false => return false
+                }
+            };
+
+            if sig.sig.challenge(key, &M, domain, data) != sig.sig.c {
+                return false
+            }
+
+            let r = rnd_scalar();
+            base += r * sig.sig.p;
+            scalars.push(r * sig.sig.c);
+            points.push(**key);
+            scalars.push(-r);
+            points.push(M);
+        }
+
+        // signatures and keys are public, variable time is fine
+        scalars.push(base);
+        points.push(G);
+        RistrettoPoint::vartime_multiscalar_mul(scalars.iter(), points.iter()) == RistrettoPoint::default()
     }
 }
 
@@ -207,9 +388,9 @@ mod tests {
         let d1 = rnd_scalar();
 
         let data = &[d0.to_bytes().to_vec(), d1.to_bytes().to_vec()];
-        let sig = ExtSignature::sign(&a, Pa, data);
+        let sig = ExtSignature::sign(&a, Pa, "fpi:test", data);
         
-        assert!(sig.verify(data) == true);
+        assert!(sig.verify("fpi:test", data));
     }
 
     #[allow(non_snake_case)]
@@ -223,9 +404,216 @@ mod tests {
         let d2 = rnd_scalar();
         
         let data1 = &[d0.to_bytes().to_vec(), d1.to_bytes().to_vec()];
-        let sig = ExtSignature::sign(&a, Pa, data1);
+        let sig = ExtSignature::sign(&a, Pa, "fpi:test", data1);
         
         let data2 = &[d0.to_bytes().to_vec(), d2.to_bytes().to_vec()];
-        assert!(sig.verify(data2) == false);
+        assert!(!sig.verify("fpi:test", data2));
+    }
+
+    #[allow(non_snake_case)]
+    #[test]
+    fn test_hedged_nonce() {
+        let a = rnd_scalar();
+        let Pa = a * G;
+
+        // same secret, data and timestamp
+        let data = &[b"same payload".to_vec()];
+        let timestamp = Utc::now().timestamp();
+        let sig1 = IndSignature::sign_at(timestamp, 0, &a, &Pa, "fpi:test", data);
+        let sig2 = IndSignature::sign_at(timestamp, 0, &a, &Pa, "fpi:test", data);
+
+        assert!(sig1.sig.c != sig2.sig.c && sig1.sig.p != sig2.sig.p);
+        assert!(sig1.sig.encoded != sig2.sig.encoded);
+
+        assert!(sig1.verify(&Pa, "fpi:test", data));
+        assert!(sig2.verify(&Pa, "fpi:test", data));
+    }
+
+    #[allow(non_snake_case)]
+    #[test]
+    fn test_timestamp() {
+        let a = rnd_scalar();
+        let Pa = a * G;
+
+        let data = &[rnd_scalar().to_bytes().to_vec()];
+        let mut sig = ExtSignature::sign(&a, Pa, "fpi:test", data).sig;
+        assert!(sig.check_timestamp(Duration::from_secs(5)));
+
+        let ts = sig.timestamp;
+        let thr = Duration::from_secs(5);
+
+        // exactly at the edges
+        assert!(sig.check_timestamp_at(ts + 5, thr));
+        assert!(sig.check_timestamp_at(ts - 5, thr));
+        assert!(!sig.check_timestamp_at(ts + 6, thr));
+        assert!(!sig.check_timestamp_at(ts - 6, thr));
+
+        // future-dated signature within the clock skew allowance
+        sig.timestamp = ts + 3;
+        assert!(sig.check_timestamp_at(ts, thr));
+
+        // far-future signature
+        sig.timestamp = ts + 3600;
+        assert!(!sig.check_timestamp_at(ts, thr));
+
+        // zero threshold only accepts the exact second
+        sig.timestamp = ts;
+        assert!(sig.check_timestamp_at(ts, Duration::from_secs(0)));
+        assert!(!sig.check_timestamp_at(ts + 1, Duration::from_secs(0)));
+        assert!(!sig.check_timestamp_at(ts - 1, Duration::from_secs(0)));
+
+        // extreme values must not overflow
+        sig.timestamp = i64::MAX;
+        assert!(!sig.check_timestamp_at(ts, thr));
+        assert!(sig.check_timestamp_at(ts, Duration::from_secs(u64::MAX)));
+        sig.timestamp = i64::MIN;
+        assert!(!sig.check_timestamp_at(ts, thr));
+    }
+
+    #[allow(non_snake_case)]
+    #[test]
+    fn test_verify_batch() {
+        let secrets: Vec<Scalar> = (0..8).map(|_| rnd_scalar()).collect();
+        let keys: Vec<RistrettoPoint> = secrets.iter().map(|s| s * G).collect();
+        let data: Vec<Vec<Vec<u8>>> = (0..8).map(|_| vec![rnd_scalar().to_bytes().to_vec()]).collect();
+
+        let mut sigs: Vec<IndSignature> = (0..8).map(|i| IndSignature::sign_batchable(i, &secrets[i], &keys[i], "fpi:test", &data[i])).collect();
+        let batch = |sigs: &[IndSignature], keys: &[RistrettoPoint]| -> Result<()> {
+            let items: Vec<(&IndSignature, &RistrettoPoint, &[Vec<u8>])> = sigs.iter().zip(keys.iter()).zip(data.iter())
+                .map(|((sig, key), data)| (sig, key, data.as_slice())).collect();
+            IndSignature::verify_batch("fpi:test", &items)
+        };
+
+        // same result of the verification loop
+        let looped = |sigs: &[IndSignature], keys: &[RistrettoPoint]| -> Result<()> {
+            match (0..sigs.len()).find(|i| !sigs[*i].verify(&keys[*i], "fpi:test", &data[*i])) {
+                None => Ok(()),
+                Some(pos) => Err(format!("Invalid signature at batch position {}!", pos))
+            }
+        };
+
+        assert!(batch(&sigs, &keys) == Ok(()));
+        assert!(batch(&sigs, &keys) == looped(&sigs, &keys));
+        assert!(batch(&[], &[]) == Ok(()));
+
+        // wrong key
+        let mut other = keys.clone();
+        other[5] = rnd_scalar() * G;
+        assert!(batch(&sigs, &other) == Err("Invalid signature at batch position 5!".into()));
+        assert!(batch(&sigs, &other) == looped(&sigs, &other));
+
+        // tampered signatures, the first failure is reported
+        sigs[6].sig.p += Scalar::one();
+        sigs[3].sig.c = rnd_scalar();
+        assert!(batch(&sigs, &keys) == Err("Invalid signature at batch position 3!".into()));
+        assert!(batch(&sigs, &keys) == looped(&sigs, &keys));
+
+        // cancelling errors between signatures are not accepted
+        let mut sigs: Vec<IndSignature> = (0..8).map(|i| IndSignature::sign_batchable(i, &secrets[i], &keys[i], "fpi:test", &data[i])).collect();
+        sigs[0].sig.p += Scalar::one();
+        sigs[1].sig.p -= Scalar::one();
+        assert!(batch(&sigs, &keys) == Err("Invalid signature at batch position 0!".into()));
+        assert!(batch(&sigs, &keys) == looped(&sigs, &keys));
+
+        // a replaced commitment M with a consistent p fails the challenge
+        let mut sigs: Vec<IndSignature> = (0..8).map(|i| IndSignature::sign_batchable(i, &secrets[i], &keys[i], "fpi:test", &data[i])).collect();
+        let m = rnd_scalar();
+        sigs[4].sig.M = Some(m * G);
+        sigs[4].sig.p = m - sigs[4].sig.c * secrets[4];
+        assert!(batch(&sigs, &keys) == Err("Invalid signature at batch position 4!".into()));
+        assert!(batch(&sigs, &keys) == looped(&sigs, &keys));
+
+        // signatures without M are mixed in the batch
+        let mut sigs: Vec<IndSignature> = (0..8).map(|i| match i % 2 {
+            0 => IndSignature::sign(i, &secrets[i], &keys[i], "fpi:test", &data[i]),
+            _ => IndSignature::sign_batchable(i, &secrets[i], &keys[i], "fpi:test", &data[i])
+        }).collect();
+        assert!(batch(&sigs, &keys) == Ok(()));
+        sigs[2].sig.p += Scalar::one();
+        assert!(batch(&sigs, &keys) == Err("Invalid signature at batch position 2!".into()));
+    }
+
+    #[allow(non_snake_case)]
+    #[test]
+    fn test_batchable_encoding() {
+        let a = rnd_scalar();
+        let Pa = a * G;
+        let data = &[rnd_scalar().to_bytes().to_vec()];
+
+        // (c, p, M) is encoded with the same serialized structure, and also verifies one by one
+        let sig = IndSignature::sign_batchable(0, &a, &Pa, "fpi:test", data);
+        assert!(bs58::decode(sig.id()).into_vec().unwrap().len() == 96);
+        assert!(sig.verify(&Pa, "fpi:test", data));
+
+        let decoded: IndSignature = bincode::deserialize(&bincode::serialize(&sig).unwrap()).unwrap();
+        assert!(decoded.sig.M == sig.sig.M && decoded.sig.c == sig.sig.c && decoded.sig.p == sig.sig.p);
+        assert!(decoded.verify(&Pa, "fpi:test", data));
+
+        // the legacy encoding (c, p) is unchanged
+        let legacy = IndSignature::sign(0, &a, &Pa, "fpi:test", data);
+        let decoded: IndSignature = bincode::deserialize(&bincode::serialize(&legacy).unwrap()).unwrap();
+        assert!(bs58::decode(legacy.id()).into_vec().unwrap().len() == 64 && decoded.sig.M.is_none());
+
+        // an encoded M not matching the verification equation
+        let mut forged = sig.clone();
+        forged.sig.M = Some(rnd_scalar() * G);
+        assert!(!forged.verify(&Pa, "fpi:test", data));
+    }
+
+    #[allow(non_snake_case)]
+    #[test]
+    fn test_domain() {
+        let a = rnd_scalar();
+        let Pa = a * G;
+        let data = &[rnd_scalar().to_bytes().to_vec()];
+
+        let sig = IndSignature::sign(0, &a, &Pa, "fpi:subject-key", data);
+        assert!(sig.verify(&Pa, "fpi:subject-key", data));
+        assert!(!sig.verify(&Pa, "fpi:profile-key", data));
+        assert!(IndSignature::verify_batch("fpi:profile-key", &[(&sig, &Pa, data)]) == Err("Invalid signature at batch position 0!".into()));
+
+        let other = IndSignature::sign(0, &a, &Pa, "fpi:profile-key", data);
+        assert!(other.verify(&Pa, "fpi:profile-key", data));
+        assert!(!other.verify(&Pa, "fpi:subject-key", data));
+
+        // signatures without the versioned tag are rejected
+        let m = rnd_scalar();
+        let timestamp = Utc::now().timestamp();
+        let hasher = Sha512::new()
+            .chain(Pa.compress().as_bytes())
+            .chain((m * G).compress().as_bytes())
+            .chain(timestamp.to_le_bytes())
+            .chain(&data[0]);
+
+        let c = Scalar::from_hash(hasher);
+        let legacy = Signature { encoded: String::new(), timestamp, c, p: m - c * a, M: None };
+        assert!(!legacy.verify(&Pa, &G, "", data));
+        assert!(!legacy.verify(&Pa, &G, "fpi:subject-key", data));
+    }
+
+    #[allow(non_snake_case)]
+    #[test]
+    fn test_verify_against() {
+        let secrets: Vec<Scalar> = (0..3).map(|_| rnd_scalar()).collect();
+        let keys: Vec<RistrettoPoint> = secrets.iter().map(|s| s * G).collect();
+        let data = &[rnd_scalar().to_bytes().to_vec()];
+
+        let sig = IndSignature::sign(2, &secrets[2], &keys[2], "fpi:test", data);
+        assert!(sig.key(&keys) == Ok(&keys[2]));
+        assert!(sig.verify_against(&keys, "fpi:test", data) == Ok(true));
+        assert!(sig.verify_against(&keys, "fpi:other", data) == Ok(false));
+
+        // signed for another index
+        let sig = IndSignature::sign(1, &secrets[2], &keys[2], "fpi:test", data);
+        assert!(sig.verify_against(&keys, "fpi:test", data) == Ok(false));
+
+        // out of range index
+        let sig = IndSignature::sign(3, &secrets[2], &keys[2], "fpi:test", data);
+        assert!(sig.key(&keys) == Err("Unexpected peer index!".into()));
+        assert!(sig.verify_against(&keys, "fpi:test", data) == Err("Unexpected peer index!".into()));
+        assert!(sig.verify_against(&[], "fpi:test", data) == Err("Unexpected peer index!".into()));
+
+        let sig = IndSignature::sign(usize::MAX, &secrets[0], &keys[0], "fpi:test", data);
+        assert!(sig.verify_against(&keys, "fpi:test", data) == Err("Unexpected peer index!".into()));
     }
 }
\ No newline at end of file
@@ -0,0 +1,62 @@
+use std::fmt::{Display, Formatter};
+
+// Typed failures of the message checks, so callers can branch on the cause instead of matching strings.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FpiError {
+    Constraint { field: String, reason: String },
+    InvalidSignature(String),
+    Timestamp,
+    NotFound(String),
+    Decode(String),
+    Crypto(String)
+}
+
+pub type FpiResult<T> = std::result::Result<T, FpiError>;
+
+impl FpiError {
+    pub fn constraint<R: Into<String>>(field: &str, reason: R) -> Self {
+        FpiError::Constraint { field: field.into(), reason: reason.into() }
+    }
+
+    pub fn signature(field: &str) -> Self {
+        FpiError::InvalidSignature(field.into())
+    }
+}
+
+impl Display for FpiError {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            FpiError::Constraint { field, reason } => write!(f, "Field Constraint - ({}, {})", field, reason),
+            FpiError::InvalidSignature(field) => write!(f, "Field Constraint - ({}, Invalid signature)", field),
+            FpiError::Timestamp => write!(f, "Field Constraint - (sig, Timestamp out of valid range)"),
+            FpiError::NotFound(msg) | FpiError::Decode(msg) | FpiError::Crypto(msg) => write!(f, "{}", msg)
+        }
+    }
+}
+
+impl std::error::Error for FpiError {}
+
+// Kept while the remaining APIs still report plain strings.
+impl From<FpiError> for String {
+    fn from(err: FpiError) -> Self {
+        err.to_string()
+    }
+}
+
+//-----------------------------------------------------------------------------------------------------------
+// Unit tests
+//-----------------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        assert!(FpiError::constraint("sid", "Expected").to_string() == "Field Constraint - (sid, Expected)");
+        assert!(FpiError::signature("sig").to_string() == "Field Constraint - (sig, Invalid signature)");
+        assert!(FpiError::Timestamp.to_string() == "Field Constraint - (sig, Timestamp out of valid range)");
+
+        let msg: String = FpiError::NotFound("No active subject-key found!".into()).into();
+        assert!(msg == "No active subject-key found!");
+    }
+}
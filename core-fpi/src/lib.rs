@@ -1,9 +1,12 @@
 #![forbid(unsafe_code)]
 
+use std::str::FromStr;
 use curve25519_dalek::constants::{RISTRETTO_BASEPOINT_POINT, RISTRETTO_BASEPOINT_TABLE};
 use rand_os::OsRng;
+use sha2::{Sha256, Digest};
 
 mod crypto;
+mod error;
 mod structs;
 
 // -- Exported --
@@ -11,6 +14,7 @@ pub use curve25519_dalek::ristretto::{RistrettoPoint, CompressedRistretto, Ristr
 pub use curve25519_dalek::scalar::Scalar;
 
 pub use crate::crypto::*;
+pub use crate::error::*;
 pub use crate::structs::*;
 
 pub const G: RistrettoPoint = RISTRETTO_BASEPOINT_POINT;
@@ -31,7 +35,15 @@ pub fn rnd_scalar() -> Scalar {
 
 pub fn uuid() -> String {
     let r = rnd_scalar();
-    bs58::encode(r.as_bytes()).into_string()
+    r.encode()
+}
+
+// Override a configuration value with an environment variable, if defined. Precedence is: env > file > default.
+pub fn env_override<T: FromStr>(name: &str, value: T) -> Result<T> {
+    match std::env::var(name) {
+        Err(_) => Ok(value),
+        Ok(env) => env.parse().map_err(|_| format!("Invalid value for environment variable {}: {:?}", name, env))
+    }
 }
 
 pub trait KeyEncoder {
@@ -42,6 +54,11 @@ pub trait HardKeyDecoder<T> {
     fn decode(&self) -> T;
 }
 
+// fallible decoding for user input (config files, etc), the error is the reason only
+pub trait TryKeyDecoder<T> {
+    fn try_decode(&self) -> Result<T>;
+}
+
 
 impl KeyEncoder for CompressedRistretto {
     fn encode(&self) -> String {
@@ -61,6 +78,13 @@ impl KeyEncoder for Scalar {
     }
 }
 
+// hashes and other raw identifiers (peers-hash, app-state hash, ...)
+impl KeyEncoder for [u8] {
+    fn encode(&self) -> String {
+        bs58::encode(self).into_string()
+    }
+}
+
 impl HardKeyDecoder<CompressedRistretto> for String {
     fn decode(&self) -> CompressedRistretto {
         let data = bs58::decode(self.as_str()).into_vec().expect("Unable to decode base58 input!");
@@ -84,4 +108,118 @@ impl HardKeyDecoder<Scalar> for String {
 
         Scalar::from_canonical_bytes(bytes).expect("Unable to decode Scalar!")
     }
+}
+
+// Short and stable fingerprint of a public key for out-of-band comparison by humans.
+// The first 10 bytes of SHA-256(compressed key) as hex, in groups of 2 bytes (ex: "9f1c 03ab 77e2 d410 5b6e").
+pub fn fingerprint(key: &RistrettoPoint) -> String {
+    let hash = Sha256::digest(key.compress().as_bytes());
+    let groups: Vec<String> = hash[..10].chunks(2).map(|pair| format!("{:02x}{:02x}", pair[0], pair[1])).collect();
+    groups.join(" ")
+}
+
+impl TryKeyDecoder<Vec<u8>> for String {
+    fn try_decode(&self) -> Result<Vec<u8>> {
+        bs58::decode(self.as_str()).into_vec().map_err(|_| "not valid base58".into())
+    }
+}
+
+impl TryKeyDecoder<CompressedRistretto> for String {
+    fn try_decode(&self) -> Result<CompressedRistretto> {
+        let data: Vec<u8> = self.try_decode()?;
+        if data.len() != 32 {
+            return Err(format!("expected 32 bytes, found {}", data.len()))
+        }
+
+        Ok(CompressedRistretto::from_slice(&data))
+    }
+}
+
+impl TryKeyDecoder<RistrettoPoint> for String {
+    fn try_decode(&self) -> Result<RistrettoPoint> {
+        let point: CompressedRistretto = self.try_decode()?;
+        point.decompress().ok_or_else(|| "not a valid RistrettoPoint".into())
+    }
+}
+
+impl TryKeyDecoder<Scalar> for String {
+    fn try_decode(&self) -> Result<Scalar> {
+        let data: Vec<u8> = self.try_decode()?;
+        if data.len() != 32 {
+            return Err(format!("expected 32 bytes, found {}", data.len()))
+        }
+
+        let mut bytes: [u8; 32] = Default::default();
+        bytes.copy_from_slice(&data);
+        Scalar::from_canonical_bytes(bytes).ok_or_else(|| "not a canonical Scalar".into())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_env_override() {
+        assert!(env_override("FEDPI_TEST_UNDEFINED", 26658usize) == Ok(26658));
+
+        std::env::set_var("FEDPI_TEST_PORT", "30000");
+        assert!(env_override("FEDPI_TEST_PORT", 26658usize) == Ok(30000));
+
+        std::env::set_var("FEDPI_TEST_THRESHOLD", "one");
+        assert!(env_override("FEDPI_TEST_THRESHOLD", 1usize) == Err("Invalid value for environment variable FEDPI_TEST_THRESHOLD: \"one\"".into()));
+
+        std::env::set_var("FEDPI_TEST_LOG", "warn");
+        assert!(env_override("FEDPI_TEST_LOG", "info".to_string()) == Ok("warn".into()));
+    }
+
+    #[test]
+    fn test_bytes_encoder() {
+        use sha2::{Sha256, Digest};
+
+        // SHA-256("fedpi")
+        let hash = Sha256::digest(b"fedpi").to_vec();
+        assert!(hash.encode() == bs58::encode(&hash).into_string());
+        assert!(hash.as_slice().encode() == hash.encode());
+
+        let decoded: Result<Vec<u8>> = hash.encode().try_decode();
+        assert!(decoded == Ok(hash.clone()));
+
+        assert!(Vec::<u8>::new().encode() == "");
+        assert!([0u8, 0, 1].encode() == "112");
+    }
+
+    #[test]
+    fn test_try_decode() {
+        let secret = rnd_scalar();
+        let point = secret * G;
+
+        let res: Result<RistrettoPoint> = point.encode().try_decode();
+        assert!(res == Ok(point));
+        let res: Result<Scalar> = secret.encode().try_decode();
+        assert!(res == Ok(secret));
+
+        let res: Result<RistrettoPoint> = "invalid 0OIl".to_string().try_decode();
+        assert!(res == Err("not valid base58".into()));
+        let res: Result<Vec<u8>> = "invalid 0OIl".to_string().try_decode();
+        assert!(res == Err("not valid base58".into()));
+        let res: Result<RistrettoPoint> = secret.to_bytes()[..16].encode().try_decode();
+        assert!(res == Err("expected 32 bytes, found 16".into()));
+        let res: Result<RistrettoPoint> = [255u8; 32][..].encode().try_decode();
+        assert!(res == Err("not a valid RistrettoPoint".into()));
+        let res: Result<Scalar> = [255u8; 32][..].encode().try_decode();
+        assert!(res == Err("not a canonical Scalar".into()));
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        // SHA-256 of the compressed base-point
+        assert!(fingerprint(&G) == "b4ae d8a6 4793 6906 f61c");
+
+        let key = rnd_scalar() * G;
+        assert!(fingerprint(&key) == fingerprint(&key));
+        assert!(fingerprint(&key) != fingerprint(&(rnd_scalar() * G)));
+        assert!(fingerprint(&key).len() == 24);
+    }
 }
\ No newline at end of file
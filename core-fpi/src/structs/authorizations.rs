@@ -1,16 +1,20 @@
 use indexmap::{IndexMap, IndexSet};
-use serde::{Serialize, Deserialize};
+use serde::{Serialize, Serializer, Deserialize};
+use serde::ser::SerializeTuple;
+use serde::de::{Deserializer, Error, SeqAccess, Visitor};
+use std::fmt::Formatter;
 use std::time::Duration;
+use chrono::Utc;
 
 use crate::ids::*;
 use crate::structs::*;
 use crate::crypto::signatures::IndSignature;
-use crate::{Result, Scalar};
+use crate::{Result, FpiError, FpiResult, Scalar, RistrettoPoint};
 
 //-----------------------------------------------------------------------------------------------------------
 // Subject Authorizations
 //-----------------------------------------------------------------------------------------------------------
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct Authorizations {
     auths: IndexMap<String, IndexSet<String>>       // All profile authorizations per subject <subject: <profile>>
 }
@@ -22,7 +26,7 @@ impl Authorizations {
 
     pub fn authorize(&mut self, consent: &Consent) {
         let aid = consent.target.clone();
-        let consents = self.auths.entry(aid).or_insert_with(|| IndexSet::<String>::new());
+        let consents = self.auths.entry(aid).or_default();
         for item in consent.profiles.iter() {
             consents.insert(item.clone());
         }
@@ -41,6 +45,20 @@ impl Authorizations {
         }
     }
 
+    // authorized profiles per target subject
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &IndexSet<String>)> {
+        self.auths.iter()
+    }
+
+    // authorized subject-ids
+    pub fn targets(&self) -> impl Iterator<Item = &String> {
+        self.auths.keys()
+    }
+
+    pub fn authorized_profiles(&self, target: &str) -> Option<&IndexSet<String>> {
+        self.auths.get(target)
+    }
+
     pub fn is_authorized(&self, target: &str, profile: &str) -> bool {
         match self.auths.get(target) {
             None => false,
@@ -57,49 +75,136 @@ pub enum ConsentType {
     Consent, Revoke
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+// Version of the encoding with a purpose, in place of the sid length of the first encoding
+const CONSENT_V1: u64 = u64::MAX;
+
+#[allow(clippy::manual_non_exhaustive)]
+#[derive(Debug, Clone)]
 pub struct Consent {
     pub sid: String,                                // Subject-id submitting consent
     pub typ: ConsentType,                           // Consent or revoke
     pub target: String,                             // Authorized data-subject target
     pub profiles: Vec<String>,                      // List of consented profiles (full disclosure)
+    pub purpose: Option<String>,                    // Purpose of the grant, for regulatory records
 
     pub sig: IndSignature,                          // Signature from data-subject
-    #[serde(skip)] _phantom: () // force use of constructor
+    _phantom: () // force use of constructor
+}
+
+// Consents without purpose keep the first encoding, so they are still decoded by previous nodes and clients.
+impl Serialize for Consent {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> where S: Serializer {
+        match &self.purpose {
+            None => {
+                let mut tuple = serializer.serialize_tuple(5)?;
+                tuple.serialize_element(&self.sid)?;
+                tuple.serialize_element(&self.typ)?;
+                tuple.serialize_element(&self.target)?;
+                tuple.serialize_element(&self.profiles)?;
+                tuple.serialize_element(&self.sig)?;
+                tuple.end()
+            },
+            Some(purpose) => {
+                let mut tuple = serializer.serialize_tuple(7)?;
+                tuple.serialize_element(&CONSENT_V1)?;
+                tuple.serialize_element(&self.sid)?;
+                tuple.serialize_element(&self.typ)?;
+                tuple.serialize_element(&self.target)?;
+                tuple.serialize_element(&self.profiles)?;
+                tuple.serialize_element(purpose)?;
+                tuple.serialize_element(&self.sig)?;
+                tuple.end()
+            }
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Consent {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error> where D: Deserializer<'de> {
+        // the first encoding has the sid bytes in place of the version (up to MAX_SUBJECT_ID_SIZE elements)
+        deserializer.deserialize_tuple(MAX_SUBJECT_ID_SIZE + 5, ConsentVisitor)
+    }
+}
+
+struct ConsentVisitor;
+
+impl<'de> Visitor<'de> for ConsentVisitor {
+    type Value = Consent;
+
+    fn expecting(&self, fmt: &mut Formatter) -> std::fmt::Result {
+        fmt.write_str("an encoded consent")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> std::result::Result<Consent, A::Error> where A: SeqAccess<'de> {
+        let version: u64 = element(&mut seq)?;
+        let sid = match version {
+            CONSENT_V1 => element(&mut seq)?,
+            len if len as usize <= MAX_SUBJECT_ID_SIZE => {
+                let bytes = (0..len).map(|_| element(&mut seq)).collect::<std::result::Result<Vec<u8>, _>>()?;
+                String::from_utf8(bytes).map_err(|_| Error::custom("Invalid subject-id!"))?
+            },
+            _ => return Err(Error::custom("Unsupported consent encoding!"))
+        };
+
+        let typ: ConsentType = element(&mut seq)?;
+        let target: String = element(&mut seq)?;
+        let profiles: Vec<String> = element(&mut seq)?;
+        let purpose: Option<String> = match version {
+            CONSENT_V1 => Some(element(&mut seq)?),
+            _ => None
+        };
+
+        let sig: IndSignature = element(&mut seq)?;
+        Ok(Consent { sid, typ, target, profiles, purpose, sig, _phantom: () })
+    }
+}
+
+fn element<'de, T: Deserialize<'de>, A: SeqAccess<'de>>(seq: &mut A) -> std::result::Result<T, A::Error> {
+    seq.next_element()?.ok_or_else(|| Error::custom("Incomplete consent!"))
 }
 
 impl Constraints for Consent {
     fn sid(&self) -> &str { &self.sid }
 
-    fn verify(&self, subject: &Subject, threshold: Duration) -> Result<()> {
+    fn verify(&self, subject: &Subject, threshold: Duration) -> FpiResult<()> {
         if self.sid.len() > MAX_SUBJECT_ID_SIZE {
-            return Err(format!("Field Constraint - (sid, max-size = {})", MAX_SUBJECT_ID_SIZE))
+            return Err(FpiError::constraint("sid", format!("max-size = {}", MAX_SUBJECT_ID_SIZE)))
         }
 
         // --------<typ> has no bounds to validate--------
 
         if self.target.len() > MAX_SUBJECT_ID_SIZE {
-            return Err(format!("Field Constraint - (target, max-size = {})", MAX_SUBJECT_ID_SIZE))
+            return Err(FpiError::constraint("target", format!("max-size = {}", MAX_SUBJECT_ID_SIZE)))
         }
 
         if self.profiles.len() > MAX_PROFILES {
-            return Err(format!("Field Constraint - (profiles, max-size = {})", MAX_PROFILES))
+            return Err(FpiError::constraint("profiles", format!("max-size = {}", MAX_PROFILES)))
         }
 
         for item in self.profiles.iter() {
             if item.len() > MAX_PROFILE_ID_SIZE {
-                return Err(format!("Field Constraint - (profile-id, max-size = {})", MAX_PROFILE_ID_SIZE))
+                return Err(FpiError::constraint("profile-id", format!("max-size = {}", MAX_PROFILE_ID_SIZE)))
+            }
+        }
+
+        if let Some(purpose) = &self.purpose {
+            if purpose.len() > MAX_PURPOSE_SIZE {
+                return Err(FpiError::constraint("purpose", format!("max-size = {}", MAX_PURPOSE_SIZE)))
             }
         }
 
         if !self.sig.sig.check_timestamp(threshold) {
-            return Err("Field Constraint - (sig, Timestamp out of valid range)".into())
+            return Err(FpiError::Timestamp)
+        }
+
+        let skey = subject.active_key()?;
+        if self.sig.index != skey.sig.index {
+            return Err(FpiError::constraint("sig", "Incorrect key index"))
         }
 
-        let skey = subject.keys.last().ok_or("No active subject-key found!")?;
-        let sig_data = Self::data(&self.sid, &self.typ, &self.target, &self.profiles);
-        if !self.sig.verify(&skey.key, &sig_data) {
-            return Err("Field Constraint - (sig, Invalid signature)".into())
+        let sig_data = Self::data(&self.sid, &self.typ, &self.target, &self.profiles, &self.purpose);
+        if !self.sig.verify(&skey.key, Self::DOMAIN, &sig_data) {
+            return Err(FpiError::signature("sig"))
         }
 
         Ok(())
@@ -107,11 +212,23 @@ impl Constraints for Consent {
 }
 
 impl Consent {
+    const DOMAIN: &'static str = "fpi:consent";
+
     pub fn sign(sid: &str, typ: ConsentType, target: &str, profiles: &[String], sig_s: &Scalar, sig_key: &SubjectKey) -> Self {
-        let sig_data = Self::data(sid, &typ, target, profiles);
-        let sig = IndSignature::sign(sig_key.sig.index, sig_s, &sig_key.key, &sig_data);
+        Self::sign_with_purpose(sid, typ, target, profiles, None, sig_s, sig_key)
+    }
+
+    pub fn sign_with_purpose(sid: &str, typ: ConsentType, target: &str, profiles: &[String], purpose: Option<&str>, sig_s: &Scalar, sig_key: &SubjectKey) -> Self {
+        Self::sign_at(Utc::now().timestamp(), sid, typ, target, profiles, purpose, sig_s, sig_key)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn sign_at(timestamp: i64, sid: &str, typ: ConsentType, target: &str, profiles: &[String], purpose: Option<&str>, sig_s: &Scalar, sig_key: &SubjectKey) -> Self {
+        let purpose = purpose.map(|item| item.to_string());
+        let sig_data = Self::data(sid, &typ, target, profiles, &purpose);
+        let sig = IndSignature::sign_at(timestamp, sig_key.sig.index, sig_s, &sig_key.key, Self::DOMAIN, &sig_data);
         
-        Self { sid: sid.into(), typ, target: target.into(), profiles: profiles.to_vec(), sig, _phantom: () }
+        Self { sid: sid.into(), typ, target: target.into(), profiles: profiles.to_vec(), purpose, sig, _phantom: () }
     }
 
     pub fn check(&self, subject: &Subject) -> Result<()> {
@@ -124,13 +241,378 @@ impl Consent {
         Ok(())
     }
 
-    fn data(sid: &str, typ: &ConsentType, target: &str, profiles: &[String]) -> [Vec<u8>; 4] {
+    // the purpose is only signed when present, consents without purpose keep the previous signature data
+    fn data(sid: &str, typ: &ConsentType, target: &str, profiles: &[String], purpose: &Option<String>) -> Vec<Vec<u8>> {
         // These unwrap() should never fail, or it's a serious code bug!
         let b_sid = bincode::serialize(sid).unwrap();
         let b_typ = bincode::serialize(typ).unwrap();
         let b_target = bincode::serialize(target).unwrap();
         let b_profiles = bincode::serialize(profiles).unwrap();
 
-        [b_sid, b_typ, b_target, b_profiles]
+        let mut data = vec![b_sid, b_typ, b_target, b_profiles];
+        if let Some(purpose) = purpose {
+            data.push(bincode::serialize(purpose).unwrap());
+        }
+
+        data
+    }
+}
+
+//-----------------------------------------------------------------------------------------------------------
+// Profile creation with a pre-authorized consent (both are applied or rejected together)
+//-----------------------------------------------------------------------------------------------------------
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProfileConsent {
+    pub update: Subject,                            // Subject update with the profile
+    pub consent: Consent,                           // Consent that may reference the profile in the update
+}
+
+impl Constraints for ProfileConsent {
+    fn sid(&self) -> &str { &self.update.sid }
+
+    fn verify(&self, subject: &Subject, threshold: Duration) -> FpiResult<()> {
+        if self.consent.sid != self.update.sid {
+            return Err(FpiError::constraint("consent", "Expected the same subject"))
+        }
+
+        if let ConsentType::Revoke = self.consent.typ {
+            return Err(FpiError::constraint("consent", "Expected a consent, not a revoke"))
+        }
+
+        self.update.verify(subject, threshold)?;
+        self.consent.verify(subject, threshold)
+    }
+}
+
+impl ProfileConsent {
+    pub fn new(update: Subject, consent: Consent) -> Self {
+        Self { update, consent }
+    }
+
+    // returns the merged subject, the consent is checked against it
+    pub fn check(&self, current: &Subject) -> Result<Subject> {
+        self.update.check(&Some(current.clone()))?;
+
+        let mut merged = current.clone();
+        merged.merge(self.update.clone());
+        self.consent.check(&merged)?;
+
+        Ok(merged)
+    }
+}
+
+//-----------------------------------------------------------------------------------------------------------
+// Query of the committed consents and revokes of the subject
+//-----------------------------------------------------------------------------------------------------------
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ConsentsQuery {
+    pub sid: String,                                // Subject-id of the consents
+
+    pub sig: IndSignature,                          // Signature from data-subject
+}
+
+impl Constraints for ConsentsQuery {
+    fn sid(&self) -> &str { &self.sid }
+
+    fn verify(&self, subject: &Subject, threshold: Duration) -> FpiResult<()> {
+        if self.sid.len() > MAX_SUBJECT_ID_SIZE {
+            return Err(FpiError::constraint("sid", format!("max-size = {}", MAX_SUBJECT_ID_SIZE)))
+        }
+
+        if !self.sig.sig.check_timestamp(threshold) {
+            return Err(FpiError::Timestamp)
+        }
+
+        let skey = subject.active_key()?;
+        if self.sig.index != skey.sig.index {
+            return Err(FpiError::constraint("sig", "Incorrect key index"))
+        }
+
+        let sig_data = Self::data(&self.sid);
+        if !self.sig.verify(&skey.key, Self::DOMAIN, &sig_data) {
+            return Err(FpiError::signature("sig"))
+        }
+
+        Ok(())
+    }
+}
+
+impl ConsentsQuery {
+    const DOMAIN: &'static str = "fpi:consents-query";
+
+    pub fn sign(sid: &str, sig_s: &Scalar, sig_key: &SubjectKey) -> Self {
+        let sig_data = Self::data(sid);
+        let sig = IndSignature::sign(sig_key.sig.index, sig_s, &sig_key.key, Self::DOMAIN, &sig_data);
+
+        Self { sid: sid.into(), sig }
+    }
+
+    fn data(sid: &str) -> [Vec<u8>; 1] {
+        // These unwrap() should never fail, or it's a serious code bug!
+        let b_sid = bincode::serialize(sid).unwrap();
+
+        [b_sid]
+    }
+}
+
+#[allow(clippy::manual_non_exhaustive)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ConsentsResult {
+    pub session: String,                            // query signature
+    pub consents: Vec<Consent>,                     // committed consents and revokes, in timestamp order
+
+    pub sig: IndSignature,                          // Signature from peer
+    #[serde(skip)] _phantom: () // force use of constructor
+}
+
+impl ConsentsResult {
+    const DOMAIN: &'static str = "fpi:consents-result";
+
+    pub fn sign(session: &str, consents: Vec<Consent>, secret: &Scalar, key: &RistrettoPoint, index: usize) -> Self {
+        let sig_data = Self::data(session, &consents);
+        let sig = IndSignature::sign(index, secret, key, Self::DOMAIN, &sig_data);
+
+        Self { session: session.into(), consents, sig, _phantom: () }
+    }
+
+    pub fn check(&self, query: &ConsentsQuery, peers: &[RistrettoPoint]) -> Result<()> {
+        if self.session != query.sig.id() {
+            return Err("Field Constraint - (session, Expected the same session)".into())
+        }
+
+        if self.consents.iter().any(|consent| consent.sid != query.sid) {
+            return Err("Field Constraint - (consents, Expected the same subject)".into())
+        }
+
+        Ok(self.verify(peers)?)
+    }
+
+    pub fn verify(&self, peers: &[RistrettoPoint]) -> FpiResult<()> {
+        let sig_data = Self::data(&self.session, &self.consents);
+        if !self.sig.verify_against(peers, Self::DOMAIN, &sig_data).map_err(FpiError::Crypto)? {
+            return Err(FpiError::signature("sig"))
+        }
+
+        Ok(())
+    }
+
+    fn data(session: &str, consents: &[Consent]) -> [Vec<u8>; 2] {
+        // These unwrap() should never fail, or it's a serious code bug!
+        let b_session = bincode::serialize(session).unwrap();
+        let b_consents = bincode::serialize(consents).unwrap();
+
+        [b_session, b_consents]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{G, rnd_scalar};
+
+    #[test]
+    fn test_profile_consent() {
+        let sig_s = rnd_scalar();
+        let sid = "s-id:shumy";
+
+        let mut current = Subject::new(sid);
+        let (_, skey) = current.evolve(sig_s);
+
+        let mut p1 = Profile::new("Assets");
+        p1.push(p1.evolve(sid, "https://profile-url.org", false, &sig_s, &skey).1);
+        current.push(p1).keys.push(skey.clone());
+
+        // create the profile and consent to it in the same submission
+        let mut p2 = Profile::new("HealthCare");
+        p2.push(p2.evolve(sid, "https://profile-url.org", false, &sig_s, &skey).1);
+
+        let mut update = Subject::new(sid);
+        update.push(p2);
+
+        let profiles = vec!["HealthCare".to_string()];
+        let consent = Consent::sign(sid, ConsentType::Consent, "s-id:regulator", &profiles, &sig_s, &skey);
+
+        // the consent alone references a not-yet-existing profile
+        assert!(consent.check(&current) == Err("No profile found: HealthCare".into()));
+
+        let bundle = ProfileConsent::new(update.clone(), consent);
+        assert!(bundle.verify(&current, Duration::from_secs(5)) == Ok(()));
+
+        let merged = bundle.check(&current).unwrap();
+        assert!(merged.find("Assets").is_some() && merged.find("HealthCare").is_some());
+
+        // a profile that is neither stored nor in the update, rejects the bundle
+        let profiles = vec!["HealthCare".to_string(), "Finance".to_string()];
+        let consent = Consent::sign(sid, ConsentType::Consent, "s-id:regulator", &profiles, &sig_s, &skey);
+        let bundle = ProfileConsent::new(update.clone(), consent);
+        assert!(bundle.verify(&current, Duration::from_secs(5)) == Ok(()));
+        assert!(bundle.check(&current).err() == Some("No profile found: Finance".into()));
+
+        // only consents, from the same subject
+        let revoke = Consent::sign(sid, ConsentType::Revoke, "s-id:regulator", &profiles, &sig_s, &skey);
+        let bundle = ProfileConsent::new(update.clone(), revoke);
+        assert!(bundle.verify(&current, Duration::from_secs(5)) == Err(FpiError::constraint("consent", "Expected a consent, not a revoke")));
+
+        let other = Consent::sign("s-id:other", ConsentType::Consent, "s-id:regulator", &profiles, &sig_s, &skey);
+        let bundle = ProfileConsent::new(update, other);
+        assert!(bundle.verify(&current, Duration::from_secs(5)) == Err(FpiError::constraint("consent", "Expected the same subject")));
+    }
+
+    #[test]
+    fn test_consent_purpose() {
+        let sig_s = rnd_scalar();
+        let sid = "s-id:shumy";
+
+        let mut subject = Subject::new(sid);
+        let (_, skey) = subject.evolve(sig_s);
+        subject.keys.push(skey.clone());
+
+        let profiles = vec!["HealthCare".to_string()];
+        let consent = Consent::sign_with_purpose(sid, ConsentType::Consent, "s-id:regulator", &profiles, Some("Clinical trial follow-up"), &sig_s, &skey);
+        assert!(consent.verify(&subject, Duration::from_secs(5)) == Ok(()));
+
+        let data = bincode::serialize(&consent).unwrap();
+        let decoded: Consent = bincode::deserialize(&data).unwrap();
+        assert!(decoded.purpose == Some("Clinical trial follow-up".to_string()));
+        assert!(decoded.verify(&subject, Duration::from_secs(5)) == Ok(()));
+
+        // the purpose is signed
+        let mut forged = decoded.clone();
+        forged.purpose = Some("Marketing".into());
+        assert!(forged.verify(&subject, Duration::from_secs(5)) == Err(FpiError::signature("sig")));
+
+        forged.purpose = None;
+        assert!(forged.verify(&subject, Duration::from_secs(5)) == Err(FpiError::signature("sig")));
+
+        // consents without purpose
+        let consent = Consent::sign(sid, ConsentType::Consent, "s-id:regulator", &profiles, &sig_s, &skey);
+        assert!(consent.purpose.is_none());
+        assert!(consent.verify(&subject, Duration::from_secs(5)) == Ok(()));
+
+        let oversized = "x".repeat(MAX_PURPOSE_SIZE + 1);
+        let consent = Consent::sign_with_purpose(sid, ConsentType::Consent, "s-id:regulator", &profiles, Some(&oversized), &sig_s, &skey);
+        assert!(consent.verify(&subject, Duration::from_secs(5)) == Err(FpiError::constraint("purpose", format!("max-size = {}", MAX_PURPOSE_SIZE))));
+    }
+
+    #[test]
+    fn test_consent_encoding() {
+        // the encoding before the purpose
+        #[derive(Serialize, Deserialize)]
+        struct ConsentV0 { sid: String, typ: ConsentType, target: String, profiles: Vec<String>, sig: IndSignature }
+
+        let sig_s = rnd_scalar();
+        let sid = "s-id:shumy";
+
+        let mut subject = Subject::new(sid);
+        let (_, skey) = subject.evolve(sig_s);
+        subject.keys.push(skey.clone());
+
+        let profiles = vec!["HealthCare".to_string()];
+        let consent = Consent::sign(sid, ConsentType::Consent, "s-id:regulator", &profiles, &sig_s, &skey);
+        let legacy = ConsentV0 { sid: sid.into(), typ: consent.typ, target: consent.target.clone(), profiles: profiles.clone(), sig: consent.sig.clone() };
+
+        // consents without purpose keep the first encoding, both ways
+        let data = bincode::serialize(&consent).unwrap();
+        assert!(data == bincode::serialize(&legacy).unwrap());
+
+        let decoded: ConsentV0 = bincode::deserialize(&data).unwrap();
+        assert!(decoded.sid == sid && decoded.target == "s-id:regulator");
+
+        let decoded: Consent = bincode::deserialize(&bincode::serialize(&legacy).unwrap()).unwrap();
+        assert!(decoded.purpose.is_none());
+        assert!(decoded.verify(&subject, Duration::from_secs(5)) == Ok(()));
+
+        // the purpose is in the versioned encoding
+        let consent = Consent::sign_with_purpose(sid, ConsentType::Revoke, "s-id:regulator", &profiles, Some("research"), &sig_s, &skey);
+        let data = bincode::serialize(&consent).unwrap();
+        assert!(data[..8] == u64::MAX.to_le_bytes());
+
+        let decoded: Consent = bincode::deserialize(&data).unwrap();
+        assert!(decoded.purpose == Some("research".into()));
+        assert!(decoded.verify(&subject, Duration::from_secs(5)) == Ok(()));
+
+        let res: std::result::Result<Consent, _> = bincode::deserialize(&data[..data.len() - 1]);
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_consents_query() {
+        let sig_s = rnd_scalar();
+        let sid = "s-id:shumy";
+
+        let mut subject = Subject::new(sid);
+        let (_, skey) = subject.evolve(sig_s);
+        subject.keys.push(skey.clone());
+
+        let query = ConsentsQuery::sign(sid, &sig_s, &skey);
+        assert!(query.verify(&subject, Duration::from_secs(5)) == Ok(()));
+
+        let (_, other) = Subject::new(sid).evolve(rnd_scalar());
+        let forged = ConsentsQuery::sign(sid, &rnd_scalar(), &other);
+        assert!(forged.verify(&subject, Duration::from_secs(5)) == Err(FpiError::signature("sig")));
+
+        let secret = rnd_scalar();
+        let peers = vec![secret * G];
+        let profiles = vec!["HealthCare".to_string()];
+        let consent = Consent::sign_with_purpose(sid, ConsentType::Consent, "s-id:regulator", &profiles, Some("research"), &sig_s, &skey);
+
+        let res = ConsentsResult::sign(query.sig.id(), vec![consent.clone()], &secret, &peers[0], 0);
+        assert!(res.check(&query, &peers) == Ok(()));
+        assert!(res.consents[0].purpose == Some("research".into()));
+
+        // bound to the query session and subject
+        let other_query = ConsentsQuery::sign(sid, &sig_s, &skey);
+        assert!(res.check(&other_query, &peers) == Err("Field Constraint - (session, Expected the same session)".into()));
+
+        let other = Consent::sign("s-id:other", ConsentType::Consent, "s-id:regulator", &profiles, &sig_s, &skey);
+        let res = ConsentsResult::sign(query.sig.id(), vec![consent, other], &secret, &peers[0], 0);
+        assert!(res.check(&query, &peers) == Err("Field Constraint - (consents, Expected the same subject)".into()));
+
+        let res = ConsentsResult::sign(query.sig.id(), Vec::new(), &rnd_scalar(), &peers[0], 0);
+        assert!(res.check(&query, &peers) == Err("Field Constraint - (sig, Invalid signature)".into()));
+    }
+
+    #[test]
+    fn test_golden_layout() {
+        let profiles = vec!["HealthCare".to_string()];
+        let consent = Consent::data("s-id:shumy", &ConsentType::Consent, "s-id:target", &profiles, &None);
+        assert!(crate::structs::layout(&consent) == "18,4,19,26:HN5DheYeKxxxE8dnbUA2N4WSTh69SHq8y8n3TuZPCWbg");
+
+        let revoke = Consent::data("s-id:shumy", &ConsentType::Revoke, "s-id:target", &profiles, &Some("research".into()));
+        assert!(crate::structs::layout(&revoke) == "18,4,19,26,16:CeZiCqLHjUcJUbwV9PCkGxSqsAZyYcsS7KE4d594pnEt");
+    }
+
+    #[test]
+    fn test_authorized_profiles() {
+        let sig_s = rnd_scalar();
+        let sid = "s-id:shumy";
+
+        let subject = Subject::new(sid);
+        let (_, skey) = subject.evolve(sig_s);
+
+        let mut auths = Authorizations::new();
+        assert!(auths.targets().next().is_none());
+        assert!(auths.authorized_profiles("s-id:regulator").is_none());
+
+        let profiles = vec!["HealthCare".to_string(), "Assets".to_string()];
+        auths.authorize(&Consent::sign(sid, ConsentType::Consent, "s-id:regulator", &profiles, &sig_s, &skey));
+        auths.authorize(&Consent::sign(sid, ConsentType::Consent, "s-id:hospital", &profiles[..1], &sig_s, &skey));
+
+        let targets: Vec<&String> = auths.targets().collect();
+        assert!(targets == vec!["s-id:regulator", "s-id:hospital"]);
+
+        let consented: Vec<&String> = auths.authorized_profiles("s-id:regulator").unwrap().iter().collect();
+        assert!(consented == vec!["HealthCare", "Assets"]);
+
+        // a partial revoke keeps the target
+        auths.revoke(&Consent::sign(sid, ConsentType::Revoke, "s-id:regulator", &profiles[..1], &sig_s, &skey));
+        let consented: Vec<&String> = auths.authorized_profiles("s-id:regulator").unwrap().iter().collect();
+        assert!(consented == vec!["Assets"]);
+        assert!(!auths.is_authorized("s-id:regulator", "HealthCare"));
+
+        // empty entries are removed
+        auths.revoke(&Consent::sign(sid, ConsentType::Revoke, "s-id:hospital", &profiles, &sig_s, &skey));
+        assert!(auths.authorized_profiles("s-id:hospital").is_none());
+        assert!(auths.targets().count() == 1);
     }
 }
\ No newline at end of file
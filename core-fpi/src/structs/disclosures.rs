@@ -3,18 +3,22 @@ use serde::{Serialize, Deserialize};
 use std::time::Duration;
 
 use crate::ids::*;
+use crate::authorizations::Authorizations;
 use crate::structs::*;
 use crate::crypto::signatures::IndSignature;
-use crate::{Result, Scalar, RistrettoPoint};
+use crate::shares::{RistrettoShare, RistrettoPolynomial, Reconstruct, Evaluate, Degree};
+use crate::{Result, FpiError, FpiResult, Scalar, RistrettoPoint};
 
 //-----------------------------------------------------------------------------------------------------------
 // Disclose Request
 //-----------------------------------------------------------------------------------------------------------
+#[allow(clippy::manual_non_exhaustive)]
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct DiscloseRequest {
     pub sid: String,                                // Subject-id requesting disclosure
     pub target: String,                             // Target subject-id for the profiles
     pub profiles: Vec<String>,                      // List of profiles for full disclose
+    pub partial: bool,                              // Disclose the authorized subset instead of failing on any denied profile
     
     pub sig: IndSignature,                          // Signature from data-subject
     #[serde(skip)] _phantom: () // force use of constructor
@@ -23,33 +27,37 @@ pub struct DiscloseRequest {
 impl Constraints for DiscloseRequest {
     fn sid(&self) -> &str { &self.sid }
 
-    fn verify(&self, subject: &Subject, threshold: Duration) -> Result<()> {
+    fn verify(&self, subject: &Subject, threshold: Duration) -> FpiResult<()> {
         if self.sid.len() > MAX_SUBJECT_ID_SIZE {
-            return Err(format!("Field Constraint - (sid, max-size = {})", MAX_SUBJECT_ID_SIZE))
+            return Err(FpiError::constraint("sid", format!("max-size = {}", MAX_SUBJECT_ID_SIZE)))
         }
 
         if self.target.len() > MAX_SUBJECT_ID_SIZE {
-            return Err(format!("Field Constraint - (target, max-size = {})", MAX_SUBJECT_ID_SIZE))
+            return Err(FpiError::constraint("target", format!("max-size = {}", MAX_SUBJECT_ID_SIZE)))
         }
 
         if self.profiles.len() > MAX_PROFILES {
-            return Err(format!("Field Constraint - (profiles, max-size = {})", MAX_PROFILES))
+            return Err(FpiError::constraint("profiles", format!("max-size = {}", MAX_PROFILES)))
         }
 
         for item in self.profiles.iter() {
             if item.len() > MAX_PROFILE_ID_SIZE {
-                return Err(format!("Field Constraint - (profile-id, max-size = {})", MAX_PROFILE_ID_SIZE))
+                return Err(FpiError::constraint("profile-id", format!("max-size = {}", MAX_PROFILE_ID_SIZE)))
             }
         }
 
         if !self.sig.sig.check_timestamp(threshold) {
-            return Err("Field Constraint - (sig, Timestamp out of valid range)".into())
+            return Err(FpiError::Timestamp)
         }
 
-        let skey = subject.keys.last().ok_or("No active subject-key found!")?;
-        let sig_data = Self::data(&self.sid, &self.target, &self.profiles);
-        if !self.sig.verify(&skey.key, &sig_data) {
-            return Err("Field Constraint - (sig, Invalid signature)".into())
+        let skey = subject.active_key()?;
+        if self.sig.index != skey.sig.index {
+            return Err(FpiError::constraint("sig", "Incorrect key index"))
+        }
+
+        let sig_data = Self::data(&self.sid, &self.target, &self.profiles, self.partial);
+        if !self.sig.verify(&skey.key, Self::DOMAIN, &sig_data) {
+            return Err(FpiError::signature("sig"))
         }
 
         Ok(())
@@ -57,29 +65,64 @@ impl Constraints for DiscloseRequest {
 }
 
 impl DiscloseRequest {
-    pub fn sign(sid: &str, target: &str, profiles: &[String], sig_s: &Scalar, sig_key: &SubjectKey) -> Self {
-        let sig_data = Self::data(sid, target, profiles);
-        let sig = IndSignature::sign(sig_key.sig.index, sig_s, &sig_key.key, &sig_data);
+    const DOMAIN: &'static str = "fpi:disclose-request";
+
+    pub fn sign(sid: &str, target: &str, profiles: &[String], partial: bool, sig_s: &Scalar, sig_key: &SubjectKey) -> Self {
+        let sig_data = Self::data(sid, target, profiles, partial);
+        let sig = IndSignature::sign(sig_key.sig.index, sig_s, &sig_key.key, Self::DOMAIN, &sig_data);
         
-        Self { sid: sid.into(), target: target.into(), profiles: profiles.to_vec(), sig, _phantom: () }
+        Self { sid: sid.into(), target: target.into(), profiles: profiles.to_vec(), partial, sig, _phantom: () }
+    }
+
+    // Verification of a past request (e.g. presented as evidence of the disclosure), outside of the timestamp window.
+    pub fn verify_sig(&self, subject: &Subject) -> FpiResult<()> {
+        if self.sid != subject.sid {
+            return Err(FpiError::constraint("sid", "Expected the requester subject"))
+        }
+
+        let skey = subject.signing_key(&self.sig)?;
+        let sig_data = Self::data(&self.sid, &self.target, &self.profiles, self.partial);
+        if !self.sig.verify(&skey.key, Self::DOMAIN, &sig_data) {
+            return Err(FpiError::signature("sig"))
+        }
+
+        Ok(())
+    }
+
+    // Collects the authorization results for all requested profiles. Returns the denied profiles in partial mode,
+    // otherwise fails with the complete denial list.
+    pub fn authorize(&self, auths: &Authorizations) -> Result<Vec<String>> {
+        if self.sid == self.target {
+            return Ok(Vec::new())
+        }
+
+        let denied: Vec<String> = self.profiles.iter().filter(|typ| !auths.is_authorized(&self.sid, typ)).cloned().collect();
+        if !denied.is_empty() && !self.partial {
+            return Err(format!("Subject has not authorization to disclose profiles: {:?}", denied))
+        }
+
+        Ok(denied)
     }
 
-    fn data(sid: &str, target: &str, profiles: &[String]) -> [Vec<u8>; 3] {
+    fn data(sid: &str, target: &str, profiles: &[String], partial: bool) -> [Vec<u8>; 4] {
         // These unwrap() should never fail, or it's a serious code bug!
         let b_sid = bincode::serialize(sid).unwrap();
         let b_target = bincode::serialize(target).unwrap();
         let b_profiles = bincode::serialize(profiles).unwrap();
+        let b_partial = bincode::serialize(&partial).unwrap();
 
-        [b_sid, b_target, b_profiles]
+        [b_sid, b_target, b_profiles, b_partial]
     }
 }
 
 //-----------------------------------------------------------------------------------------------------------
 // Disclose Result
 //-----------------------------------------------------------------------------------------------------------
+#[allow(clippy::manual_non_exhaustive)]
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct DiscloseResult {
     pub session: String,                            // Identifies the disclose by the encoded signature
+    pub height: i64,                                // Block height of the peer state used in the result
     pub keys: DiscloseKeys,                         // MPC result
 
     pub sig: IndSignature,                          // Signature from peer
@@ -87,42 +130,67 @@ pub struct DiscloseResult {
 }
 
 impl DiscloseResult {
-    pub fn sign(session: &str, keys: DiscloseKeys, secret: &Scalar, key: &RistrettoPoint, index: usize) -> Self {
-        let sig_data = Self::data(session, &keys);
-        let sig = IndSignature::sign(index, secret, &key, &sig_data);
+    const DOMAIN: &'static str = "fpi:disclose-result";
+
+    pub fn sign(session: &str, height: i64, keys: DiscloseKeys, secret: &Scalar, key: &RistrettoPoint, index: usize) -> Self {
+        let sig_data = Self::data(session, height, &keys);
+        let sig = IndSignature::sign(index, secret, key, Self::DOMAIN, &sig_data);
         
-        Self { session: session.into(), keys, sig, _phantom: () }
+        Self { session: session.into(), height, keys, sig, _phantom: () }
     }
 
-    pub fn check(&self, session: &str, profiles: &[String], key: &RistrettoPoint) -> Result<()> {
+    pub fn check(&self, session: &str, profiles: &[String], peers: &[RistrettoPoint]) -> Result<()> {
         if self.session != session {
             return Err("Field Constraint - (session, Expected the same session)".into())
         }
 
+        // the result is controlled by the peer, bound it before any further processing
+        self.keys.check_bounds()?;
+
         if !self.keys.constains(profiles) {
             return Err("Field Constraint - (keys, Expected the same profile list)".into())
         }
 
-        let sig_data = Self::data(&self.session, &self.keys);
-        if !self.sig.verify(&key, &sig_data) {
-            return Err("Field Constraint - (sig, Invalid signature)".into())
+        if !self.keys.is_paired() {
+            return Err("Field Constraint - (keys, Expected paired pseudonym and encryption shares)".into())
+        }
+
+        Ok(self.verify(peers)?)
+    }
+
+    pub fn verify(&self, peers: &[RistrettoPoint]) -> FpiResult<()> {
+        let sig_data = Self::data(&self.session, self.height, &self.keys);
+        if !self.sig.verify_against(peers, Self::DOMAIN, &sig_data).map_err(FpiError::Crypto)? {
+            return Err(FpiError::signature("sig"))
         }
 
         Ok(())
     }
     
-    fn data(session: &str, keys: &DiscloseKeys) -> [Vec<u8>; 2] {
+    fn data(session: &str, height: i64, keys: &DiscloseKeys) -> [Vec<u8>; 3] {
         // These unwrap() should never fail, or it's a serious code bug!
         let b_session = bincode::serialize(session).unwrap();
+        let b_height = bincode::serialize(&height).unwrap();
         let b_keys = bincode::serialize(keys).unwrap();
 
-        [b_session, b_keys]
+        [b_session, b_height, b_keys]
     }
 }
 
 #[derive(Serialize, Deserialize, Default, Debug, Clone)]
 pub struct DiscloseKeys {
-    pub keys: IndexMap<String, IndexMap<String, Vec<(RistrettoPoint, Option<RistrettoPoint>)>>>,     //MPC result <type <lurl <share>>>
+    pub keys: IndexMap<String, IndexMap<String, LocationShares>>,  //MPC result <type <lurl <shares>>>
+    pub migrations: IndexMap<String, String>,                       //Migrated locations <typ@lurl, lurl>
+    pub denied: Vec<String>,                                        //Requested profiles without authorization (partial disclosure)
+    pub absent: Vec<String>,                                        //Authorized profiles no longer in the target (removed after the consent)
+}
+
+// Parallel collections, one entry per profile-key of the location chain.
+// An encryption share is present iff the profile-key is encrypted.
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+pub struct LocationShares {
+    pub pseudonyms: Vec<RistrettoPoint>,                // Pseudonym shares
+    pub encryptions: Vec<Option<RistrettoPoint>>,       // Encryption shares
 }
 
 impl DiscloseKeys {
@@ -130,23 +198,533 @@ impl DiscloseKeys {
         Self { ..Default::default() }
     }
 
-    pub fn put(&mut self, typ: &str, loc: &str, share: (RistrettoPoint, Option<RistrettoPoint>)) {
-        let typs = self.keys.entry(typ.into()).or_insert_with(|| IndexMap::<String, Vec<(RistrettoPoint, Option<RistrettoPoint>)>>::new());
-        let locs = typs.entry(loc.into()).or_insert_with(|| Vec::<(RistrettoPoint, Option<RistrettoPoint>)>::new());
-        locs.push(share);
+    pub fn put(&mut self, typ: &str, loc: &str, pseudonym: RistrettoPoint, encryption: Option<RistrettoPoint>) {
+        let typs = self.keys.entry(typ.into()).or_default();
+        let locs = typs.entry(loc.into()).or_default();
+        locs.pseudonyms.push(pseudonym);
+        locs.encryptions.push(encryption);
     }
 
+    pub fn migrate(&mut self, typ: &str, from: &str, to: &str) {
+        self.migrations.insert(ProfileLocation::pid(typ, from), to.into());
+    }
+
+    pub fn deny(&mut self, typ: &str) {
+        self.denied.push(typ.into());
+    }
+
+    pub fn absent(&mut self, typ: &str) {
+        self.absent.push(typ.into());
+    }
+
+    // each requested profile is either disclosed, denied or absent
     pub fn constains(&self, profiles: &[String]) -> bool {
-        if profiles.len() != self.keys.len() {
+        if profiles.len() != self.keys.len() + self.denied.len() + self.absent.len() {
             return false
         }
 
         for item in profiles.iter() {
-            if !self.keys.contains_key(item) {
+            let found = [self.keys.contains_key(item), self.denied.contains(item), self.absent.contains(item)];
+            if found.iter().filter(|is| **is).count() != 1 {
                 return false
             }
         }
 
         true
     }
+
+    pub fn is_paired(&self) -> bool {
+        self.keys.values().all(|locs| locs.values().all(|shares| shares.pseudonyms.len() == shares.encryptions.len()))
+    }
+
+    fn check_bounds(&self) -> Result<()> {
+        if self.keys.len() + self.denied.len() + self.absent.len() > MAX_PROFILES {
+            return Err(format!("Field Constraint - (keys, max-profiles = {})", MAX_PROFILES))
+        }
+
+        if self.migrations.len() > MAX_PROFILES * MAX_LOCATIONS {
+            return Err(format!("Field Constraint - (migrations, max-size = {})", MAX_PROFILES * MAX_LOCATIONS))
+        }
+
+        for locs in self.keys.values() {
+            if locs.len() > MAX_LOCATIONS {
+                return Err(format!("Field Constraint - (keys, max-locations = {})", MAX_LOCATIONS))
+            }
+
+            for shares in locs.values() {
+                if shares.pseudonyms.len() > MAX_KEY_CHAIN || shares.encryptions.len() > MAX_KEY_CHAIN {
+                    return Err(format!("Field Constraint - (keys, max-shares = {})", MAX_KEY_CHAIN))
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+//-----------------------------------------------------------------------------------------------------------
+// Disclosed Keys
+//-----------------------------------------------------------------------------------------------------------
+#[derive(Default, Debug, Clone)]
+pub struct DisclosedKeys {
+    pub pseudonyms: IndexMap<String, RistrettoPoint>,   // Reconstructed pseudonyms <typ-lurl-i, pseudonym>
+    pub encryptions: IndexMap<String, RistrettoPoint>,  // Reconstructed encryption secrets <typ-lurl-i, secret>
+    pub migrations: IndexMap<String, String>,           // Migrated locations <typ@lurl, lurl>
+    pub denied: Vec<String>,                            // Requested profiles without authorization
+    pub absent: Vec<String>,                            // Authorized profiles not found in the target
+}
+
+// Peers must serve the results from (nearly) the same snapshot, otherwise the shares may be from different key sets.
+// Returns the highest height of the results.
+pub fn check_heights(results: &[DiscloseResult], tolerance: u64) -> Result<i64> {
+    let min = results.iter().map(|dr| dr.height).min().ok_or("No results to process disclosure!")?;
+    let max = results.iter().map(|dr| dr.height).max().ok_or("No results to process disclosure!")?;
+
+    // heights are reported by the peers, a negative one would overflow the difference
+    if min < 0 {
+        return Err(format!("Invalid height on disclosure: {}", min))
+    }
+
+    if (max - min) as u64 > tolerance {
+        return Err(format!("Inconsistent heights on disclosure: (min = {}, max = {})", min, max))
+    }
+
+    Ok(max)
+}
+
+// Verifies the peer results of a disclose request and combines the shares (at least 2t + 1 results).
+// The peers are the federation keys, indexed by the peer index of the result signature.
+pub fn reconstruct_pseudonyms(request: &DiscloseRequest, results: &[DiscloseResult], peers: &[RistrettoPoint], threshold: usize) -> Result<DisclosedKeys> {
+    let mut checked = IndexMap::<usize, &DiscloseResult>::with_capacity(results.len());
+    for dr in results.iter() {
+        dr.check(&request.sig.sig.encoded, &request.profiles, peers)?;
+
+        if checked.contains_key(&dr.sig.index) {
+            return Err("Replaced response on key disclosure!".into())
+        }
+
+        // all peers must agree on the denied profiles
+        if let Some(other) = checked.values().next() {
+            if other.keys.denied != dr.keys.denied {
+                return Err("Inconsistent denied profiles on disclosure!".into())
+            }
+
+            if other.keys.absent != dr.keys.absent {
+                return Err("Inconsistent absent profiles on disclosure!".into())
+            }
+        }
+
+        checked.insert(dr.sig.index, dr);
+    }
+
+    if checked.len() < 2*threshold + 1 {
+        return Err("Not enought responses to process disclosure!".into())
+    }
+
+    // collect shares for each profile-key
+    let mut disclosed = DisclosedKeys::default();
+    let mut pseudo_poly_shares = IndexMap::<String, Vec<RistrettoShare>>::new();
+    let mut crypto_poly_shares = IndexMap::<String, Vec<RistrettoShare>>::new();
+    for (n, dr) in checked.into_iter() {
+        disclosed.denied = dr.keys.denied.clone();
+        disclosed.absent = dr.keys.absent.clone();
+
+        for (pid, to) in dr.keys.migrations.iter() {
+            if let Some(other) = disclosed.migrations.get(pid) {
+                if other != to {
+                    return Err("Inconsistent location migrations on disclosure!".into())
+                }
+            }
+
+            disclosed.migrations.insert(pid.clone(), to.clone());
+        }
+
+        for (typ, locs) in dr.keys.keys.iter() {
+            for (loc, shares) in locs.iter() {
+                let paired = shares.pseudonyms.iter().zip(shares.encryptions.iter());
+                for (i, (pseudo, crypto)) in paired.enumerate() {
+                    let key = format!("{}-{}-{}", typ, loc, i);
+
+                    let v_shares = pseudo_poly_shares.entry(key.clone()).or_default();
+                    v_shares.push(RistrettoShare { i: (n + 1) as u32, Yi: *pseudo });
+
+                    if let Some(crypto) = crypto {
+                        let v_shares = crypto_poly_shares.entry(key).or_default();
+                        v_shares.push(RistrettoShare { i: (n + 1) as u32, Yi: *crypto });
+                    }
+                }
+            }
+        }
+    }
+
+    // reconstruct pseudonyms
+    for (key, shares) in pseudo_poly_shares.into_iter() {
+        let rpoly = RistrettoPolynomial::reconstruct(&shares)?;
+        if rpoly.degree() != threshold {
+            return Err("Incorrect set of pseudo shares!".into())
+        }
+
+        disclosed.pseudonyms.insert(key, rpoly.evaluate(&Scalar::zero()));
+    }
+
+    // reconstruct encryption secrets
+    for (key, shares) in crypto_poly_shares.into_iter() {
+        let rpoly = RistrettoPolynomial::reconstruct(&shares)?;
+        if rpoly.degree() != threshold {
+            return Err("Incorrect set of crypto shares!".into())
+        }
+
+        disclosed.encryptions.insert(key, rpoly.evaluate(&Scalar::zero()));
+    }
+
+    Ok(disclosed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{G, rnd_scalar};
+    use crate::shares::*;
+
+    #[test]
+    fn test_disclosed_migration() {
+        let secret = rnd_scalar();
+        let key = secret * G;
+        let profiles = vec!["Assets".to_string()];
+
+        let mut dkeys = DiscloseKeys::new();
+        dkeys.put("Assets", "https://new-url.org", rnd_scalar() * G, None);
+        dkeys.migrate("Assets", "https://old-url.org", "https://new-url.org");
+
+        let res = DiscloseResult::sign("session", 0, dkeys, &secret, &key, 0);
+        assert!(res.check("session", &profiles, &[key]) == Ok(()));
+        assert!(res.keys.migrations.get("Assets@https://old-url.org") == Some(&"https://new-url.org".to_string()));
+
+        // the migrations are part of the signature
+        let mut res = res;
+        res.keys.migrations.insert("Assets@https://old-url.org".into(), "https://evil-url.org".into());
+        assert!(res.check("session", &profiles, &[key]) == Err("Field Constraint - (sig, Invalid signature)".into()));
+    }
+
+    #[test]
+    fn test_partial_authorization() {
+        use crate::authorizations::*;
+
+        let sig_s = rnd_scalar();
+        let (_, skey) = Subject::new("s-id:verifier").evolve(sig_s);
+
+        let (_, tkey) = Subject::new("s-id:target").evolve(sig_s);
+        let consent = Consent::sign("s-id:target", ConsentType::Consent, "s-id:verifier", &["Assets".to_string()], &sig_s, &tkey);
+
+        let mut auths = Authorizations::new();
+        auths.authorize(&consent);
+
+        let profiles = vec!["Assets".to_string(), "Finance".to_string(), "HealthCare".to_string()];
+
+        // fails with the complete denial list
+        let full = DiscloseRequest::sign("s-id:verifier", "s-id:target", &profiles, false, &sig_s, &skey);
+        assert!(full.authorize(&auths) == Err("Subject has not authorization to disclose profiles: [\"Finance\", \"HealthCare\"]".into()));
+
+        // the authorized subset and the denied list
+        let partial = DiscloseRequest::sign("s-id:verifier", "s-id:target", &profiles, true, &sig_s, &skey);
+        let denied = partial.authorize(&auths).unwrap();
+        assert!(denied == vec!["Finance".to_string(), "HealthCare".to_string()]);
+
+        // the owner is always authorized
+        let own = DiscloseRequest::sign("s-id:target", "s-id:target", &profiles, false, &sig_s, &tkey);
+        assert!(own.authorize(&Authorizations::new()) == Ok(Vec::new()));
+
+        let secret = rnd_scalar();
+        let key = secret * G;
+
+        let mut dkeys = DiscloseKeys::new();
+        dkeys.put("Assets", "https://url.org", rnd_scalar() * G, None);
+        for typ in denied.iter() {
+            dkeys.deny(typ);
+        }
+
+        let res = DiscloseResult::sign("session", 0, dkeys.clone(), &secret, &key, 0);
+        assert!(res.check("session", &profiles, &[key]) == Ok(()));
+
+        // a denied profile can't be disclosed at the same time
+        let mut both = dkeys.clone();
+        both.put("Finance", "https://url.org", rnd_scalar() * G, None);
+        both.denied.pop();
+        let res = DiscloseResult::sign("session", 0, both, &secret, &key, 0);
+        assert!(res.check("session", &profiles, &[key]) == Err("Field Constraint - (keys, Expected the same profile list)".into()));
+
+        // the denied list is part of the signature
+        let mut res = DiscloseResult::sign("session", 0, dkeys, &secret, &key, 0);
+        res.keys.denied.pop();
+        assert!(res.check("session", &profiles, &[key]) == Err("Field Constraint - (keys, Expected the same profile list)".into()));
+        res.keys.denied.push("HealthCare".into());
+        res.keys.denied.swap(0, 1);
+        assert!(res.check("session", &profiles, &[key]) == Err("Field Constraint - (sig, Invalid signature)".into()));
+    }
+
+    #[test]
+    fn test_absent_profiles() {
+        let secret = rnd_scalar();
+        let key = secret * G;
+        let profiles = vec!["Assets".to_string(), "Finance".to_string(), "HealthCare".to_string()];
+
+        let mut dkeys = DiscloseKeys::new();
+        dkeys.put("Assets", "https://url.org", rnd_scalar() * G, None);
+        dkeys.deny("Finance");
+        dkeys.absent("HealthCare");
+
+        let res = DiscloseResult::sign("session", 0, dkeys.clone(), &secret, &key, 0);
+        assert!(res.check("session", &profiles, &[key]) == Ok(()));
+
+        // an absent profile can't be denied or disclosed at the same time
+        let mut both = dkeys.clone();
+        both.absent("Finance");
+        both.denied.pop();
+        both.deny("HealthCare");
+        let res = DiscloseResult::sign("session", 0, both, &secret, &key, 0);
+        assert!(res.check("session", &profiles, &[key]) == Err("Field Constraint - (keys, Expected the same profile list)".into()));
+
+        let mut both = dkeys.clone();
+        both.absent("Assets");
+        let res = DiscloseResult::sign("session", 0, both, &secret, &key, 0);
+        assert!(res.check("session", &profiles, &[key]) == Err("Field Constraint - (keys, Expected the same profile list)".into()));
+
+        // the absent list is part of the signature
+        let mut res = DiscloseResult::sign("session", 0, dkeys, &secret, &key, 0);
+        res.keys.absent = vec!["Finance".into()];
+        res.keys.denied = vec!["HealthCare".into()];
+        assert!(res.check("session", &profiles, &[key]) == Err("Field Constraint - (sig, Invalid signature)".into()));
+    }
+
+    #[test]
+    fn test_signed_query_result() {
+        use crate::messages::QResult;
+
+        let secrets: Vec<Scalar> = (0..4).map(|_| rnd_scalar()).collect();
+        let peers: Vec<RistrettoPoint> = secrets.iter().map(|s| s * G).collect();
+
+        let mut dkeys = DiscloseKeys::new();
+        dkeys.put("Assets", "https://url.org", rnd_scalar() * G, None);
+
+        let res = QResult::QDiscloseResult(DiscloseResult::sign("session", 0, dkeys.clone(), &secrets[1], &peers[1], 1));
+        assert!(res.verify(&peers) == Ok(()));
+
+        // signed by a key that is not the configured peer
+        let other = rnd_scalar();
+        let res = QResult::QDiscloseResult(DiscloseResult::sign("session", 0, dkeys.clone(), &other, &(other * G), 1));
+        assert!(res.verify(&peers) == Err(FpiError::signature("sig")));
+
+        // signed for a different peer index
+        let res = QResult::QDiscloseResult(DiscloseResult::sign("session", 0, dkeys.clone(), &secrets[1], &peers[1], 2));
+        assert!(res.verify(&peers) == Err(FpiError::signature("sig")));
+
+        let res = QResult::QDiscloseResult(DiscloseResult::sign("session", 0, dkeys.clone(), &secrets[1], &peers[1], 10));
+        assert!(res.verify(&peers) == Err(FpiError::Crypto("Unexpected peer index!".into())));
+
+        // body not covered by the signature (unsigned content)
+        let mut forged = DiscloseResult::sign("session", 0, dkeys, &secrets[1], &peers[1], 1);
+        forged.keys.put("Finance", "https://url.org", rnd_scalar() * G, None);
+        let res = QResult::QDiscloseResult(forged);
+        assert!(res.verify(&peers) == Err(FpiError::signature("sig")));
+    }
+
+    #[allow(non_snake_case)]
+    #[test]
+    fn test_disclosed_shares() {
+        let threshold = 2;
+        let parties = 3*threshold + 1;
+
+        // pseudonym and encryption master-keys
+        let ps = rnd_scalar();
+        let es = rnd_scalar();
+        let p_shares = Polynomial::rnd(ps, threshold).shares(parties);
+        let e_shares = Polynomial::rnd(es, threshold).shares(parties);
+
+        // one encrypted and one plain profile-key
+        let k1 = rnd_scalar() * G;
+        let k2 = rnd_scalar() * G;
+
+        let mut results = Vec::<DiscloseKeys>::new();
+        for i in 0..parties {
+            let mut dkeys = DiscloseKeys::new();
+            dkeys.put("Assets", "https://url.org", (&p_shares.0[i] * &k1).Yi, Some((&e_shares.0[i] * &k1).Yi));
+            dkeys.put("Assets", "https://url.org", (&p_shares.0[i] * &k2).Yi, None);
+            assert!(dkeys.is_paired());
+            results.push(dkeys);
+        }
+
+        let mut pseudo = [Vec::<RistrettoShare>::new(), Vec::<RistrettoShare>::new()];
+        let mut crypto = [Vec::<RistrettoShare>::new(), Vec::<RistrettoShare>::new()];
+        for (n, dkeys) in results.iter().enumerate() {
+            let shares = &dkeys.keys["Assets"]["https://url.org"];
+            for (k, (p, e)) in shares.pseudonyms.iter().zip(shares.encryptions.iter()).enumerate() {
+                pseudo[k].push(RistrettoShare { i: (n + 1) as u32, Yi: *p });
+                if let Some(e) = e {
+                    crypto[k].push(RistrettoShare { i: (n + 1) as u32, Yi: *e });
+                }
+            }
+        }
+
+        assert!(RistrettoPolynomial::interpolate(&pseudo[0][0..threshold + 1]) == Ok(ps * k1));
+        assert!(RistrettoPolynomial::interpolate(&pseudo[1][0..threshold + 1]) == Ok(ps * k2));
+        assert!(RistrettoPolynomial::interpolate(&crypto[0][0..threshold + 1]) == Ok(es * k1));
+        assert!(crypto[1].is_empty());
+
+        // unpaired shares are rejected
+        let secret = rnd_scalar();
+        let key = secret * G;
+        let profiles = vec!["Assets".to_string()];
+
+        let mut dkeys = results[0].clone();
+        dkeys.keys["Assets"]["https://url.org"].encryptions.pop();
+        let res = DiscloseResult::sign("session", 0, dkeys, &secret, &key, 0);
+        assert!(res.check("session", &profiles, &[key]) == Err("Field Constraint - (keys, Expected paired pseudonym and encryption shares)".into()));
+    }
+
+    #[test]
+    fn test_reconstruct_pseudonyms() {
+        let threshold = 1;
+        let parties = 3*threshold + 1;
+
+        let sig_s = rnd_scalar();
+        let (_, skey) = Subject::new("s-id:target").evolve(sig_s);
+        let profiles = vec!["Assets".to_string()];
+        let request = DiscloseRequest::sign("s-id:target", "s-id:target", &profiles, false, &sig_s, &skey);
+
+        let secrets: Vec<Scalar> = (0..parties).map(|_| rnd_scalar()).collect();
+        let peers: Vec<RistrettoPoint> = secrets.iter().map(|s| s * G).collect();
+
+        let ps = rnd_scalar();
+        let es = rnd_scalar();
+        let p_shares = Polynomial::rnd(ps, threshold).shares(parties);
+        let e_shares = Polynomial::rnd(es, threshold).shares(parties);
+        let k1 = rnd_scalar() * G;
+
+        let results: Vec<DiscloseResult> = (0..parties).map(|i| {
+            let mut dkeys = DiscloseKeys::new();
+            dkeys.put("Assets", "https://url.org", (&p_shares.0[i] * &k1).Yi, Some((&e_shares.0[i] * &k1).Yi));
+            DiscloseResult::sign(&request.sig.sig.encoded, 0, dkeys, &secrets[i], &peers[i], i)
+        }).collect();
+
+        // valid reconstruction
+        let quorum = &results[..2*threshold + 1];
+        let disclosed = reconstruct_pseudonyms(&request, quorum, &peers, threshold).unwrap();
+        assert!(disclosed.pseudonyms["Assets-https://url.org-0"] == ps * k1);
+        assert!(disclosed.encryptions["Assets-https://url.org-0"] == es * k1);
+        assert!(disclosed.denied.is_empty());
+        assert!(disclosed.absent.is_empty());
+
+        // insufficient quorum
+        assert!(reconstruct_pseudonyms(&request, &results[..2*threshold], &peers, threshold).err() == Some("Not enought responses to process disclosure!".into()));
+
+        // replayed response
+        let replayed = vec![results[0].clone(), results[1].clone(), results[1].clone()];
+        assert!(reconstruct_pseudonyms(&request, &replayed, &peers, threshold).err() == Some("Replaced response on key disclosure!".into()));
+
+        // a bad share, correctly signed by the peer
+        let mut dkeys = DiscloseKeys::new();
+        dkeys.put("Assets", "https://url.org", rnd_scalar() * G, Some((&e_shares.0[2] * &k1).Yi));
+        let bad = DiscloseResult::sign(&request.sig.sig.encoded, 0, dkeys, &secrets[2], &peers[2], 2);
+        let quorum = vec![results[0].clone(), results[1].clone(), bad];
+        assert!(reconstruct_pseudonyms(&request, &quorum, &peers, threshold).err() == Some("Incorrect set of pseudo shares!".into()));
+
+        // a share from another session
+        let mut dkeys = DiscloseKeys::new();
+        dkeys.put("Assets", "https://url.org", (&p_shares.0[2] * &k1).Yi, None);
+        let other = DiscloseResult::sign("other-session", 0, dkeys, &secrets[2], &peers[2], 2);
+        let quorum = vec![results[0].clone(), results[1].clone(), other];
+        assert!(reconstruct_pseudonyms(&request, &quorum, &peers, threshold).err() == Some("Field Constraint - (session, Expected the same session)".into()));
+    }
+
+    #[test]
+    fn test_disclose_heights() {
+        let secrets: Vec<Scalar> = (0..4).map(|_| rnd_scalar()).collect();
+        let peers: Vec<RistrettoPoint> = secrets.iter().map(|s| s * G).collect();
+        let profiles = vec!["Assets".to_string()];
+
+        let mut dkeys = DiscloseKeys::new();
+        dkeys.put("Assets", "https://url.org", rnd_scalar() * G, None);
+
+        let results = |heights: &[i64]| -> Vec<DiscloseResult> {
+            heights.iter().enumerate().map(|(i, height)| DiscloseResult::sign("session", *height, dkeys.clone(), &secrets[i], &peers[i], i)).collect()
+        };
+
+        // the height is part of the signature
+        let mut res = results(&[10])[0].clone();
+        assert!(res.check("session", &profiles, &peers) == Ok(()));
+        res.height = 11;
+        assert!(res.check("session", &profiles, &peers) == Err("Field Constraint - (sig, Invalid signature)".into()));
+
+        // one peer served a later snapshot
+        let first = results(&[10, 10, 12]);
+        assert!(check_heights(&first, 1) == Err("Inconsistent heights on disclosure: (min = 10, max = 12)".into()));
+        assert!(check_heights(&first, 2) == Ok(12));
+
+        // a retry after the lagging peers catch up is reconciled
+        let retry = results(&[12, 13, 12]);
+        assert!(check_heights(&retry, 1) == Ok(13));
+
+        assert!(check_heights(&[], 1) == Err("No results to process disclosure!".into()));
+
+        // a byzantine peer can't overflow the difference
+        let byzantine = results(&[12, i64::MIN, 12]);
+        assert!(check_heights(&byzantine, u64::MAX) == Err(format!("Invalid height on disclosure: {}", i64::MIN)));
+        assert!(check_heights(&results(&[i64::MAX, 0, -1]), u64::MAX) == Err("Invalid height on disclosure: -1".into()));
+        assert!(check_heights(&results(&[i64::MAX, 0, 0]), u64::MAX) == Ok(i64::MAX));
+    }
+
+    #[test]
+    fn test_disclose_bounds() {
+        let secret = rnd_scalar();
+        let key = secret * G;
+        let profiles = vec!["Assets".to_string()];
+
+        let mut dkeys = DiscloseKeys::new();
+        for _ in 0..MAX_KEY_CHAIN {
+            dkeys.put("Assets", "https://url.org", rnd_scalar() * G, None);
+        }
+
+        let res = DiscloseResult::sign("session", 0, dkeys.clone(), &secret, &key, 0);
+        assert!(res.check("session", &profiles, &[key]) == Ok(()));
+
+        // too many shares for a location
+        let mut oversized = dkeys.clone();
+        oversized.put("Assets", "https://url.org", rnd_scalar() * G, None);
+        let res = DiscloseResult::sign("session", 0, oversized, &secret, &key, 0);
+        assert!(res.check("session", &profiles, &[key]) == Err(format!("Field Constraint - (keys, max-shares = {})", MAX_KEY_CHAIN)));
+
+        // too many locations for a type
+        let mut oversized = dkeys.clone();
+        for i in 0..MAX_LOCATIONS {
+            oversized.put("Assets", &format!("https://url-{}.org", i), rnd_scalar() * G, None);
+        }
+        let res = DiscloseResult::sign("session", 0, oversized, &secret, &key, 0);
+        assert!(res.check("session", &profiles, &[key]) == Err(format!("Field Constraint - (keys, max-locations = {})", MAX_LOCATIONS)));
+
+        // too many types
+        let mut oversized = dkeys.clone();
+        for i in 0..MAX_PROFILES {
+            oversized.put(&format!("Type-{}", i), "https://url.org", rnd_scalar() * G, None);
+        }
+        let res = DiscloseResult::sign("session", 0, oversized, &secret, &key, 0);
+        assert!(res.check("session", &profiles, &[key]) == Err(format!("Field Constraint - (keys, max-profiles = {})", MAX_PROFILES)));
+
+        // too many migrations
+        let mut oversized = dkeys;
+        for i in 0..(MAX_PROFILES * MAX_LOCATIONS + 1) {
+            oversized.migrate("Assets", &format!("https://url-{}.org", i), "https://url.org");
+        }
+        let res = DiscloseResult::sign("session", 0, oversized, &secret, &key, 0);
+        assert!(res.check("session", &profiles, &[key]) == Err(format!("Field Constraint - (migrations, max-size = {})", MAX_PROFILES * MAX_LOCATIONS)));
+    }
+
+    #[test]
+    fn test_golden_layout() {
+        let profiles = vec!["HealthCare".to_string()];
+        let full = DiscloseRequest::data("s-id:shumy", "s-id:target", &profiles, false);
+        assert!(crate::structs::layout(&full) == "18,19,26,1:2SBTMKeyD37MKTQrDg1oaQ1QejvhYzNoSj3MtRU7V3U4");
+
+        let partial = DiscloseRequest::data("s-id:shumy", "s-id:target", &profiles, true);
+        assert!(crate::structs::layout(&partial) == "18,19,26,1:9wYmUHXyhny28836bBQK7cU4qZXK19S3odBnRJiwEV2e");
+    }
 }
\ No newline at end of file
@@ -0,0 +1,323 @@
+use std::time::Duration;
+use indexmap::IndexMap;
+use serde::{Serialize, Deserialize};
+use sha2::{Sha512, Digest};
+
+use crate::ids::*;
+use crate::structs::*;
+use crate::crypto::signatures::IndSignature;
+use crate::{Result, FpiError, FpiResult, Scalar, RistrettoPoint};
+
+const MAX_PEER_NAME_SIZE: usize = 64;
+
+// Hash of the ordered peer's list, identifies the federation for negotiations and votes
+pub fn peers_hash(pkeys: &[RistrettoPoint]) -> Vec<u8> {
+    let mut hasher = Sha512::new();
+    for pkey in pkeys.iter() {
+        hasher.input(pkey.compress().as_bytes());
+    }
+
+    hasher.result().to_vec()
+}
+
+//-----------------------------------------------------------------------------------------------------------
+// PeerSet (governance record to replace the peer's list at a block boundary)
+//-----------------------------------------------------------------------------------------------------------
+#[allow(clippy::manual_non_exhaustive)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PeerSet {
+    pub sid: String,                                // Subject-id submitting the change (must be the admin)
+    pub threshold: usize,                           // Number of permitted failing nodes, where #peers >= 3 * t + 1
+    pub peers: Vec<(String, RistrettoPoint)>,       // Ordered list of peers (name, pkey)
+
+    pub sig: IndSignature,                          // Signature from the admin
+    #[serde(skip)] _phantom: () // force use of constructor
+}
+
+impl Constraints for PeerSet {
+    fn sid(&self) -> &str { &self.sid }
+
+    fn verify(&self, subject: &Subject, threshold: Duration) -> FpiResult<()> {
+        if self.sid.len() > MAX_SUBJECT_ID_SIZE {
+            return Err(FpiError::constraint("sid", format!("max-size = {}", MAX_SUBJECT_ID_SIZE)))
+        }
+
+        if self.peers.len() > MAX_PEERS {
+            return Err(FpiError::constraint("peers", format!("max-size = {}", MAX_PEERS)))
+        }
+
+        for (name, _) in self.peers.iter() {
+            if name.len() > MAX_PEER_NAME_SIZE {
+                return Err(FpiError::constraint("peer-name", format!("max-size = {}", MAX_PEER_NAME_SIZE)))
+            }
+        }
+
+        if !self.sig.sig.check_timestamp(threshold) {
+            return Err(FpiError::Timestamp)
+        }
+
+        let skey = subject.active_key()?;
+        if self.sig.index != skey.sig.index {
+            return Err(FpiError::constraint("sig", "Incorrect key index"))
+        }
+
+        let sig_data = Self::data(&self.sid, self.threshold, &self.peers);
+        if !self.sig.verify(&skey.key, Self::DOMAIN, &sig_data) {
+            return Err(FpiError::signature("sig"))
+        }
+
+        Ok(())
+    }
+}
+
+impl PeerSet {
+    const DOMAIN: &'static str = "fpi:peer-set";
+
+    pub fn sign(sid: &str, threshold: usize, peers: &[(String, RistrettoPoint)], sig_s: &Scalar, sig_key: &SubjectKey) -> Self {
+        let sig_data = Self::data(sid, threshold, peers);
+        let sig = IndSignature::sign(sig_key.sig.index, sig_s, &sig_key.key, Self::DOMAIN, &sig_data);
+
+        Self { sid: sid.into(), threshold, peers: peers.to_vec(), sig, _phantom: () }
+    }
+
+    pub fn check(&self) -> Result<()> {
+        if self.peers.len() < 3 * self.threshold + 1 {
+            return Err("Field Constraint - (peers, Expecting #peers >= 3 * t + 1)".into())
+        }
+
+        for (i, (_, pkey)) in self.peers.iter().enumerate() {
+            if self.peers[..i].iter().any(|(_, item)| item == pkey) {
+                return Err("Field Constraint - (peers, Duplicated peer-key)".into())
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn keys(&self) -> Vec<RistrettoPoint> {
+        self.peers.iter().map(|(_, pkey)| *pkey).collect()
+    }
+
+    pub fn hash(&self) -> Vec<u8> {
+        peers_hash(&self.keys())
+    }
+
+    fn data(sid: &str, threshold: usize, peers: &[(String, RistrettoPoint)]) -> [Vec<u8>; 3] {
+        let c_peers: Vec<(&str, _)> = peers.iter().map(|(name, pkey)| (name.as_str(), pkey.compress())).collect();
+
+        // These unwrap() should never fail, or it's a serious code bug!
+        let b_sid = bincode::serialize(sid).unwrap();
+        let b_threshold = bincode::serialize(&threshold).unwrap();
+        let b_peers = bincode::serialize(&c_peers).unwrap();
+
+        [b_sid, b_threshold, b_peers]
+    }
+}
+
+//-----------------------------------------------------------------------------------------------------------
+// PeerChange (evidence of the transition from the current peer-set, committed before the new PeerSet)
+// Evidence of the previous peer-set is still accepted until the next change, e.g. a resharing delivered after the PeerSet.
+//-----------------------------------------------------------------------------------------------------------
+#[allow(clippy::manual_non_exhaustive)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PeerChange {
+    pub sid: String,                                // Subject-id submitting the change (must be the admin)
+    pub old_hash: Vec<u8>,
+    pub new_hash: Vec<u8>,
+    pub threshold: usize,                           // Threshold of the previous peer-set
+    pub peers: Vec<RistrettoPoint>,                 // Ordered keys of the previous peer-set
+
+    pub sig: IndSignature,                          // Signature from the admin
+    #[serde(skip)] _phantom: () // force use of constructor
+}
+
+impl Constraints for PeerChange {
+    fn sid(&self) -> &str { &self.sid }
+
+    fn verify(&self, subject: &Subject, threshold: Duration) -> FpiResult<()> {
+        if self.sid.len() > MAX_SUBJECT_ID_SIZE {
+            return Err(FpiError::constraint("sid", format!("max-size = {}", MAX_SUBJECT_ID_SIZE)))
+        }
+
+        if self.old_hash.len() > MAX_HASH_SIZE || self.new_hash.len() > MAX_HASH_SIZE {
+            return Err(FpiError::constraint("hash", format!("max-size = {}", MAX_HASH_SIZE)))
+        }
+
+        if self.peers.len() > MAX_PEERS {
+            return Err(FpiError::constraint("peers", format!("max-size = {}", MAX_PEERS)))
+        }
+
+        if !self.sig.sig.check_timestamp(threshold) {
+            return Err(FpiError::Timestamp)
+        }
+
+        let skey = subject.active_key()?;
+        if self.sig.index != skey.sig.index {
+            return Err(FpiError::constraint("sig", "Incorrect key index"))
+        }
+
+        let sig_data = Self::data(&self.sid, &self.old_hash, &self.new_hash, self.threshold, &self.peers);
+        if !self.sig.verify(&skey.key, Self::DOMAIN, &sig_data) {
+            return Err(FpiError::signature("sig"))
+        }
+
+        Ok(())
+    }
+}
+
+impl PeerChange {
+    const DOMAIN: &'static str = "fpi:peer-change";
+
+    // change from the current peer-set (threshold, old_peers) to the new one
+    pub fn sign(sid: &str, threshold: usize, old_peers: &[RistrettoPoint], new_peers: &[RistrettoPoint], sig_s: &Scalar, sig_key: &SubjectKey) -> Self {
+        let (old_hash, new_hash) = (peers_hash(old_peers), peers_hash(new_peers));
+        let sig_data = Self::data(sid, &old_hash, &new_hash, threshold, old_peers);
+        let sig = IndSignature::sign(sig_key.sig.index, sig_s, &sig_key.key, Self::DOMAIN, &sig_data);
+
+        Self { sid: sid.into(), old_hash, new_hash, threshold, peers: old_peers.to_vec(), sig, _phantom: () }
+    }
+
+    // the previous peer-set must be consistent with the old hash
+    pub fn check(&self) -> Result<()> {
+        if peers_hash(&self.peers) != self.old_hash {
+            return Err("Field Constraint - (old_hash, Doesn't match the previous peers)".into())
+        }
+
+        if self.peers.len() < 3 * self.threshold + 1 {
+            return Err("Field Constraint - (peers, Expecting #peers >= 3 * t + 1)".into())
+        }
+
+        Ok(())
+    }
+
+    // the previous (peers_hash, pkeys, t), only if the transition is to the given peer-set
+    pub fn previous(&self, peers_hash: &[u8]) -> Option<(&[u8], &[RistrettoPoint], usize)> {
+        match self.new_hash == peers_hash {
+            true => Some((&self.old_hash, &self.peers, self.threshold)),
+            false => None
+        }
+    }
+
+    fn data(sid: &str, old_hash: &[u8], new_hash: &[u8], threshold: usize, peers: &[RistrettoPoint]) -> [Vec<u8>; 5] {
+        let c_peers: Vec<_> = peers.iter().map(|pkey| pkey.compress()).collect();
+
+        // These unwrap() should never fail, or it's a serious code bug!
+        let b_sid = bincode::serialize(sid).unwrap();
+        let b_old_hash = bincode::serialize(old_hash).unwrap();
+        let b_new_hash = bincode::serialize(new_hash).unwrap();
+        let b_threshold = bincode::serialize(&threshold).unwrap();
+        let b_peers = bincode::serialize(&c_peers).unwrap();
+
+        [b_sid, b_old_hash, b_new_hash, b_threshold, b_peers]
+    }
+}
+
+//-----------------------------------------------------------------------------------------------------------
+// Namespaces (subject-id prefixes reserved for admin-approved registrations, unrestricted by default)
+//-----------------------------------------------------------------------------------------------------------
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+pub struct Namespaces {
+    pub reserved: IndexMap<String, Vec<String>>,    // Reserved prefixes <prefix, approved subject-ids>
+}
+
+impl Namespaces {
+    pub fn new() -> Self {
+        Self { ..Default::default() }
+    }
+
+    pub fn reserve(&mut self, prefix: &str, approved: &[String]) -> &mut Self {
+        self.reserved.insert(prefix.into(), approved.to_vec());
+        self
+    }
+
+    pub fn check(&self, sid: &str) -> Result<()> {
+        for (prefix, approved) in self.reserved.iter() {
+            if sid.starts_with(prefix.as_str()) && !approved.iter().any(|item| item == sid) {
+                return Err(format!("Subject-id is in a reserved namespace: {}", prefix))
+            }
+        }
+
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{G, rnd_scalar};
+
+    #[allow(non_snake_case)]
+    #[test]
+    fn test_peer_set() {
+        let sig_s = rnd_scalar();
+        let sid = "s-id:admin";
+
+        let mut admin = Subject::new(sid);
+        let (_, skey) = admin.evolve(sig_s);
+        admin.keys.push(skey.clone());
+
+        let peers: Vec<(String, RistrettoPoint)> = (0..4).map(|i| (format!("node{}", i), rnd_scalar() * G)).collect();
+        let pset = PeerSet::sign(sid, 1, &peers, &sig_s, &skey);
+        assert!(pset.verify(&admin, Duration::from_secs(5)) == Ok(()));
+        assert!(pset.check() == Ok(()));
+
+        // the hash is computed the same way as in the node/client configurations
+        let keys: Vec<RistrettoPoint> = peers.iter().map(|(_, pkey)| *pkey).collect();
+        assert!(pset.hash() == peers_hash(&keys));
+
+        let mut reordered = peers.clone();
+        reordered.swap(0, 1);
+        let other = PeerSet::sign(sid, 1, &reordered, &sig_s, &skey);
+        assert!(other.hash() != pset.hash());
+
+        // changing the set invalidates the signature
+        let mut tampered = pset.clone();
+        tampered.peers.pop();
+        assert!(tampered.verify(&admin, Duration::from_secs(5)) == Err(FpiError::signature("sig")));
+
+        let small = PeerSet::sign(sid, 1, &peers[..3], &sig_s, &skey);
+        assert!(small.check() == Err("Field Constraint - (peers, Expecting #peers >= 3 * t + 1)".into()));
+    }
+
+    #[test]
+    fn test_peer_change() {
+        let sig_s = rnd_scalar();
+        let sid = "s-id:admin";
+
+        let mut admin = Subject::new(sid);
+        let (_, skey) = admin.evolve(sig_s);
+        admin.keys.push(skey.clone());
+
+        let old_peers: Vec<RistrettoPoint> = (0..4).map(|_| rnd_scalar() * G).collect();
+        let mut new_peers = old_peers[1..].to_vec();
+        new_peers.push(rnd_scalar() * G);
+
+        let change = PeerChange::sign(sid, 1, &old_peers, &new_peers, &sig_s, &skey);
+        assert!(change.verify(&admin, Duration::from_secs(5)) == Ok(()));
+        assert!(change.check() == Ok(()));
+
+        // the transition window is only open for the new peer-set
+        assert!(change.previous(&peers_hash(&new_peers)) == Some((&peers_hash(&old_peers)[..], &old_peers[..], 1)));
+        assert!(change.previous(&peers_hash(&old_peers)).is_none());
+
+        let mut tampered = change.clone();
+        tampered.peers.swap(0, 1);
+        assert!(tampered.verify(&admin, Duration::from_secs(5)) == Err(FpiError::signature("sig")));
+        assert!(tampered.check() == Err("Field Constraint - (old_hash, Doesn't match the previous peers)".into()));
+    }
+
+    #[test]
+    fn test_namespaces() {
+        // unrestricted by default
+        let open = Namespaces::new();
+        assert!(open.check("health:shumy") == Ok(()));
+
+        let mut ns = Namespaces::new();
+        ns.reserve("health:", &["health:hospital-a".to_string()]);
+
+        assert!(ns.check("health:hospital-a") == Ok(()));
+        assert!(ns.check("health:shumy") == Err("Subject-id is in a reserved namespace: health:".into()));
+        assert!(ns.check("s-id:shumy") == Ok(()));
+    }
+}
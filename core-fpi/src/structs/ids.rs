@@ -1,23 +1,34 @@
 use indexmap::IndexMap;
+use std::collections::HashMap;
 use std::fmt::{Debug, Formatter};
 use std::time::Duration;
 
-use serde::{Serialize, Deserialize};
+use serde::{Serialize, Serializer, Deserialize};
+use serde::ser::SerializeTuple;
+use serde::de::{Deserializer, Error, SeqAccess, Visitor};
+use sha2::{Sha256, Sha512, Digest};
 
 use crate::structs::*;
 use crate::crypto::signatures::IndSignature;
-use crate::{G, rnd_scalar, Result, KeyEncoder, Scalar, RistrettoPoint};
+use crate::{G, rnd_scalar, fingerprint, Result, FpiError, FpiResult, KeyEncoder, Scalar, RistrettoPoint};
 
 //-----------------------------------------------------------------------------------------------------------
 // Subject
 //-----------------------------------------------------------------------------------------------------------
-#[derive(Serialize, Deserialize, Default, Clone)]
+// Encoding version of the subject, in the position of the sid length of the first encoding (sid, keys, profiles).
+// Subjects in the first encoding (stored by nodes and clients) are still decoded, but always encoded with the version.
+const SUBJECT_V1: u64 = u64::MAX;
+
+#[allow(clippy::manual_non_exhaustive)]
+#[derive(Default, Clone)]
 pub struct Subject {
     pub sid: String,                                            // Subject ID - <Name>
     pub keys: Vec<SubjectKey>,                                  // All subject keys
-    pub profiles: IndexMap<String, Profile>,                    // All subject profiles <typ:lurl>
+    pub revocations: Vec<Revocation>,                           // Revoked subject keys (compromised)
+    pub profiles: IndexMap<String, Profile>,                    // All subject profiles <typ>
+    pub base: Option<SubjectBase>,                              // Signed digest of the stored subject that the update is based on (None when stored, rejected on creation and never merged)
 
-    #[serde(skip)] _phantom: () // force use of constructor
+    _phantom: () // force use of constructor
 }
 
 impl Debug for Subject {
@@ -25,77 +36,232 @@ impl Debug for Subject {
         fmt.debug_struct("Subject")
             .field("sid", &self.sid)
             .field("keys", &self.keys)
+            .field("revocations", &self.revocations)
             .field("profiles", &self.profiles.values())
+            .field("base", &self.base)
             .finish()
     }
 }
 
+impl Serialize for Subject {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> where S: Serializer {
+        let mut tuple = serializer.serialize_tuple(6)?;
+        tuple.serialize_element(&SUBJECT_V1)?;
+        tuple.serialize_element(&self.sid)?;
+        tuple.serialize_element(&self.keys)?;
+        tuple.serialize_element(&self.revocations)?;
+        tuple.serialize_element(&self.profiles)?;
+        tuple.serialize_element(&self.base)?;
+        tuple.end()
+    }
+}
+
+// Deserialization bypasses the constructor, so re-establish the map-key invariant enforced by push.
+impl<'de> Deserialize<'de> for Subject {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error> where D: Deserializer<'de> {
+        // the first encoding has the sid bytes in place of the version (up to MAX_SUBJECT_ID_SIZE elements)
+        deserializer.deserialize_tuple(MAX_SUBJECT_ID_SIZE + 5, SubjectVisitor)
+    }
+}
+
+struct SubjectVisitor;
+
+impl<'de> Visitor<'de> for SubjectVisitor {
+    type Value = Subject;
+
+    fn expecting(&self, fmt: &mut Formatter) -> std::fmt::Result {
+        fmt.write_str("an encoded subject")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> std::result::Result<Subject, A::Error> where A: SeqAccess<'de> {
+        let version: u64 = element(&mut seq)?;
+        let sid = match version {
+            SUBJECT_V1 => element(&mut seq)?,
+            len if len as usize <= MAX_SUBJECT_ID_SIZE => {
+                let bytes = (0..len).map(|_| element(&mut seq)).collect::<std::result::Result<Vec<u8>, _>>()?;
+                String::from_utf8(bytes).map_err(|_| Error::custom("Invalid subject-id!"))?
+            },
+            _ => return Err(Error::custom("Unsupported subject encoding!"))
+        };
+
+        let keys: Vec<SubjectKey> = element(&mut seq)?;
+        let (revocations, profiles, base) = match version {
+            SUBJECT_V1 => {
+                let revocations: Vec<Revocation> = element(&mut seq)?;
+                let profiles: IndexMap<String, Profile> = element(&mut seq)?;
+                let base: Option<SubjectBase> = element(&mut seq)?;
+                (revocations, profiles, base)
+            },
+            _ => {
+                // the first encoding ends with the profiles, and the profile-keys have no migration pointer
+                let profiles: IndexMap<String, ProfileV0> = element(&mut seq)?;
+                let profiles = profiles.into_iter().map(|(typ, prof)| Ok((typ, prof.upgrade()?)))
+                    .collect::<std::result::Result<IndexMap<String, Profile>, String>>().map_err(Error::custom)?;
+                (Vec::new(), profiles, None)
+            }
+        };
+
+        for (typ, prof) in profiles.iter() {
+            if *typ != prof.typ {
+                return Err(Error::custom("Incorrect profile map-key!"))
+            }
+        }
+
+        Ok(Subject { sid, keys, revocations, profiles, base, _phantom: () })
+    }
+}
+
+// Profiles of the first subject encoding, the profile-keys without the migration pointer.
+#[derive(Deserialize)]
+struct ProfileV0 {
+    typ: String,
+    locations: IndexMap<String, ProfileLocationV0>
+}
+
+#[derive(Deserialize)]
+struct ProfileLocationV0 {
+    lurl: String,
+    chain: Vec<ProfileKeyV0>
+}
+
+#[derive(Deserialize)]
+struct ProfileKeyV0 {
+    index: usize,
+    encrypted: bool,
+    pkey: RistrettoPoint,
+    sig: IndSignature
+}
+
+impl ProfileV0 {
+    fn upgrade(self) -> Result<Profile> {
+        let mut profile = Profile::new(&self.typ);
+        for (lurl, loc) in self.locations.into_iter() {
+            if lurl != loc.lurl {
+                return Err("Incorrect location map-key!".into())
+            }
+
+            let chain = loc.chain.into_iter()
+                .map(|key| ProfileKey { index: key.index, encrypted: key.encrypted, pkey: key.pkey, migrated_to: None, sig: key.sig, _phantom: () })
+                .collect();
+
+            profile.push(ProfileLocation { lurl, chain, _phantom: () });
+        }
+
+        Ok(profile)
+    }
+}
+
+fn element<'de, T: Deserialize<'de>, A: SeqAccess<'de>>(seq: &mut A) -> std::result::Result<T, A::Error> {
+    seq.next_element()?.ok_or_else(|| Error::custom("Incomplete subject!"))
+}
+
 impl Constraints for Subject {
     fn sid(&self) -> &str { &self.sid }
 
-    fn verify(&self, subject: &Subject, threshold: Duration) -> Result<()> {
-        let skey = subject.keys.last().ok_or("No active subject-key found!")?;
+    fn verify(&self, subject: &Subject, threshold: Duration) -> FpiResult<()> {
+        let skey = subject.active_key()?;
 
         // TODO: check "sid" format
         if self.sid.len() > MAX_SUBJECT_ID_SIZE {
-            return Err(format!("Field Constraint - (sid, max-size = {})", MAX_SUBJECT_ID_SIZE))
+            return Err(FpiError::constraint("sid", format!("max-size = {}", MAX_SUBJECT_ID_SIZE)))
+        }
+
+        // signatures are verified against the stored subject (or itself on creation)
+        if self.sid != subject.sid {
+            return Err(FpiError::constraint("sid", "Expected the same subject"))
         }
 
         // it's very important to only submit one key per transaction.
         if self.keys.len() > 1 {
-            return Err(format!("Field Constraint - (keys, max-size = {})", 1))
+            return Err(FpiError::constraint("keys", format!("max-size = {}", 1)))
+        }
+
+        if self.revocations.len() > 1 {
+            return Err(FpiError::constraint("revocations", format!("max-size = {}", 1)))
         }
 
         if self.profiles.len() > MAX_PROFILES {
-            return Err(format!("Field Constraint - (profiles, max-size = {})", MAX_PROFILES))
+            return Err(FpiError::constraint("profiles", format!("max-size = {}", MAX_PROFILES)))
+        }
+
+        // the base is signed by the active key of the stored subject, a relay can't strip or replace it
+        if let Some(base) = &self.base {
+            base.verify(&subject.sid, skey, threshold)?;
+        }
+
+        // the authorizing key is verified before the (more expensive) profile key chains
+        for key in self.keys.iter() {
+            key.verify(&subject.sid, skey, threshold)?;
+
+            // a key-evolution signed by a revoked key must precede the revocation (in the same or a previous transaction)
+            if revoked(self.revocations.iter().chain(subject.revocations.iter()), skey.sig.index, key.sig.sig.timestamp) {
+                return Err(FpiError::constraint("sig", "Signed by a revoked key"))
+            }
+        }
+
+        // profile-keys and revocations submitted with a (verified) key-evolution are signed by the new key
+        let pkey_signer = self.keys.last().unwrap_or(skey);
+
+        for rev in self.revocations.iter() {
+            rev.verify(&subject.sid, pkey_signer, threshold)?;
         }
 
         for (typ, prof) in self.profiles.iter() {
             // TODO: check "typ" format
 
             if typ.len() > MAX_PROFILE_ID_SIZE {
-                return Err(format!("Field Constraint - (profile-id, max-size = {})", MAX_PROFILE_ID_SIZE))
+                return Err(FpiError::constraint("profile-id", format!("max-size = {}", MAX_PROFILE_ID_SIZE)))
             }
 
             if *typ != prof.typ {
-                return Err("Field Constraint - (profile-id, Incorrect map-key)".into())
+                return Err(FpiError::constraint("profile-id", "Incorrect map-key"))
+            }
+
+            if typ.contains(PID_SEPARATOR) {
+                return Err(FpiError::constraint("profile-id", format!("Cannot contain {:?}", PID_SEPARATOR)))
             }
 
             if prof.locations.len() > MAX_LOCATIONS {
-                return Err(format!("Field Constraint - (locations, max-size = {})", MAX_LOCATIONS))
+                return Err(FpiError::constraint("locations", format!("max-size = {}", MAX_LOCATIONS)))
             }
 
             for (lurl, loc) in prof.locations.iter() {
                 // TODO: check "lurl" format
 
                 if lurl.len() > MAX_LOCATION_ID_SIZE {
-                    return Err(format!("Field Constraint - (location-id, max-size = {})", MAX_LOCATION_ID_SIZE))
+                    return Err(FpiError::constraint("location-id", format!("max-size = {}", MAX_LOCATION_ID_SIZE)))
                 }
 
                 if *lurl != loc.lurl {
-                    return Err("Field Constraint - (location-id, Incorrect map-key)".into())
+                    return Err(FpiError::constraint("location-id", "Incorrect map-key"))
                 }
 
                 if loc.chain.len() > MAX_KEY_CHAIN {
-                    return Err(format!("Field Constraint - (chain, max-size = {})", MAX_KEY_CHAIN))
+                    return Err(FpiError::constraint("chain", format!("max-size = {}", MAX_KEY_CHAIN)))
                 }
 
-                let mut prev = loc.chain.get(0).ok_or("Field Constraint - (chain, Location must have keys)")?;
+                let mut prev = loc.chain.first().ok_or_else(|| FpiError::constraint("chain", "Location must have keys"))?;
                 for (i, key) in loc.chain.iter().enumerate() {
                     if i > 0 && prev.index + 1 != key.index {
-                        return Err("Field Constraint - (chain, Keys are not correcly chained)".into())
+                        return Err(FpiError::constraint("chain", "Keys are not correcly chained"))
+                    }
+
+                    if let Some(to) = &key.migrated_to {
+                        if to.len() > MAX_LOCATION_ID_SIZE {
+                            return Err(FpiError::constraint("migrated-to", format!("max-size = {}", MAX_LOCATION_ID_SIZE)))
+                        }
+
+                        if to == lurl {
+                            return Err(FpiError::constraint("migrated-to", "Cannot migrate to the same location"))
+                        }
                     }
 
-                    key.verify(&self.sid, &typ, &lurl, &skey, threshold)?;
+                    key.verify(&self.sid, typ, lurl, pkey_signer, threshold)?;
                     prev = key;
                 }
             }
         }
 
-        for key in self.keys.iter() {
-            key.verify(&subject.sid, &skey, threshold)?;
-        }
-
         Ok(())
     }
 }
@@ -105,6 +271,24 @@ impl Subject {
         Self { sid: sid.into(), ..Default::default() }
     }
 
+    // The key that authorizes the transactions of the subject. Always take it from the stored subject, updates may not have keys.
+    pub fn active_key(&self) -> FpiResult<&SubjectKey> {
+        self.keys.last().ok_or_else(|| FpiError::NotFound("No active subject-key found!".into()))
+    }
+
+    // The subject-key at the signature index, rejecting signatures of a revoked key timestamped after the revocation.
+    // Used to verify historical messages, the transactions are always authorized by the active key.
+    pub fn signing_key(&self, sig: &IndSignature) -> FpiResult<&SubjectKey> {
+        let key = self.keys.iter().find(|key| key.sig.index == sig.index)
+            .ok_or_else(|| FpiError::NotFound(format!("No subject-key found at index {}!", sig.index)))?;
+
+        if revoked(self.revocations.iter(), sig.index, sig.sig.timestamp) {
+            return Err(FpiError::constraint("sig", "Signed by a revoked key"))
+        }
+
+        Ok(key)
+    }
+
     pub fn evolve(&self, sig_s: Scalar) -> (Scalar, SubjectKey) {
         let sig_key = sig_s * G;
         match self.keys.last() {
@@ -117,10 +301,59 @@ impl Subject {
         }
     }
 
+    // Evolve the subject-key and re-sign all active profile-keys under the new key, in the same update.
+    // Re-anchored keys reuse the active public key, so the pseudonym streams don't change.
+    pub fn reanchor(&self, sig_s: Scalar) -> Result<(Scalar, Subject)> {
+        self.active_key()?;
+        let (secret, skey) = self.evolve(sig_s);
+
+        let mut update = Subject::new(&self.sid);
+        for (typ, prof) in self.profiles.iter() {
+            let mut profile = Profile::new(typ);
+            for (lurl, loc) in prof.locations.iter() {
+                if loc.migrated().is_some() {
+                    continue
+                }
+
+                let mut location = ProfileLocation::new(lurl);
+                location.chain.push(loc.reanchor(&self.sid, typ, &secret, &skey)?);
+                profile.push(location);
+            }
+
+            if !profile.locations.is_empty() {
+                update.push(profile);
+            }
+        }
+
+        update.keys.push(skey);
+        Ok((secret, update))
+    }
+
+    // SHA-256 of the encoded subject, identifies the stored state an update is based on
+    pub fn digest(&self) -> String {
+        // This unwrap() should never fail, or it's a serious code bug!
+        let data = bincode::serialize(self).unwrap();
+        let hash = Sha256::new().chain(data).result();
+        hash.as_slice().encode()
+    }
+
+    // Optimistic concurrency, the update is rejected if the stored subject changes before it's delivered.
+    // Signed by the active key of the current subject.
+    pub fn based_on(&mut self, current: &Subject, sig_s: &Scalar, sig_key: &SubjectKey) -> &mut Self {
+        self.base = Some(SubjectBase::sign(&current.sid, &current.digest(), sig_s, sig_key));
+        self
+    }
+
     pub fn find(&self, typ: &str) -> Option<&Profile> {
         self.profiles.get(typ)
     }
 
+    // Find the location of a profile-location-id <typ@lurl>, the key convention used outside the subject maps.
+    pub fn locate(&self, pid: &str) -> Option<&ProfileLocation> {
+        let (typ, lurl) = ProfileLocation::split_pid(pid)?;
+        self.find(typ)?.find(lurl)
+    }
+
     pub fn push(&mut self, profile: Profile) -> &mut Self {
         self.profiles.insert(profile.typ.clone(), profile);
         self
@@ -128,6 +361,7 @@ impl Subject {
 
     pub fn merge(&mut self, update: Subject) {
         self.keys.extend_from_slice(&update.keys);
+        self.revocations.extend_from_slice(&update.revocations);
 
         for (typ, item) in update.profiles.into_iter() {
             match self.profiles.get_mut(&typ) {
@@ -141,6 +375,13 @@ impl Subject {
         match current {
             None => self.check_create(),
             Some(ref current) => {
+                if let Some(base) = &self.base {
+                    if base.digest != current.digest() {
+                        return Err("Concurrent modification, the update is based on a stale subject!".into())
+                    }
+                }
+
+                self.check_revocations(current)?;
                 match self.keys.len() {
                     0 => self.check_update(current),
                     1 => self.check_evolve(current),
@@ -150,6 +391,34 @@ impl Subject {
         }
     }
 
+    // Pairs of profile-keys <typ@lurl#index> sharing the same public key, i.e. linkable pseudonyms.
+    // Migration pointers and re-anchored keys reuse the active key by design and are not reported.
+    pub fn linked_keys(&self) -> Vec<(String, String)> {
+        let mut keys = Vec::<(String, &RistrettoPoint)>::new();
+        for (typ, prof) in self.profiles.iter() {
+            for (lurl, loc) in prof.locations.iter() {
+                for (i, key) in loc.chain.iter().enumerate() {
+                    if key.migrated_to.is_some() || (i > 0 && loc.chain[i - 1].pkey == key.pkey) {
+                        continue
+                    }
+
+                    keys.push((format!("{}#{}", ProfileLocation::pid(typ, lurl), key.index), &key.pkey));
+                }
+            }
+        }
+
+        let mut linked = Vec::<(String, String)>::new();
+        for (i, (id_i, key_i)) in keys.iter().enumerate() {
+            for (id_j, key_j) in keys[i+1..].iter() {
+                if key_i == key_j {
+                    linked.push((id_i.clone(), id_j.clone()));
+                }
+            }
+        }
+
+        linked
+    }
+
     fn check_create(&self) -> Result<()> {
         // if it reaches here it must have one key with index 0
         let active_key = self.keys.last().ok_or("No key found for subject creation!")?;
@@ -157,6 +426,14 @@ impl Subject {
             return Err("Incorrect key index for subject creation!".into())
         }
 
+        if self.base.is_some() {
+            return Err("Subject creation cannot have a base!".into())
+        }
+
+        if !self.revocations.is_empty() {
+            return Err("Subject creation cannot have revocations!".into())
+        }
+
         // check profiles (it's ok if there are no profiles)
         for item in self.profiles.values() {
             item.check(None)?;
@@ -168,15 +445,35 @@ impl Subject {
     fn check_evolve(&self, current: &Subject) -> Result<()>  {
         // it's very important to only submit one key per transaction.
 
-        let active_key = current.keys.last().ok_or("Current subject must have an active key!")?;
+        let active_key = current.active_key()?;
         let new_key = self.keys.last().ok_or("key found for subject evolution!")?;
 
         if active_key.sig.index + 1 != new_key.sig.index {
             return Err("Incorrect index for new subject-key!".into())
         }
 
-        if !self.profiles.is_empty() {
-            return Err("Subject key-evolution cannot have profiles!".into())
+        // only re-anchored profile-keys are accepted with a key-evolution
+        for (typ, item) in self.profiles.iter() {
+            let current_profile = current.profiles.get(typ).ok_or("Subject key-evolution can only re-anchor existing profiles!")?;
+            for (lurl, location) in item.locations.iter() {
+                let current_location = current_profile.locations.get(lurl).ok_or("Subject key-evolution can only re-anchor existing profiles!")?;
+                location.check_reanchor(current_location)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_revocations(&self, current: &Subject) -> Result<()> {
+        for rev in self.revocations.iter() {
+            // the revoked key exists if it's previous to the signing key (stored or the new key of the same transaction)
+            if rev.sig.index > current.active_key()?.sig.index + self.keys.len() {
+                return Err("Revocation signed by an unknown subject-key!".into())
+            }
+
+            if current.revocations.iter().any(|item| item.index == rev.index) {
+                return Err("Subject-key already revoked!".into())
+            }
         }
 
         Ok(())
@@ -188,22 +485,57 @@ impl Subject {
             return Err("self.sid != update.sid".into())
         }
         
-        // check profiles
-        if self.profiles.is_empty() {
+        // check profiles (a revocation may be submitted alone)
+        if self.profiles.is_empty() && self.revocations.is_empty() {
             return Err("Subject update must have at least one profile!".into())
         }
 
+        // An update either creates profiles or adds keys/locations to existing ones, never both.
+        // Inside a profile, new locations may be mixed with keys of existing ones (e.g. the migration pointer and target).
+        let mut created = false;
+        let mut updated = false;
         for (typ, item) in self.profiles.iter() {
-            item.check(current.profiles.get(typ))?;
+            if item.locations.is_empty() {
+                return Err("Subject update with an empty profile!".into())
+            }
+
+            let current_profile = current.profiles.get(typ);
+            match current_profile {
+                None => created = true,
+                Some(_) => updated = true
+            }
+
+            if created && updated {
+                return Err("Subject update cannot mix new and existing profiles!".into())
+            }
+
+            item.check(current_profile)?;
         }
 
         Ok(())
     }
 }
 
+// Pairs of secrets that are equal or differ by one of the known constants, i.e. derivable from each other.
+// Profile secrets must be independent, otherwise the respective pseudonyms are linkable.
+pub fn linked_secrets(secrets: &[(String, Scalar)], constants: &[Scalar]) -> Vec<(String, String)> {
+    let mut linked = Vec::<(String, String)>::new();
+    for (i, (id_i, s_i)) in secrets.iter().enumerate() {
+        for (id_j, s_j) in secrets[i+1..].iter() {
+            let diff = s_i - s_j;
+            if diff == Scalar::zero() || constants.iter().any(|c| diff == *c || -diff == *c) {
+                linked.push((id_i.clone(), id_j.clone()));
+            }
+        }
+    }
+
+    linked
+}
+
 //-----------------------------------------------------------------------------------------------------------
 // SubjectKey
 //-----------------------------------------------------------------------------------------------------------
+#[allow(clippy::manual_non_exhaustive)]
 #[derive(Serialize, Deserialize, Clone)]
 pub struct SubjectKey {
     pub key: RistrettoPoint,                        // The public key
@@ -216,27 +548,35 @@ impl Debug for SubjectKey {
     fn fmt(&self, fmt: &mut Formatter<'_>) -> std::fmt::Result {
         fmt.debug_struct("SubjectKey")
             .field("key", &self.key.encode())
+            .field("fingerprint", &self.fingerprint())
             .field("sig", &self.sig)
             .finish()
     }
 }
 
 impl SubjectKey {
+    const DOMAIN: &'static str = "fpi:subject-key";
+
+    // for the out-of-band verification of the subject key
+    pub fn fingerprint(&self) -> String {
+        fingerprint(&self.key)
+    }
+
     pub fn sign(sid: &str, index: usize, skey: RistrettoPoint, sig_s: &Scalar, sig_key: &RistrettoPoint) -> Self {
         let sig_data = Self::data(sid, index, &skey);
-        let sig = IndSignature::sign(index, sig_s, sig_key, &sig_data);
+        let sig = IndSignature::sign(index, sig_s, sig_key, Self::DOMAIN, &sig_data);
         
         Self { key: skey, sig, _phantom: () }
     }
 
-    fn verify(&self, sid: &str, sig_key: &SubjectKey, threshold: Duration) -> Result<()> {
+    fn verify(&self, sid: &str, sig_key: &SubjectKey, threshold: Duration) -> FpiResult<()> {
         if !self.sig.sig.check_timestamp(threshold) {
-            return Err("Field Constraint - (sig, Timestamp out of valid range)".into())
+            return Err(FpiError::Timestamp)
         }
 
         let sig_data = Self::data(sid, self.sig.index, &self.key);
-        if !self.sig.verify(&sig_key.key, &sig_data) {
-            return Err("Field Constraint - (sig, Invalid signature)".into())
+        if !self.sig.verify(&sig_key.key, Self::DOMAIN, &sig_data) {
+            return Err(FpiError::signature("sig"))
         }
 
         Ok(())
@@ -254,166 +594,518 @@ impl SubjectKey {
     }
 }
 
+// signatures of a revoked key are only accepted up to the revocation timestamp
+fn revoked<'a>(mut revocations: impl Iterator<Item = &'a Revocation>, index: usize, timestamp: i64) -> bool {
+    revocations.any(|rev| rev.index == index && timestamp > rev.at)
+}
+
 //-----------------------------------------------------------------------------------------------------------
-// Profile
+// Revocation
 //-----------------------------------------------------------------------------------------------------------
-#[derive(Serialize, Deserialize, Default, Clone)]
-pub struct Profile {
-    pub typ: String,                                    // Profile Type ex: HealthCare, Financial, Assets, etc
-    pub locations: IndexMap<String, ProfileLocation>,    // Location <lurl>
-    
-    #[serde(skip)] _phantom: (), // force use of constructor
-    
-    // TODO: how to manage replicas without using identity keys?
-}
+#[allow(clippy::manual_non_exhaustive)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Revocation {
+    pub index: usize,                               // Index of the revoked (compromised) subject-key
+    pub at: i64,                                    // Revoked as of this timestamp, the key signatures are valid before it
 
-impl Debug for Profile {
-    fn fmt(&self, fmt: &mut Formatter<'_>) -> std::fmt::Result {
-        fmt.debug_struct("Profile")
-            .field("typ", &self.typ)
-            .field("locations", &self.locations.values())
-            .finish()
-    }
+    pub sig: IndSignature,                          // Signature from a later subject-key for (sid, index, at)
+    #[serde(skip)] _phantom: () // force use of constructor
 }
 
-impl Profile {
-    pub fn new(typ: &str) -> Self {
-        Self { typ: typ.into(), ..Default::default() }
-    }
+impl Revocation {
+    const DOMAIN: &'static str = "fpi:revocation";
 
-    pub fn find(&self, lurl: &str) -> Option<&ProfileLocation> {
-        self.locations.get(lurl)
-    }
+    pub fn sign(sid: &str, index: usize, at: i64, sig_s: &Scalar, sig_key: &SubjectKey) -> Self {
+        let sig_data = Self::data(sid, index, at);
+        let sig = IndSignature::sign(sig_key.sig.index, sig_s, &sig_key.key, Self::DOMAIN, &sig_data);
 
-    pub fn evolve(&self, sid: &str, lurl: &str, encrypted: bool, sig_s: &Scalar, sig_key: &SubjectKey) -> (Scalar, ProfileLocation) {
-        match self.locations.get(lurl) {
-            None => {
-                let mut location = ProfileLocation::new(lurl);
-                let (secret, pkey) = location.evolve(sid, &self.typ, encrypted, sig_s, sig_key);
-                location.chain.push(pkey);
-                (secret, location)
-            },
-            Some(location) => {
-                let (secret, pkey) = location.evolve(sid, &self.typ, encrypted, sig_s, sig_key);
+        Self { index, at, sig, _phantom: () }
+    }
 
-                let mut location = ProfileLocation::new(lurl);
-                location.chain.push(pkey);
-                (secret, location)
-            }
+    fn verify(&self, sid: &str, sig_key: &SubjectKey, threshold: Duration) -> FpiResult<()> {
+        if self.index >= self.sig.index {
+            return Err(FpiError::constraint("index", "Expected a previous subject-key"))
         }
-    }
 
-    pub fn push(&mut self, location: ProfileLocation) -> &mut Self {
-        self.locations.insert(location.lurl.clone(), location);
-        self
-    }
+        if self.at > self.sig.sig.timestamp {
+            return Err(FpiError::constraint("at", "Cannot be after the signature"))
+        }
 
-    fn merge(&mut self, update: Profile) {
-        for (lurl, item) in update.locations.into_iter() {
-            match self.locations.get_mut(&lurl) {
-                None => {self.locations.insert(lurl, item);},
-                Some(ref mut current) => current.merge(item)
-            }
+        if !self.sig.sig.check_timestamp(threshold) {
+            return Err(FpiError::Timestamp)
         }
-    }
 
-    fn check(&self, current: Option<&Profile>) -> Result<()> {
-        for (lurl, item) in self.locations.iter() {
-            let current_location = match current {
-                None => None,
-                Some(current) => {
-                    current.locations.get(lurl)
-                }
-            };
+        if self.sig.index != sig_key.sig.index {
+            return Err(FpiError::constraint("sig", "Incorrect key index"))
+        }
 
-            item.check(current_location)?;
+        let sig_data = Self::data(sid, self.index, self.at);
+        if !self.sig.verify(&sig_key.key, Self::DOMAIN, &sig_data) {
+            return Err(FpiError::signature("sig"))
         }
 
         Ok(())
     }
+
+    fn data(sid: &str, index: usize, at: i64) -> [Vec<u8>; 3] {
+        // These unwrap() should never fail, or it's a serious code bug!
+        let b_sid = bincode::serialize(sid).unwrap();
+        let b_index = bincode::serialize(&index).unwrap();
+        let b_at = bincode::serialize(&at).unwrap();
+
+        [b_sid, b_index, b_at]
+    }
 }
 
 //-----------------------------------------------------------------------------------------------------------
-// ProfileLocation
+// SubjectBase
 //-----------------------------------------------------------------------------------------------------------
-#[derive(Serialize, Deserialize, Default, Clone)]
-pub struct ProfileLocation {
-    pub lurl: String,                           // Location URL (URL for the profile server)
-    pub chain: Vec<ProfileKey>,
+#[allow(clippy::manual_non_exhaustive)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SubjectBase {
+    pub digest: String,                             // Digest of the stored subject that the update is based on
 
+    pub sig: IndSignature,                          // Signature from the active subject-key for (sid, digest)
     #[serde(skip)] _phantom: () // force use of constructor
 }
 
-impl Debug for ProfileLocation {
-    fn fmt(&self, fmt: &mut Formatter<'_>) -> std::fmt::Result {
-        fmt.debug_struct("ProfileLocation")
-            .field("lurl", &self.lurl)
-            .field("chain", &self.chain)
-            .finish()
-    }
-}
-
-impl ProfileLocation {
-    pub fn pid(typ: &str, lurl: &str) -> String {
-        format!("{}@{}", typ, lurl).to_string()
-    }
-
-    pub fn new(lurl: &str) -> Self {
-        Self { lurl: lurl.into(), ..Default::default() }
-    }
-
-    pub fn evolve(&self, sid: &str, typ: &str, encrypted: bool, sig_s: &Scalar, sig_key: &SubjectKey) -> (Scalar, ProfileKey) {
-        let secret = rnd_scalar();
-        let pkey = secret * G;
+impl SubjectBase {
+    const DOMAIN: &'static str = "fpi:subject-base";
 
-        let pkey = match self.chain.last() {
-            None => ProfileKey::sign(sid, typ, &self.lurl, 0, encrypted, pkey, sig_s, sig_key),
-            Some(active) => ProfileKey::sign(sid, typ, &self.lurl, active.index + 1, encrypted, pkey, sig_s, sig_key)
-        };
+    pub fn sign(sid: &str, digest: &str, sig_s: &Scalar, sig_key: &SubjectKey) -> Self {
+        let sig_data = Self::data(sid, digest);
+        let sig = IndSignature::sign(sig_key.sig.index, sig_s, &sig_key.key, Self::DOMAIN, &sig_data);
 
-        (secret, pkey)
+        Self { digest: digest.into(), sig, _phantom: () }
     }
 
-    fn merge(&mut self, update: ProfileLocation) {
-        self.chain.extend(update.chain);
-    }
+    fn verify(&self, sid: &str, sig_key: &SubjectKey, threshold: Duration) -> FpiResult<()> {
+        if self.digest.len() > MAX_HASH_SIZE {
+            return Err(FpiError::constraint("base", format!("max-size = {}", MAX_HASH_SIZE)))
+        }
 
-    fn check(&self, current: Option<&ProfileLocation>) -> Result<()> {
-        // check profile
-        let mut prev = match current {
-            None => {
-                // TODO: check "typ" and "lurl" fields?
-                -1
-            },
-            Some(current) => {
-                let pkey = current.chain.last().ok_or("Current profile-location must have keys!")?;
-                pkey.index as i32
-            }
-        };
+        if !self.sig.sig.check_timestamp(threshold) {
+            return Err(FpiError::Timestamp)
+        }
 
-        for item in self.chain.iter() {
-            if prev + 1 != item.index as i32 {
-                return Err("ProfileKey is not correcly chained!".into())
-            }
+        if self.sig.index != sig_key.sig.index {
+            return Err(FpiError::constraint("base", "Incorrect key index"))
+        }
 
-            prev = item.index as i32;
+        let sig_data = Self::data(sid, &self.digest);
+        if !self.sig.verify(&sig_key.key, Self::DOMAIN, &sig_data) {
+            return Err(FpiError::signature("base"))
         }
 
         Ok(())
     }
-}
 
+    fn data(sid: &str, digest: &str) -> [Vec<u8>; 2] {
+        // These unwrap() should never fail, or it's a serious code bug!
+        let b_sid = bincode::serialize(sid).unwrap();
+        let b_digest = bincode::serialize(digest).unwrap();
 
-//-----------------------------------------------------------------------------------------------------------
+        [b_sid, b_digest]
+    }
+}
+
+//-----------------------------------------------------------------------------------------------------------
+// Subject Erasure (right to be forgotten)
+//-----------------------------------------------------------------------------------------------------------
+#[allow(clippy::manual_non_exhaustive)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Erase {
+    pub sid: String,                                // Subject-id to erase (subject, authorizations and evidences)
+
+    pub sig: IndSignature,                          // Signature from the active subject-key
+    #[serde(skip)] _phantom: () // force use of constructor
+}
+
+impl Constraints for Erase {
+    fn sid(&self) -> &str { &self.sid }
+
+    fn verify(&self, subject: &Subject, threshold: Duration) -> FpiResult<()> {
+        if self.sid.len() > MAX_SUBJECT_ID_SIZE {
+            return Err(FpiError::constraint("sid", format!("max-size = {}", MAX_SUBJECT_ID_SIZE)))
+        }
+
+        if !self.sig.sig.check_timestamp(threshold) {
+            return Err(FpiError::Timestamp)
+        }
+
+        let skey = subject.active_key()?;
+        if self.sig.index != skey.sig.index {
+            return Err(FpiError::constraint("sig", "Incorrect key index"))
+        }
+
+        let sig_data = Self::data(&self.sid);
+        if !self.sig.verify(&skey.key, Self::DOMAIN, &sig_data) {
+            return Err(FpiError::signature("sig"))
+        }
+
+        Ok(())
+    }
+}
+
+impl Erase {
+    const DOMAIN: &'static str = "fpi:erase";
+
+    pub fn sign(sid: &str, sig_s: &Scalar, sig_key: &SubjectKey) -> Self {
+        let sig_data = Self::data(sid);
+        let sig = IndSignature::sign(sig_key.sig.index, sig_s, &sig_key.key, Self::DOMAIN, &sig_data);
+
+        Self { sid: sid.into(), sig, _phantom: () }
+    }
+
+    fn data(sid: &str) -> [Vec<u8>; 1] {
+        // This unwrap() should never fail, or it's a serious code bug!
+        let b_sid = bincode::serialize(sid).unwrap();
+
+        [b_sid]
+    }
+}
+
+//-----------------------------------------------------------------------------------------------------------
+// Profile
+//-----------------------------------------------------------------------------------------------------------
+#[derive(Deserialize)]
+struct SerializedProfile {
+    pub typ: String,
+    pub locations: IndexMap<String, ProfileLocation>
+}
+
+#[allow(clippy::manual_non_exhaustive)]
+#[derive(Serialize, Default, Clone)]
+pub struct Profile {
+    pub typ: String,                                    // Profile Type ex: HealthCare, Financial, Assets, etc
+    pub locations: IndexMap<String, ProfileLocation>,    // Location <lurl>
+    
+    #[serde(skip)] _phantom: (), // force use of constructor
+    
+    // TODO: how to manage replicas without using identity keys?
+}
+
+impl Debug for Profile {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> std::fmt::Result {
+        fmt.debug_struct("Profile")
+            .field("typ", &self.typ)
+            .field("locations", &self.locations.values())
+            .finish()
+    }
+}
+
+// Deserialization bypasses the constructor, so re-establish the map-key invariant enforced by push.
+impl<'de> Deserialize<'de> for Profile {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error> where D: Deserializer<'de> {
+        let sp = SerializedProfile::deserialize(deserializer)?;
+
+        for (lurl, loc) in sp.locations.iter() {
+            if *lurl != loc.lurl {
+                return Err(Error::custom("Incorrect location map-key!"))
+            }
+        }
+
+        Ok(Self { typ: sp.typ, locations: sp.locations, _phantom: () })
+    }
+}
+
+impl Profile {
+    pub fn new(typ: &str) -> Self {
+        Self { typ: typ.into(), ..Default::default() }
+    }
+
+    pub fn find(&self, lurl: &str) -> Option<&ProfileLocation> {
+        self.locations.get(lurl)
+    }
+
+    pub fn evolve(&self, sid: &str, lurl: &str, encrypted: bool, sig_s: &Scalar, sig_key: &SubjectKey) -> (Scalar, ProfileLocation) {
+        let (secret, pkey) = match self.locations.get(lurl) {
+            None => ProfileLocation::new(lurl).evolve(sid, &self.typ, encrypted, sig_s, sig_key),
+            Some(location) => location.evolve(sid, &self.typ, encrypted, sig_s, sig_key)
+        };
+
+        let mut location = ProfileLocation::new(lurl);
+        location.chain.push(pkey);
+        (secret, location)
+    }
+
+    // Same as evolve, but the secret is derived from the subject-key secret (opt-in, no forward secrecy)
+    pub fn derive(&self, sid: &str, lurl: &str, encrypted: bool, sig_s: &Scalar, sig_key: &SubjectKey) -> (Scalar, ProfileLocation) {
+        let (secret, pkey) = match self.locations.get(lurl) {
+            None => ProfileLocation::new(lurl).derive(sid, &self.typ, encrypted, sig_s, sig_key),
+            Some(location) => location.derive(sid, &self.typ, encrypted, sig_s, sig_key)
+        };
+
+        let mut location = ProfileLocation::new(lurl);
+        location.chain.push(pkey);
+        (secret, location)
+    }
+
+    pub fn migrate(&self, sid: &str, from: &str, to: &str, sig_s: &Scalar, sig_key: &SubjectKey) -> Result<(Scalar, Profile)> {
+        let current = self.locations.get(from).ok_or("No profile-location found to migrate!")?;
+        if self.locations.contains_key(to) {
+            return Err("Profile-location already exists for the migration target!".into())
+        }
+
+        let active = current.chain.last().ok_or("Profile-location must have keys to migrate!")?;
+        let encrypted = active.encrypted;
+
+        let mut pointer = ProfileLocation::new(from);
+        pointer.chain.push(current.migrate(sid, &self.typ, to, sig_s, sig_key)?);
+
+        let (secret, target) = self.evolve(sid, to, encrypted, sig_s, sig_key);
+
+        let mut profile = Profile::new(&self.typ);
+        profile.push(pointer).push(target);
+        Ok((secret, profile))
+    }
+
+    pub fn push(&mut self, location: ProfileLocation) -> &mut Self {
+        self.locations.insert(location.lurl.clone(), location);
+        self
+    }
+
+    fn merge(&mut self, update: Profile) {
+        for (lurl, item) in update.locations.into_iter() {
+            match self.locations.get_mut(&lurl) {
+                None => {self.locations.insert(lurl, item);},
+                Some(ref mut current) => current.merge(item)
+            }
+        }
+    }
+
+    fn check(&self, current: Option<&Profile>) -> Result<()> {
+        for (lurl, item) in self.locations.iter() {
+            let current_location = match current {
+                None => None,
+                Some(current) => {
+                    current.locations.get(lurl)
+                }
+            };
+
+            item.check(current_location)?;
+        }
+
+        Ok(())
+    }
+}
+
+//-----------------------------------------------------------------------------------------------------------
+// ProfileBuilder
+//-----------------------------------------------------------------------------------------------------------
+// Builds a profile update chained on the current profile (if any), collecting the secrets of the new locations <pid>.
+pub struct ProfileBuilder<'a> {
+    sid: String,
+    current: Option<&'a Profile>,
+    sig_s: &'a Scalar,
+    sig_key: &'a SubjectKey,
+
+    profile: Profile,
+    secrets: HashMap<String, Scalar>
+}
+
+impl<'a> ProfileBuilder<'a> {
+    pub fn new(sid: &str, typ: &str, current: Option<&'a Profile>, sig_s: &'a Scalar, sig_key: &'a SubjectKey) -> Self {
+        Self { sid: sid.into(), current, sig_s, sig_key, profile: Profile::new(typ), secrets: HashMap::new() }
+    }
+
+    pub fn evolve(&mut self, lurl: &str, encrypted: bool) -> Result<&mut Self> {
+        let (secret, location) = self.base().evolve(&self.sid, lurl, encrypted, self.sig_s, self.sig_key);
+        self.push(secret, location)
+    }
+
+    // Same as evolve, but the secret is derived from the subject-key secret (opt-in, no forward secrecy)
+    pub fn derive(&mut self, lurl: &str, encrypted: bool) -> Result<&mut Self> {
+        let (secret, location) = self.base().derive(&self.sid, lurl, encrypted, self.sig_s, self.sig_key);
+        self.push(secret, location)
+    }
+
+    // accepts only locations with one key, chained on the current profile-location
+    pub fn push(&mut self, secret: Scalar, location: ProfileLocation) -> Result<&mut Self> {
+        if location.chain.len() != 1 {
+            return Err("ProfileBuilder expects a location with one key!".into())
+        }
+
+        if self.profile.locations.contains_key(&location.lurl) {
+            return Err("ProfileBuilder already has the profile-location!".into())
+        }
+
+        let current = self.current.and_then(|profile| profile.find(&location.lurl));
+        location.check(current)?;
+
+        self.secrets.insert(ProfileLocation::pid(&self.profile.typ, &location.lurl), secret);
+        self.profile.push(location);
+        Ok(self)
+    }
+
+    pub fn build(self) -> Result<(HashMap<String, Scalar>, Profile)> {
+        if self.profile.locations.is_empty() {
+            return Err("ProfileBuilder without locations!".into())
+        }
+
+        Ok((self.secrets, self.profile))
+    }
+
+    fn base(&self) -> &Profile {
+        self.current.unwrap_or(&self.profile)
+    }
+}
+
+//-----------------------------------------------------------------------------------------------------------
+// ProfileLocation
+//-----------------------------------------------------------------------------------------------------------
+#[allow(clippy::manual_non_exhaustive)]
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct ProfileLocation {
+    pub lurl: String,                           // Location URL (URL for the profile server)
+    pub chain: Vec<ProfileKey>,
+
+    #[serde(skip)] _phantom: () // force use of constructor
+}
+
+impl Debug for ProfileLocation {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> std::fmt::Result {
+        fmt.debug_struct("ProfileLocation")
+            .field("lurl", &self.lurl)
+            .field("chain", &self.chain)
+            .finish()
+    }
+}
+
+impl ProfileLocation {
+    pub fn pid(typ: &str, lurl: &str) -> String {
+        format!("{}{}{}", typ, PID_SEPARATOR, lurl)
+    }
+
+    // The profile type cannot contain the separator, so the first one splits the pid.
+    pub fn split_pid(pid: &str) -> Option<(&str, &str)> {
+        let pos = pid.find(PID_SEPARATOR)?;
+        Some((&pid[..pos], &pid[pos + 1..]))
+    }
+
+    pub fn new(lurl: &str) -> Self {
+        Self { lurl: lurl.into(), ..Default::default() }
+    }
+
+    pub fn evolve(&self, sid: &str, typ: &str, encrypted: bool, sig_s: &Scalar, sig_key: &SubjectKey) -> (Scalar, ProfileKey) {
+        let secret = rnd_scalar();
+        let pkey = ProfileKey::sign(sid, typ, &self.lurl, self.next(), encrypted, secret * G, None, sig_s, sig_key);
+        (secret, pkey)
+    }
+
+    // The secret is recomputable from the subject-key secret that signs the profile-key
+    pub fn derive(&self, sid: &str, typ: &str, encrypted: bool, sig_s: &Scalar, sig_key: &SubjectKey) -> (Scalar, ProfileKey) {
+        let index = self.next();
+        let secret = ProfileLocation::derive_secret(sig_s, typ, &self.lurl, index);
+        let pkey = ProfileKey::sign(sid, typ, &self.lurl, index, encrypted, secret * G, None, sig_s, sig_key);
+        (secret, pkey)
+    }
+
+    // H(subject-secret || typ || lurl || index)
+    pub fn derive_secret(sig_s: &Scalar, typ: &str, lurl: &str, index: usize) -> Scalar {
+        let hasher = Sha512::new()
+            .chain(sig_s.as_bytes())
+            .chain(bincode::serialize(typ).unwrap())
+            .chain(bincode::serialize(lurl).unwrap())
+            .chain(bincode::serialize(&index).unwrap());
+
+        Scalar::from_hash(hasher)
+    }
+
+    fn next(&self) -> usize {
+        match self.chain.last() {
+            None => 0,
+            Some(active) => active.index + 1
+        }
+    }
+
+    pub fn migrate(&self, sid: &str, typ: &str, to: &str, sig_s: &Scalar, sig_key: &SubjectKey) -> Result<ProfileKey> {
+        let active = self.chain.last().ok_or("Profile-location must have keys to migrate!")?;
+        if active.migrated_to.is_some() {
+            return Err("Profile-location is already migrated!".into())
+        }
+
+        // the pointer reuses the active key, so the pseudonym stream doesn't change
+        Ok(ProfileKey::sign(sid, typ, &self.lurl, active.index + 1, active.encrypted, active.pkey, Some(to.into()), sig_s, sig_key))
+    }
+
+    pub fn reanchor(&self, sid: &str, typ: &str, sig_s: &Scalar, sig_key: &SubjectKey) -> Result<ProfileKey> {
+        let active = self.chain.last().ok_or("Profile-location must have keys to re-anchor!")?;
+        if active.migrated_to.is_some() {
+            return Err("Profile-location is migrated, no more keys are accepted!".into())
+        }
+
+        // same as the migration pointer, the active key is reused
+        Ok(ProfileKey::sign(sid, typ, &self.lurl, active.index + 1, active.encrypted, active.pkey, None, sig_s, sig_key))
+    }
+
+    pub fn migrated(&self) -> Option<&str> {
+        match self.chain.last() {
+            None => None,
+            Some(active) => active.migrated_to.as_deref()
+        }
+    }
+
+    fn merge(&mut self, update: ProfileLocation) {
+        self.chain.extend(update.chain);
+    }
+
+    fn check_reanchor(&self, current: &ProfileLocation) -> Result<()> {
+        self.check(Some(current))?;
+
+        let active = current.chain.last().ok_or("Current profile-location must have keys!")?;
+        match self.chain.as_slice() {
+            [item] if item.pkey == active.pkey && item.encrypted == active.encrypted && item.migrated_to.is_none() => Ok(()),
+            _ => Err("Subject key-evolution can only re-anchor the active profile-keys!".into())
+        }
+    }
+
+    fn check(&self, current: Option<&ProfileLocation>) -> Result<()> {
+        // check profile
+        let mut prev = match current {
+            None => {
+                // TODO: check "typ" and "lurl" fields?
+                -1
+            },
+            Some(current) => {
+                let pkey = current.chain.last().ok_or("Current profile-location must have keys!")?;
+                if pkey.migrated_to.is_some() {
+                    return Err("Profile-location is migrated, no more keys are accepted!".into())
+                }
+
+                pkey.index as i32
+            }
+        };
+
+        for (i, item) in self.chain.iter().enumerate() {
+            if prev + 1 != item.index as i32 {
+                return Err("ProfileKey is not correcly chained!".into())
+            }
+
+            if item.migrated_to.is_some() && i + 1 != self.chain.len() {
+                return Err("Profile-location migration must be the last key!".into())
+            }
+
+            prev = item.index as i32;
+        }
+
+        Ok(())
+    }
+}
+
+
+//-----------------------------------------------------------------------------------------------------------
 // ProfileKey
 //-----------------------------------------------------------------------------------------------------------
+#[allow(clippy::manual_non_exhaustive)]
 #[derive(Serialize, Deserialize, Clone)]
 pub struct ProfileKey {
     pub index: usize,                       // Profile key index on the vector
     pub encrypted: bool,                    // is the stream encrypted
     pub pkey: RistrettoPoint,               // Public key to derive the pseudonym
+    pub migrated_to: Option<String>,        // Location URL where the profile was migrated (final key of the chain)
 
-    pub sig: IndSignature,                  // Subject signature for (sid, typ, lurl, index, key)
+    pub sig: IndSignature,                  // Subject signature for (sid, typ, lurl, index, key, migrated_to)
     #[serde(skip)] _phantom: () // force use of constructor
 }
 
@@ -423,33 +1115,40 @@ impl Debug for ProfileKey {
             .field("index", &self.index)
             .field("encrypted", &self.encrypted)
             .field("pkey", &self.pkey.encode())
+            .field("migrated_to", &self.migrated_to)
             .field("sig", &self.sig)
             .finish()
     }
 }
 
 impl ProfileKey {
-    pub fn sign(sid: &str, typ: &str, lurl: &str, index: usize, encrypted: bool, pkey: RistrettoPoint, sig_s: &Scalar, sig_key: &SubjectKey) -> Self {
-        let sig_data = Self::data(sid, typ, lurl, index, encrypted, &pkey);
-        let sig = IndSignature::sign(sig_key.sig.index, sig_s, &sig_key.key, &sig_data);
+    const DOMAIN: &'static str = "fpi:profile-key";
+
+    pub fn sign(sid: &str, typ: &str, lurl: &str, index: usize, encrypted: bool, pkey: RistrettoPoint, migrated_to: Option<String>, sig_s: &Scalar, sig_key: &SubjectKey) -> Self {
+        let sig_data = Self::data(sid, typ, lurl, index, encrypted, &pkey, &migrated_to);
+        let sig = IndSignature::sign(sig_key.sig.index, sig_s, &sig_key.key, Self::DOMAIN, &sig_data);
         
-        Self { index, encrypted, pkey, sig, _phantom: () }
+        Self { index, encrypted, pkey, migrated_to, sig, _phantom: () }
     }
 
-    fn verify(&self, sid: &str, typ: &str, lurl: &str, sig_key: &SubjectKey, threshold: Duration) -> Result<()> {
+    fn verify(&self, sid: &str, typ: &str, lurl: &str, sig_key: &SubjectKey, threshold: Duration) -> FpiResult<()> {
         if !self.sig.sig.check_timestamp(threshold) {
-            return Err("Field Constraint - (sig, Timestamp out of valid range)".into())
+            return Err(FpiError::Timestamp)
         }
 
-        let sig_data = Self::data(sid, typ, lurl, self.index, self.encrypted, &self.pkey);
-        if !self.sig.verify(&sig_key.key, &sig_data) {
-            return Err("Field Constraint - (sig, Invalid signature)".into())
+        if self.sig.index != sig_key.sig.index {
+            return Err(FpiError::constraint("sig", "Incorrect key index"))
+        }
+
+        let sig_data = Self::data(sid, typ, lurl, self.index, self.encrypted, &self.pkey, &self.migrated_to);
+        if !self.sig.verify(&sig_key.key, Self::DOMAIN, &sig_data) {
+            return Err(FpiError::signature("sig"))
         }
 
         Ok(())
     }
 
-    fn data(sid: &str, typ: &str, lurl: &str, index: usize, encrypted: bool, pkey: &RistrettoPoint) -> [Vec<u8>; 6] {
+    fn data(sid: &str, typ: &str, lurl: &str, index: usize, encrypted: bool, pkey: &RistrettoPoint, migrated_to: &Option<String>) -> [Vec<u8>; 7] {
         let p_key = pkey.compress();
 
         // These unwrap() should never fail, or it's a serious code bug!
@@ -459,8 +1158,9 @@ impl ProfileKey {
         let b_index = bincode::serialize(&index).unwrap();
         let b_encrypted = bincode::serialize(&encrypted).unwrap();
         let b_pkey = bincode::serialize(&p_key).unwrap();
+        let b_migrated_to = bincode::serialize(migrated_to).unwrap();
 
-        [b_sid, b_typ, b_lurl, b_index, b_encrypted, b_pkey]
+        [b_sid, b_typ, b_lurl, b_index, b_encrypted, b_pkey, b_migrated_to]
     }
 }
 
@@ -469,6 +1169,7 @@ impl ProfileKey {
 mod tests {
     use super::*;
     use crate::{G, rnd_scalar};
+    use crate::messages::{encode, decode};
 
     #[allow(non_snake_case)]
     #[test]
@@ -483,10 +1184,10 @@ mod tests {
         let (_, skey1) = new1.evolve(sig_s1);
 
         let mut p1 = Profile::new("Assets");
-        p1.push(p1.evolve(&sid, "https://profile-url.org", false, &sig_s1, &skey1).1);
+        p1.push(p1.evolve(sid, "https://profile-url.org", false, &sig_s1, &skey1).1);
 
         let mut p2 = Profile::new("Finance");
-        p2.push(p2.evolve(&sid, "https://profile-url.org", false, &sig_s1, &skey1).1);
+        p2.push(p2.evolve(sid, "https://profile-url.org", false, &sig_s1, &skey1).1);
 
         new1
             .push(p1)
@@ -588,14 +1289,14 @@ mod tests {
 
         let mut incorrect = Subject::new(sid);
         incorrect.keys.push(skey3);
-        assert!(incorrect.verify(&new1, Duration::from_secs(5)) == Err("Field Constraint - (sig, Invalid signature)".into()));
+        assert!(incorrect.verify(&new1, Duration::from_secs(5)) == Err(FpiError::signature("sig")));
 
         //--------------------------------------------------
         // Updating Profile
         // -------------------------------------------------
         let mut p2 = Profile::new("Assets");
         let mut p2_loc = p1.evolve(sid, "https://profile-url.org", false, &sig_s1, &skey1).1;
-        let mut p2_key = &mut p2_loc.chain[0];
+        let p2_key = &mut p2_loc.chain[0];
         p2_key.index = 0usize;
         p2.push(p2_loc);
 
@@ -604,4 +1305,800 @@ mod tests {
         assert!(update1.check(&Some(new1.clone())) == Err("ProfileKey is not correcly chained!".into()));
 
     }
+
+    #[allow(non_snake_case)]
+    #[test]
+    fn test_bypass_constructor() {
+        let sig_s1 = rnd_scalar();
+        let sid = "s-id:shumy";
+
+        let mut new1 = Subject::new(sid);
+        let (_, skey1) = new1.evolve(sig_s1);
+        new1.keys.push(skey1.clone());
+
+        //--------------------------------------------------
+        // ProfileKey signed with a different key index
+        // -------------------------------------------------
+        let mut p1 = Profile::new("Assets");
+        let mut p1_loc = p1.evolve(sid, "https://profile-url.org", false, &sig_s1, &skey1).1;
+        p1_loc.chain[0].sig.index = 1;
+        p1.push(p1_loc);
+
+        let mut update1 = Subject::new(sid);
+        update1.push(p1);
+        assert!(update1.verify(&new1, Duration::from_secs(5)) == Err(FpiError::constraint("sig", "Incorrect key index")));
+
+        //--------------------------------------------------
+        // Profiles and locations under the wrong map-key
+        // -------------------------------------------------
+        let mut p2 = Profile::new("Assets");
+        p2.push(p2.evolve(sid, "https://profile-url.org", false, &sig_s1, &skey1).1);
+
+        let mut update2 = Subject::new(sid);
+        update2.profiles.insert("Finance".into(), p2.clone());
+
+        let data = encode(&update2).unwrap();
+        assert!(decode::<Subject>(&data).is_err());
+
+        let loc = p2.locations.values().next().unwrap().clone();
+        p2.locations.insert("https://other-url.org".into(), loc);
+
+        let mut update3 = Subject::new(sid);
+        update3.push(p2);
+
+        let data = encode(&update3).unwrap();
+        assert!(decode::<Subject>(&data).is_err());
+
+        // a correctly built subject survives the round trip
+        let data = encode(&new1).unwrap();
+        let decoded: Subject = decode(&data).unwrap();
+        assert!(decoded.verify(&new1, Duration::from_secs(5)) == Ok(()));
+    }
+
+    #[test]
+    fn test_migrate_location() {
+        let sig_s1 = rnd_scalar();
+        let sid = "s-id:shumy";
+
+        let mut new1 = Subject::new(sid);
+        let (_, skey1) = new1.evolve(sig_s1);
+
+        let mut p1 = Profile::new("Assets");
+        p1.push(p1.evolve(sid, "https://old-url.org", true, &sig_s1, &skey1).1);
+
+        new1.push(p1).keys.push(skey1.clone());
+        assert!(new1.check(&None) == Ok(()));
+
+        //--------------------------------------------------
+        // Migrating the location
+        // -------------------------------------------------
+        let current = new1.find("Assets").unwrap();
+        assert!(current.migrate(sid, "https://none-url.org", "https://new-url.org", &sig_s1, &skey1).is_err());
+        assert!(current.migrate(sid, "https://old-url.org", "https://old-url.org", &sig_s1, &skey1).is_err());
+
+        let (_, p2) = current.migrate(sid, "https://old-url.org", "https://new-url.org", &sig_s1, &skey1).unwrap();
+        let mut update1 = Subject::new(sid);
+        update1.push(p2.clone());
+        assert!(update1.verify(&new1, Duration::from_secs(5)) == Ok(()));
+        assert!(update1.check(&Some(new1.clone())) == Ok(()));
+
+        let pointer = p2.find("https://old-url.org").unwrap();
+        assert!(pointer.migrated() == Some("https://new-url.org"));
+        assert!(pointer.chain[0].index == 1);
+        assert!(pointer.chain[0].pkey == current.find("https://old-url.org").unwrap().chain[0].pkey);
+
+        let target = p2.find("https://new-url.org").unwrap();
+        assert!(target.migrated().is_none());
+        assert!(target.chain[0].encrypted);
+
+        // the pointer is part of the signature
+        let mut update2 = update1.clone();
+        update2.profiles["Assets"].locations["https://old-url.org"].chain[0].migrated_to = Some("https://evil-url.org".into());
+        assert!(update2.verify(&new1, Duration::from_secs(5)) == Err(FpiError::signature("sig")));
+
+        //--------------------------------------------------
+        // No more keys after the migration
+        // -------------------------------------------------
+        new1.merge(update1);
+        let current = new1.find("Assets").unwrap();
+        assert!(current.find("https://old-url.org").unwrap().migrated() == Some("https://new-url.org"));
+        assert!(current.migrate(sid, "https://old-url.org", "https://other-url.org", &sig_s1, &skey1).is_err());
+
+        let mut p3 = Profile::new("Assets");
+        p3.push(current.evolve(sid, "https://old-url.org", false, &sig_s1, &skey1).1);
+
+        let mut update3 = Subject::new(sid);
+        update3.push(p3);
+        assert!(update3.verify(&new1, Duration::from_secs(5)) == Ok(()));
+        assert!(update3.check(&Some(new1.clone())) == Err("Profile-location is migrated, no more keys are accepted!".into()));
+    }
+
+    #[test]
+    fn test_update_composition() {
+        let sig_s1 = rnd_scalar();
+        let sid = "s-id:shumy";
+
+        let mut new1 = Subject::new(sid);
+        let (_, skey1) = new1.evolve(sig_s1);
+        new1.keys.push(skey1.clone());
+
+        let mut assets = Profile::new("Assets");
+        assets.push(assets.evolve(sid, "https://profile-url.org", false, &sig_s1, &skey1).1);
+        new1.push(assets);
+        let current = Some(new1.clone());
+        let existing = new1.find("Assets").unwrap();
+
+        let mut finance = Profile::new("Finance");
+        finance.push(finance.evolve(sid, "https://profile-url.org", false, &sig_s1, &skey1).1);
+
+        let mut health = Profile::new("Health");
+        health.push(health.evolve(sid, "https://profile-url.org", false, &sig_s1, &skey1).1);
+
+        // allowed: only new profiles (one or more)
+        let mut update = Subject::new(sid);
+        update.push(finance.clone()).push(health.clone());
+        assert!(update.check(&current) == Ok(()));
+
+        // allowed: a new key for an existing location, or a new location of an existing profile
+        let mut p_key = Profile::new("Assets");
+        p_key.push(existing.evolve(sid, "https://profile-url.org", false, &sig_s1, &skey1).1);
+        let mut update = Subject::new(sid);
+        update.push(p_key.clone());
+        assert!(update.check(&current) == Ok(()));
+
+        let mut p_loc = Profile::new("Assets");
+        p_loc.push(existing.evolve(sid, "https://other-url.org", false, &sig_s1, &skey1).1);
+        let mut update = Subject::new(sid);
+        update.push(p_loc);
+        assert!(update.check(&current) == Ok(()));
+
+        // allowed: a migration, a key for an existing location and a new location in the same profile
+        let (_, p_mig) = existing.migrate(sid, "https://profile-url.org", "https://new-url.org", &sig_s1, &skey1).unwrap();
+        let mut update = Subject::new(sid);
+        update.push(p_mig);
+        assert!(update.check(&current) == Ok(()));
+
+        // disallowed: new and existing profiles in the same update
+        let mut update = Subject::new(sid);
+        update.push(p_key.clone()).push(finance.clone());
+        assert!(update.check(&current) == Err("Subject update cannot mix new and existing profiles!".into()));
+
+        let mut update = Subject::new(sid);
+        update.push(finance.clone()).push(p_key.clone());
+        assert!(update.check(&current) == Err("Subject update cannot mix new and existing profiles!".into()));
+
+        // disallowed: empty profiles, new or existing
+        let mut update = Subject::new(sid);
+        update.push(Profile::new("Empty"));
+        assert!(update.check(&current) == Err("Subject update with an empty profile!".into()));
+
+        let mut update = Subject::new(sid);
+        update.push(Profile::new("Assets"));
+        assert!(update.check(&current) == Err("Subject update with an empty profile!".into()));
+
+        // disallowed: no profiles
+        assert!(Subject::new(sid).check(&current) == Err("Subject update must have at least one profile!".into()));
+    }
+
+    #[test]
+    fn test_linked_keys() {
+        let sig_s1 = rnd_scalar();
+        let sid = "s-id:shumy";
+
+        let mut new1 = Subject::new(sid);
+        let (_, skey1) = new1.evolve(sig_s1);
+
+        let mut p1 = Profile::new("Assets");
+        p1.push(p1.evolve(sid, "https://profile-url.org", false, &sig_s1, &skey1).1);
+
+        let mut p2 = Profile::new("Finance");
+        p2.push(p2.evolve(sid, "https://profile-url.org", false, &sig_s1, &skey1).1);
+
+        new1.push(p1).push(p2).keys.push(skey1.clone());
+        assert!(new1.linked_keys().is_empty());
+
+        // migration pointers are not reported
+        let (_, p3) = new1.find("Assets").unwrap().migrate(sid, "https://profile-url.org", "https://new-url.org", &sig_s1, &skey1).unwrap();
+        new1.merge(Subject::new(sid).push(p3).clone());
+        assert!(new1.linked_keys().is_empty());
+
+        // a reused secret links both profiles
+        let reused = new1.profiles["Assets"].locations["https://profile-url.org"].chain[0].pkey;
+        let key = ProfileKey::sign(sid, "Finance", "https://profile-url.org", 1, false, reused, None, &sig_s1, &skey1);
+        new1.profiles["Finance"].locations["https://profile-url.org"].chain.push(key);
+        assert!(new1.linked_keys() == vec![("Assets@https://profile-url.org#0".to_string(), "Finance@https://profile-url.org#1".to_string())]);
+    }
+
+    #[test]
+    fn test_linked_secrets() {
+        let secret = rnd_scalar();
+        let constants = [Scalar::one(), secret];
+
+        let s1 = rnd_scalar();
+        let independent = vec![("A".to_string(), s1), ("B".to_string(), rnd_scalar()), ("C".to_string(), rnd_scalar())];
+        assert!(linked_secrets(&independent, &constants).is_empty());
+
+        let linked = vec![("A".to_string(), s1), ("B".to_string(), rnd_scalar()), ("C".to_string(), s1 + Scalar::one())];
+        assert!(linked_secrets(&linked, &constants) == vec![("A".to_string(), "C".to_string())]);
+
+        let linked = vec![("A".to_string(), s1 + secret), ("B".to_string(), s1)];
+        assert!(linked_secrets(&linked, &constants) == vec![("A".to_string(), "B".to_string())]);
+
+        let linked = vec![("A".to_string(), s1), ("B".to_string(), s1)];
+        assert!(linked_secrets(&linked, &[]) == vec![("A".to_string(), "B".to_string())]);
+    }
+
+    #[test]
+    fn test_profile_keys() {
+        let sig_s1 = rnd_scalar();
+        let sid = "s-id:shumy";
+
+        let mut new1 = Subject::new(sid);
+        let (_, skey1) = new1.evolve(sig_s1);
+        new1.keys.push(skey1.clone());
+
+        let mut p1 = Profile::new("Assets");
+        p1.push(p1.evolve(sid, "https://profile-url.org", false, &sig_s1, &skey1).1);
+
+        let mut update1 = Subject::new(sid);
+        update1.push(p1);
+        assert!(update1.verify(&new1, Duration::from_secs(5)) == Ok(()));
+
+        // profiles are keyed by type, secrets by pid
+        new1.merge(update1);
+        let pid = ProfileLocation::pid("Assets", "https://profile-url.org");
+        assert!(new1.profiles.keys().collect::<Vec<_>>() == vec!["Assets"]);
+        assert!(ProfileLocation::split_pid(&pid) == Some(("Assets", "https://profile-url.org")));
+        assert!(new1.locate(&pid).unwrap().lurl == "https://profile-url.org");
+        assert!(new1.locate("Assets").is_none());
+        assert!(new1.locate("Finance@https://profile-url.org").is_none());
+
+        // the type cannot hold the separator, or the pid would be ambiguous
+        let mut p2 = Profile::new("Assets@https");
+        p2.push(p2.evolve(sid, "https://profile-url.org", false, &sig_s1, &skey1).1);
+
+        let mut update2 = Subject::new(sid);
+        update2.push(p2);
+        assert!(update2.verify(&new1, Duration::from_secs(5)) == Err(FpiError::constraint("profile-id", "Cannot contain '@'")));
+    }
+
+    #[test]
+    fn test_derived_secrets() {
+        let sig_s1 = rnd_scalar();
+        let sid = "s-id:shumy";
+
+        let mut new1 = Subject::new(sid);
+        let (_, skey1) = new1.evolve(sig_s1);
+        new1.keys.push(skey1.clone());
+
+        // derived secrets are reproducible from the subject-key secret
+        let p1 = Profile::new("Assets");
+        let (s1, loc1) = p1.derive(sid, "https://profile-url.org", false, &sig_s1, &skey1);
+        let (s2, loc2) = p1.derive(sid, "https://profile-url.org", false, &sig_s1, &skey1);
+        assert!(s1 == s2);
+        assert!(loc1.chain[0].pkey == s1 * G);
+        assert!(loc1.chain[0].pkey == loc2.chain[0].pkey);
+        assert!(s1 == ProfileLocation::derive_secret(&sig_s1, "Assets", "https://profile-url.org", 0));
+
+        let mut p1 = Profile::new("Assets");
+        p1.push(loc1);
+
+        let mut update1 = Subject::new(sid);
+        update1.push(p1.clone());
+        assert!(update1.verify(&new1, Duration::from_secs(5)) == Ok(()));
+
+        // each index, location and type has its own secret
+        let (s3, loc3) = p1.derive(sid, "https://profile-url.org", false, &sig_s1, &skey1);
+        assert!(loc3.chain[0].index == 1);
+        assert!(s3 == ProfileLocation::derive_secret(&sig_s1, "Assets", "https://profile-url.org", 1));
+        assert!(s3 != s1);
+        assert!(ProfileLocation::derive_secret(&sig_s1, "Assets", "https://other-url.org", 0) != s1);
+        assert!(ProfileLocation::derive_secret(&sig_s1, "Finance", "https://profile-url.org", 0) != s1);
+        assert!(ProfileLocation::derive_secret(&rnd_scalar(), "Assets", "https://profile-url.org", 0) != s1);
+
+        // random secrets are not reproducible
+        let (r1, _) = p1.evolve(sid, "https://profile-url.org", false, &sig_s1, &skey1);
+        let (r2, _) = p1.evolve(sid, "https://profile-url.org", false, &sig_s1, &skey1);
+        assert!(r1 != r2);
+        assert!(r1 != s3);
+    }
+
+    #[test]
+    fn test_authorizing_key() {
+        let sig_s1 = rnd_scalar();
+        let sid = "s-id:shumy";
+
+        // creation is self-signed
+        let mut new1 = Subject::new(sid);
+        let (_, skey1) = new1.evolve(sig_s1);
+        new1.keys.push(skey1.clone());
+        assert!(new1.active_key().unwrap().key == skey1.key);
+        assert!(new1.verify(&new1, Duration::from_secs(5)) == Ok(()));
+        assert!(new1.check(&None) == Ok(()));
+
+        // evolution is authorized by the stored key, not by the new one
+        let (sig_s2, skey2) = new1.evolve(sig_s1);
+        let mut evolve = Subject::new(sid);
+        evolve.keys.push(skey2.clone());
+        assert!(evolve.verify(&new1, Duration::from_secs(5)) == Ok(()));
+        assert!(evolve.verify(&evolve, Duration::from_secs(5)) == Err(FpiError::signature("sig")));
+        assert!(evolve.check(&Some(new1.clone())) == Ok(()));
+
+        // updates have no keys, only the stored subject can authorize them
+        let mut p1 = Profile::new("Assets");
+        p1.push(p1.evolve(sid, "https://profile-url.org", false, &sig_s1, &skey1).1);
+
+        let mut update = Subject::new(sid);
+        update.push(p1);
+        assert!(update.active_key().is_err());
+        assert!(update.verify(&new1, Duration::from_secs(5)) == Ok(()));
+        assert!(update.verify(&update, Duration::from_secs(5)) == Err(FpiError::NotFound("No active subject-key found!".into())));
+
+        // after the evolution the old key doesn't authorize updates
+        let mut evolved = new1.clone();
+        evolved.merge(evolve);
+        assert!(evolved.active_key().unwrap().key == skey2.key);
+        assert!(update.verify(&evolved, Duration::from_secs(5)) == Err(FpiError::constraint("sig", "Incorrect key index")));
+
+        let mut p2 = Profile::new("Assets");
+        p2.push(p2.evolve(sid, "https://profile-url.org", false, &sig_s2, &skey2).1);
+        let mut update2 = Subject::new(sid);
+        update2.push(p2);
+        assert!(update2.verify(&evolved, Duration::from_secs(5)) == Ok(()));
+
+        // and never for a different subject
+        let other = Subject::new("s-id:other");
+        assert!(other.verify(&new1, Duration::from_secs(5)) == Err(FpiError::constraint("sid", "Expected the same subject")));
+    }
+
+    #[test]
+    fn test_verify_key_first() {
+        let sig_s1 = rnd_scalar();
+        let sid = "s-id:shumy";
+
+        let mut new1 = Subject::new(sid);
+        let (_, skey1) = new1.evolve(sig_s1);
+
+        let mut p1 = Profile::new("Assets");
+        p1.push(p1.evolve(sid, "https://profile-url.org", false, &sig_s1, &skey1).1);
+        new1.push(p1).keys.push(skey1.clone());
+
+        // evolution with profiles and a forged key signature
+        let (_, mut skey2) = new1.evolve(sig_s1);
+        skey2.key = rnd_scalar() * G;
+
+        let mut p2 = Profile::new("Finance");
+        for i in 0..5 {
+            p2.push(p2.evolve(sid, &format!("https://profile-url-{}.org", i), false, &sig_s1, &skey1).1);
+        }
+
+        let mut update1 = Subject::new(sid);
+        update1.push(p2).keys.push(skey2);
+        assert!(update1.verify(&new1, Duration::from_secs(5)) == Err(FpiError::signature("sig")));
+
+        // the profiles are still verified for a valid key
+        let mut update2 = update1.clone();
+        update2.keys.clear();
+        assert!(update2.verify(&new1, Duration::from_secs(5)) == Ok(()));
+
+        // an expired profile-key is only reported after the key signature
+        let expired = |update: &Subject| {
+            let mut update = update.clone();
+            let loc = update.profiles.get_mut("Finance").unwrap().locations.get_index_mut(4).unwrap().1;
+            loc.chain[0].sig.sig.timestamp = 0;
+            update
+        };
+
+        assert!(expired(&update1).verify(&new1, Duration::from_secs(5)) == Err(FpiError::signature("sig")));
+        assert!(expired(&update2).verify(&new1, Duration::from_secs(5)) == Err(FpiError::Timestamp));
+    }
+
+    #[test]
+    fn test_reanchor() {
+        let sig_s1 = rnd_scalar();
+        let sid = "s-id:shumy";
+
+        let mut new1 = Subject::new(sid);
+        let (_, skey1) = new1.evolve(sig_s1);
+        assert!(new1.reanchor(sig_s1).err() == Some("No active subject-key found!".into()));
+
+        let mut p1 = Profile::new("Assets");
+        p1.push(p1.evolve(sid, "https://old-url.org", true, &sig_s1, &skey1).1);
+        p1.push(p1.evolve(sid, "https://profile-url.org", false, &sig_s1, &skey1).1);
+        new1.push(p1).keys.push(skey1.clone());
+
+        // migrated locations are not re-anchored
+        let (_, p2) = new1.find("Assets").unwrap().migrate(sid, "https://old-url.org", "https://new-url.org", &sig_s1, &skey1).unwrap();
+        let mut update1 = Subject::new(sid);
+        update1.push(p2);
+        new1.merge(update1);
+
+        //--------------------------------------------------
+        // Evolving SubjectKey with re-anchored ProfileKeys
+        // -------------------------------------------------
+        let (sig_s2, update2) = new1.reanchor(sig_s1).unwrap();
+        let skey2 = update2.keys[0].clone();
+        assert!(update2.verify(&new1, Duration::from_secs(5)) == Ok(()));
+        assert!(update2.check(&Some(new1.clone())) == Ok(()));
+
+        let assets = update2.find("Assets").unwrap();
+        assert!(assets.locations.len() == 2);
+        assert!(assets.find("https://old-url.org").is_none());
+
+        let mut current = new1.clone();
+        current.merge(update2);
+        assert!(current.active_key().unwrap().sig.index == 1);
+        assert!(current.linked_keys().is_empty());
+
+        // the active profile-keys verify under the new subject-key
+        for (lurl, loc) in current.find("Assets").unwrap().locations.iter() {
+            let active = loc.chain.last().unwrap();
+            if loc.migrated().is_none() {
+                assert!(active.sig.index == skey2.sig.index);
+                assert!(active.verify(sid, "Assets", lurl, &skey2, Duration::from_secs(5)) == Ok(()));
+                assert!(active.pkey == loc.chain[loc.chain.len() - 2].pkey);
+            }
+        }
+
+        // further profile updates are signed by the new key
+        let mut p3 = Profile::new("Assets");
+        p3.push(current.find("Assets").unwrap().evolve(sid, "https://profile-url.org", false, &sig_s2, &skey2).1);
+        let mut update3 = Subject::new(sid);
+        update3.push(p3);
+        assert!(update3.verify(&current, Duration::from_secs(5)) == Ok(()));
+        assert!(update3.check(&Some(current.clone())) == Ok(()));
+
+        //--------------------------------------------------
+        // Only re-anchored keys are accepted with a key-evolution
+        // -------------------------------------------------
+        let (sig_s3, skey3) = current.evolve(sig_s2);
+
+        let mut p4 = Profile::new("Assets");
+        p4.push(current.find("Assets").unwrap().evolve(sid, "https://profile-url.org", false, &sig_s3, &skey3).1);
+        let mut update4 = Subject::new(sid);
+        update4.push(p4).keys.push(skey3.clone());
+        assert!(update4.verify(&current, Duration::from_secs(5)) == Ok(()));
+        assert!(update4.check(&Some(current.clone())) == Err("Subject key-evolution can only re-anchor the active profile-keys!".into()));
+
+        let mut p5 = Profile::new("Finance");
+        p5.push(p5.evolve(sid, "https://profile-url.org", false, &sig_s3, &skey3).1);
+        let mut update5 = Subject::new(sid);
+        update5.push(p5).keys.push(skey3.clone());
+        assert!(update5.check(&Some(current.clone())) == Err("Subject key-evolution can only re-anchor existing profiles!".into()));
+
+        // re-anchored keys signed by the previous key
+        let (_, mut update6) = current.reanchor(sig_s2).unwrap();
+        let loc = current.find("Assets").unwrap().find("https://profile-url.org").unwrap();
+        let mut p6 = Profile::new("Assets");
+        let mut location = ProfileLocation::new("https://profile-url.org");
+        location.chain.push(loc.reanchor(sid, "Assets", &sig_s2, &skey2).unwrap());
+        p6.push(location);
+        update6.profiles.clear();
+        update6.push(p6);
+        assert!(update6.verify(&current, Duration::from_secs(5)) == Err(FpiError::constraint("sig", "Incorrect key index")));
+    }
+
+    #[test]
+    fn test_concurrent_update() {
+        let sig_s = rnd_scalar();
+        let sid = "s-id:shumy";
+
+        let mut new1 = Subject::new(sid);
+        let (_, skey) = new1.evolve(sig_s);
+        new1.keys.push(skey.clone());
+        assert!(new1.check(&None) == Ok(()));
+
+        let mut based = new1.clone();
+        based.based_on(&new1, &sig_s, &skey);
+        assert!(based.check(&None) == Err("Subject creation cannot have a base!".into()));
+
+        // two updates based on the same state
+        let mut p1 = Profile::new("Assets");
+        p1.push(p1.evolve(sid, "https://profile-url.org", false, &sig_s, &skey).1);
+        let mut update1 = Subject::new(sid);
+        update1.push(p1).based_on(&new1, &sig_s, &skey);
+
+        let mut p2 = Profile::new("Finance");
+        p2.push(p2.evolve(sid, "https://profile-url.org", false, &sig_s, &skey).1);
+        let mut update2 = Subject::new(sid);
+        update2.push(p2).based_on(&new1, &sig_s, &skey);
+
+        assert!(update1.verify(&new1, Duration::from_secs(5)) == Ok(()));
+        assert!(update1.check(&Some(new1.clone())) == Ok(()));
+
+        let mut current = new1.clone();
+        current.merge(update1);
+        assert!(current.base.is_none());
+        assert!(current.digest() != new1.digest());
+
+        // the second one is stale after the first is merged
+        assert!(update2.check(&Some(new1.clone())) == Ok(()));
+        assert!(update2.check(&Some(current.clone())) == Err("Concurrent modification, the update is based on a stale subject!".into()));
+
+        // refetched and retried
+        update2.based_on(&current, &sig_s, &skey);
+        assert!(update2.verify(&current, Duration::from_secs(5)) == Ok(()));
+        assert!(update2.check(&Some(current.clone())) == Ok(()));
+
+        // a relay can't replace the signed base
+        let mut replaced = update2.clone();
+        replaced.base.as_mut().unwrap().digest = new1.digest();
+        assert!(replaced.verify(&current, Duration::from_secs(5)) == Err(FpiError::signature("base")));
+
+        let (_, other) = Subject::new(sid).evolve(rnd_scalar());
+        let mut forged = update2.clone();
+        forged.based_on(&new1, &rnd_scalar(), &other);
+        assert!(forged.verify(&current, Duration::from_secs(5)) == Err(FpiError::signature("base")));
+
+        // the digest is preserved on the encoded subject
+        let data = encode(&current).unwrap();
+        let decoded: Subject = decode(&data).unwrap();
+        assert!(decoded.digest() == current.digest());
+    }
+
+    #[test]
+    fn test_profile_builder() {
+        let sig_s = rnd_scalar();
+        let sid = "s-id:shumy";
+
+        let mut new1 = Subject::new(sid);
+        let (_, skey) = new1.evolve(sig_s);
+        new1.keys.push(skey.clone());
+
+        let mut builder = ProfileBuilder::new(sid, "Assets", None, &sig_s, &skey);
+        builder.evolve("https://profile-url.org", false).unwrap().derive("https://other-url.org", true).unwrap();
+        assert!(builder.evolve("https://profile-url.org", false).err() == Some("ProfileBuilder already has the profile-location!".into()));
+        let (secrets, profile) = builder.build().unwrap();
+
+        let pid = ProfileLocation::pid("Assets", "https://profile-url.org");
+        assert!(secrets.len() == 2);
+        assert!(secrets[&pid] * G == profile.find("https://profile-url.org").unwrap().chain[0].pkey);
+
+        new1.push(profile);
+        assert!(new1.verify(&new1, Duration::from_secs(5)) == Ok(()));
+        assert!(new1.check(&None) == Ok(()));
+
+        // evolve the existing location
+        let current = new1.find("Assets").unwrap();
+        let mut builder = ProfileBuilder::new(sid, "Assets", Some(current), &sig_s, &skey);
+        builder.evolve("https://profile-url.org", false).unwrap();
+        let (secrets, profile) = builder.build().unwrap();
+        assert!(profile.find("https://profile-url.org").unwrap().chain[0].index == 1);
+        assert!(secrets[&pid] * G == profile.find("https://profile-url.org").unwrap().chain[0].pkey);
+
+        let mut update = Subject::new(sid);
+        update.push(profile);
+        assert!(update.verify(&new1, Duration::from_secs(5)) == Ok(()));
+        assert!(update.check(&Some(new1.clone())) == Ok(()));
+
+        // out-of-order keys are rejected
+        let fresh = Profile::new("Assets");
+        let (secret, location) = fresh.evolve(sid, "https://profile-url.org", false, &sig_s, &skey);
+        let mut builder = ProfileBuilder::new(sid, "Assets", Some(current), &sig_s, &skey);
+        assert!(builder.push(secret, location).err() == Some("ProfileKey is not correcly chained!".into()));
+
+        let (secret, mut location) = current.evolve(sid, "https://profile-url.org", false, &sig_s, &skey);
+        location.chain.push(location.chain[0].clone());
+        assert!(builder.push(secret, location).err() == Some("ProfileBuilder expects a location with one key!".into()));
+
+        assert!(builder.build().err() == Some("ProfileBuilder without locations!".into()));
+    }
+
+    #[test]
+    fn test_revocation_timestamps() {
+        let sig_s1 = rnd_scalar();
+        let sid = "s-id:shumy";
+
+        let mut subject = Subject::new(sid);
+        let (_, skey1) = subject.evolve(sig_s1);
+        subject.keys.push(skey1.clone());
+
+        let (sig_s2, skey2) = subject.evolve(sig_s1);
+        let mut evolve = Subject::new(sid);
+        evolve.keys.push(skey2.clone());
+        subject.merge(evolve);
+
+        // the compromised key is revoked by the new key
+        let at = skey2.sig.sig.timestamp;
+        let mut update = Subject::new(sid);
+        update.revocations.push(Revocation::sign(sid, 0, at, &sig_s2, &skey2));
+        assert!(update.verify(&subject, Duration::from_secs(5)) == Ok(()));
+        assert!(update.check(&Some(subject.clone())) == Ok(()));
+        subject.merge(update.clone());
+
+        // historical signatures of the revoked key are only valid up to the revocation
+        let data = [b"data".to_vec()];
+        let before = IndSignature::sign_at(at - 1, 0, &sig_s1, &skey1.key, "fpi:test", &data);
+        let after = IndSignature::sign_at(at + 1, 0, &sig_s1, &skey1.key, "fpi:test", &data);
+        assert!(subject.signing_key(&before).unwrap().key == skey1.key);
+        assert!(subject.signing_key(&after).err() == Some(FpiError::constraint("sig", "Signed by a revoked key")));
+
+        // the new key is not affected
+        let current = IndSignature::sign_at(at + 1, 1, &sig_s2, &skey2.key, "fpi:test", &data);
+        assert!(subject.signing_key(&current).unwrap().key == skey2.key);
+
+        let unknown = IndSignature::sign_at(at, 2, &sig_s2, &skey2.key, "fpi:test", &data);
+        assert!(subject.signing_key(&unknown).err() == Some(FpiError::NotFound("No subject-key found at index 2!".into())));
+
+        // a key is only revoked once, and never by itself
+        assert!(update.check(&Some(subject.clone())) == Err("Subject-key already revoked!".into()));
+
+        let mut update = Subject::new(sid);
+        update.revocations.push(Revocation::sign(sid, 1, at, &sig_s2, &skey2));
+        assert!(update.verify(&subject, Duration::from_secs(5)) == Err(FpiError::constraint("index", "Expected a previous subject-key")));
+
+        let mut update = Subject::new(sid);
+        update.revocations.push(Revocation::sign(sid, 0, at, &sig_s1, &skey1));
+        assert!(update.verify(&subject, Duration::from_secs(5)) == Err(FpiError::constraint("index", "Expected a previous subject-key")));
+    }
+
+    #[test]
+    fn test_revocation_with_evolution() {
+        let sig_s1 = rnd_scalar();
+        let sid = "s-id:shumy";
+
+        let mut subject = Subject::new(sid);
+        let (_, skey1) = subject.evolve(sig_s1);
+        subject.keys.push(skey1.clone());
+
+        // the active key is revoked by the new key in the same transaction
+        let (sig_s2, skey2) = subject.evolve(sig_s1);
+        let at = skey2.sig.sig.timestamp;
+
+        let mut update = Subject::new(sid);
+        update.keys.push(skey2.clone());
+        update.revocations.push(Revocation::sign(sid, 0, at, &sig_s2, &skey2));
+        assert!(update.verify(&subject, Duration::from_secs(5)) == Ok(()));
+        assert!(update.check(&Some(subject.clone())) == Ok(()));
+
+        // the evolution itself was signed after the key was revoked
+        let mut update = Subject::new(sid);
+        update.keys.push(skey2.clone());
+        update.revocations.push(Revocation::sign(sid, 0, at - 1, &sig_s2, &skey2));
+        assert!(update.verify(&subject, Duration::from_secs(5)) == Err(FpiError::constraint("sig", "Signed by a revoked key")));
+
+        // the revocation must be signed by the new key, not by the revoked one
+        let mut update = Subject::new(sid);
+        update.keys.push(skey2.clone());
+        update.revocations.push(Revocation::sign(sid, 0, at, &sig_s1, &SubjectKey::sign(sid, 1, skey1.key, &sig_s1, &skey1.key)));
+        assert!(update.verify(&subject, Duration::from_secs(5)) == Err(FpiError::signature("sig")));
+
+        // and by a known key
+        let (sig_s3, skey3) = Subject::new(sid).evolve(rnd_scalar());
+        let skey3 = SubjectKey::sign(sid, 2, skey3.key, &sig_s3, &skey3.key);
+        let mut update = Subject::new(sid);
+        update.revocations.push(Revocation::sign(sid, 0, at, &sig_s3, &skey3));
+        assert!(update.check(&Some(subject.clone())) == Err("Revocation signed by an unknown subject-key!".into()));
+
+        // revocations are never accepted on creation
+        let mut create = subject.clone();
+        create.revocations.push(Revocation::sign(sid, 0, at, &sig_s2, &skey2));
+        assert!(create.check(&None) == Err("Subject creation cannot have revocations!".into()));
+    }
+
+    // the baseline encoding of the subjects, before the revocations and the migration pointer of the profile-keys
+    #[derive(Serialize)]
+    struct BaselineSubject {
+        sid: String,
+        keys: Vec<SubjectKey>,
+        profiles: IndexMap<String, BaselineProfile>
+    }
+
+    #[derive(Serialize)]
+    struct BaselineProfile {
+        typ: String,
+        locations: IndexMap<String, BaselineLocation>
+    }
+
+    #[derive(Serialize)]
+    struct BaselineLocation {
+        lurl: String,
+        chain: Vec<BaselineKey>
+    }
+
+    #[derive(Serialize)]
+    struct BaselineKey {
+        index: usize,
+        encrypted: bool,
+        pkey: RistrettoPoint,
+        sig: IndSignature
+    }
+
+    fn baseline(subject: &Subject) -> BaselineSubject {
+        let profiles = subject.profiles.iter().map(|(typ, prof)| {
+            let locations = prof.locations.iter().map(|(lurl, loc)| {
+                let chain = loc.chain.iter().map(|key| BaselineKey { index: key.index, encrypted: key.encrypted, pkey: key.pkey, sig: key.sig.clone() }).collect();
+                (lurl.clone(), BaselineLocation { lurl: lurl.clone(), chain })
+            }).collect();
+
+            (typ.clone(), BaselineProfile { typ: typ.clone(), locations })
+        }).collect();
+
+        BaselineSubject { sid: subject.sid.clone(), keys: subject.keys.clone(), profiles }
+    }
+
+    #[test]
+    fn test_subject_encoding() {
+        let sig_s = rnd_scalar();
+        let mut subject = Subject::new("s-id:shumy");
+        let (_, skey) = subject.evolve(sig_s);
+        subject.keys.push(skey.clone());
+
+        let mut profile = Profile::new("HealthCare");
+        profile.push(profile.evolve("s-id:shumy", "https://profile-url.org", false, &sig_s, &skey).1);
+        subject.push(profile);
+
+        // subjects stored by the baseline nodes and clients
+        let legacy = encode(&baseline(&subject)).unwrap();
+        let decoded: Subject = decode(&legacy).unwrap();
+        assert!(decoded.sid == subject.sid && decoded.keys.len() == 1 && decoded.revocations.is_empty() && decoded.base.is_none());
+        assert!(decoded.profiles["HealthCare"].locations["https://profile-url.org"].chain[0].pkey == subject.profiles["HealthCare"].locations["https://profile-url.org"].chain[0].pkey);
+        assert!(decoded.digest() == subject.digest());
+
+        // the current encoding keeps the revocations, and is tagged with the version
+        let at = skey.sig.sig.timestamp;
+        subject.revocations.push(Revocation::sign("s-id:shumy", 0, at, &sig_s, &skey));
+        let data = encode(&subject).unwrap();
+        assert!(data[..8] == SUBJECT_V1.to_le_bytes());
+
+        let decoded: Subject = decode(&data).unwrap();
+        assert!(decoded.sid == subject.sid && decoded.revocations.len() == 1 && decoded.profiles.len() == 1);
+
+        // encodings of the first version are bounded by the subject-id size
+        let mut data = legacy;
+        data[..8].copy_from_slice(&((MAX_SUBJECT_ID_SIZE + 1) as u64).to_le_bytes());
+        assert!(decode::<Subject>(&data).is_err());
+    }
+
+    #[test]
+    fn test_baseline_profile_keys() {
+        let sig_s = rnd_scalar();
+        let sid = "s-id:shumy";
+
+        let mut subject = Subject::new(sid);
+        let (_, skey) = subject.evolve(sig_s);
+        subject.keys.push(skey.clone());
+
+        let mut profile = Profile::new("Assets");
+        let (_, location) = profile.evolve(sid, "https://profile-url.org", true, &sig_s, &skey);
+        profile.push(location);
+        let (_, location) = profile.evolve(sid, "https://profile-url.org", true, &sig_s, &skey);
+        profile.locations["https://profile-url.org"].chain.extend(location.chain);
+        subject.push(profile);
+
+        // keys of the baseline layout have no migration pointer
+        let decoded: Subject = decode(&encode(&baseline(&subject)).unwrap()).unwrap();
+        let chain = &decoded.profiles["Assets"].locations["https://profile-url.org"].chain;
+        let expected = &subject.profiles["Assets"].locations["https://profile-url.org"].chain;
+        assert!(chain.len() == 2 && decoded.locate("Assets@https://profile-url.org").unwrap().migrated().is_none());
+        for (key, exp) in chain.iter().zip(expected.iter()) {
+            assert!(key.index == exp.index && key.encrypted && key.pkey == exp.pkey && key.migrated_to.is_none());
+            assert!(key.sig.sig.encoded == exp.sig.sig.encoded && key.sig.index == exp.sig.index);
+        }
+
+        // the map-key invariant is kept on the upgraded profiles
+        let mut legacy = baseline(&subject);
+        let location = legacy.profiles["Assets"].locations.swap_remove("https://profile-url.org").unwrap();
+        legacy.profiles["Assets"].locations.insert("https://other-url.org".into(), location);
+        assert!(decode::<Subject>(&encode(&legacy).unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_golden_layout() {
+        let key = Scalar::from(2u64) * G;
+        let skey = SubjectKey::data("s-id:shumy", 1, &key);
+        assert!(crate::structs::layout(&skey) == "18,8,40:72qJGz4nN8FUqxJ1BmEjtpbq84NJg1uyfQgxEjjZ7w77");
+
+        let pkey = ProfileKey::data("s-id:shumy", "HealthCare", "https://profile.org", 0, true, &key, &None);
+        assert!(crate::structs::layout(&pkey) == "18,18,27,8,1,40,1:C5UfJvacE2C91XYCDpDGsYcCzRXdgWyaYhmhLgTbbSam");
+
+        let migrated = ProfileKey::data("s-id:shumy", "HealthCare", "https://profile.org", 0, false, &key, &Some("https://new.org".into()));
+        assert!(crate::structs::layout(&migrated) == "18,18,27,8,1,40,24:4MCgxPZ3qyHe56NqEkwt9ag6oXbSLRgYhSTAzTErondC");
+
+        let rev = Revocation::data("s-id:shumy", 1, 1_500_000_000);
+        assert!(crate::structs::layout(&rev) == "18,8,8:536QTkkp3KsiVCBRqtjKw9E9p9ByzbsbRbCWvyieZ63y");
+
+        let erase = Erase::data("s-id:shumy");
+        assert!(crate::structs::layout(&erase) == "18:E48tLZn2AxSNDd8RaxBdFnw2Luir2DqhDNMxoNGxEV7R");
+    }
 }
\ No newline at end of file
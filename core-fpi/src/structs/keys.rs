@@ -3,8 +3,9 @@ use std::time::Duration;
 
 use crate::ids::*;
 use crate::structs::*;
-use crate::{Result, Scalar, RistrettoPoint};
-use crate::shares::{Share, RistrettoPolynomial, Degree};
+use crate::{G, Result, FpiError, FpiResult, Scalar, RistrettoPoint, CompressedRistretto, KeyEncoder};
+use crate::shares::{pedersen_h, Share, RistrettoShare, Polynomial, RistrettoPolynomial, Interpolate, Degree};
+use crate::governance::{peers_hash, PeerChange};
 use crate::signatures::IndSignature;
 
 use serde::{Serialize, Deserialize};
@@ -23,27 +24,31 @@ pub struct MasterKeyRequest {
 impl Constraints for MasterKeyRequest {
     fn sid(&self) -> &str { &self.sid }
 
-    fn verify(&self, subject: &Subject, threshold: Duration) -> Result<()> {
+    fn verify(&self, subject: &Subject, threshold: Duration) -> FpiResult<()> {
         if self.sid.len() > MAX_SUBJECT_ID_SIZE {
-            return Err(format!("Field Constraint - (sid, max-size = {})", MAX_SUBJECT_ID_SIZE))
+            return Err(FpiError::constraint("sid", format!("max-size = {}", MAX_SUBJECT_ID_SIZE)))
         }
 
         if self.kid.len() > MAX_KEY_ID_SIZE {
-            return Err(format!("Field Constraint - (kid, max-size = {})", MAX_KEY_ID_SIZE))
+            return Err(FpiError::constraint("kid", format!("max-size = {}", MAX_KEY_ID_SIZE)))
         }
 
         if self.peers.len() > MAX_HASH_SIZE {
-            return Err(format!("Field Constraint - (peers, max-size = {})", MAX_HASH_SIZE))
+            return Err(FpiError::constraint("peers", format!("max-size = {}", MAX_HASH_SIZE)))
         }
 
         if !self.sig.sig.check_timestamp(threshold) {
-            return Err("Field Constraint - (sig, Timestamp out of valid range)".into())
+            return Err(FpiError::Timestamp)
+        }
+
+        let skey = subject.active_key()?;
+        if self.sig.index != skey.sig.index {
+            return Err(FpiError::constraint("sig", "Incorrect key index"))
         }
 
-        let skey = subject.keys.last().ok_or("No active subject-key found!")?;
         let sig_data = Self::data(&self.sid, &self.kid, &self.peers);
-        if !self.sig.verify(&skey.key, &sig_data) {
-            return Err("Field Constraint - (sig, Invalid signature)".into())
+        if !self.sig.verify(&skey.key, Self::DOMAIN, &sig_data) {
+            return Err(FpiError::signature("sig"))
         }
 
         Ok(())
@@ -51,10 +56,87 @@ impl Constraints for MasterKeyRequest {
 }
 
 impl MasterKeyRequest {
+    const DOMAIN: &'static str = "fpi:master-key-request";
+
     pub fn sign(sid: &str, kid: &str, peers: &[u8], sig_s: &Scalar, sig_key: &SubjectKey) -> Self {
         let sig_data = Self::data(sid, kid, peers);
-        let sig = IndSignature::sign(sig_key.sig.index, sig_s, &sig_key.key, &sig_data); 
+        let sig = IndSignature::sign(sig_key.sig.index, sig_s, &sig_key.key, Self::DOMAIN, &sig_data); 
+        
+        Self { sid: sid.into(), kid: kid.into(), peers: peers.to_vec(), sig }
+    }
+
+    pub fn check(&self, peers_hash: &[u8]) -> Result<()> {
+        if self.peers != peers_hash {
+            return Err("Field Constraint - (peers, Incorrect peers-hash)".into())
+        }
+
+        Ok(())
+    }
+
+    fn data(sid: &str, kid: &str, peers: &[u8]) -> [Vec<u8>; 3] {
+        // These unwrap() should never fail, or it's a serious code bug!
+        let b_sid = bincode::serialize(sid).unwrap();
+        let b_kid = bincode::serialize(kid).unwrap();
+        let b_peers = bincode::serialize(peers).unwrap();
         
+        [b_sid, b_kid, b_peers]
+    }
+}
+
+//--------------------------------------------------------------------
+// Request the share refresh of an existing MasterKey
+// Peers vote with shares of a zero secret (same MasterKeyVote), so the committed evidence (MasterKey) rotates the
+// shares of each peer without changing the master public-key.
+//--------------------------------------------------------------------
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MasterKeyRefresh {
+    pub sid: String,
+    pub kid: String,
+    pub peers: Vec<u8>,
+    pub sig: IndSignature
+}
+
+impl Constraints for MasterKeyRefresh {
+    fn sid(&self) -> &str { &self.sid }
+
+    fn verify(&self, subject: &Subject, threshold: Duration) -> FpiResult<()> {
+        if self.sid.len() > MAX_SUBJECT_ID_SIZE {
+            return Err(FpiError::constraint("sid", format!("max-size = {}", MAX_SUBJECT_ID_SIZE)))
+        }
+
+        if self.kid.len() > MAX_KEY_ID_SIZE {
+            return Err(FpiError::constraint("kid", format!("max-size = {}", MAX_KEY_ID_SIZE)))
+        }
+
+        if self.peers.len() > MAX_HASH_SIZE {
+            return Err(FpiError::constraint("peers", format!("max-size = {}", MAX_HASH_SIZE)))
+        }
+
+        if !self.sig.sig.check_timestamp(threshold) {
+            return Err(FpiError::Timestamp)
+        }
+
+        let skey = subject.active_key()?;
+        if self.sig.index != skey.sig.index {
+            return Err(FpiError::constraint("sig", "Incorrect key index"))
+        }
+
+        let sig_data = Self::data(&self.sid, &self.kid, &self.peers);
+        if !self.sig.verify(&skey.key, Self::DOMAIN, &sig_data) {
+            return Err(FpiError::signature("sig"))
+        }
+
+        Ok(())
+    }
+}
+
+impl MasterKeyRefresh {
+    const DOMAIN: &'static str = "fpi:master-key-refresh";
+
+    pub fn sign(sid: &str, kid: &str, peers: &[u8], sig_s: &Scalar, sig_key: &SubjectKey) -> Self {
+        let sig_data = Self::data(sid, kid, peers);
+        let sig = IndSignature::sign(sig_key.sig.index, sig_s, &sig_key.key, Self::DOMAIN, &sig_data);
+
         Self { sid: sid.into(), kid: kid.into(), peers: peers.to_vec(), sig }
     }
 
@@ -76,6 +158,175 @@ impl MasterKeyRequest {
     }
 }
 
+//--------------------------------------------------------------------
+// Request the resharing of an existing MasterKey to a new peer-set
+// Each current share-holder votes (same MasterKeyVote) with the shares of its own share y_i, targeting the new peers
+// under the new threshold. The new share of peer j is the interpolation at 0 of the received shares (Shamir re-sharing),
+// so the master secret and public-key don't change.
+//--------------------------------------------------------------------
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MasterKeyReshare {
+    pub sid: String,
+    pub kid: String,
+    pub peers: Vec<u8>,                         // current peers-hash
+    pub threshold: usize,                       // threshold of the new peer-set
+    pub new_peers: Vec<RistrettoPoint>,         // ordered keys of the new peer-set
+
+    pub sig: IndSignature
+}
+
+impl Constraints for MasterKeyReshare {
+    fn sid(&self) -> &str { &self.sid }
+
+    fn verify(&self, subject: &Subject, threshold: Duration) -> FpiResult<()> {
+        if self.sid.len() > MAX_SUBJECT_ID_SIZE {
+            return Err(FpiError::constraint("sid", format!("max-size = {}", MAX_SUBJECT_ID_SIZE)))
+        }
+
+        if self.kid.len() > MAX_KEY_ID_SIZE {
+            return Err(FpiError::constraint("kid", format!("max-size = {}", MAX_KEY_ID_SIZE)))
+        }
+
+        if self.peers.len() > MAX_HASH_SIZE {
+            return Err(FpiError::constraint("peers", format!("max-size = {}", MAX_HASH_SIZE)))
+        }
+
+        if self.new_peers.len() > MAX_PEERS {
+            return Err(FpiError::constraint("new_peers", format!("max-size = {}", MAX_PEERS)))
+        }
+
+        if !self.sig.sig.check_timestamp(threshold) {
+            return Err(FpiError::Timestamp)
+        }
+
+        let skey = subject.active_key()?;
+        if self.sig.index != skey.sig.index {
+            return Err(FpiError::constraint("sig", "Incorrect key index"))
+        }
+
+        let sig_data = Self::data(&self.sid, &self.kid, &self.peers, self.threshold, &self.new_peers);
+        if !self.sig.verify(&skey.key, Self::DOMAIN, &sig_data) {
+            return Err(FpiError::signature("sig"))
+        }
+
+        Ok(())
+    }
+}
+
+impl MasterKeyReshare {
+    const DOMAIN: &'static str = "fpi:master-key-reshare";
+
+    pub fn sign(sid: &str, kid: &str, peers: &[u8], threshold: usize, new_peers: &[RistrettoPoint], sig_s: &Scalar, sig_key: &SubjectKey) -> Self {
+        let sig_data = Self::data(sid, kid, peers, threshold, new_peers);
+        let sig = IndSignature::sign(sig_key.sig.index, sig_s, &sig_key.key, Self::DOMAIN, &sig_data);
+
+        Self { sid: sid.into(), kid: kid.into(), peers: peers.to_vec(), threshold, new_peers: new_peers.to_vec(), sig }
+    }
+
+    pub fn check(&self, peers_hash: &[u8]) -> Result<()> {
+        if self.peers != peers_hash {
+            return Err("Field Constraint - (peers, Incorrect peers-hash)".into())
+        }
+
+        check_new_peers(&self.new_peers, self.threshold)
+    }
+
+    fn data(sid: &str, kid: &str, peers: &[u8], threshold: usize, new_peers: &[RistrettoPoint]) -> [Vec<u8>; 5] {
+        let c_new_peers: Vec<_> = new_peers.iter().map(|pkey| pkey.compress()).collect();
+
+        // These unwrap() should never fail, or it's a serious code bug!
+        let b_sid = bincode::serialize(sid).unwrap();
+        let b_kid = bincode::serialize(kid).unwrap();
+        let b_peers = bincode::serialize(peers).unwrap();
+        let b_threshold = bincode::serialize(&threshold).unwrap();
+        let b_new_peers = bincode::serialize(&c_new_peers).unwrap();
+        
+        [b_sid, b_kid, b_peers, b_threshold, b_new_peers]
+    }
+}
+
+// same rules of the PeerSet
+fn check_new_peers(new_peers: &[RistrettoPoint], t: usize) -> Result<()> {
+    if new_peers.len() < 3 * t + 1 {
+        return Err("Field Constraint - (new_peers, Expecting #peers >= 3 * t + 1)".into())
+    }
+
+    for (i, pkey) in new_peers.iter().enumerate() {
+        if new_peers[..i].contains(pkey) {
+            return Err("Field Constraint - (new_peers, Duplicated peer-key)".into())
+        }
+    }
+
+    Ok(())
+}
+
+//--------------------------------------------------------------------
+// Request the reveal of the Feldman's commitments, after the commit of the Pedersen's votes (PedersenKey)
+// Each peer votes (same MasterKeyVote) with the committed encrypted shares, but with the Feldman's commitments (a_k * G)
+// of the same polynomial. The public part (a_0 * G) of each secret is only disclosed after the shares are fixed on-chain.
+//--------------------------------------------------------------------
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MasterKeyReveal {
+    pub sid: String,
+    pub kid: String,
+    pub session: String,                        // session of the committed PedersenKey
+    pub sig: IndSignature
+}
+
+impl Constraints for MasterKeyReveal {
+    fn sid(&self) -> &str { &self.sid }
+
+    fn verify(&self, subject: &Subject, threshold: Duration) -> FpiResult<()> {
+        if self.sid.len() > MAX_SUBJECT_ID_SIZE {
+            return Err(FpiError::constraint("sid", format!("max-size = {}", MAX_SUBJECT_ID_SIZE)))
+        }
+
+        if self.kid.len() > MAX_KEY_ID_SIZE {
+            return Err(FpiError::constraint("kid", format!("max-size = {}", MAX_KEY_ID_SIZE)))
+        }
+
+        if self.session.len() > MAX_HASH_SIZE {
+            return Err(FpiError::constraint("session", format!("max-size = {}", MAX_HASH_SIZE)))
+        }
+
+        if !self.sig.sig.check_timestamp(threshold) {
+            return Err(FpiError::Timestamp)
+        }
+
+        let skey = subject.active_key()?;
+        if self.sig.index != skey.sig.index {
+            return Err(FpiError::constraint("sig", "Incorrect key index"))
+        }
+
+        let sig_data = Self::data(&self.sid, &self.kid, &self.session);
+        if !self.sig.verify(&skey.key, Self::DOMAIN, &sig_data) {
+            return Err(FpiError::signature("sig"))
+        }
+
+        Ok(())
+    }
+}
+
+impl MasterKeyReveal {
+    const DOMAIN: &'static str = "fpi:master-key-reveal";
+
+    pub fn sign(sid: &str, kid: &str, session: &str, sig_s: &Scalar, sig_key: &SubjectKey) -> Self {
+        let sig_data = Self::data(sid, kid, session);
+        let sig = IndSignature::sign(sig_key.sig.index, sig_s, &sig_key.key, Self::DOMAIN, &sig_data);
+
+        Self { sid: sid.into(), kid: kid.into(), session: session.into(), sig }
+    }
+
+    fn data(sid: &str, kid: &str, session: &str) -> [Vec<u8>; 3] {
+        // These unwrap() should never fail, or it's a serious code bug!
+        let b_sid = bincode::serialize(sid).unwrap();
+        let b_kid = bincode::serialize(kid).unwrap();
+        let b_session = bincode::serialize(session).unwrap();
+
+        [b_sid, b_kid, b_session]
+    }
+}
+
 //--------------------------------------------------------------------
 // Response to MasterKey negotiation
 //--------------------------------------------------------------------
@@ -93,14 +344,15 @@ pub struct MasterKeyVote {
     pub sig: IndSignature
 }
 
+// The encrypted shares (e_i + y_i) are never formatted, with a peer e_i they leak y_i.
 impl Debug for MasterKeyVote {
     fn fmt(&self, fmt: &mut Formatter<'_>) -> std::fmt::Result {
-        let peers = bs58::encode(&self.peers).into_string();
+        let peers = self.peers.encode();
         fmt.debug_struct("MasterKeyVote")
             .field("session", &self.session)
             .field("kid", &self.kid)
             .field("peers", &peers)
-            .field("shares", &self.shares)
+            .field("#shares", &self.shares.len())
             .field("pkeys", &self.pkeys)
             .field("commit", &self.commit)
             .field("sig", &self.sig)
@@ -109,75 +361,225 @@ impl Debug for MasterKeyVote {
 }
 
 impl MasterKeyVote {
+    const DOMAIN: &'static str = "fpi:master-key-vote";
+
     pub fn sign(session: &str, kid: &str, peers_hash: &[u8], shares: Vec<Share>, pkeys: Vec<RistrettoPoint>, commit: RistrettoPolynomial, secret: &Scalar, key: &RistrettoPoint, index: usize) -> Self {
         let sig_data = Self::data(session, kid, peers_hash, &shares, &pkeys, &commit);
-        let sig = IndSignature::sign(index, secret, key, &sig_data);
+        let sig = IndSignature::sign_batchable(index, secret, key, Self::DOMAIN, &sig_data);
 
         Self { session: session.into(), kid: kid.into(), peers: peers_hash.to_vec(), shares, pkeys, commit, sig }
     }
 
-    pub fn check(&self, session: &str, kid: &str, peers_hash: &[u8], n: usize, pkey: &RistrettoPoint) -> Result<()> {
+    pub fn check(&self, session: &str, kid: &str, peers_hash: &[u8], n: usize, t: usize, pkey: &RistrettoPoint) -> Result<()> {
         /*if !self.sig.sig.check_timestamp(threshold) {
             return Err("Timestamp out of valid range!".into())
         }*/
 
-        if self.session != session {
-            return Err("Field Constraint - (session, Expected the same session)".into())
+        check_session(&self.session, &self.kid, &self.peers, session, kid, peers_hash)?;
+        Self::verify(&self.session, &self.kid, &self.peers, &self.shares, &self.pkeys, &self.commit, &self.sig, n, t, pkey)
+    }
+
+    // Verification over borrowed fields. MasterKey::check uses the parts directly, avoiding to rebuild (clone) each vote.
+    #[allow(clippy::too_many_arguments)]
+    fn verify(session: &str, kid: &str, peers: &[u8], shares: &[Share], pkeys: &[RistrettoPoint], commit: &RistrettoPolynomial, sig: &IndSignature, n: usize, t: usize, pkey: &RistrettoPoint) -> Result<()> {
+        Self::verify_fields(shares, pkeys, commit, n, t)?;
+
+        let sig_data = Self::data(session, kid, peers, shares, pkeys, commit);
+        if !sig.verify(pkey, Self::DOMAIN, &sig_data) {
+            return Err("Invalid master-key request signature!".into())
         }
 
-        if self.kid != kid {
-            return Err("Field Constraint - (kid, Expected the same key-id)".into())
+        Self::verify_shares(shares, pkeys, commit)
+    }
+
+    fn verify_fields(shares: &[Share], pkeys: &[RistrettoPoint], commit: &RistrettoPolynomial, n: usize, t: usize) -> Result<()> {
+        if shares.len() != n || pkeys.len() != n {
+            return Err("Field Constraint - (shares/pkeys, Expected vectors with the correct lenght)".into())
         }
 
-        if self.peers != peers_hash {
-            return Err("Field Constraint - (peers, Incorrect peers-hash)".into())
+        if commit.degree() != t {
+            return Err("Field Constraint - (commit, Incorrect polynomial degree)".into())
         }
 
-        if self.shares.len() != n || self.pkeys.len() != n {
-            return Err("Field Constraint - (shares/pkeys, Expected vectors with the correct lenght)".into())
+        Ok(())
+    }
+
+    // verify all encrypted shares in one batch, the vectors should be already checked with verify_fields
+    fn verify_shares(shares: &[Share], pkeys: &[RistrettoPoint], commit: &RistrettoPolynomial) -> Result<()> {
+        use crate::G;
+
+        // (e_i * G - P_i) -> Y_i
+        let points: Vec<RistrettoShare> = shares.iter().zip(pkeys.iter()).map(|(share, pkey)| &(share * &G) - pkey).collect();
+        if !commit.batch_verify(&points) {
+            return Err("KeyResponse with invalid shares!".into())
+        }
+
+        Ok(())
+    }
+
+    // redacted summary for logging
+    pub fn summary(&self) -> String {
+        format!("(session = {:?}, kid = {:?}, index = {:?}, #shares = {:?})", self.session, self.kid, self.sig.index, self.shares.len())
+    }
+
+    fn data(session: &str, kid: &str, peers: &[u8], shares: &[Share], pkeys: &[RistrettoPoint], commit: &RistrettoPolynomial) -> [Vec<u8>; 6] {
+        // These unwrap() should never fail, or it's a serious code bug!
+        let b_session = bincode::serialize(session).unwrap();
+        let b_kid = bincode::serialize(kid).unwrap();
+        let b_peers = bincode::serialize(peers).unwrap();
+        let b_shares = bincode::serialize(shares).unwrap();
+        let b_pkeys = bincode::serialize(pkeys).unwrap();
+        let b_commit = bincode::serialize(commit).unwrap();
+
+        [b_session, b_kid, b_peers, b_shares, b_pkeys, b_commit]
+    }
+}
+
+fn check_session(v_session: &str, v_kid: &str, v_peers: &[u8], session: &str, kid: &str, peers_hash: &[u8]) -> Result<()> {
+    if v_session != session {
+        return Err("Field Constraint - (session, Expected the same session)".into())
+    }
+
+    if v_kid != kid {
+        return Err("Field Constraint - (kid, Expected the same key-id)".into())
+    }
+
+    if v_peers != peers_hash {
+        return Err("Field Constraint - (peers, Incorrect peers-hash)".into())
+    }
+
+    Ok(())
+}
+
+//--------------------------------------------------------------------
+// Response to MasterKey negotiation with Pedersen's commitments (first phase)
+// The commit (a_k * G + b_k * H) hides the secret polynomial, including the public part of the secret (a_0 * G).
+// The votes are committed (PedersenKey) before any public part is revealed (MasterKeyReveal), so a peer can't choose
+// its secret after seeing the public parts of the others, biasing the master-key.
+//--------------------------------------------------------------------
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PedersenKeyVote {
+    pub session: String,
+    pub kid: String,
+    pub peers: Vec<u8>,
+
+    // share structures with public verifiability
+    pub shares: Vec<Share>,
+    pub pkeys: Vec<RistrettoPoint>,
+    pub commit: RistrettoPolynomial,
+    pub b_shares: Vec<Share>,               // encrypted blinding shares (f_i + z_i)
+    pub b_keys: Vec<RistrettoPoint>,        // blinding public keys (f_i * H)
+
+    pub sig: IndSignature
+}
+
+// The encrypted shares and blinding shares are never formatted.
+impl Debug for PedersenKeyVote {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> std::fmt::Result {
+        let peers = self.peers.encode();
+        fmt.debug_struct("PedersenKeyVote")
+            .field("session", &self.session)
+            .field("kid", &self.kid)
+            .field("peers", &peers)
+            .field("#shares", &self.shares.len())
+            .field("pkeys", &self.pkeys)
+            .field("commit", &self.commit)
+            .field("b_keys", &self.b_keys)
+            .field("sig", &self.sig)
+            .finish()
+    }
+}
+
+impl PedersenKeyVote {
+    const DOMAIN: &'static str = "fpi:pedersen-key-vote";
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn sign(session: &str, kid: &str, peers_hash: &[u8], shares: Vec<Share>, pkeys: Vec<RistrettoPoint>, commit: RistrettoPolynomial, b_shares: Vec<Share>, b_keys: Vec<RistrettoPoint>, secret: &Scalar, key: &RistrettoPoint, index: usize) -> Self {
+        let sig_data = Self::data(session, kid, peers_hash, &shares, &pkeys, &commit, &b_shares, &b_keys);
+        let sig = IndSignature::sign(index, secret, key, Self::DOMAIN, &sig_data);
+
+        Self { session: session.into(), kid: kid.into(), peers: peers_hash.to_vec(), shares, pkeys, commit, b_shares, b_keys, sig }
+    }
+
+    pub fn check(&self, session: &str, kid: &str, peers_hash: &[u8], n: usize, t: usize, pkey: &RistrettoPoint) -> Result<()> {
+        check_session(&self.session, &self.kid, &self.peers, session, kid, peers_hash)?;
+
+        if self.shares.len() != n || self.pkeys.len() != n || self.b_shares.len() != n || self.b_keys.len() != n {
+            return Err("Field Constraint - (shares/pkeys/b_shares/b_keys, Expected vectors with the correct lenght)".into())
         }
 
-        if self.commit.degree() != n + 1 {
+        if self.commit.degree() != t {
             return Err("Field Constraint - (commit, Incorrect polynomial degree)".into())
         }
 
-        let sig_data = Self::data(&self.session, &self.kid, &self.peers, &self.shares, &self.pkeys, &self.commit);
-        if !self.sig.verify(pkey, &sig_data) {
+        let sig_data = Self::data(&self.session, &self.kid, &self.peers, &self.shares, &self.pkeys, &self.commit, &self.b_shares, &self.b_keys);
+        if !self.sig.verify(pkey, Self::DOMAIN, &sig_data) {
             return Err("Invalid master-key request signature!".into())
         }
 
-        // it's assured that all vectors are of the same size
-        // verify each encrypted share
-        use crate::G;
-        #[allow(non_snake_case)]
-        for i in 0..n {
-            // (e_i * G - P_i) -> Y_i
+        self.verify_shares()
+    }
+
+    // verify all encrypted shares in one batch, the Pedersen's commit evaluates to (Y_i + Z_i)
+    #[allow(non_snake_case)]
+    fn verify_shares(&self) -> Result<()> {
+        let h = pedersen_h();
+
+        let mut points = Vec::<RistrettoShare>::with_capacity(self.shares.len());
+        for i in 0..self.shares.len() {
+            // (e_i * G - P_i) -> Y_i and (f_i * H - F_i) -> Z_i
             let Yi = &(&self.shares[i] * &G) - &self.pkeys[i];
-            if !self.commit.verify(&Yi) {
+            let Zi = &(&self.b_shares[i] * &h) - &self.b_keys[i];
+            if Zi.i != Yi.i {
                 return Err("KeyResponse with invalid shares!".into())
             }
+
+            points.push(RistrettoShare { i: Yi.i, Yi: Yi.Yi + Zi.Yi });
+        }
+
+        if !self.commit.batch_verify(&points) {
+            return Err("KeyResponse with invalid shares!".into())
         }
 
         Ok(())
     }
 
-    fn data(session: &str, kid: &str, peers: &[u8], shares: &[Share], pkeys: &[RistrettoPoint], commit: &RistrettoPolynomial) -> [Vec<u8>; 6] {
+    // redacted summary for logging
+    pub fn summary(&self) -> String {
+        format!("(session = {:?}, kid = {:?}, index = {:?}, #shares = {:?})", self.session, self.kid, self.sig.index, self.shares.len())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn data(session: &str, kid: &str, peers: &[u8], shares: &[Share], pkeys: &[RistrettoPoint], commit: &RistrettoPolynomial, b_shares: &[Share], b_keys: &[RistrettoPoint]) -> [Vec<u8>; 7] {
         // These unwrap() should never fail, or it's a serious code bug!
         let b_session = bincode::serialize(session).unwrap();
         let b_kid = bincode::serialize(kid).unwrap();
         let b_peers = bincode::serialize(peers).unwrap();
+        let b_blinding = bincode::serialize(&(b_shares, b_keys)).unwrap();
         let b_shares = bincode::serialize(shares).unwrap();
         let b_pkeys = bincode::serialize(pkeys).unwrap();
         let b_commit = bincode::serialize(commit).unwrap();
 
-        [b_session, b_kid, b_peers, b_shares, b_pkeys, b_commit]
+        [b_session, b_kid, b_peers, b_shares, b_pkeys, b_commit, b_blinding]
     }
 }
 
+// Peer indexes of the votes produced under a different peer-set from the request.
+// A peer with a divergent configuration is reported before the generic vote checks.
+pub fn divergent_peers(votes: &[MasterKeyVote], peers_hash: &[u8]) -> Vec<usize> {
+    votes.iter().filter(|vote| vote.peers != peers_hash).map(|vote| vote.sig.index).collect()
+}
+
+// The master public-key expected from a set of votes, the sum of the first Feldman's coefficient of each vote.
+pub fn aggregate_public(votes: &[MasterKeyVote]) -> RistrettoPoint {
+    votes.iter().fold(RistrettoPoint::default(), |total, vote| total + vote.commit.coefficients()[0])
+}
+
 
 //--------------------------------------------------------------------
 // Commit the master key negotiation
 //--------------------------------------------------------------------
+#[allow(clippy::manual_non_exhaustive)]
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct MasterKey {
     pub sid: String,
@@ -193,41 +595,45 @@ pub struct MasterKey {
 impl Constraints for MasterKey {
     fn sid(&self) -> &str { &self.sid }
 
-    fn verify(&self, subject: &Subject, threshold: Duration) -> Result<()> {
+    fn verify(&self, subject: &Subject, threshold: Duration) -> FpiResult<()> {
         if self.sid.len() > MAX_SUBJECT_ID_SIZE {
-            return Err(format!("Field Constraint - (sid, max-size = {})", MAX_SUBJECT_ID_SIZE))
+            return Err(FpiError::constraint("sid", format!("max-size = {}", MAX_SUBJECT_ID_SIZE)))
         }
 
         if self.session.len() > MAX_HASH_SIZE {
-            return Err(format!("Field Constraint - (session, max-size = {})", MAX_HASH_SIZE))
+            return Err(FpiError::constraint("session", format!("max-size = {}", MAX_HASH_SIZE)))
         }
 
         if self.kid.len() > MAX_KEY_ID_SIZE {
-            return Err(format!("Field Constraint - (kid, max-size = {})", MAX_KEY_ID_SIZE))
+            return Err(FpiError::constraint("kid", format!("max-size = {}", MAX_KEY_ID_SIZE)))
         }
 
         if self.matrix.triangle.len() > MAX_PEERS {
-            return Err(format!("Field Constraint - (matrix, max-size = {})", MAX_PEERS))
+            return Err(FpiError::constraint("matrix", format!("max-size = {}", MAX_PEERS)))
         }
 
         for line in self.matrix.triangle.iter() {
             if line.len() > MAX_PEERS {
-                return Err(format!("Field Constraint - (matrix-line, max-size = {})", MAX_PEERS))
+                return Err(FpiError::constraint("matrix-line", format!("max-size = {}", MAX_PEERS)))
             }
         }
 
         if self.votes.len() > MAX_PEERS {
-            return Err(format!("Field Constraint - (votes, max-size = {})", MAX_PEERS))
+            return Err(FpiError::constraint("votes", format!("max-size = {}", MAX_PEERS)))
         }
 
         if !self.sig.sig.check_timestamp(threshold) {
-            return Err("Field Constraint - (sig, Timestamp out of valid range)".into())
+            return Err(FpiError::Timestamp)
+        }
+
+        let skey = subject.active_key()?;
+        if self.sig.index != skey.sig.index {
+            return Err(FpiError::constraint("sig", "Incorrect key index"))
         }
 
-        let skey = subject.keys.last().ok_or("No active subject-key found!")?;
         let sig_data = Self::data(&self.sid, &self.session, &self.kid, &self.matrix, &self.votes);
-        if !self.sig.verify(&skey.key, &sig_data) {
-            return Err("Field Constraint - (sig, Invalid signature)".into())
+        if !self.sig.verify(&skey.key, Self::DOMAIN, &sig_data) {
+            return Err(FpiError::signature("sig"))
         }
 
         Ok(())
@@ -235,14 +641,14 @@ impl Constraints for MasterKey {
 }
 
 impl MasterKey {
-    pub fn sign(sid: &str, session: &str, kid: &str, peers_hash: &[u8], votes: Vec<MasterKeyVote>, pkeys: &[RistrettoPoint], sig_s: &Scalar, sig_key: &SubjectKey) -> Result<Self> {
+    const DOMAIN: &'static str = "fpi:master-key";
+
+    pub fn sign(sid: &str, session: &str, kid: &str, peers_hash: &[u8], votes: Vec<MasterKeyVote>, pkeys: &[RistrettoPoint], t: usize, sig_s: &Scalar, sig_key: &SubjectKey) -> Result<Self> {
         let n = pkeys.len();
 
         // check all peer responses
         for item in votes.iter() {
-            let key = pkeys.get(item.sig.index)
-                .ok_or_else(|| format!("MasterKey, expecting to find a peer at index: {}", item.sig.index))?;
-            item.check(session, kid, peers_hash, n, key)?;
+            item.check(session, kid, peers_hash, n, t, item.sig.key(pkeys)?)?;
         }
 
         let matrix = PublicMatrix::create(&votes)?;
@@ -250,12 +656,25 @@ impl MasterKey {
             .map(|vote| MasterKeyCompressedVote { shares: vote.shares, commit: vote.commit, sig: vote.sig }).collect();
 
         let sig_data = Self::data(sid, session, kid, &matrix, &votes);
-        let sig = IndSignature::sign(sig_key.sig.index, sig_s, &sig_key.key, &sig_data);
+        let sig = IndSignature::sign(sig_key.sig.index, sig_s, &sig_key.key, Self::DOMAIN, &sig_data);
 
         Ok(Self { sid: sid.into(), session: session.into(), kid: kid.into(), matrix, votes, sig, _phantom: () })
     }
 
-    pub fn check(&self, peers_hash: &[u8], pkeys: &[RistrettoPoint]) -> Result<()> {
+    // Evidence negotiated by the previous peer-set is still accepted during the transition to the current one (change).
+    pub fn check(&self, peers_hash: &[u8], pkeys: &[RistrettoPoint], t: usize, change: Option<&PeerChange>) -> Result<()> {
+        let err = match self.check_votes(peers_hash, pkeys, t) {
+            Ok(()) => return Ok(()),
+            Err(e) => e
+        };
+
+        match change.and_then(|change| change.previous(peers_hash)) {
+            Some((old_hash, old_keys, old_t)) => self.check_votes(old_hash, old_keys, old_t).map_err(|_| err),
+            None => Err(err)
+        }
+    }
+
+    fn check_votes(&self, peers_hash: &[u8], pkeys: &[RistrettoPoint], t: usize) -> Result<()> {
         let n = pkeys.len();
 
         self.matrix.check(n)?;
@@ -264,30 +683,76 @@ impl MasterKey {
             return Err("Expecting votes from all peers!".into())
         }
 
-        // reconstruct each KeyResponse and check
-        for i in 0..n {
-            let item = &self.votes[i];
-            item.check(n)?;
+        // check the fields of each vote with the expanded pkeys, the vote fields are borrowed
+        let mut expanded = Vec::<Vec<RistrettoPoint>>::with_capacity(n);
+        let mut keys = Vec::<&RistrettoPoint>::with_capacity(n);
+        for (i, item) in self.votes.iter().enumerate() {
+            item.check(n, t)?;
+            expanded.push(self.matrix.expand(n, i)?);
+            keys.push(item.sig.key(pkeys)?);
+            MasterKeyVote::verify_fields(&item.shares, &expanded[i], &item.commit, n, t)?;
+        }
 
-            let resp = MasterKeyVote {
-                session: self.session.clone(),
-                kid: self.kid.clone(),
-                peers: peers_hash.to_vec(),
-                
-                shares: item.shares.clone(),
-                pkeys: self.matrix.expand(n, i),
-                commit: item.commit.clone(),
+        // verify the signatures of all votes in one batch, and then the shares
+        let sig_data: Vec<[Vec<u8>; 6]> = self.votes.iter().zip(expanded.iter())
+            .map(|(item, pkeys)| MasterKeyVote::data(&self.session, &self.kid, peers_hash, &item.shares, pkeys, &item.commit)).collect();
+        let batch: Vec<(&IndSignature, &RistrettoPoint, &[Vec<u8>])> = self.votes.iter().zip(keys).zip(sig_data.iter())
+            .map(|((item, key), data)| (&item.sig, key, &data[..])).collect();
+        IndSignature::verify_batch(MasterKeyVote::DOMAIN, &batch)
+            .map_err(|_| "Invalid master-key request signature!".to_string())?;
 
-                sig: item.sig.clone()
-            };
+        for (item, pkeys) in self.votes.iter().zip(expanded.iter()) {
+            MasterKeyVote::verify_shares(&item.shares, pkeys, &item.commit)?;
+        }
 
-            let key = pkeys.get(item.sig.index).ok_or("MasterKey, expecting to find a peer at index!")?;
-            resp.check(&self.session, &self.kid, peers_hash, n, key)?;
+        Ok(())
+    }
+
+    // Client-side verification before the commit, the evidence must aggregate to the public-key of the collected votes.
+    pub fn check_aggregate(&self, expected: &RistrettoPoint) -> Result<()> {
+        if self.public() != *expected {
+            return Err("MasterKey with an incorrect aggregated public-key!".into())
         }
 
         Ok(())
     }
 
+    // Evidence of a share refresh, each vote must share a zero secret (a_0 * G = 0 * G).
+    // Checking only the aggregate would accept votes cancelling each other.
+    pub fn check_zero(&self) -> Result<()> {
+        for vote in self.votes.iter() {
+            if vote.commit.coefficients()[0] != RistrettoPoint::default() {
+                return Err(format!("Refresh vote with a non-zero secret from peer {}!", vote.sig.index))
+            }
+        }
+
+        Ok(())
+    }
+
+    // redacted summary for logging
+    pub fn summary(&self) -> String {
+        format!("(session = {:?}, kid = {:?}, #votes = {:?})", self.session, self.kid, self.votes.len())
+    }
+
+    // Evidence of the resharing to a new peer-set. The votes are from the current peer-set (pkeys, t),
+    // and must reconstruct the current master public-key.
+    pub fn reshare(sid: &str, session: &str, kid: &str, threshold: usize, new_peers: &[RistrettoPoint], mut votes: Vec<MasterKeyVote>, pkeys: &[RistrettoPoint], t: usize, public: &RistrettoPoint, sig_s: &Scalar, sig_key: &SubjectKey) -> Result<ResharedKey> {
+        votes.sort_by_key(|vote| vote.sig.index);
+
+        let sig_data = ResharedKey::data(sid, session, kid, threshold, new_peers, &votes);
+        let sig = IndSignature::sign(sig_key.sig.index, sig_s, &sig_key.key, ResharedKey::DOMAIN, &sig_data);
+
+        let reshared = ResharedKey { sid: sid.into(), session: session.into(), kid: kid.into(), threshold, new_peers: new_peers.to_vec(), votes, sig, _phantom: () };
+        reshared.check(pkeys, t, public)?;
+
+        Ok(reshared)
+    }
+
+    // the master public-key (Y) is the sum of the first Feldman's coefficient of each vote
+    pub fn public(&self) -> RistrettoPoint {
+        self.votes.iter().fold(RistrettoPoint::default(), |total, vote| total + vote.commit.coefficients()[0])
+    }
+
     pub fn extract(&self, index: usize) -> (Vec<Share>, Vec<RistrettoPolynomial>, RistrettoPoint) {
         let n = self.votes.len();
 
@@ -300,7 +765,7 @@ impl MasterKey {
             let share = vote.shares[index].clone();
             let commit = vote.commit.clone();
             
-            pkey += commit.A[0];
+            pkey += commit.coefficients()[0];
             shares.push(share);
             commits.push(commit);
         }
@@ -320,20 +785,320 @@ impl MasterKey {
     }
 }
 
+//--------------------------------------------------------------------
+// Commit the Pedersen's votes of a MasterKey negotiation (first phase)
+// Fixes the encrypted shares of all peers before the public parts are revealed. The negotiation is concluded with the
+// MasterKey of the revealed votes (see MasterKeyReveal), that must share the same committed shares (check_reveal).
+//--------------------------------------------------------------------
+#[allow(clippy::manual_non_exhaustive)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PedersenKey {
+    pub sid: String,
+    pub session: String,
+    pub kid: String,
+    pub votes: Vec<PedersenKeyVote>,
+
+    pub sig: IndSignature,       //signature from admin
+    #[serde(skip)] _phantom: () // force use of constructor
+}
+
+impl Constraints for PedersenKey {
+    fn sid(&self) -> &str { &self.sid }
+
+    fn verify(&self, subject: &Subject, threshold: Duration) -> FpiResult<()> {
+        if self.sid.len() > MAX_SUBJECT_ID_SIZE {
+            return Err(FpiError::constraint("sid", format!("max-size = {}", MAX_SUBJECT_ID_SIZE)))
+        }
+
+        if self.session.len() > MAX_HASH_SIZE {
+            return Err(FpiError::constraint("session", format!("max-size = {}", MAX_HASH_SIZE)))
+        }
+
+        if self.kid.len() > MAX_KEY_ID_SIZE {
+            return Err(FpiError::constraint("kid", format!("max-size = {}", MAX_KEY_ID_SIZE)))
+        }
+
+        if self.votes.len() > MAX_PEERS {
+            return Err(FpiError::constraint("votes", format!("max-size = {}", MAX_PEERS)))
+        }
+
+        if !self.sig.sig.check_timestamp(threshold) {
+            return Err(FpiError::Timestamp)
+        }
+
+        let skey = subject.active_key()?;
+        if self.sig.index != skey.sig.index {
+            return Err(FpiError::constraint("sig", "Incorrect key index"))
+        }
+
+        let sig_data = Self::data(&self.sid, &self.session, &self.kid, &self.votes);
+        if !self.sig.verify(&skey.key, Self::DOMAIN, &sig_data) {
+            return Err(FpiError::signature("sig"))
+        }
+
+        Ok(())
+    }
+}
+
+impl PedersenKey {
+    const DOMAIN: &'static str = "fpi:pedersen-key";
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn sign(sid: &str, session: &str, kid: &str, peers_hash: &[u8], mut votes: Vec<PedersenKeyVote>, pkeys: &[RistrettoPoint], t: usize, sig_s: &Scalar, sig_key: &SubjectKey) -> Result<Self> {
+        votes.sort_by_key(|vote| vote.sig.index);
+
+        let sig_data = Self::data(sid, session, kid, &votes);
+        let sig = IndSignature::sign(sig_key.sig.index, sig_s, &sig_key.key, Self::DOMAIN, &sig_data);
+
+        let evidence = Self { sid: sid.into(), session: session.into(), kid: kid.into(), votes, sig, _phantom: () };
+        evidence.check(peers_hash, pkeys, t)?;
+
+        Ok(evidence)
+    }
+
+    pub fn check(&self, peers_hash: &[u8], pkeys: &[RistrettoPoint], t: usize) -> Result<()> {
+        let n = pkeys.len();
+        if self.votes.len() != n {
+            return Err("Expecting votes from all peers!".into())
+        }
+
+        for (i, vote) in self.votes.iter().enumerate() {
+            if vote.sig.index != i {
+                return Err("Expecting ordered votes from different peers!".into())
+            }
+
+            vote.check(&self.session, &self.kid, peers_hash, n, t, &pkeys[i])?;
+        }
+
+        // The encryption keys (e_ij * G) and blinding keys (f_ij * H) are shared between each pair of peers. With the same keys
+        // in both votes, a peer can't move part of the share (Y_i) to the blinding (Z_i) of another peer.
+        for i in 0..n {
+            for j in i..n {
+                if self.votes[i].pkeys[j] != self.votes[j].pkeys[i] || self.votes[i].b_keys[j] != self.votes[j].b_keys[i] {
+                    return Err("Expecting a symmetric public-matrix!".into())
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // The revealed votes must share the committed encrypted shares, then the Feldman's commitments verify the same (Y_i)
+    // of the Pedersen's commitments and are fixed by them. The evidence should be already checked.
+    pub fn check_reveal(&self, evidence: &MasterKey) -> Result<()> {
+        let n = self.votes.len();
+        if evidence.session != self.session || evidence.kid != self.kid || evidence.votes.len() != n {
+            return Err("MasterKey doesn't reveal the committed Pedersen's votes!".into())
+        }
+
+        for (i, (committed, revealed)) in self.votes.iter().zip(evidence.votes.iter()).enumerate() {
+            let same_shares = committed.shares.len() == revealed.shares.len()
+                && committed.shares.iter().zip(revealed.shares.iter()).all(|(a, b)| a.i == b.i && a.yi == b.yi);
+
+            if revealed.sig.index != committed.sig.index || !same_shares || evidence.matrix.expand(n, i)? != committed.pkeys {
+                return Err(format!("MasterKey with a different vote from peer {}!", committed.sig.index))
+            }
+        }
+
+        Ok(())
+    }
+
+    // redacted summary for logging
+    pub fn summary(&self) -> String {
+        format!("(session = {:?}, kid = {:?}, #votes = {:?})", self.session, self.kid, self.votes.len())
+    }
+
+    fn data(sid: &str, session: &str, kid: &str, votes: &[PedersenKeyVote]) -> [Vec<u8>; 4] {
+        // These unwrap() should never fail, or it's a serious code bug!
+        let b_sid = bincode::serialize(sid).unwrap();
+        let b_session = bincode::serialize(session).unwrap();
+        let b_kid = bincode::serialize(kid).unwrap();
+        let b_votes = bincode::serialize(votes).unwrap();
+
+        [b_sid, b_session, b_kid, b_votes]
+    }
+}
+
+//--------------------------------------------------------------------
+// Commit the resharing of a MasterKey
+// The votes are from the current peer-set (at least t + 1, ordered by peer index) and target the new peer-set.
+//--------------------------------------------------------------------
+#[allow(clippy::manual_non_exhaustive)]
 #[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ResharedKey {
+    pub sid: String,
+    pub session: String,
+    pub kid: String,
+    pub threshold: usize,
+    pub new_peers: Vec<RistrettoPoint>,
+    pub votes: Vec<MasterKeyVote>,
+
+    pub sig: IndSignature,       //signature from admin
+    #[serde(skip)] _phantom: () // force use of constructor
+}
+
+impl Constraints for ResharedKey {
+    fn sid(&self) -> &str { &self.sid }
+
+    fn verify(&self, subject: &Subject, threshold: Duration) -> FpiResult<()> {
+        if self.sid.len() > MAX_SUBJECT_ID_SIZE {
+            return Err(FpiError::constraint("sid", format!("max-size = {}", MAX_SUBJECT_ID_SIZE)))
+        }
+
+        if self.session.len() > MAX_HASH_SIZE {
+            return Err(FpiError::constraint("session", format!("max-size = {}", MAX_HASH_SIZE)))
+        }
+
+        if self.kid.len() > MAX_KEY_ID_SIZE {
+            return Err(FpiError::constraint("kid", format!("max-size = {}", MAX_KEY_ID_SIZE)))
+        }
+
+        if self.new_peers.len() > MAX_PEERS {
+            return Err(FpiError::constraint("new_peers", format!("max-size = {}", MAX_PEERS)))
+        }
+
+        if self.votes.len() > MAX_PEERS {
+            return Err(FpiError::constraint("votes", format!("max-size = {}", MAX_PEERS)))
+        }
+
+        if !self.sig.sig.check_timestamp(threshold) {
+            return Err(FpiError::Timestamp)
+        }
+
+        let skey = subject.active_key()?;
+        if self.sig.index != skey.sig.index {
+            return Err(FpiError::constraint("sig", "Incorrect key index"))
+        }
+
+        let sig_data = Self::data(&self.sid, &self.session, &self.kid, self.threshold, &self.new_peers, &self.votes);
+        if !self.sig.verify(&skey.key, Self::DOMAIN, &sig_data) {
+            return Err(FpiError::signature("sig"))
+        }
+
+        Ok(())
+    }
+}
+
+impl ResharedKey {
+    const DOMAIN: &'static str = "fpi:reshared-key";
+
+    // (pkeys, t) of the current peer-set
+    pub fn check(&self, pkeys: &[RistrettoPoint], t: usize, public: &RistrettoPoint) -> Result<()> {
+        check_new_peers(&self.new_peers, self.threshold)?;
+
+        if self.votes.len() < t + 1 {
+            return Err("Expecting votes from at least t + 1 peers!".into())
+        }
+
+        if self.votes.windows(2).any(|pair| pair[0].sig.index >= pair[1].sig.index) {
+            return Err("Expecting ordered votes from different peers!".into())
+        }
+
+        let n = self.new_peers.len();
+        let new_hash = peers_hash(&self.new_peers);
+        for vote in self.votes.iter() {
+            vote.check(&self.session, &self.kid, &new_hash, n, self.threshold, vote.sig.key(pkeys)?)?;
+        }
+
+        // the first coefficient of each vote commits to the share of the voting peer
+        if self.public() != *public {
+            return Err("Reshared commitments don't reconstruct the master public-key!".into())
+        }
+
+        Ok(())
+    }
+
+    // The resharing may be delivered after the change to the new peer-set, then the votes are from the previous one.
+    // Returns the keys of the voting peers, required to derive the encryption keys of the shares.
+    pub fn check_transition(&self, pkeys: &[RistrettoPoint], t: usize, public: &RistrettoPoint, change: Option<&PeerChange>) -> Result<Vec<RistrettoPoint>> {
+        let voters = |pkeys: &[RistrettoPoint]| self.votes.iter().map(|vote| pkeys[vote.sig.index]).collect();
+        let err = match self.check(pkeys, t, public) {
+            Ok(()) => return Ok(voters(pkeys)),
+            Err(e) => e
+        };
+
+        match change.and_then(|change| change.previous(&peers_hash(pkeys))) {
+            Some((_, old_keys, old_t)) => self.check(old_keys, old_t, public).map(|_| voters(old_keys)).map_err(|_| err),
+            None => Err(err)
+        }
+    }
+
+    // interpolation of the committed shares (y_i * G) at the share index of each voting peer
+    pub fn public(&self) -> RistrettoPoint {
+        let shares: Vec<RistrettoShare> = self.votes.iter().map(|vote| RistrettoShare { i: vote.sig.index as u32 + 1, Yi: vote.commit.coefficients()[0] }).collect();
+        RistrettoPolynomial::interpolate(&shares).unwrap_or_default()
+    }
+
+    // Key-pair of the peer at the index of the new peer-set. The e_keys are the encryption keys (e_ij) shared with each voting peer.
+    pub fn recover(&self, index: usize, e_keys: &[Scalar]) -> Result<MasterKeyPair> {
+        if index >= self.new_peers.len() || e_keys.len() != self.votes.len() {
+            return Err("Incorrect sizes on the reshared key (index >= #new_peers || #e_keys != #votes)!".into())
+        }
+
+        let share_index = index as u32 + 1;
+        let mut shares = Vec::<Share>::with_capacity(self.votes.len());
+        for (vote, e_ij) in self.votes.iter().zip(e_keys.iter()) {
+            let e_share = vote.shares.get(index).ok_or("Invalid share index!")?;
+            if e_share.i != share_index {
+                return Err("Invalid share index!".into())
+            }
+
+            let share = e_share - e_ij;
+            if !vote.commit.verify(&(&share * &G)) {
+                return Err("Invalid recovered share!".into())
+            }
+
+            // the share of the voting peer secret, at the index of the voting peer
+            shares.push(Share { i: vote.sig.index as u32 + 1, yi: share.yi });
+        }
+
+        let yi = Polynomial::interpolate(&shares)?;
+        Ok(MasterKeyPair { kid: self.kid.clone(), share: Share { i: share_index, yi }, public: self.public() })
+    }
+
+    pub fn summary(&self) -> String {
+        format!("(session = {:?}, kid = {:?}, threshold = {:?}, #new_peers = {:?}, #votes = {:?})", self.session, self.kid, self.threshold, self.new_peers.len(), self.votes.len())
+    }
+
+    fn data(sid: &str, session: &str, kid: &str, threshold: usize, new_peers: &[RistrettoPoint], votes: &[MasterKeyVote]) -> [Vec<u8>; 6] {
+        let c_new_peers: Vec<_> = new_peers.iter().map(|pkey| pkey.compress()).collect();
+
+        // These unwrap() should never fail, or it's a serious code bug!
+        let b_sid = bincode::serialize(sid).unwrap();
+        let b_session = bincode::serialize(session).unwrap();
+        let b_kid = bincode::serialize(kid).unwrap();
+        let b_threshold = bincode::serialize(&threshold).unwrap();
+        let b_new_peers = bincode::serialize(&c_new_peers).unwrap();
+        let b_votes = bincode::serialize(votes).unwrap();
+
+        [b_sid, b_session, b_kid, b_threshold, b_new_peers, b_votes]
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct MasterKeyCompressedVote {
     pub shares: Vec<Share>,
     pub commit: RistrettoPolynomial,
     pub sig: IndSignature
 }
 
+impl Debug for MasterKeyCompressedVote {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> std::fmt::Result {
+        fmt.debug_struct("MasterKeyCompressedVote")
+            .field("#shares", &self.shares.len())
+            .field("commit", &self.commit)
+            .field("sig", &self.sig)
+            .finish()
+    }
+}
+
 impl MasterKeyCompressedVote {
-    fn check(&self, n: usize) -> Result<()> {
+    fn check(&self, n: usize, t: usize) -> Result<()> {
         if self.shares.len() != n {
             return Err("Field Constraint - (shares, Expected vector with the correct lenght)".into())
         }
 
-        if self.commit.degree() != n + 1 {
+        if self.commit.degree() != t {
             return Err("Field Constraint - (commit, Incorrect polynomial degree)".into())
         }
 
@@ -341,25 +1106,26 @@ impl MasterKeyCompressedVote {
     }
 }
 
+// Stored in the compressed form (same encoding on the wire), each line is only decompressed when expanded for verification.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct PublicMatrix {
-    pub triangle: Vec<Vec<RistrettoPoint>>
+    pub triangle: Vec<Vec<CompressedRistretto>>
 }
 
 impl PublicMatrix {
     fn create(res: &[MasterKeyVote]) -> Result<Self> {
         let n = res.len();
 
-        let mut matrix = Vec::<Vec<RistrettoPoint>>::with_capacity(n);
+        let mut matrix = Vec::<Vec<CompressedRistretto>>::with_capacity(n);
         for i in 0..n {
-            let mut line = Vec::<RistrettoPoint>::with_capacity(n-i);
+            let mut line = Vec::<CompressedRistretto>::with_capacity(n-i);
             for j in 0..n {
                 if res[i].pkeys[j] != res[j].pkeys[i] {
                     return Err("Expecting a symmetric public-matrix!".into())
                 }
 
                 if j >= i {
-                    line.push(res[i].pkeys[j]);
+                    line.push(res[i].pkeys[j].compress());
                 }
             }
 
@@ -384,15 +1150,20 @@ impl PublicMatrix {
         Ok(())
     }
 
-    fn expand(&self, length: usize, index: usize) -> Vec<RistrettoPoint> {
-        let mut pkeys = Vec::<RistrettoPoint>::with_capacity(length);
+    fn expand(&self, length: usize, index: usize) -> Result<Vec<RistrettoPoint>> {
+        let mut compressed = Vec::<&CompressedRistretto>::with_capacity(length);
         for j in 0..index {
             // (requires [index-j] instead fo [index]). The matrix is shifted left due to the lack of items
-            let replicated = self.triangle[j][index-j];
-            pkeys.push(replicated);
+            let replicated = &self.triangle[j][index-j];
+            compressed.push(replicated);
         }
 
-        pkeys.extend(&self.triangle[index]);
+        compressed.extend(&self.triangle[index]);
+
+        let mut pkeys = Vec::<RistrettoPoint>::with_capacity(length);
+        for item in compressed {
+            pkeys.push(item.decompress().ok_or("Field Constraint - (matrix, Invalid point encoding)")?);
+        }
         
         /*print!("L{} {}:", length, index);
         for k in pkeys.iter() {
@@ -400,7 +1171,139 @@ impl PublicMatrix {
         }
         println!("");*/
 
-        pkeys
+        Ok(pkeys)
+    }
+}
+
+//--------------------------------------------------------------------
+// Query the public-key of a negotiated MasterKey
+//--------------------------------------------------------------------
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MasterKeyQuery {
+    pub sid: String,
+    pub kid: String,
+    pub sig: IndSignature
+}
+
+impl Constraints for MasterKeyQuery {
+    fn sid(&self) -> &str { &self.sid }
+
+    fn verify(&self, subject: &Subject, threshold: Duration) -> FpiResult<()> {
+        if self.sid.len() > MAX_SUBJECT_ID_SIZE {
+            return Err(FpiError::constraint("sid", format!("max-size = {}", MAX_SUBJECT_ID_SIZE)))
+        }
+
+        if self.kid.len() > MAX_KEY_ID_SIZE {
+            return Err(FpiError::constraint("kid", format!("max-size = {}", MAX_KEY_ID_SIZE)))
+        }
+
+        if !self.sig.sig.check_timestamp(threshold) {
+            return Err(FpiError::Timestamp)
+        }
+
+        let skey = subject.active_key()?;
+        if self.sig.index != skey.sig.index {
+            return Err(FpiError::constraint("sig", "Incorrect key index"))
+        }
+
+        let sig_data = Self::data(&self.sid, &self.kid);
+        if !self.sig.verify(&skey.key, Self::DOMAIN, &sig_data) {
+            return Err(FpiError::signature("sig"))
+        }
+
+        Ok(())
+    }
+}
+
+impl MasterKeyQuery {
+    const DOMAIN: &'static str = "fpi:master-key-query";
+
+    pub fn sign(sid: &str, kid: &str, sig_s: &Scalar, sig_key: &SubjectKey) -> Self {
+        let sig_data = Self::data(sid, kid);
+        let sig = IndSignature::sign(sig_key.sig.index, sig_s, &sig_key.key, Self::DOMAIN, &sig_data);
+
+        Self { sid: sid.into(), kid: kid.into(), sig }
+    }
+
+    fn data(sid: &str, kid: &str) -> [Vec<u8>; 2] {
+        // These unwrap() should never fail, or it's a serious code bug!
+        let b_sid = bincode::serialize(sid).unwrap();
+        let b_kid = bincode::serialize(kid).unwrap();
+
+        [b_sid, b_kid]
+    }
+}
+
+// The public-key is carried with the committed evidence, so it can be verified without trusting the peer.
+#[allow(clippy::manual_non_exhaustive)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MasterKeyResult {
+    pub kid: String,
+    pub public: Option<RistrettoPoint>,      // None for an unknown kid
+    pub evidence: Option<MasterKey>,
+    pub change: Option<PeerChange>,          // Transition of the peer-set, the evidence may be from the previous one
+
+    pub sig: IndSignature,                  // Signature from peer
+    #[serde(skip)] _phantom: () // force use of constructor
+}
+
+impl MasterKeyResult {
+    const DOMAIN: &'static str = "fpi:master-key-result";
+
+    pub fn sign(kid: &str, evidence: Option<MasterKey>, change: Option<PeerChange>, secret: &Scalar, key: &RistrettoPoint, index: usize) -> Self {
+        let public = evidence.as_ref().map(|mkey| mkey.public());
+        let sig_data = Self::data(kid, &public, &evidence, &change);
+        let sig = IndSignature::sign(index, secret, key, Self::DOMAIN, &sig_data);
+
+        Self { kid: kid.into(), public, evidence, change, sig, _phantom: () }
+    }
+
+    pub fn check(&self, kid: &str, peers_hash: &[u8], pkeys: &[RistrettoPoint], t: usize) -> Result<()> {
+        if self.kid != kid {
+            return Err("Field Constraint - (kid, Expected the same key-id)".into())
+        }
+
+        self.verify(pkeys)?;
+
+        match (&self.public, &self.evidence) {
+            (None, None) => Ok(()),
+            (Some(public), Some(evidence)) => {
+                if evidence.kid != self.kid {
+                    return Err("Field Constraint - (evidence, Expected the same key-id)".into())
+                }
+
+                if let Some(change) = &self.change {
+                    change.check()?;
+                }
+
+                evidence.check(peers_hash, pkeys, t, self.change.as_ref())?;
+                if evidence.public() != *public {
+                    return Err("Field Constraint - (public, Doesn't match the evidence)".into())
+                }
+
+                Ok(())
+            },
+            _ => Err("Field Constraint - (public, Expected the public-key with the evidence)".into())
+        }
+    }
+
+    pub fn verify(&self, pkeys: &[RistrettoPoint]) -> FpiResult<()> {
+        let sig_data = Self::data(&self.kid, &self.public, &self.evidence, &self.change);
+        if !self.sig.verify_against(pkeys, Self::DOMAIN, &sig_data).map_err(FpiError::Crypto)? {
+            return Err(FpiError::signature("sig"))
+        }
+
+        Ok(())
+    }
+
+    fn data(kid: &str, public: &Option<RistrettoPoint>, evidence: &Option<MasterKey>, change: &Option<PeerChange>) -> [Vec<u8>; 4] {
+        // These unwrap() should never fail, or it's a serious code bug!
+        let b_kid = bincode::serialize(kid).unwrap();
+        let b_public = bincode::serialize(public).unwrap();
+        let b_evidence = bincode::serialize(evidence).unwrap();
+        let b_change = bincode::serialize(change).unwrap();
+
+        [b_kid, b_public, b_evidence, b_change]
     }
 }
 
@@ -412,4 +1315,685 @@ pub struct MasterKeyPair {
     pub kid: String,
     pub share: Share,
     pub public: RistrettoPoint
+}
+
+impl MasterKeyPair {
+    // Adds the recovered share of a zero secret (share refresh). The aggregated commitment of the refresh votes
+    // must keep the public-key, otherwise the shares would no longer reconstruct the same secret.
+    pub fn refresh(&self, delta: &Share, delta_public: &RistrettoPoint) -> Result<MasterKeyPair> {
+        if delta.i != self.share.i {
+            return Err("Refresh share with a different index!".into())
+        }
+
+        if self.public + delta_public != self.public {
+            return Err("Share refresh must keep the master public-key!".into())
+        }
+
+        Ok(MasterKeyPair { kid: self.kid.clone(), share: &self.share + delta, public: self.public })
+    }
+}
+
+// Key-id of the master-key with its own base for the profile type. It's negotiated independently of the default one,
+// so linking the pseudonyms of different types requires the secret of both keys, and not a public factor.
+pub fn typed_kid(kid: &str, typ: &str) -> Result<String> {
+    let typed = format!("{}:{}", kid, typ);
+    if typed.len() > MAX_KEY_ID_SIZE {
+        return Err(format!("Field Constraint - (kid, max-size = {}, for the type {:?})", MAX_KEY_ID_SIZE, typ))
+    }
+
+    Ok(typed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{G, rnd_scalar, KeyEncoder};
+    use crate::shares::{Polynomial, pedersen_h};
+
+    #[allow(non_snake_case)]
+    fn vote(session: &str, kid: &str, peers_hash: &[u8], n: usize, t: usize, secret: &Scalar, index: usize) -> MasterKeyVote {
+        let poly = Polynomial::rnd(rnd_scalar(), t);
+        let shares = poly.shares(n);
+
+        let e_keys: Vec<Scalar> = (0..n).map(|_| rnd_scalar()).collect();
+        let p_keys: Vec<RistrettoPoint> = e_keys.iter().map(|e_i| e_i * G).collect();
+        let e_shares: Vec<Share> = shares.0.iter().zip(e_keys.iter()).map(|(y_i, e_i)| y_i + e_i).collect();
+
+        MasterKeyVote::sign(session, kid, peers_hash, e_shares, p_keys, &poly * &G, secret, &(secret * G), index)
+    }
+
+    #[allow(non_snake_case)]
+    #[test]
+    fn test_vote_peers_hash() {
+        let (n, t) = (4, 1);
+        let secret = rnd_scalar();
+        let pkey = secret * G;
+
+        let peers_hash = vec![1u8; 64];
+        let other_hash = vec![2u8; 64];
+
+        let correct = vote("session", "kid", &peers_hash, n, t, &secret, 0);
+        assert!(correct.check("session", "kid", &peers_hash, n, t, &pkey) == Ok(()));
+        assert!(correct.check("other-session", "kid", &peers_hash, n, t, &pkey) == Err("Field Constraint - (session, Expected the same session)".into()));
+
+        // a vote built by a node with a different peer-set is rejected before the Feldman checks
+        let incorrect = vote("session", "kid", &other_hash, n, t, &secret, 0);
+        assert!(incorrect.check("session", "kid", &peers_hash, n, t, &pkey) == Err("Field Constraint - (peers, Incorrect peers-hash)".into()));
+
+        let incorrect = vote("session", "kid", &peers_hash, n, t + 1, &secret, 0);
+        assert!(incorrect.check("session", "kid", &peers_hash, n, t, &pkey) == Err("Field Constraint - (commit, Incorrect polynomial degree)".into()));
+    }
+
+    // the previous MasterKey::check implementation, rebuilding each vote
+    fn check_rebuild(mkey: &MasterKey, peers_hash: &[u8], pkeys: &[RistrettoPoint], t: usize) -> Result<()> {
+        let n = pkeys.len();
+
+        mkey.matrix.check(n)?;
+        if mkey.votes.len() != n {
+            return Err("Expecting votes from all peers!".into())
+        }
+
+        for i in 0..n {
+            let item = &mkey.votes[i];
+            item.check(n, t)?;
+
+            let resp = MasterKeyVote {
+                session: mkey.session.clone(),
+                kid: mkey.kid.clone(),
+                peers: peers_hash.to_vec(),
+                shares: item.shares.clone(),
+                pkeys: mkey.matrix.expand(n, i)?,
+                commit: item.commit.clone(),
+                sig: item.sig.clone()
+            };
+
+            let key = pkeys.get(item.sig.index).ok_or("MasterKey, expecting to find a peer at index!")?;
+            resp.check(&mkey.session, &mkey.kid, peers_hash, n, t, key)?;
+        }
+
+        Ok(())
+    }
+
+    #[allow(non_snake_case)]
+    fn evidence(sid: &str, kid: &str, peers_hash: &[u8], secrets: &[Scalar], pkeys: &[RistrettoPoint], t: usize) -> MasterKey {
+        let votes = peer_votes(kid, peers_hash, secrets, pkeys, t);
+
+        let sig_s = rnd_scalar();
+        let (_, skey) = Subject::new(sid).evolve(sig_s);
+        MasterKey::sign(sid, "session", kid, peers_hash, votes, pkeys, t, &sig_s, &skey).unwrap()
+    }
+
+    #[allow(non_snake_case)]
+    fn peer_votes(kid: &str, peers_hash: &[u8], secrets: &[Scalar], pkeys: &[RistrettoPoint], t: usize) -> Vec<MasterKeyVote> {
+        let polys: Vec<Polynomial> = secrets.iter().map(|_| Polynomial::rnd(rnd_scalar(), t)).collect();
+        polynomial_votes(kid, peers_hash, &polys, secrets, pkeys)
+    }
+
+    #[allow(non_snake_case)]
+    fn polynomial_votes(kid: &str, peers_hash: &[u8], polys: &[Polynomial], secrets: &[Scalar], pkeys: &[RistrettoPoint]) -> Vec<MasterKeyVote> {
+        let n = secrets.len();
+
+        // symmetric matrix of encryption keys between peers
+        let mut e_keys = vec![vec![Scalar::zero(); n]; n];
+        #[allow(clippy::needless_range_loop)]
+        for i in 0..n {
+            for j in i..n {
+                let e_ij = rnd_scalar();
+                e_keys[i][j] = e_ij;
+                e_keys[j][i] = e_ij;
+            }
+        }
+
+        (0..n).map(|i| {
+            let poly = polys[i].clone();
+            let shares = poly.shares(n);
+
+            let p_keys: Vec<RistrettoPoint> = e_keys[i].iter().map(|e_ij| e_ij * G).collect();
+            let e_shares: Vec<Share> = shares.0.iter().zip(e_keys[i].iter()).map(|(y_j, e_ij)| y_j + e_ij).collect();
+            MasterKeyVote::sign("session", kid, peers_hash, e_shares, p_keys, &poly * &G, &secrets[i], &pkeys[i], i)
+        }).collect()
+    }
+
+    #[allow(non_snake_case)]
+    #[test]
+    fn test_master_key_aggregate() {
+        let (n, t) = (4, 1);
+        let peers_hash = vec![1u8; 64];
+
+        let secrets: Vec<Scalar> = (0..n).map(|_| rnd_scalar()).collect();
+        let pkeys: Vec<RistrettoPoint> = secrets.iter().map(|s| s * G).collect();
+        let votes = peer_votes("kid", &peers_hash, &secrets, &pkeys, t);
+        let expected = aggregate_public(&votes);
+
+        let sig_s = rnd_scalar();
+        let (_, skey) = Subject::new("s-id:admin").evolve(sig_s);
+        let mkey = MasterKey::sign("s-id:admin", "session", "kid", &peers_hash, votes, &pkeys, t, &sig_s, &skey).unwrap();
+        assert!(mkey.check_aggregate(&expected) == Ok(()));
+
+        // a tampered vote commitment
+        let mut tampered = mkey.clone();
+        let mut coefs = tampered.votes[2].commit.coefficients().to_vec();
+        coefs[0] += G;
+        tampered.votes[2].commit = RistrettoPolynomial::from_coefficients(coefs).unwrap();
+        assert!(tampered.check_aggregate(&expected) == Err("MasterKey with an incorrect aggregated public-key!".into()));
+        assert!(tampered.check(&peers_hash, &pkeys, t, None).is_err());
+    }
+
+    #[allow(non_snake_case)]
+    #[test]
+    fn test_pedersen_votes() {
+        let (n, t) = (4, 1);
+        let peers_hash = vec![1u8; 64];
+        let H = pedersen_h();
+
+        let secrets: Vec<Scalar> = (0..n).map(|_| rnd_scalar()).collect();
+        let pkeys: Vec<RistrettoPoint> = secrets.iter().map(|s| s * G).collect();
+
+        let sig_s = rnd_scalar();
+        let (_, skey) = Subject::new("s-id:admin").evolve(sig_s);
+
+        // symmetric matrices of encryption and blinding keys between peers
+        let mut e_keys = vec![vec![Scalar::zero(); n]; n];
+        let mut f_keys = vec![vec![Scalar::zero(); n]; n];
+        for i in 0..n {
+            for j in i..n {
+                let (e_ij, f_ij) = (rnd_scalar(), rnd_scalar());
+                e_keys[i][j] = e_ij; e_keys[j][i] = e_ij;
+                f_keys[i][j] = f_ij; f_keys[j][i] = f_ij;
+            }
+        }
+
+        let polys: Vec<(Polynomial, Polynomial)> = (0..n).map(|_| (Polynomial::rnd(rnd_scalar(), t), Polynomial::rnd(rnd_scalar(), t))).collect();
+        let y = polys.iter().fold(Scalar::zero(), |total, (ak, _)| total + ak.coefficients()[0]);
+
+        let pedersen_vote = |i: usize, ak: &Polynomial, bk: &Polynomial| {
+            let p_keys: Vec<RistrettoPoint> = e_keys[i].iter().map(|e_ij| e_ij * G).collect();
+            let e_shares: Vec<Share> = ak.shares(n).0.iter().zip(e_keys[i].iter()).map(|(y_j, e_ij)| y_j + e_ij).collect();
+            let b_keys: Vec<RistrettoPoint> = f_keys[i].iter().map(|f_ij| f_ij * H).collect();
+            let b_shares: Vec<Share> = bk.shares(n).0.iter().zip(f_keys[i].iter()).map(|(z_j, f_ij)| z_j + f_ij).collect();
+            (e_shares, p_keys, RistrettoPolynomial::pedersen(ak, bk).unwrap(), b_shares, b_keys)
+        };
+
+        let votes: Vec<PedersenKeyVote> = polys.iter().enumerate().map(|(i, (ak, bk))| {
+            let (e_shares, p_keys, commit, b_shares, b_keys) = pedersen_vote(i, ak, bk);
+            PedersenKeyVote::sign("session", "kid", &peers_hash, e_shares, p_keys, commit, b_shares, b_keys, &secrets[i], &pkeys[i], i)
+        }).collect();
+
+        for vote in votes.iter() {
+            assert!(vote.check("session", "kid", &peers_hash, n, t, &pkeys[vote.sig.index]) == Ok(()));
+        }
+
+        // the commit hides the public part of the secret
+        assert!(votes.iter().zip(polys.iter()).all(|(vote, (ak, _))| vote.commit.coefficients()[0] != ak.coefficients()[0] * G));
+
+        // first phase, the Pedersen's votes are committed
+        let pk = PedersenKey::sign("s-id:admin", "session", "kid", &peers_hash, votes.clone(), &pkeys, t, &sig_s, &skey).unwrap();
+        assert!(pk.check(&peers_hash, &pkeys, t) == Ok(()));
+
+        // a share moved to the blinding (same Y_i + Z_i) is a valid vote, but the blinding key is not the shared one
+        let (ak, bk) = &polys[1];
+        let (mut e_shares, p_keys, commit, b_shares, mut b_keys) = pedersen_vote(1, ak, bk);
+        e_shares[2].yi += Scalar::one();
+        b_keys[2] += G;
+        let moved = PedersenKeyVote::sign("session", "kid", &peers_hash, e_shares, p_keys, commit, b_shares, b_keys, &secrets[1], &pkeys[1], 1);
+        assert!(moved.check("session", "kid", &peers_hash, n, t, &pkeys[1]) == Ok(()));
+
+        let mut c_votes = votes.clone();
+        c_votes[1] = moved;
+        assert!(PedersenKey::sign("s-id:admin", "session", "kid", &peers_hash, c_votes, &pkeys, t, &sig_s, &skey).err() == Some("Expecting a symmetric public-matrix!".into()));
+
+        // second phase, the Feldman's commitments of the same polynomials reveal the master-key
+        let revealed: Vec<MasterKeyVote> = pk.votes.iter().zip(polys.iter()).enumerate().map(|(i, (vote, (ak, _)))| {
+            MasterKeyVote::sign("session", "kid", &peers_hash, vote.shares.clone(), vote.pkeys.clone(), ak * &G, &secrets[i], &pkeys[i], i)
+        }).collect();
+
+        let mkey = MasterKey::sign("s-id:admin", "session", "kid", &peers_hash, revealed.clone(), &pkeys, t, &sig_s, &skey).unwrap();
+        assert!(mkey.check(&peers_hash, &pkeys, t, None) == Ok(()));
+        assert!(pk.check_reveal(&mkey) == Ok(()));
+        assert!(mkey.public() == y * G);
+
+        // recover the shares of each peer, checked against both commits
+        let mut shares = Vec::<Share>::with_capacity(n);
+        for i in 0..n {
+            let (e_shares, commits, public) = mkey.extract(i);
+            assert!(public == y * G);
+
+            let mut yi = Scalar::zero();
+            for j in 0..n {
+                let share = &e_shares[j] - &e_keys[j][i];
+                let b_share = &pk.votes[j].b_shares[i] - &f_keys[j][i];
+                assert!(commits[j].verify(&(&share * &G)));
+                assert!(pk.votes[j].commit.verify_pedersen(&(&share * &G), &(&b_share * &H)));
+                yi += share.yi;
+            }
+
+            shares.push(Share { i: i as u32 + 1, yi });
+        }
+
+        assert!(Polynomial::interpolate(&shares) == Ok(y));
+
+        // a revealed vote choosing another secret, after the public parts of the others are known
+        let other = Polynomial::rnd(rnd_scalar(), t);
+        let mut o_votes = revealed.clone();
+        let o_shares: Vec<Share> = other.shares(n).0.iter().zip(e_keys[0].iter()).map(|(y_j, e_ij)| y_j + e_ij).collect();
+        o_votes[0] = MasterKeyVote::sign("session", "kid", &peers_hash, o_shares, revealed[0].pkeys.clone(), &other * &G, &secrets[0], &pkeys[0], 0);
+
+        let o_mkey = MasterKey::sign("s-id:admin", "session", "kid", &peers_hash, o_votes, &pkeys, t, &sig_s, &skey).unwrap();
+        assert!(pk.check_reveal(&o_mkey) == Err("MasterKey with a different vote from peer 0!".into()));
+
+        // the evidence keeps the encoding of the stored master-keys (shares, commit, sig)
+        let item = &mkey.votes[0];
+        let stored = [bincode::serialize(&item.shares).unwrap(), bincode::serialize(&item.commit).unwrap(), bincode::serialize(&item.sig).unwrap()].concat();
+        assert!(bincode::serialize(item).unwrap() == stored);
+    }
+
+    #[allow(non_snake_case)]
+    #[test]
+    fn test_master_key_check() {
+        let (n, t) = (4, 1);
+        let peers_hash = vec![1u8; 64];
+
+        let secrets: Vec<Scalar> = (0..n).map(|_| rnd_scalar()).collect();
+        let pkeys: Vec<RistrettoPoint> = secrets.iter().map(|s| s * G).collect();
+        let mkey = evidence("s-id:admin", "kid", &peers_hash, &secrets, &pkeys, t);
+
+        // valid evidence, also for a deep clone
+        assert!(mkey.check(&peers_hash, &pkeys, t, None) == Ok(()));
+        assert!(mkey.check(&peers_hash, &pkeys, t, None) == check_rebuild(&mkey, &peers_hash, &pkeys, t));
+
+        let cloned = mkey.clone();
+        assert!(cloned.check(&peers_hash, &pkeys, t, None) == Ok(()));
+        assert!(cloned.votes[0].commit == mkey.votes[0].commit);
+
+        // invalid evidence
+        let other_hash = vec![2u8; 64];
+        let res = mkey.check(&other_hash, &pkeys, t, None);
+        assert!(res == Err("Invalid master-key request signature!".into()));
+        assert!(res == check_rebuild(&mkey, &other_hash, &pkeys, t));
+
+        let res = mkey.check(&peers_hash, &pkeys, t + 1, None);
+        assert!(res == Err("Field Constraint - (commit, Incorrect polynomial degree)".into()));
+        assert!(res == check_rebuild(&mkey, &peers_hash, &pkeys, t + 1));
+
+        let mut tampered = mkey.clone();
+        tampered.votes[1].shares.swap(0, 1);
+        let res = tampered.check(&peers_hash, &pkeys, t, None);
+        assert!(res == Err("Invalid master-key request signature!".into()));
+        assert!(res == check_rebuild(&tampered, &peers_hash, &pkeys, t));
+
+        // the clone is independent from the original
+        assert!(mkey.check(&peers_hash, &pkeys, t, None) == Ok(()));
+
+        let mut tampered = mkey.clone();
+        tampered.matrix.triangle[0].pop();
+        let res = tampered.check(&peers_hash, &pkeys, t, None);
+        assert!(res == Err("Matrix with incorrect triangle!".into()));
+        assert!(res == check_rebuild(&tampered, &peers_hash, &pkeys, t));
+    }
+
+    #[allow(non_snake_case)]
+    #[test]
+    fn test_type_base() {
+        use crate::shares::{RistrettoShare, Interpolate};
+
+        let threshold = 1;
+        let parties = 3*threshold + 1;
+
+        assert!(typed_kid("p-master", "HealthCare") == Ok("p-master:HealthCare".into()));
+        assert!(typed_kid("p-master", "HealthCare-Records-Of-Type") == Err("Field Constraint - (kid, max-size = 32, for the type \"HealthCare-Records-Of-Type\")".into()));
+
+        // each type has its own key, negotiated with an independent secret
+        let typed_pairs = |typ: &str| -> Vec<MasterKeyPair> {
+            let y = rnd_scalar();
+            let shares = Polynomial::rnd(y, threshold).shares(parties);
+            shares.0.iter().map(|share| MasterKeyPair { kid: typed_kid("p-master", typ).unwrap(), share: share.clone(), public: y * G }).collect()
+        };
+
+        let p = rnd_scalar();
+        let P = p * G;
+
+        let health = typed_pairs("HealthCare");
+        let finance = typed_pairs("Financial");
+        assert!(health[0].public != finance[0].public);
+
+        // disclosed shares reconstruct the pseudonym under the type base
+        for typed in [health, finance].iter() {
+            let d_shares: Vec<RistrettoShare> = typed.iter().map(|pair| &pair.share * &P).collect();
+            let pseudonym = RistrettoPolynomial::interpolate(&d_shares[0..2*threshold + 1]).unwrap();
+            assert!(pseudonym == p * typed[0].public);
+            assert!(Ok(pseudonym) == RistrettoPolynomial::interpolate(&d_shares[1..]));
+        }
+    }
+
+    #[test]
+    fn test_master_key_query() {
+        let (n, t) = (4, 1);
+        let peers_hash = vec![1u8; 64];
+
+        let secrets: Vec<Scalar> = (0..n).map(|_| rnd_scalar()).collect();
+        let pkeys: Vec<RistrettoPoint> = secrets.iter().map(|s| s * G).collect();
+        let mkey = evidence("s-id:admin", "p-master", &peers_hash, &secrets, &pkeys, t);
+
+        // the public-key is the sum of the secret commits
+        let public = mkey.public();
+        assert!(mkey.extract(0).2 == public);
+
+        let res = MasterKeyResult::sign("p-master", Some(mkey.clone()), None, &secrets[1], &pkeys[1], 1);
+        assert!(res.check("p-master", &peers_hash, &pkeys, t) == Ok(()));
+        assert!(res.public == Some(public));
+
+        // unknown key-id
+        let res = MasterKeyResult::sign("unknown", None, None, &secrets[1], &pkeys[1], 1);
+        assert!(res.check("unknown", &peers_hash, &pkeys, t) == Ok(()));
+        assert!(res.public.is_none());
+
+        // results for other keys, or not verifiable against the evidence
+        let res = MasterKeyResult::sign("e-master", Some(mkey.clone()), None, &secrets[1], &pkeys[1], 1);
+        assert!(res.check("p-master", &peers_hash, &pkeys, t) == Err("Field Constraint - (kid, Expected the same key-id)".into()));
+        assert!(res.check("e-master", &peers_hash, &pkeys, t) == Err("Field Constraint - (evidence, Expected the same key-id)".into()));
+
+        let mut res = MasterKeyResult::sign("p-master", Some(mkey.clone()), None, &secrets[1], &pkeys[1], 1);
+        res.public = Some(rnd_scalar() * G);
+        assert!(res.check("p-master", &peers_hash, &pkeys, t) == Err("Field Constraint - (sig, Invalid signature)".into()));
+
+        let mut forged = mkey.clone();
+        forged.votes.pop();
+        let res = MasterKeyResult::sign("p-master", Some(forged), None, &secrets[1], &pkeys[1], 1);
+        assert!(res.check("p-master", &peers_hash, &pkeys, t) == Err("Expecting votes from all peers!".into()));
+    }
+
+    #[test]
+    fn test_divergent_peers() {
+        let (n, t) = (4, 1);
+        let peers_hash = vec![1u8; 64];
+        let other_hash = vec![2u8; 64];
+
+        let secrets: Vec<Scalar> = (0..n).map(|_| rnd_scalar()).collect();
+        let mut votes: Vec<MasterKeyVote> = (0..n).map(|i| vote("session", "kid", &peers_hash, n, t, &secrets[i], i)).collect();
+        assert!(divergent_peers(&votes, &peers_hash).is_empty());
+
+        // the peer at index 2 is configured with a different peer-set
+        votes[2] = vote("session", "kid", &other_hash, n, t, &secrets[2], 2);
+        assert!(divergent_peers(&votes, &peers_hash) == vec![2]);
+        assert!(divergent_peers(&votes, &other_hash) == vec![0, 1, 3]);
+    }
+
+    #[test]
+    fn test_redacted_votes() {
+        let (n, t) = (4, 1);
+        let peers_hash = vec![1u8; 64];
+
+        let secrets: Vec<Scalar> = (0..n).map(|_| rnd_scalar()).collect();
+        let pkeys: Vec<RistrettoPoint> = secrets.iter().map(|s| s * G).collect();
+
+        let vote = vote("session", "kid", &peers_hash, n, t, &secrets[0], 0);
+        let mkey = evidence("s-id:admin", "kid", &peers_hash, &secrets, &pkeys, t);
+
+        let logs = [format!("{:?}", vote), vote.summary(), format!("{:?}", mkey), mkey.summary()];
+        let shares = vote.shares.iter().chain(mkey.votes.iter().flat_map(|item| item.shares.iter()));
+        for share in shares {
+            let encoded = share.yi.encode();
+            assert!(logs.iter().all(|log| !log.contains(&encoded)));
+        }
+
+        assert!(vote.summary() == "(session = \"session\", kid = \"kid\", index = 0, #shares = 4)");
+        assert!(mkey.summary() == "(session = \"session\", kid = \"kid\", #votes = 4)");
+    }
+
+    #[test]
+    fn test_compact_matrix() {
+        let (n, t) = (4, 1);
+        let peers_hash = vec![1u8; 64];
+
+        let secrets: Vec<Scalar> = (0..n).map(|_| rnd_scalar()).collect();
+        let pkeys: Vec<RistrettoPoint> = secrets.iter().map(|s| s * G).collect();
+        let mkey = evidence("s-id:admin", "kid", &peers_hash, &secrets, &pkeys, t);
+
+        // same encoding of the expanded representation
+        let expanded: Vec<Vec<RistrettoPoint>> = mkey.matrix.triangle.iter().map(|line| line.iter().map(|item| item.decompress().unwrap()).collect()).collect();
+        let data = bincode::serialize(&mkey.matrix).unwrap();
+        assert!(data == bincode::serialize(&expanded).unwrap());
+
+        // round-trips and expands to the same lines
+        let matrix: PublicMatrix = bincode::deserialize(&data).unwrap();
+        assert!(matrix.triangle == mkey.matrix.triangle);
+        for i in 0..n {
+            let line = matrix.expand(n, i).unwrap();
+            assert!(line.len() == n);
+            for (j, item) in line.iter().enumerate() {
+                let (a, b) = if i <= j { (i, j - i) } else { (j, i - j) };
+                assert!(*item == expanded[a][b]);
+            }
+        }
+
+        // invalid points are only detected when expanded
+        let mut tampered = mkey.clone();
+        tampered.matrix.triangle[1][0] = CompressedRistretto([255u8; 32]);
+        assert!(tampered.matrix.expand(n, 0).unwrap()[1] == expanded[0][1]);
+        assert!(tampered.matrix.expand(n, 1) == Err("Field Constraint - (matrix, Invalid point encoding)".into()));
+        assert!(tampered.check(&peers_hash, &pkeys, t, None) == Err("Field Constraint - (matrix, Invalid point encoding)".into()));
+    }
+
+    #[allow(non_snake_case)]
+    #[test]
+    fn test_refresh() {
+        use crate::shares::{Interpolate, Evaluate};
+
+        let (n, t) = (4, 1);
+        let y = rnd_scalar();
+        let Y = y * G;
+        let shares = Polynomial::rnd(y, t).shares(n);
+        let pairs: Vec<MasterKeyPair> = shares.0.iter().map(|share| MasterKeyPair { kid: "p-master".into(), share: share.clone(), public: Y }).collect();
+
+        // each peer shares a polynomial with a zero secret
+        let zero: Vec<Polynomial> = (0..n).map(|_| Polynomial::rnd(Scalar::zero(), t)).collect();
+        let delta_public = zero.iter().fold(RistrettoPoint::default(), |total, poly| total + (poly * &G).coefficients()[0]);
+
+        let refreshed: Vec<MasterKeyPair> = pairs.iter().map(|pair| {
+            let x = Scalar::from(pair.share.i);
+            let delta = Share { i: pair.share.i, yi: zero.iter().fold(Scalar::zero(), |total, poly| total + poly.evaluate(&x)) };
+            pair.refresh(&delta, &delta_public).unwrap()
+        }).collect();
+
+        // same secret and public-key, but different shares
+        let new_shares: Vec<Share> = refreshed.iter().map(|pair| pair.share.clone()).collect();
+        assert!(Polynomial::interpolate(&new_shares[0..t + 1]) == Ok(y));
+        assert!(Polynomial::interpolate(&new_shares[1..]) == Ok(y));
+        assert!(refreshed.iter().zip(pairs.iter()).all(|(new, old)| new.public == Y && new.share.yi != old.share.yi));
+
+        // old and new shares don't mix
+        let mixed = vec![shares.0[0].clone(), new_shares[1].clone()];
+        assert!(Polynomial::interpolate(&mixed) != Ok(y));
+
+        // a refresh with a non-zero secret is rejected
+        let other = Polynomial::rnd(rnd_scalar(), t);
+        let delta = Share { i: 1, yi: other.evaluate(&Scalar::one()) };
+        assert!(pairs[0].refresh(&delta, &(&other * &G).coefficients()[0]).err() == Some("Share refresh must keep the master public-key!".into()));
+
+        let delta = Share { i: 2, yi: Scalar::zero() };
+        assert!(pairs[0].refresh(&delta, &delta_public).err() == Some("Refresh share with a different index!".into()));
+    }
+
+    #[test]
+    fn test_refresh_zero_votes() {
+        let (n, t) = (4, 1);
+        let peers_hash = vec![1u8; 64];
+
+        let secrets: Vec<Scalar> = (0..n).map(|_| rnd_scalar()).collect();
+        let pkeys: Vec<RistrettoPoint> = secrets.iter().map(|s| s * G).collect();
+
+        let sig_s = rnd_scalar();
+        let (_, skey) = Subject::new("s-id:admin").evolve(sig_s);
+
+        let zero: Vec<Polynomial> = (0..n).map(|_| Polynomial::rnd(Scalar::zero(), t)).collect();
+        let votes = polynomial_votes("kid", &peers_hash, &zero, &secrets, &pkeys);
+        let mkey = MasterKey::sign("s-id:admin", "session", "kid", &peers_hash, votes, &pkeys, t, &sig_s, &skey).unwrap();
+        assert!(mkey.check(&peers_hash, &pkeys, t, None) == Ok(()));
+        assert!(mkey.check_zero() == Ok(()));
+        assert!(mkey.public() == RistrettoPoint::default());
+
+        // two votes cancelling each other keep the aggregate, but are rejected per vote
+        let y = rnd_scalar();
+        let mut cancel = zero.clone();
+        cancel[1] = Polynomial::rnd(y, t);
+        cancel[2] = Polynomial::rnd(-y, t);
+        let votes = polynomial_votes("kid", &peers_hash, &cancel, &secrets, &pkeys);
+        let mkey = MasterKey::sign("s-id:admin", "session", "kid", &peers_hash, votes, &pkeys, t, &sig_s, &skey).unwrap();
+        assert!(mkey.public() == RistrettoPoint::default());
+        assert!(mkey.check_zero() == Err("Refresh vote with a non-zero secret from peer 1!".into()));
+    }
+
+    #[allow(non_snake_case)]
+    #[test]
+    fn test_reshare() {
+        // current peer-set (n, t) and the new one (n2, t2)
+        let (n, t, n2, t2) = (4, 1, 7, 2);
+        let y = rnd_scalar();
+        let Y = y * G;
+        let shares = Polynomial::rnd(y, t).shares(n);
+
+        let secrets: Vec<Scalar> = (0..n).map(|_| rnd_scalar()).collect();
+        let pkeys: Vec<RistrettoPoint> = secrets.iter().map(|s| s * G).collect();
+        let new_peers: Vec<RistrettoPoint> = (0..n2).map(|_| rnd_scalar() * G).collect();
+        let new_hash = peers_hash(&new_peers);
+
+        // encryption keys between the voting peer i and the new peer j
+        let e_keys: Vec<Vec<Scalar>> = (0..n).map(|_| (0..n2).map(|_| rnd_scalar()).collect()).collect();
+        let reshare_vote = |i: usize, yi: Scalar| -> MasterKeyVote {
+            let poly = Polynomial::rnd(yi, t2);
+            let p_keys: Vec<RistrettoPoint> = e_keys[i].iter().map(|e_ij| e_ij * G).collect();
+            let e_shares: Vec<Share> = poly.shares(n2).0.iter().zip(e_keys[i].iter()).map(|(y_j, e_ij)| y_j + e_ij).collect();
+            MasterKeyVote::sign("session", "kid", &new_hash, e_shares, p_keys, &poly * &G, &secrets[i], &pkeys[i], i)
+        };
+
+        let sig_s = rnd_scalar();
+        let (_, skey) = Subject::new("s-id:admin").evolve(sig_s);
+
+        // t + 1 votes are enough, in any order
+        let votes = vec![reshare_vote(3, shares.0[3].yi), reshare_vote(1, shares.0[1].yi)];
+        let reshared = MasterKey::reshare("s-id:admin", "session", "kid", t2, &new_peers, votes, &pkeys, t, &Y, &sig_s, &skey).unwrap();
+        assert!(reshared.votes[0].sig.index == 1 && reshared.public() == Y);
+
+        let pairs: Vec<MasterKeyPair> = (0..n2).map(|j| {
+            let keys: Vec<Scalar> = reshared.votes.iter().map(|vote| e_keys[vote.sig.index][j]).collect();
+            reshared.recover(j, &keys).unwrap()
+        }).collect();
+
+        // same secret and public-key, under the new threshold
+        let new_shares: Vec<Share> = pairs.iter().map(|pair| pair.share.clone()).collect();
+        assert!(pairs.iter().enumerate().all(|(j, pair)| pair.public == Y && pair.share.i == j as u32 + 1 && pair.kid == "kid"));
+        assert!(Polynomial::interpolate(&new_shares[0..t2 + 1]) == Ok(y));
+        assert!(Polynomial::interpolate(&new_shares[4..]) == Ok(y));
+        assert!(Polynomial::interpolate(&new_shares[0..t2]) != Ok(y));
+
+        // the new shares are on a polynomial of degree t2
+        let x = Scalar::from(9u32);
+        let at = Polynomial::interpolate_at(&new_shares[0..t2 + 1], &x).unwrap();
+        assert!(Polynomial::interpolate_at(&new_shares[3..], &x) == Ok(at));
+
+        // a voting peer that doesn't use its share
+        let votes = vec![reshare_vote(0, shares.0[0].yi), reshare_vote(2, rnd_scalar())];
+        let res = MasterKey::reshare("s-id:admin", "session", "kid", t2, &new_peers, votes, &pkeys, t, &Y, &sig_s, &skey);
+        assert!(res.err() == Some("Reshared commitments don't reconstruct the master public-key!".into()));
+
+        // not enough or repeated votes
+        let votes = vec![reshare_vote(0, shares.0[0].yi)];
+        let res = MasterKey::reshare("s-id:admin", "session", "kid", t2, &new_peers, votes, &pkeys, t, &Y, &sig_s, &skey);
+        assert!(res.err() == Some("Expecting votes from at least t + 1 peers!".into()));
+
+        let votes = vec![reshare_vote(0, shares.0[0].yi), reshare_vote(0, shares.0[0].yi)];
+        let res = MasterKey::reshare("s-id:admin", "session", "kid", t2, &new_peers, votes, &pkeys, t, &Y, &sig_s, &skey);
+        assert!(res.err() == Some("Expecting ordered votes from different peers!".into()));
+
+        // a new peer-set that doesn't support the threshold
+        let votes = vec![reshare_vote(0, shares.0[0].yi), reshare_vote(1, shares.0[1].yi)];
+        let res = MasterKey::reshare("s-id:admin", "session", "kid", 3, &new_peers, votes, &pkeys, t, &Y, &sig_s, &skey);
+        assert!(res.err() == Some("Field Constraint - (new_peers, Expecting #peers >= 3 * t + 1)".into()));
+
+        // wrong encryption key
+        let mut keys: Vec<Scalar> = reshared.votes.iter().map(|vote| e_keys[vote.sig.index][0]).collect();
+        keys[1] = rnd_scalar();
+        assert!(reshared.recover(0, &keys).err() == Some("Invalid recovered share!".into()));
+        assert!(reshared.recover(n2, &keys).is_err());
+    }
+
+    #[allow(non_snake_case)]
+    #[test]
+    fn test_reshare_transition() {
+        // the previous peer-set (n, t), the new one drops the last peer and adds 3 (n2, t2)
+        let (n, t, n2, t2) = (4, 1, 6, 1);
+        let peers_hash_of = |pkeys: &[RistrettoPoint]| peers_hash(pkeys);
+
+        let secrets: Vec<Scalar> = (0..n).map(|_| rnd_scalar()).collect();
+        let pkeys: Vec<RistrettoPoint> = secrets.iter().map(|s| s * G).collect();
+        let mut new_peers = pkeys[0..n - 1].to_vec();
+        new_peers.extend((0..n2 - n + 1).map(|_| rnd_scalar() * G));
+        
+        let sig_s = rnd_scalar();
+        let (_, skey) = Subject::new("s-id:admin").evolve(sig_s);
+        let change = PeerChange::sign("s-id:admin", t, &pkeys, &new_peers, &sig_s, &skey);
+
+        // evidence negotiated by the previous peer-set
+        let old_hash = peers_hash_of(&pkeys);
+        let mkey = evidence("s-id:admin", "kid", &old_hash, &secrets, &pkeys, t);
+        let new_hash = peers_hash_of(&new_peers);
+        assert!(mkey.check(&new_hash, &new_peers, t2, None).is_err());
+        assert!(mkey.check(&new_hash, &new_peers, t2, Some(&change)) == Ok(()));
+        assert!(mkey.check(&old_hash, &pkeys, t, Some(&change)) == Ok(()));
+
+        // the transition is only to the recorded peer-set
+        let other = PeerChange::sign("s-id:admin", t, &pkeys, &pkeys[1..], &sig_s, &skey);
+        assert!(mkey.check(&new_hash, &new_peers, t2, Some(&other)).is_err());
+
+        // the query result carries the transition, the evidence is verified with the previous peer-set
+        let res = MasterKeyResult::sign("kid", Some(mkey.clone()), Some(change.clone()), &secrets[0], &pkeys[0], 0);
+        assert!(res.check("kid", &new_hash, &new_peers, t2) == Ok(()));
+        let res = MasterKeyResult::sign("kid", Some(mkey.clone()), None, &secrets[0], &pkeys[0], 0);
+        assert!(res.check("kid", &new_hash, &new_peers, t2).is_err());
+
+        // shares of the previous peer-set, one of the votes is from the removed peer
+        let y = rnd_scalar();
+        let Y = y * G;
+        let shares = Polynomial::rnd(y, t).shares(n);
+
+        let e_keys: Vec<Vec<Scalar>> = (0..n).map(|_| (0..n2).map(|_| rnd_scalar()).collect()).collect();
+        let votes: Vec<MasterKeyVote> = [1, 3].iter().map(|i| {
+            let poly = Polynomial::rnd(shares.0[*i].yi, t2);
+            let p_keys: Vec<RistrettoPoint> = e_keys[*i].iter().map(|e_ij| e_ij * G).collect();
+            let e_shares: Vec<Share> = poly.shares(n2).0.iter().zip(e_keys[*i].iter()).map(|(y_j, e_ij)| y_j + e_ij).collect();
+            MasterKeyVote::sign("session", "kid", &new_hash, e_shares, p_keys, &poly * &G, &secrets[*i], &pkeys[*i], *i)
+        }).collect();
+
+        let reshared = MasterKey::reshare("s-id:admin", "session", "kid", t2, &new_peers, votes, &pkeys, t, &Y, &sig_s, &skey).unwrap();
+
+        // delivered after the change, the votes are from the previous peer-set
+        assert!(reshared.check(&new_peers, t2, &Y).is_err());
+        assert!(reshared.check_transition(&new_peers, t2, &Y, None).is_err());
+        assert!(reshared.check_transition(&new_peers, t2, &Y, Some(&change)) == Ok(vec![pkeys[1], pkeys[3]]));
+        assert!(reshared.check_transition(&pkeys, t, &Y, None) == Ok(vec![pkeys[1], pkeys[3]]));
+
+        // the new peer-set reconstructs the same public-key
+        let pairs: Vec<MasterKeyPair> = (0..n2).map(|j| {
+            let keys: Vec<Scalar> = reshared.votes.iter().map(|vote| e_keys[vote.sig.index][j]).collect();
+            reshared.recover(j, &keys).unwrap()
+        }).collect();
+
+        let r_shares: Vec<RistrettoShare> = pairs.iter().map(|pair| RistrettoShare { i: pair.share.i, Yi: pair.share.yi * G }).collect();
+        assert!(RistrettoPolynomial::interpolate(&r_shares[n2 - t2 - 1..]) == Ok(Y));
+        assert!(RistrettoPolynomial::interpolate(&r_shares[0..t2 + 1]) == Ok(Y));
+        assert!(pairs.iter().all(|pair| pair.public == Y));
+    }
+
+    #[test]
+    fn test_golden_layout() {
+        let peers = [1u8; 32];
+        let request = MasterKeyRequest::data("s-id:admin", "p-master", &peers);
+        assert!(crate::structs::layout(&request) == "18,16,40:EJEknKwNf7P9fNvvyWGJjUcs1xx7oqaPHVB9WM86RyRz");
+
+        let shares = vec![Share { i: 1, yi: Scalar::from(5u64) }, Share { i: 2, yi: Scalar::from(6u64) }];
+        let pkeys = vec![Scalar::from(7u64) * G, Scalar::from(8u64) * G];
+        let commit = RistrettoPolynomial::from_coefficients(vec![Scalar::from(3u64) * G, Scalar::from(4u64) * G]).unwrap();
+        let vote = MasterKeyVote::data("session", "p-master", &peers, &shares, &pkeys, &commit);
+        assert!(crate::structs::layout(&vote) == "15,16,40,96,88,88:9eK2RXLRdDkehQfTVLx3sZiNspvLfMdasm7FhMadJLqA");
+    }
 }
\ No newline at end of file
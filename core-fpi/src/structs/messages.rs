@@ -1,14 +1,18 @@
 use std::time::Duration;
 
-use crate::{Result, Constraints};
+use crate::{Result, FpiResult, Constraints, RistrettoPoint, KeyEncoder};
+use crate::signatures::IndSignature;
 use crate::structs::authorizations::*;
 use crate::structs::disclosures::*;
+use crate::structs::governance::*;
 use crate::structs::ids::*;
+use crate::structs::recovery::*;
 use crate::structs::records::*;
 use crate::structs::keys::*;
 
 use log::error;
 use serde::{Serialize, Deserialize};
+use serde::de::DeserializeOwned;
 use bincode::{serialize, deserialize};
 
 pub fn decode<'a, T: Deserialize<'a>>(data: &'a [u8]) -> Result<T> {
@@ -29,6 +33,39 @@ pub fn encode<T: Serialize>(msg: &T) -> Result<Vec<u8>> {
     Ok(data)
 }
 
+// Base58 text form of the encoded messages, as submitted in the tendermint tx and query parameters.
+pub fn decode_base58<T: DeserializeOwned>(data: &str) -> Result<T> {
+    let data = bs58::decode(data.trim().trim_matches('"')).into_vec().map_err(|_| "Unable to decode base58!")?;
+    decode(&data)
+}
+
+pub fn encode_base58<T: Serialize>(msg: &T) -> Result<String> {
+    let data = encode(msg)?;
+    Ok(data.encode())
+}
+
+// Version of the message format. Peers in a different major version (or minor, while in 0.x) may not decode the messages.
+pub const MSG_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+pub fn compatible(version: &str) -> Result<()> {
+    let (major, minor) = release(version)?;
+    let (msg_major, msg_minor) = release(MSG_VERSION)?;
+
+    if major != msg_major || (major == 0 && minor != msg_minor) {
+        return Err(format!("Incompatible version {} with the message format {}", version, MSG_VERSION))
+    }
+
+    Ok(())
+}
+
+fn release(version: &str) -> Result<(u64, u64)> {
+    let mut parts = version.trim().split('.').map(|part| part.parse::<u64>());
+    match (parts.next(), parts.next()) {
+        (Some(Ok(major)), Some(Ok(minor))) => Ok((major, minor)),
+        _ => Err(format!("Invalid version format: {:?}", version))
+    }
+}
+
 /*
 -----------------------------------------------
                 Message Hierarchy
@@ -53,13 +90,20 @@ pub enum Request {
 }
 
 
-fn request_msg(req: &Request) -> &Constraints {
+fn request_msg(req: &Request) -> &dyn Constraints {
     match req {
         Request::Negotiate(neg) => match neg {
-            Negotiate::NMasterKeyRequest(req) => req
+            Negotiate::NMasterKeyRequest(req) => req,
+            Negotiate::NMasterKeyRefresh(req) => req,
+            Negotiate::NMasterKeyReshare(req) => req,
+            Negotiate::NMasterKeyReveal(req) => req
         },
         Request::Query(query) => match query {
-            Query::QDiscloseRequest(req) => req
+            Query::QDiscloseRequest(req) => req,
+            Query::QMasterKey(req) => req,
+            Query::QRecordStream(req) => req,
+            Query::QConsents(req) => req,
+            Query::QSubject(req) => req
         }
     }
 }
@@ -69,19 +113,26 @@ impl Constraints for Request {
         request_msg(self).sid()
     }
 
-    fn verify(&self, subject: &Subject, threshold: Duration) -> Result<()> {
+    fn verify(&self, subject: &Subject, threshold: Duration) -> FpiResult<()> {
         request_msg(self).verify(subject, threshold)
     }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum Negotiate {
-    NMasterKeyRequest(MasterKeyRequest)
+    NMasterKeyRequest(MasterKeyRequest),
+    NMasterKeyRefresh(MasterKeyRefresh),
+    NMasterKeyReshare(MasterKeyReshare),
+    NMasterKeyReveal(MasterKeyReveal)       // second phase of a negotiation with Pedersen's commitments
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum Query {
-    QDiscloseRequest(DiscloseRequest)
+    QDiscloseRequest(DiscloseRequest),
+    QMasterKey(MasterKeyQuery),
+    QRecordStream(RecordStreamQuery),
+    QConsents(ConsentsQuery),               // committed consents and revokes of the subject
+    QSubject(SubjectQuery)                  // committed subject, signed with a recovered key
 }
 
 //--------------------------------------------------------------------
@@ -95,12 +146,50 @@ pub enum Response {
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum Vote {
-    VMasterKeyVote(MasterKeyVote)
+    VMasterKeyVote(MasterKeyVote),
+    VPedersenKeyVote(PedersenKeyVote)       // first phase of a negotiation with Pedersen's commitments
+}
+
+impl Vote {
+    pub fn sig(&self) -> &IndSignature {
+        match self {
+            Vote::VMasterKeyVote(vote) => &vote.sig,
+            Vote::VPedersenKeyVote(vote) => &vote.sig
+        }
+    }
 }
 
+// Every QResult variant must carry the answering peer signature over the response body.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum QResult {
-    QDiscloseResult(DiscloseResult)
+    QDiscloseResult(DiscloseResult),
+    QMasterKeyResult(MasterKeyResult),
+    QRecordStream(RecordStreamResult),
+    QConsentsResult(ConsentsResult),
+    QSubjectResult(SubjectResult)
+}
+
+impl QResult {
+    pub fn sig(&self) -> &IndSignature {
+        match self {
+            QResult::QDiscloseResult(res) => &res.sig,
+            QResult::QMasterKeyResult(res) => &res.sig,
+            QResult::QRecordStream(res) => &res.sig,
+            QResult::QConsentsResult(res) => &res.sig,
+            QResult::QSubjectResult(res) => &res.sig
+        }
+    }
+
+    // verify the peer signature (against the configured peer keys) before trusting any query result
+    pub fn verify(&self, peers: &[RistrettoPoint]) -> FpiResult<()> {
+        match self {
+            QResult::QDiscloseResult(res) => res.verify(peers),
+            QResult::QMasterKeyResult(res) => res.verify(peers),
+            QResult::QRecordStream(res) => res.verify(peers),
+            QResult::QConsentsResult(res) => res.verify(peers),
+            QResult::QSubjectResult(res) => res.verify(peers)
+        }
+    }
 }
 
 //--------------------------------------------------------------------
@@ -112,16 +201,24 @@ pub enum Commit {
     Value(Value)
 }
 
-fn commit_msg(req: &Commit) -> &Constraints {
+fn commit_msg(req: &Commit) -> &dyn Constraints {
     match req {
         Commit::Evidence(evd) => match evd {
-            Evidence::EMasterKey(req) => req
+            Evidence::EMasterKey(req) => req,
+            Evidence::EMasterKeyRefresh(req) => req,
+            Evidence::EMasterKeyReshare(req) => req,
+            Evidence::EErase(req) => req,
+            Evidence::EPeerChange(req) => req,
+            Evidence::EPedersenKey(req) => req
         },
 
         Commit::Value(value) => match value {
             Value::VSubject(req) => req,
             Value::VConsent(req) => req,
-            _ => unimplemented!()
+            Value::VProfileConsent(req) => req,
+            Value::VPeerSet(req) => req,
+            Value::VRecovery(req) => req,
+            Value::VNewRecord(req) => req
         }
     }
 }
@@ -131,20 +228,73 @@ impl Constraints for Commit {
         commit_msg(self).sid()
     }
 
-    fn verify(&self, subject: &Subject, threshold: Duration) -> Result<()> {
+    fn verify(&self, subject: &Subject, threshold: Duration) -> FpiResult<()> {
         commit_msg(self).verify(subject, threshold)
     }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum Evidence {
-    EMasterKey(MasterKey)
+    EMasterKey(MasterKey),
+    EMasterKeyRefresh(MasterKey),           // evidence of a share refresh, the votes share a zero secret
+    EMasterKeyReshare(ResharedKey),         // evidence of a resharing to a new peer-set
+    EErase(Erase),                          // erasure of the subject data, requested by the subject
+    EPeerChange(PeerChange),                // transition to a new peer-set, committed before the PeerSet
+    EPedersenKey(PedersenKey)               // Pedersen's votes of a negotiation, committed before the reveal of the MasterKey
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum Value {
     VSubject(Subject),
     VConsent(Consent),
+    VProfileConsent(ProfileConsent),
+    VPeerSet(PeerSet),
+    VRecovery(Recovery),
 
     VNewRecord(NewRecord)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compatible() {
+        let (major, minor) = release(MSG_VERSION).unwrap();
+
+        assert!(compatible(MSG_VERSION) == Ok(()));
+        assert!(compatible(&format!("{}.{}.99", major, minor)) == Ok(()));
+
+        let other = format!("{}.{}.0", major + 1, minor);
+        assert!(compatible(&other) == Err(format!("Incompatible version {} with the message format {}", other, MSG_VERSION)));
+
+        if major == 0 {
+            let other = format!("0.{}.0", minor + 1);
+            assert!(compatible(&other) == Err(format!("Incompatible version {} with the message format {}", other, MSG_VERSION)));
+        }
+
+        assert!(compatible("") == Err("Invalid version format: \"\"".into()));
+        assert!(compatible("FedPI Node") == Err("Invalid version format: \"FedPI Node\"".into()));
+    }
+
+    #[test]
+    fn test_base58() {
+        use crate::rnd_scalar;
+
+        let sig_s = rnd_scalar();
+        let (_, skey) = Subject::new("s-id:shumy").evolve(sig_s);
+
+        let consent = Consent::sign("s-id:shumy", ConsentType::Consent, "s-id:alex", &["Assets".into(), "Finance".into()], &sig_s, &skey);
+        let msg = Commit::Value(Value::VConsent(consent.clone()));
+
+        let tx = encode_base58(&msg).unwrap();
+        let dmsg: Commit = decode_base58(&format!("{:?}", tx)).unwrap();
+        match dmsg {
+            Commit::Value(Value::VConsent(dconsent)) => assert!(format!("{:?}", dconsent) == format!("{:?}", consent)),
+            _ => panic!("Unexpected message!")
+        }
+
+        assert!(decode_base58::<Commit>("0OIl").err() == Some("Unable to decode base58!".into()));
+        assert!(decode_base58::<Commit>("").err() == Some("Unable to decode structure!".into()));
+    }
+}
@@ -1,12 +1,14 @@
 pub mod authorizations;
 pub mod disclosures;
+pub mod governance;
 pub mod ids;
+pub mod recovery;
 pub mod records;
 pub mod keys;
 pub mod messages;
 
 use std::time::Duration;
-use crate::Result;
+use crate::FpiResult;
 use crate::ids::Subject;
 
 //-------------------------------------------------------------------------------------------------------
@@ -22,16 +24,34 @@ const MAX_SUBJECT_ID_SIZE: usize = 128;
 
 const MAX_PROFILES: usize = 16;
 const MAX_PROFILE_ID_SIZE: usize = 128;
+const PID_SEPARATOR: char = '@';                          // profile-location-id <typ@lurl>
 
 const MAX_LOCATIONS: usize = 16;
 const MAX_LOCATION_ID_SIZE: usize = 256;
 
 const MAX_KEY_CHAIN: usize = 16;
 
+const MAX_PURPOSE_SIZE: usize = 512;
+
+const MAX_TRUSTEES: usize = 16;
+
+const MAX_RECORDS_QUERY: usize = 100;                    // max records per stream query
+
 const MAX_META_SIZE: usize = 1024 * 1024 * 1024;        // max 1MB per record (streams must be designed around this limitation)
 const MAX_DATA_SIZE: usize = 100 * MAX_META_SIZE;       // max 100MB per record (streams must be designed around this limitation)
 
 pub trait Constraints {
     fn sid(&self) -> &str;
-    fn verify(&self, subject: &Subject, threshold: Duration) -> Result<()>;
-}
\ No newline at end of file
+    fn verify(&self, subject: &Subject, threshold: Duration) -> FpiResult<()>;
+}
+
+// Pins the signed byte layout of a data() array: element sizes (in signing order) and the digest of the signed bytes.
+// Golden tests compare against fixed values, any change in the layout invalidates previously committed signatures.
+#[cfg(test)]
+pub(crate) fn layout<T: AsRef<[u8]>>(data: &[T]) -> String {
+    use sha2::{Sha256, Digest};
+
+    let sizes: Vec<String> = data.iter().map(|d| d.as_ref().len().to_string()).collect();
+    let hash = data.iter().fold(Sha256::new(), |h, d| h.chain(d.as_ref())).result();
+    format!("{}:{}", sizes.join(","), bs58::encode(hash).into_string())
+}
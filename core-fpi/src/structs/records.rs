@@ -1,11 +1,24 @@
+use std::time::Duration;
 use serde::{Serialize, Deserialize};
+use sha2::{Sha256, Sha512, Digest};
 
 use crate::structs::*;
-use crate::crypto::signatures::Signature;
-use crate::{Result, Scalar, RistrettoPoint};
+use crate::ids::*;
+use crate::disclosures::{DiscloseRequest, DiscloseResult, reconstruct_pseudonyms};
+use crate::crypto::signatures::{Signature, IndSignature};
+use crate::crypto::aead::{encrypt_record, decrypt_record};
+use crate::{Result, FpiError, FpiResult, Scalar, RistrettoPoint, RistrettoBasepointTable, KeyEncoder};
 
 pub const OPEN: &str = "OPEN";
 pub const CLOSED: &str = "CLOSED";
+pub const TAIL: usize = usize::MAX;     // from_index of a stream query for the last records (up to the limit)
+
+// Stream identification derived from the pseudonym, SHA-256(compressed pseudonym) in base58.
+// Records are indexed by this value, so a disclosed pseudonym can be used directly as a lookup key.
+pub fn stream_id(pseudonym: &RistrettoPoint) -> String {
+    let hash = Sha256::digest(pseudonym.compress().as_bytes());
+    hash.as_slice().encode()
+}
 
 //-----------------------------------------------------------------------------------------------------------
 // An anonymous profile record
@@ -26,19 +39,19 @@ pub enum RecordType {
 }
 
 impl RecordType {
-    pub fn check(&self) -> Result<()> {
+    pub fn check(&self) -> FpiResult<()> {
         match self {
-            RecordType::AnonymousAttach(attach) => if attach.len() > MAX_HASH_SIZE {
-                return Err(format!("Field Constraint - (attach, max-size = {})", MAX_HASH_SIZE))
+            RecordType::AnonymousAttach(attach) if attach.len() > MAX_HASH_SIZE => {
+                return Err(FpiError::constraint("attach", format!("max-size = {}", MAX_HASH_SIZE)))
             },
 
             RecordType::IdentifiedAttach(sid, attach) => {
                 if sid.len() > MAX_SUBJECT_ID_SIZE {
-                    return Err(format!("Field Constraint - (sid, max-size = {})", MAX_SUBJECT_ID_SIZE))
+                    return Err(FpiError::constraint("sid", format!("max-size = {}", MAX_SUBJECT_ID_SIZE)))
                 }
 
                 if attach.len() > MAX_HASH_SIZE {
-                    return Err(format!("Field Constraint - (attach, max-size = {})", MAX_HASH_SIZE))
+                    return Err(FpiError::constraint("attach", format!("max-size = {}", MAX_HASH_SIZE)))
                 }
             },
 
@@ -49,32 +62,146 @@ impl RecordType {
     }
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum RecordPayload {
+    Inline(Vec<u8>),                            // Data committed with the record
+    Reference(String, Vec<u8>)                  // Off-chain data (lurl, SHA-512 hash). Only the hash is committed, the data is fetched from the profile server
+}
+
+impl RecordPayload {
+    pub fn check(&self) -> FpiResult<()> {
+        match self {
+            RecordPayload::Inline(data) => if data.len() > MAX_DATA_SIZE {
+                return Err(FpiError::constraint("data", format!("max-size = {}", MAX_DATA_SIZE)))
+            },
+
+            RecordPayload::Reference(lurl, hash) => {
+                if lurl.len() > MAX_LOCATION_ID_SIZE {
+                    return Err(FpiError::constraint("lurl", format!("max-size = {}", MAX_LOCATION_ID_SIZE)))
+                }
+
+                if hash.len() != 64 {
+                    return Err(FpiError::constraint("hash", "Expected a SHA-512 hash"))
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // verify the data fetched from the profile server against the committed hash
+    pub fn verify(&self, data: &[u8]) -> Result<()> {
+        match self {
+            RecordPayload::Inline(inline) => if inline.as_slice() != data {
+                return Err("Record data doesn't match the inline data!".into())
+            },
+
+            RecordPayload::Reference(_, hash) => if Sha512::digest(data).as_slice() != hash.as_slice() {
+                return Err("Record data doesn't match the committed hash!".into())
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Validation of the meta structure for a specific format
+pub trait MetaValidator {
+    fn validate(&self, meta: &[u8]) -> FpiResult<()>;
+}
+
+// Well-formed JSON
+pub struct JsonValidator;
+
+impl MetaValidator for JsonValidator {
+    fn validate(&self, meta: &[u8]) -> FpiResult<()> {
+        serde_json::from_slice::<serde_json::Value>(meta).map_err(|_| FpiError::constraint("meta", "Invalid JSON"))?;
+        Ok(())
+    }
+}
+
+// DICOM attributes as a JSON object keyed by the DICOM keyword, requires the indexation tags
+pub struct DicomValidator;
+
+const DICOM_REQUIRED: [&str; 3] = ["Modality", "Rows", "Columns"];
+
+impl MetaValidator for DicomValidator {
+    fn validate(&self, meta: &[u8]) -> FpiResult<()> {
+        let value: serde_json::Value = serde_json::from_slice(meta).map_err(|_| FpiError::constraint("meta", "Invalid JSON"))?;
+        let attrs = value.as_object().ok_or_else(|| FpiError::constraint("meta", "Expected a DICOM attribute object"))?;
+
+        for tag in DICOM_REQUIRED.iter() {
+            if !attrs.contains_key(*tag) {
+                return Err(FpiError::constraint("meta", format!("Missing DICOM tag {}", tag)))
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Built-in validators keyed by format, unknown formats are not validated
+pub fn meta_validator(format: &str) -> Option<Box<dyn MetaValidator>> {
+    match format {
+        "JSON" => Some(Box::new(JsonValidator)),
+        "DICOM" => Some(Box::new(DicomValidator)),
+        _ => None
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct RecordData {
     pub format: String,                     // reported data format, i.e: JSON, XML, DICOM, etc. Specifies what goes into the meta/data fields.
     pub meta: Vec<u8>,                      // open access metadata for indexation: DICOM(Modality, Laterality, Columns, Rows, etc)
-    pub data: Vec<u8>                       // data that may be in encrypted form. Ek[data] where H(y.Pe) = H(e.Y) = k
+    pub data: RecordPayload                 // data that may be in encrypted form. Ek[data] where H(y.Pe) = H(e.Y) = k
 }
 
 impl RecordData {
-    pub fn check(&self) -> Result<()> {
+    pub fn inline(format: &str, meta: Vec<u8>, data: Vec<u8>) -> Self {
+        Self { format: format.into(), meta, data: RecordPayload::Inline(data) }
+    }
+
+    pub fn reference(format: &str, meta: Vec<u8>, lurl: &str, data: &[u8]) -> Self {
+        let hash = Sha512::digest(data).to_vec();
+        Self { format: format.into(), meta, data: RecordPayload::Reference(lurl.into(), hash) }
+    }
+
+    // inline data encrypted with the record key (see derive_record_key), the meta is always open access
+    pub fn sealed(format: &str, meta: Vec<u8>, plaintext: &[u8], key: &[u8; 32]) -> Result<Self> {
+        let data = encrypt_record(key, plaintext)?;
+        Ok(Self::inline(format, meta, data))
+    }
+
+    // off-chain data is fetched from the profile server, verify it against the committed hash and decrypt with decrypt_record
+    pub fn open(&self, key: &[u8; 32]) -> Result<Vec<u8>> {
+        match &self.data {
+            RecordPayload::Inline(data) => decrypt_record(key, data),
+            RecordPayload::Reference(..) => Err("Record data is not inline!".into())
+        }
+    }
+
+    // the meta structure is only validated in strict mode
+    pub fn check(&self, strict: bool) -> FpiResult<()> {
         if self.format.len() > MAX_FORMAT_SIZE {
-            return Err(format!("Field Constraint - (format, max-size = {})", MAX_FORMAT_SIZE))
+            return Err(FpiError::constraint("format", format!("max-size = {}", MAX_FORMAT_SIZE)))
         }
 
         if self.meta.len() > MAX_META_SIZE {
-            return Err(format!("Field Constraint - (meta, max-size = {})", MAX_META_SIZE))
+            return Err(FpiError::constraint("meta", format!("max-size = {}", MAX_META_SIZE)))
         }
 
-        if self.data.len() > MAX_DATA_SIZE {
-            return Err(format!("Field Constraint - (data, max-size = {})", MAX_DATA_SIZE))
+        if strict {
+            if let Some(validator) = meta_validator(&self.format) {
+                validator.validate(&self.meta)?;
+            }
         }
 
-        Ok(())
+        self.data.check()
     }
 }
 
 // Records should not have any timestamp associated, cannot use IndSignature.
+#[allow(clippy::manual_non_exhaustive)]
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Record {
     pub prev: String,
@@ -86,32 +213,97 @@ pub struct Record {
 }
 
 impl Record {
+    const DOMAIN: &'static str = "fpi:record";
+
     pub fn sign(prev: &str, typ: RecordType, rdata: RecordData, base: &RistrettoPoint, secret: &Scalar, pseudonym: &RistrettoPoint) -> Self {
-        let sig_data = Self::data(&prev, &typ, &rdata);
-        let sig = Signature::sign(secret, pseudonym, base, &sig_data);
+        let sig_data = Self::data(prev, &typ, &rdata);
+        let sig = Signature::sign_batchable(secret, pseudonym, base, Self::DOMAIN, &sig_data);
 
         Self { typ, rdata, prev: prev.into(), sig, _phantom: () }
     }
 
-    pub fn check(&self, last: Option<&Record>, base: &RistrettoPoint, pseudonym: &RistrettoPoint) -> Result<()> {
-        if self.prev.len() > MAX_HASH_SIZE {
-            return Err(format!("Field Constraint - (prev, max-size = {})", MAX_HASH_SIZE))
+    // terminal record of the stream, no other record can be appended after it
+    pub fn close(prev: &str, base: &RistrettoPoint, secret: &Scalar, pseudonym: &RistrettoPoint) -> Self {
+        let rdata = RecordData::inline(CLOSED, Vec::new(), Vec::new());
+        Self::sign(prev, RecordType::Owned, rdata, base, secret, pseudonym)
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.rdata.format == CLOSED
+    }
+
+    // field constraints and record signature, doesn't require the stream state
+    pub fn verify(&self, base: &RistrettoPoint, pseudonym: &RistrettoPoint) -> FpiResult<()> {
+        self.check_fields()?;
+
+        let sig_data = Self::data(&self.prev, &self.typ, &self.rdata);
+        if !self.sig.verify(pseudonym, base, Self::DOMAIN, &sig_data) {
+            return Err(FpiError::signature("sig"))
         }
 
-        self.typ.check()?;
+        Ok(())
+    }
 
-        self.rdata.check()?;
+    // Contiguous part of a stream with the same pseudonym and base, returns the first broken link (the first prev is not verified).
+    // All signatures are verified in a single multiscalar (see Signature::verify_batch), only a failed batch is verified record by record
+    // to locate the broken link, sharing the precomputed tables of the pseudonym and base.
+    pub fn verify_chain(records: &[Record], base: &RistrettoPoint, pseudonym: &RistrettoPoint) -> Result<()> {
+        let sig_data: Vec<[Vec<u8>; 3]> = records.iter().map(|record| Self::data(&record.prev, &record.typ, &record.rdata)).collect();
+        let batch: Vec<(&Signature, &[Vec<u8>])> = records.iter().zip(sig_data.iter()).map(|(record, data)| (&record.sig, &data[..])).collect();
+        let valid = Signature::verify_batch(&batch, pseudonym, base, Self::DOMAIN);
+
+        let tables = match valid {
+            true => None,
+            false => Some((RistrettoBasepointTable::create(pseudonym), RistrettoBasepointTable::create(base)))
+        };
+
+        let mut last: Option<&Record> = None;
+        for (i, (record, data)) in records.iter().zip(sig_data.iter()).enumerate() {
+            let link = || -> Result<()> {
+                if let Some(last) = last {
+                    if last.is_closed() {
+                        return Err("The stream is closed!".into())
+                    }
+
+                    if record.prev != last.sig.encoded {
+                        return Err("Field Constraint - (prev, Record is not part of the stream)".into())
+                    }
+                }
 
-        let prev = match last {
+                record.check_fields()?;
+
+                if let Some((t_pseudonym, t_base)) = &tables {
+                    if !record.sig.verify_with(pseudonym, t_pseudonym, t_base, Self::DOMAIN, data) {
+                        return Err("Field Constraint - (sig, Invalid signature)".into())
+                    }
+                }
+
+                Ok(())
+            };
+
+            link().map_err(|e| format!("Broken chain at record {} - {}", i, e))?;
+            last = Some(record);
+        }
+
+        Ok(())
+    }
+
+    // Content address of the record, SHA-256 of the signed data in base58 (the same for a re-signed copy). Kept in the retention log entries.
+    pub fn hash(&self) -> String {
+        let data = bincode::serialize(&Self::data(&self.prev, &self.typ, &self.rdata)).unwrap();
+        let hash = Sha256::digest(&data);
+        hash.as_slice().encode()
+    }
+
+    pub fn check(&self, last: Option<&Record>, base: &RistrettoPoint, pseudonym: &RistrettoPoint) -> Result<()> {
+        match last {
             None => if self.prev != OPEN {
                 return Err("Field Constraint - (prev, Record not marked as open)".into())
-            } else {
-                OPEN
             },
             
             Some(last) => {
                 // verify if the stream is not closed
-                if last.rdata.format == CLOSED {
+                if last.is_closed() {
                     return Err("The stream is closed!".into())
                 }
 
@@ -122,24 +314,28 @@ impl Record {
 
                 // verify signature of last record with the same key. The chain must have the same key.
                 let sig_data = Self::data(&last.prev, &last.typ, &last.rdata);
-                if !self.sig.verify(pseudonym, base, &sig_data) {
+                if !last.sig.verify(pseudonym, base, Self::DOMAIN, &sig_data) {
                     return Err("Last record doesn't match the key for the signature!".into())
                 }
-
-                self.prev.as_ref()
             }
-        };
+        }
         
         // verify the record signature
-        let sig_data = Self::data(prev, &self.typ, &self.rdata);
-        if !self.sig.verify(pseudonym, base, &sig_data) {
-            return Err("Field Constraint - (sig, Invalid signature)".into())
+        Ok(self.verify(base, pseudonym)?)
+    }
+
+    fn check_fields(&self) -> FpiResult<()> {
+        if self.prev.len() > MAX_HASH_SIZE {
+            return Err(FpiError::constraint("prev", format!("max-size = {}", MAX_HASH_SIZE)))
         }
 
-        Ok(())
+        self.typ.check()?;
+        self.rdata.check(false)
     }
 
+    // the type is signed before prev (not in argument order), this layout is pinned by committed record signatures
     fn data(prev: &str, typ: &RecordType, data: &RecordData) -> [Vec<u8>; 3] {
+        // These unwrap() should never fail, or it's a serious code bug!
         let b_prev = bincode::serialize(prev).unwrap();
         let b_typ = bincode::serialize(&typ).unwrap();
         let b_data = bincode::serialize(data).unwrap();
@@ -148,20 +344,473 @@ impl Record {
     }
 }
 
+//--------------------------------------------------------------------
+// Authorization from the stream owner to attach records to one of its records
+//--------------------------------------------------------------------
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AttachToken {
+    pub attach: String,                 // referenced record of the owner stream (sig.encoded)
+    pub attacher: RistrettoPoint,       // pseudonym of the authorized attacher
+    pub owner: RistrettoPoint,          // pseudonym of the stream owner
+    pub base: RistrettoPoint,           // base-point of the owner pseudonym (must be one of the existing master-keys)
+
+    pub sig: Signature
+}
+
+impl AttachToken {
+    const DOMAIN: &'static str = "fpi:attach-token";
+
+    pub fn sign(attach: &str, attacher: &RistrettoPoint, base: &RistrettoPoint, secret: &Scalar, owner: &RistrettoPoint) -> Self {
+        let sig_data = Self::data(attach, attacher);
+        let sig = Signature::sign(secret, owner, base, Self::DOMAIN, &sig_data);
+
+        Self { attach: attach.into(), attacher: *attacher, owner: *owner, base: *base, sig }
+    }
+
+    // owner is the stream of the referenced record, it's resolved from the stored records
+    pub fn check(&self, attach: &str, attacher: &RistrettoPoint, owner: Option<&str>, bases: &[RistrettoPoint]) -> Result<()> {
+        if self.attach != attach {
+            return Err("Field Constraint - (token, Incorrect attach reference)".into())
+        }
+
+        if self.attacher != *attacher {
+            return Err("Field Constraint - (token, Incorrect attacher)".into())
+        }
+
+        if !bases.contains(&self.base) {
+            return Err("Field Constraint - (token, Expected a master-key)".into())
+        }
+
+        if owner != Some(stream_id(&self.owner).as_str()) {
+            return Err("Field Constraint - (token, Not signed by the stream owner)".into())
+        }
+
+        let sig_data = Self::data(&self.attach, &self.attacher);
+        if !self.sig.verify(&self.owner, &self.base, Self::DOMAIN, &sig_data) {
+            return Err("Field Constraint - (token, Invalid signature)".into())
+        }
+
+        Ok(())
+    }
+
+    fn data(attach: &str, attacher: &RistrettoPoint) -> [Vec<u8>; 2] {
+        let b_attach = bincode::serialize(attach).unwrap();
+        let b_attacher = bincode::serialize(attacher).unwrap();
+
+        [b_attach, b_attacher]
+    }
+}
+
+//--------------------------------------------------------------------
+// Retention of the stream record entries on the node
+//--------------------------------------------------------------------
+// Disabled by default. Ages are measured in blocks, so pruning only depends on the committed state and the block height.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Retention {
+    pub keep_last: usize,                   // number of entries kept per stream (0 = unlimited)
+    pub max_age: i64,                       // entries older than max_age blocks are pruned (0 = unlimited)
+    pub tombstones: bool                    // summarize the pruned entries in the tombstone hash
+}
+
+impl Retention {
+    pub fn enabled(&self) -> bool {
+        self.keep_last > 0 || self.max_age > 0
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RecordEntry {
+    pub height: i64,                        // block height of the record
+    pub hash: String,                       // content address of the record (Record::hash)
+    pub sig: String                         // record reference (sig.encoded)
+}
+
+// Retained entries of a stream, ordered by the stream chain. The last entry is never pruned,
+// it's the head of the stream (or the CLOSED record), so the chain can still be extended and closed streams stay closed.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct RecordLog {
+    pub entries: Vec<RecordEntry>,
+    pub tombstone: Option<String>           // SHA-256 chain over the hashes of the pruned entries
+}
+
+impl RecordLog {
+    pub fn push(&mut self, entry: RecordEntry) {
+        self.entries.push(entry);
+    }
+
+    // removes and returns the expired entries
+    pub fn prune(&mut self, policy: &Retention, height: i64) -> Vec<RecordEntry> {
+        let len = self.entries.len();
+        let by_count = if policy.keep_last > 0 && len > policy.keep_last { len - policy.keep_last } else { 0 };
+        let by_age = match policy.max_age {
+            0 => 0,
+            max_age => self.entries.iter().take_while(|entry| height - entry.height > max_age).count()
+        };
+
+        let n_pruned = by_count.max(by_age).min(len.saturating_sub(1));
+        let pruned: Vec<RecordEntry> = self.entries.drain(..n_pruned).collect();
+
+        if policy.tombstones {
+            for entry in pruned.iter() {
+                self.tombstone = Some(Self::fold(self.tombstone.as_ref(), &entry.hash));
+            }
+        }
+
+        pruned
+    }
+
+    // verify the retained state against the full chain of record hashes (in stream order)
+    pub fn verify(&self, hashes: &[String]) -> Result<()> {
+        if hashes.len() < self.entries.len() {
+            return Err("Record log with more entries than the stream!".into())
+        }
+
+        let (pruned, retained) = hashes.split_at(hashes.len() - self.entries.len());
+        if self.entries.iter().zip(retained.iter()).any(|(entry, hash)| entry.hash != *hash) {
+            return Err("Record log doesn't match the stream!".into())
+        }
+
+        if let Some(tombstone) = &self.tombstone {
+            let expected = pruned.iter().fold(None, |acc: Option<String>, hash| Some(Self::fold(acc.as_ref(), hash)));
+            if expected.as_ref() != Some(tombstone) {
+                return Err("Record log with an incorrect tombstone!".into())
+            }
+        }
+
+        Ok(())
+    }
+
+    fn fold(tombstone: Option<&String>, hash: &str) -> String {
+        let mut hasher = Sha256::new();
+        if let Some(tombstone) = tombstone {
+            hasher.input(tombstone.as_bytes());
+        }
+
+        hasher.input(hash.as_bytes());
+        hasher.result().as_slice().encode()
+    }
+}
+
 //--------------------------------------------------------------------
 // NewRecord
 //--------------------------------------------------------------------
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct NewRecord {
     pub record: Record,
-    pub pseudonym: RistrettoPoint,      // pseudonym or stream identification. Should I use SHA-256(pseudonym) instead?
-    pub base: RistrettoPoint            // base-point for signature verification (must be one of the existing master-keys)
+    pub pseudonym: RistrettoPoint,      // pseudonym for signature verification, the stream is identified by stream_id(pseudonym)
+    pub base: RistrettoPoint,           // base-point for signature verification (must be one of the existing master-keys)
+    pub token: Option<AttachToken>      // authorization for anonymous attachments, only required by the strict policy
+}
+
+impl NewRecord {
+    pub fn stream(&self) -> String {
+        stream_id(&self.pseudonym)
+    }
+
+    // Full validation against the stream state. Records are anonymous, they are authenticated by the pseudonym signature and not by a subject.
+    pub fn check(&self, last: Option<&Record>, bases: &[RistrettoPoint], strict: bool) -> Result<()> {
+        if !bases.contains(&self.base) {
+            return Err("Field Constraint - (base, Expected a master-key)".into())
+        }
+
+        self.record.check(last, &self.base, &self.pseudonym)?;
+
+        if strict {
+            self.record.rdata.check(true)?;
+        }
+
+        Ok(())
+    }
+
+    // Anonymous attachments under the strict policy require a token from the owner of the referenced record (stream).
+    pub fn check_attach(&self, owner: Option<&str>, bases: &[RistrettoPoint], strict: bool) -> Result<()> {
+        match &self.record.typ {
+            RecordType::AnonymousAttach(attach) => match (strict, &self.token) {
+                (false, _) => Ok(()),
+                (true, None) => Err("Field Constraint - (token, Attachment not authorized)".into()),
+                (true, Some(token)) => token.check(attach, &self.pseudonym, owner, bases)
+            },
+
+            _ => match self.token {
+                None => Ok(()),
+                Some(_) => Err("Field Constraint - (token, Only for anonymous attachments)".into())
+            }
+        }
+    }
+}
+
+// Records are anonymous, there is no owning subject. Only stateless constraints are verified here,
+// the master-key base and the stream chain are verified with NewRecord::check.
+impl Constraints for NewRecord {
+    fn sid(&self) -> &str { "" }
+
+    fn verify(&self, _: &Subject, _: Duration) -> FpiResult<()> {
+        if let Some(token) = &self.token {
+            if token.attach.len() > MAX_HASH_SIZE {
+                return Err(FpiError::constraint("token", format!("max-size = {}", MAX_HASH_SIZE)))
+            }
+        }
+
+        self.record.verify(&self.base, &self.pseudonym)
+    }
+}
+
+//--------------------------------------------------------------------
+// RecordStreamQuery
+//--------------------------------------------------------------------
+// Read of a stream in chain order. The pseudonym is only known by the owner or from a disclosure,
+// but peers still require a disclosure served to the requester before returning records.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RecordStreamQuery {
+    pub sid: String,
+    pub pseudonym: RistrettoPoint,
+    pub base: RistrettoPoint,
+    pub from_index: usize,              // position in the stream, the OPEN record is at 0 (or TAIL)
+    pub from_prev: Option<String>,      // cursor, the page starts after the record with this signature (or OPEN) instead of from_index
+    pub limit: usize,
+    pub access: StreamAccess,
+    pub sig: IndSignature
+}
+
+// The pseudonym of a stream is only known by the owner (profile secret) or reconstructed from a disclosure of the profile.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum StreamAccess {
+    Owner(Signature),                                   // Query signed with the profile secret (pseudonym under the query base)
+    Disclosure(DiscloseRequest, Vec<DiscloseResult>)    // Disclosure of the stream profile to the requester
+}
+
+impl Constraints for RecordStreamQuery {
+    fn sid(&self) -> &str { &self.sid }
+
+    fn verify(&self, subject: &Subject, threshold: Duration) -> FpiResult<()> {
+        if self.sid.len() > MAX_SUBJECT_ID_SIZE {
+            return Err(FpiError::constraint("sid", format!("max-size = {}", MAX_SUBJECT_ID_SIZE)))
+        }
+
+        if self.limit == 0 || self.limit > MAX_RECORDS_QUERY {
+            return Err(FpiError::constraint("limit", format!("range = [1, {}]", MAX_RECORDS_QUERY)))
+        }
+
+        if let Some(prev) = &self.from_prev {
+            if prev.len() > MAX_HASH_SIZE {
+                return Err(FpiError::constraint("from_prev", format!("max-size = {}", MAX_HASH_SIZE)))
+            }
+
+            if self.from_index != 0 {
+                return Err(FpiError::constraint("from_index", "Expected 0 for a cursor query"))
+            }
+        }
+
+        if let StreamAccess::Disclosure(_, results) = &self.access {
+            if results.len() > MAX_PEERS {
+                return Err(FpiError::constraint("access", format!("max-results = {}", MAX_PEERS)))
+            }
+        }
+
+        if !self.sig.sig.check_timestamp(threshold) {
+            return Err(FpiError::Timestamp)
+        }
+
+        let skey = subject.active_key()?;
+        if self.sig.index != skey.sig.index {
+            return Err(FpiError::constraint("sig", "Incorrect key index"))
+        }
+
+        let sig_data = Self::data(&self.sid, &self.pseudonym, &self.base, self.from_index, self.limit, &self.from_prev);
+        if !self.sig.verify(&skey.key, Self::DOMAIN, &sig_data) {
+            return Err(FpiError::signature("sig"))
+        }
+
+        Ok(())
+    }
+}
+
+impl RecordStreamQuery {
+    const DOMAIN: &'static str = "fpi:record-stream-query";
+    const OWNER_DOMAIN: &'static str = "fpi:record-stream-owner";
+
+    // query of the owner, proving the profile secret of the pseudonym
+    #[allow(clippy::too_many_arguments)]
+    pub fn sign_owner(sid: &str, pseudonym: &RistrettoPoint, base: &RistrettoPoint, from_index: usize, limit: usize, secret: &Scalar, sig_s: &Scalar, sig_key: &SubjectKey) -> Self {
+        Self::owner(sid, pseudonym, base, from_index, None, limit, secret, sig_s, sig_key)
+    }
+
+    // query of the owner for the page after the record with the signature prev (or OPEN for the first page)
+    #[allow(clippy::too_many_arguments)]
+    pub fn sign_owner_after(sid: &str, pseudonym: &RistrettoPoint, base: &RistrettoPoint, prev: &str, limit: usize, secret: &Scalar, sig_s: &Scalar, sig_key: &SubjectKey) -> Self {
+        Self::owner(sid, pseudonym, base, 0, Some(prev.into()), limit, secret, sig_s, sig_key)
+    }
+
+    // query of a reader, with the disclosure (request and peer results) where the pseudonym was reconstructed
+    #[allow(clippy::too_many_arguments)]
+    pub fn sign_disclosed(sid: &str, pseudonym: &RistrettoPoint, base: &RistrettoPoint, from_index: usize, limit: usize, request: DiscloseRequest, results: Vec<DiscloseResult>, sig_s: &Scalar, sig_key: &SubjectKey) -> Self {
+        let access = StreamAccess::Disclosure(request, results);
+        Self::sign(sid, pseudonym, base, from_index, None, limit, access, sig_s, sig_key)
+    }
+
+    // query of a reader for the page after the record with the signature prev (or OPEN for the first page)
+    #[allow(clippy::too_many_arguments)]
+    pub fn sign_disclosed_after(sid: &str, pseudonym: &RistrettoPoint, base: &RistrettoPoint, prev: &str, limit: usize, request: DiscloseRequest, results: Vec<DiscloseResult>, sig_s: &Scalar, sig_key: &SubjectKey) -> Self {
+        let access = StreamAccess::Disclosure(request, results);
+        Self::sign(sid, pseudonym, base, 0, Some(prev.into()), limit, access, sig_s, sig_key)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn owner(sid: &str, pseudonym: &RistrettoPoint, base: &RistrettoPoint, from_index: usize, from_prev: Option<String>, limit: usize, secret: &Scalar, sig_s: &Scalar, sig_key: &SubjectKey) -> Self {
+        let sig_data = Self::data(sid, pseudonym, base, from_index, limit, &from_prev);
+        let access = StreamAccess::Owner(Signature::sign(secret, pseudonym, base, Self::OWNER_DOMAIN, &sig_data));
+        Self::sign(sid, pseudonym, base, from_index, from_prev, limit, access, sig_s, sig_key)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn sign(sid: &str, pseudonym: &RistrettoPoint, base: &RistrettoPoint, from_index: usize, from_prev: Option<String>, limit: usize, access: StreamAccess, sig_s: &Scalar, sig_key: &SubjectKey) -> Self {
+        let sig_data = Self::data(sid, pseudonym, base, from_index, limit, &from_prev);
+        let sig = IndSignature::sign(sig_key.sig.index, sig_s, &sig_key.key, Self::DOMAIN, &sig_data);
+
+        Self { sid: sid.into(), pseudonym: *pseudonym, base: *base, from_index, from_prev, limit, access, sig }
+    }
+
+    pub fn stream(&self) -> String {
+        stream_id(&self.pseudonym)
+    }
+
+    // Access to the stream. The owner proves the pseudonym secret, a reader presents a disclosure (requested by the same subject)
+    // where the federation revealed the pseudonym. The peers and threshold are the ones that signed the disclosure results.
+    pub fn authorize(&self, requester: &Subject, peers: &[RistrettoPoint], threshold: usize) -> Result<()> {
+        match &self.access {
+            StreamAccess::Owner(sig) => {
+                let sig_data = Self::data(&self.sid, &self.pseudonym, &self.base, self.from_index, self.limit, &self.from_prev);
+                if !sig.verify(&self.pseudonym, &self.base, Self::OWNER_DOMAIN, &sig_data) {
+                    return Err("Stream access denied, invalid owner signature!".into())
+                }
+            },
+
+            StreamAccess::Disclosure(request, results) => {
+                if request.sid != self.sid {
+                    return Err("Stream access denied, the disclosure is from another subject!".into())
+                }
+
+                request.verify_sig(requester)?;
+                let disclosed = reconstruct_pseudonyms(request, results, peers, threshold)?;
+                if !disclosed.pseudonyms.values().any(|pseudo| *pseudo == self.pseudonym) {
+                    return Err("Stream access denied, the pseudonym is not in the disclosure!".into())
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // the cursor is only signed when present, index queries keep the previous signature data
+    fn data(sid: &str, pseudonym: &RistrettoPoint, base: &RistrettoPoint, from_index: usize, limit: usize, from_prev: &Option<String>) -> Vec<Vec<u8>> {
+        // These unwrap() should never fail, or it's a serious code bug!
+        let b_sid = bincode::serialize(sid).unwrap();
+        let b_pseudonym = bincode::serialize(pseudonym).unwrap();
+        let b_base = bincode::serialize(base).unwrap();
+        let b_from_index = bincode::serialize(&from_index).unwrap();
+        let b_limit = bincode::serialize(&limit).unwrap();
+
+        let mut data = vec![b_sid, b_pseudonym, b_base, b_from_index, b_limit];
+        if let Some(prev) = from_prev {
+            data.push(bincode::serialize(prev).unwrap());
+        }
+
+        data
+    }
+}
+
+//--------------------------------------------------------------------
+// RecordStreamResult
+//--------------------------------------------------------------------
+// An unknown stream has no records. Records removed by the retention policy are not returned,
+// so the first record may be after the requested index.
+#[allow(clippy::manual_non_exhaustive)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RecordStreamResult {
+    pub session: String,                // query signature
+    pub from_index: usize,              // position of the first record
+    pub records: Vec<Record>,
+
+    pub sig: IndSignature,
+    #[serde(skip)] _phantom: () // force use of constructor
+}
+
+impl RecordStreamResult {
+    const DOMAIN: &'static str = "fpi:record-stream-result";
+
+    pub fn sign(session: &str, from_index: usize, records: Vec<Record>, secret: &Scalar, key: &RistrettoPoint, index: usize) -> Self {
+        let sig_data = Self::data(session, from_index, &records);
+        let sig = IndSignature::sign(index, secret, key, Self::DOMAIN, &sig_data);
+
+        Self { session: session.into(), from_index, records, sig, _phantom: () }
+    }
+
+    // the records are verified against the query pseudonym and base, and must be a contiguous part of the stream chain
+    pub fn check(&self, query: &RecordStreamQuery, peers: &[RistrettoPoint]) -> Result<()> {
+        if self.session != query.sig.id() {
+            return Err("Field Constraint - (session, Expected the same session)".into())
+        }
+
+        if query.from_index != TAIL && self.from_index < query.from_index {
+            return Err("Field Constraint - (from_index, Expected records after the requested index)".into())
+        }
+
+        if self.records.len() > query.limit {
+            return Err("Field Constraint - (records, Expected at most the requested limit)".into())
+        }
+
+        let mut last: Option<&Record> = None;
+        for record in self.records.iter() {
+            let prev = match last {
+                None if query.from_prev.is_some() => query.from_prev.as_ref().unwrap(),
+                None if self.from_index == 0 => OPEN,
+                None => &record.prev,
+                Some(last) => {
+                    if last.is_closed() {
+                        return Err("Field Constraint - (records, Records after a closed stream)".into())
+                    }
+
+                    &last.sig.encoded
+                }
+            };
+
+            if record.prev != prev {
+                return Err("Field Constraint - (records, Record is not part of the stream)".into())
+            }
+
+            record.verify(&query.base, &query.pseudonym)?;
+            last = Some(record);
+        }
+
+        Ok(self.verify(peers)?)
+    }
+
+    pub fn verify(&self, peers: &[RistrettoPoint]) -> FpiResult<()> {
+        let sig_data = Self::data(&self.session, self.from_index, &self.records);
+        if !self.sig.verify_against(peers, Self::DOMAIN, &sig_data).map_err(FpiError::Crypto)? {
+            return Err(FpiError::signature("sig"))
+        }
+
+        Ok(())
+    }
+
+    fn data(session: &str, from_index: usize, records: &[Record]) -> [Vec<u8>; 3] {
+        // These unwrap() should never fail, or it's a serious code bug!
+        let b_session = bincode::serialize(session).unwrap();
+        let b_from_index = bincode::serialize(&from_index).unwrap();
+        let b_records = bincode::serialize(records).unwrap();
+
+        [b_session, b_from_index, b_records]
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::{G, rnd_scalar};
+    use crate::disclosures::DiscloseKeys;
+    use crate::shares::{Polynomial, RistrettoPolynomial, RistrettoShare, Interpolate};
 
     #[allow(non_snake_case)]
     #[test]
@@ -170,7 +819,7 @@ mod tests {
         let secret = rnd_scalar();
         let pseudonym = secret * base;
         
-        let r_data = RecordData { format: "DICOM".into(), meta: "record meta".as_bytes().to_vec(), data: "record data".as_bytes().to_vec() };
+        let r_data = RecordData::inline("DICOM", "record meta".as_bytes().to_vec(), "record data".as_bytes().to_vec());
         let record = Record::sign(OPEN, RecordType::Owned, r_data, &base, &secret, &pseudonym);
         assert!(record.check(None, &base, &pseudonym) == Ok(()));
     }
@@ -182,19 +831,612 @@ mod tests {
         let secret = rnd_scalar();
         let pseudonym = secret * base;
         
-        let r_data = RecordData { format: "DICOM".into(), meta: "record meta".as_bytes().to_vec(), data: "record data".as_bytes().to_vec() };
+        let r_data = RecordData::inline("DICOM", "record meta".as_bytes().to_vec(), "record data".as_bytes().to_vec());
         let record = Record::sign(OPEN, RecordType::Owned, r_data, &base, &secret, &pseudonym);
         assert!(record.check(None, &base, &pseudonym) == Ok(()));
 
-        let r_data1 = RecordData { format: "DICOM".into(), meta: "record meta".as_bytes().to_vec(), data: "next data1".as_bytes().to_vec() };
+        let r_data1 = RecordData::inline("DICOM", "record meta".as_bytes().to_vec(), "next data1".as_bytes().to_vec());
         let record1 = Record::sign(OPEN, RecordType::Owned, r_data1, &base, &secret, &pseudonym);
-        assert!(record1.check(Some(&record), &base, &pseudonym) == Err("Record is not part of the stream!".into()));
+        assert!(record1.check(Some(&record), &base, &pseudonym) == Err("Field Constraint - (prev, Record is not part of the stream)".into()));
 
         let secret1 = rnd_scalar();
         let pseudonym1 = secret1 * base;
 
-        let r_data2 = RecordData { format: "DICOM".into(), meta: "record meta".as_bytes().to_vec(), data: "next data2".as_bytes().to_vec() };
+        let r_data2 = RecordData::inline("DICOM", "record meta".as_bytes().to_vec(), "next data2".as_bytes().to_vec());
         let record2 = Record::sign(&record.sig.encoded, RecordType::Owned, r_data2, &base, &secret1, &pseudonym1);
-        assert!(record2.check(Some(&record), &base, &pseudonym) == Err("Last record doesn't match the key for the signature!".into()));
+        assert!(record2.check(Some(&record), &base, &pseudonym1) == Err("Last record doesn't match the key for the signature!".into()));
+        assert!(record2.check(Some(&record), &base, &pseudonym) == Err("Field Constraint - (sig, Invalid signature)".into()));
+    }
+
+    #[allow(non_snake_case)]
+    #[test]
+    fn test_reference() {
+        let base = rnd_scalar() * G;
+        let secret = rnd_scalar();
+        let pseudonym = secret * base;
+
+        let data = "off-chain record data".as_bytes();
+        let r_data = RecordData::reference("DICOM", "record meta".as_bytes().to_vec(), "https://profile-url.org", data);
+        let record = Record::sign(OPEN, RecordType::Owned, r_data, &base, &secret, &pseudonym);
+        assert!(record.check(None, &base, &pseudonym) == Ok(()));
+
+        // data fetched from the profile server
+        assert!(record.rdata.data.verify(data) == Ok(()));
+        assert!(record.rdata.data.verify("tampered record data".as_bytes()) == Err("Record data doesn't match the committed hash!".into()));
+
+        // the committed hash must be a SHA-512
+        let r_data = RecordData { format: "DICOM".into(), meta: Vec::new(), data: RecordPayload::Reference("https://profile-url.org".into(), vec![0u8; 32]) };
+        let record = Record::sign(OPEN, RecordType::Owned, r_data, &base, &secret, &pseudonym);
+        assert!(record.check(None, &base, &pseudonym) == Err("Field Constraint - (hash, Expected a SHA-512 hash)".into()));
+    }
+
+    #[allow(non_snake_case)]
+    #[test]
+    fn test_disclosed_stream() {
+        let threshold = 1;
+        let parties = 3*threshold + 1;
+
+        // pseudonym master-key (y, Y) distributed by the peers
+        let y = rnd_scalar();
+        let Y = y * G;
+        let shares = Polynomial::rnd(y, threshold).shares(parties);
+
+        // profile key (p, P) owned by the subject, records are signed with the pseudonym p * Y
+        let p = rnd_scalar();
+        let P = p * G;
+
+        let r_data = RecordData::inline("DICOM", "record meta".as_bytes().to_vec(), "record data".as_bytes().to_vec());
+        let record = Record::sign(OPEN, RecordType::Owned, r_data, &Y, &p, &(p * Y));
+        let new_record = NewRecord { record, pseudonym: p * Y, base: Y, token: None };
+
+        // disclosure shares (y_i * P) reconstruct the same pseudonym
+        let d_shares: Vec<RistrettoShare> = shares.0.iter().map(|s| s * &P).collect();
+        let pseudonym = RistrettoPolynomial::interpolate(&d_shares[0..2*threshold + 1]).unwrap();
+        assert!(stream_id(&pseudonym) == new_record.stream());
+
+        let other = rnd_scalar() * Y;
+        assert!(stream_id(&other) != new_record.stream());
+    }
+
+    #[allow(non_snake_case)]
+    #[test]
+    fn test_new_record() {
+        let Y = rnd_scalar() * G;
+        let p = rnd_scalar();
+
+        let r_data = RecordData::inline("DICOM", "record meta".as_bytes().to_vec(), "record data".as_bytes().to_vec());
+        let record = Record::sign(OPEN, RecordType::Owned, r_data, &Y, &p, &(p * Y));
+        let new_record = NewRecord { record: record.clone(), pseudonym: p * Y, base: Y, token: None };
+
+        // no subject is required, only the master-key base
+        assert!(new_record.check(None, &[Y], false) == Ok(()));
+        assert!(new_record.check(None, &[rnd_scalar() * G], false) == Err("Field Constraint - (base, Expected a master-key)".into()));
+
+        // the record must be signed with the declared pseudonym
+        let forged = NewRecord { record, pseudonym: rnd_scalar() * Y, base: Y, token: None };
+        assert!(forged.check(None, &[Y], false) == Err("Field Constraint - (sig, Invalid signature)".into()));
+
+        // next record of the stream
+        let r_data = RecordData::inline("DICOM", "record meta".as_bytes().to_vec(), "next data".as_bytes().to_vec());
+        let next = Record::sign(&new_record.record.sig.encoded, RecordType::Owned, r_data, &Y, &p, &(p * Y));
+        let next = NewRecord { record: next, pseudonym: p * Y, base: Y, token: None };
+        assert!(next.check(Some(&new_record.record), &[Y], false) == Ok(()));
+        assert!(next.check(None, &[Y], false) == Err("Field Constraint - (prev, Record not marked as open)".into()));
+    }
+
+    #[allow(non_snake_case)]
+    #[test]
+    fn test_new_record_commit() {
+        use crate::messages::*;
+
+        let Y = rnd_scalar() * G;
+        let p = rnd_scalar();
+
+        let r_data = RecordData::inline("DICOM", "record meta".as_bytes().to_vec(), "record data".as_bytes().to_vec());
+        let record = Record::sign(OPEN, RecordType::Owned, r_data, &Y, &p, &(p * Y));
+
+        // there is no owning subject for records
+        let commit = Commit::Value(Value::VNewRecord(NewRecord { record: record.clone(), pseudonym: p * Y, base: Y, token: None }));
+        let anonymous = Subject::new("");
+        assert!(commit.sid() == "");
+        assert!(commit.verify(&anonymous, Duration::from_secs(5)) == Ok(()));
+
+        let commit = Commit::Value(Value::VNewRecord(NewRecord { record, pseudonym: rnd_scalar() * Y, base: Y, token: None }));
+        assert!(commit.verify(&anonymous, Duration::from_secs(5)) == Err(FpiError::signature("sig")));
+    }
+
+    #[allow(non_snake_case)]
+    #[test]
+    fn test_sealed() {
+        let base = rnd_scalar() * G;
+        let secret = rnd_scalar();
+        let pseudonym = secret * base;
+
+        // the profile server encrypts with y.Pe, and the recipient decrypts with the disclosed e.Y
+        let y = rnd_scalar();
+        let e = rnd_scalar();
+        let key = crate::aead::derive_record_key(&(y * (e * G)));
+
+        let r_data = RecordData::sealed("JSON", "{}".as_bytes().to_vec(), b"{\"diagnosis\": \"...\"}", &key).unwrap();
+        let record = Record::sign(OPEN, RecordType::Owned, r_data, &base, &secret, &pseudonym);
+        assert!(record.check(None, &base, &pseudonym) == Ok(()));
+
+        let disclosed = crate::aead::derive_record_key(&(e * (y * G)));
+        assert!(record.rdata.open(&disclosed) == Ok(b"{\"diagnosis\": \"...\"}".to_vec()));
+
+        let other = crate::aead::derive_record_key(&(rnd_scalar() * G));
+        assert!(record.rdata.open(&other) == Err("Unable to decrypt the record data, incorrect key?".into()));
+
+        let r_data = RecordData::reference("JSON", Vec::new(), "https://profile-url.org", b"data");
+        assert!(r_data.open(&key) == Err("Record data is not inline!".into()));
+    }
+
+    #[test]
+    fn test_close() {
+        let base = rnd_scalar() * G;
+        let secret = rnd_scalar();
+        let pseudonym = secret * base;
+
+        let r_data = RecordData::inline("JSON", "{}".as_bytes().to_vec(), vec![0]);
+        let record = Record::sign(OPEN, RecordType::Owned, r_data, &base, &secret, &pseudonym);
+        assert!(!record.is_closed());
+
+        let closed = Record::close(&record.sig.encoded, &base, &secret, &pseudonym);
+        assert!(closed.is_closed());
+        assert!(closed.check(Some(&record), &base, &pseudonym) == Ok(()));
+
+        // appending after the close, even another close
+        let r_data = RecordData::inline("JSON", "{}".as_bytes().to_vec(), vec![1]);
+        let after = Record::sign(&closed.sig.encoded, RecordType::Owned, r_data, &base, &secret, &pseudonym);
+        assert!(after.check(Some(&closed), &base, &pseudonym) == Err("The stream is closed!".into()));
+
+        let after = Record::close(&closed.sig.encoded, &base, &secret, &pseudonym);
+        assert!(after.check(Some(&closed), &base, &pseudonym) == Err("The stream is closed!".into()));
+
+        // a stream can be closed on the first record, and only by the owner
+        let closed = Record::close(OPEN, &base, &secret, &pseudonym);
+        assert!(closed.check(None, &base, &pseudonym) == Ok(()));
+
+        let forged = Record::close(&record.sig.encoded, &base, &rnd_scalar(), &pseudonym);
+        assert!(forged.check(Some(&record), &base, &pseudonym) == Err("Field Constraint - (sig, Invalid signature)".into()));
+    }
+
+    #[test]
+    fn test_verify_chain() {
+        let base = rnd_scalar() * G;
+        let secret = rnd_scalar();
+        let pseudonym = secret * base;
+
+        let mut records = Vec::<Record>::new();
+        for i in 0..6 {
+            let prev = records.last().map(|r: &Record| r.sig.encoded.clone()).unwrap_or_else(|| OPEN.into());
+            let r_data = RecordData::inline("JSON", "{}".as_bytes().to_vec(), vec![i as u8]);
+            records.push(Record::sign(&prev, RecordType::Owned, r_data, &base, &secret, &pseudonym));
+        }
+
+        assert!(Record::verify_chain(&records, &base, &pseudonym) == Ok(()));
+        assert!(Record::verify_chain(&records[2..], &base, &pseudonym) == Ok(()));
+        assert!(Record::verify_chain(&[], &base, &pseudonym) == Ok(()));
+
+        // broken linkage
+        let mut broken = records.clone();
+        broken.remove(3);
+        assert!(Record::verify_chain(&broken, &base, &pseudonym) == Err("Broken chain at record 3 - Field Constraint - (prev, Record is not part of the stream)".into()));
+
+        // records after a closed stream
+        let r_data = RecordData::inline(CLOSED, Vec::new(), Vec::new());
+        let closed = Record::sign(&records[2].sig.encoded, RecordType::Owned, r_data, &base, &secret, &pseudonym);
+        let r_data = RecordData::inline("JSON", "{}".as_bytes().to_vec(), vec![9]);
+        let after = Record::sign(&closed.sig.encoded, RecordType::Owned, r_data, &base, &secret, &pseudonym);
+        let chain = vec![records[0].clone(), records[1].clone(), records[2].clone(), closed, after];
+        assert!(Record::verify_chain(&chain, &base, &pseudonym) == Err("Broken chain at record 4 - The stream is closed!".into()));
+
+        // forged signatures, by another key or with tampered data
+        let mut forged = records.clone();
+        let r_data = RecordData::inline("JSON", "{}".as_bytes().to_vec(), vec![4]);
+        forged[4] = Record::sign(&records[3].sig.encoded, RecordType::Owned, r_data, &base, &rnd_scalar(), &pseudonym);
+        forged[5].prev = forged[4].sig.encoded.clone();
+        assert!(Record::verify_chain(&forged, &base, &pseudonym) == Err("Broken chain at record 4 - Field Constraint - (sig, Invalid signature)".into()));
+
+        let mut forged = records.clone();
+        forged[1].rdata.meta = "{\"tampered\": true}".as_bytes().to_vec();
+        assert!(Record::verify_chain(&forged, &base, &pseudonym) == Err("Broken chain at record 1 - Field Constraint - (sig, Invalid signature)".into()));
+
+        // under another pseudonym or base
+        let other = rnd_scalar() * base;
+        assert!(Record::verify_chain(&records, &base, &other) == Err("Broken chain at record 0 - Field Constraint - (sig, Invalid signature)".into()));
+        assert!(Record::verify_chain(&records, &(rnd_scalar() * G), &pseudonym) == Err("Broken chain at record 0 - Field Constraint - (sig, Invalid signature)".into()));
+
+        // errors cancelling each other in the combined equation
+        let mut forged = records.clone();
+        forged[2].sig.p += Scalar::one();
+        forged[3].sig.p -= Scalar::one();
+        assert!(Record::verify_chain(&forged, &base, &pseudonym) == Err("Broken chain at record 2 - Field Constraint - (sig, Invalid signature)".into()));
+
+        // committed records signed without M are verified one by one
+        let mut legacy = Vec::<Record>::new();
+        for i in 0..4 {
+            let prev = legacy.last().map(|r: &Record| r.sig.encoded.clone()).unwrap_or_else(|| OPEN.into());
+            let rdata = RecordData::inline("JSON", "{}".as_bytes().to_vec(), vec![i as u8]);
+            let sig_data = Record::data(&prev, &RecordType::Owned, &rdata);
+            let sig = match i % 2 {
+                0 => Signature::sign(&secret, &pseudonym, &base, Record::DOMAIN, &sig_data),
+                _ => Signature::sign_batchable(&secret, &pseudonym, &base, Record::DOMAIN, &sig_data)
+            };
+            legacy.push(Record { prev, typ: RecordType::Owned, rdata, sig, _phantom: () });
+        }
+
+        assert!(legacy[0].sig.M.is_none() && legacy[1].sig.M.is_some());
+        assert!(Record::verify_chain(&legacy, &base, &pseudonym) == Ok(()));
+        legacy[2].rdata.meta = "{\"tampered\": true}".as_bytes().to_vec();
+        assert!(Record::verify_chain(&legacy, &base, &pseudonym) == Err("Broken chain at record 2 - Field Constraint - (sig, Invalid signature)".into()));
+    }
+
+    #[allow(non_snake_case)]
+    #[test]
+    fn test_record_hash() {
+        let Y = rnd_scalar() * G;
+        let p = rnd_scalar();
+
+        let r_data = RecordData::inline("DICOM", "record meta".as_bytes().to_vec(), "record data".as_bytes().to_vec());
+        let record = Record::sign(OPEN, RecordType::Owned, r_data.clone(), &Y, &p, &(p * Y));
+
+        // a re-signed record has the same content address
+        let retry = Record::sign(OPEN, RecordType::Owned, r_data, &Y, &p, &(p * Y));
+        assert!(record.hash() == retry.hash());
+
+        // a distinct payload or position in the stream doesn't
+        let r_data = RecordData::inline("DICOM", "record meta".as_bytes().to_vec(), "other data".as_bytes().to_vec());
+        let other = Record::sign(OPEN, RecordType::Owned, r_data.clone(), &Y, &p, &(p * Y));
+        assert!(record.hash() != other.hash());
+
+        let next = Record::sign(&record.sig.encoded, RecordType::Owned, r_data, &Y, &p, &(p * Y));
+        assert!(other.hash() != next.hash());
+    }
+
+    #[allow(non_snake_case)]
+    #[test]
+    fn test_meta_validation() {
+        let Y = rnd_scalar() * G;
+        let p = rnd_scalar();
+
+        let meta = r#"{"Modality": "MR", "Rows": 512, "Columns": 512, "Laterality": "L"}"#;
+        let r_data = RecordData::inline("DICOM", meta.as_bytes().to_vec(), "record data".as_bytes().to_vec());
+        assert!(r_data.check(true) == Ok(()));
+
+        let record = Record::sign(OPEN, RecordType::Owned, r_data, &Y, &p, &(p * Y));
+        let new_record = NewRecord { record, pseudonym: p * Y, base: Y, token: None };
+        assert!(new_record.check(None, &[Y], true) == Ok(()));
+
+        // invalid DICOM meta is only rejected in strict mode
+        let meta = r#"{"Modality": "MR", "Rows": 512}"#;
+        let r_data = RecordData::inline("DICOM", meta.as_bytes().to_vec(), "record data".as_bytes().to_vec());
+        assert!(r_data.check(false) == Ok(()));
+        assert!(r_data.check(true) == Err(FpiError::constraint("meta", "Missing DICOM tag Columns")));
+
+        let record = Record::sign(OPEN, RecordType::Owned, r_data, &Y, &p, &(p * Y));
+        let new_record = NewRecord { record, pseudonym: p * Y, base: Y, token: None };
+        assert!(new_record.check(None, &[Y], false) == Ok(()));
+        assert!(new_record.check(None, &[Y], true) == Err("Field Constraint - (meta, Missing DICOM tag Columns)".into()));
+
+        let r_data = RecordData::inline("DICOM", r#"["MR", 512, 512]"#.as_bytes().to_vec(), Vec::new());
+        assert!(r_data.check(true) == Err(FpiError::constraint("meta", "Expected a DICOM attribute object")));
+
+        // malformed JSON meta
+        let r_data = RecordData::inline("JSON", r#"{"key": "value"}"#.as_bytes().to_vec(), Vec::new());
+        assert!(r_data.check(true) == Ok(()));
+
+        let r_data = RecordData::inline("JSON", r#"{"key": "value""#.as_bytes().to_vec(), Vec::new());
+        assert!(r_data.check(true) == Err(FpiError::constraint("meta", "Invalid JSON")));
+
+        let r_data = RecordData::inline("DICOM", "record meta".as_bytes().to_vec(), Vec::new());
+        assert!(r_data.check(true) == Err(FpiError::constraint("meta", "Invalid JSON")));
+
+        // unknown formats skip validation
+        let r_data = RecordData::inline("XML", "<meta".as_bytes().to_vec(), Vec::new());
+        assert!(r_data.check(true) == Ok(()));
+    }
+
+    #[allow(non_snake_case)]
+    #[test]
+    fn test_attach_token() {
+        let Y = rnd_scalar() * G;
+
+        // owner stream
+        let p = rnd_scalar();
+        let r_data = RecordData::inline("DICOM", "record meta".as_bytes().to_vec(), "record data".as_bytes().to_vec());
+        let owned = Record::sign(OPEN, RecordType::Owned, r_data, &Y, &p, &(p * Y));
+        let owner = stream_id(&(p * Y));
+
+        // attacher stream
+        let a = rnd_scalar();
+        let r_data = RecordData::inline("DICOM", "attach meta".as_bytes().to_vec(), "attach data".as_bytes().to_vec());
+        let attached = Record::sign(OPEN, RecordType::AnonymousAttach(owned.sig.encoded.clone()), r_data, &Y, &a, &(a * Y));
+
+        // fully-open attach
+        let open = NewRecord { record: attached.clone(), pseudonym: a * Y, base: Y, token: None };
+        assert!(open.check(None, &[Y], false) == Ok(()));
+        assert!(open.check_attach(Some(&owner), &[Y], false) == Ok(()));
+        assert!(open.check_attach(Some(&owner), &[Y], true) == Err("Field Constraint - (token, Attachment not authorized)".into()));
+
+        // authorized by the owner
+        let token = AttachToken::sign(&owned.sig.encoded, &(a * Y), &Y, &p, &(p * Y));
+        let authorized = NewRecord { record: attached.clone(), pseudonym: a * Y, base: Y, token: Some(token.clone()) };
+        assert!(authorized.check_attach(Some(&owner), &[Y], true) == Ok(()));
+        assert!(authorized.check_attach(None, &[Y], true) == Err("Field Constraint - (token, Not signed by the stream owner)".into()));
+        assert!(authorized.check_attach(Some(&owner), &[rnd_scalar() * G], true) == Err("Field Constraint - (token, Expected a master-key)".into()));
+
+        // the token is bound to the attacher
+        let s = rnd_scalar();
+        let r_data = RecordData::inline("DICOM", "spam meta".as_bytes().to_vec(), "spam data".as_bytes().to_vec());
+        let spam = Record::sign(OPEN, RecordType::AnonymousAttach(owned.sig.encoded.clone()), r_data, &Y, &s, &(s * Y));
+        let stolen = NewRecord { record: spam, pseudonym: s * Y, base: Y, token: Some(token) };
+        assert!(stolen.check_attach(Some(&owner), &[Y], true) == Err("Field Constraint - (token, Incorrect attacher)".into()));
+
+        // only the owner can sign the token
+        let forged = AttachToken::sign(&owned.sig.encoded, &(s * Y), &Y, &s, &(p * Y));
+        let stolen = NewRecord { token: Some(forged), ..stolen };
+        assert!(stolen.check_attach(Some(&owner), &[Y], true) == Err("Field Constraint - (token, Invalid signature)".into()));
+
+        let other = AttachToken::sign("other-record", &(a * Y), &Y, &p, &(p * Y));
+        let wrong = NewRecord { record: attached, pseudonym: a * Y, base: Y, token: Some(other) };
+        assert!(wrong.check_attach(Some(&owner), &[Y], true) == Err("Field Constraint - (token, Incorrect attach reference)".into()));
+    }
+
+    #[test]
+    fn test_record_retention() {
+        let base = rnd_scalar() * G;
+        let secret = rnd_scalar();
+        let pseudonym = secret * base;
+
+        // stream of records, one per block
+        let mut records = Vec::<Record>::new();
+        for i in 0..10 {
+            let prev = records.last().map(|r| r.sig.encoded.clone()).unwrap_or_else(|| OPEN.into());
+            let format = if i == 9 { CLOSED } else { "JSON" };
+            let r_data = RecordData::inline(format, b"{}".to_vec(), format!("data-{}", i).into_bytes());
+            let record = Record::sign(&prev, RecordType::Owned, r_data, &base, &secret, &pseudonym);
+            assert!(record.check(records.last(), &base, &pseudonym) == Ok(()));
+            records.push(record);
+        }
+
+        let hashes: Vec<String> = records.iter().map(|r| r.hash()).collect();
+        let node = |policy: &Retention| -> (RecordLog, Vec<RecordEntry>) {
+            let mut log = RecordLog::default();
+            let mut pruned = Vec::<RecordEntry>::new();
+            for (i, record) in records.iter().enumerate() {
+                let height = (i + 1) as i64;
+                log.push(RecordEntry { height, hash: record.hash(), sig: record.sig.encoded.clone() });
+                pruned.extend(log.prune(policy, height));
+            }
+
+            (log, pruned)
+        };
+
+        // disabled by default
+        let policy = Retention::default();
+        assert!(!policy.enabled());
+        let (log, pruned) = node(&policy);
+        assert!(log.entries.len() == 10 && pruned.is_empty() && log.tombstone.is_none());
+        assert!(log.verify(&hashes) == Ok(()));
+
+        // keep the last entries, identical across nodes
+        let policy = Retention { keep_last: 3, max_age: 0, tombstones: true };
+        let (log1, pruned1) = node(&policy);
+        let (log2, pruned2) = node(&policy);
+        assert!(log1.entries.len() == 3 && pruned1.len() == 7);
+        assert!(bincode::serialize(&log1).unwrap() == bincode::serialize(&log2).unwrap());
+        assert!(pruned1 == pruned2);
+        assert!(log1.verify(&hashes) == Ok(()));
+
+        // the closed head is retained
+        assert!(log1.entries.last().unwrap().sig == records[9].sig.encoded);
+
+        // by age, in blocks
+        let policy = Retention { keep_last: 0, max_age: 4, tombstones: true };
+        let (mut log, _) = node(&policy);
+        assert!(log.entries.iter().map(|e| e.height).collect::<Vec<_>>() == vec![6, 7, 8, 9, 10]);
+        assert!(log.verify(&hashes) == Ok(()));
+
+        // later blocks without records keep the head
+        assert!(log.prune(&policy, 100).len() == 4);
+        assert!(log.entries.len() == 1 && log.verify(&hashes) == Ok(()));
+
+        // tampered history
+        let mut tampered = hashes.clone();
+        tampered[0] = hashes[1].clone();
+        assert!(log.verify(&tampered) == Err("Record log with an incorrect tombstone!".into()));
+        assert!(log.verify(&hashes[..9]) == Err("Record log doesn't match the stream!".into()));
+
+        // without tombstones only the retained entries are verified
+        let policy = Retention { keep_last: 2, max_age: 0, tombstones: false };
+        let (log, _) = node(&policy);
+        assert!(log.tombstone.is_none() && log.verify(&hashes) == Ok(()));
+    }
+
+    #[allow(non_snake_case)]
+    #[test]
+    fn test_stream_query() {
+        let base = rnd_scalar() * G;
+        let secret = rnd_scalar();
+        let pseudonym = secret * base;
+
+        let peer_s = rnd_scalar();
+        let peers = vec![rnd_scalar() * G, peer_s * G];
+
+        let sig_s = rnd_scalar();
+        let (_, skey) = Subject::new("s-id:reader").evolve(sig_s);
+
+        let mut records = Vec::<Record>::new();
+        for i in 0..4 {
+            let prev = records.last().map(|r: &Record| r.sig.encoded.clone()).unwrap_or_else(|| OPEN.into());
+            let format = if i == 3 { CLOSED } else { "JSON" };
+            let r_data = RecordData::inline(format, "{}".as_bytes().to_vec(), vec![i as u8]);
+            records.push(Record::sign(&prev, RecordType::Owned, r_data, &base, &secret, &pseudonym));
+        }
+
+        let query = RecordStreamQuery::sign_owner("s-id:reader", &pseudonym, &base, 0, 10, &secret, &sig_s, &skey);
+        let mut subject = Subject::new("s-id:reader");
+        subject.keys.push(skey.clone());
+        assert!(query.verify(&subject, Duration::from_secs(5)) == Ok(()));
+        assert!(query.stream() == stream_id(&pseudonym));
+
+        let bad = RecordStreamQuery::sign_owner("s-id:reader", &pseudonym, &base, 0, MAX_RECORDS_QUERY + 1, &secret, &sig_s, &skey);
+        assert!(bad.verify(&subject, Duration::from_secs(5)) == Err(FpiError::constraint("limit", format!("range = [1, {}]", MAX_RECORDS_QUERY))));
+
+        // complete stream and a part of the stream
+        let res = RecordStreamResult::sign(query.sig.id(), 0, records.clone(), &peer_s, &peers[1], 1);
+        assert!(res.check(&query, &peers) == Ok(()));
+
+        let res = RecordStreamResult::sign(query.sig.id(), 1, records[1..3].to_vec(), &peer_s, &peers[1], 1);
+        assert!(res.check(&query, &peers) == Ok(()));
+
+        // unknown stream
+        let res = RecordStreamResult::sign(query.sig.id(), 0, Vec::new(), &peer_s, &peers[1], 1);
+        assert!(res.check(&query, &peers) == Ok(()));
+
+        // the OPEN record must be the first one
+        let res = RecordStreamResult::sign(query.sig.id(), 0, records[1..].to_vec(), &peer_s, &peers[1], 1);
+        assert!(res.check(&query, &peers) == Err("Field Constraint - (records, Record is not part of the stream)".into()));
+
+        // gaps in the chain
+        let res = RecordStreamResult::sign(query.sig.id(), 0, vec![records[0].clone(), records[2].clone()], &peer_s, &peers[1], 1);
+        assert!(res.check(&query, &peers) == Err("Field Constraint - (records, Record is not part of the stream)".into()));
+
+        // records of another pseudonym
+        let o_secret = rnd_scalar();
+        let other = o_secret * base;
+        let query2 = RecordStreamQuery::sign_owner("s-id:reader", &other, &base, 0, 10, &o_secret, &sig_s, &skey);
+        let res = RecordStreamResult::sign(query2.sig.id(), 0, records.clone(), &peer_s, &peers[1], 1);
+        assert!(res.check(&query2, &peers) == Err("Field Constraint - (sig, Invalid signature)".into()));
+
+        // more than requested, or for another session
+        let query3 = RecordStreamQuery::sign_owner("s-id:reader", &pseudonym, &base, 0, 2, &secret, &sig_s, &skey);
+        let res = RecordStreamResult::sign(query3.sig.id(), 0, records.clone(), &peer_s, &peers[1], 1);
+        assert!(res.check(&query3, &peers) == Err("Field Constraint - (records, Expected at most the requested limit)".into()));
+        assert!(res.check(&query, &peers) == Err("Field Constraint - (session, Expected the same session)".into()));
+
+        // before the requested index
+        let query4 = RecordStreamQuery::sign_owner("s-id:reader", &pseudonym, &base, 2, 10, &secret, &sig_s, &skey);
+        let res = RecordStreamResult::sign(query4.sig.id(), 1, records[1..].to_vec(), &peer_s, &peers[1], 1);
+        assert!(res.check(&query4, &peers) == Err("Field Constraint - (from_index, Expected records after the requested index)".into()));
+
+        // the last records of the stream, at any position
+        let query5 = RecordStreamQuery::sign_owner("s-id:reader", &pseudonym, &base, TAIL, 2, &secret, &sig_s, &skey);
+        let res = RecordStreamResult::sign(query5.sig.id(), 2, records[2..].to_vec(), &peer_s, &peers[1], 1);
+        assert!(res.check(&query5, &peers) == Ok(()));
+
+        let res = RecordStreamResult::sign(query5.sig.id(), 1, records[1..].to_vec(), &peer_s, &peers[1], 1);
+        assert!(res.check(&query5, &peers) == Err("Field Constraint - (records, Expected at most the requested limit)".into()));
+
+        // peer signature
+        let res = RecordStreamResult::sign(query.sig.id(), 0, records.clone(), &peer_s, &peers[1], 0);
+        assert!(res.check(&query, &peers) == Err("Field Constraint - (sig, Invalid signature)".into()));
+
+        let res = RecordStreamResult::sign(query.sig.id(), 0, records.clone(), &peer_s, &peers[1], 2);
+        assert!(res.check(&query, &peers) == Err("Unexpected peer index!".into()));
+
+        let mut res = RecordStreamResult::sign(query.sig.id(), 0, records[..2].to_vec(), &peer_s, &peers[1], 1);
+        res.records.push(records[2].clone());
+        assert!(res.check(&query, &peers) == Err("Field Constraint - (sig, Invalid signature)".into()));
+
+        // cursor paging, the page continues the record of the cursor at any position
+        let after = RecordStreamQuery::sign_owner_after("s-id:reader", &pseudonym, &base, &records[1].sig.encoded, 10, &secret, &sig_s, &skey);
+        assert!(after.verify(&subject, Duration::from_secs(5)) == Ok(()));
+        assert!(after.authorize(&subject, &peers, 0) == Ok(()));
+
+        let res = RecordStreamResult::sign(after.sig.id(), 2, records[2..].to_vec(), &peer_s, &peers[1], 1);
+        assert!(res.check(&after, &peers) == Ok(()));
+
+        let res = RecordStreamResult::sign(after.sig.id(), 3, records[3..].to_vec(), &peer_s, &peers[1], 1);
+        assert!(res.check(&after, &peers) == Err("Field Constraint - (records, Record is not part of the stream)".into()));
+
+        let first = RecordStreamQuery::sign_owner_after("s-id:reader", &pseudonym, &base, OPEN, 10, &secret, &sig_s, &skey);
+        let res = RecordStreamResult::sign(first.sig.id(), 0, records.clone(), &peer_s, &peers[1], 1);
+        assert!(res.check(&first, &peers) == Ok(()));
+
+        // the cursor is signed, and replaces the index
+        let mut moved = after.clone();
+        moved.from_prev = Some(records[2].sig.encoded.clone());
+        assert!(moved.verify(&subject, Duration::from_secs(5)) == Err(FpiError::signature("sig")));
+
+        let mut indexed = after;
+        indexed.from_index = 2;
+        assert!(indexed.verify(&subject, Duration::from_secs(5)) == Err(FpiError::constraint("from_index", "Expected 0 for a cursor query")));
+    }
+
+    #[test]
+    fn test_stream_access() {
+        let threshold = 1;
+        let parties = 3*threshold + 1;
+
+        let secrets: Vec<Scalar> = (0..parties).map(|_| rnd_scalar()).collect();
+        let peers: Vec<RistrettoPoint> = secrets.iter().map(|s| s * G).collect();
+
+        // the pseudonym master-key, shared by the peers
+        let y = rnd_scalar();
+        let y_shares = Polynomial::rnd(y, threshold).shares(parties);
+        let base = y * G;
+
+        let secret = rnd_scalar();
+        let pseudonym = secret * base;
+
+        let sig_s = rnd_scalar();
+        let mut reader = Subject::new("s-id:reader");
+        let (_, skey) = reader.evolve(sig_s);
+        reader.keys.push(skey.clone());
+
+        // the owner proves the profile secret
+        let query = RecordStreamQuery::sign_owner("s-id:reader", &pseudonym, &base, 0, 10, &secret, &sig_s, &skey);
+        assert!(query.authorize(&reader, &peers, threshold) == Ok(()));
+
+        let query = RecordStreamQuery::sign_owner("s-id:reader", &pseudonym, &base, 0, 10, &rnd_scalar(), &sig_s, &skey);
+        assert!(query.authorize(&reader, &peers, threshold) == Err("Stream access denied, invalid owner signature!".into()));
+
+        // a reader presents the disclosure of the profile
+        let profiles = vec!["Assets".to_string()];
+        let request = DiscloseRequest::sign("s-id:reader", "s-id:target", &profiles, false, &sig_s, &skey);
+        let results: Vec<DiscloseResult> = (0..parties).map(|i| {
+            let mut dkeys = DiscloseKeys::new();
+            dkeys.put("Assets", "https://url.org", (&y_shares.0[i] * &(secret * G)).Yi, None);
+            DiscloseResult::sign(&request.sig.sig.encoded, 0, dkeys, &secrets[i], &peers[i], i)
+        }).collect();
+
+        let quorum = results[..2*threshold + 1].to_vec();
+        let query = RecordStreamQuery::sign_disclosed("s-id:reader", &pseudonym, &base, 0, 10, request.clone(), quorum.clone(), &sig_s, &skey);
+        assert!(query.authorize(&reader, &peers, threshold) == Ok(()));
+
+        // the disclosure doesn't reveal other streams
+        let other = rnd_scalar() * base;
+        let query = RecordStreamQuery::sign_disclosed("s-id:reader", &other, &base, 0, 10, request.clone(), quorum.clone(), &sig_s, &skey);
+        assert!(query.authorize(&reader, &peers, threshold) == Err("Stream access denied, the pseudonym is not in the disclosure!".into()));
+
+        // not enough peer results
+        let query = RecordStreamQuery::sign_disclosed("s-id:reader", &pseudonym, &base, 0, 10, request.clone(), results[..2*threshold].to_vec(), &sig_s, &skey);
+        assert!(query.authorize(&reader, &peers, threshold) == Err("Not enought responses to process disclosure!".into()));
+
+        // the request fields are bound to the signature of the session
+        let mut forged = request.clone();
+        forged.target = "s-id:other".into();
+        let query = RecordStreamQuery::sign_disclosed("s-id:reader", &pseudonym, &base, 0, 10, forged, quorum.clone(), &sig_s, &skey);
+        assert!(query.authorize(&reader, &peers, threshold) == Err("Field Constraint - (sig, Invalid signature)".into()));
+
+        // the disclosure of another subject
+        let o_sig_s = rnd_scalar();
+        let (_, okey) = Subject::new("s-id:other").evolve(o_sig_s);
+        let o_request = DiscloseRequest::sign("s-id:other", "s-id:target", &profiles, false, &o_sig_s, &okey);
+        let query = RecordStreamQuery::sign_disclosed("s-id:reader", &pseudonym, &base, 0, 10, o_request, quorum, &sig_s, &skey);
+        assert!(query.authorize(&reader, &peers, threshold) == Err("Stream access denied, the disclosure is from another subject!".into()));
+    }
+
+    #[test]
+    fn test_golden_layout() {
+        let r_data = RecordData::inline("DICOM", b"record meta".to_vec(), b"record data".to_vec());
+        let owned = Record::data("prev-sig", &RecordType::Owned, &r_data);
+        assert!(crate::structs::layout(&owned) == "4,16,55:Gj6G9yMBrScMmZoekw2ASp1SNe1tpmbYwNnXFvpx6AmA");
+
+        let attach = RecordType::IdentifiedAttach("s-id:attacher".into(), "record-sig".into());
+        let attached = Record::data("prev-sig", &attach, &r_data);
+        assert!(crate::structs::layout(&attached) == "43,16,55:62fzE76biPSmWoy5uZEVfCWwvsB62WG12MJitkxfcFeL");
     }
 }
\ No newline at end of file
@@ -0,0 +1,469 @@
+use std::collections::HashSet;
+use std::time::Duration;
+use chrono::Utc;
+
+use crate::ids::*;
+use crate::structs::*;
+use crate::shares::{Share, RistrettoShare, Polynomial, RistrettoPolynomial, Interpolate};
+use crate::signatures::{IndSignature, ExtSignature};
+use crate::{G, Result, FpiError, FpiResult, Scalar, RistrettoPoint, KeyEncoder};
+
+use serde::{Serialize, Deserialize};
+
+//-----------------------------------------------------------------------------------------------------------
+// Subject Recovery
+//-----------------------------------------------------------------------------------------------------------
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Trustee {
+    pub tid: String,                                // Trustee subject-id
+    pub share: RistrettoShare                       // Public share (y_i * G), the trustee acknowledges the private share against it
+}
+
+// The subject secret of the signing key is split across the trustees (threshold-of-n).
+// Only the public shares are on-chain, the private shares are handed out-of-band to each trustee.
+#[allow(clippy::manual_non_exhaustive)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Recovery {
+    pub sid: String,                                // Subject-id protected by the recovery
+    pub threshold: usize,                           // Number of trustees required to recover the secret
+    pub trustees: Vec<Trustee>,                     // Ordered trustees, the position is the share index (i = position + 1)
+
+    pub sig: IndSignature,                          // Signature from data-subject, the protected key is the one at sig.index
+    #[serde(skip)] _phantom: () // force use of constructor
+}
+
+impl Constraints for Recovery {
+    fn sid(&self) -> &str { &self.sid }
+
+    fn verify(&self, subject: &Subject, threshold: Duration) -> FpiResult<()> {
+        if self.sid.len() > MAX_SUBJECT_ID_SIZE {
+            return Err(FpiError::constraint("sid", format!("max-size = {}", MAX_SUBJECT_ID_SIZE)))
+        }
+
+        if self.threshold == 0 || self.threshold > self.trustees.len() {
+            return Err(FpiError::constraint("threshold", "expected 1..=#trustees"))
+        }
+
+        if self.trustees.len() > MAX_TRUSTEES {
+            return Err(FpiError::constraint("trustees", format!("max-size = {}", MAX_TRUSTEES)))
+        }
+
+        for item in self.trustees.iter() {
+            if item.tid.len() > MAX_SUBJECT_ID_SIZE {
+                return Err(FpiError::constraint("tid", format!("max-size = {}", MAX_SUBJECT_ID_SIZE)))
+            }
+        }
+
+        if !self.sig.sig.check_timestamp(threshold) {
+            return Err(FpiError::Timestamp)
+        }
+
+        let skey = subject.active_key()?;
+        if self.sig.index != skey.sig.index {
+            return Err(FpiError::constraint("sig", "Incorrect key index"))
+        }
+
+        let sig_data = Self::data(&self.sid, self.threshold, &self.trustees);
+        if !self.sig.verify(&skey.key, Self::DOMAIN, &sig_data) {
+            return Err(FpiError::signature("sig"))
+        }
+
+        Ok(())
+    }
+}
+
+impl Recovery {
+    const DOMAIN: &'static str = "fpi:recovery";
+
+    // split the subject secret (of the signing key), returning the recovery and the private shares in the trustees order
+    pub fn setup(sid: &str, trustees: &[String], threshold: usize, sig_s: &Scalar, sig_key: &SubjectKey) -> Result<(Self, Vec<RecoveryShare>)> {
+        if threshold == 0 || threshold > trustees.len() {
+            return Err("Recovery threshold out of range, expected 1..=#trustees!".into())
+        }
+
+        let poly = Polynomial::rnd(*sig_s, threshold - 1);
+        let shares = poly.shares(trustees.len());
+
+        let public = trustees.iter().zip(shares.0.iter())
+            .map(|(tid, share)| Trustee { tid: tid.clone(), share: share * &G })
+            .collect();
+
+        let private = trustees.iter().zip(shares.0.iter())
+            .map(|(tid, share)| RecoveryShare { sid: sid.into(), tid: tid.clone(), key: sig_key.key, share: share.clone() })
+            .collect();
+
+        Ok((Self::sign(sid, threshold, public, sig_s, sig_key), private))
+    }
+
+    pub fn sign(sid: &str, threshold: usize, trustees: Vec<Trustee>, sig_s: &Scalar, sig_key: &SubjectKey) -> Self {
+        let sig_data = Self::data(sid, threshold, &trustees);
+        let sig = IndSignature::sign_at(Utc::now().timestamp(), sig_key.sig.index, sig_s, &sig_key.key, Self::DOMAIN, &sig_data);
+
+        Self { sid: sid.into(), threshold, trustees, sig, _phantom: () }
+    }
+
+    // The public shares must be on a polynomial of degree (threshold - 1) with the protected key at x = 0.
+    // Any threshold of private shares (acknowledged against the public ones) recovers the secret of that key.
+    pub fn check(&self, subject: &Subject) -> Result<()> {
+        let mut tids = HashSet::<&str>::new();
+        for (i, item) in self.trustees.iter().enumerate() {
+            if item.tid == self.sid {
+                return Err("The subject can't be a trustee of itself!".into())
+            }
+
+            if !tids.insert(&item.tid) {
+                return Err(format!("Duplicated trustee: {}", item.tid))
+            }
+
+            if item.share.i as usize != i + 1 {
+                return Err(format!("Unexpected share index for trustee: {}", item.tid))
+            }
+        }
+
+        // the protected key must not be revoked after the recovery setup
+        let skey = subject.signing_key(&self.sig)?;
+
+        let shares: Vec<RistrettoShare> = self.trustees.iter().map(|item| item.share.clone()).collect();
+        let (head, tail) = shares.split_at(self.threshold);
+        if RistrettoPolynomial::interpolate(head)? != skey.key {
+            return Err("Recovery shares don't match the subject-key!".into())
+        }
+
+        for item in tail.iter() {
+            let x = Scalar::from(item.i);
+            if RistrettoPolynomial::interpolate_at(head, &x)? != item.Yi {
+                return Err(format!("Inconsistent recovery share at index {}!", item.i))
+            }
+        }
+
+        Ok(())
+    }
+
+    // an evolution (or the revoke of the key) supersedes the protected key, and the trustees shares no longer recover the active key
+    pub fn protects(&self, subject: &Subject) -> bool {
+        match subject.active_key() {
+            Ok(skey) => skey.sig.index == self.sig.index,
+            Err(_) => false
+        }
+    }
+
+    fn data(sid: &str, threshold: usize, trustees: &[Trustee]) -> Vec<Vec<u8>> {
+        // These unwrap() should never fail, or it's a serious code bug!
+        let b_sid = bincode::serialize(sid).unwrap();
+        let b_threshold = bincode::serialize(&threshold).unwrap();
+        let b_trustees = bincode::serialize(trustees).unwrap();
+
+        vec![b_sid, b_threshold, b_trustees]
+    }
+}
+
+//-----------------------------------------------------------------------------------------------------------
+// Recovery Share (private, never on-chain)
+//-----------------------------------------------------------------------------------------------------------
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RecoveryShare {
+    pub sid: String,                                // Subject-id protected by the recovery
+    pub tid: String,                                // Trustee subject-id
+    pub key: RistrettoPoint,                        // Protected subject-key
+    pub share: Share                                // Private share of the subject secret
+}
+
+impl RecoveryShare {
+    // base58 of the share, to hand out-of-band to the trustee
+    pub fn encode(&self) -> String {
+        // This unwrap() should never fail, or it's a serious code bug!
+        bincode::serialize(self).unwrap().encode()
+    }
+
+    pub fn decode(data: &str) -> Result<Self> {
+        let data = bs58::decode(data.trim()).into_vec().map_err(|_| "Unable to decode base58 input!")?;
+        bincode::deserialize(&data).map_err(|_| "Unable to decode the recovery share!".into())
+    }
+
+    // acknowledgment of the trustee, the private share against the public share in the recovery
+    pub fn check(&self, recovery: &Recovery) -> Result<()> {
+        if self.sid != recovery.sid {
+            return Err("Recovery share from a different subject!".into())
+        }
+
+        let trustee = recovery.trustees.iter().find(|item| item.tid == self.tid).ok_or("No trustee found for the recovery share!")?;
+        if trustee.share.i != self.share.i || trustee.share.Yi != self.share.yi * G {
+            return Err("Recovery share doesn't match the public share!".into())
+        }
+
+        Ok(())
+    }
+
+    // the secret of the protected key, from at least threshold shares
+    pub fn recover(shares: &[RecoveryShare]) -> Result<Scalar> {
+        let first = shares.first().ok_or("No recovery shares!")?;
+
+        let mut indexes = HashSet::<u32>::new();
+        for item in shares.iter() {
+            if item.sid != first.sid || item.key != first.key {
+                return Err("Recovery shares from different subjects or keys!".into())
+            }
+
+            if !indexes.insert(item.share.i) {
+                return Err(format!("Duplicated recovery share from trustee: {}", item.tid))
+            }
+        }
+
+        let shares: Vec<Share> = shares.iter().map(|item| item.share.clone()).collect();
+        let secret = Polynomial::interpolate(&shares)?;
+        if secret * G != first.key {
+            return Err("Not enough or invalid recovery shares!".into())
+        }
+
+        Ok(secret)
+    }
+}
+
+//-----------------------------------------------------------------------------------------------------------
+// Query of the committed subject, to rebuild the local store with the recovered secret
+//-----------------------------------------------------------------------------------------------------------
+// The recovery shares don't have the key index, the query is signed with the recovered key.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SubjectQuery {
+    pub sid: String,                                // Subject-id to query
+
+    pub sig: ExtSignature,                          // Signature from the active subject-key
+}
+
+impl Constraints for SubjectQuery {
+    fn sid(&self) -> &str { &self.sid }
+
+    fn verify(&self, subject: &Subject, threshold: Duration) -> FpiResult<()> {
+        if self.sid.len() > MAX_SUBJECT_ID_SIZE {
+            return Err(FpiError::constraint("sid", format!("max-size = {}", MAX_SUBJECT_ID_SIZE)))
+        }
+
+        if !self.sig.sig.check_timestamp(threshold) {
+            return Err(FpiError::Timestamp)
+        }
+
+        let skey = subject.active_key()?;
+        if self.sig.key != skey.key {
+            return Err(FpiError::constraint("sig", "Expected the active key"))
+        }
+
+        let sig_data = Self::data(&self.sid);
+        if !self.sig.verify(Self::DOMAIN, &sig_data) {
+            return Err(FpiError::signature("sig"))
+        }
+
+        Ok(())
+    }
+}
+
+impl SubjectQuery {
+    const DOMAIN: &'static str = "fpi:subject-query";
+
+    pub fn sign(sid: &str, sig_s: &Scalar) -> Self {
+        let sig_data = Self::data(sid);
+        let sig = ExtSignature::sign(sig_s, sig_s * G, Self::DOMAIN, &sig_data);
+
+        Self { sid: sid.into(), sig }
+    }
+
+    fn data(sid: &str) -> [Vec<u8>; 1] {
+        // These unwrap() should never fail, or it's a serious code bug!
+        let b_sid = bincode::serialize(sid).unwrap();
+
+        [b_sid]
+    }
+}
+
+#[allow(clippy::manual_non_exhaustive)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SubjectResult {
+    pub session: String,                            // query signature
+    pub subject: Subject,                           // committed subject
+
+    pub sig: IndSignature,                          // Signature from peer
+    #[serde(skip)] _phantom: () // force use of constructor
+}
+
+impl SubjectResult {
+    const DOMAIN: &'static str = "fpi:subject-result";
+
+    pub fn sign(session: &str, subject: Subject, secret: &Scalar, key: &RistrettoPoint, index: usize) -> Self {
+        let sig_data = Self::data(session, &subject);
+        let sig = IndSignature::sign(index, secret, key, Self::DOMAIN, &sig_data);
+
+        Self { session: session.into(), subject, sig, _phantom: () }
+    }
+
+    pub fn check(&self, query: &SubjectQuery, peers: &[RistrettoPoint]) -> Result<()> {
+        if self.session != query.sig.id() {
+            return Err("Field Constraint - (session, Expected the same session)".into())
+        }
+
+        if self.subject.sid != query.sid {
+            return Err("Field Constraint - (subject, Expected the same subject)".into())
+        }
+
+        if self.subject.active_key()?.key != query.sig.key {
+            return Err("Field Constraint - (subject, Expected the key of the query)".into())
+        }
+
+        Ok(self.verify(peers)?)
+    }
+
+    pub fn verify(&self, peers: &[RistrettoPoint]) -> FpiResult<()> {
+        let sig_data = Self::data(&self.session, &self.subject);
+        if !self.sig.verify_against(peers, Self::DOMAIN, &sig_data).map_err(FpiError::Crypto)? {
+            return Err(FpiError::signature("sig"))
+        }
+
+        Ok(())
+    }
+
+    fn data(session: &str, subject: &Subject) -> [Vec<u8>; 2] {
+        // These unwrap() should never fail, or it's a serious code bug!
+        let b_session = bincode::serialize(session).unwrap();
+        let b_subject = bincode::serialize(subject).unwrap();
+
+        [b_session, b_subject]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rnd_scalar;
+
+    fn trustees(n: usize) -> Vec<String> {
+        (0..n).map(|i| format!("s-id:trustee-{}", i)).collect()
+    }
+
+    #[test]
+    fn test_recover_secret() {
+        let sid = "s-id:shumy";
+        let mut subject = Subject::new(sid);
+        let (secret, skey) = subject.evolve(rnd_scalar());
+        subject.keys.push(skey.clone());
+
+        let (recovery, shares) = Recovery::setup(sid, &trustees(5), 3, &secret, &skey).unwrap();
+        assert!(recovery.verify(&subject, Duration::from_secs(5)) == Ok(()));
+        assert!(recovery.check(&subject) == Ok(()));
+
+        // each trustee acknowledges the private share
+        for share in shares.iter() {
+            assert!(share.check(&recovery) == Ok(()));
+        }
+
+        // any threshold of shares, in any order
+        assert!(RecoveryShare::recover(&shares[0..3]) == Ok(secret));
+        assert!(RecoveryShare::recover(&[shares[4].clone(), shares[1].clone(), shares[2].clone()]) == Ok(secret));
+        assert!(RecoveryShare::recover(&shares) == Ok(secret));
+
+        // out-of-band encoding
+        let decoded: Vec<RecoveryShare> = shares[2..5].iter().map(|item| RecoveryShare::decode(&item.encode()).unwrap()).collect();
+        assert!(RecoveryShare::recover(&decoded) == Ok(secret));
+
+        // below the threshold
+        assert!(RecoveryShare::recover(&shares[0..2]) == Err("Not enough or invalid recovery shares!".into()));
+        assert!(RecoveryShare::recover(&[shares[0].clone(), shares[0].clone(), shares[1].clone()]) == Err("Duplicated recovery share from trustee: s-id:trustee-0".into()));
+        assert!(RecoveryShare::recover(&[]) == Err("No recovery shares!".into()));
+
+        // the protected key was revoked before the recovery setup
+        let (n_secret, n_skey) = subject.evolve(secret);
+        let mut revoked = subject.clone();
+        revoked.keys.push(n_skey.clone());
+        revoked.revocations.push(Revocation::sign(sid, 0, recovery.sig.sig.timestamp - 1, &n_secret, &n_skey));
+        assert!(recovery.check(&revoked) == Err("Field Constraint - (sig, Signed by a revoked key)".into()));
+    }
+
+    #[test]
+    fn test_invalid_recovery() {
+        let sid = "s-id:shumy";
+        let mut subject = Subject::new(sid);
+        let (secret, skey) = subject.evolve(rnd_scalar());
+        subject.keys.push(skey.clone());
+
+        assert!(Recovery::setup(sid, &trustees(2), 3, &secret, &skey).err() == Some("Recovery threshold out of range, expected 1..=#trustees!".into()));
+        assert!(Recovery::setup(sid, &trustees(2), 0, &secret, &skey).err() == Some("Recovery threshold out of range, expected 1..=#trustees!".into()));
+
+        let (recovery, shares) = Recovery::setup(sid, &trustees(3), 2, &secret, &skey).unwrap();
+
+        // a tampered public share is inconsistent with the others
+        let mut trustees_data = recovery.trustees.clone();
+        trustees_data[2].share.Yi = rnd_scalar() * G;
+        let tampered = Recovery::sign(sid, 2, trustees_data, &secret, &skey);
+        assert!(tampered.verify(&subject, Duration::from_secs(5)) == Ok(()));
+        assert!(tampered.check(&subject) == Err("Inconsistent recovery share at index 3!".into()));
+        assert!(shares[2].check(&tampered) == Err("Recovery share doesn't match the public share!".into()));
+
+        // shares of another secret
+        let (other, _) = Recovery::setup(sid, &trustees(3), 2, &rnd_scalar(), &skey).unwrap();
+        assert!(other.check(&subject) == Err("Recovery shares don't match the subject-key!".into()));
+
+        let mut trustees_data = recovery.trustees.clone();
+        trustees_data[1].tid = trustees_data[0].tid.clone();
+        let duplicated = Recovery::sign(sid, 2, trustees_data, &secret, &skey);
+        assert!(duplicated.check(&subject) == Err("Duplicated trustee: s-id:trustee-0".into()));
+
+        // the signature covers the trustees
+        let mut forged = recovery.clone();
+        forged.threshold = 1;
+        assert!(forged.verify(&subject, Duration::from_secs(5)) == Err(FpiError::signature("sig")));
+    }
+
+    #[test]
+    fn test_superseded_key() {
+        let sid = "s-id:shumy";
+        let mut subject = Subject::new(sid);
+        let (secret, skey) = subject.evolve(rnd_scalar());
+        subject.keys.push(skey.clone());
+
+        let (recovery, _) = Recovery::setup(sid, &trustees(3), 2, &secret, &skey).unwrap();
+        assert!(recovery.protects(&subject));
+
+        // the evolution supersedes the protected key
+        let (_, n_skey) = subject.evolve(secret);
+        subject.keys.push(n_skey);
+        assert!(!recovery.protects(&subject));
+        assert!(recovery.verify(&subject, Duration::from_secs(5)) == Err(FpiError::constraint("sig", "Incorrect key index")));
+    }
+
+    #[test]
+    fn test_subject_query() {
+        let sid = "s-id:shumy";
+        let mut subject = Subject::new(sid);
+        let (secret, skey) = subject.evolve(rnd_scalar());
+        subject.keys.push(skey);
+
+        // signed with the recovered secret, without the key index
+        let query = SubjectQuery::sign(sid, &secret);
+        assert!(query.verify(&subject, Duration::from_secs(5)) == Ok(()));
+        assert!(SubjectQuery::sign(sid, &rnd_scalar()).verify(&subject, Duration::from_secs(5)) == Err(FpiError::constraint("sig", "Expected the active key")));
+
+        let mut forged = query.clone();
+        forged.sid = "s-id:other".into();
+        assert!(forged.verify(&subject, Duration::from_secs(5)) == Err(FpiError::signature("sig")));
+
+        let peer_s = rnd_scalar();
+        let peers = vec![rnd_scalar() * G, peer_s * G];
+        let res = SubjectResult::sign(query.sig.id(), subject.clone(), &peer_s, &peers[1], 1);
+        assert!(res.check(&query, &peers) == Ok(()));
+
+        // a result for another query or key
+        let other = SubjectQuery::sign(sid, &secret);
+        assert!(res.check(&other, &peers) == Err("Field Constraint - (session, Expected the same session)".into()));
+
+        let (n_secret, n_skey) = subject.evolve(secret);
+        let mut evolved = subject.clone();
+        evolved.keys.push(n_skey);
+        let res = SubjectResult::sign(query.sig.id(), evolved, &peer_s, &peers[1], 1);
+        assert!(res.check(&query, &peers) == Err("Field Constraint - (subject, Expected the key of the query)".into()));
+        assert!(SubjectResult::sign(query.sig.id(), subject, &n_secret, &peers[1], 1).verify(&peers) == Err(FpiError::signature("sig")));
+    }
+
+    #[test]
+    fn test_golden_layout() {
+        let trustees = vec![Trustee { tid: "s-id:trustee".into(), share: RistrettoShare { i: 1, Yi: Scalar::from(2u64) * G } }];
+        let recovery = Recovery::data("s-id:shumy", 2, &trustees);
+        assert!(crate::structs::layout(&recovery) == "18,8,72:8w7BMjiGM1t7kk1fFFxMejLiogMRXgwb5VLQ9puvmojy");
+    }
+}
\ No newline at end of file
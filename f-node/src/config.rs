@@ -1,9 +1,34 @@
 use std::collections::HashMap;
-use log::LevelFilter;
-use sha2::{Sha512, Digest};
+use std::time::Duration;
+use indexmap::IndexMap;
+use log::{warn, LevelFilter};
 
 use serde::{Deserialize};
-use core_fpi::{G, rnd_scalar, KeyEncoder, HardKeyDecoder, Scalar, RistrettoPoint, CompressedRistretto};
+use core_fpi::{G, rnd_scalar, env_override, KeyEncoder, TryKeyDecoder, Result, Scalar, RistrettoPoint};
+use core_fpi::governance::{peers_hash, Namespaces, PeerChange};
+use core_fpi::keys::typed_kid;
+use core_fpi::messages::*;
+use core_fpi::records::Retention;
+
+use crate::db::PMASTER;
+
+// Fields that can be overridden with environment variables (env > file > default)
+const ENV_PORT: &str = "FEDPI_PORT";
+const ENV_BIND: &str = "FEDPI_BIND";
+const ENV_LOG: &str = "FEDPI_LOG";
+const ENV_THRESHOLD: &str = "FEDPI_THRESHOLD";
+
+fn bind_default() -> String {
+    "127.0.0.1".into()
+}
+
+fn vss_default() -> String {
+    "feldman".into()
+}
+
+fn timestamp_default() -> u64 {
+    60
+}
 
 fn cfg_default() -> String {
     let secret = rnd_scalar();
@@ -16,15 +41,127 @@ fn cfg_default() -> String {
     
     threshold = 0                       # Number of permitted failing nodes, where #peers >= 3 * t
     port = 26658                        # Set the service port for tendermint
+    bind = "127.0.0.1"                  # Set the service bind address for tendermint
 
     log = "info"                        # Set the log level
     admin = <subject-id>                # Set the admin subject authorized for negotiations
+    strict_meta = false                 # Validate the record meta structure for known formats (DICOM, JSON)
+    typed_bases = []                    # Profile types with pseudonyms under their own master-key (negotiated as p-master:<type>)
+    strict_attach = false               # Anonymous attachments require a token signed by the stream owner
+    vss = "feldman"                     # Verifiable secret sharing of the negotiations (feldman, pedersen hides the shared polynomial until the votes are committed)
+
+    # Subject-id prefixes reserved for approved subjects (unrestricted if empty)
+    # "health:" = ["health:hospital-a"]
+    [namespaces]
+
+    # Retention of the stream record entries (disabled if keep_last and max_age are 0). Must be the same in all peers!
+    [retention]
+    keep_last = 0                       # Number of record entries kept per stream
+    max_age = 0                         # Record entries older than max_age blocks are pruned
+    tombstones = false                  # Summarize the pruned entries in a tombstone hash
 
-    # List of valid peers
-    [peers]
+    # Accepted range (in seconds) of the message timestamps, the message types without an override use the default.
+    # Only checked on check_tx (mempool) and queries, the delivery of a block never depends on them, so peers may differ.
+    [timestamps]
+    default = 60
+    # subject = 60                      # Subject commits
+    # consent = 60                      # Consents, revokes and profile-consent bundles
+    # disclose = 60                     # Disclosure queries
+    # negotiate = 60                    # Master-key negotiations and evidences
+    # query = 60                        # Master-key and record-stream queries
+    # peers = 60                        # Peer-set changes
+
+    # List of valid peers, in order (the position is the peer index)
+    # [[peers]]
+    # name = "peer-0"
+    # pkey = "<CompressedRistretto>"
     "#, secret.encode(), pkey.encode())
 }
 
+// File locations of the node, all derived from HOME. The data folder is shared with tendermint (HOME/data), the app store is nested in it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Paths {
+    pub home: String,
+    pub config: String,                 // HOME/config/app.config.toml
+    pub data: String,                   // HOME/data
+    pub store: String                   // HOME/data/app/store.db
+}
+
+impl Paths {
+    pub fn new(home: &str) -> Self {
+        let home = home.trim_end_matches('/');
+
+        let data = format!("{}/data", home);
+        Self {
+            home: home.into(),
+            config: format!("{}/config/app.config.toml", home),
+            store: format!("{}/app/store.db", data),
+            data
+        }
+    }
+}
+
+// Commitments of the negotiated shares. Pedersen's commitments are committed first (PedersenKey), the Feldman's ones are
+// only revealed after that (MasterKeyReveal). Refresh and resharing always use Feldman's commitments.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Vss { Feldman, Pedersen }
+
+// Accepted timestamp range per message type, falling back to the default.
+// Node-local policy, the ranges are resolved in Processor::request and Processor::filter only (never on deliver).
+#[derive(Deserialize, Debug, Clone)]
+pub struct Timestamps {
+    #[serde(default = "timestamp_default")]
+    pub default: u64,
+
+    pub subject: Option<u64>,
+    pub consent: Option<u64>,
+    pub disclose: Option<u64>,
+    pub negotiate: Option<u64>,
+    pub query: Option<u64>,
+    pub peers: Option<u64>
+}
+
+impl Default for Timestamps {
+    fn default() -> Self {
+        Self { default: timestamp_default(), subject: None, consent: None, disclose: None, negotiate: None, query: None, peers: None }
+    }
+}
+
+impl Timestamps {
+    pub fn request(&self, msg: &Request) -> Duration {
+        let value = match msg {
+            Request::Negotiate(_) => self.negotiate,
+            Request::Query(Query::QDiscloseRequest(_)) => return self.disclose_window(),
+            Request::Query(_) => self.query
+        };
+
+        Duration::from_secs(value.unwrap_or(self.default))
+    }
+
+    // accepted range of the disclosure requests, also the lifetime of the served responses
+    pub fn disclose_window(&self) -> Duration {
+        Duration::from_secs(self.disclose.unwrap_or(self.default))
+    }
+
+    pub fn commit(&self, msg: &Commit) -> Duration {
+        let value = match msg {
+            Commit::Evidence(evd) => match evd {
+                Evidence::EErase(_) => self.subject,
+                Evidence::EPeerChange(_) => self.peers,
+                _ => self.negotiate
+            },
+            Commit::Value(value) => match value {
+                Value::VSubject(_) | Value::VRecovery(_) => self.subject,
+                Value::VConsent(_) | Value::VProfileConsent(_) => self.consent,
+                Value::VPeerSet(_) => self.peers,
+                Value::VNewRecord(_) => None
+            }
+        };
+
+        Duration::from_secs(value.unwrap_or(self.default))
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Peer {
     pub name: String,
@@ -33,87 +170,171 @@ pub struct Peer {
 
 #[derive(Debug, Clone)]
 pub struct Config {
-    pub home: String,
+    pub paths: Paths,
 
     pub name: String,
-    pub index: usize,
+    pub index: Option<usize>,               // None for an observer, a node removed from the committed peer-set
     pub secret: Scalar,
     pub pkey: RistrettoPoint,
 
     pub threshold: usize,
     pub port: usize,
+    pub bind: String,
 
     pub log: LevelFilter,
     pub admin: String,
+    pub strict_meta: bool,
+    pub typed_bases: Vec<String>,
+    pub strict_attach: bool,
+    pub vss: Vss,
+    pub namespaces: Namespaces,
+    pub retention: Retention,
+    pub timestamps: Timestamps,
     
     pub peers: Vec<Peer>,
     pub peers_hash: Vec<u8>,
     pub peers_keys: Vec<RistrettoPoint>,
+    pub change: Option<PeerChange>,         // committed transition of the peer-set (evidence of the previous one is accepted after the PeerSet)
 }
 
 impl Config {
     pub fn new(home: &str) -> Self {
-        let filename = format!("{}/config/app.config.toml", home);
+        let paths = Paths::new(home);
         
-        let cfg = match std::fs::read_to_string(&filename) {
+        let cfg = match std::fs::read_to_string(&paths.config) {
             Ok(content) => content,
             Err(_) => {
                 let def_cfg = cfg_default();
-                std::fs::write(&filename, &def_cfg).unwrap_or_else(|e| panic!("Problems when creating the default config file: {}", e));
+                std::fs::write(&paths.config, &def_cfg).unwrap_or_else(|e| panic!("Problems when creating the default config file: {}", e));
                 def_cfg
             }
         };
 
         let t_cfg: TomlConfig = toml::from_str(&cfg).expect("Unable to decode toml configuration!");
-        let pkey: CompressedRistretto = t_cfg.pkey.decode();
-        
-        let mut peers = Vec::<Peer>::with_capacity(t_cfg.peers.len());
-        let mut hasher = Sha512::new();
-        for i in 0..t_cfg.peers.len() {
-            let index = format!("{}", i);
-            let peer = t_cfg.peers.get(&index).unwrap_or_else(|| panic!("Expected peer at index {}!", i));
+        Self::load(paths, t_cfg).unwrap_or_else(|e| panic!("{}", e))
+    }
 
-            let pkey: CompressedRistretto = peer.pkey.decode();
-            hasher.input(pkey.as_bytes());
+    fn load(paths: Paths, mut t_cfg: TomlConfig) -> Result<Self> {
+        t_cfg.port = env_override(ENV_PORT, t_cfg.port)?;
+        t_cfg.bind = env_override(ENV_BIND, t_cfg.bind)?;
+        t_cfg.log = env_override(ENV_LOG, t_cfg.log)?;
+        t_cfg.threshold = env_override(ENV_THRESHOLD, t_cfg.threshold)?;
 
-            let pkey = pkey.decompress().unwrap_or_else(|| panic!("Unable to decompress peer-key: {}", peer.name));
+        let pkey: RistrettoPoint = t_cfg.pkey.try_decode().map_err(|e| format!("Invalid pkey: {}", e))?;
+        let secret: Scalar = t_cfg.secret.try_decode().map_err(|e| format!("Invalid secret: {}", e))?;
+        
+        let t_peers = t_cfg.peers.ordered()?;
+        let mut peers = Vec::<Peer>::with_capacity(t_peers.len());
+        for peer in t_peers {
+            let pkey: RistrettoPoint = peer.pkey.try_decode().map_err(|e| format!("Invalid pkey for peer '{}': {}", peer.name, e))?;
             let peer = Peer { name: peer.name.clone(), pkey };
 
             peers.push(peer);
         }
-
-        let pkey = pkey.decompress().expect("Unable to decompress pkey!");
-        let index = peers.iter().position(|item| item.pkey == pkey).expect("Configuration error! Expecting to find the corresponding peer index!");
         
         let llog = match t_cfg.log.as_ref() {
             "info" => LevelFilter::Info,
             "warn" => LevelFilter::Warn,
             "error" => LevelFilter::Error,
-            _ => panic!("Log level not recognized!")
+            _ => return Err(format!("Log level not recognized: {:?}", t_cfg.log))
         };
 
-        let peers_hash = hasher.result().to_vec();
-        let peers_keys: Vec<RistrettoPoint> = peers.iter().map(|p| p.pkey).collect();
+        let vss = match t_cfg.vss.as_ref() {
+            "feldman" => Vss::Feldman,
+            "pedersen" => Vss::Pedersen,
+            _ => return Err(format!("VSS scheme not recognized: {:?}", t_cfg.vss))
+        };
 
-        Self {
-            home: home.into(),
+        for typ in t_cfg.typed_bases.iter() {
+            typed_kid(PMASTER, typ)?;
+        }
+
+        let mut namespaces = Namespaces::new();
+        for (prefix, approved) in t_cfg.namespaces.iter() {
+            namespaces.reserve(prefix, approved);
+        }
+
+        let mut cfg = Self {
+            paths,
 
             name: t_cfg.name,
-            index,
-            secret: t_cfg.secret.decode(),
+            index: None,
+            secret,
             pkey,
             
             threshold: t_cfg.threshold,
             port: t_cfg.port,
+            bind: t_cfg.bind,
 
             log: llog,
             admin: t_cfg.admin,
+            strict_meta: t_cfg.strict_meta,
+            typed_bases: t_cfg.typed_bases,
+            strict_attach: t_cfg.strict_attach,
+            vss,
+            namespaces,
+            retention: t_cfg.retention,
+            timestamps: t_cfg.timestamps,
+
+            peers: Vec::new(),
+            peers_hash: Vec::new(),
+            peers_keys: Vec::new(),
+            change: None
+        };
+
+        // fail here with a precise message, instead of an incorrect set of shares on the first reconstruction
+        check_peers(cfg.threshold, &peers, &cfg.pkey)?;
+
+        cfg.set_peers(cfg.threshold, peers);
+        Ok(cfg)
+    }
 
-            peers,
-            peers_hash,
-            peers_keys
+    // key-id of the pseudonym master-key for the profile type, a typed key if the type has its own base
+    pub fn pseudonym_kid(&self, typ: &str) -> String {
+        match self.typed_bases.iter().any(|item| item == typ) {
+            true => format!("{}:{}", PMASTER, typ),
+            false => PMASTER.into()
         }
     }
+
+    // key-ids of the typed pseudonym master-keys
+    pub fn typed_kids(&self) -> Vec<String> {
+        self.typed_bases.iter().map(|typ| self.pseudonym_kid(typ)).collect()
+    }
+
+    // index of the local node, an observer doesn't vote or sign results
+    pub fn index(&self) -> Result<usize> {
+        self.index.ok_or_else(|| "The node is not in the peer-set (observer)!".into())
+    }
+
+    // Replace the peer's list and recompute all derived fields (index, peers_hash, peers_keys).
+    // A committed peer-set may remove the local node, it keeps delivering the blocks as an observer.
+    pub fn set_peers(&mut self, threshold: usize, peers: Vec<Peer>) {
+        let pkey = self.pkey;
+        self.index = peers.iter().position(|item| item.pkey == pkey);
+        if self.index.is_none() {
+            warn!("OBSERVER - The local node is not in the peer-set (name = {:?})", self.name);
+        }
+
+        self.peers_keys = peers.iter().map(|p| p.pkey).collect();
+        self.peers_hash = peers_hash(&self.peers_keys);
+
+        self.threshold = threshold;
+        self.peers = peers;
+    }
+}
+
+// same rules of the PeerSet, and the local node must be one of the peers
+fn check_peers(threshold: usize, peers: &[Peer], pkey: &RistrettoPoint) -> Result<()> {
+    if peers.len() < 3 * threshold + 1 {
+        return Err(format!("Configuration error! Expecting #peers >= 3 * t + 1 (#peers = {}, t = {})", peers.len(), threshold))
+    }
+
+    if !peers.iter().any(|item| item.pkey == *pkey) {
+        return Err("Configuration error! Expecting to find the local pkey in the peers list".into())
+    }
+
+    Ok(())
 }
 
 //--------------------------------------------------------------------------------------------
@@ -128,14 +349,296 @@ struct TomlConfig {
     threshold: usize,
     port: usize,
 
+    #[serde(default = "bind_default")]
+    bind: String,
+
     log: String,
     admin: String,
 
-    peers: HashMap<String, TomlPeer>
+    #[serde(default)]
+    strict_meta: bool,
+
+    #[serde(default)]
+    typed_bases: Vec<String>,
+
+    #[serde(default)]
+    strict_attach: bool,
+
+    #[serde(default = "vss_default")]
+    vss: String,
+
+    #[serde(default)]
+    namespaces: IndexMap<String, Vec<String>>,
+
+    #[serde(default)]
+    retention: Retention,
+
+    #[serde(default)]
+    timestamps: Timestamps,
+
+    #[serde(default)]
+    peers: TomlPeers
+}
+
+// The canonical form is the [[peers]] array of tables, where the order is preserved.
+// The indexed table ([peers] 0 = {...}) is deprecated, and only accepted for existing configurations.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum TomlPeers {
+    List(Vec<TomlPeer>),
+    Indexed(HashMap<String, TomlPeer>)
+}
+
+impl Default for TomlPeers {
+    fn default() -> Self {
+        TomlPeers::List(Vec::new())
+    }
+}
+
+impl TomlPeers {
+    fn ordered(&self) -> Result<Vec<&TomlPeer>> {
+        match self {
+            TomlPeers::List(peers) => Ok(peers.iter().collect()),
+            TomlPeers::Indexed(peers) => (0..peers.len())
+                .map(|i| peers.get(&i.to_string()).ok_or_else(|| format!("Expected peer at index {}!", i)))
+                .collect()
+        }
+    }
 }
 
 #[derive(Deserialize, Debug)]
 struct TomlPeer {
     name: String,
     pkey: String
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use core_fpi::{Constraints, FpiError};
+    use core_fpi::ids::*;
+    use core_fpi::authorizations::*;
+
+    fn toml_config(threshold: usize, n: usize, local: bool) -> TomlConfig {
+        let secret = rnd_scalar();
+        let pkey = (secret * G).compress().encode();
+
+        let mut peers = String::new();
+        for i in 0..n {
+            let p_key = if local && i == 0 { pkey.clone() } else { (rnd_scalar() * G).compress().encode() };
+            peers.push_str(&format!("{} = {{ name = \"peer-{}\", pkey = \"{}\" }}\n", i, i, p_key));
+        }
+
+        let content = format!(r#"
+            name = "peer-0"
+            secret = "{}"
+            pkey = "{}"
+            threshold = {}
+            port = 26658
+            log = "info"
+            admin = "s-id:admin"
+
+            [peers]
+            {}
+        "#, secret.encode(), pkey, threshold, peers);
+
+        toml::from_str(&content).unwrap()
+    }
+
+    // single peer configuration, for the handler tests
+    pub(crate) fn test_config() -> Config {
+        Config::load(Paths::new("."), toml_config(0, 1, true)).unwrap()
+    }
+
+    // configuration of every node in the same peer-set, each one with a store under <home>/peer-<i>
+    pub(crate) fn test_network(threshold: usize, n: usize, home: &str) -> Vec<Config> {
+        let secrets: Vec<Scalar> = (0..n).map(|_| rnd_scalar()).collect();
+        let pkeys: Vec<String> = secrets.iter().map(|secret| (secret * G).compress().encode()).collect();
+
+        let mut peers = String::new();
+        for (i, pkey) in pkeys.iter().enumerate() {
+            peers.push_str(&format!("{} = {{ name = \"peer-{}\", pkey = \"{}\" }}\n", i, i, pkey));
+        }
+
+        secrets.iter().zip(pkeys.iter()).enumerate().map(|(i, (secret, pkey))| {
+            let content = format!(r#"
+                name = "peer-{}"
+                secret = "{}"
+                pkey = "{}"
+                threshold = {}
+                port = 26658
+                log = "info"
+                admin = "s-id:admin"
+
+                [peers]
+                {}
+            "#, i, secret.encode(), pkey, threshold, peers);
+
+            let paths = Paths::new(&format!("{}/peer-{}", home, i));
+            Config::load(paths, toml::from_str(&content).unwrap()).unwrap()
+        }).collect()
+    }
+
+    #[test]
+    fn test_typed_bases() {
+        let mut t_cfg = toml_config(0, 1, true);
+        t_cfg.typed_bases = vec!["HealthCare".into()];
+        let cfg = Config::load(Paths::new("."), t_cfg).unwrap();
+
+        assert!(cfg.pseudonym_kid("HealthCare") == "p-master:HealthCare");
+        assert!(cfg.pseudonym_kid("Finance") == PMASTER);
+        assert!(cfg.typed_kids() == vec!["p-master:HealthCare".to_string()]);
+
+        // the typed key-id must be negotiable
+        let mut t_cfg = toml_config(0, 1, true);
+        t_cfg.typed_bases = vec!["HealthCare-Records-Of-Type".into()];
+        let res = Config::load(Paths::new("."), t_cfg);
+        assert!(res.err() == Some("Field Constraint - (kid, max-size = 32, for the type \"HealthCare-Records-Of-Type\")".into()));
+    }
+
+    #[test]
+    fn test_peers_ratio() {
+        assert!(Config::load(Paths::new("."), toml_config(1, 4, true)).is_ok());
+
+        let res = Config::load(Paths::new("."), toml_config(5, 3, true));
+        assert!(res.err() == Some("Configuration error! Expecting #peers >= 3 * t + 1 (#peers = 3, t = 5)".into()));
+
+        let res = Config::load(Paths::new("."), toml_config(1, 3, true));
+        assert!(res.err() == Some("Configuration error! Expecting #peers >= 3 * t + 1 (#peers = 3, t = 1)".into()));
+    }
+
+    #[test]
+    fn test_invalid_keys() {
+        let mut t_cfg = toml_config(0, 1, true);
+        t_cfg.pkey = "0OIl".into();
+        let res = Config::load(Paths::new("."), t_cfg);
+        assert!(res.err() == Some("Invalid pkey: not valid base58".into()));
+
+        let mut t_cfg = toml_config(0, 1, true);
+        t_cfg.secret = (rnd_scalar() * G).compress().as_bytes()[..16].encode();
+        let res = Config::load(Paths::new("."), t_cfg);
+        assert!(res.err() == Some("Invalid secret: expected 32 bytes, found 16".into()));
+
+        let mut t_cfg = toml_config(1, 4, true);
+        if let TomlPeers::Indexed(peers) = &mut t_cfg.peers {
+            peers.get_mut("3").unwrap().pkey = [255u8; 32][..].encode();
+        }
+
+        let res = Config::load(Paths::new("."), t_cfg);
+        assert!(res.err() == Some("Invalid pkey for peer 'peer-3': not a valid RistrettoPoint".into()));
+    }
+
+    #[test]
+    fn test_local_peer() {
+        let res = Config::load(Paths::new("."), toml_config(0, 1, false));
+        assert!(res.err() == Some("Configuration error! Expecting to find the local pkey in the peers list".into()));
+    }
+
+    #[test]
+    fn test_timestamps() {
+        let sig_s = rnd_scalar();
+        let sid = "s-id:shumy";
+
+        let mut subject = Subject::new(sid);
+        let (_, skey) = subject.evolve(sig_s);
+        subject.keys.push(skey.clone());
+
+        let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs() as i64;
+        let profiles = vec!["HealthCare".to_string()];
+        let consent = Consent::sign_at(now - 90, sid, ConsentType::Consent, "s-id:target", &profiles, None, &sig_s, &skey);
+        let msg = Commit::Value(Value::VConsent(consent));
+
+        let cfg = Config::load(Paths::new("."), toml_config(0, 1, true)).unwrap();
+        assert!(cfg.timestamps.commit(&msg) == Duration::from_secs(60));
+        assert!(msg.verify(&subject, cfg.timestamps.commit(&msg)) == Err(FpiError::Timestamp));
+
+        let mut t_cfg = toml_config(0, 1, true);
+        t_cfg.timestamps.consent = Some(120);
+        let cfg = Config::load(Paths::new("."), t_cfg).unwrap();
+        assert!(cfg.timestamps.commit(&msg) == Duration::from_secs(120));
+        assert!(msg.verify(&subject, cfg.timestamps.commit(&msg)) == Ok(()));
+
+        // other message types keep the default
+        let subject_msg = Commit::Value(Value::VSubject(subject.clone()));
+        assert!(cfg.timestamps.commit(&subject_msg) == Duration::from_secs(60));
+    }
+
+    #[test]
+    fn test_paths() {
+        let paths = Paths::new("/var/fedpi/node-0");
+        assert!(paths.home == "/var/fedpi/node-0");
+        assert!(paths.config == "/var/fedpi/node-0/config/app.config.toml");
+        assert!(paths.data == "/var/fedpi/node-0/data");
+        assert!(paths.store == "/var/fedpi/node-0/data/app/store.db");
+
+        // the store is always inside the data folder
+        assert!(paths.store.starts_with(&format!("{}/", paths.data)));
+        assert!(Paths::new("/var/fedpi/node-0/") == paths);
+        assert!(Paths::new(".").store == "./data/app/store.db");
+
+        // a custom home relocates all files together
+        let cfg = Config::load(Paths::new("/opt/node-1"), toml_config(0, 1, true)).unwrap();
+        for path in [&cfg.paths.config, &cfg.paths.data, &cfg.paths.store].iter() {
+            assert!(path.starts_with("/opt/node-1/"));
+        }
+    }
+
+    #[test]
+    fn test_peers_array() {
+        let secret = rnd_scalar();
+        let pkey = (secret * G).compress().encode();
+        let keys: Vec<String> = (0..4).map(|i| if i == 2 { pkey.clone() } else { (rnd_scalar() * G).compress().encode() }).collect();
+
+        let header = format!(r#"
+            name = "peer-2"
+            secret = "{}"
+            pkey = "{}"
+            threshold = 1
+            port = 26658
+            log = "info"
+            admin = "s-id:admin"
+        "#, secret.encode(), pkey);
+
+        let mut list = header.clone();
+        for (i, key) in keys.iter().enumerate() {
+            list.push_str(&format!("\n[[peers]]\nname = \"peer-{}\"\npkey = \"{}\"\n", i, key));
+        }
+
+        // deprecated form, in a different declaration order
+        let mut indexed = header.clone();
+        indexed.push_str("\n[peers]\n");
+        for (i, key) in keys.iter().enumerate().rev() {
+            indexed.push_str(&format!("{} = {{ name = \"peer-{}\", pkey = \"{}\" }}\n", i, i, key));
+        }
+
+        let c_list = Config::load(Paths::new("."), toml::from_str(&list).unwrap()).unwrap();
+        let c_indexed = Config::load(Paths::new("."), toml::from_str(&indexed).unwrap()).unwrap();
+
+        assert!(c_list.index == Some(2));
+        assert!(c_list.peers.iter().map(|p| p.name.as_str()).collect::<Vec<_>>() == vec!["peer-0", "peer-1", "peer-2", "peer-3"]);
+        assert!(c_list.peers_keys == c_indexed.peers_keys);
+        assert!(c_list.peers_hash == c_indexed.peers_hash);
+        assert!(c_list.peers_hash == peers_hash(&c_list.peers_keys));
+    }
+
+    #[test]
+    fn test_peers_missing_index() {
+        let mut t_cfg = toml_config(0, 3, true);
+        if let TomlPeers::Indexed(peers) = &mut t_cfg.peers {
+            peers.remove("1");
+        }
+
+        assert!(Config::load(Paths::new("."), t_cfg).err() == Some("Expected peer at index 1!".into()));
+    }
+
+    #[test]
+    fn test_invalid_options() {
+        let mut t_cfg = toml_config(0, 3, false);
+        t_cfg.log = "debug".into();
+        assert!(Config::load(Paths::new("."), t_cfg).err() == Some("Log level not recognized: \"debug\"".into()));
+
+        let mut t_cfg = toml_config(0, 3, false);
+        t_cfg.vss = "shamir".into();
+        assert!(Config::load(Paths::new("."), t_cfg).err() == Some("VSS scheme not recognized: \"shamir\"".into()));
+    }
 }
\ No newline at end of file
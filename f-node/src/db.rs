@@ -1,6 +1,7 @@
-use indexmap::IndexMap;
+use indexmap::{IndexMap, IndexSet};
 
-use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock, RwLockReadGuard};
+use std::collections::{BTreeSet, HashSet};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::any::Any;
 use std::cell::RefCell;
@@ -9,54 +10,80 @@ use serde::{Serialize, Deserialize};
 use serde::de::DeserializeOwned;
 
 use sled::{Db, IVec, Batch};
-use sha2::{Sha512, Digest};
-use log::info;
+use sha2::{Sha256, Digest};
+use log::{info, error};
 
+use core_fpi::{Result, KeyEncoder};
 use core_fpi::keys::*;
 use core_fpi::messages::*;
 
 pub const STATE: &str = "$state";
 pub const PMASTER: &str = "p-master";       // master-key to derive pseudonyms
 pub const EMASTER: &str = "e-master";       // master-key to derive encryption keys
+pub const PEERS: &str = "peer-set";         // committed peer-set, overrides the peers in the config file
+pub const PEER_CHANGE: &str = "peer-change"; // evidence of the last transition of the peer-set (PeerChange)
 
 //--------------------------------------------------------------------
 // Rules to derive keys. Always use a prefix to avoid security issues, such as data override from different protocols!
 //--------------------------------------------------------------------
 pub fn sid(sid: &str) -> String { format!("sid-{}", sid) }                              // subject-id
 pub fn aid(sid: &str) -> String { format!("aid-{}", sid) }                              // authorizations-id
+pub fn recid(sid: &str) -> String { format!("recid-{}", sid) }                          // recovery-id (public shares of the trustees)
 pub fn mkpid(kid: &str) -> String { format!("mkpid-{}", kid) }                          // master-key-pair-id
+pub fn rrid(sig: &str) -> String { format!("rrid-{}", sig) }                            // record-reference-id (stream of the record)
+pub fn rlid(stream: &str) -> String { format!("rlid-{}", stream) }                      // record-log-id (retained records of the stream)
+pub fn rcid(stream: &str) -> String { format!("rcid-{}", stream) }                      // record-count-id (number of records in the stream)
+pub fn rec(pseudonym: &str, index: usize) -> String { format!("rec-{}-{}", pseudonym, index) } // record-id (record of the pseudonym stream at the position, the last one is the head)
+pub fn rnid(sig: &str) -> String { format!("rnid-{}", sig) }                            // record-number-id (stream position of the record, for cursor queries)
+pub fn rxid(stream: &str) -> String { format!("rxid-{}", stream) }                      // record-closed-id (set when the stream is closed)
+pub fn rtid(height: i64, stream: &str) -> String { format!("rtid-{:020}-{}", height, stream) } // record-time-id (streams with records at the height, ordered by age)
 
 pub fn cid(sid: &str, sig: &str) -> String { format!("cid-{}-{}", sid, sig) }           // consent-id    (evidence)
 pub fn did(sid: &str, sig: &str) -> String { format!("did-{}-{}", sid, sig) }           // disclosure-id (evidence)
+pub fn drid(sid: &str, sig: &str) -> String { format!("drid-{}-{}", sid, sig) }         // disclosure-result-id (served response, until the expiration)
+pub fn dxid(expires: i64, sid: &str, sig: &str) -> String { format!("dxid-{:020}-{}-{}", expires, sid, sig) } // disclosure-expiration-id (drid of the served response, ordered by expiration)
 
 pub fn mkrid(kid: &str, sig: &str) -> String { format!("mkrid-{}-{}", kid, sig) }       // master-key-request-id    (evidence)
 pub fn mkid(kid: &str, sig: &str) -> String { format!("mkid-{}-{}", kid, sig) }         // master-key-id            (evidence)
+pub fn mklid(kid: &str) -> String { format!("mklid-{}", kid) }                          // master-key-last-id       (mkid of the current evidence)
+pub fn mkbid(kid: &str, sig: &str) -> String { format!("mkbid-{}-{}", kid, sig) }       // master-key-blinded-id    (evidence of the Pedersen's votes)
+pub fn mkcid(kid: &str, sig: &str) -> String { format!("mkcid-{}-{}", kid, sig) }       // master-key-commit-id     (Feldman's commitments, revealed after the mkbid)
+pub fn mkfrid(sid: &str, sig: &str) -> String { format!("mkfrid-{}-{}", sid, sig) }     // master-key-refresh-request-id (evidence)
+pub fn mkfid(kid: &str, sig: &str) -> String { format!("mkfid-{}-{}", kid, sig) }       // master-key-refresh-id         (evidence)
+pub fn mksrid(sid: &str, sig: &str) -> String { format!("mksrid-{}-{}", sid, sig) }     // master-key-reshare-request-id (evidence)
+pub fn mksid(kid: &str, sig: &str) -> String { format!("mksid-{}-{}", kid, sig) }       // master-key-reshare-id         (evidence)
+pub fn mkspid(kid: &str) -> String { format!("mkspid-{}", kid) }                        // master-key-reshared-pair-id   (pending, until the peer-set change)
 
 //--------------------------------------------------------------------
 // AppDB
+// Locking:
+//  * deliver and commit are serialized by the tx mutex, the values of a DbTx are only in memory until the commit.
+//  * commit applies the batch and updates the cached app-state while holding the write side of the commit lock.
+//  * queries read from a Snapshot (read side of the commit lock), so they see the values and app-state of a single
+//    committed height, and never the values of an in-progress DbTx.
 //--------------------------------------------------------------------
 pub struct AppDB {
     store: Arc<Db>,
     cache: Arc<Mutex<MemCache>>,
     tx: Mutex<DbTx>,
+    commit_lock: RwLock<()>,
 }
 
 impl AppDB {
-    pub fn new(home: &str) -> Self {
-        let store_file = format!("{}/app/store.db", home);
+    pub fn new(store_file: &str) -> Self {
         let store = Arc::new(Db::open(store_file).unwrap());
 
         // initialize app-state cache
         let state: Option<AppState> = get(store.clone(), STATE);
         let state = state.unwrap_or_else(|| AppState { height: 0, hash: Vec::<u8>::new() });
-        info!("STATE - (height = {:?}, hash = {:?})", state.height, bs58::encode(&state.hash).into_string());
+        info!("STATE - (height = {:?}, hash = {:?})", state.height, state.hash.encode());
 
         let cache = MemCache::new();
-        cache.set(STATE, state);
+        cache.set(STATE, state).expect("Unable to encode structure!");
         let cache = Arc::new(Mutex::new(cache));
 
         let tx = Mutex::new(DbTx::new(store.clone()));
-        Self { store, cache, tx }
+        Self { store, cache, tx, commit_lock: RwLock::new(()) }
     }
 
     pub fn state(&self) -> AppState {
@@ -64,6 +91,13 @@ impl AppDB {
         guard.get(STATE).unwrap()
     }
 
+    // Consistent view of the committed state for the query path. Commits wait until the snapshot is dropped,
+    // so don't hold it across blocking operations and don't request a second one in the same thread.
+    pub fn snapshot(&self) -> Snapshot<'_> {
+        let guard = self.commit_lock.read().unwrap();
+        Snapshot { state: self.state(), store: self.store.clone(), _guard: guard }
+    }
+
     pub fn key(&self, kid: &str) -> Option<MasterKeyPair> {
         let mkpid = mkpid(kid);
 
@@ -78,17 +112,26 @@ impl AppDB {
         match mkey {
             None => None,
             Some(obj) => {
-                guard.set(&mkpid, obj.clone());
+                guard.set(&mkpid, obj.clone()).expect("Unable to encode structure!");
                 Some(obj)
             }
         }
     }
 
+    // replace the key-pair outside of a transaction, also in the cache
+    pub fn set_key(&self, pair: MasterKeyPair) {
+        let mkpid = mkpid(&pair.kid);
+
+        self.set_local(&mkpid, pair.clone());
+        let guard = self.cache.lock().unwrap();
+        guard.set(&mkpid, pair).expect("Unable to encode structure!");
+    }
+
     pub fn get<T: DeserializeOwned + Clone + Send + Sync + 'static>(&self, id: &str) -> Option<T> {
         get(self.store.clone(), id)
     }
 
-    // doesn't include the value in the app-state
+    // doesn't include the value in the app-state, a cached value of the key is invalidated
     pub fn set_local<T: Serialize + Clone + Send + Sync + 'static>(&self, id: &str, value: T)  {
         if id.starts_with('$') {
             panic!("Trying to set a reserved key!");
@@ -96,6 +139,31 @@ impl AppDB {
 
         //TODO: encrypt storage?
         set(self.store.clone(), id, value);
+        self.cache.lock().unwrap().remove(id);
+    }
+
+    // removes a node-local key outside of a transaction, also from the cache
+    pub fn remove_local(&self, id: &str) {
+        if id.starts_with('$') {
+            panic!("Trying to remove a reserved key!");
+        }
+
+        remove(self.store.clone(), id);
+        self.cache.lock().unwrap().remove(id);
+    }
+
+    // Lazy iteration over the stored values, ordered by key. Values are loaded from sled in chunks while iterating.
+    // Always include the separator of the key rules (e.g. "cid-<sid>-"), otherwise the scan may leak into other ids.
+    pub fn scan_prefix(&self, prefix: &str) -> impl Iterator<Item=(String, Vec<u8>)> {
+        scan_prefix(self.store.clone(), prefix)
+    }
+
+    // decoded values of scan_prefix, the prefix must select a single value type
+    pub fn scan<T: DeserializeOwned>(&self, prefix: &str) -> impl Iterator<Item=(String, T)> {
+        self.scan_prefix(prefix).map(|(id, data)| {
+            let obj: T = decode(&data).map_err(|e| format!("Unable to decode value from storage: {}", e)).unwrap();
+            (id, obj)
+        })
     }
 
     pub fn start(&self) {
@@ -105,19 +173,26 @@ impl AppDB {
         }
     }
 
-    pub fn tx(&self) -> MutexGuard<DbTx> {
-        self.tx.lock().unwrap()
+    // the handler name identifies the operation in the abort logs
+    pub fn tx(&self, handler: &'static str) -> MutexGuard<DbTx> {
+        let tx = self.tx.lock().unwrap();
+        *tx.handler.lock().unwrap() = handler;
+        tx
     }
 
     pub fn commit(&self, height: i64) -> AppState {
         let state = self.state();
         let tx = self.tx.lock().unwrap();
+        let _guard = self.commit_lock.write().unwrap();
 
         if tx.pending() {
+            // key-pairs replaced in the transaction (negotiation, refresh or reshare) are reloaded from the store
+            let replaced = tx.local_changes(&mkpid(""));
             let new_state = tx.commit(height, state.hash);
-            
+
             let guard = self.cache.lock().unwrap();
-            guard.set(STATE, new_state.clone());
+            guard.invalidate(&replaced);
+            guard.set(STATE, new_state.clone()).expect("Unable to encode structure!");
 
             new_state
         } else if height != state.height {
@@ -125,7 +200,7 @@ impl AppDB {
             
             set(self.store.clone(), STATE, new_state.clone());
             let guard = self.cache.lock().unwrap();
-            guard.set(STATE, new_state.clone());
+            guard.set(STATE, new_state.clone()).expect("Unable to encode structure!");
 
             new_state
         } else {
@@ -134,6 +209,33 @@ impl AppDB {
     }
 }
 
+//--------------------------------------------------------------------
+// Snapshot
+//--------------------------------------------------------------------
+pub struct Snapshot<'a> {
+    state: AppState,
+    store: Arc<Db>,
+    _guard: RwLockReadGuard<'a, ()>
+}
+
+impl<'a> Snapshot<'a> {
+    pub fn state(&self) -> &AppState {
+        &self.state
+    }
+
+    pub fn get<T: DeserializeOwned>(&self, id: &str) -> Option<T> {
+        get(self.store.clone(), id)
+    }
+
+    // decoded values of the committed keys with the prefix, the prefix must select a single value type
+    pub fn scan<T: DeserializeOwned>(&self, prefix: &str) -> impl Iterator<Item=(String, T)> {
+        scan_prefix(self.store.clone(), prefix).map(|(id, data)| {
+            let obj: T = decode(&data).map_err(|e| format!("Unable to decode value from storage: {}", e)).unwrap();
+            (id, obj)
+        })
+    }
+}
+
 //--------------------------------------------------------------------
 // DbTx
 //--------------------------------------------------------------------
@@ -143,11 +245,15 @@ pub struct DbTx {
     pending: AtomicBool,
     view: Mutex<MemCache>,
     local: Mutex<MemCache>,
+    absent: Mutex<HashSet<String>>,     // keys known to be absent from the store, reset on commit
+    removed: Mutex<IndexSet<String>>,   // keys to remove from the store on commit (in removal order)
+    removed_local: Mutex<IndexSet<String>>, // node-local keys to remove from the store on commit (not in the app-state)
+    handler: Mutex<&'static str>,       // handler of the current operation
 }
 
 impl DbTx {
     fn new(store: Arc<Db>) -> Self {
-        Self { store, pending: AtomicBool::new(false), view: Mutex::new(MemCache::new()), local: Mutex::new(MemCache::new()) }
+        Self { store, pending: AtomicBool::new(false), view: Mutex::new(MemCache::new()), local: Mutex::new(MemCache::new()), absent: Mutex::new(HashSet::new()), removed: Mutex::new(IndexSet::new()), removed_local: Mutex::new(IndexSet::new()), handler: Mutex::new("none") }
     }
 
     pub fn pending(&self) -> bool {
@@ -158,6 +264,10 @@ impl DbTx {
         let guard = self.view.lock().unwrap();
 
         if !guard.contains(id) {
+            if self.absent.lock().unwrap().contains(id) {
+                return false
+            }
+
             return contains(self.store.clone(), id)
         }
 
@@ -172,37 +282,143 @@ impl DbTx {
             return cached
         }
 
+        if self.removed.lock().unwrap().contains(id) {
+            return None
+        }
+
         let value: Option<T> = get(self.store.clone(), id);
         if let Some(value) = &value {
             //may poison the mutex if the encode fails! The transaction should fail.
-            guard.set(id, value.clone());
+            guard.set(id, value.clone()).unwrap_or_else(|e| panic!("{}", self.abort(id, e)));
         }
 
         value
     }
 
-    pub fn set<T: Serialize + Clone + Send + Sync + 'static>(&self, id: &str, value: T) {
+    // same as get, but also remembers an absent key, so a subsequent contains doesn't read the store
+    pub fn get_or_absent<T: Serialize + DeserializeOwned + Clone + Send + Sync + 'static>(&self, id: &str) -> Option<T> {
+        let value: Option<T> = self.get(id);
+        if value.is_none() {
+            self.absent.lock().unwrap().insert(id.into());
+        }
+
+        value
+    }
+
+    pub fn set<T: Serialize + Clone + Send + Sync + 'static>(&self, id: &str, value: T) -> Result<()> {
         if id.starts_with('$') {
             panic!("Trying to set a reserved key!");
         }
         
+        let guard = self.view.lock().unwrap();
+        guard.set(id, value).map_err(|e| self.abort(id, e))?;
+
         self.pending.store(true, Ordering::Relaxed);
-        
+        self.absent.lock().unwrap().remove(id);
+        self.removed.lock().unwrap().shift_remove(id);
+        Ok(())
+    }
+
+    // the removed keys are included in the app-state
+    pub fn remove(&self, id: &str) {
+        if id.starts_with('$') {
+            panic!("Trying to remove a reserved key!");
+        }
+
         let guard = self.view.lock().unwrap();
-        guard.set(id, value);
+        guard.remove(id);
+
+        self.pending.store(true, Ordering::Relaxed);
+        self.absent.lock().unwrap().insert(id.into());
+        self.removed.lock().unwrap().insert(id.into());
     }
 
-    // doesn't include the value in the app-state
-    pub fn set_local<T: Serialize + Clone + Send + Sync + 'static>(&self, id: &str, value: T)  {
+    // removes a node-local key (e.g. "did-"), not included in the app-state
+    pub fn remove_local(&self, id: &str) {
         if id.starts_with('$') {
-            panic!("Trying to set a reserved key!");
+            panic!("Trying to remove a reserved key!");
         }
 
+        let guard = self.local.lock().unwrap();
+        guard.remove(id);
+
         self.pending.store(true, Ordering::Relaxed);
+        self.removed_local.lock().unwrap().insert(id.into());
+    }
+
+    // Stored and pending keys under the prefix (ordered), without the removed ones.
+    // Always include the separator of the key rules (e.g. "cid-<sid>-"), otherwise the scan may leak into other ids.
+    pub fn keys(&self, prefix: &str) -> Vec<String> {
+        let guard = self.view.lock().unwrap();
+        let removed = self.removed.lock().unwrap();
+
+        let mut keys: BTreeSet<String> = scan_prefix(self.store.clone(), prefix).map(|(id, _)| id).collect();
+        keys.extend(guard.keys(prefix));
+        keys.into_iter().filter(|id| !removed.contains(id)).collect()
+    }
+
+    // pending node-local keys under the prefix, set or removed in the transaction
+    fn local_changes(&self, prefix: &str) -> Vec<String> {
+        let mut keys = self.local.lock().unwrap().keys(prefix);
+        keys.extend(self.removed_local.lock().unwrap().iter().filter(|id| id.starts_with(prefix)).cloned());
+        keys
+    }
+
+    // same as keys, but for the node-local values
+    fn local_keys(&self, prefix: &str) -> Vec<String> {
+        let guard = self.local.lock().unwrap();
+        let removed = self.removed_local.lock().unwrap();
+
+        let mut keys: BTreeSet<String> = scan_prefix(self.store.clone(), prefix).map(|(id, _)| id).collect();
+        keys.extend(guard.keys(prefix));
+        keys.into_iter().filter(|id| !removed.contains(id)).collect()
+    }
+
+    // removes all keys under the prefixes, returning the number of removed keys (tombstones in the app-state)
+    pub fn erase(&self, prefixes: &[&str]) -> usize {
+        let mut n_keys = 0;
+        for prefix in prefixes.iter() {
+            for id in self.keys(prefix) {
+                self.remove(&id);
+                n_keys += 1;
+            }
+        }
+
+        n_keys
+    }
+
+    // removes all node-local keys under the prefixes, returning the number of removed keys (not in the app-state)
+    pub fn erase_local(&self, prefixes: &[&str]) -> usize {
+        let mut n_keys = 0;
+        for prefix in prefixes.iter() {
+            for id in self.local_keys(prefix) {
+                self.remove_local(&id);
+                n_keys += 1;
+            }
+        }
+
+        n_keys
+    }
+
+    // doesn't include the value in the app-state
+    pub fn set_local<T: Serialize + Clone + Send + Sync + 'static>(&self, id: &str, value: T) -> Result<()> {
+        if id.starts_with('$') {
+            panic!("Trying to set a reserved key!");
+        }
 
         //TODO: encrypt storage?
         let guard = self.local.lock().unwrap();
-        guard.set(id, value);
+        guard.set(id, value).map_err(|e| self.abort(id, e))?;
+
+        self.pending.store(true, Ordering::Relaxed);
+        self.removed_local.lock().unwrap().shift_remove(id);
+        Ok(())
+    }
+
+    fn abort(&self, id: &str, err: String) -> String {
+        let handler = *self.handler.lock().unwrap();
+        error!("TX-ABORT - (handler = {:?}, id = {:?}) - {}", handler, id, err);
+        format!("Transaction aborted on {:?}: {}", id, err)
     }
 
     fn commit(&self, height: i64, prev: Vec<u8>) -> AppState {
@@ -211,9 +427,15 @@ impl DbTx {
         // returns and clears all MemCache data
         let global_data = self.view.lock().unwrap().data();
         let local_data = self.local.lock().unwrap().data();
+        let removed: Vec<String> = self.removed.lock().unwrap().drain(..).collect();
+        let removed_local: Vec<String> = self.removed_local.lock().unwrap().drain(..).collect();
+        let n_values = global_data.len() + local_data.len() + removed.len() + removed_local.len();
+        self.absent.lock().unwrap().clear();
 
         let mut batch = Batch::default();
-        let mut hasher = Sha512::new();
+
+        // tendermint expects a 32 bytes app-hash
+        let mut hasher = Sha256::new();
         hasher.input(prev);
 
         // update global tx data
@@ -227,13 +449,27 @@ impl DbTx {
             batch.insert(&key as &str, value);
         }
 
+        // remove global tx data, the keys are part of the app-state
+        for key in removed.into_iter() {
+            hasher.input(&key);
+            batch.remove(&key as &str);
+        }
+
+        // remove local tx data
+        for key in removed_local.into_iter() {
+            batch.remove(&key as &str);
+        }
+
         // update app-state
         let new_state = AppState { height, hash: hasher.result().to_vec() };
-        let state_data = encode(&new_state).expect("Unable to encode structure!");;
+        let state_data = encode(&new_state).expect("Unable to encode structure!");
         batch.insert(STATE, state_data);
 
         // commit batch
-        self.store.apply_batch(batch).unwrap();
+        self.store.apply_batch(batch).unwrap_or_else(|e| {
+            error!("TX-ABORT - (height = {:?}, #values = {:?}) - {}", height, n_values, e);
+            panic!("Unable to apply the transaction batch: {}", e)
+        });
         self.store.flush().map_err(|e| format!("Unable to flush: {}", e)).unwrap();
 
         self.pending.store(false, Ordering::Relaxed);
@@ -278,13 +514,30 @@ impl MemCache {
         }
     }
 
-    fn set<T: Serialize + Clone + Send + Sync + 'static>(&self, id: &str, value: T) {
-        let data = encode(&value).expect("Unable to encode structure!");
+    fn set<T: Serialize + Clone + Send + Sync + 'static>(&self, id: &str, value: T) -> Result<()> {
+        let data = encode(&value)?;
         let mut map = self.data_cache.borrow_mut();
         map.insert(id.into(), data);
 
         let mut map = self.obj_cache.borrow_mut();
         map.insert(id.into(), Box::new(value));
+        Ok(())
+    }
+
+    fn keys(&self, prefix: &str) -> Vec<String> {
+        let map = self.data_cache.borrow();
+        map.keys().filter(|id| id.starts_with(prefix)).cloned().collect()
+    }
+
+    fn remove(&self, id: &str) {
+        self.data_cache.borrow_mut().shift_remove(id);
+        self.obj_cache.borrow_mut().shift_remove(id);
+    }
+
+    fn invalidate(&self, ids: &[String]) {
+        for id in ids.iter() {
+            self.remove(id);
+        }
     }
 
     fn data(&self) -> IndexMap<String, Vec<u8>> {
@@ -301,7 +554,7 @@ impl MemCache {
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct AppState {
     pub height: i64,
-    pub hash: Vec<u8>
+    pub hash: Vec<u8>                       // SHA-256 chain of the committed values (32 bytes, empty before the first commit)
 }
 
 fn contains(db: Arc<Db>, id: &str) -> bool {
@@ -309,11 +562,24 @@ fn contains(db: Arc<Db>, id: &str) -> bool {
 }
 
 fn set<T: Serialize>(db: Arc<Db>, id: &str, value: T) {
-    let data = encode(&value).expect("Unable to encode structure!");
+    let data = encode(&value).unwrap_or_else(|e| panic!("Unable to encode structure for {:?}: {}", id, e));
     db.insert(id, data).map_err(|e| format!("Unable to set value in storage: {}", e)).unwrap();
     db.flush().map_err(|e| format!("Unable to flush: {}", e)).unwrap();
 }
 
+fn remove(db: Arc<Db>, id: &str) {
+    db.remove(id).map_err(|e| format!("Unable to remove value from storage: {}", e)).unwrap();
+    db.flush().map_err(|e| format!("Unable to flush: {}", e)).unwrap();
+}
+
+fn scan_prefix(db: Arc<Db>, prefix: &str) -> impl Iterator<Item=(String, Vec<u8>)> {
+    db.scan_prefix(prefix).map(|item| {
+        let (id, data) = item.map_err(|e| format!("Unable to scan values from storage: {}", e)).unwrap();
+        let id = String::from_utf8(id.to_vec()).map_err(|e| format!("Unable to decode key from storage: {}", e)).unwrap();
+        (id, data.to_vec())
+    })
+}
+
 fn get<T: DeserializeOwned>(db: Arc<Db>, id: &str) -> Option<T> {
     let res: Option<IVec> = db.get(id)
         .map_err(|e| format!("Unable to get value from storage: {}", e)).unwrap();
@@ -325,4 +591,300 @@ fn get<T: DeserializeOwned>(db: Arc<Db>, id: &str) -> Option<T> {
             Some(obj)
         }
     }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core_fpi::uuid;
+
+    // captures the log lines of the current thread
+    struct TestLogger;
+    static LOGGER: TestLogger = TestLogger;
+    thread_local!(static LINES: RefCell<Vec<String>> = RefCell::new(Vec::new()));
+
+    impl log::Log for TestLogger {
+        fn enabled(&self, _: &log::Metadata) -> bool { true }
+        fn log(&self, record: &log::Record) {
+            LINES.with(|lines| lines.borrow_mut().push(format!("{}", record.args())));
+        }
+        fn flush(&self) {}
+    }
+
+    #[derive(Clone)]
+    struct Unencodable;
+
+    impl Serialize for Unencodable {
+        fn serialize<S: serde::Serializer>(&self, _: S) -> std::result::Result<S::Ok, S::Error> {
+            Err(serde::ser::Error::custom("unencodable value"))
+        }
+    }
+
+    fn stores(n: usize) -> (std::path::PathBuf, Vec<AppDB>) {
+        let path = std::env::temp_dir().join(format!("fedpi-db-{}", uuid()));
+        let stores = (0..n).map(|i| {
+            let store = AppDB::new(path.join(format!("store-{}", i)).to_str().unwrap());
+            {
+                let tx = store.tx("test");
+                tx.set("x-1", 1u32).unwrap();
+                tx.set("x-2", 2u32).unwrap();
+                tx.set_local("l-1", 1u32).unwrap();
+            }
+            store.commit(1);
+            store
+        }).collect();
+
+        (path, stores)
+    }
+
+    #[test]
+    fn test_app_hash() {
+        let (path, stores) = stores(2);
+
+        // same blocks in all peers, the hash is chained and truncated to what tendermint expects
+        let first: Vec<AppState> = stores.iter().map(|store| store.state()).collect();
+        assert!(first[0].hash.len() == 32 && first[0].hash == first[1].hash);
+
+        for store in stores.iter() {
+            store.tx("test").set("x-3", 3u32).unwrap();
+        }
+
+        let second: Vec<AppState> = stores.iter().map(|store| store.commit(2)).collect();
+        assert!(second[0].hash.len() == 32 && second[0].hash == second[1].hash);
+        assert!(second[0].hash != first[0].hash);
+
+        // an empty block keeps the hash, and the state survives a restart
+        assert!(stores[0].commit(3).hash == second[0].hash);
+        drop(stores);
+
+        let store = AppDB::new(path.join("store-0").to_str().unwrap());
+        assert!(store.state().height == 3 && store.state().hash == second[0].hash);
+
+        drop(store);
+        std::fs::remove_dir_all(&path).ok();
+    }
+
+    #[test]
+    fn test_cached_reads() {
+        let (path, stores) = stores(1);
+        let store = &stores[0];
+
+        // the store is changed behind the transaction, only a read of the store would see it
+        {
+            let tx = store.tx("test");
+            assert!(tx.get::<u32>("x-1") == Some(1));
+            assert!(tx.get_or_absent::<u32>("x-9").is_none());
+
+            store.remove_local("x-1");
+            store.set_local("x-9", 9u32);
+
+            // the contains after a get is answered by the transaction caches
+            assert!(tx.contains("x-1"));
+            assert!(!tx.contains("x-9"));
+
+            // a plain get doesn't remember the absent key
+            assert!(tx.get::<u32>("x-8").is_none());
+            store.set_local("x-8", 8u32);
+            assert!(tx.contains("x-8"));
+        }
+
+        drop(stores);
+        std::fs::remove_dir_all(&path).ok();
+    }
+
+    #[test]
+    fn test_scan_prefix() {
+        let (path, stores) = stores(1);
+        let store = &stores[0];
+        {
+            // inserted out of order, with ids that share the start of the prefix
+            let tx = store.tx("test");
+            for (sid, n) in [("s-id:b", 2u32), ("s-id:a", 1u32), ("s-id:c", 3u32), ("s-id:ab", 4u32)].iter() {
+                tx.set(&format!("cid-{}-target", sid), *n).unwrap();
+            }
+        }
+        store.commit(2);
+
+        // ordered by key, the separator selects a single id
+        let all: Vec<(String, u32)> = store.scan("cid-").collect();
+        assert!(all.iter().map(|(_, n)| *n).collect::<Vec<_>>() == vec![1, 4, 2, 3]);
+        assert!(all[0].0 == "cid-s-id:a-target");
+
+        let one: Vec<(String, u32)> = store.scan("cid-s-id:a-").collect();
+        assert!(one == vec![("cid-s-id:a-target".to_string(), 1)]);
+
+        // the raw values, and the same values from a snapshot
+        let raw: Vec<(String, Vec<u8>)> = store.scan_prefix("x-").collect();
+        assert!(raw.len() == 2 && decode::<u32>(&raw[1].1) == Ok(2));
+
+        let snap = store.snapshot();
+        assert!(snap.scan::<u32>("cid-").collect::<Vec<_>>() == all);
+        assert!(snap.scan::<u32>("none-").next().is_none());
+        drop(snap);
+
+        drop(stores);
+        std::fs::remove_dir_all(&path).ok();
+    }
+
+    #[test]
+    fn test_abort_log() {
+        log::set_logger(&LOGGER).ok();
+        log::set_max_level(log::LevelFilter::Error);
+
+        let (path, stores) = stores(1);
+        {
+            let tx = stores[0].tx("TestHandler");
+            let res = tx.set("x-bad", Unencodable);
+            assert!(res.err() == Some("Transaction aborted on \"x-bad\": Unable to encode structure!".into()));
+
+            let res = tx.set_local("l-bad", Unencodable);
+            assert!(res.err() == Some("Transaction aborted on \"l-bad\": Unable to encode structure!".into()));
+
+            // the failed values are not part of the transaction
+            assert!(!tx.pending() && !tx.contains("x-bad"));
+        }
+
+        // the abort logs name the handler and the failing key
+        let lines = LINES.with(|lines| lines.borrow().clone());
+        let aborts: Vec<&String> = lines.iter().filter(|line| line.starts_with("TX-ABORT")).collect();
+        assert!(aborts.len() == 2);
+        assert!(*aborts[0] == "TX-ABORT - (handler = \"TestHandler\", id = \"x-bad\") - Unable to encode structure!");
+        assert!(*aborts[1] == "TX-ABORT - (handler = \"TestHandler\", id = \"l-bad\") - Unable to encode structure!");
+
+        drop(stores);
+        std::fs::remove_dir_all(&path).ok();
+    }
+
+    #[test]
+    fn test_concurrent_reads() {
+        let (path, stores) = stores(1);
+        let store = Arc::new(stores.into_iter().next().unwrap());
+
+        // values and app-state of a single committed height
+        let read = |store: &AppDB| {
+            let snap = store.snapshot();
+            (snap.state().height, snap.get::<u32>("x-1").unwrap(), snap.get::<u32>("x-2").unwrap())
+        };
+
+        // the pending values of a transaction are not visible
+        {
+            let tx = store.tx("test");
+            tx.set("x-1", 10u32).unwrap();
+            tx.set("x-2", 20u32).unwrap();
+
+            let readers: Vec<_> = (0..4).map(|_| {
+                let store = store.clone();
+                std::thread::spawn(move || read(&store))
+            }).collect();
+
+            for reader in readers {
+                assert!(reader.join().unwrap() == (1, 1, 2));
+            }
+        }
+
+        // the commit waits for an open snapshot
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let reader = {
+            let store = store.clone();
+            std::thread::spawn(move || {
+                let snap = store.snapshot();
+                sender.send(()).unwrap();
+                std::thread::sleep(std::time::Duration::from_millis(200));
+                (snap.state().height, snap.get::<u32>("x-1").unwrap(), snap.get::<u32>("x-2").unwrap())
+            })
+        };
+
+        // and the readers during the commit see one of the states, never a mix of both
+        let running = Arc::new(AtomicBool::new(true));
+        let observers: Vec<_> = (0..4).map(|_| {
+            let (store, running) = (store.clone(), running.clone());
+            std::thread::spawn(move || {
+                let mut seen = Vec::new();
+                while running.load(Ordering::Relaxed) {
+                    seen.push(read(&store));
+                }
+                seen
+            })
+        }).collect();
+
+        receiver.recv().unwrap();
+        assert!(store.commit(2).height == 2);
+        assert!(reader.join().unwrap() == (1, 1, 2));
+
+        running.store(false, Ordering::Relaxed);
+        for observer in observers {
+            assert!(observer.join().unwrap().iter().all(|values| *values == (1, 1, 2) || *values == (2, 10, 20)));
+        }
+
+        assert!(read(&store) == (2, 10, 20));
+
+        drop(store);
+        std::fs::remove_dir_all(&path).ok();
+    }
+
+    #[test]
+    fn test_remove() {
+        let (path, stores) = stores(3);
+
+        // same block, with and without the removal
+        for (i, store) in stores.iter().enumerate() {
+            let tx = store.tx("test");
+            tx.set("x-3", 3u32).unwrap();
+            if i < 2 {
+                tx.remove("x-1");
+
+                // removed in the transaction, before the commit
+                assert!(!tx.contains("x-1"));
+                assert!(tx.get::<u32>("x-1").is_none());
+                assert!(tx.keys("x-") == vec!["x-2".to_string(), "x-3".to_string()]);
+            }
+        }
+
+        let states: Vec<AppState> = stores.iter().map(|store| store.commit(2)).collect();
+        assert!(states[0].hash == states[1].hash);
+        assert!(states[0].hash != states[2].hash);
+
+        assert!(stores[0].get::<u32>("x-1").is_none());
+        assert!(stores[2].get::<u32>("x-1") == Some(1));
+        {
+            let tx = stores[0].tx("test");
+            assert!(!tx.contains("x-1"));
+            assert!(tx.get::<u32>("x-1").is_none());
+        }
+
+        // a set after the remove restores the key
+        {
+            let tx = stores[0].tx("test");
+            tx.remove("x-2");
+            tx.set("x-2", 4u32).unwrap();
+        }
+        stores[0].commit(3);
+        assert!(stores[0].get::<u32>("x-2") == Some(4));
+
+        drop(stores);
+        std::fs::remove_dir_all(&path).ok();
+    }
+
+    #[test]
+    fn test_remove_local() {
+        let (path, stores) = stores(2);
+
+        // node-local removals are not in the app-state
+        for (i, store) in stores.iter().enumerate() {
+            let tx = store.tx("test");
+            tx.set("x-3", 3u32).unwrap();
+            if i == 0 {
+                tx.remove_local("l-1");
+            }
+        }
+
+        let states: Vec<AppState> = stores.iter().map(|store| store.commit(2)).collect();
+        assert!(states[0].hash == states[1].hash);
+        assert!(stores[0].get::<u32>("l-1").is_none());
+        assert!(stores[1].get::<u32>("l-1") == Some(1));
+
+        drop(stores);
+        std::fs::remove_dir_all(&path).ok();
+    }
 }
\ No newline at end of file
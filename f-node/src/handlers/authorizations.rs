@@ -4,16 +4,34 @@ use log::info;
 use core_fpi::Result;
 use core_fpi::ids::*;
 use core_fpi::authorizations::*;
+use core_fpi::messages::*;
 
 use crate::db::*;
+use crate::config::Config;
 
 pub struct AuthorizationHandler {
+    cfg: Arc<Config>,
     store: Arc<AppDB>
 }
 
 impl AuthorizationHandler {
-    pub fn new(store: Arc<AppDB>) -> Self {
-        Self { store }
+    pub fn new(cfg: Arc<Config>, store: Arc<AppDB>) -> Self {
+        Self { cfg, store }
+    }
+
+    pub fn query(&mut self, snap: &Snapshot, req: ConsentsQuery) -> Result<Vec<u8>> {
+        info!("QUERY-CONSENTS - (sid = {:?})", req.sid);
+
+        // the prefix also selects subjects with a sid extending the requested one
+        let mut consents: Vec<Consent> = snap.scan::<Consent>(&cid(&req.sid, ""))
+            .map(|(_, consent)| consent)
+            .filter(|consent| consent.sid == req.sid)
+            .collect();
+        consents.sort_by_key(|consent| consent.sig.sig.timestamp);
+
+        let res = ConsentsResult::sign(req.sig.id(), consents, &self.cfg.secret, &self.cfg.pkey, self.cfg.index()?);
+        let msg = Response::QResult(QResult::QConsentsResult(res));
+        encode(&msg)
     }
 
     pub fn deliver(&mut self, consent: Consent) -> Result<()> {
@@ -25,7 +43,7 @@ impl AuthorizationHandler {
         let aid = aid(&consent.sid);
 
         // ---------------transaction---------------
-        let tx = self.store.tx();
+        let tx = self.store.tx("AuthorizationHandler");
             // check constraints
             let subject: Subject = tx.get(&sid).ok_or("Subject not found!")?;
             consent.check(&subject)?;
@@ -47,8 +65,42 @@ impl AuthorizationHandler {
                 ConsentType::Revoke => auths.revoke(&consent)
             }
 
-            tx.set(&cid, consent);
-            tx.set(&aid, auths);
+            tx.set(&cid, consent)?;
+            tx.set(&aid, auths)?;
+        Ok(())
+    }
+
+    pub fn deliver_bundle(&mut self, bundle: ProfileConsent) -> Result<()> {
+        info!("DELIVER-PROFILE-CONSENT -  (sid = {:?}, #profiles = {:?}, auth = {:?})", bundle.update.sid, bundle.update.profiles.len(), bundle.consent.target);
+        let consent = &bundle.consent;
+        let tid = sid(&consent.target);
+        let sid = sid(&consent.sid);
+
+        let cid = cid(&consent.sid, consent.sig.id());
+        let aid = aid(&consent.sid);
+
+        // ---------------transaction---------------
+        let tx = self.store.tx("AuthorizationHandler");
+            // check both messages before any change, the consent may reference the profiles of the update
+            let current: Subject = tx.get(&sid).ok_or("Subject not found!")?;
+            let merged = bundle.check(&current)?;
+
+            // avoid consent override
+            if tx.contains(&cid) {
+                return Err("Consent already exists!".into())
+            }
+
+            // search for target subject and check
+            if !tx.contains(&tid) {
+                return Err("No target subject found!".into())
+            }
+
+            let mut auths: Authorizations = tx.get(&aid).unwrap_or_else(|| Authorizations::new());
+            auths.authorize(consent);
+
+            tx.set(&sid, merged)?;
+            tx.set(&cid, bundle.consent)?;
+            tx.set(&aid, auths)?;
         Ok(())
     }
 }
@@ -1,4 +1,5 @@
 use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 use log::info;
 
 use core_fpi::Result;
@@ -20,27 +21,68 @@ impl DisclosureHandler {
         Self { cfg, store }
     }
 
-    pub fn request(&mut self, disclose: DiscloseRequest) -> Result<Vec<u8>> {
+    pub fn request(&mut self, snap: &Snapshot, disclose: DiscloseRequest) -> Result<Vec<u8>> {
         info!("REQUEST-DISCLOSE - (sid = {:?}, target = {:?}, #profiles = {:?})", disclose.sid, disclose.target, disclose.profiles.len());
         let tid = sid(&disclose.target);
         let aid = aid(&disclose.target);
+        let index = self.cfg.index()?;
+
+        // the committed snapshot used to serve the request
+        let height = snap.state().height;
+
+        // A replayed request (only possible within the timestamp window) is served with the first response, so the shares
+        // are computed once per signed request. The client signs a new request to retry on a different height.
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+        self.expire(now);
+
+        let did = did(&disclose.sid, disclose.sig.id());
+        let drid = drid(&disclose.sid, disclose.sig.id());
+        let served: Option<Vec<u8>> = self.store.get(&drid);
+        if let Some(data) = served {
+            info!("REQUEST-DISCLOSE - Replayed request (session = {:?}, height = {:?})", disclose.sig.id(), height);
+            return Ok(data)
+        }
 
-        let pmkey = self.store.key(PMASTER).ok_or("Pseudonym master-key unavailable!")?;
         let emkey = self.store.key(EMASTER).ok_or("Encryption master-key unavailable!")?;
 
-        let target: Subject = self.store.get(&tid).ok_or("No target subject found!")?;
-        let auths: Authorizations = self.store.get(&aid).ok_or("No authorizations found for target!")?;
+        let target: Subject = snap.get(&tid).ok_or("No target subject found!")?;
+        let auths: Authorizations = snap.get(&aid).unwrap_or_else(Authorizations::new);
+
+        // verify if the client has authorization to disclose all profiles (or the authorized subset, for partial requests)
+        let denied = disclose.authorize(&auths)?;
 
-        // verify if the client has authorization to disclose profiles
         let mut dkeys = DiscloseKeys::new();
         for typ in disclose.profiles.iter() {
-            if disclose.sid != disclose.target && !auths.is_authorized(&disclose.sid, typ) {
-                return Err(format!("Subject has not authorization to disclose profile: {}", typ))
+            if denied.contains(typ) {
+                dkeys.deny(typ);
+                continue
             }
 
-            let prof = target.profiles.get(typ).ok_or("No profile found, but there is an authorization!")?;
+            // the target may have removed the profile after the consent
+            let prof = match target.profiles.get(typ) {
+                Some(prof) => prof,
+                None => {
+                    info!("REQUEST-DISCLOSE - Authorized profile not found (target = {:?}, type = {:?})", disclose.target, typ);
+                    dkeys.absent(typ);
+                    continue
+                }
+            };
+
+            // a type with its own base requires the negotiation of the typed key
+            let pmkey = self.store.key(&self.cfg.pseudonym_kid(typ)).ok_or("Pseudonym master-key unavailable!")?;
             for (_, loc) in prof.locations.iter() {
-                for pkey in loc.chain.iter() {
+                for (i, pkey) in loc.chain.iter().enumerate() {
+                    if let Some(to) = &pkey.migrated_to {
+                        // the migration pointer reuses the previous key, there is no new stream
+                        dkeys.migrate(&typ, &loc.lurl, to);
+                        continue
+                    }
+
+                    // a re-anchored key also reuses the previous key
+                    if i > 0 && loc.chain[i - 1].pkey == pkey.pkey {
+                        continue
+                    }
+
                     let pseudo_i = &pmkey.share * &pkey.pkey;
                     
                     let encryp_i = match pkey.encrypted {
@@ -51,18 +93,229 @@ impl DisclosureHandler {
                         false => None
                     };
 
-                    dkeys.put(&typ, &loc.lurl, (pseudo_i.Yi, encryp_i));
+                    dkeys.put(&typ, &loc.lurl, pseudo_i.Yi, encryp_i);
                 }
             }
         }
 
-        let res = DiscloseResult::sign(&disclose.sig.sig.encoded, dkeys, &self.cfg.secret, &self.cfg.pkey, self.cfg.index);
+        let res = DiscloseResult::sign(&disclose.sig.sig.encoded, height, dkeys, &self.cfg.secret, &self.cfg.pkey, index);
         let msg = Response::QResult(QResult::QDiscloseResult(res));
+        let data = encode(&msg)?;
         
-        // store local evidence
-        let did = did(&disclose.sid, disclose.sig.id());
+        // store local evidence and the served response, until the request is out of the timestamp window
+        let window = self.cfg.timestamps.disclose_window().as_secs() as i64;
+        let expires = disclose.sig.sig.timestamp.saturating_add(window);
+        self.store.set_local(&dxid(expires, &disclose.sid, disclose.sig.id()), drid.clone());
+        self.store.set_local(&drid, data.clone());
         self.store.set_local(&did, disclose);
         
-        encode(&msg)
+        Ok(data)
+    }
+
+    // removes the served responses of the requests that are no longer accepted, the expiration index is ordered by time
+    fn expire(&self, now: i64) {
+        let prefix = "dxid-";
+        for (id, data) in self.store.scan_prefix(prefix) {
+            // "dxid-<expires:020>-<sid>-<sig>", these unwrap() should never fail, or it's a serious code bug!
+            let expires: i64 = id[prefix.len()..prefix.len() + 20].parse().unwrap();
+            if expires >= now {
+                break
+            }
+
+            let drid: String = decode(&data).unwrap();
+            self.store.remove_local(&drid);
+            self.store.remove_local(&id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core_fpi::{rnd_scalar, uuid, G};
+    use core_fpi::shares::Share;
+    use core_fpi::keys::MasterKeyPair;
+    use crate::config::tests::test_config;
+
+    #[test]
+    fn test_replayed_request() {
+        let path = std::env::temp_dir().join(format!("fedpi-disclosures-{}", uuid()));
+        let store = Arc::new(AppDB::new(path.to_str().unwrap()));
+        let mut handler = DisclosureHandler::new(Arc::new(test_config()), store.clone());
+
+        let sig_s = rnd_scalar();
+        let mut target = Subject::new("s-id:target");
+        let (_, tkey) = target.evolve(sig_s);
+
+        let mut profile = Profile::new("HealthCare");
+        profile.push(profile.evolve("s-id:target", "https://profile-url.org", false, &sig_s, &tkey).1);
+        target.push(profile).keys.push(tkey.clone());
+
+        let profiles = vec!["HealthCare".to_string()];
+        let consent = Consent::sign("s-id:target", ConsentType::Consent, "s-id:shumy", &profiles, &sig_s, &tkey);
+        let mut auths = Authorizations::new();
+        auths.authorize(&consent);
+
+        {
+            let tx = store.tx("test");
+            tx.set(&sid("s-id:target"), target).unwrap();
+            tx.set(&aid("s-id:target"), auths).unwrap();
+        }
+        store.commit(1);
+
+        let pair = |kid: &str| MasterKeyPair { kid: kid.to_string(), share: Share { i: 1, yi: rnd_scalar() }, public: rnd_scalar() * G };
+        for kid in [PMASTER, EMASTER].iter() {
+            store.set_key(pair(kid));
+        }
+
+        let (_, skey) = Subject::new("s-id:shumy").evolve(sig_s);
+        let disclose = DiscloseRequest::sign("s-id:shumy", "s-id:target", &profiles, false, &sig_s, &skey);
+        let first = handler.request(&store.snapshot(), disclose.clone()).unwrap();
+
+        // the shares are not recomputed for a replayed request, even on a new height
+        store.set_key(pair(PMASTER));
+        {
+            let tx = store.tx("test");
+            tx.set("test-height", 2).unwrap();
+        }
+        store.commit(2);
+
+        let snap = store.snapshot();
+        assert!(snap.state().height == 2);
+        assert!(handler.request(&snap, disclose.clone()) == Ok(first.clone()));
+
+        // a new request is computed with the current state
+        let other = DiscloseRequest::sign("s-id:shumy", "s-id:target", &profiles, false, &sig_s, &skey);
+        let second = handler.request(&snap, other).unwrap();
+        assert!(second != first);
+
+        // the served responses expire with the timestamp window of the request
+        let window = test_config().timestamps.disclose_window().as_secs() as i64;
+        handler.expire(disclose.sig.sig.timestamp + window);
+        assert!(store.get::<Vec<u8>>(&drid(&disclose.sid, disclose.sig.id())).is_some());
+
+        handler.expire(disclose.sig.sig.timestamp + window + 1);
+        assert!(store.get::<Vec<u8>>(&drid(&disclose.sid, disclose.sig.id())).is_none());
+        assert!(store.get::<String>(&dxid(disclose.sig.sig.timestamp + window, &disclose.sid, disclose.sig.id())).is_none());
+        assert!(store.get::<DiscloseRequest>(&did(&disclose.sid, disclose.sig.id())).is_some());
+
+        drop(snap);
+        drop(handler);
+        drop(store);
+        std::fs::remove_dir_all(&path).ok();
+    }
+
+    #[test]
+    fn test_absent_profiles() {
+        let path = std::env::temp_dir().join(format!("fedpi-disclosures-{}", uuid()));
+        let store = Arc::new(AppDB::new(path.to_str().unwrap()));
+        let mut handler = DisclosureHandler::new(Arc::new(test_config()), store.clone());
+
+        let sig_s = rnd_scalar();
+        let mut target = Subject::new("s-id:target");
+        let (_, tkey) = target.evolve(sig_s);
+
+        let mut profile = Profile::new("HealthCare");
+        profile.push(profile.evolve("s-id:target", "https://profile-url.org", false, &sig_s, &tkey).1);
+        target.push(profile).keys.push(tkey.clone());
+
+        // consented profiles, but "Finance" was removed from the target
+        let profiles = vec!["Finance".to_string(), "HealthCare".to_string()];
+        let consent = Consent::sign("s-id:target", ConsentType::Consent, "s-id:shumy", &profiles, &sig_s, &tkey);
+        let mut auths = Authorizations::new();
+        auths.authorize(&consent);
+
+        {
+            let tx = store.tx("test");
+            tx.set(&sid("s-id:target"), target).unwrap();
+            tx.set(&aid("s-id:target"), auths).unwrap();
+        }
+        store.commit(1);
+
+        for kid in [PMASTER, EMASTER].iter() {
+            let pair = MasterKeyPair { kid: kid.to_string(), share: Share { i: 1, yi: rnd_scalar() }, public: rnd_scalar() * G };
+            store.set_local(&mkpid(kid), pair);
+        }
+
+        let (_, skey) = Subject::new("s-id:shumy").evolve(sig_s);
+        let disclose = DiscloseRequest::sign("s-id:shumy", "s-id:target", &profiles, false, &sig_s, &skey);
+
+        let data = handler.request(&store.snapshot(), disclose).unwrap();
+        match decode::<Response>(&data).unwrap() {
+            Response::QResult(QResult::QDiscloseResult(res)) => {
+                assert!(res.keys.keys.contains_key("HealthCare"));
+                assert!(res.keys.absent == vec!["Finance".to_string()]);
+                assert!(res.keys.denied.is_empty());
+                assert!(res.keys.constains(&profiles));
+            },
+            _ => panic!("Expecting a disclose result!")
+        }
+
+        drop(handler);
+        drop(store);
+        std::fs::remove_dir_all(&path).ok();
+    }
+
+    #[test]
+    fn test_typed_base() {
+        let path = std::env::temp_dir().join(format!("fedpi-disclosures-{}", uuid()));
+        let store = Arc::new(AppDB::new(path.to_str().unwrap()));
+
+        let mut cfg = test_config();
+        cfg.typed_bases = vec!["HealthCare".into()];
+        let mut handler = DisclosureHandler::new(Arc::new(cfg), store.clone());
+
+        let sig_s = rnd_scalar();
+        let mut target = Subject::new("s-id:target");
+        let (_, tkey) = target.evolve(sig_s);
+
+        let profiles = vec!["Finance".to_string(), "HealthCare".to_string()];
+        for typ in profiles.iter() {
+            let mut profile = Profile::new(typ);
+            profile.push(profile.evolve("s-id:target", "https://profile-url.org", false, &sig_s, &tkey).1);
+            target.push(profile);
+        }
+        target.keys.push(tkey.clone());
+
+        let consent = Consent::sign("s-id:target", ConsentType::Consent, "s-id:shumy", &profiles, &sig_s, &tkey);
+        let mut auths = Authorizations::new();
+        auths.authorize(&consent);
+
+        let pkeys: Vec<_> = profiles.iter().map(|typ| target.profiles[typ].locations["https://profile-url.org"].chain[0].pkey).collect();
+        {
+            let tx = store.tx("test");
+            tx.set(&sid("s-id:target"), target).unwrap();
+            tx.set(&aid("s-id:target"), auths).unwrap();
+        }
+        store.commit(1);
+
+        let pair = |kid: &str| MasterKeyPair { kid: kid.to_string(), share: Share { i: 1, yi: rnd_scalar() }, public: rnd_scalar() * G };
+        for kid in [PMASTER, EMASTER].iter() {
+            store.set_local(&mkpid(kid), pair(kid));
+        }
+
+        let (_, skey) = Subject::new("s-id:shumy").evolve(sig_s);
+        let disclose = DiscloseRequest::sign("s-id:shumy", "s-id:target", &profiles, false, &sig_s, &skey);
+
+        // the typed key is not negotiated
+        assert!(handler.request(&store.snapshot(), disclose.clone()) == Err("Pseudonym master-key unavailable!".into()));
+
+        let typed = pair("p-master:HealthCare");
+        store.set_local(&mkpid("p-master:HealthCare"), typed.clone());
+        let pmkey = store.key(PMASTER).unwrap();
+
+        let data = handler.request(&store.snapshot(), disclose).unwrap();
+        match decode::<Response>(&data).unwrap() {
+            Response::QResult(QResult::QDiscloseResult(res)) => {
+                let share = |typ: &str| res.keys.keys[typ]["https://profile-url.org"].pseudonyms[0];
+                assert!(share("Finance") == (&pmkey.share * &pkeys[0]).Yi);
+                assert!(share("HealthCare") == (&typed.share * &pkeys[1]).Yi);
+            },
+            _ => panic!("Expecting a disclose result!")
+        }
+
+        drop(handler);
+        drop(store);
+        std::fs::remove_dir_all(&path).ok();
     }
 }
\ No newline at end of file
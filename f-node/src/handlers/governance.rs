@@ -0,0 +1,67 @@
+use std::sync::Arc;
+use log::info;
+
+use core_fpi::Result;
+use core_fpi::governance::*;
+
+use crate::config::Config;
+use crate::db::*;
+
+pub struct GovernanceHandler {
+    cfg: Arc<Config>,
+    store: Arc<AppDB>
+}
+
+impl GovernanceHandler {
+    pub fn new(cfg: Arc<Config>, store: Arc<AppDB>) -> Self {
+        Self { cfg, store }
+    }
+
+    pub fn deliver(&mut self, pset: PeerSet) -> Result<()> {
+        info!("DELIVER-PEERS - (sid = {:?}, threshold = {:?}, #peers = {:?})", pset.sid, pset.threshold, pset.peers.len());
+
+        // ---------------transaction---------------
+        let tx = self.store.tx("GovernanceHandler");
+            // check constraints
+            pset.check()?;
+
+            // verify if the subject has authorization to change the peer-set
+            if pset.sid != self.cfg.admin {
+                return Err("Subject has not authorization to change the peer-set!".into())
+            }
+
+            // a pending transition (from the current peer-set) is only to the recorded one
+            let change: Option<PeerChange> = tx.get(PEER_CHANGE);
+            if let Some(change) = change {
+                if change.old_hash == self.cfg.peers_hash && change.new_hash != pset.hash() {
+                    return Err("PeerSet doesn't match the committed peer change!".into())
+                }
+            }
+
+            // the new peer-set is only applied at the block commit (see Processor::commit)
+            tx.set(PEERS, pset)?;
+
+        Ok(())
+    }
+
+    // Transition from the current peer-set, committed before the PeerSet (e.g. after the resharing of the master-keys).
+    pub fn deliver_change(&mut self, change: PeerChange) -> Result<()> {
+        info!("DELIVER-PEER-CHANGE - (sid = {:?}, threshold = {:?}, #peers = {:?})", change.sid, change.threshold, change.peers.len());
+
+        // ---------------transaction---------------
+        let tx = self.store.tx("GovernanceHandler");
+            // check constraints
+            change.check()?;
+
+            // verify if the subject has authorization to change the peer-set
+            if change.sid != self.cfg.admin {
+                return Err("Subject has not authorization to change the peer-set!".into())
+            }
+
+            if change.old_hash != self.cfg.peers_hash || change.threshold != self.cfg.threshold {
+                return Err("Peer change is not from the current peer-set!".into())
+            }
+
+            tx.set(PEER_CHANGE, change)
+    }
+}
@@ -3,12 +3,13 @@ use log::info;
 use sha2::{Sha512, Digest};
 use clear_on_drop::clear::Clear;
 
-use core_fpi::{rnd_scalar, G, Result, Scalar};
+use core_fpi::{rnd_scalar, G, Result, Scalar, RistrettoPoint};
 use core_fpi::shares::*;
 use core_fpi::messages::*;
 use core_fpi::keys::*;
+use core_fpi::governance::peers_hash;
 
-use crate::config::Config;
+use crate::config::{Config, Vss};
 use crate::db::*;
 
 pub struct MasterKeyHandler {
@@ -32,13 +33,30 @@ impl MasterKeyHandler {
             return Err("Subject has not authorization to negotiate a master-key!".into())
         }
 
-        let e_keys = self.derive_encryption_keys(&req.sig.id());        // encryption keys (e_i)
-        let p_keys = e_keys.0.iter().map(|e_i| e_i * G).collect();      // public keys (e_i * G -> E_i)
-        let e_shares = self.derive_encrypted_shares(&e_keys);           // encrypted shares and Feldman's Coefficients (e_i + y_i -> p_i, A_k)
-
-        // (session, ordered peer's list, encrypted shares, Feldman's Coefficients, peer signature)
-        let vote = MasterKeyVote::sign(&req.sig.id(), &req.kid, &self.cfg.peers_hash, e_shares.0, p_keys, e_shares.1, &self.cfg.secret, &self.cfg.pkey, self.cfg.index);
-        let msg = Response::Vote(Vote::VMasterKeyVote(vote));
+        let index = self.cfg.index()?;
+        let e_keys = self.derive_encryption_keys(&self.cfg.peers_keys, &req.sig.id());    // encryption keys (e_i)
+        let p_keys = e_keys.0.iter().map(|e_i| e_i * G).collect();                          // public keys (e_i * G -> E_i)
+        let e_shares = self.derive_encrypted_shares(&e_keys, rnd_scalar(), self.cfg.threshold, self.cfg.vss)?; // encrypted shares and commitments (e_i + y_i -> p_i, A_k)
+
+        // (session, ordered peer's list, encrypted shares, Feldman's or Pedersen's commitments, peer signature)
+        let session = req.sig.id();
+        let EncryptedShares(shares, commit, pedersen) = e_shares;
+        let msg = match pedersen {
+            None => {
+                let vote = MasterKeyVote::sign(session, &req.kid, &self.cfg.peers_hash, shares, p_keys, commit, &self.cfg.secret, &self.cfg.pkey, index);
+                info!("VOTE-KEY - {}", vote.summary());
+                Response::Vote(Vote::VMasterKeyVote(vote))
+            },
+
+            Some(PedersenShares(p_commit, b_shares, b_keys)) => {
+                // the Feldman's commitments are only revealed after the commit of the Pedersen's votes (see reveal)
+                self.store.set_local(&mkcid(&req.sid, session), commit);
+
+                let vote = PedersenKeyVote::sign(session, &req.kid, &self.cfg.peers_hash, shares, p_keys, p_commit, b_shares, b_keys, &self.cfg.secret, &self.cfg.pkey, index);
+                info!("VOTE-PEDERSEN-KEY - {}", vote.summary());
+                Response::Vote(Vote::VPedersenKeyVote(vote))
+            }
+        };
 
         // store local evidence
         let mkrid = mkrid(&req.sid, req.sig.id());
@@ -47,18 +65,121 @@ impl MasterKeyHandler {
         encode(&msg)
     }
 
+    pub fn refresh(&mut self, req: MasterKeyRefresh) -> Result<Vec<u8>> {
+        info!("REFRESH-KEY - (session = {:?}, kid = {:?})", req.sig.id(), req.kid);
+
+        // check constraints
+        req.check(&self.cfg.peers_hash)?;
+
+        // verify if the subject has authorization to fire the refresh
+        if req.sid != self.cfg.admin {
+            return Err("Subject has not authorization to refresh a master-key!".into())
+        }
+
+        let index = self.cfg.index()?;
+        if self.store.key(&req.kid).is_none() {
+            return Err("Master-key not found!".into())
+        }
+
+        // same vote of a negotiation, but sharing a zero secret. The public part is known (0 * G), there is nothing to hide with Pedersen's commitments.
+        let e_keys = self.derive_encryption_keys(&self.cfg.peers_keys, &req.sig.id());
+        let p_keys = e_keys.0.iter().map(|e_i| e_i * G).collect();
+        let e_shares = self.derive_encrypted_shares(&e_keys, Scalar::zero(), self.cfg.threshold, Vss::Feldman)?;
+
+        let vote = self.vote(&req.sig.id(), &req.kid, &self.cfg.peers_hash, e_shares, p_keys, index);
+        info!("VOTE-REFRESH - {}", vote.summary());
+        let msg = Response::Vote(Vote::VMasterKeyVote(vote));
+
+        // store local evidence
+        let mkfrid = mkfrid(&req.sid, req.sig.id());
+        self.store.set_local(&mkfrid, req);
+
+        encode(&msg)
+    }
+
+    pub fn reshare(&mut self, req: MasterKeyReshare) -> Result<Vec<u8>> {
+        info!("RESHARE-KEY - (session = {:?}, kid = {:?}, threshold = {:?}, #new_peers = {:?})", req.sig.id(), req.kid, req.threshold, req.new_peers.len());
+
+        // check constraints
+        req.check(&self.cfg.peers_hash)?;
+
+        // verify if the subject has authorization to fire the resharing
+        if req.sid != self.cfg.admin {
+            return Err("Subject has not authorization to reshare a master-key!".into())
+        }
+
+        let index = self.cfg.index()?;
+        let pair = self.store.key(&req.kid).ok_or("Master-key not found!")?;
+        if pair.share.i as usize != index + 1 {
+            return Err("Master-key share with an unexpected index!".into())
+        }
+
+        // shares of the local share (y_i), targeting the new peer-set
+        let e_keys = self.derive_encryption_keys(&req.new_peers, &req.sig.id());
+        let p_keys = e_keys.0.iter().map(|e_ij| e_ij * G).collect();
+        // the resharing interpolates Feldman's coefficients, independent of the configured VSS
+        let e_shares = self.derive_encrypted_shares(&e_keys, pair.share.yi, req.threshold, Vss::Feldman)?;
+
+        let new_hash = peers_hash(&req.new_peers);
+        let vote = self.vote(&req.sig.id(), &req.kid, &new_hash, e_shares, p_keys, index);
+        info!("VOTE-RESHARE - {}", vote.summary());
+        let msg = Response::Vote(Vote::VMasterKeyVote(vote));
+
+        // store local evidence
+        let mksrid = mksrid(&req.sid, req.sig.id());
+        self.store.set_local(&mksrid, req);
+
+        encode(&msg)
+    }
+
+    // Second phase of a negotiation with Pedersen's commitments, the vote reveals the Feldman's commitments of the committed shares.
+    pub fn reveal(&mut self, snap: &Snapshot, req: MasterKeyReveal) -> Result<Vec<u8>> {
+        info!("REVEAL-KEY - (session = {:?}, kid = {:?})", req.session, req.kid);
+
+        // verify if the subject has authorization to fire negotiation
+        if req.sid != self.cfg.admin {
+            return Err("Subject has not authorization to negotiate a master-key!".into())
+        }
+
+        let index = self.cfg.index()?;
+        let evidence: PedersenKey = snap.get(&mkbid(&req.kid, &req.session)).ok_or("Pedersen's evidence not found!")?;
+        let commit: RistrettoPolynomial = snap.get(&mkcid(&req.sid, &req.session)).ok_or("Feldman's commitments not found!")?;
+        let committed = evidence.votes.get(index).ok_or("Pedersen's vote not found!")?;
+
+        let vote = MasterKeyVote::sign(&evidence.session, &evidence.kid, &committed.peers, committed.shares.clone(), committed.pkeys.clone(), commit, &self.cfg.secret, &self.cfg.pkey, index);
+        info!("VOTE-REVEAL - {}", vote.summary());
+        let msg = Response::Vote(Vote::VMasterKeyVote(vote));
+
+        encode(&msg)
+    }
+
+    pub fn query(&mut self, snap: &Snapshot, req: MasterKeyQuery) -> Result<Vec<u8>> {
+        info!("REQUEST-PUBLIC-KEY - (sid = {:?}, kid = {:?})", req.sid, req.kid);
+
+        // the evidence proves the public-key, unknown keys return an empty result
+        let evidence: Option<MasterKey> = snap.get::<String>(&mklid(&req.kid))
+            .and_then(|mkid| snap.get(&mkid));
+
+        let res = MasterKeyResult::sign(&req.kid, evidence, self.cfg.change.clone(), &self.cfg.secret, &self.cfg.pkey, self.cfg.index()?);
+        let msg = Response::QResult(QResult::QMasterKeyResult(res));
+
+        encode(&msg)
+    }
+
     pub fn deliver(&mut self, evidence: MasterKey) -> Result<()> {
-        info!("DELIVER-KEY - (session = {:?}, #votes = {:?})", evidence.session, evidence.votes.len());
+        info!("DELIVER-KEY - {}", evidence.summary());
         let mkrid = mkrid(&evidence.sid, &evidence.session);
         let mkid = mkid(&evidence.kid, evidence.sig.id());
         let mkpid = mkpid(&evidence.kid);
+        let mklid = mklid(&evidence.kid);
 
         // ---------------transaction---------------
-        let tx = self.store.tx();
-            // check constraints
-            evidence.check(&self.cfg.peers_hash, &self.cfg.peers_keys)?;
+        let tx = self.store.tx("MasterKeyHandler");
+            // check constraints, the shares target the current peer-set (there is no transition)
+            evidence.check(&self.cfg.peers_hash, &self.cfg.peers_keys, self.cfg.threshold, None)?;
 
-            if !tx.contains(&mkrid) {
+            // an observer doesn't receive the request, and has no share of the key
+            if self.cfg.index.is_some() && !tx.contains(&mkrid) {
                 return Err("MasterKeyRequest not found!".into())
             }
 
@@ -71,46 +192,29 @@ impl MasterKeyHandler {
             if tx.contains(&mkid) {
                 return Err("Master-key evidence already exists!".into())
             }
-        
-            let n = self.cfg.peers.len();
-            let e_shares = evidence.extract(self.cfg.index);                    // encrypted shares, Feldman's Coefs and PublicKey (e_i + y_i -> p_i, A_k, Y)
-            let e_keys = self.derive_encryption_keys(&evidence.session);        // encryption keys (e_i)
 
-            if e_shares.0.len() != n || e_keys.0.len() != n {
-                return Err("Incorrect sizes on MasterKey commit (#e_shares != n || #e_keys != n)!".into())
+            // a negotiation with Pedersen's commitments must reveal the committed shares
+            let committed: Option<PedersenKey> = tx.get(&mkbid(&evidence.kid, &evidence.session));
+            if let Some(committed) = committed {
+                committed.check_reveal(&evidence)?;
             }
+        
+            // recovered the key-pair for this peer
+            if let Some(index) = self.cfg.index {
+                let (share, y_public) = self.recover(&evidence, index)?;
 
-            // recover an check encrypted shares
-            let share_index = e_shares.0[0].i;
-            let mut shares = Vec::<Share>::with_capacity(n);
-            for (i, e_i) in e_keys.0.iter().enumerate() {
-                if e_shares.0[i].i != share_index {
-                    return Err("Invalid share index!".into())
-                }
-
-                let share = &e_shares.0[i] - e_i;
-                let r_share = &share * &G;
-
-                if !e_shares.1[i].verify(&r_share) {
-                    return Err("Invalid recovered share!".into())
-                }
+                //info!("KEY-PAIR (yi*G = {:?}, Y = {:?})", (share.yi * G).encode(), y_public.encode());
+                let pair = MasterKeyPair {
+                    kid: evidence.kid.clone(),
+                    share,
+                    public: y_public
+                };
 
-                shares.push(share);
+                tx.set_local(&mkpid, pair)?;
             }
 
-            // recovered the key-pair for this peer
-            let y_secret = shares.iter().fold(Scalar::zero(), |total, share| total +  share.yi);
-            let y_public = e_shares.2;
-
-            //info!("KEY-PAIR (yi*G = {:?}, Y = {:?})", (y_secret * G).encode(), y_public.encode());
-            let pair = MasterKeyPair {
-                kid: evidence.kid.clone(),
-                share: Share { i: share_index, yi: y_secret },
-                public: y_public
-            };
-
-            tx.set(&mkid, evidence);
-            tx.set_local(&mkpid, pair);
+            tx.set(&mklid, mkid.clone())?;
+            tx.set(&mkid, evidence)?;
 
             /* TODO: how to to evolve all existing pseudonyms?
                 * This is an issue, because the pseudonyms are not in the federated network!
@@ -121,13 +225,172 @@ impl MasterKeyHandler {
         Ok(())
     }
 
-    fn derive_encryption_keys(&self, session: &str) -> EncryptionKeys {
+    // The committed Pedersen's votes fix the shares of the negotiation, the MasterKey is only delivered after the reveal.
+    pub fn deliver_pedersen(&mut self, evidence: PedersenKey) -> Result<()> {
+        info!("DELIVER-PEDERSEN-KEY - {}", evidence.summary());
+        let mkrid = mkrid(&evidence.sid, &evidence.session);
+        let mkbid = mkbid(&evidence.kid, &evidence.session);
+
+        // ---------------transaction---------------
+        let tx = self.store.tx("MasterKeyHandler");
+            // check constraints, the shares are publicly verifiable with the symmetric encryption and blinding keys
+            evidence.check(&self.cfg.peers_hash, &self.cfg.peers_keys, self.cfg.threshold)?;
+
+            if self.cfg.index.is_some() && !tx.contains(&mkrid) {
+                return Err("MasterKeyRequest not found!".into())
+            }
+
+            // verify if the subject has authorization to commit evidence
+            if evidence.sid != self.cfg.admin {
+                return Err("Subject has not authorization to commit the master-key evidence!".into())
+            }
+
+            // avoid evidence override
+            if tx.contains(&mkbid) {
+                return Err("Pedersen's evidence already exists!".into())
+            }
+
+            tx.set(&mkbid, evidence)?;
+
+        Ok(())
+    }
+
+    pub fn deliver_refresh(&mut self, evidence: MasterKey) -> Result<()> {
+        info!("DELIVER-REFRESH - {}", evidence.summary());
+        let mkfrid = mkfrid(&evidence.sid, &evidence.session);
+        let mkfid = mkfid(&evidence.kid, evidence.sig.id());
+        let mkpid = mkpid(&evidence.kid);
+
+        // ---------------transaction---------------
+        let tx = self.store.tx("MasterKeyHandler");
+            // check constraints
+            evidence.check(&self.cfg.peers_hash, &self.cfg.peers_keys, self.cfg.threshold, None)?;
+            evidence.check_zero()?;
+
+            if self.cfg.index.is_some() && !tx.contains(&mkfrid) {
+                return Err("MasterKeyRefresh not found!".into())
+            }
+
+            // verify if the subject has authorization to commit evidence
+            if evidence.sid != self.cfg.admin {
+                return Err("Subject has not authorization to commit the master-key refresh!".into())
+            }
+
+            // avoid evidence replay
+            if tx.contains(&mkfid) {
+                return Err("Master-key refresh evidence already exists!".into())
+            }
+
+            if !tx.contains(&mklid(&evidence.kid)) {
+                return Err("Master-key not found!".into())
+            }
+
+            // shares of a zero secret, the aggregated commitment must keep the public-key
+            if let Some(index) = self.cfg.index {
+                let current = self.store.key(&evidence.kid).ok_or("Master-key not found!")?;
+                let (delta, delta_public) = self.recover(&evidence, index)?;
+                let pair = current.refresh(&delta, &delta_public)?;
+                tx.set_local(&mkpid, pair)?;
+            }
+
+            tx.set(&mkfid, evidence)?;
+
+        Ok(())
+    }
+
+    // The new key-pair is only used after the change to the new peer-set (see activate). If the change is already committed,
+    // the votes are verified with the previous peer-set and the key-pair replaces the current one.
+    // The delivery only depends on the committed state, the joining peers never received the request (MasterKeyReshare).
+    pub fn deliver_reshare(&mut self, evidence: ResharedKey) -> Result<()> {
+        info!("DELIVER-RESHARE - {}", evidence.summary());
+        let mksid = mksid(&evidence.kid, evidence.sig.id());
+        let mkspid = mkspid(&evidence.kid);
+
+        // ---------------transaction---------------
+        let tx = self.store.tx("MasterKeyHandler");
+            // verify if the subject has authorization to commit evidence
+            if evidence.sid != self.cfg.admin {
+                return Err("Subject has not authorization to commit the master-key resharing!".into())
+            }
+
+            // avoid evidence replay
+            if tx.contains(&mksid) {
+                return Err("Master-key resharing evidence already exists!".into())
+            }
+
+            // the reshared commitments must reconstruct the public-key of the committed evidence
+            let current: Option<MasterKey> = tx.get::<String>(&mklid(&evidence.kid))
+                .and_then(|mkid| tx.get(&mkid));
+            let public = current.ok_or("Master-key not found!")?.public();
+            let voters = evidence.check_transition(&self.cfg.peers_keys, self.cfg.threshold, &public, self.cfg.change.as_ref())?;
+
+            if let Some(index) = evidence.new_peers.iter().position(|pkey| *pkey == self.cfg.pkey) {
+                // encryption keys shared with each voting peer
+                let e_keys = self.derive_encryption_keys(&voters, &evidence.session);
+
+                let pair = evidence.recover(index, &e_keys.0)?;
+                let new_hash = peers_hash(&evidence.new_peers);
+                if new_hash == self.cfg.peers_hash {
+                    info!("ACTIVATE-RESHARED-KEY - (kid = {:?})", pair.kid);
+                    tx.set_local(&mkpid(&evidence.kid), pair)?;
+                } else {
+                    tx.set_local(&mkspid, (new_hash, pair))?;
+                }
+            }
+
+            tx.set(&mksid, evidence)?;
+
+        Ok(())
+    }
+
+    // Replace the key-pairs reshared to the current peer-set, called after the peer-set change.
+    pub fn activate(&mut self) {
+        let pending: Vec<(String, (Vec<u8>, MasterKeyPair))> = self.store.scan(&mkspid("")).collect();
+        for (_, (hash, pair)) in pending {
+            if hash == self.cfg.peers_hash {
+                info!("ACTIVATE-RESHARED-KEY - (kid = {:?})", pair.kid);
+                self.store.set_key(pair);
+            }
+        }
+    }
+
+    // Recover and check the encrypted shares targeting this peer (at the index), returning the summed share and the aggregated public-key.
+    fn recover(&self, evidence: &MasterKey, index: usize) -> Result<(Share, RistrettoPoint)> {
         let n = self.cfg.peers.len();
+        let e_shares = evidence.extract(index);                             // encrypted shares, commitments and PublicKey (e_i + y_i -> p_i, A_k, Y)
+        let e_keys = self.derive_encryption_keys(&self.cfg.peers_keys, &evidence.session); // encryption keys (e_i)
+
+        if e_shares.0.len() != n || e_keys.0.len() != n {
+            return Err("Incorrect sizes on MasterKey commit (#e_shares != n || #e_keys != n)!".into())
+        }
 
-        let mut e_keys = Vec::<Scalar>::with_capacity(n);
-        for peer in self.cfg.peers.iter() {
+        // recover an check encrypted shares
+        let share_index = e_shares.0[0].i;
+        let mut shares = Vec::<Share>::with_capacity(n);
+        for (i, e_i) in e_keys.0.iter().enumerate() {
+            if e_shares.0[i].i != share_index {
+                return Err("Invalid share index!".into())
+            }
+
+            let share = &e_shares.0[i] - e_i;
+            let r_share = &share * &G;
+            if !e_shares.1[i].verify(&r_share) {
+                return Err("Invalid recovered share!".into())
+            }
+
+            shares.push(share);
+        }
+
+        let y_secret = shares.iter().fold(Scalar::zero(), |total, share| total +  share.yi);
+        Ok((Share { i: share_index, yi: y_secret }, e_shares.2))
+    }
+
+    // encryption keys shared with each peer of the list (the current peer-set, or the new one on resharing)
+    fn derive_encryption_keys(&self, pkeys: &[RistrettoPoint], session: &str) -> EncryptionKeys {
+        let mut e_keys = Vec::<Scalar>::with_capacity(pkeys.len());
+        for pkey in pkeys.iter() {
             // perform a Diffie-Hellman between local and peer
-            let dh = (self.cfg.secret * peer.pkey).compress();
+            let dh = (self.cfg.secret * pkey).compress();
 
             // derive secret key between peers
             let mut hasher = Sha512::new();
@@ -141,27 +404,70 @@ impl MasterKeyHandler {
         EncryptionKeys(e_keys)
     }
 
-    fn derive_encrypted_shares(&self, e_keys: &EncryptionKeys) -> (Vec<Share>, RistrettoPolynomial) {
-        let n = self.cfg.peers.len();
+    fn derive_encrypted_shares(&self, e_keys: &EncryptionKeys, y: Scalar, t: usize, vss: Vss) -> Result<EncryptedShares> {
+        let n = e_keys.0.len();
 
         // derive secret polynomial and shares
-        let y = rnd_scalar();
-        let ak = Polynomial::rnd(y, self.cfg.threshold);
+        let ak = Polynomial::rnd(y, t);
         let sv = ak.shares(n);
 
-        // commit with Feldman's Coefficients
-        let fk = &ak * &G;
-
         // encrypted shares
         let mut e_shares = Vec::<Share>::with_capacity(n);
         for i in 0..n {
             e_shares.push( &sv.0[i] + &e_keys.0[i] );
         }
 
-        (e_shares, fk)
-    } // (sv: ShareVector) containing secrets will be cleared here
+        // commit with Feldman's Coefficients
+        let commit = &ak * &G;
+        match vss {
+            Vss::Feldman => Ok(EncryptedShares(e_shares, commit, None)),
+
+            // also commit with Pedersen's Coefficients, the blinding shares are encrypted with keys derived from e_i
+            Vss::Pedersen => {
+                let bk = Polynomial::rnd(rnd_scalar(), t);
+                let p_commit = RistrettoPolynomial::pedersen(&ak, &bk)?;
+
+                let b_keys = derive_blinding_keys(e_keys);
+                let bv = bk.shares(n);
+
+                let mut b_shares = Vec::<Share>::with_capacity(n);
+                for i in 0..n {
+                    b_shares.push( &bv.0[i] + &b_keys.0[i] );
+                }
+
+                let h = pedersen_h();
+                let b_pkeys = b_keys.0.iter().map(|f_i| f_i * h).collect();
+
+                Ok(EncryptedShares(e_shares, commit, Some(PedersenShares(p_commit, b_shares, b_pkeys))))
+            }
+        }
+    } // (sv, bv: ShareVector) containing secrets will be cleared here
+
+    // vote with the Feldman's commitments
+    fn vote(&self, session: &str, kid: &str, peers_hash: &[u8], e_shares: EncryptedShares, p_keys: Vec<RistrettoPoint>, index: usize) -> MasterKeyVote {
+        let EncryptedShares(shares, commit, _) = e_shares;
+        MasterKeyVote::sign(session, kid, peers_hash, shares, p_keys, commit, &self.cfg.secret, &self.cfg.pkey, index)
+    }
+}
+
+// blinding keys (f_i) derived from the encryption keys, independent from e_i so that (e_i + y_i) and (f_i + z_i) don't leak (y_i - z_i)
+fn derive_blinding_keys(e_keys: &EncryptionKeys) -> EncryptionKeys {
+    let f_keys = e_keys.0.iter().map(|e_i| {
+        let mut hasher = Sha512::new();
+        hasher.input(b"fpi:pedersen-blinding");
+        hasher.input(e_i.as_bytes());
+        Scalar::from_hash(hasher)
+    }).collect();
+
+    EncryptionKeys(f_keys)
 }
 
+// (encrypted shares, Feldman's commitments, Pedersen's commitments with the encrypted blinding shares)
+struct EncryptedShares(Vec<Share>, RistrettoPolynomial, Option<PedersenShares>);
+
+// (Pedersen's commitments, encrypted blinding shares, blinding public keys)
+struct PedersenShares(RistrettoPolynomial, Vec<Share>, Vec<RistrettoPoint>);
+
 struct EncryptionKeys(Vec<Scalar>);
 
 impl Drop for EncryptionKeys {
@@ -170,4 +476,277 @@ impl Drop for EncryptionKeys {
             item.clear();
         }
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core_fpi::uuid;
+    use core_fpi::ids::Subject;
+    use core_fpi::governance::{PeerChange, PeerSet};
+    use crate::config::Peer;
+    use crate::handlers::governance::GovernanceHandler;
+    use crate::config::tests::test_config;
+
+    // Feldman's votes of the peers (by index) sharing the polynomials to the pkeys, the same way of the handler
+    fn votes(session: &str, peers_hash: &[u8], voters: &[usize], polys: &[Polynomial], secrets: &[Scalar], pkeys: &[RistrettoPoint]) -> Vec<MasterKeyVote> {
+        voters.iter().zip(polys.iter()).map(|(i, poly)| {
+            let e_keys: Vec<Scalar> = pkeys.iter().map(|pkey| {
+                let dh = (secrets[*i] * pkey).compress();
+                Scalar::from_hash(Sha512::new().chain(dh.as_bytes()).chain(session.as_bytes()))
+            }).collect();
+
+            let p_keys: Vec<RistrettoPoint> = e_keys.iter().map(|e_ij| e_ij * G).collect();
+            let e_shares: Vec<Share> = poly.shares(pkeys.len()).0.iter().zip(e_keys.iter()).map(|(y_j, e_ij)| y_j + e_ij).collect();
+            MasterKeyVote::sign(session, "kid", peers_hash, e_shares, p_keys, poly * &G, &secrets[*i], &(secrets[*i] * G), *i)
+        }).collect()
+    }
+
+    fn peers(pkeys: &[RistrettoPoint]) -> Vec<Peer> {
+        pkeys.iter().enumerate().map(|(i, pkey)| Peer { name: format!("peer-{}", i), pkey: *pkey }).collect()
+    }
+
+    #[allow(non_snake_case)]
+    #[test]
+    fn test_reshare_after_peer_change() {
+        let (t, t2) = (1, 1);
+        let cfg = test_config();
+
+        let sig_s = rnd_scalar();
+        let (_, skey) = Subject::new(&cfg.admin).evolve(sig_s);
+
+        // the previous peer-set (without this peer), and the new one where this peer joins
+        let secrets: Vec<Scalar> = (0..4).map(|_| rnd_scalar()).collect();
+        let old_peers: Vec<RistrettoPoint> = secrets.iter().map(|s| s * G).collect();
+        let mut new_peers = vec![cfg.pkey];
+        new_peers.extend(old_peers[1..].iter());
+
+        // the master-key negotiated by the previous peer-set
+        let old_hash = peers_hash(&old_peers);
+        let polys: Vec<Polynomial> = (0..4).map(|_| Polynomial::rnd(rnd_scalar(), t)).collect();
+        let n_votes = votes("negotiation", &old_hash, &[0, 1, 2, 3], &polys, &secrets, &old_peers);
+        let mk = MasterKey::sign(&cfg.admin, "negotiation", "kid", &old_hash, n_votes, &old_peers, t, &sig_s, &skey).unwrap();
+        let Y = mk.public();
+
+        let share = |j: usize| polys.iter().fold(Scalar::zero(), |total, poly| total + poly.shares(4).0[j].yi);
+
+        // the votes of a resharing negotiated by the previous peer-set (the removed peer 0 is one of the voters)
+        let new_hash = peers_hash(&new_peers);
+        let r_polys: Vec<Polynomial> = [0, 2].iter().map(|i| Polynomial::rnd(share(*i), t2)).collect();
+        let r_votes = votes("session", &new_hash, &[0, 2], &r_polys, &secrets, &new_peers);
+        let evidence = MasterKey::reshare(&cfg.admin, "session", "kid", t2, &new_peers, r_votes, &old_peers, t, &Y, &sig_s, &skey).unwrap();
+
+        let path = std::env::temp_dir().join(format!("fedpi-keys-{}", uuid()));
+        let store = Arc::new(AppDB::new(path.to_str().unwrap()));
+
+        // the joining peer observed the negotiation, it has no share and never received the resharing request
+        let mut o_cfg = cfg.clone();
+        o_cfg.set_peers(t, peers(&old_peers));
+        let o_cfg = Arc::new(o_cfg);
+        let mut handler = MasterKeyHandler::new(o_cfg.clone(), store.clone());
+        handler.deliver(mk).unwrap();
+        store.commit(1);
+        assert!(store.key("kid").is_none());
+
+        // the transition is committed under the previous peer-set, before the new PeerSet
+        let named: Vec<(String, RistrettoPoint)> = peers(&new_peers).into_iter().map(|peer| (peer.name, peer.pkey)).collect();
+        let mut governance = GovernanceHandler::new(o_cfg.clone(), store.clone());
+        governance.deliver_change(PeerChange::sign(&cfg.admin, t, &old_peers, &new_peers, &sig_s, &skey)).unwrap();
+
+        let other = PeerSet::sign(&cfg.admin, t2, &[&named[1..], &named[..1]].concat(), &sig_s, &skey);
+        assert!(governance.deliver(other) == Err("PeerSet doesn't match the committed peer change!".into()));
+        governance.deliver(PeerSet::sign(&cfg.admin, t2, &named, &sig_s, &skey)).unwrap();
+        store.commit(2);
+
+        // without the recorded transition, the votes are verified with the new peer-set
+        let mut n_cfg = cfg.clone();
+        n_cfg.set_peers(t2, peers(&new_peers));
+        let mut handler = MasterKeyHandler::new(Arc::new(n_cfg.clone()), store.clone());
+        assert!(handler.deliver_reshare(evidence.clone()).is_err());
+
+        // the evidence of the previous peer-set is accepted with the committed change, and the key-pair is activated
+        n_cfg.change = store.get(PEER_CHANGE);
+        let mut handler = MasterKeyHandler::new(Arc::new(n_cfg), store.clone());
+        handler.deliver_reshare(evidence.clone()).unwrap();
+        store.commit(3);
+
+        let pair = store.key("kid").unwrap();
+        assert!(pair.public == Y && pair.share.i == 1);
+        assert!(store.scan::<(Vec<u8>, MasterKeyPair)>(&mkspid("")).next().is_none());
+
+        // the new peer-set reconstructs the same public-key, with the committed shares of the other peers
+        let committed = |i: u32| {
+            let x = Scalar::from(i);
+            let points: Vec<RistrettoShare> = evidence.votes.iter().map(|vote| RistrettoShare { i: vote.sig.index as u32 + 1, Yi: vote.commit.evaluate(&x) }).collect();
+            RistrettoShare { i, Yi: RistrettoPolynomial::interpolate(&points).unwrap() }
+        };
+
+        assert!(committed(1).Yi == pair.share.yi * G);
+        let r_shares = vec![RistrettoShare { i: pair.share.i, Yi: pair.share.yi * G }, committed(3)];
+        assert!(RistrettoPolynomial::interpolate(&r_shares) == Ok(Y));
+        assert!(RistrettoPolynomial::interpolate(&[committed(2), committed(4)]) == Ok(Y));
+
+        drop(handler);
+        drop(governance);
+        drop(store);
+        std::fs::remove_dir_all(&path).ok();
+    }
+
+    #[allow(non_snake_case)]
+    #[test]
+    fn test_pedersen_negotiation() {
+        let (n, t) = (4, 1);
+        let base = test_config();
+
+        let sig_s = rnd_scalar();
+        let (_, skey) = Subject::new(&base.admin).evolve(sig_s);
+
+        let secrets: Vec<Scalar> = (0..n).map(|_| rnd_scalar()).collect();
+        let pkeys: Vec<RistrettoPoint> = secrets.iter().map(|s| s * G).collect();
+        let hash = peers_hash(&pkeys);
+
+        let paths: Vec<_> = (0..n).map(|_| std::env::temp_dir().join(format!("fedpi-keys-{}", uuid()))).collect();
+        let stores: Vec<Arc<AppDB>> = paths.iter().map(|path| Arc::new(AppDB::new(path.to_str().unwrap()))).collect();
+        let mut handlers: Vec<MasterKeyHandler> = (0..n).map(|i| {
+            let mut cfg = base.clone();
+            cfg.secret = secrets[i];
+            cfg.pkey = pkeys[i];
+            cfg.vss = Vss::Pedersen;
+            cfg.set_peers(t, peers(&pkeys));
+            MasterKeyHandler::new(Arc::new(cfg), stores[i].clone())
+        }).collect();
+
+        // first phase, the votes only have Pedersen's commitments
+        let req = MasterKeyRequest::sign(&base.admin, "kid", &hash, &sig_s, &skey);
+        let session = req.sig.id().to_string();
+        let p_votes: Vec<PedersenKeyVote> = handlers.iter_mut().map(|handler| match decode(&handler.request(req.clone()).unwrap()).unwrap() {
+            Response::Vote(Vote::VPedersenKeyVote(vote)) => vote,
+            _ => panic!("Expecting a Pedersen's vote!")
+        }).collect();
+
+        let pk = PedersenKey::sign(&base.admin, &session, "kid", &hash, p_votes, &pkeys, t, &sig_s, &skey).unwrap();
+
+        // the Feldman's commitments are only revealed after the commit of the Pedersen's votes
+        let reveal = MasterKeyReveal::sign(&base.admin, "kid", &session, &sig_s, &skey);
+        assert!(handlers[0].reveal(&stores[0].snapshot(), reveal.clone()) == Err("Pedersen's evidence not found!".into()));
+
+        for (handler, store) in handlers.iter_mut().zip(stores.iter()) {
+            handler.deliver_pedersen(pk.clone()).unwrap();
+            store.commit(1);
+        }
+
+        assert!(handlers[0].deliver_pedersen(pk.clone()) == Err("Pedersen's evidence already exists!".into()));
+
+        // second phase, the revealed votes conclude the negotiation
+        let revealed: Vec<MasterKeyVote> = handlers.iter_mut().zip(stores.iter()).map(|(handler, store)| match decode(&handler.reveal(&store.snapshot(), reveal.clone()).unwrap()).unwrap() {
+            Response::Vote(Vote::VMasterKeyVote(vote)) => vote,
+            _ => panic!("Expecting a Feldman's vote!")
+        }).collect();
+
+        let mk = MasterKey::sign(&base.admin, &session, "kid", &hash, revealed.clone(), &pkeys, t, &sig_s, &skey).unwrap();
+        assert!(pk.check_reveal(&mk) == Ok(()));
+
+        // a peer choosing another secret after the reveal of the others
+        let mut o_votes = revealed.clone();
+        o_votes[0] = votes(&session, &hash, &[0], &[Polynomial::rnd(rnd_scalar(), t)], &secrets, &pkeys).remove(0);
+        let other = MasterKey::sign(&base.admin, &session, "kid", &hash, o_votes, &pkeys, t, &sig_s, &skey).unwrap();
+        assert!(handlers[1].deliver(other) == Err("MasterKey with a different vote from peer 0!".into()));
+
+        for (handler, store) in handlers.iter_mut().zip(stores.iter()) {
+            handler.deliver(mk.clone()).unwrap();
+            store.commit(2);
+        }
+
+        // the shares of all peers reconstruct the public-key of the committed evidence
+        let Y = mk.public();
+        let pairs: Vec<MasterKeyPair> = stores.iter().map(|store| store.key("kid").unwrap()).collect();
+        assert!(pairs.iter().all(|pair| pair.public == Y));
+
+        let r_shares: Vec<RistrettoShare> = pairs.iter().map(|pair| RistrettoShare { i: pair.share.i, Yi: pair.share.yi * G }).collect();
+        assert!(RistrettoPolynomial::interpolate(&r_shares[..t + 1]) == Ok(Y));
+        assert!(RistrettoPolynomial::interpolate(&r_shares[n - t - 1..]) == Ok(Y));
+
+        drop(handlers);
+        drop(stores);
+        for path in paths.iter() {
+            std::fs::remove_dir_all(path).ok();
+        }
+    }
+
+    #[allow(non_snake_case)]
+    #[test]
+    fn test_refresh() {
+        let (n, t) = (4, 1);
+        let base = test_config();
+
+        let sig_s = rnd_scalar();
+        let (_, skey) = Subject::new(&base.admin).evolve(sig_s);
+
+        let secrets: Vec<Scalar> = (0..n).map(|_| rnd_scalar()).collect();
+        let pkeys: Vec<RistrettoPoint> = secrets.iter().map(|s| s * G).collect();
+        let hash = peers_hash(&pkeys);
+
+        let paths: Vec<_> = (0..n).map(|_| std::env::temp_dir().join(format!("fedpi-keys-{}", uuid()))).collect();
+        let stores: Vec<Arc<AppDB>> = paths.iter().map(|path| Arc::new(AppDB::new(path.to_str().unwrap()))).collect();
+        let mut handlers: Vec<MasterKeyHandler> = (0..n).map(|i| {
+            let mut cfg = base.clone();
+            cfg.secret = secrets[i];
+            cfg.pkey = pkeys[i];
+            cfg.set_peers(t, peers(&pkeys));
+            MasterKeyHandler::new(Arc::new(cfg), stores[i].clone())
+        }).collect();
+
+        let vote = |res: Vec<u8>| match decode(&res).unwrap() {
+            Response::Vote(Vote::VMasterKeyVote(vote)) => vote,
+            _ => panic!("Expecting a Feldman's vote!")
+        };
+
+        // negotiation of the key
+        let req = MasterKeyRequest::sign(&base.admin, "kid", &hash, &sig_s, &skey);
+        let votes: Vec<MasterKeyVote> = handlers.iter_mut().map(|handler| vote(handler.request(req.clone()).unwrap())).collect();
+        let mk = MasterKey::sign(&base.admin, req.sig.id(), "kid", &hash, votes, &pkeys, t, &sig_s, &skey).unwrap();
+        for (handler, store) in handlers.iter_mut().zip(stores.iter()) {
+            handler.deliver(mk.clone()).unwrap();
+            store.commit(1);
+        }
+
+        // the key-pairs are cached before the refresh
+        let Y = mk.public();
+        let before: Vec<MasterKeyPair> = stores.iter().map(|store| store.key("kid").unwrap()).collect();
+
+        // the votes share a zero secret
+        let req = MasterKeyRefresh::sign(&base.admin, "kid", &hash, &sig_s, &skey);
+        let votes: Vec<MasterKeyVote> = handlers.iter_mut().map(|handler| vote(handler.refresh(req.clone()).unwrap())).collect();
+        let evidence = MasterKey::sign(&base.admin, req.sig.id(), "kid", &hash, votes, &pkeys, t, &sig_s, &skey).unwrap();
+        assert!(evidence.check_zero() == Ok(()));
+
+        for (handler, store) in handlers.iter_mut().zip(stores.iter()) {
+            handler.deliver_refresh(evidence.clone()).unwrap();
+            store.commit(2);
+        }
+
+        assert!(handlers[0].deliver_refresh(evidence.clone()) == Err("Master-key refresh evidence already exists!".into()));
+
+        // new shares of the same public-key, the cached key-pairs are replaced after the commit
+        let pairs: Vec<MasterKeyPair> = stores.iter().map(|store| store.key("kid").unwrap()).collect();
+        assert!(pairs.iter().zip(before.iter()).all(|(pair, old)| pair.public == Y && pair.share.i == old.share.i && pair.share.yi != old.share.yi));
+
+        let r_shares: Vec<RistrettoShare> = pairs.iter().map(|pair| RistrettoShare { i: pair.share.i, Yi: pair.share.yi * G }).collect();
+        assert!(RistrettoPolynomial::interpolate(&r_shares[..t + 1]) == Ok(Y));
+        assert!(RistrettoPolynomial::interpolate(&r_shares[n - t - 1..]) == Ok(Y));
+
+        // old and new shares don't mix
+        let mixed = vec![RistrettoShare { i: before[0].share.i, Yi: before[0].share.yi * G }, r_shares[1].clone()];
+        assert!(RistrettoPolynomial::interpolate(&mixed) != Ok(Y));
+
+        // a refresh of an unknown key
+        let req = MasterKeyRefresh::sign(&base.admin, "other", &hash, &sig_s, &skey);
+        assert!(handlers[0].refresh(req) == Err("Master-key not found!".into()));
+
+        drop(handlers);
+        drop(stores);
+        for path in paths.iter() {
+            std::fs::remove_dir_all(path).ok();
+        }
+    }
 }
\ No newline at end of file
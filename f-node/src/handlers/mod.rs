@@ -1,4 +1,6 @@
 pub mod authorizations;
 pub mod disclosures;
+pub mod governance;
 pub mod keys;
+pub mod records;
 pub mod subjects;
\ No newline at end of file
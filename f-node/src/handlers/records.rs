@@ -0,0 +1,415 @@
+use std::sync::Arc;
+use std::collections::BTreeMap;
+use log::info;
+
+use core_fpi::{Result, RistrettoPoint, KeyEncoder};
+use core_fpi::ids::Subject;
+use core_fpi::keys::MasterKeyPair;
+use core_fpi::records::*;
+use core_fpi::messages::*;
+
+use crate::config::Config;
+use crate::db::*;
+
+pub struct RecordHandler {
+    cfg: Arc<Config>,
+    store: Arc<AppDB>,
+    touched: BTreeMap<String, String>   // streams with records in the current block <stream, pseudonym>
+}
+
+impl RecordHandler {
+    pub fn new(cfg: Arc<Config>, store: Arc<AppDB>) -> Self {
+        Self { cfg, store, touched: BTreeMap::new() }
+    }
+
+    pub fn deliver(&mut self, new: NewRecord, height: i64) -> Result<()> {
+        let stream = new.stream();
+        let hash = new.record.hash();
+        info!("DELIVER-RECORD - (stream = {:?}, hash = {:?})", stream, hash);
+
+        let bases = record_bases(&self.cfg, |kid| self.store.key(kid))?;
+        let pseudonym = new.pseudonym.encode();
+        let rcid = rcid(&stream);
+
+        // ---------------transaction---------------
+        let tx = self.store.tx("RecordHandler");
+            // closed streams are marked, independently of the stored last record
+            let rxid = rxid(&stream);
+            if tx.contains(&rxid) {
+                return Err("The stream is closed!".into())
+            }
+
+            // check signature and stream chain, the last record is never pruned
+            let count: usize = tx.get(&rcid).unwrap_or(0);
+            let last: Option<Record> = match count {
+                0 => None,
+                _ => Some(tx.get(&rec(&pseudonym, count - 1)).ok_or("Record not found in the stream sequence!")?)
+            };
+
+            // a retried delivery is already the stream head, and would otherwise fail the chain check
+            if last.as_ref().map(Record::hash).as_ref() == Some(&hash) {
+                return Err("Duplicate record!".into())
+            }
+
+            new.check(last.as_ref(), &bases, self.cfg.strict_meta)?;
+
+            // anonymous attachments may require the authorization of the referenced stream owner
+            let owner: Option<String> = match &new.record.typ {
+                RecordType::AnonymousAttach(attach) => tx.get(&rrid(attach)),
+                _ => None
+            };
+            new.check_attach(owner.as_ref().map(|o| o.as_str()), &bases, self.cfg.strict_attach)?;
+
+            // the log of retained entries is only kept with an active retention policy
+            if self.cfg.retention.enabled() {
+                let rlid = rlid(&stream);
+                let mut log: RecordLog = tx.get(&rlid).unwrap_or_default();
+                log.push(RecordEntry { height, hash, sig: new.record.sig.encoded.clone() });
+                tx.set(&rlid, log)?;
+                self.touched.insert(stream.clone(), pseudonym.clone());
+
+                if self.cfg.retention.max_age > 0 {
+                    tx.set(&rtid(height, &stream), pseudonym.clone())?;
+                }
+            }
+
+            // records are kept in stream order, for the chain and the stream queries
+            tx.set(&rec(&pseudonym, count), new.record.clone())?;
+            tx.set(&rcid, count + 1)?;
+
+            if new.record.is_closed() {
+                info!("CLOSE-STREAM - (stream = {:?})", stream);
+                tx.set(&rxid, true)?;
+            }
+
+            tx.set(&rnid(&new.record.sig.encoded), count)?;
+            tx.set(&rrid(&new.record.sig.encoded), stream)?;
+        Ok(())
+    }
+
+    // Applied before the block commit. Streams are pruned in key order, and the policy only depends on the block transaction
+    // and the block height, so all peers remove the same entries and reach the same app-state.
+    pub fn prune(&mut self, height: i64) -> Result<()> {
+        let policy = &self.cfg.retention;
+        let mut streams = std::mem::replace(&mut self.touched, BTreeMap::new());
+        if !policy.enabled() {
+            return Ok(())
+        }
+
+        // ---------------transaction---------------
+        let tx = self.store.tx("RecordHandler");
+            // expiration by age only affects the streams with records older than max_age, the age index is ordered by height.
+            // A retained head entry is only pruned after a new record, and then the stream is touched.
+            if policy.max_age > 0 {
+                let prefix = "rtid-";
+                for id in tx.keys(prefix) {
+                    // "rtid-<height:020>-<stream>", these unwrap() should never fail, or it's a serious code bug!
+                    let (at, stream) = id[prefix.len()..].split_at(20);
+                    let at: i64 = at.parse().unwrap();
+                    if height - at <= policy.max_age {
+                        break
+                    }
+
+                    let pseudonym: String = tx.get(&id).unwrap();
+                    streams.insert(stream[1..].to_string(), pseudonym);
+                    tx.remove(&id);
+                }
+            }
+
+            for (stream, pseudonym) in streams.iter() {
+                let rlid = rlid(stream);
+                let mut log: RecordLog = match tx.get(&rlid) {
+                    None => continue,
+                    Some(log) => log
+                };
+
+                // the log entries are the last records of the stream
+                let count: usize = tx.get(&rcid(stream)).unwrap_or(0);
+                let first = count.saturating_sub(log.entries.len());
+
+                let pruned = log.prune(policy, height);
+                if pruned.is_empty() {
+                    continue
+                }
+
+                info!("PRUNE-RECORDS - (stream = {:?}, #pruned = {:?})", stream, pruned.len());
+                for (i, entry) in pruned.iter().enumerate() {
+                    // the record reference is kept, anonymous attachments may still reference a pruned record
+                    tx.remove(&rnid(&entry.sig));
+                    tx.remove(&rec(pseudonym, first + i));
+                }
+
+                tx.set(&rlid, log)?;
+            }
+        Ok(())
+    }
+
+    // Records of the stream in chain order. An unknown stream has no records, for the others the requester must prove
+    // the pseudonym secret (owner) or present the disclosure of the stream profile (see RecordStreamQuery::authorize).
+    pub fn query(&mut self, snap: &Snapshot, req: RecordStreamQuery) -> Result<Vec<u8>> {
+        let stream = req.stream();
+        let pseudonym = req.pseudonym.encode();
+        info!("QUERY-RECORDS - (sid = {:?}, stream = {:?}, from = {:?}, limit = {:?})", req.sid, stream, req.from_index, req.limit);
+
+        let count: usize = snap.get(&rcid(&stream)).unwrap_or(0);
+        let mut records = Vec::<Record>::new();
+
+        // records removed by the retention policy are before the retained log
+        let first = match snap.get::<RecordLog>(&rlid(&stream)) {
+            None => 0,
+            Some(log) => count.saturating_sub(log.entries.len())
+        };
+
+        if count > 0 {
+            let requester: Subject = snap.get(&sid(&req.sid)).ok_or("No requester subject found!")?;
+            req.authorize(&requester, &self.cfg.peers_keys, self.cfg.threshold)?;
+        }
+
+        // the tail of the stream is the last records up to the limit, a cursor starts after the record of the cursor
+        let from_index = match (&req.from_prev, req.from_index) {
+            (Some(prev), _) => self.cursor(snap, &stream, prev, first)?,
+            (None, TAIL) => count.saturating_sub(req.limit),
+            (None, index) => index
+        }.max(first);
+
+        let to_index = count.min(from_index.saturating_add(req.limit));
+        for index in from_index..to_index {
+            let record: Record = snap.get(&rec(&pseudonym, index)).ok_or("Record not found in the stream sequence!")?;
+            records.push(record);
+        }
+
+        let res = RecordStreamResult::sign(req.sig.id(), from_index, records, &self.cfg.secret, &self.cfg.pkey, self.cfg.index()?);
+        let msg = Response::QResult(QResult::QRecordStream(res));
+        encode(&msg)
+    }
+
+    // stream position after the record of the cursor, the page can't continue a record removed by the retention policy
+    fn cursor(&self, snap: &Snapshot, stream: &str, prev: &str, first: usize) -> Result<usize> {
+        if prev == OPEN {
+            return match first {
+                0 => Ok(0),
+                _ => Err("Records after the cursor were removed by the retention policy!".into())
+            }
+        }
+
+        let owner: Option<String> = snap.get(&rrid(prev));
+        let index: usize = match owner {
+            Some(owner) if owner == stream => snap.get(&rnid(prev)).ok_or("Records after the cursor were removed by the retention policy!")?,
+            _ => return Err("Cursor record not found in the stream!".into())
+        };
+
+        Ok(index + 1)
+    }
+}
+
+// valid bases of the record signatures, the pseudonym master-key and the typed keys already negotiated (key-pairs by kid)
+pub fn record_bases(cfg: &Config, key: impl Fn(&str) -> Option<MasterKeyPair>) -> Result<Vec<RistrettoPoint>> {
+    let pmkey = key(PMASTER).ok_or("Pseudonym master-key unavailable!")?;
+
+    let mut bases = vec![pmkey.public];
+    bases.extend(cfg.typed_kids().iter().filter_map(|kid| key(kid)).map(|pair| pair.public));
+    Ok(bases)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core_fpi::{rnd_scalar, uuid, G, Scalar};
+    use core_fpi::shares::Share;
+    use crate::config::tests::test_config;
+
+    #[test]
+    fn test_query_access() {
+        let path = std::env::temp_dir().join(format!("fedpi-records-{}", uuid()));
+        let store = Arc::new(AppDB::new(path.to_str().unwrap()));
+        let cfg = Arc::new(test_config());
+        let mut handler = RecordHandler::new(cfg.clone(), store.clone());
+
+        let sig_s = rnd_scalar();
+        let mut reader = Subject::new("s-id:reader");
+        let (_, skey) = reader.evolve(sig_s);
+        reader.keys.push(skey.clone());
+
+        let base = rnd_scalar() * G;
+        let secret = rnd_scalar();
+        let pseudonym = secret * base;
+        let first = Record::sign(OPEN, RecordType::Owned, RecordData::inline("JSON", b"{}".to_vec(), Vec::new()), &base, &secret, &pseudonym);
+        let last = Record::sign(&first.sig.encoded, RecordType::Owned, RecordData::inline("JSON", b"{}".to_vec(), Vec::new()), &base, &secret, &pseudonym);
+
+        let stream = stream_id(&pseudonym);
+        {
+            let tx = store.tx("test");
+            tx.set(&sid("s-id:reader"), reader).unwrap();
+            tx.set(&rrid(&first.sig.encoded), stream.clone()).unwrap();
+            tx.set(&rnid(&first.sig.encoded), 0usize).unwrap();
+            tx.set(&rec(&pseudonym.encode(), 0), first.clone()).unwrap();
+            tx.set(&rrid(&last.sig.encoded), stream.clone()).unwrap();
+            tx.set(&rnid(&last.sig.encoded), 1usize).unwrap();
+            tx.set(&rec(&pseudonym.encode(), 1), last.clone()).unwrap();
+            tx.set(&rcid(&stream), 2usize).unwrap();
+        }
+        store.commit(1);
+
+        let result = |data: Vec<u8>| match decode(&data).unwrap() {
+            Response::QResult(QResult::QRecordStream(res)) => res,
+            _ => panic!("Unexpected response!")
+        };
+
+        {
+            let snap = store.snapshot();
+
+            // the owner proves the pseudonym secret
+            let query = RecordStreamQuery::sign_owner("s-id:reader", &pseudonym, &base, 0, 10, &secret, &sig_s, &skey);
+            let res = result(handler.query(&snap, query.clone()).unwrap());
+            assert!(res.from_index == 0 && res.records.len() == 2);
+            assert!(res.check(&query, &cfg.peers_keys) == Ok(()));
+
+            // knowing the pseudonym is not enough
+            let query = RecordStreamQuery::sign_owner("s-id:reader", &pseudonym, &base, 0, 10, &rnd_scalar(), &sig_s, &skey);
+            assert!(handler.query(&snap, query) == Err("Stream access denied, invalid owner signature!".into()));
+
+            // the last record
+            let query = RecordStreamQuery::sign_owner("s-id:reader", &pseudonym, &base, TAIL, 1, &secret, &sig_s, &skey);
+            let res = result(handler.query(&snap, query).unwrap());
+            assert!(res.from_index == 1 && res.records.len() == 1 && res.records[0].sig.encoded == last.sig.encoded);
+
+            // cursor paging, the records after the cursor record
+            let query = RecordStreamQuery::sign_owner_after("s-id:reader", &pseudonym, &base, &first.sig.encoded, 10, &secret, &sig_s, &skey);
+            let res = result(handler.query(&snap, query.clone()).unwrap());
+            assert!(res.from_index == 1 && res.records.len() == 1 && res.check(&query, &cfg.peers_keys) == Ok(()));
+
+            let query = RecordStreamQuery::sign_owner_after("s-id:reader", &pseudonym, &base, OPEN, 1, &secret, &sig_s, &skey);
+            let res = result(handler.query(&snap, query.clone()).unwrap());
+            assert!(res.from_index == 0 && res.records[0].sig.encoded == first.sig.encoded && res.check(&query, &cfg.peers_keys) == Ok(()));
+
+            let query = RecordStreamQuery::sign_owner_after("s-id:reader", &pseudonym, &base, &last.sig.encoded, 10, &secret, &sig_s, &skey);
+            assert!(result(handler.query(&snap, query).unwrap()).records.is_empty());
+
+            // the cursor must be a record of the stream
+            let o_secret = rnd_scalar();
+            let o_record = Record::sign(OPEN, RecordType::Owned, RecordData::inline("JSON", b"{}".to_vec(), Vec::new()), &base, &o_secret, &(o_secret * base));
+            let query = RecordStreamQuery::sign_owner_after("s-id:reader", &pseudonym, &base, &o_record.sig.encoded, 10, &secret, &sig_s, &skey);
+            assert!(handler.query(&snap, query) == Err("Cursor record not found in the stream!".into()));
+
+            // an unknown stream has no records
+            let o_secret = rnd_scalar();
+            let query = RecordStreamQuery::sign_owner("s-id:reader", &(o_secret * base), &base, 0, 10, &o_secret, &sig_s, &skey);
+            assert!(result(handler.query(&snap, query).unwrap()).records.is_empty());
+        }
+
+        drop(handler);
+        drop(store);
+        std::fs::remove_dir_all(&path).ok();
+    }
+
+    #[test]
+    fn test_redelivery() {
+        let path = std::env::temp_dir().join(format!("fedpi-records-{}", uuid()));
+        let store = Arc::new(AppDB::new(path.to_str().unwrap()));
+        let mut handler = RecordHandler::new(Arc::new(test_config()), store.clone());
+
+        let pmkey = MasterKeyPair { kid: PMASTER.into(), share: Share { i: 1, yi: rnd_scalar() }, public: rnd_scalar() * G };
+        let base = pmkey.public;
+        store.set_key(pmkey);
+
+        let secret = rnd_scalar();
+        let pseudonym = secret * base;
+        let new = |prev: &str, data: &[u8]| {
+            let record = Record::sign(prev, RecordType::Owned, RecordData::inline("JSON", Vec::new(), data.to_vec()), &base, &secret, &pseudonym);
+            NewRecord { record, pseudonym, base, token: None }
+        };
+
+        let first = new(OPEN, b"{\"n\": 1}");
+        let stream = first.stream();
+
+        // the same record twice in the block (a client retry), the first write is already the stream head
+        handler.deliver(first.clone(), 1).unwrap();
+        assert!(handler.deliver(first.clone(), 1) == Err("Duplicate record!".into()));
+        store.commit(1);
+
+        // and in a later block (a replay at the head)
+        assert!(handler.deliver(first.clone(), 2) == Err("Duplicate record!".into()));
+
+        // a new record with a distinct payload is accepted
+        let second = new(&first.record.sig.encoded, b"{\"n\": 2}");
+        handler.deliver(second.clone(), 2).unwrap();
+        assert!(handler.deliver(second.clone(), 2) == Err("Duplicate record!".into()));
+
+        // a replay of the first one after it is no longer the head, and fails the chain check
+        assert!(handler.deliver(first.clone(), 2) == Err("Field Constraint - (prev, Record is not part of the stream)".into()));
+        store.commit(2);
+
+        assert!(store.get::<usize>(&rcid(&stream)) == Some(2));
+        assert!(store.get::<Record>(&rec(&pseudonym.encode(), 1)).unwrap().sig.encoded == second.record.sig.encoded);
+
+        drop(handler);
+        drop(store);
+        std::fs::remove_dir_all(&path).ok();
+    }
+
+    #[test]
+    fn test_prune() {
+        let path = std::env::temp_dir().join(format!("fedpi-prune-{}", uuid()));
+        let mut cfg = test_config();
+        cfg.retention = Retention { keep_last: 0, max_age: 2, tombstones: true };
+        let cfg = Arc::new(cfg);
+
+        let pmkey = MasterKeyPair { kid: PMASTER.into(), share: Share { i: 1, yi: rnd_scalar() }, public: rnd_scalar() * G };
+        let base = pmkey.public;
+
+        // independent nodes with the same policy and the same blocks
+        let nodes: Vec<(Arc<AppDB>, RecordHandler)> = (0..2).map(|i| {
+            let store = Arc::new(AppDB::new(path.join(format!("peer-{}", i)).to_str().unwrap()));
+            store.set_key(pmkey.clone());
+            let handler = RecordHandler::new(cfg.clone(), store.clone());
+            (store, handler)
+        }).collect();
+
+        let new = |prev: &str, secret: &Scalar| {
+            let pseudonym = secret * base;
+            let record = Record::sign(prev, RecordType::Owned, RecordData::inline("JSON", b"{}".to_vec(), Vec::new()), &base, secret, &pseudonym);
+            NewRecord { record, pseudonym, base, token: None }
+        };
+
+        let (a_secret, b_secret) = (rnd_scalar(), rnd_scalar());
+        let a0 = new(OPEN, &a_secret);
+        let a1 = new(&a0.record.sig.encoded, &a_secret);
+        let b0 = new(OPEN, &b_secret);
+        let (a_stream, b_stream) = (a0.stream(), b0.stream());
+
+        let blocks = vec![vec![a0.clone(), b0.clone()], vec![a1.clone()], vec![], vec![]];
+        let mut nodes = nodes;
+        for (height, block) in (1..).zip(blocks) {
+            let states: Vec<AppState> = nodes.iter_mut().map(|(store, handler)| {
+                for new in block.iter() {
+                    handler.deliver(new.clone(), height).unwrap();
+                }
+
+                handler.prune(height).unwrap();
+                store.commit(height)
+            }).collect();
+
+            assert!(states[0].hash == states[1].hash);
+        }
+
+        for (store, _) in nodes.iter() {
+            // a0 expired at height 4, the heads of both streams are retained
+            let log: RecordLog = store.get(&rlid(&a_stream)).unwrap();
+            assert!(log.entries.len() == 1 && log.entries[0].sig == a1.record.sig.encoded && log.tombstone.is_some());
+            assert!(store.get::<Record>(&rec(&a0.pseudonym.encode(), 0)).is_none());
+            assert!(store.get::<Record>(&rec(&a1.pseudonym.encode(), 1)).is_some());
+            assert!(store.get::<usize>(&rnid(&a0.record.sig.encoded)).is_none());
+            assert!(store.get::<RecordLog>(&rlid(&b_stream)).unwrap().entries.len() == 1);
+
+            // the reference of the pruned record is kept for the anonymous attachments
+            assert!(store.get::<String>(&rrid(&a0.record.sig.encoded)) == Some(a_stream.clone()));
+
+            // only the age index of the unexpired records remains
+            let ages: Vec<String> = store.scan_prefix("rtid-").map(|(id, _)| id).collect();
+            assert!(ages == vec![rtid(2, &a_stream)]);
+        }
+
+        drop(nodes);
+        std::fs::remove_dir_all(&path).ok();
+    }
+}
\ No newline at end of file
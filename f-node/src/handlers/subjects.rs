@@ -3,16 +3,21 @@ use log::info;
 
 use core_fpi::Result;
 use core_fpi::ids::*;
+use core_fpi::recovery::*;
+use core_fpi::authorizations::Authorizations;
+use core_fpi::messages::*;
 
+use crate::config::Config;
 use crate::db::*;
 
 pub struct SubjectHandler {
+    cfg: Arc<Config>,
     store: Arc<AppDB>
 }
 
 impl SubjectHandler {
-    pub fn new(store: Arc<AppDB>) -> Self {
-        Self { store }
+    pub fn new(cfg: Arc<Config>, store: Arc<AppDB>) -> Self {
+        Self { cfg, store }
     }
 
     pub fn deliver(&mut self, subject: Subject) -> Result<()> {
@@ -20,19 +25,275 @@ impl SubjectHandler {
         let sid = sid(&subject.sid);
 
         // ---------------transaction---------------
-        let tx = self.store.tx();
+        let tx = self.store.tx("SubjectHandler");
             // check signatures and constraints
-            let current: Option<Subject> = tx.get(&sid);
+            let current: Option<Subject> = tx.get_or_absent(&sid);
             subject.check(&current)?;
 
-            match current {
-                None => tx.set(&sid, subject),
+            // reserved namespaces are only checked on creation
+            if current.is_none() {
+                self.cfg.namespaces.check(&subject.sid)?;
+            }
+
+            let merged = match current {
+                None => subject,
                 Some(mut current) => {
                     current.merge(subject);
-                    tx.set(&sid, current)
+                    current
+                }
+            };
+
+            // the trustees shares of a superseded key don't recover the active key, the subject must setup a new recovery
+            let recid = recid(&merged.sid);
+            if let Some(recovery) = tx.get::<Recovery>(&recid) {
+                if !recovery.protects(&merged) {
+                    info!("INVALIDATE-RECOVERY - (sid = {:?})", merged.sid);
+                    tx.remove(&recid);
+                }
+            }
+
+            tx.set(&sid, merged)
+    }
+
+    // the committed subject, for the restore of the local store with a recovered key
+    pub fn query(&mut self, snap: &Snapshot, req: SubjectQuery) -> Result<Vec<u8>> {
+        info!("QUERY-SUBJECT - (sid = {:?})", req.sid);
+
+        let subject: Subject = snap.get(&sid(&req.sid)).ok_or("Subject not found!")?;
+        let res = SubjectResult::sign(req.sig.id(), subject, &self.cfg.secret, &self.cfg.pkey, self.cfg.index()?);
+        let msg = Response::QResult(QResult::QSubjectResult(res));
+        encode(&msg)
+    }
+
+    // replaces any previous recovery of the subject
+    pub fn deliver_recovery(&mut self, recovery: Recovery) -> Result<()> {
+        info!("DELIVER-RECOVERY - (sid = {:?}, threshold = {:?}, #trustees = {:?})", recovery.sid, recovery.threshold, recovery.trustees.len());
+        let tids: Vec<String> = recovery.trustees.iter().map(|item| sid(&item.tid)).collect();
+        let sid = sid(&recovery.sid);
+        let recid = recid(&recovery.sid);
+
+        // ---------------transaction---------------
+        let tx = self.store.tx("SubjectHandler");
+            let subject: Subject = tx.get(&sid).ok_or("Subject not found!")?;
+            if !recovery.protects(&subject) {
+                return Err("The recovery doesn't protect the active subject-key!".into())
+            }
+
+            recovery.check(&subject)?;
+
+            // trustees must be registered subjects
+            for (item, tid) in recovery.trustees.iter().zip(tids.iter()) {
+                if !tx.contains(tid) {
+                    return Err(format!("No trustee subject found: {}", item.tid))
+                }
+            }
+
+            tx.set(&recid, recovery)
+    }
+
+    // Purges the subject, authorizations, recovery and the consent/disclosure evidences.
+    // Only the removed global keys are folded into the app-state, so all peers reach the same state hash.
+    // The disclosure evidences and served responses are node-local, each peer has its own set.
+    pub fn deliver_erase(&mut self, erase: Erase) -> Result<()> {
+        info!("DELIVER-ERASE - (sid = {:?})", erase.sid);
+        let sid = sid(&erase.sid);
+        let aid = aid(&erase.sid);
+
+        // ---------------transaction---------------
+        let tx = self.store.tx("SubjectHandler");
+            if !tx.contains(&sid) {
+                return Err("Subject not found!".into())
+            }
+
+            // others may still disclose the subject profiles, revoked consents are removed from the authorizations
+            for other in tx.keys("aid-") {
+                if other == aid {
+                    continue
+                }
+
+                let auths: Authorizations = tx.get(&other).ok_or("Authorizations not found!")?;
+                if auths.authorized_profiles(&erase.sid).is_some() {
+                    return Err("Subject is the target of active consents!".into())
                 }
             }
-        
+
+            // exact keys are not erased by prefix, "sid-<sid>" is also the prefix of other subject-ids
+            for id in [sid, aid, recid(&erase.sid)].iter() {
+                if tx.contains(id) {
+                    tx.remove(id);
+                }
+            }
+
+            let n_keys = tx.erase(&[&cid(&erase.sid, "")]);
+            let n_local = tx.erase_local(&[&did(&erase.sid, ""), &drid(&erase.sid, "")]);
+            info!("ERASED - (sid = {:?}, #evidences = {:?}, #local = {:?})", erase.sid, n_keys, n_local);
         Ok(())
     }
+}
+
+//-----------------------------------------------------------------------------------------------------------
+// Unit tests
+//-----------------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core_fpi::{rnd_scalar, uuid, Scalar};
+    use core_fpi::authorizations::*;
+    use core_fpi::disclosures::DiscloseRequest;
+    use crate::config::tests::test_config;
+    use crate::handlers::authorizations::AuthorizationHandler;
+
+    fn subject(sid: &str) -> (Scalar, SubjectKey, Subject) {
+        let sig_s = rnd_scalar();
+        let mut subject = Subject::new(sid);
+        let (_, skey) = subject.evolve(sig_s);
+
+        let mut profile = Profile::new("HealthCare");
+        profile.push(profile.evolve(sid, "https://profile-url.org", false, &sig_s, &skey).1);
+        subject.push(profile).keys.push(skey.clone());
+
+        (sig_s, skey, subject)
+    }
+
+    #[test]
+    fn test_erase() {
+        let (sig_s1, skey1, sub1) = subject("s-id:shumy");
+        let (sig_s2, skey2, sub2) = subject("s-id:target");
+        let consent = Consent::sign("s-id:shumy", ConsentType::Consent, "s-id:target", &["HealthCare".to_string()], &sig_s1, &skey1);
+
+        // a disclosure is only served (and stored) by the first peer
+        let disclose = DiscloseRequest::sign("s-id:shumy", "s-id:target", &["HealthCare".to_string()], false, &sig_s1, &skey1);
+        let disclosure_id = did(&disclose.sid, disclose.sig.id());
+        let response_id = drid(&disclose.sid, disclose.sig.id());
+
+        // two peers delivering the same transactions
+        let mut hashes = Vec::new();
+        for peer in 0..2 {
+            let path = std::env::temp_dir().join(format!("fedpi-subjects-{}", uuid()));
+            let store = Arc::new(AppDB::new(path.to_str().unwrap()));
+            let mut handler = SubjectHandler::new(Arc::new(test_config()), store.clone());
+            let mut auth_handler = AuthorizationHandler::new(Arc::new(test_config()), store.clone());
+
+            handler.deliver(sub1.clone()).unwrap();
+            handler.deliver(sub2.clone()).unwrap();
+            auth_handler.deliver(consent.clone()).unwrap();
+            let before = store.commit(1);
+
+            if peer == 0 {
+                store.set_local(&disclosure_id, disclose.clone());
+                store.set_local(&response_id, b"served".to_vec());
+            }
+
+            // the target of an active consent can't be erased
+            let erase = Erase::sign("s-id:target", &sig_s2, &skey2);
+            assert!(handler.deliver_erase(erase.clone()) == Err("Subject is the target of active consents!".into()));
+
+            // but the consenting subject can
+            handler.deliver_erase(Erase::sign("s-id:shumy", &sig_s1, &skey1)).unwrap();
+            let after = store.commit(2);
+            assert!(after.hash != before.hash);
+
+            let erased: Option<Subject> = store.get(&sid("s-id:shumy"));
+            assert!(erased.is_none());
+            assert!(store.scan_prefix(&aid("s-id:shumy")).next().is_none());
+            assert!(store.scan_prefix(&cid("s-id:shumy", "")).next().is_none());
+            assert!(store.scan_prefix(&did("s-id:shumy", "")).next().is_none());
+            assert!(store.scan_prefix(&drid("s-id:shumy", "")).next().is_none());
+
+            let other: Option<Subject> = store.get(&sid("s-id:target"));
+            assert!(other.is_some());
+            hashes.push(after.hash);
+
+            // the consent is gone with the erased subject
+            handler.deliver_erase(erase).unwrap();
+            assert!(handler.deliver_erase(Erase::sign("s-id:shumy", &sig_s1, &skey1)) == Err("Subject not found!".into()));
+            store.commit(3);
+
+            let erased: Option<Subject> = store.get(&sid("s-id:target"));
+            assert!(erased.is_none());
+
+            drop(handler);
+            drop(auth_handler);
+            drop(store);
+            std::fs::remove_dir_all(&path).ok();
+        }
+
+        assert!(hashes[0] == hashes[1]);
+    }
+
+    #[test]
+    fn test_deliver_recovery() {
+        let path = std::env::temp_dir().join(format!("fedpi-subjects-{}", uuid()));
+        let store = Arc::new(AppDB::new(path.to_str().unwrap()));
+        let mut handler = SubjectHandler::new(Arc::new(test_config()), store.clone());
+
+        let (sig_s, skey, sub) = subject("s-id:shumy");
+        handler.deliver(sub).unwrap();
+        handler.deliver(subject("s-id:trustee-0").2).unwrap();
+        store.commit(1);
+
+        let trustees = vec!["s-id:trustee-0".to_string(), "s-id:trustee-1".to_string()];
+        let (recovery, shares) = Recovery::setup("s-id:shumy", &trustees, 2, &sig_s, &skey).unwrap();
+
+        // trustees must be registered subjects
+        assert!(handler.deliver_recovery(recovery.clone()) == Err("No trustee subject found: s-id:trustee-1".into()));
+        handler.deliver(subject("s-id:trustee-1").2).unwrap();
+
+        let (unknown, _) = Recovery::setup("s-id:unknown", &trustees, 2, &sig_s, &skey).unwrap();
+        assert!(handler.deliver_recovery(unknown) == Err("Subject not found!".into()));
+
+        let (other, _) = Recovery::setup("s-id:shumy", &trustees, 2, &rnd_scalar(), &skey).unwrap();
+        assert!(handler.deliver_recovery(other) == Err("Recovery shares don't match the subject-key!".into()));
+
+        handler.deliver_recovery(recovery.clone()).unwrap();
+        store.commit(2);
+
+        let stored: Recovery = store.get(&recid("s-id:shumy")).unwrap();
+        assert!(stored.sig.id() == recovery.sig.id());
+        assert!(RecoveryShare::recover(&shares) == Ok(sig_s));
+
+        // the evolution of the subject-key invalidates the recovery
+        let current: Subject = store.get(&sid("s-id:shumy")).unwrap();
+        let (_, n_skey) = current.evolve(sig_s);
+        let mut update = Subject::new("s-id:shumy");
+        update.keys.push(n_skey);
+        handler.deliver(update).unwrap();
+        store.commit(3);
+
+        assert!(store.get::<Recovery>(&recid("s-id:shumy")).is_none());
+        assert!(handler.deliver_recovery(recovery) == Err("The recovery doesn't protect the active subject-key!".into()));
+
+        drop(handler);
+        drop(store);
+        std::fs::remove_dir_all(&path).ok();
+    }
+
+    #[test]
+    fn test_subject_query() {
+        let path = std::env::temp_dir().join(format!("fedpi-subjects-{}", uuid()));
+        let store = Arc::new(AppDB::new(path.to_str().unwrap()));
+        let cfg = Arc::new(test_config());
+        let mut handler = SubjectHandler::new(cfg.clone(), store.clone());
+
+        let (sig_s, _, sub) = subject("s-id:shumy");
+        handler.deliver(sub).unwrap();
+        store.commit(1);
+
+        let query = SubjectQuery::sign("s-id:shumy", &sig_s);
+        let data = handler.query(&store.snapshot(), query.clone()).unwrap();
+        match decode::<Response>(&data).unwrap() {
+            Response::QResult(QResult::QSubjectResult(res)) => {
+                assert!(res.check(&query, &cfg.peers_keys) == Ok(()));
+                assert!(res.subject.profiles.contains_key("HealthCare"));
+            },
+            _ => panic!("Expecting a subject result!")
+        }
+
+        let query = SubjectQuery::sign("s-id:unknown", &sig_s);
+        assert!(handler.query(&store.snapshot(), query) == Err("Subject not found!".into()));
+
+        drop(handler);
+        drop(store);
+        std::fs::remove_dir_all(&path).ok();
+    }
 }
\ No newline at end of file
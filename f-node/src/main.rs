@@ -8,6 +8,8 @@ use env_logger::fmt::Color;
 use log::info;
 use log::Level::{Info, Warn, Error};
 
+use core_fpi::fingerprint;
+
 mod db;
 mod config;
 mod handlers;
@@ -30,12 +32,11 @@ fn main() {
         .get_matches();
     
     let home = matches.value_of("home").unwrap_or(".");
-    let home = if home.ends_with('/') { &home[..home.len()-1] } else { home };
 
     // read configuration from HOME/config/app.config.toml file
-    let cfg = config::Config::new(&home);
+    let cfg = config::Config::new(home);
 
-    let addr = format!("127.0.0.1:{}", cfg.port).parse().unwrap();
+    let addr = format!("{}:{}", cfg.bind, cfg.port).parse().expect("Invalid bind address!");
 
     // config logger
     let cfg_clone = cfg.clone();
@@ -56,7 +57,13 @@ fn main() {
         .filter(None, cfg.log)
         .init();
 
-    info!("Initializing FedPI Node (Tendermint) at port: {}", cfg.port);
+    info!("Initializing FedPI Node (Tendermint) at: {}:{}", cfg.bind, cfg.port);
+
+    // fingerprints for the out-of-band verification of the peer keys
+    info!("NODE-KEY - (name = {:?}, fingerprint = {:?})", cfg.name, fingerprint(&cfg.pkey));
+    for peer in cfg.peers.iter() {
+        info!("PEER - (name = {:?}, fingerprint = {:?})", peer.name, fingerprint(&peer.pkey));
+    }
 
     // init message processor (generic processor that doesn't depend on tendermint)
     let prc = processor::Processor::new(cfg);
@@ -1,61 +1,110 @@
 use std::sync::Arc;
-use std::time::Duration;
 
 use log::{info, error};
 
-use core_fpi::{Result, Constraints};
+use core_fpi::{Result, Constraints, KeyEncoder};
 use core_fpi::ids::*;
+use core_fpi::records::*;
 use core_fpi::messages::*;
+use core_fpi::governance::*;
 
 use crate::handlers::keys::*;
+use crate::handlers::records::*;
 use crate::handlers::subjects::*;
 use crate::handlers::authorizations::*;
 use crate::handlers::disclosures::*;
+use crate::handlers::governance::*;
 
-use crate::config::Config;
+use crate::config::{Peer, Config};
 use crate::db::*;
 
-const TIMESTAMP_THRESHOLD: u64 = 60;
-
 /* TODO: replay attack protections.
     1) Requests should be idempotent and have limited timestamps ranges
     2) Responses should be encrypted with the current subject-key. Even if someone uses the same request, responses can't be read.
 */
 
+// metadata of a commit accepted by the filter, reported on the check-tx response
+pub struct TxInfo {
+    pub action: &'static str,
+    pub sid: String                 // the stream-id for records, there is no subject
+}
+
+fn action(msg: &Commit) -> &'static str {
+    match msg {
+        Commit::Evidence(evd) => match evd {
+            Evidence::EMasterKey(_) => "master-key",
+            Evidence::EMasterKeyRefresh(_) => "master-key-refresh",
+            Evidence::EMasterKeyReshare(_) => "master-key-reshare",
+            Evidence::EErase(_) => "erase",
+            Evidence::EPeerChange(_) => "peer-change",
+            Evidence::EPedersenKey(_) => "pedersen-key"
+        },
+
+        Commit::Value(value) => match value {
+            Value::VSubject(_) => "subject",
+            Value::VNewRecord(_) => "record",
+            Value::VConsent(_) => "consent",
+            Value::VProfileConsent(_) => "profile-consent",
+            Value::VRecovery(_) => "recovery",
+            Value::VPeerSet(_) => "peer-set"
+        }
+    }
+}
+
 // decode and log dispatch messages to the respective handlers
 pub struct Processor {
+    cfg: Arc<Config>,
     store: Arc<AppDB>,
+    reload: bool,
+    block: Option<i64>,         // height of the open block (between begin-block and commit)
 
     mkey_handler: MasterKeyHandler,
     subject_handler: SubjectHandler,
+    record_handler: RecordHandler,
     auth_handler: AuthorizationHandler,
-    disclosure_handler: DisclosureHandler
+    disclosure_handler: DisclosureHandler,
+    governance_handler: GovernanceHandler
 }
 
 impl Processor {
-    pub fn new(cfg: Config) -> Self {
-        let cfg = Arc::new(cfg);
+    pub fn new(mut cfg: Config) -> Self {
+        let store = Arc::new(AppDB::new(&cfg.paths.store));
 
-        let path = format!("{}/data", cfg.home);
-        let store = Arc::new(AppDB::new(&path));
-        
+        // a committed peer-set overrides the peers in the config file
+        let pset: Option<PeerSet> = store.get(PEERS);
+        if let Some(pset) = pset {
+            info!("PEER-SET - (threshold = {:?}, #peers = {:?})", pset.threshold, pset.peers.len());
+            reconfig(&mut cfg, &pset);
+        }
+
+        cfg.change = store.get(PEER_CHANGE);
+
+        let cfg = Arc::new(cfg);
         Self {
+            cfg: cfg.clone(),
             store: store.clone(),
+            reload: false,
+            block: None,
 
             mkey_handler: MasterKeyHandler::new(cfg.clone(), store.clone()),
-            subject_handler: SubjectHandler::new(store.clone()),
-            auth_handler: AuthorizationHandler::new(store.clone()),
+            subject_handler: SubjectHandler::new(cfg.clone(), store.clone()),
+            record_handler: RecordHandler::new(cfg.clone(), store.clone()),
+            auth_handler: AuthorizationHandler::new(cfg.clone(), store.clone()),
             disclosure_handler: DisclosureHandler::new(cfg.clone(), store.clone()),
+            governance_handler: GovernanceHandler::new(cfg.clone(), store.clone())
         }
     }
 
     pub fn request(&mut self, data: &[u8]) -> Result<Vec<u8>> {
         let msg: Request = decode(data)?;
         
+        // all reads of the request are from the same committed state
+        let snap = self.store.snapshot();
+
         // check field constraints, signature and timestamp range
         let sid = sid(msg.sid());
-        let subject: Subject = self.store.get(&sid).ok_or("Subject not found!")?;
-        msg.verify(&subject, Duration::from_secs(TIMESTAMP_THRESHOLD))?;
+        let subject: Subject = snap.get(&sid).ok_or("Subject not found!")?;
+        msg.verify(&subject, self.cfg.timestamps.request(&msg))?;
 
         match msg {
             Request::Negotiate(neg) => match neg {
@@ -63,35 +112,100 @@ impl Processor {
                     self.mkey_handler.request(req).map_err(|e|{
                         error!("REQUEST-ERR - Negotiate::NMasterKeyRequest - {:?}", e);
                     e})
+                },
+                Negotiate::NMasterKeyRefresh(req) => {
+                    self.mkey_handler.refresh(req).map_err(|e|{
+                        error!("REQUEST-ERR - Negotiate::NMasterKeyRefresh - {:?}", e);
+                    e})
+                },
+                Negotiate::NMasterKeyReshare(req) => {
+                    self.mkey_handler.reshare(req).map_err(|e|{
+                        error!("REQUEST-ERR - Negotiate::NMasterKeyReshare - {:?}", e);
+                    e})
+                },
+                Negotiate::NMasterKeyReveal(req) => {
+                    self.mkey_handler.reveal(&snap, req).map_err(|e|{
+                        error!("REQUEST-ERR - Negotiate::NMasterKeyReveal - {:?}", e);
+                    e})
                 }
             },
             Request::Query(query) => match query {
                 Query::QDiscloseRequest(req) => {
-                    self.disclosure_handler.request(req).map_err(|e|{
+                    self.disclosure_handler.request(&snap, req).map_err(|e|{
                         error!("REQUEST-ERR - Query::QDiscloseRequest - {:?}", e);
                     e})
+                },
+                Query::QMasterKey(req) => {
+                    self.mkey_handler.query(&snap, req).map_err(|e|{
+                        error!("REQUEST-ERR - Query::QMasterKey - {:?}", e);
+                    e})
+                },
+                Query::QRecordStream(req) => {
+                    self.record_handler.query(&snap, req).map_err(|e|{
+                        error!("REQUEST-ERR - Query::QRecordStream - {:?}", e);
+                    e})
+                },
+                Query::QConsents(req) => {
+                    self.auth_handler.query(&snap, req).map_err(|e|{
+                        error!("REQUEST-ERR - Query::QConsents - {:?}", e);
+                    e})
+                },
+                Query::QSubject(req) => {
+                    self.subject_handler.query(&snap, req).map_err(|e|{
+                        error!("REQUEST-ERR - Query::QSubject - {:?}", e);
+                    e})
                 }
             }
         }
     }
 
-    pub fn start(&self) {
-        info!("START-BLOCK");
+    pub fn start(&mut self, height: i64) {
+        info!("START-BLOCK - (height = {:?})", height);
         self.store.start();
+
+        let state = self.store.state();
+        if height != state.height + 1 {
+            error!("START-BLOCK - Unexpected height (expected = {:?}, received = {:?})", state.height + 1, height);
+        }
+
+        self.block = Some(height);
     }
 
     // check signature and timestamp range
-    pub fn filter(&self, data: &[u8]) -> Result<()> {
+    pub fn filter(&self, data: &[u8]) -> Result<TxInfo> {
         let msg: Commit = decode(data)?;
+        let snap = self.store.snapshot();
+
+        // records are keyed by the stream and authenticated by the pseudonym, there is no subject to lookup
+        if let Commit::Value(Value::VNewRecord(new)) = &msg {
+            let closed: Option<bool> = snap.get(&rxid(&new.stream()));
+            if closed.is_some() {
+                return Err("The stream is closed!".into())
+            }
+
+            let count: usize = snap.get(&rcid(&new.stream())).unwrap_or(0);
+            let last: Option<Record> = match count {
+                0 => None,
+                _ => Some(snap.get(&rec(&new.pseudonym.encode(), count - 1)).ok_or("Record not found in the stream sequence!")?)
+            };
+
+            if last.as_ref().map(Record::hash) == Some(new.record.hash()) {
+                return Err("Duplicate record!".into())
+            }
+
+            new.check(last.as_ref(), &record_bases(&self.cfg, |kid| snap.get(&mkpid(kid)))?, self.cfg.strict_meta)?;
+            return Ok(TxInfo { action: action(&msg), sid: new.stream() })
+        }
 
         let sid = sid(msg.sid());
-        let t_sub: Option<Subject> = self.store.get(&sid);
+        let t_sub: Option<Subject> = snap.get(&sid);
         let mut subject = t_sub.as_ref();
         
         // handle exception for creation
         if subject.is_none() {
             if let Commit::Value(value) = &msg {
                 if let Value::VSubject(sub) = value {
+                    self.cfg.namespaces.check(&sub.sid)?;
                     subject = Some(sub)
                 }
             }
@@ -102,10 +216,19 @@ impl Processor {
             return Err("Subject not found!".into());
         }
 
-        msg.verify(subject.unwrap(), Duration::from_secs(TIMESTAMP_THRESHOLD))
+        msg.verify(subject.unwrap(), self.cfg.timestamps.commit(&msg))?;
+        Ok(TxInfo { action: action(&msg), sid: msg.sid().into() })
     }
 
+    // timestamps are only checked on filter, the delivery must not depend on the local clock of each peer
     pub fn deliver(&mut self, data: &[u8]) -> Result<()> {
+        // blocks start at height 1, there is no genesis state to deliver against before that
+        match self.block {
+            None => return Err("Transaction delivered outside of a block!".into()),
+            Some(height) if height < 1 => return Err("Transaction delivered before genesis!".into()),
+            _ => ()
+        }
+
         let msg: Commit = decode(data)?;
         match msg {
             Commit::Evidence(evd) => match evd {
@@ -114,6 +237,36 @@ impl Processor {
                     self.mkey_handler.deliver(mkey).map_err(|e|{
                         error!("DELIVER-ERR - Evidence::EMasterKey - {:?}", e);
                     e})
+                },
+                Evidence::EMasterKeyRefresh(mkey) => {
+                    info!("DELIVER - Evidence::EMasterKeyRefresh");
+                    self.mkey_handler.deliver_refresh(mkey).map_err(|e|{
+                        error!("DELIVER-ERR - Evidence::EMasterKeyRefresh - {:?}", e);
+                    e})
+                },
+                Evidence::EMasterKeyReshare(reshared) => {
+                    info!("DELIVER - Evidence::EMasterKeyReshare");
+                    self.mkey_handler.deliver_reshare(reshared).map_err(|e|{
+                        error!("DELIVER-ERR - Evidence::EMasterKeyReshare - {:?}", e);
+                    e})
+                },
+                Evidence::EErase(erase) => {
+                    info!("DELIVER - Evidence::EErase");
+                    self.subject_handler.deliver_erase(erase).map_err(|e|{
+                        error!("DELIVER-ERR - Evidence::EErase - {:?}", e);
+                    e})
+                },
+                Evidence::EPeerChange(change) => {
+                    info!("DELIVER - Evidence::EPeerChange");
+                    self.governance_handler.deliver_change(change).map_err(|e|{
+                        error!("DELIVER-ERR - Evidence::EPeerChange - {:?}", e);
+                    e})
+                },
+                Evidence::EPedersenKey(mkey) => {
+                    info!("DELIVER - Evidence::EPedersenKey");
+                    self.mkey_handler.deliver_pedersen(mkey).map_err(|e|{
+                        error!("DELIVER-ERR - Evidence::EPedersenKey - {:?}", e);
+                    e})
                 }
             },
 
@@ -124,24 +277,393 @@ impl Processor {
                         error!("DELIVER-ERR - Value::VSubject - {:?}", e);
                     e})
                 },
+                Value::VNewRecord(record) => {
+                    info!("DELIVER - Value::VNewRecord");
+                    let height = self.block.unwrap_or_default();
+                    self.record_handler.deliver(record, height).map_err(|e|{
+                        error!("DELIVER-ERR - Value::VNewRecord - {:?}", e);
+                    e})
+                },
                 Value::VConsent(consent) => {
                     info!("DELIVER - Value::VConsent");
                     self.auth_handler.deliver(consent).map_err(|e|{
                         error!("DELIVER-ERR - Value::VConsent - {:?}", e);
                     e})
                 },
-                _ => Err("Not implemented!".into())
+                Value::VProfileConsent(bundle) => {
+                    info!("DELIVER - Value::VProfileConsent");
+                    self.auth_handler.deliver_bundle(bundle).map_err(|e|{
+                        error!("DELIVER-ERR - Value::VProfileConsent - {:?}", e);
+                    e})
+                },
+                Value::VRecovery(recovery) => {
+                    info!("DELIVER - Value::VRecovery");
+                    self.subject_handler.deliver_recovery(recovery).map_err(|e|{
+                        error!("DELIVER-ERR - Value::VRecovery - {:?}", e);
+                    e})
+                },
+                Value::VPeerSet(pset) => {
+                    info!("DELIVER - Value::VPeerSet");
+                    self.governance_handler.deliver(pset).map_err(|e|{
+                        error!("DELIVER-ERR - Value::VPeerSet - {:?}", e);
+                    e})?;
+
+                    // reload at the block boundary, so all peers switch at the same height
+                    self.reload = true;
+                    Ok(())
+                }
             }
         }
     }
 
-    pub fn commit(&self, height: i64) -> AppState {
+    pub fn commit(&mut self, height: i64) -> AppState {
+        self.block = None;
+        if let Err(e) = self.record_handler.prune(height) {
+            error!("PRUNE-ERR - {:?}", e);
+        }
+
         let state = self.store.commit(height);
-        info!("COMMIT - (height = {:?}, hash = {:?})", state.height, bs58::encode(&state.hash).into_string());
+        info!("COMMIT - (height = {:?}, hash = {:?})", state.height, state.hash.encode());
+
+        if self.reload {
+            self.reload = false;
+            self.reload_peers();
+        }
+
         state
     }
 
     pub fn state(&self) -> AppState {
         self.store.state()
     }
-}
\ No newline at end of file
+
+    // replace the handlers configuration with the committed peer-set
+    fn reload_peers(&mut self) {
+        let pset: PeerSet = match self.store.get(PEERS) {
+            None => return,
+            Some(pset) => pset
+        };
+
+        info!("RELOAD-PEERS - (threshold = {:?}, #peers = {:?})", pset.threshold, pset.peers.len());
+        let mut cfg = (*self.cfg).clone();
+        reconfig(&mut cfg, &pset);
+        cfg.change = self.store.get(PEER_CHANGE);
+
+        let cfg = Arc::new(cfg);
+        self.mkey_handler = MasterKeyHandler::new(cfg.clone(), self.store.clone());
+        self.subject_handler = SubjectHandler::new(cfg.clone(), self.store.clone());
+        self.record_handler = RecordHandler::new(cfg.clone(), self.store.clone());
+        self.auth_handler = AuthorizationHandler::new(cfg.clone(), self.store.clone());
+        self.disclosure_handler = DisclosureHandler::new(cfg.clone(), self.store.clone());
+        self.governance_handler = GovernanceHandler::new(cfg.clone(), self.store.clone());
+        self.cfg = cfg;
+
+        // key-pairs reshared to this peer-set replace the previous ones
+        self.mkey_handler.activate();
+    }
+}
+
+fn reconfig(cfg: &mut Config, pset: &PeerSet) {
+    let peers = pset.peers.iter().map(|(name, pkey)| Peer { name: name.clone(), pkey: *pkey }).collect();
+    cfg.set_peers(pset.threshold, peers);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core_fpi::{rnd_scalar, uuid, G, RistrettoPoint};
+    use core_fpi::Scalar;
+    use core_fpi::keys::*;
+    use core_fpi::shares::Share;
+    use core_fpi::authorizations::*;
+    use core_fpi::disclosures::*;
+    use crate::config::Paths;
+    use crate::config::tests::{test_config, test_network};
+
+    // In-process network of real processors, every commit is filtered and delivered on all nodes in the same block.
+    struct Network {
+        home: std::path::PathBuf,
+        nodes: Vec<Processor>,
+        height: i64
+    }
+
+    impl Network {
+        fn new(threshold: usize, n: usize) -> Self {
+            let home = std::env::temp_dir().join(format!("fedpi-network-{}", uuid()));
+            let nodes = test_network(threshold, n, home.to_str().unwrap()).into_iter().map(Processor::new).collect();
+            Self { home, nodes, height: 0 }
+        }
+
+        fn peers(&self) -> (Vec<u8>, Vec<RistrettoPoint>, usize) {
+            let cfg = &self.nodes[0].cfg;
+            (cfg.peers_hash.clone(), cfg.peers_keys.clone(), cfg.threshold)
+        }
+
+        fn commit(&mut self, msg: Commit) {
+            let data = encode(&msg).unwrap();
+            self.height += 1;
+            let height = self.height;
+
+            let states: Vec<AppState> = self.nodes.iter_mut().map(|node| {
+                node.filter(&data).unwrap();
+                node.start(height);
+                node.deliver(&data).unwrap();
+                node.commit(height)
+            }).collect();
+
+            // the same state on every node
+            assert!(states.iter().all(|state| state.height == height && state.hash == states[0].hash));
+        }
+
+        fn request(&mut self, msg: Request) -> Vec<Response> {
+            let data = encode(&msg).unwrap();
+            self.nodes.iter_mut().map(|node| decode(&node.request(&data).unwrap()).unwrap()).collect()
+        }
+
+        // negotiate the master-key with the votes of all nodes, and commit the evidence
+        fn negotiate(&mut self, kid: &str, sig_s: &Scalar, skey: &SubjectKey) -> MasterKey {
+            let (peers_hash, pkeys, threshold) = self.peers();
+            let req = MasterKeyRequest::sign("s-id:admin", kid, &peers_hash, sig_s, skey);
+            let votes = self.request(Request::Negotiate(Negotiate::NMasterKeyRequest(req.clone()))).into_iter().map(|res| match res {
+                Response::Vote(Vote::VMasterKeyVote(vote)) => vote,
+                _ => panic!("Expecting a master-key vote!")
+            }).collect();
+
+            let mkey = MasterKey::sign("s-id:admin", req.sig.id(), kid, &peers_hash, votes, &pkeys, threshold, sig_s, skey).unwrap();
+            self.commit(Commit::Evidence(Evidence::EMasterKey(mkey.clone())));
+            mkey
+        }
+
+        fn disclose(&mut self, request: &DiscloseRequest) -> Vec<DiscloseResult> {
+            self.request(Request::Query(Query::QDiscloseRequest(request.clone()))).into_iter().map(|res| match res {
+                Response::QResult(QResult::QDiscloseResult(res)) => res,
+                _ => panic!("Expecting a disclose result!")
+            }).collect()
+        }
+    }
+
+    impl Drop for Network {
+        fn drop(&mut self) {
+            self.nodes.clear();
+            std::fs::remove_dir_all(&self.home).ok();
+        }
+    }
+
+    fn create(sid: &str, profiles: &[&str]) -> (Subject, Scalar, SubjectKey, Vec<Scalar>) {
+        let sig_s = rnd_scalar();
+        let mut subject = Subject::new(sid);
+        let (_, skey) = subject.evolve(sig_s);
+
+        let mut secrets = Vec::new();
+        for typ in profiles.iter() {
+            let mut profile = Profile::new(typ);
+            let (secret, location) = profile.evolve(sid, "https://url.org", false, &sig_s, &skey);
+            profile.push(location);
+            subject.push(profile);
+            secrets.push(secret);
+        }
+
+        subject.keys.push(skey.clone());
+        (subject, sig_s, skey, secrets)
+    }
+
+    // End-to-end guarantee over the node handlers: negotiate -> create -> consent -> disclose,
+    // the reconstructed pseudonym is (profile-secret * master-public)
+    #[test]
+    fn test_disclosure_pipeline() {
+        let mut network = Network::new(1, 4);
+        let (peers_hash, pkeys, threshold) = network.peers();
+
+        let (admin, admin_s, admin_key, _) = create("s-id:admin", &[]);
+        network.commit(Commit::Value(Value::VSubject(admin)));
+
+        let pmkey = network.negotiate(PMASTER, &admin_s, &admin_key);
+        network.negotiate(EMASTER, &admin_s, &admin_key);
+        assert!(pmkey.check(&peers_hash, &pkeys, threshold, None) == Ok(()));
+
+        // the target consents the disclosure of the profile to the verifier
+        let (target, target_s, target_key, secrets) = create("s-id:target", &["Assets"]);
+        let (verifier, verifier_s, verifier_key, _) = create("s-id:verifier", &[]);
+        network.commit(Commit::Value(Value::VSubject(target)));
+        network.commit(Commit::Value(Value::VSubject(verifier)));
+
+        let profiles = vec!["Assets".to_string()];
+        let consent = Consent::sign("s-id:target", ConsentType::Consent, "s-id:verifier", &profiles, &target_s, &target_key);
+        network.commit(Commit::Value(Value::VConsent(consent)));
+
+        let request = DiscloseRequest::sign("s-id:verifier", "s-id:target", &profiles, false, &verifier_s, &verifier_key);
+        let results = network.disclose(&request);
+
+        let disclosed = reconstruct_pseudonyms(&request, &results[..2*threshold + 1], &pkeys, threshold).unwrap();
+        assert!(disclosed.pseudonyms["Assets-https://url.org-0"] == secrets[0] * pmkey.public());
+
+        // any quorum of peers reconstructs the same pseudonym
+        let disclosed = reconstruct_pseudonyms(&request, &results[1..], &pkeys, threshold).unwrap();
+        assert!(disclosed.pseudonyms["Assets-https://url.org-0"] == secrets[0] * pmkey.public());
+
+        // without the consent there is nothing to disclose
+        let other = DiscloseRequest::sign("s-id:admin", "s-id:target", &profiles, false, &admin_s, &admin_key);
+        let res = network.nodes[0].request(&encode(&Request::Query(Query::QDiscloseRequest(other))).unwrap());
+        assert!(res == Err("Subject has not authorization to disclose profiles: [\"Assets\"]".into()));
+
+        //--------------------------------------------------
+        // Failure injection, one peer returns bad shares
+        // -------------------------------------------------
+        let pair = network.nodes[2].store.key(PMASTER).unwrap();
+        let bad = MasterKeyPair { kid: pair.kid.clone(), share: Share { i: pair.share.i, yi: rnd_scalar() }, public: pair.public };
+        network.nodes[2].store.set_key(bad);
+
+        let request = DiscloseRequest::sign("s-id:verifier", "s-id:target", &profiles, false, &verifier_s, &verifier_key);
+        let results = network.disclose(&request);
+
+        let quorum = &results[..2*threshold + 1];
+        assert!(reconstruct_pseudonyms(&request, quorum, &pkeys, threshold).err() == Some("Incorrect set of pseudo shares!".into()));
+        assert!(reconstruct_pseudonyms(&request, &results, &pkeys, threshold).err() == Some("Incorrect set of pseudo shares!".into()));
+
+        // the quorum of honest peers still reconstructs the pseudonym
+        let honest = vec![results[0].clone(), results[1].clone(), results[3].clone()];
+        let disclosed = reconstruct_pseudonyms(&request, &honest, &pkeys, threshold).unwrap();
+        assert!(disclosed.pseudonyms["Assets-https://url.org-0"] == secrets[0] * pmkey.public());
+    }
+
+    #[test]
+    fn test_consents_query() {
+        let mut network = Network::new(1, 4);
+        let (_, pkeys, _) = network.peers();
+
+        let (target, target_s, target_key, _) = create("s-id:target", &["Assets"]);
+        let (other, other_s, other_key, _) = create("s-id:target-b", &["Assets"]);
+        let (verifier, _, _, _) = create("s-id:verifier", &[]);
+        network.commit(Commit::Value(Value::VSubject(target)));
+        network.commit(Commit::Value(Value::VSubject(other)));
+        network.commit(Commit::Value(Value::VSubject(verifier)));
+
+        let profiles = vec!["Assets".to_string()];
+        let consent = Consent::sign_with_purpose("s-id:target", ConsentType::Consent, "s-id:verifier", &profiles, Some("audit"), &target_s, &target_key);
+        let revoke = Consent::sign_at(consent.sig.sig.timestamp + 1, "s-id:target", ConsentType::Revoke, "s-id:verifier", &profiles, None, &target_s, &target_key);
+        let unrelated = Consent::sign("s-id:target-b", ConsentType::Consent, "s-id:verifier", &profiles, &other_s, &other_key);
+        network.commit(Commit::Value(Value::VConsent(revoke)));
+        network.commit(Commit::Value(Value::VConsent(consent)));
+        network.commit(Commit::Value(Value::VConsent(unrelated)));
+
+        // the history of the subject only (the sid prefix selects other subjects), in signing order
+        let query = ConsentsQuery::sign("s-id:target", &target_s, &target_key);
+        for res in network.request(Request::Query(Query::QConsents(query.clone()))) {
+            let res = match res {
+                Response::QResult(QResult::QConsentsResult(res)) => res,
+                _ => panic!("Expecting a consents result!")
+            };
+
+            assert!(res.check(&query, &pkeys) == Ok(()));
+            assert!(res.consents.len() == 2);
+            assert!(matches!(res.consents[0].typ, ConsentType::Consent) && res.consents[0].purpose == Some("audit".into()));
+            assert!(matches!(res.consents[1].typ, ConsentType::Revoke) && res.consents[1].purpose.is_none());
+        }
+
+        // only the data-subject queries the consents
+        let forged = ConsentsQuery::sign("s-id:target", &other_s, &other_key);
+        let res = network.nodes[0].request(&encode(&Request::Query(Query::QConsents(forged))).unwrap());
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_local_timestamps() {
+        let home = std::env::temp_dir().join(format!("fedpi-network-{}", uuid()));
+        let mut cfgs = test_network(1, 4, home.to_str().unwrap());
+        cfgs[0].timestamps.consent = Some(120);
+        let mut nodes: Vec<Processor> = cfgs.into_iter().map(Processor::new).collect();
+
+        let (subject, sig_s, skey, _) = create("s-id:shumy", &["HealthCare"]);
+        let (target, _, _, _) = create("s-id:target", &["Assets"]);
+        let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs() as i64;
+        let consent = Consent::sign_at(now - 90, "s-id:shumy", ConsentType::Consent, "s-id:target", &["HealthCare".to_string()], None, &sig_s, &skey);
+
+        let blocks = vec![
+            vec![Commit::Value(Value::VSubject(subject)), Commit::Value(Value::VSubject(target))],
+            vec![Commit::Value(Value::VConsent(consent))]
+        ];
+
+        for (height, block) in (1..).zip(blocks) {
+            let states: Vec<AppState> = nodes.iter_mut().map(|node| {
+                node.start(height);
+                for msg in block.iter() {
+                    node.deliver(&encode(msg).unwrap()).unwrap();
+                }
+                node.commit(height)
+            }).collect();
+
+            // a block proposed by the node with the wider range is delivered in the same way by all nodes
+            assert!(states.iter().all(|state| state.hash == states[0].hash));
+        }
+
+        // the override only applies to the mempool of the node
+        let consent = Consent::sign_at(now - 90, "s-id:shumy", ConsentType::Revoke, "s-id:target", &["HealthCare".to_string()], None, &sig_s, &skey);
+        let data = encode(&Commit::Value(Value::VConsent(consent))).unwrap();
+        assert!(nodes[0].filter(&data).is_ok());
+        assert!(nodes[1].filter(&data).is_err());
+
+        drop(nodes);
+        std::fs::remove_dir_all(&home).ok();
+    }
+
+    #[test]
+    fn test_pre_genesis() {
+        let mut network = Network::new(1, 4);
+        let (subject, _, _, _) = create("s-id:shumy", &[]);
+        let data = encode(&Commit::Value(Value::VSubject(subject))).unwrap();
+
+        // the same rejection on every node, without changing the empty state
+        let states: Vec<AppState> = network.nodes.iter_mut().map(|node| {
+            assert!(node.deliver(&data) == Err("Transaction delivered outside of a block!".into()));
+
+            node.start(0);
+            assert!(node.deliver(&data) == Err("Transaction delivered before genesis!".into()));
+            node.commit(0)
+        }).collect();
+
+        assert!(states.iter().all(|state| state.height == 0 && state.hash.is_empty()));
+        assert!(network.nodes.iter().all(|node| node.store.get::<Subject>(&sid("s-id:shumy")).is_none()));
+
+        // the first block is delivered normally
+        network.commit(decode(&data).unwrap());
+        assert!(network.nodes.iter().all(|node| node.state().height == 1 && node.store.get::<Subject>(&sid("s-id:shumy")).is_some()));
+    }
+
+    #[test]
+    fn test_reload_observer() {
+        let home = std::env::temp_dir().join(format!("fedpi-processor-{}", uuid()));
+        let mut cfg = test_config();
+        cfg.paths = Paths::new(home.to_str().unwrap());
+
+        let sig_s = rnd_scalar();
+        let (_, skey) = Subject::new(&cfg.admin).evolve(sig_s);
+
+        // a committed peer-set without the local node
+        let peers: Vec<(String, RistrettoPoint)> = (0..4).map(|i| (format!("peer-{}", i), rnd_scalar() * G)).collect();
+        let pset = PeerSet::sign(&cfg.admin, 1, &peers, &sig_s, &skey);
+        let data = encode(&Commit::Value(Value::VPeerSet(pset.clone()))).unwrap();
+
+        let mut processor = Processor::new(cfg.clone());
+        processor.start(1);
+        processor.deliver(&data).unwrap();
+        processor.commit(1);
+
+        // the node keeps running as an observer, without signing results
+        assert!(processor.cfg.index.is_none());
+        assert!(processor.cfg.peers_hash == pset.hash());
+
+        let query = MasterKeyQuery::sign(&cfg.admin, PMASTER, &sig_s, &skey);
+        let snap = processor.store.snapshot();
+        assert!(processor.mkey_handler.query(&snap, query) == Err("The node is not in the peer-set (observer)!".into()));
+        drop(snap);
+        drop(processor);
+
+        // also on restart, with the committed peer-set
+        let processor = Processor::new(cfg);
+        assert!(processor.cfg.index.is_none());
+        assert!(processor.cfg.peers_hash == pset.hash());
+
+        drop(processor);
+        std::fs::remove_dir_all(&home).ok();
+    }
+}
@@ -1,12 +1,11 @@
-use core_fpi::Result;
+use core_fpi::{Result, KeyEncoder};
+use core_fpi::messages::MSG_VERSION;
 
 use log::{error, info};
 use abci::*;
 
 use crate::processor::Processor;
 
-const VERSION: &str = env!("CARGO_PKG_VERSION");
-
 fn convert(tx: &[u8]) -> Result<Vec<u8>> {
     bs58::decode(tx).into_vec().map_err(|_| "Unable to decode base58 input!".into())
 }
@@ -56,10 +55,16 @@ impl abci::Application for NodeApp {
             }
         };
 
-        if let Err(err) = self.processor.filter(&msg) {
-            error!("CheckTx-Error: {:?}", err);
-            resp.set_code(1);
-            resp.set_log(err.into());
+        match self.processor.filter(&msg) {
+            Ok(tx) => {
+                resp.set_info(format!("action={}, sid={}", tx.action, tx.sid));
+                resp.set_data(tx.sid.into_bytes());
+            },
+            Err(err) => {
+                error!("CheckTx-Error: {:?}", err);
+                resp.set_code(1);
+                resp.set_log(err.into());
+            }
         }
         
         resp
@@ -89,8 +94,8 @@ impl abci::Application for NodeApp {
         resp
     }
 
-    fn begin_block(&mut self, _req: &RequestBeginBlock) -> ResponseBeginBlock {
-        self.processor.start();
+    fn begin_block(&mut self, req: &RequestBeginBlock) -> ResponseBeginBlock {
+        self.processor.start(req.get_header().get_height());
         ResponseBeginBlock::new()
     }
 
@@ -110,13 +115,84 @@ impl abci::Application for NodeApp {
     fn info(&mut self, _req: &RequestInfo) -> ResponseInfo {
         let mut resp = ResponseInfo::new();
         resp.set_data("FedPI Node".into());
-        resp.set_version(VERSION.into());
+        // the clients check the compatibility with the message format, not with the node build
+        resp.set_version(MSG_VERSION.into());
 
         let state = self.processor.state();
-        info!("INFO - (ver = {:?}, height = {:?}, hash = {:?})", VERSION, state.height, bs58::encode(&state.hash).into_string());
+        info!("INFO - (ver = {:?}, height = {:?}, hash = {:?})", MSG_VERSION, state.height, state.hash.encode());
         
         resp.set_last_block_height(state.height);
         resp.set_last_block_app_hash(state.hash);
         resp
     }
+}
+
+//-----------------------------------------------------------------------------------------------------------
+// Unit tests
+//-----------------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core_fpi::{rnd_scalar, uuid};
+    use core_fpi::ids::*;
+    use core_fpi::authorizations::*;
+    use core_fpi::messages::*;
+    use crate::config::tests::test_config;
+
+    #[test]
+    fn test_check_tx_info() {
+        let path = std::env::temp_dir().join(format!("fedpi-tendermint-{}", uuid()));
+        let mut cfg = test_config();
+        cfg.paths.store = path.to_str().unwrap().into();
+
+        let mut app = NodeApp { height: 0, processor: Processor::new(cfg) };
+
+        let sig_s = rnd_scalar();
+        let sid = "s-id:shumy";
+
+        let mut subject = Subject::new(sid);
+        let (_, skey) = subject.evolve(sig_s);
+        subject.keys.push(skey.clone());
+
+        app.processor.start(1);
+        app.processor.deliver(&encode(&Commit::Value(Value::VSubject(subject))).unwrap()).unwrap();
+        app.processor.commit(1);
+
+        let consent = Consent::sign(sid, ConsentType::Consent, "s-id:target", &["HealthCare".to_string()], &sig_s, &skey);
+        let mut req = RequestCheckTx::new();
+        req.set_tx(encode_base58(&Commit::Value(Value::VConsent(consent))).unwrap().into_bytes());
+
+        let resp = app.check_tx(&req);
+        assert!(resp.get_code() == 0);
+        assert!(resp.get_info() == "action=consent, sid=s-id:shumy");
+        assert!(resp.get_data() == sid.as_bytes());
+
+        // rejected transactions have no info
+        let mut req = RequestCheckTx::new();
+        req.set_tx(b"invalid".to_vec());
+
+        let resp = app.check_tx(&req);
+        assert!(resp.get_code() == 1);
+        assert!(resp.get_info().is_empty());
+
+        drop(app);
+        std::fs::remove_dir_all(&path).ok();
+    }
+
+    #[test]
+    fn test_info_version() {
+        let path = std::env::temp_dir().join(format!("fedpi-tendermint-{}", uuid()));
+        let mut cfg = test_config();
+        cfg.paths.store = path.to_str().unwrap().into();
+
+        let mut app = NodeApp { height: 0, processor: Processor::new(cfg) };
+
+        // the reported version is the one checked by the clients
+        let resp = app.info(&RequestInfo::new());
+        assert!(resp.get_version() == MSG_VERSION);
+        assert!(compatible(resp.get_version()) == Ok(()));
+
+        drop(app);
+        std::fs::remove_dir_all(&path).ok();
+    }
 }
\ No newline at end of file
@@ -1,18 +1,35 @@
 use std::collections::HashMap;
 use log::LevelFilter;
-use sha2::{Sha512, Digest};
 
 use serde::{Deserialize};
-use core_fpi::{HardKeyDecoder, RistrettoPoint, CompressedRistretto};
+use core_fpi::{env_override, TryKeyDecoder, Result, RistrettoPoint};
+use core_fpi::governance::peers_hash;
+
+// Fields that can be overridden with environment variables (env > file > default)
+const ENV_LOG: &str = "FEDPI_LOG";
+const ENV_THRESHOLD: &str = "FEDPI_THRESHOLD";
 
 fn cfg_default() -> String {
     format!(r#"
     log = "info"        # Set the log level
 
     threshold = 0       # Number of permitted failing nodes, where #peers >= 3 * t
+    audit = false       # Append the mutating operations to HOME/<sid>.audit (secrets are never included)
+    derived_keys = false # Derive the profile secrets from the subject secret, instead of random ones (no forward secrecy)
+    height_tolerance = 1 # Maximum difference of block heights between the peers answering a disclosure
+
+    [client]
+    max_retries = 3     # Other peers to try when the selected peer is unreachable on commits (0 to disable the failover)
     
-    # List of valid peers
-    [peers]
+    # Named network profiles with their own peers, selected with --network <name> (the top-level peers are the default)
+    # [networks.<name>]
+    # threshold = 0
+    # [[networks.<name>.peers]]
+
+    # List of valid peers, in order (the position is the peer index)
+    # [[peers]]
+    # host = "http://127.0.0.1:26657"
+    # pkey = "<CompressedRistretto>"
     "#)
 }
 
@@ -24,7 +41,12 @@ pub struct Peer {
 
 #[derive(Debug, Clone)]
 pub struct Config {
+    pub network: Option<String>,                        // selected network profile (None for the default)
     pub log: LevelFilter,
+    pub audit: bool,
+    pub derived_keys: bool,
+    pub height_tolerance: u64,
+    pub max_retries: usize,
 
     pub threshold: usize,
     pub peers: Vec<Peer>,
@@ -33,30 +55,40 @@ pub struct Config {
 }
 
 impl Config {
-    pub fn new(home: &str, sid: &str) -> Self {
+    pub fn new(home: &str, sid: &str, network: Option<&str>) -> Result<Self> {
         let filename = format!("{}/{}.toml", home, sid);
         
         let cfg = match std::fs::read_to_string(&filename) {
             Ok(content) => content,
             Err(_) => {
                 let def_cfg = cfg_default();
-                std::fs::write(&filename, &def_cfg).unwrap_or_else(|e| panic!("Problems when creating the default config file: {}", e));
+                std::fs::write(&filename, &def_cfg).map_err(|e| format!("Problems when creating the default config file: {}", e))?;
                 def_cfg
             }
         };
 
-        let t_cfg: TomlConfig = toml::from_str(&cfg).expect("Unable to decode toml configuration!");
-        
-        let mut peers = Vec::<Peer>::with_capacity(t_cfg.peers.len());
-        let mut hasher = Sha512::new();
-        for i in 0..t_cfg.peers.len() {
-            let index = format!("{}", i);
-            let peer = t_cfg.peers.get(&index).unwrap_or_else(|| panic!("Expected peer at index {}!", i));
+        Self::parse(&cfg, network)
+    }
 
-            let pkey: CompressedRistretto = peer.pkey.decode();
-            hasher.input(pkey.as_bytes());
+    fn parse(cfg: &str, network: Option<&str>) -> Result<Self> {
+        let mut t_cfg: TomlConfig = toml::from_str(cfg).map_err(|e| format!("Unable to decode toml configuration: {}", e))?;
+        t_cfg.log = env_override(ENV_LOG, t_cfg.log)?;
 
-            let pkey = pkey.decompress().unwrap_or_else(|| panic!("Unable to decompress peer-key: {}", peer.host));
+        // the top-level peers are the default network
+        let (threshold, t_peers) = match network {
+            None => (t_cfg.threshold, &t_cfg.peers),
+            Some(name) => {
+                let net = t_cfg.networks.get(name).ok_or_else(|| format!("Network profile not found: {:?}", name))?;
+                (net.threshold, &net.peers)
+            }
+        };
+
+        let threshold = env_override(ENV_THRESHOLD, threshold)?;
+        
+        let t_peers = t_peers.ordered()?;
+        let mut peers = Vec::<Peer>::with_capacity(t_peers.len());
+        for peer in t_peers {
+            let pkey: RistrettoPoint = peer.pkey.try_decode().map_err(|e| format!("Invalid pkey for peer '{}': {}", peer.host, e))?;
 
             let host = if peer.host.ends_with('/') { &peer.host[..peer.host.len()-1] } else { &peer.host };
             let peer = Peer { host: host.into(), pkey };
@@ -68,13 +100,14 @@ impl Config {
             "info" => LevelFilter::Info,
             "warn" => LevelFilter::Warn,
             "error" => LevelFilter::Error,
-            _ => panic!("Log level not recognized!")
+            _ => return Err(format!("Log level not recognized: {:?}", t_cfg.log))
         };
 
-        let peers_hash = hasher.result().to_vec();
         let peers_keys: Vec<RistrettoPoint> = peers.iter().map(|p| p.pkey).collect();
+        let peers_hash = peers_hash(&peers_keys);
 
-        Self { log, threshold: t_cfg.threshold, peers, peers_hash, peers_keys }
+        let network = network.map(|name| name.to_owned());
+        Ok(Self { network, log, audit: t_cfg.audit, derived_keys: t_cfg.derived_keys, height_tolerance: t_cfg.height_tolerance, max_retries: t_cfg.client.max_retries, threshold, peers, peers_hash, peers_keys })
     }
 }
 
@@ -84,13 +117,238 @@ impl Config {
 #[derive(Deserialize, Debug)]
 struct TomlConfig {
     log: String,
+
+    #[serde(default)]
+    audit: bool,
+
+    #[serde(default)]
+    derived_keys: bool,
+
+    #[serde(default = "height_tolerance_default")]
+    height_tolerance: u64,
     
     threshold: usize,
-    peers: HashMap<String, TomlPeer>
+
+    #[serde(default)]
+    peers: TomlPeers,
+
+    #[serde(default)]
+    networks: HashMap<String, TomlNetwork>,
+
+    #[serde(default)]
+    client: TomlClient
+}
+
+fn height_tolerance_default() -> u64 { 1 }
+
+#[derive(Deserialize, Debug)]
+struct TomlClient {
+    #[serde(default = "max_retries_default")]
+    max_retries: usize
+}
+
+impl Default for TomlClient {
+    fn default() -> Self {
+        Self { max_retries: max_retries_default() }
+    }
+}
+
+fn max_retries_default() -> usize { 3 }
+
+#[derive(Deserialize, Debug)]
+struct TomlNetwork {
+    threshold: usize,
+
+    #[serde(default)]
+    peers: TomlPeers
+}
+
+// The canonical form is the [[peers]] array of tables, where the order is preserved.
+// The indexed table ([peers] 0 = {...}) is deprecated, and only accepted for existing configurations.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum TomlPeers {
+    List(Vec<TomlPeer>),
+    Indexed(HashMap<String, TomlPeer>)
+}
+
+impl Default for TomlPeers {
+    fn default() -> Self {
+        TomlPeers::List(Vec::new())
+    }
+}
+
+impl TomlPeers {
+    fn ordered(&self) -> Result<Vec<&TomlPeer>> {
+        match self {
+            TomlPeers::List(peers) => Ok(peers.iter().collect()),
+            TomlPeers::Indexed(peers) => (0..peers.len())
+                .map(|i| peers.get(&i.to_string()).ok_or_else(|| format!("Expected peer at index {}!", i)))
+                .collect()
+        }
+    }
 }
 
 #[derive(Deserialize, Debug)]
 struct TomlPeer {
     host: String,
     pkey: String
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core_fpi::{G, rnd_scalar, KeyEncoder};
+
+    fn pkey() -> (RistrettoPoint, String) {
+        let pkey = rnd_scalar() * G;
+        (pkey, pkey.compress().encode())
+    }
+
+    #[test]
+    fn test_networks() {
+        let (d0, e_d0) = pkey();
+        let (a0, e_a0) = pkey();
+        let (a1, e_a1) = pkey();
+        let (b0, e_b0) = pkey();
+
+        let content = format!(r#"
+            log = "info"
+            threshold = 0
+
+            [networks.health]
+            threshold = 1
+            [networks.health.peers]
+            0 = {{ host = "http://health-0:26657/", pkey = "{}" }}
+            1 = {{ host = "http://health-1:26657", pkey = "{}" }}
+
+            [networks.research]
+            threshold = 0
+            [networks.research.peers]
+            0 = {{ host = "http://research-0:26657", pkey = "{}" }}
+
+            [peers]
+            0 = {{ host = "http://default-0:26657", pkey = "{}" }}
+        "#, e_a0, e_a1, e_b0, e_d0);
+
+        let default = Config::parse(&content, None).unwrap();
+        assert!(default.network == None);
+        assert!(default.peers_keys == vec![d0]);
+        assert!(default.peers[0].host == "http://default-0:26657");
+
+        let health = Config::parse(&content, Some("health")).unwrap();
+        assert!(health.network == Some("health".into()));
+        assert!(health.threshold == 1);
+        assert!(health.peers_keys == vec![a0, a1]);
+        assert!(health.peers_hash == peers_hash(&[a0, a1]));
+        assert!(health.peers[0].host == "http://health-0:26657");
+
+        let research = Config::parse(&content, Some("research")).unwrap();
+        assert!(research.threshold == 0);
+        assert!(research.peers_keys == vec![b0]);
+        assert!(research.peers[0].host == "http://research-0:26657");
+
+        // each network has its own peers-hash
+        assert!(health.peers_hash != research.peers_hash);
+        assert!(default.peers_hash != health.peers_hash);
+    }
+
+    #[test]
+    fn test_unknown_network() {
+        let content = r#"
+            log = "info"
+            threshold = 0
+
+            [peers]
+        "#;
+
+        let res = Config::parse(content, Some("unknown"));
+        assert!(res.err() == Some("Network profile not found: \"unknown\"".into()));
+    }
+
+    #[test]
+    fn test_peers_array() {
+        let keys: Vec<(RistrettoPoint, String)> = (0..3).map(|_| pkey()).collect();
+
+        let mut list = String::from("log = \"info\"\nthreshold = 0\n");
+        for (i, (_, key)) in keys.iter().enumerate() {
+            list.push_str(&format!("\n[[peers]]\nhost = \"http://peer-{}:26657\"\npkey = \"{}\"\n", i, key));
+        }
+
+        list.push_str("\n[networks.health]\nthreshold = 0\n");
+        for (i, (_, key)) in keys.iter().enumerate().rev() {
+            list.push_str(&format!("\n[[networks.health.peers]]\nhost = \"http://health-{}:26657\"\npkey = \"{}\"\n", i, key));
+        }
+
+        // deprecated form, in a different declaration order
+        let mut indexed = String::from("log = \"info\"\nthreshold = 0\n\n[peers]\n");
+        for (i, (_, key)) in keys.iter().enumerate().rev() {
+            indexed.push_str(&format!("{} = {{ host = \"http://peer-{}:26657\", pkey = \"{}\" }}\n", i, i, key));
+        }
+
+        let c_list = Config::parse(&list, None).unwrap();
+        let c_indexed = Config::parse(&indexed, None).unwrap();
+
+        let expected: Vec<RistrettoPoint> = keys.iter().map(|(key, _)| *key).collect();
+        assert!(c_list.peers_keys == expected);
+        assert!(c_list.peers[2].host == "http://peer-2:26657");
+        assert!(c_list.peers_keys == c_indexed.peers_keys);
+        assert!(c_list.peers_hash == c_indexed.peers_hash);
+
+        // the declaration order is the peer order
+        let health = Config::parse(&list, Some("health")).unwrap();
+        assert!(health.peers_keys == expected.iter().rev().cloned().collect::<Vec<_>>());
+        assert!(health.peers[0].host == "http://health-2:26657");
+    }
+
+    #[test]
+    fn test_invalid_peer_key() {
+        let content = format!(r#"
+            log = "info"
+            threshold = 0
+
+            [[peers]]
+            host = "http://peer-0:26657"
+            pkey = "{}"
+
+            [[peers]]
+            host = "http://peer-1:26657"
+            pkey = "0OIl"
+        "#, pkey().1);
+
+        let res = Config::parse(&content, None);
+        assert!(res.err() == Some("Invalid pkey for peer 'http://peer-1:26657': not valid base58".into()));
+
+        // other parsing errors are also returned
+        let res = Config::parse("log = \"debug\"\nthreshold = 0\n", None);
+        assert!(res.err() == Some("Log level not recognized: \"debug\"".into()));
+
+        let res = Config::parse("log = \"info\"\n", None);
+        assert!(res.err().unwrap().starts_with("Unable to decode toml configuration:"));
+    }
+
+    #[test]
+    fn test_peers_missing_index() {
+        let content = format!(r#"
+            log = "info"
+            threshold = 0
+
+            [peers]
+            0 = {{ host = "http://peer-0:26657", pkey = "{}" }}
+            2 = {{ host = "http://peer-2:26657", pkey = "{}" }}
+        "#, pkey().1, pkey().1);
+
+        let res = Config::parse(&content, None);
+        assert!(res.err() == Some("Expected peer at index 1!".into()));
+    }
+
+    #[test]
+    fn test_max_retries() {
+        assert!(Config::parse(&cfg_default(), None).unwrap().max_retries == 3);
+        assert!(Config::parse("log = \"info\"\nthreshold = 0\n", None).unwrap().max_retries == 3);
+
+        // CI environments without failover
+        let content = "log = \"info\"\nthreshold = 0\n\n[client]\nmax_retries = 0\n";
+        assert!(Config::parse(content, None).unwrap().max_retries == 0);
+    }
 }
\ No newline at end of file
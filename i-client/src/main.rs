@@ -1,15 +1,21 @@
 #![forbid(unsafe_code)]
 
 use std::io::{Result, Error, ErrorKind};
+use std::sync::{Arc, Mutex};
+use std::collections::HashSet;
 use clap::{Arg, App, SubCommand};
+use core_fpi::{KeyEncoder, TryKeyDecoder, RistrettoPoint, fingerprint};
 use core_fpi::messages::*;
+use core_fpi::records::{Record, RecordData, RecordPayload};
 
 use serde::Deserialize;
 
 mod config;
 mod manager;
+mod vault;
 
 use config::Peer;
+use manager::{Operation, SubjectManager};
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
@@ -28,20 +34,213 @@ fn main() {
             .required(true)
             .long("sid")
             .takes_value(true))
+        .arg(Arg::with_name("network")
+            .help("Select the network profile of the subject config (the top-level peers if not present)")
+            .required(false)
+            .long("network")
+            .takes_value(true))
+        .arg(Arg::with_name("passphrase")
+            .help("Passphrase of the encrypted subject store (or FEDPI_PASSPHRASE)")
+            .required(false)
+            .long("passphrase")
+            .env("FEDPI_PASSPHRASE")
+            .hide_env_values(true)
+            .takes_value(true))
+        .arg(Arg::with_name("peer")
+            .help("Force the peer (index or host) for queries and commits, disclosures only pin the first peer")
+            .required(false)
+            .long("peer")
+            .takes_value(true))
         .subcommand(SubCommand::with_name("reset")
             .about("Reset the local subject data"))
         .subcommand(SubCommand::with_name("view")
-            .about("View the local subject data"))
+            .about("View the local subject data")
+            .arg(Arg::with_name("format")
+                .help("Output format, json is a redacted view without the secrets")
+                .long("format")
+                .takes_value(true)
+                .possible_values(&["debug", "json"])
+                .default_value("debug")))
+        .subcommand(SubCommand::with_name("decode")
+            .about("Decode and print a base58 tx payload (as submitted to the network)")
+            .arg(Arg::with_name("tx")
+                .help("The base58 payload")
+                .takes_value(true)
+                .required(true))
+            .arg(Arg::with_name("query")
+                .help("Decode the payload as a query request instead of a commit")
+                .long("query")))
+        .subcommand(SubCommand::with_name("check-unlinkability")
+            .about("Audit the local profile keys for linkable pseudonyms"))
         .subcommand(SubCommand::with_name("create")
-            .about("Request the creation of a subject"))
+            .about("Request the creation of a subject")
+            .arg(Arg::with_name("trustees")
+                .help("Setup the recovery with a set of trustee subject-ids, right after the creation")
+                .long("trustees")
+                .min_values(1)
+                .takes_value(true)
+                .requires("threshold"))
+            .arg(Arg::with_name("threshold")
+                .help("Number of trustees required to recover the subject secret")
+                .long("threshold")
+                .takes_value(true)))
+        .subcommand(SubCommand::with_name("recovery")
+            .about("Manage the recovery of the subject secret by a threshold of trustees")
+            .subcommand(SubCommand::with_name("setup")
+                .about("Split the secret of the active subject-key across the trustees (replaces a previous recovery)")
+                .arg(Arg::with_name("trustees")
+                    .help("Trustee subject-ids")
+                    .min_values(1)
+                    .takes_value(true)
+                    .required(true))
+                .arg(Arg::with_name("threshold")
+                    .help("Number of trustees required to recover the subject secret")
+                    .long("threshold")
+                    .takes_value(true)
+                    .required(true)))
+            .subcommand(SubCommand::with_name("restore")
+                .about("Rebuild the local store with the subject secret recovered from the shares of the trustees")
+                .arg(Arg::with_name("shares")
+                    .help("Base58 shares, as handed to the trustees")
+                    .min_values(1)
+                    .takes_value(true)
+                    .required(true))))
         .subcommand(SubCommand::with_name("evolve")
-            .about("Request the evolution of the subject-key"))
+            .about("Request the evolution of the subject-key")
+            .arg(Arg::with_name("reanchor")
+                .help("Also re-sign the active profile-keys under the new subject-key")
+                .long("reanchor"))
+            .arg(Arg::with_name("queue")
+                .help("Queue the operation, it's only processed on flush")
+                .long("queue")))
+        .subcommand(SubCommand::with_name("revoke-key")
+            .about("Revoke a compromised subject-key, the active key is replaced by an evolution")
+            .arg(Arg::with_name("index")
+                .help("Index of the subject-key")
+                .takes_value(true)
+                .required(true))
+            .arg(Arg::with_name("at")
+                .help("Revoked as of this unix timestamp, the previous signatures remain valid (defaults to now)")
+                .takes_value(true)
+                .long("at"))
+            .arg(Arg::with_name("queue")
+                .help("Queue the operation, it's only processed on flush")
+                .long("queue")))
+        .subcommand(SubCommand::with_name("flush")
+            .about("Finish any pending synchronization and process the queued operations in order"))
         .subcommand(SubCommand::with_name("negotiate")
             .about("Fires the negotiation protocol to create or update a master key")
             .arg(Arg::with_name("kid")
                 .help("Select the key-id")
                 .takes_value(true)
                 .required(true)))
+        .subcommand(SubCommand::with_name("refresh")
+            .about("Fires the refresh protocol to rotate the peer shares of a master key")
+            .arg(Arg::with_name("kid")
+                .help("Select the key-id")
+                .takes_value(true)
+                .required(true)))
+        .subcommand(SubCommand::with_name("reshare")
+            .about("Fires the resharing protocol to move a master key to a new peer-set (before the peer-set change)")
+            .arg(Arg::with_name("kid")
+                .help("Select the key-id")
+                .takes_value(true)
+                .required(true))
+            .arg(Arg::with_name("threshold")
+                .help("Threshold of the new peer-set")
+                .takes_value(true)
+                .required(true))
+            .arg(Arg::with_name("pkeys")
+                .help("Ordered public-keys of the new peer-set")
+                .min_values(1)
+                .takes_value(true)
+                .required(true)))
+        .subcommand(SubCommand::with_name("keys")
+            .about("Manage the master keys negotiated by the subject")
+            .subcommand(SubCommand::with_name("list")
+                .about("List the negotiated master keys recorded in the local store")))
+        .subcommand(SubCommand::with_name("master-key")
+            .about("Query the verified public-key of a negotiated master key")
+            .arg(Arg::with_name("kid")
+                .help("Select the key-id")
+                .takes_value(true)
+                .required(true)))
+        .subcommand(SubCommand::with_name("records")
+            .about("Read the records of a stream (requires a disclosure of the pseudonym)")
+            .arg(Arg::with_name("pseudonym")
+                .help("Select the stream pseudonym")
+                .takes_value(true)
+                .required(true))
+            .arg(Arg::with_name("base")
+                .help("Base-point of the pseudonym, defaults to the pseudonym master-key")
+                .long("base")
+                .takes_value(true))
+            .arg(Arg::with_name("from")
+                .help("Position of the first record in the stream")
+                .long("from")
+                .takes_value(true)
+                .default_value("0"))
+            .arg(Arg::with_name("after")
+                .help("Records after the record with this signature (cursor), --from is ignored")
+                .long("after")
+                .takes_value(true))
+            .arg(Arg::with_name("limit")
+                .help("Max number of records")
+                .long("limit")
+                .takes_value(true)
+                .default_value("10")))
+        .subcommand(SubCommand::with_name("record")
+            .about("Manage the records of an own profile-location stream (signed with the profile secret)")
+            .subcommand(SubCommand::with_name("push")
+                .about("Append a record to the stream, the first one opens it")
+                .arg(Arg::with_name("type")
+                    .help("Select the profile type")
+                    .long("type")
+                    .takes_value(true)
+                    .required(true))
+                .arg(Arg::with_name("lurl")
+                    .help("Select the profile location")
+                    .long("lurl")
+                    .takes_value(true)
+                    .required(true))
+                .arg(Arg::with_name("format")
+                    .help("Format of the record data")
+                    .long("format")
+                    .takes_value(true)
+                    .required(true))
+                .arg(Arg::with_name("meta")
+                    .help("File with the record metadata")
+                    .long("meta")
+                    .takes_value(true))
+                .arg(Arg::with_name("data")
+                    .help("File with the record data (inline)")
+                    .long("data")
+                    .takes_value(true)
+                    .required(true)))
+            .subcommand(SubCommand::with_name("close")
+                .about("Close the stream, no record can be pushed after it")
+                .arg(Arg::with_name("type")
+                    .help("Select the profile type")
+                    .long("type")
+                    .takes_value(true)
+                    .required(true))
+                .arg(Arg::with_name("lurl")
+                    .help("Select the profile location")
+                    .long("lurl")
+                    .takes_value(true)
+                    .required(true)))
+            .subcommand(SubCommand::with_name("list")
+                .about("Read the stream chain (requires a disclosure served to the subject)")
+                .arg(Arg::with_name("type")
+                    .help("Select the profile type")
+                    .long("type")
+                    .takes_value(true)
+                    .required(true))
+                .arg(Arg::with_name("lurl")
+                    .help("Select the profile location")
+                    .long("lurl")
+                    .takes_value(true)
+                    .required(true))))
         .subcommand(SubCommand::with_name("profile")
             .about("Request the creation or evolution of a subject profile")
             .arg(Arg::with_name("type")
@@ -55,7 +254,31 @@ fn main() {
             .arg(Arg::with_name("encrypted")
                 .help("IS the profile stream encrypted?")
                 .takes_value(true)
-                .required(true)))
+                .required(true))
+            .arg(Arg::with_name("consent")
+                .help("Authorize full-disclosure of the profile to a subject-id, in the same submission")
+                .takes_value(true)
+                .long("consent"))
+            .arg(Arg::with_name("queue")
+                .help("Queue the operation, it's only processed on flush")
+                .long("queue")))
+        .subcommand(SubCommand::with_name("migrate-location")
+            .about("Move a subject profile location to a new profile server")
+            .arg(Arg::with_name("type")
+                .help("Select the profile type")
+                .takes_value(true)
+                .required(true))
+            .arg(Arg::with_name("old-lurl")
+                .help("Select the current profile location")
+                .takes_value(true)
+                .required(true))
+            .arg(Arg::with_name("new-lurl")
+                .help("Select the new profile location")
+                .takes_value(true)
+                .required(true))
+            .arg(Arg::with_name("queue")
+                .help("Queue the operation, it's only processed on flush")
+                .long("queue")))
         .subcommand(SubCommand::with_name("consent")
             .about("Authorize full-disclosure to another subject-id for a set of profiles")
             .arg(Arg::with_name("auth")
@@ -66,7 +289,14 @@ fn main() {
                 .help("Selects a set of profile types")
                 .min_values(1)
                 .takes_value(true)
-                .required(true)))
+                .required(true))
+            .arg(Arg::with_name("purpose")
+                .help("Purpose of the authorization, signed with the consent")
+                .takes_value(true)
+                .long("purpose"))
+            .arg(Arg::with_name("queue")
+                .help("Queue the operation, it's only processed on flush")
+                .long("queue")))
         .subcommand(SubCommand::with_name("revoke")
             .about("Revoke a previous authorizations")
             .arg(Arg::with_name("auth")
@@ -77,7 +307,15 @@ fn main() {
                 .help("Selects a set of profile types")
                 .min_values(1)
                 .takes_value(true)
-                .required(true)))
+                .required(true))
+            .arg(Arg::with_name("queue")
+                .help("Queue the operation, it's only processed on flush")
+                .long("queue")))
+        .subcommand(SubCommand::with_name("consents")
+            .about("List the consented profiles per authorized subject-id")
+            .arg(Arg::with_name("history")
+                .help("List the committed consents and revokes with the purpose, from the peers")
+                .long("history")))
         .subcommand(SubCommand::with_name("disclose")
             .about("Request profile disclosures for subject (requires consent)")
             .arg(Arg::with_name("target")
@@ -88,23 +326,98 @@ fn main() {
                 .help("Selects a set of profile types")
                 .min_values(1)
                 .takes_value(true)
-                .required(true)))
+                .required(true))
+            .arg(Arg::with_name("hashed")
+                .help("Output the stream identification SHA-256(pseudonym) instead of the pseudonym")
+                .long("hashed"))
+            .arg(Arg::with_name("partial")
+                .help("Disclose the authorized profiles and report the denied ones, instead of failing")
+                .long("partial")))
         .get_matches();
     
+    // decoding is independent of the subject store
+    if let Some(matches) = matches.subcommand_matches("decode") {
+        let tx = matches.value_of("tx").unwrap();
+        let res = if matches.is_present("query") {
+            core_fpi::messages::decode_base58::<Request>(tx).map(|msg| format!("{:#?}", msg))
+        } else {
+            core_fpi::messages::decode_base58::<Commit>(tx).map(|msg| format!("{:#?}", msg))
+        };
+
+        match res {
+            Err(e) => println!("ERROR -> {}", e),
+            Ok(msg) => println!("{}", msg)
+        }
+
+        return
+    }
+
     let home = matches.value_of("home").unwrap_or(".");
     let home = if home.ends_with('/') { &home[..home.len()-1] } else { home };
 
     // read configuration from HOME/<sid>.toml file
     let sid = matches.value_of("sid").unwrap().to_owned();
-    let cfg = config::Config::new(&home, &sid);
+    let network = matches.value_of("network");
+    let cfg = match config::Config::new(&home, &sid, network) {
+        Ok(cfg) => cfg,
+        Err(e) => {
+            println!("ERROR -> {}", e);
+            return
+        }
+    };
+
+    // the subject state is different in each network, stored at HOME/<network>/
+    let store = match network {
+        None => home.to_owned(),
+        Some(name) => {
+            let store = format!("{}/{}", home, name);
+            if let Err(e) = std::fs::create_dir_all(&store) {
+                println!("ERROR -> Unable to create the network store {:?}: {}", store, e);
+                return
+            }
+
+            store
+        }
+    };
+
+    // the subject secrets are encrypted at rest, there is no store without a passphrase
+    let passphrase = match matches.value_of("passphrase") {
+        Some(passphrase) if !passphrase.is_empty() => passphrase.to_owned(),
+        _ => {
+            println!("ERROR -> A passphrase is required for the subject store (--passphrase or FEDPI_PASSPHRASE)");
+            return
+        }
+    };
 
-    let tx_handler = |peer: &Peer, msg: Commit| -> Result<()> {
-        let msg_data = core_fpi::messages::encode(&msg).map_err(|_| Error::new(ErrorKind::Other, "Unable to encode message!"))?;
-        let data = bs58::encode(&msg_data).into_string();
+    // version handshake, only once per peer (shared with the threads of the concurrent queries)
+    let checked = Mutex::new(HashSet::<String>::new());
+    let handshake = Arc::new(move |peer: &Peer| -> Result<()> {
+        if checked.lock().unwrap().contains(&peer.host) {
+            return Ok(())
+        }
+
+        let url = format!("{}/abci_info", peer.host);
+
+        let mut resp = reqwest::get(url.as_str()).map_err(|_| Error::new(ErrorKind::NotConnected, "Unable to query network!"))?;
+        let res: InfoResult = resp.json().map_err(|e| Error::new(ErrorKind::Other, format!("Unable to parse JSON - {:?}", e)))?;
+
+        let version = res.result.response.version.unwrap_or_default();
+        core_fpi::messages::compatible(&version).map_err(|e| Error::new(ErrorKind::Other, format!("Peer {} - {}", peer.host, e)))?;
+
+        checked.lock().unwrap().insert(peer.host.clone());
+        Ok(())
+    });
+
+    // returns the block height of the committed transaction
+    let tx_handshake = handshake.clone();
+    let tx_handler = move |peer: &Peer, msg: Commit| -> Result<i64> {
+        tx_handshake(peer)?;
+
+        let data = core_fpi::messages::encode_base58(&msg).map_err(|_| Error::new(ErrorKind::Other, "Unable to encode message!"))?;
 
         let url = format!("{}/broadcast_tx_commit?tx={:?}", peer.host, data);
         
-        let mut resp = reqwest::get(url.as_str()).map_err(|_| Error::new(ErrorKind::Other, "Unable to commit to network!"))?;
+        let mut resp = reqwest::get(url.as_str()).map_err(|_| Error::new(ErrorKind::NotConnected, "Unable to commit to network!"))?;
         //println!("RES: {:?}", resp.text());
         let res: TxResult = resp.json().map_err(|e| Error::new(ErrorKind::Other, format!("Unable to parse JSON - {:?}", e)))?;
 
@@ -122,12 +435,13 @@ fn main() {
             return Err(Error::new(ErrorKind::Other, format!("Transaction error from network. On deliver: {}", result.deliver_tx.log)))
         }
 
-        Ok(())
+        result.height.parse::<i64>().map_err(|_| Error::new(ErrorKind::Other, "Unable to parse the transaction height!"))
     };
 
-    let query_handler = |peer: &Peer, msg: Request| -> Result<Response> {
-        let msg_data = core_fpi::messages::encode(&msg).map_err(|_| Error::new(ErrorKind::Other, "Unable to encode message!"))?;
-        let data = bs58::encode(&msg_data).into_string();
+    let query_handler = move |peer: &Peer, msg: Request| -> Result<Response> {
+        handshake(peer)?;
+
+        let data = core_fpi::messages::encode_base58(&msg).map_err(|_| Error::new(ErrorKind::Other, "Unable to encode message!"))?;
 
         let url = format!("{}/abci_query?data={:?}", peer.host, data);
 
@@ -148,22 +462,133 @@ fn main() {
     };
 
     // tx_handler and query_handler are tendermint adaptors. The SubjectManager is independent of the used blockchain technology.
-    let mut sm = manager::SubjectManager::new(home, &sid, cfg, tx_handler, query_handler);
+    let mut sm = match SubjectManager::new(&store, &sid, &passphrase, cfg, tx_handler, query_handler) {
+        Ok(sm) => sm,
+        Err(e) => {
+            println!("ERROR -> {}", e);
+            return
+        }
+    };
+
+    if let Some(peer) = matches.value_of("peer") {
+        if let Err(e) = sm.pin(peer) {
+            println!("ERROR -> {}", e);
+            return
+        }
+    }
 
     if matches.is_present("reset") {
         println!("Reseting {:?}", sid);
         sm.reset();
     } else if matches.is_present("view") {
-        match sm.sto {
-            None => println!("No subject available"),
-            Some(my) => println!("{:#?}", my)
+        let matches = matches.subcommand_matches("view").unwrap();
+
+        // only the json document is printed, so it can be piped
+        if matches.value_of("format") == Some("json") {
+            let view = sm.sto.as_ref().map(|my| my.view());
+            match serde_json::to_string_pretty(&view) {
+                Err(e) => println!("ERROR -> {}", e),
+                Ok(json) => println!("{}", json)
+            }
+        } else {
+            if let Some(name) = &sm.config.network {
+                println!("Network: {:?}", name);
+            }
+
+            // fingerprints for the out-of-band verification of the peer keys
+            for (i, peer) in sm.config.peers.iter().enumerate() {
+                println!("PEER {} -> (host = {}, fingerprint = {})", i, peer.host, fingerprint(&peer.pkey));
+            }
+
+            if !sm.queue.is_empty() {
+                println!("Queued operations: {:#?}", sm.queue);
+            }
+
+            match sm.sto {
+                None => println!("No subject available"),
+                Some(my) => println!("{:#?}", my)
+            }
+        }
+    } else if matches.is_present("check-unlinkability") {
+        match sm.check_unlinkability() {
+            Err(e) => println!("ERROR -> {}", e),
+            Ok(linked) => {
+                if linked.is_empty() {
+                    println!("OK -> No linkable profile keys found");
+                }
+
+                for (first, second) in linked.iter() {
+                    println!("WARNING -> Linkable profile keys: {} <-> {}", first, second);
+                }
+            }
         }
     } else if matches.is_present("create") {
-        if let Err(e) = sm.create() {
+        let matches = matches.subcommand_matches("create").unwrap();
+        let res = sm.create().and_then(|_| match matches.values_of("trustees") {
+            None => Ok(()),
+            Some(trustees) => {
+                let trustees: Vec<String> = trustees.map(|v| v.to_string()).collect();
+                recovery(&mut sm, &trustees, matches.value_of("threshold").unwrap())
+            }
+        });
+
+        if let Err(e) = res {
             println!("ERROR -> {}", e);
         }
+    } else if matches.is_present("recovery") {
+        let matches = matches.subcommand_matches("recovery").unwrap();
+        if let Some(matches) = matches.subcommand_matches("setup") {
+            let trustees: Vec<String> = matches.values_of("trustees").unwrap().map(|v| v.to_string()).collect();
+            if let Err(e) = recovery(&mut sm, &trustees, matches.value_of("threshold").unwrap()) {
+                println!("ERROR -> {}", e);
+            }
+        } else if let Some(matches) = matches.subcommand_matches("restore") {
+            let shares: Vec<String> = matches.values_of("shares").unwrap().map(|v| v.to_string()).collect();
+            match sm.restore(&shares) {
+                Err(e) => println!("ERROR -> {}", e),
+                Ok(lost) => {
+                    println!("RESTORED {}", sid);
+
+                    // profile secrets that are not derived from the recovered key, evolve these profiles
+                    for pid in lost.iter() {
+                        println!("LOST {}", pid);
+                    }
+                }
+            }
+        }
     } else if matches.is_present("evolve") {
-        sm.evolve().unwrap();
+        let matches = matches.subcommand_matches("evolve").unwrap();
+        let res = match matches.is_present("reanchor") {
+            true => if matches.is_present("queue") { sm.enqueue(Operation::Reanchor) } else { sm.reanchor() },
+            false => if matches.is_present("queue") { sm.enqueue(Operation::Evolve) } else { sm.evolve() }
+        };
+
+        if let Err(e) = res {
+            println!("ERROR -> {}", e);
+        }
+    } else if matches.is_present("revoke-key") {
+        let matches = matches.subcommand_matches("revoke-key").unwrap();
+        let args = || -> core_fpi::Result<_> {
+            let index: usize = matches.value_of("index").unwrap().parse().map_err(|_| "Invalid value for index!")?;
+            let at = match matches.value_of("at") {
+                None => None,
+                Some(at) => Some(at.parse::<i64>().map_err(|_| "Invalid value for --at!")?)
+            };
+
+            Ok((index, at))
+        };
+
+        let res = args()
+            .map_err(|e| Error::new(ErrorKind::Other, e))
+            .and_then(|(index, at)| if matches.is_present("queue") { sm.enqueue(Operation::RevokeKey(index, at)) } else { sm.revoke_key(index, at) });
+
+        if let Err(e) = res {
+            println!("ERROR -> {}", e);
+        }
+    } else if matches.is_present("flush") {
+        if let Err(e) = sm.flush() {
+            println!("ERROR -> {}", e);
+        }
     } else if matches.is_present("negotiate") {
         let matches = matches.subcommand_matches("negotiate").unwrap();
         let kid = matches.value_of("kid").unwrap().to_owned();
@@ -171,6 +596,124 @@ fn main() {
         if let Err(e) = sm.negotiate(&kid) {
             println!("ERROR -> {}", e);
         }
+    } else if matches.is_present("refresh") {
+        let matches = matches.subcommand_matches("refresh").unwrap();
+        let kid = matches.value_of("kid").unwrap().to_owned();
+
+        if let Err(e) = sm.refresh(&kid) {
+            println!("ERROR -> {}", e);
+        }
+    } else if matches.is_present("reshare") {
+        let matches = matches.subcommand_matches("reshare").unwrap();
+        let kid = matches.value_of("kid").unwrap().to_owned();
+        let threshold = matches.value_of("threshold").unwrap();
+        let pkeys: Vec<String> = matches.values_of("pkeys").unwrap().map(|pkey| pkey.to_owned()).collect();
+
+        let res = threshold.parse::<usize>()
+            .map_err(|_| Error::new(ErrorKind::Other, "Invalid threshold!"))
+            .and_then(|threshold| {
+                let new_peers = pkeys.iter().map(|pkey| pkey.try_decode().map_err(|e| Error::new(ErrorKind::Other, format!("Invalid pkey {:?}: {}", pkey, e))))
+                    .collect::<Result<Vec<RistrettoPoint>>>()?;
+                sm.reshare(&kid, threshold, &new_peers)
+            });
+
+        if let Err(e) = res {
+            println!("ERROR -> {}", e);
+        }
+    } else if matches.is_present("keys") {
+        let matches = matches.subcommand_matches("keys").unwrap();
+        if matches.is_present("list") {
+            match sm.keys() {
+                Err(e) => println!("ERROR -> {}", e),
+                Ok(keys) => {
+                    if keys.is_empty() {
+                        println!("No negotiated keys available");
+                    }
+
+                    for key in keys.iter() {
+                        println!("KEY {} -> (public = {}, fingerprint = {}, session = {}, height = {})", key.kid, key.public.encode(), fingerprint(&key.public), key.session, key.height);
+                    }
+                }
+            }
+        }
+    } else if matches.is_present("master-key") {
+        let matches = matches.subcommand_matches("master-key").unwrap();
+        let kid = matches.value_of("kid").unwrap().to_owned();
+
+        match sm.master_key(&kid) {
+            Err(e) => println!("ERROR -> {}", e),
+            Ok(None) => println!("NOT-FOUND -> {}", kid),
+            Ok(Some(public)) => println!("PUBLIC {} -> {} ({})", kid, public.encode(), fingerprint(&public))
+        }
+    } else if matches.is_present("records") {
+        let matches = matches.subcommand_matches("records").unwrap();
+        let args = || -> core_fpi::Result<_> {
+            let pseudonym = matches.value_of("pseudonym").unwrap().trim().to_owned();
+            let pseudonym: RistrettoPoint = pseudonym.try_decode().map_err(|e| format!("Invalid pseudonym {:?}: {}", pseudonym, e))?;
+            let base = match matches.value_of("base") {
+                None => None,
+                Some(base) => {
+                    let base = base.trim().to_owned();
+                    let base: RistrettoPoint = base.try_decode().map_err(|e| format!("Invalid base {:?}: {}", base, e))?;
+                    Some(base)
+                }
+            };
+
+            let from: usize = matches.value_of("from").unwrap().parse().map_err(|_| "Invalid value for --from!")?;
+            let limit: usize = matches.value_of("limit").unwrap().parse().map_err(|_| "Invalid value for --limit!")?;
+            Ok((pseudonym, base, from, limit))
+        };
+
+        let res = args()
+            .map_err(|e| Error::new(ErrorKind::Other, e))
+            .and_then(|(pseudonym, base, from, limit)| match matches.value_of("after") {
+                None => sm.records(&pseudonym, base, from, limit),
+                Some(prev) => sm.records_after(&pseudonym, base, prev, limit)
+            });
+
+        match res {
+            Err(e) => println!("ERROR -> {}", e),
+            Ok((from, records)) => print_records(from, &records)
+        }
+    } else if matches.is_present("record") {
+        let matches = matches.subcommand_matches("record").unwrap();
+        if let Some(matches) = matches.subcommand_matches("push") {
+            let typ = matches.value_of("type").unwrap();
+            let lurl = matches.value_of("lurl").unwrap();
+            let format = matches.value_of("format").unwrap();
+
+            let read = |file: Option<&str>| -> Result<Vec<u8>> {
+                match file {
+                    None => Ok(Vec::new()),
+                    Some(file) => std::fs::read(file).map_err(|e| Error::new(ErrorKind::Other, format!("Unable to read {:?} - {}", file, e)))
+                }
+            };
+
+            let res = read(matches.value_of("meta"))
+                .and_then(|meta| Ok((meta, read(matches.value_of("data"))?)))
+                .and_then(|(meta, data)| sm.push_record(typ, lurl, RecordData::inline(format, meta, data)));
+
+            match res {
+                Err(e) => println!("ERROR -> {}", e),
+                Ok((stream, height)) => println!("RECORD {} -> (height = {})", stream, height)
+            }
+        } else if let Some(matches) = matches.subcommand_matches("close") {
+            let typ = matches.value_of("type").unwrap();
+            let lurl = matches.value_of("lurl").unwrap();
+
+            match sm.close_record(typ, lurl) {
+                Err(e) => println!("ERROR -> {}", e),
+                Ok((stream, height)) => println!("CLOSED {} -> (height = {})", stream, height)
+            }
+        } else if let Some(matches) = matches.subcommand_matches("list") {
+            let typ = matches.value_of("type").unwrap();
+            let lurl = matches.value_of("lurl").unwrap();
+
+            match sm.profile_records(typ, lurl) {
+                Err(e) => println!("ERROR -> {}", e),
+                Ok((from, records)) => print_records(from, &records)
+            }
+        }
     } else if matches.is_present("profile") {
         let matches = matches.subcommand_matches("profile").unwrap();
         let typ = matches.value_of("type").unwrap().to_owned();
@@ -179,7 +722,21 @@ fn main() {
         let encrypted = matches.value_of("encrypted").unwrap().to_owned();
         let encrypted = encrypted.parse().unwrap();
         
-        if let Err(e) = sm.profile(&typ, &lurl, encrypted) {
+        let res = match matches.value_of("consent") {
+            Some(auth) => if matches.is_present("queue") { sm.enqueue(Operation::ProfileConsent(typ, lurl, encrypted, auth.into())) } else { sm.profile_consent(&typ, &lurl, encrypted, auth) },
+            None => if matches.is_present("queue") { sm.enqueue(Operation::Profile(typ, lurl, encrypted)) } else { sm.profile(&typ, &lurl, encrypted) }
+        };
+        if let Err(e) = res {
+            println!("ERROR -> {}", e);
+        }
+    } else if matches.is_present("migrate-location") {
+        let matches = matches.subcommand_matches("migrate-location").unwrap();
+        let typ = matches.value_of("type").unwrap().to_owned();
+        let from = matches.value_of("old-lurl").unwrap().to_owned();
+        let to = matches.value_of("new-lurl").unwrap().to_owned();
+
+        let res = if matches.is_present("queue") { sm.enqueue(Operation::Migrate(typ, from, to)) } else { sm.migrate(&typ, &from, &to) };
+        if let Err(e) = res {
             println!("ERROR -> {}", e);
         }
     } else if matches.is_present("consent") {
@@ -188,16 +745,48 @@ fn main() {
         let profiles: Vec<&str> = matches.values_of("profiles").unwrap().collect();
         let profiles: Vec<String> = profiles.iter().map(|v| v.to_string()).collect();
 
-        if let Err(e) = sm.consent(&auth, &profiles) {
+        let purpose = matches.value_of("purpose");
+
+        let res = if matches.is_present("queue") { sm.enqueue(Operation::consent(&auth, &profiles, purpose)) } else { sm.consent(&auth, &profiles, purpose) };
+        if let Err(e) = res {
             println!("ERROR -> {}", e);
         }
+    } else if matches.is_present("consents") && matches.subcommand_matches("consents").unwrap().is_present("history") {
+        match sm.consents() {
+            Err(e) => println!("ERROR -> {}", e),
+            Ok(consents) => {
+                if consents.is_empty() {
+                    println!("No consents available");
+                }
+
+                for consent in consents.iter() {
+                    let purpose = consent.purpose.as_deref().unwrap_or("-");
+                    println!("{:?} {} -> {:?} (purpose = {:?}, timestamp = {})", consent.typ, consent.target, consent.profiles, purpose, consent.sig.sig.timestamp);
+                }
+            }
+        }
+    } else if matches.is_present("consents") {
+        match sm.authorizations() {
+            Err(e) => println!("ERROR -> {}", e),
+            Ok(auths) => {
+                if auths.targets().next().is_none() {
+                    println!("No consents available");
+                }
+
+                for target in auths.targets() {
+                    let profiles: Vec<&String> = auths.authorized_profiles(target).map(|set| set.iter().collect()).unwrap_or_default();
+                    println!("CONSENT {} -> {:?}", target, profiles);
+                }
+            }
+        }
     } else if matches.is_present("revoke") {
         let matches = matches.subcommand_matches("revoke").unwrap();
         let auth = matches.value_of("auth").unwrap().to_owned();
         let profiles: Vec<&str> = matches.values_of("profiles").unwrap().collect();
         let profiles: Vec<String> = profiles.iter().map(|v| v.to_string()).collect();
 
-        if let Err(e) = sm.revoke(&auth, &profiles) {
+        let res = if matches.is_present("queue") { sm.enqueue(Operation::Revoke(auth, profiles)) } else { sm.revoke(&auth, &profiles) };
+        if let Err(e) = res {
             println!("ERROR -> {}", e);
         }
     } else if matches.is_present("disclose") {
@@ -205,13 +794,47 @@ fn main() {
         let target = matches.value_of("target").unwrap().to_owned();
         let profiles: Vec<&str> = matches.values_of("profiles").unwrap().collect();
         let profiles: Vec<String> = profiles.iter().map(|v| v.to_string()).collect();
+        let hashed = matches.is_present("hashed");
+        let partial = matches.is_present("partial");
 
-        if let Err(e) = sm.disclose(&target, &profiles) {
+        if let Err(e) = sm.disclose(&target, &profiles, hashed, partial) {
             println!("ERROR -> {}", e);
         }
     }
 }
 
+// records in chain order, from the stream position of the first one
+fn print_records(from: usize, records: &[Record]) {
+    if records.is_empty() {
+        println!("No records available");
+    }
+
+    for (i, record) in records.iter().enumerate() {
+        let data = match &record.rdata.data {
+            RecordPayload::Inline(data) => format!("inline, {} bytes", data.len()),
+            RecordPayload::Reference(lurl, _) => format!("reference, {}", lurl)
+        };
+
+        println!("RECORD {} -> (sig = {}, type = {:?}, format = {}, data = {})", from + i, record.sig.encoded, record.typ, record.rdata.format, data);
+        if !record.rdata.meta.is_empty() {
+            println!("  META {}", String::from_utf8_lossy(&record.rdata.meta));
+        }
+    }
+}
+
+// setup the recovery and print the private shares, to hand out-of-band to each trustee
+fn recovery<F, Q>(sm: &mut SubjectManager<F, Q>, trustees: &[String], threshold: &str) -> Result<()>
+    where F: Fn(&Peer, Commit) -> Result<i64>, Q: Fn(&Peer, Request) -> Result<Response> + Send + Sync + 'static
+{
+    let threshold: usize = threshold.parse().map_err(|_| Error::new(ErrorKind::Other, "Invalid value for --threshold!"))?;
+    let shares = sm.recovery(trustees, threshold)?;
+    for share in shares.iter() {
+        println!("SHARE {} -> {}", share.tid, share.encode());
+    }
+
+    Ok(())
+}
+
 #[derive(Deserialize, Debug)]
 struct TxResult {
     jsonrpc: String,
@@ -271,6 +894,21 @@ struct QueryResultResponse {
     value: Option<String>
 }
 
+#[derive(Deserialize, Debug)]
+struct InfoResult {
+    result: InfoResultBody
+}
+
+#[derive(Deserialize, Debug)]
+struct InfoResultBody {
+    response: InfoResultResponse
+}
+
+#[derive(Deserialize, Debug)]
+struct InfoResultResponse {
+    version: Option<String>
+}
+
 /*{
   "error": "",
   "result": {
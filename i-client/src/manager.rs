@@ -1,31 +1,44 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, BTreeMap};
 use std::fmt::{Debug, Formatter};
 
 use std::fs::{File, OpenOptions, remove_file};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::thread;
+use std::sync::{Arc, mpsc};
 use std::io::{Result, Error, ErrorKind};
 
 use rand::prelude::*;
 use std::io::prelude::*;
 
 use serde::{Serialize, Deserialize};
+use serde::de::DeserializeOwned;
 use bincode::{serialize, deserialize};
 use clear_on_drop::clear::Clear;
 
-use core_fpi::{G, rnd_scalar, Scalar, KeyEncoder};
+use core_fpi::{G, rnd_scalar, fingerprint, Scalar, RistrettoPoint, KeyEncoder};
 use core_fpi::ids::*;
 use core_fpi::authorizations::*;
 use core_fpi::disclosures::*;
+use core_fpi::records::{stream_id, Record, RecordData, RecordType, NewRecord, RecordStreamQuery, OPEN, TAIL};
 use core_fpi::messages::*;
 use core_fpi::keys::*;
-use core_fpi::shares::*;
+use core_fpi::recovery::*;
 
 use crate::config::{Peer, Config};
+use crate::vault::Vault;
+
+const DISCLOSE_RETRIES: usize = 3;             // retries when the peers answer from inconsistent heights
+const RECORDS_PAGE: usize = 100;                // records per stream query (the max accepted by the peers)
+const PSEUDONYM_KEY: &str = "p-master";         // master-key of the pseudonyms, the default base of the record streams
 
 fn select(home: &str, sid: &str, typ: SType) -> String {
     match typ {
         SType::Updating => format!("{}/{}.upd", home, sid),
         SType::Merged => format!("{}/{}.mrg", home, sid),
         SType::Stored => format!("{}/{}.sto", home, sid),
+        SType::Queued => format!("{}/{}.que", home, sid),
+        SType::Audited => format!("{}/{}.audit", home, sid),
+        SType::Disclosed => format!("{}/{}.dsc", home, sid),
     }
 }
 
@@ -53,49 +66,124 @@ fn read(name: &str) -> Option<Vec<u8>> {
 }
 
 fn write(name: &str, data: Vec<u8>) -> Result<()> {
-    let mut file = OpenOptions::new().write(true).create(true).open(name)?;
+    let mut file = OpenOptions::new().write(true).create(true).truncate(true).open(name)?;
     file.write_all(&data)
 }
 
+fn append(name: &str, line: &str) -> Result<()> {
+    let mut file = OpenOptions::new().append(true).create(true).open(name)?;
+    writeln!(file, "{}", line)
+}
+
 //-----------------------------------------------------------------------------------------------------------
 // Storage
 //-----------------------------------------------------------------------------------------------------------
-enum SType { Updating, Merged, Stored }
+enum SType { Updating, Merged, Stored, Queued, Audited, Disclosed }
+
+type Disclosure = (DiscloseRequest, Vec<DiscloseResult>);
 
 struct Storage {}
 
 impl Storage {
-    fn load(home: &str, sid: &str) -> (Option<Update>, Option<MySubject>, Option<MySubject>) {
-        let upd_data = read(&select(home, sid, SType::Updating));
-        let mrg_data = read(&select(home, sid, SType::Merged));
-        let sto_data = read(&select(home, sid, SType::Stored));
-
-        // read what you can and ignore the rest
-        let upd: Option<Update> = match upd_data { None => None, Some(data) => deserialize(&data).ok() };
-        let mrg: Option<MySubject> = match mrg_data { None => None, Some(data) => deserialize(&data).ok() };
-        let sto: Option<MySubject> = match sto_data { None => None, Some(data) => deserialize(&data).ok() };
+    fn load(home: &str, sid: &str, vault: &Vault) -> Result<(Option<Update>, Option<MySubject>, Option<MySubject>, Vec<Operation>)> {
+        // files with secret material are sealed, the queue has none
+        let upd_data = Storage::open(home, sid, SType::Updating, vault)?;
+        let mrg_data = Storage::open(home, sid, SType::Merged, vault)?;
+        let sto_data = Storage::open(home, sid, SType::Stored, vault)?;
+        let que_data = read(&select(home, sid, SType::Queued));
+
+        // a file that doesn't decode is reported, instead of silently replaced on the next write
+        let file = |typ: SType| select(home, sid, typ);
+        let upd: Option<Update> = upd_data.map(|data| Storage::decode(&data, &file(SType::Updating))).transpose()?;
+        let mrg = mrg_data.map(|data| Storage::subject(&data, &file(SType::Merged))).transpose()?;
+        let sto = sto_data.map(|data| Storage::subject(&data, &file(SType::Stored))).transpose()?;
+        let que: Vec<Operation> = que_data.map(|data| Storage::decode(&data, &file(SType::Queued))).transpose()?.unwrap_or_default();
         
-        (upd, mrg, sto)
+        Ok((upd, mrg, sto, que))
     }
 
-    fn update(home: &str, sid: &str, update: &Update) -> Result<()>{
-        let data = serialize(&update).map_err(|_| Error::new(ErrorKind::Other, "Unable to encode subject!"))?;
-        let file = select(home, sid, SType::Updating);
+    fn decode<T: DeserializeOwned>(data: &[u8], file: &str) -> Result<T> {
+        deserialize(data).map_err(|e| Error::new(ErrorKind::InvalidData, format!("Unable to decode {:?} - {}", file, e)))
+    }
 
-        write(&file, data)
+    // subjects stored before the negotiated keys are migrated without keys
+    fn subject(data: &[u8], file: &str) -> Result<MySubject> {
+        Storage::decode::<MySubject>(data, file).or_else(|e| match deserialize::<LegacySubject>(data) {
+            Ok(legacy) => Ok(MySubject { secret: legacy.secret, profile_secrets: legacy.profile_secrets, subject: legacy.subject, auths: legacy.auths, keys: Vec::new() }),
+            Err(_) => Err(e)
+        })
     }
 
-    fn store(home: &str, sid: &str, typ: SType, my: &MySubject) -> Result<()> {
-        let data = serialize(&my).map_err(|_| Error::new(ErrorKind::Other, "Unable to encode subject!"))?;
+    fn open(home: &str, sid: &str, typ: SType, vault: &Vault) -> Result<Option<Vec<u8>>> {
         let file = select(home, sid, typ);
+        match read(&file) {
+            None => Ok(None),
+            Some(data) if Vault::is_sealed(&data) => vault.open(&data).map(Some),
+
+            // plaintext file of the previous store format, sealed in place
+            Some(data) => {
+                write(&file, vault.seal(&data)?)?;
+                Ok(Some(data))
+            }
+        }
+    }
+
+    fn update(home: &str, sid: &str, update: &Update, vault: &Vault) -> Result<()>{
+        let mut data = serialize(&update).map_err(|_| Error::new(ErrorKind::Other, "Unable to encode subject!"))?;
+        let sealed = vault.seal(&data);
+        data.as_mut_slice().clear();
+
+        let file = select(home, sid, SType::Updating);
+        write(&file, sealed?)
+    }
+
+    fn queue(home: &str, sid: &str, queue: &[Operation]) -> Result<()> {
+        let file = select(home, sid, SType::Queued);
+        if queue.is_empty() {
+            remove_file(&file).ok();
+            return Ok(())
+        }
 
+        let data = serialize(queue).map_err(|_| Error::new(ErrorKind::Other, "Unable to encode queue!"))?;
         write(&file, data)
     }
 
+    // disclosures received by the subject, the evidence to query the disclosed streams
+    fn disclosures(home: &str, sid: &str, vault: &Vault) -> Result<Vec<Disclosure>> {
+        match Storage::open(home, sid, SType::Disclosed, vault)? {
+            None => Ok(Vec::new()),
+            Some(data) => deserialize(&data).map_err(|_| Error::new(ErrorKind::Other, "Unable to decode disclosures!"))
+        }
+    }
+
+    fn disclosed(home: &str, sid: &str, disclosures: &[Disclosure], vault: &Vault) -> Result<()> {
+        let data = serialize(disclosures).map_err(|_| Error::new(ErrorKind::Other, "Unable to encode disclosures!"))?;
+        let file = select(home, sid, SType::Disclosed);
+        write(&file, vault.seal(&data)?)
+    }
+
+    fn audit(home: &str, sid: &str, line: &str) -> Result<()> {
+        let file = select(home, sid, SType::Audited);
+        append(&file, line)
+    }
+
+    fn store(home: &str, sid: &str, typ: SType, my: &MySubject, vault: &Vault) -> Result<()> {
+        let mut data = serialize(&my).map_err(|_| Error::new(ErrorKind::Other, "Unable to encode subject!"))?;
+        let sealed = vault.seal(&data);
+        data.as_mut_slice().clear();
+
+        let file = select(home, sid, typ);
+        write(&file, sealed?)
+    }
+
     fn reset(home: &str, sid: &str) {
         Storage::clean(home, sid);
         let sto = select(home, sid, SType::Stored);
+        let que = select(home, sid, SType::Queued);
+        let dsc = select(home, sid, SType::Disclosed);
         remove_file(&sto).ok();
+        remove_file(&que).ok();
+        remove_file(&dsc).ok();
     }
 
     fn clean(home: &str, sid: &str) {
@@ -111,7 +199,7 @@ impl Storage {
 //-----------------------------------------------------------------------------------------------------------
 // SubjectManager
 //-----------------------------------------------------------------------------------------------------------
-pub struct SubjectManager<F, Q> where F: Fn(&Peer, Commit) -> Result<()>, Q: Fn(&Peer, Request) -> Result<Response> {
+pub struct SubjectManager<F, Q> where F: Fn(&Peer, Commit) -> Result<i64>, Q: Fn(&Peer, Request) -> Result<Response> + Send + Sync + 'static {
     pub home: String,
     pub sid: String,
     pub config: Config,
@@ -119,15 +207,38 @@ pub struct SubjectManager<F, Q> where F: Fn(&Peer, Commit) -> Result<()>, Q: Fn(
     pub upd: Option<Update>,
     pub mrg: Option<MySubject>,
     pub sto: Option<MySubject>,
+    pub queue: Vec<Operation>,
+    pub pinned: Option<usize>,                          // peer index forced for single-peer operations
 
+    vault: Vault,
     commit: F,
-    query: Q
+    query: Arc<Q>                                       // shared with the threads of the concurrent queries
 }
 
-impl<F: Fn(&Peer, Commit) -> Result<()>, Q: Fn(&Peer, Request) -> Result<Response>> SubjectManager<F, Q> {
-    pub fn new(home: &str, sid: &str, cfg: Config, commit: F, query: Q) -> Self {
-        let res = Storage::load(home, sid);
-        Self { home: home.into(), sid: sid.into(), config: cfg, upd: res.0, mrg: res.1, sto: res.2, commit, query }
+impl<F: Fn(&Peer, Commit) -> Result<i64>, Q: Fn(&Peer, Request) -> Result<Response> + Send + Sync + 'static> SubjectManager<F, Q> {
+    pub fn new(home: &str, sid: &str, passphrase: &str, cfg: Config, commit: F, query: Q) -> Result<Self> {
+        let vault = Vault::new(passphrase);
+        let res = Storage::load(home, sid, &vault)?;
+        Ok(Self { home: home.into(), sid: sid.into(), config: cfg, upd: res.0, mrg: res.1, sto: res.2, queue: res.3, pinned: None, vault, commit, query: Arc::new(query) })
+    }
+
+    // force the peer (by index or host) instead of the random selection, for diagnostics
+    pub fn pin(&mut self, peer: &str) -> Result<()> {
+        let index = match peer.parse::<usize>() {
+            Ok(index) => index,
+            Err(_) => {
+                let host = if peer.ends_with('/') { &peer[..peer.len()-1] } else { peer };
+                self.config.peers.iter().position(|item| item.host == host)
+                    .ok_or_else(|| Error::new(ErrorKind::Other, format!("No peer found for host {:?}!", peer)))?
+            }
+        };
+
+        if index >= self.config.peers.len() {
+            return Err(Error::new(ErrorKind::Other, format!("No peer found at index {}!", index)))
+        }
+
+        self.pinned = Some(index);
+        Ok(())
     }
 
     pub fn reset(&mut self) {
@@ -135,7 +246,14 @@ impl<F: Fn(&Peer, Commit) -> Result<()>, Q: Fn(&Peer, Request) -> Result<Respons
     }
 
     pub fn create(&mut self) -> Result<()> {
+        let res = self.create_subject();
+        self.audit("Create", &res)?;
+        res
+    }
+
+    fn create_subject(&mut self) -> Result<()> {
         self.check_pending()?;
+        self.check_peers(1)?;
         if self.sto.is_some() {
             return Err(Error::new(ErrorKind::Other, "You already have a subject in the store!"))
         }
@@ -148,193 +266,409 @@ impl<F: Fn(&Peer, Commit) -> Result<()>, Q: Fn(&Peer, Request) -> Result<Respons
 
         // sync update
         let update = Update { sid: self.sid.clone(), msg: Value::VSubject(subject), secret, profile_secrets: HashMap::new() };
-        Storage::update(&self.home, &self.sid, &update)?;
+        Storage::update(&self.home, &self.sid, &update, &self.vault)?;
         self.upd = Some(update);
         self.submit()
     }
 
     pub fn evolve(&mut self) -> Result<()> {
+        self.execute(Operation::Evolve)
+    }
+
+    // evolve the subject-key and re-sign the active profile-keys under the new key
+    pub fn reanchor(&mut self) -> Result<()> {
+        self.execute(Operation::Reanchor)
+    }
+
+    pub fn profile(&mut self, typ: &str, lurl: &str, encrypted: bool) -> Result<()> {
+        self.execute(Operation::Profile(typ.into(), lurl.into(), encrypted))
+    }
+
+    // create the profile and consent to it in a single submission
+    pub fn profile_consent(&mut self, typ: &str, lurl: &str, encrypted: bool, authorized: &str) -> Result<()> {
+        self.execute(Operation::ProfileConsent(typ.into(), lurl.into(), encrypted, authorized.into()))
+    }
+
+    pub fn migrate(&mut self, typ: &str, from: &str, to: &str) -> Result<()> {
+        self.execute(Operation::Migrate(typ.into(), from.into(), to.into()))
+    }
+
+    pub fn consent(&mut self, authorized: &str, profiles: &[String], purpose: Option<&str>) -> Result<()> {
+        self.execute(Operation::consent(authorized, profiles, purpose))
+    }
+
+    pub fn revoke(&mut self, authorized: &str, profiles: &[String]) -> Result<()> {
+        self.execute(Operation::Revoke(authorized.into(), profiles.to_vec()))
+    }
+
+    // revoke a compromised subject-key, the active key is replaced by an evolution
+    pub fn revoke_key(&mut self, index: usize, at: Option<i64>) -> Result<()> {
+        self.execute(Operation::RevokeKey(index, at))
+    }
+
+    // split the subject secret across the trustees, returning the private shares to hand out-of-band (never stored)
+    pub fn recovery(&mut self, trustees: &[String], threshold: usize) -> Result<Vec<RecoveryShare>> {
+        let res = self.setup_recovery(trustees, threshold);
+        let audited = res.as_ref().map(|_| ()).map_err(|e| Error::new(e.kind(), e.to_string()));
+        self.audit(&format!("Recovery({:?}, threshold = {:?})", trustees, threshold), &audited)?;
+        res
+    }
+
+    fn setup_recovery(&mut self, trustees: &[String], threshold: usize) -> Result<Vec<RecoveryShare>> {
         self.check_pending()?;
+        self.check_peers(1)?;
 
-        match &self.sto {
-            None => Err(Error::new(ErrorKind::Other, "There is not subject in the store!")),
-            Some(my) => {
-                let (secret, skey) = my.subject.evolve(my.secret);
+        let my = self.sto.as_ref().ok_or_else(|| Error::new(ErrorKind::Other, "There is not subject in the store!"))?;
+        let skey = my.subject.active_key().map_err(|e| Error::new(ErrorKind::Other, e))?;
+        let (recovery, shares) = Recovery::setup(&self.sid, trustees, threshold, &my.secret, skey)
+            .map_err(|e| Error::new(ErrorKind::Other, e))?;
 
-                let mut subject = Subject::new(&self.sid);
-                subject.keys.push(skey);
+        // sync update
+        let update = Update { sid: self.sid.clone(), msg: Value::VRecovery(recovery), secret: my.secret, profile_secrets: HashMap::new() };
+        Storage::update(&self.home, &self.sid, &update, &self.vault)?;
+        self.upd = Some(update);
+        self.submit()?;
 
-                // sync update
-                let update = Update { sid: self.sid.clone(), msg: Value::VSubject(subject), secret, profile_secrets: HashMap::new() };
-                Storage::update(&self.home, &self.sid, &update)?;
-                self.upd = Some(update);
-                self.submit()
-            }
+        Ok(shares)
+    }
+
+    // the subject secret from a threshold of the trustees shares, it doesn't require the local store
+    pub fn recover(&self, shares: &[String]) -> Result<Scalar> {
+        let shares = shares.iter().map(|item| RecoveryShare::decode(item)).collect::<core_fpi::Result<Vec<_>>>()
+            .map_err(|e| Error::new(ErrorKind::Other, e))?;
+
+        if shares.iter().any(|item| item.sid != self.sid) {
+            return Err(Error::new(ErrorKind::Other, "Recovery share from a different subject!"))
         }
+
+        RecoveryShare::recover(&shares).map_err(|e| Error::new(ErrorKind::Other, e))
     }
 
-    pub fn profile(&mut self, typ: &str, lurl: &str, encrypted: bool) -> Result<()> {
+    // Rebuild the local store from the committed subject, with the secret recovered from the trustees shares.
+    // Only the derived profile secrets of the recovered key are restored, returns the profile locations without a secret.
+    pub fn restore(&mut self, shares: &[String]) -> Result<Vec<String>> {
+        let res = self.restore_subject(shares);
+        let audited = res.as_ref().map(|_| ()).map_err(|e| Error::new(e.kind(), e.to_string()));
+        self.audit("Restore", &audited)?;
+        res
+    }
+
+    fn restore_subject(&mut self, shares: &[String]) -> Result<Vec<String>> {
         self.check_pending()?;
+        self.check_peers(1)?;
+        if self.sto.is_some() {
+            return Err(Error::new(ErrorKind::Other, "You already have a subject in the store!"))
+        }
 
-        match &self.sto {
-            None => Err(Error::new(ErrorKind::Other, "There is not subject in the store!")),
-            Some(my) => {
-                let skey = my.subject.keys.last().ok_or_else(|| Error::new(ErrorKind::Other, "Subject doesn't have a key!"))?;
+        let secret = self.recover(shares)?;
+        let req = SubjectQuery::sign(&self.sid, &secret);
+
+        let sel = self.select().ok_or_else(|| Error::new(ErrorKind::Other, "No peer found to send request!"))?;
+        let subject = match (self.query)(sel, Request::Query(Query::QSubject(req.clone())))? {
+            Response::QResult(QResult::QSubjectResult(sr)) => {
+                sr.check(&req, &self.config.peers_keys)
+                    .map_err(|e| Error::new(ErrorKind::Other, e))?;
+
+                sr.subject
+            },
+            _ => return Err(Error::new(ErrorKind::Other, "Unexpected response on subject query!"))
+        };
 
-                let mut profile = Profile::new(typ);
-                let (secret, location) = match my.subject.find(typ) {
-                    None => profile.evolve(&self.sid, &lurl, encrypted, &my.secret, skey),
-                    Some(current) => current.evolve(&self.sid, &lurl, encrypted, &my.secret, skey)
+        // active profile-keys derived from the recovered secret, the others are lost (and can be evolved)
+        let mut profile_secrets = HashMap::<String, Scalar>::new();
+        let mut lost = Vec::<String>::new();
+        for (typ, prof) in subject.profiles.iter() {
+            for (lurl, loc) in prof.locations.iter() {
+                let pkey = match loc.chain.last() {
+                    Some(pkey) if pkey.migrated_to.is_none() => pkey,
+                    _ => continue
                 };
-                
-                profile.push(location);
 
-                let mut profile_secrets = HashMap::<String, Scalar>::new();
-                profile_secrets.insert(ProfileLocation::pid(typ, lurl), secret);
+                let derived = ProfileLocation::derive_secret(&secret, typ, lurl, pkey.index);
+                match derived * G == pkey.pkey {
+                    true => { profile_secrets.insert(ProfileLocation::pid(typ, lurl), derived); },
+                    false => lost.push(ProfileLocation::pid(typ, lurl))
+                }
+            }
+        }
 
-                let mut subject = Subject::new(&self.sid);
-                subject.push(profile);
+        // authorizations from the committed consents and revokes, the query is signed with the restored subject
+        self.sto = Some(MySubject { secret, profile_secrets, subject, auths: Authorizations::new(), keys: Vec::new() });
+        let consents = self.consents();
 
-                // sync update
-                let update = Update { sid: self.sid.clone(), msg: Value::VSubject(subject), secret: my.secret, profile_secrets };
-                Storage::update(&self.home, &self.sid, &update)?;
-                self.upd = Some(update);
-                self.submit()
+        // This unwrap() should never fail, the store was set above!
+        let mut my = self.sto.take().unwrap();
+        for consent in consents?.iter() {
+            match consent.typ {
+                ConsentType::Consent => my.auths.authorize(consent),
+                ConsentType::Revoke => my.auths.revoke(consent)
             }
         }
+
+        Storage::store(&self.home, &self.sid, SType::Stored, &my, &self.vault)?;
+        self.sto = Some(my);
+        Ok(lost)
     }
 
-    pub fn consent(&mut self, authorized: &str, profiles: &[String]) -> Result<()> {
-        self.check_pending()?;
-        
-        match &self.sto {
-            None => Err(Error::new(ErrorKind::Other, "There is not subject in the store!")),
-            Some(my) => {
-                let skey = my.subject.keys.last().ok_or_else(|| Error::new(ErrorKind::Other, "Subject doesn't have a key!"))?;
-                let consent = Consent::sign(&self.sid, ConsentType::Consent, authorized, profiles, &my.secret, skey);
+    // accepts operations even with a pending synchronization, these are only processed on flush
+    pub fn enqueue(&mut self, op: Operation) -> Result<()> {
+        self.queue.push(op);
+        Storage::queue(&self.home, &self.sid, &self.queue)
+    }
 
-                // sync update
-                let update = Update { sid: self.sid.clone(), msg: Value::VConsent(consent), secret: my.secret, profile_secrets: HashMap::new() };
-                Storage::update(&self.home, &self.sid, &update)?;
-                self.upd = Some(update);
-                self.submit()
-            }
+    // finish any pending synchronization and process the queued operations in order
+    pub fn flush(&mut self) -> Result<()> {
+        self.check_peers(1)?;
+
+        if self.mrg.is_some() {
+            self.store(&self.sid.clone())?;
+        }
+
+        if self.upd.is_some() {
+            self.submit()?;
         }
+
+        while !self.queue.is_empty() {
+            // the update is in the log before it leaves the queue, a failure is recovered on the next flush
+            let op = self.queue[0].clone();
+            self.prepare(&op)?;
+            self.queue.remove(0);
+            Storage::queue(&self.home, &self.sid, &self.queue)?;
+
+            let res = self.submit();
+            self.audit(&format!("{:?}", op), &res)?;
+            res?;
+        }
+
+        Ok(())
     }
 
-    pub fn revoke(&mut self, authorized: &str, profiles: &[String]) -> Result<()> {
-        self.check_pending()?;
-        
-        match &self.sto {
-            None => Err(Error::new(ErrorKind::Other, "There is not subject in the store!")),
-            Some(my) => {
-                let skey = my.subject.keys.last().ok_or_else(|| Error::new(ErrorKind::Other, "Subject doesn't have a key!"))?;
+    fn execute(&mut self, op: Operation) -> Result<()> {
+        let res = self.check_pending()
+            .and_then(|_| self.check_peers(1))
+            .and_then(|_| self.prepare(&op))
+            .and_then(|_| self.submit());
+
+        self.audit(&format!("{:?}", op), &res)?;
+        res
+    }
+
+    // create the update for the operation in the write-ahead log, it's signed against the current stored state
+    fn prepare(&mut self, op: &Operation) -> Result<()> {
+        let my = self.sto.as_ref().ok_or_else(|| Error::new(ErrorKind::Other, "There is not subject in the store!"))?;
+
+        let mut update = match op {
+            Operation::Evolve => {
+                let (secret, skey) = my.subject.evolve(my.secret);
+
+                let mut subject = Subject::new(&self.sid);
+                subject.keys.push(skey);
+
+                Update { sid: self.sid.clone(), msg: Value::VSubject(subject), secret, profile_secrets: HashMap::new() }
+            },
+
+            Operation::Reanchor => {
+                // the profile secrets don't change, re-anchored keys reuse the active public keys
+                let (secret, subject) = my.subject.reanchor(my.secret).map_err(|e| Error::new(ErrorKind::Other, e))?;
+                Update { sid: self.sid.clone(), msg: Value::VSubject(subject), secret, profile_secrets: HashMap::new() }
+            },
+
+            Operation::Profile(typ, lurl, encrypted) => {
+                let (subject, profile_secrets) = self.profile_update(my, typ, lurl, *encrypted)?;
+                Update { sid: self.sid.clone(), msg: Value::VSubject(subject), secret: my.secret, profile_secrets }
+            },
+
+            Operation::ProfileConsent(typ, lurl, encrypted, authorized) => {
+                let (subject, profile_secrets) = self.profile_update(my, typ, lurl, *encrypted)?;
+
+                let skey = my.subject.active_key().map_err(|e| Error::new(ErrorKind::Other, e))?;
+                let consent = Consent::sign(&self.sid, ConsentType::Consent, authorized, &[typ.clone()], &my.secret, skey);
+
+                Update { sid: self.sid.clone(), msg: Value::VProfileConsent(ProfileConsent::new(subject, consent)), secret: my.secret, profile_secrets }
+            },
+
+            Operation::Migrate(typ, from, to) => {
+                let skey = my.subject.active_key().map_err(|e| Error::new(ErrorKind::Other, e))?;
+                let current = my.subject.find(typ).ok_or_else(|| Error::new(ErrorKind::Other, "No profile found to migrate!"))?;
+
+                let (secret, profile) = current.migrate(&self.sid, from, to, &my.secret, skey)
+                    .map_err(|e| Error::new(ErrorKind::Other, e))?;
+
+                let mut profile_secrets = HashMap::<String, Scalar>::new();
+                profile_secrets.insert(ProfileLocation::pid(typ, to), secret);
+
+                let mut subject = Subject::new(&self.sid);
+                subject.push(profile);
+
+                Update { sid: self.sid.clone(), msg: Value::VSubject(subject), secret: my.secret, profile_secrets }
+            },
+
+            Operation::Consent(authorized, profiles) => {
+                let skey = my.subject.active_key().map_err(|e| Error::new(ErrorKind::Other, e))?;
+                let consent = Consent::sign(&self.sid, ConsentType::Consent, authorized, profiles, &my.secret, skey);
+
+                Update { sid: self.sid.clone(), msg: Value::VConsent(consent), secret: my.secret, profile_secrets: HashMap::new() }
+            },
+
+            Operation::ConsentPurpose(authorized, profiles, purpose) => {
+                let skey = my.subject.active_key().map_err(|e| Error::new(ErrorKind::Other, e))?;
+                let consent = Consent::sign_with_purpose(&self.sid, ConsentType::Consent, authorized, profiles, Some(purpose), &my.secret, skey);
+
+                Update { sid: self.sid.clone(), msg: Value::VConsent(consent), secret: my.secret, profile_secrets: HashMap::new() }
+            },
+
+            Operation::Revoke(authorized, profiles) => {
+                let skey = my.subject.active_key().map_err(|e| Error::new(ErrorKind::Other, e))?;
                 let revoke = Consent::sign(&self.sid, ConsentType::Revoke, authorized, profiles, &my.secret, skey);
 
-                // sync update
-                let update = Update { sid: self.sid.clone(), msg: Value::VConsent(revoke), secret: my.secret, profile_secrets: HashMap::new() };
-        
-                Storage::update(&self.home, &self.sid, &update)?;
-                self.upd = Some(update);
-                self.submit()
+                Update { sid: self.sid.clone(), msg: Value::VConsent(revoke), secret: my.secret, profile_secrets: HashMap::new() }
+            },
+
+            Operation::RevokeKey(index, at) => {
+                let active = my.subject.active_key().map_err(|e| Error::new(ErrorKind::Other, e))?;
+                let mut subject = Subject::new(&self.sid);
+
+                let secret = if *index == active.sig.index {
+                    // the new key signs the revocation, the evolution (signed by the revoked key) must precede it
+                    let (secret, skey) = my.subject.evolve(my.secret);
+                    let at = at.unwrap_or(skey.sig.sig.timestamp);
+                    if at < skey.sig.sig.timestamp {
+                        return Err(Error::new(ErrorKind::Other, "The active subject-key can only be revoked after the evolution that replaces it!"))
+                    }
+
+                    subject.revocations.push(Revocation::sign(&self.sid, *index, at, &secret, &skey));
+                    subject.keys.push(skey);
+                    secret
+                } else {
+                    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|t| t.as_secs() as i64).unwrap_or(0);
+                    subject.revocations.push(Revocation::sign(&self.sid, *index, at.unwrap_or(now), &my.secret, active));
+                    my.secret
+                };
+
+                Update { sid: self.sid.clone(), msg: Value::VSubject(subject), secret, profile_secrets: HashMap::new() }
             }
+        };
+
+        // optimistic concurrency, the node rejects subject updates based on a stale subject
+        let active = my.subject.active_key().map_err(|e| Error::new(ErrorKind::Other, e))?;
+        match &mut update.msg {
+            Value::VSubject(subject) => { subject.based_on(&my.subject, &my.secret, active); },
+            Value::VProfileConsent(bundle) => { bundle.update.based_on(&my.subject, &my.secret, active); },
+            _ => ()
         }
+
+        // sync update
+        Storage::update(&self.home, &self.sid, &update, &self.vault)?;
+        self.upd = Some(update);
+        Ok(())
+    }
+
+    // the first 2t + 1 valid results of all peers, a pinned peer must be one of them
+    fn query_disclose(&self, disclose: &DiscloseRequest) -> Result<Vec<DiscloseResult>> {
+        let min = 2*self.config.threshold + 1;
+        let pinned = self.pinned.map(|pinned| self.config.peers[pinned].host.as_str());
+
+        let peers_keys = &self.config.peers_keys;
+        query_quorum(&self.config.peers, &self.query, Request::Query(Query::QDiscloseRequest(disclose.clone())), min, pinned, |res| {
+            match res {
+                Response::QResult(res) => {
+                    // never trust a query result without the peer signature
+                    res.verify(peers_keys).map_err(|e| Error::new(ErrorKind::Other, e))?;
+                    match res {
+                        QResult::QDiscloseResult(dr) => Ok((dr.sig.index, dr)),
+                        _ => Err(Error::new(ErrorKind::Other, "Unexpected response on disclosure!"))
+                    }
+                },
+                _ => Err(Error::new(ErrorKind::Other, "Unexpected response on disclosure!"))
+            }
+        })
+    }
+
+    // subject update with a new location for the profile, and the respective profile secret
+    fn profile_update(&self, my: &MySubject, typ: &str, lurl: &str, encrypted: bool) -> Result<(Subject, HashMap<String, Scalar>)> {
+        let skey = my.subject.active_key().map_err(|e| Error::new(ErrorKind::Other, e))?;
+
+        let mut builder = ProfileBuilder::new(&self.sid, typ, my.subject.find(typ), &my.secret, skey);
+        match self.config.derived_keys {
+            true => builder.derive(lurl, encrypted),
+            false => builder.evolve(lurl, encrypted)
+        }.map_err(|e| Error::new(ErrorKind::Other, e))?;
+
+        let (profile_secrets, profile) = builder.build().map_err(|e| Error::new(ErrorKind::Other, e))?;
+
+        let mut subject = Subject::new(&self.sid);
+        subject.push(profile);
+
+        Ok((subject, profile_secrets))
+    }
+
+    pub fn disclose(&mut self, target: &str, profiles: &[String], hashed: bool, partial: bool) -> Result<()> {
+        let res = self.request_disclose(target, profiles, hashed, partial);
+        self.audit(&format!("Disclose({:?}, {:?}, partial = {:?})", target, profiles, partial), &res)?;
+        res
     }
 
-    pub fn disclose(&mut self, target: &str, profiles: &[String]) -> Result<()> {
+    fn request_disclose(&mut self, target: &str, profiles: &[String], hashed: bool, partial: bool) -> Result<()> {
         self.check_pending()?;
+        self.check_peers(2*self.config.threshold + 1)?;
         
         match &self.sto {
             None => Err(Error::new(ErrorKind::Other, "There is not subject in the store!")),
             Some(my) => {
-                let skey = my.subject.keys.last().ok_or_else(|| Error::new(ErrorKind::Other, "Subject doesn't have a key!"))?;
-                let disclose = DiscloseRequest::sign(&self.sid, target, profiles, &my.secret, skey);
-
-                let min = 2*self.config.threshold + 1;
+                let skey = my.subject.active_key().map_err(|e| Error::new(ErrorKind::Other, e))?;
+                // retry until the peers answer from a consistent snapshot, the peers serve a replayed request with the same response
+                let mut retries = 0;
+                let (disclose, results) = loop {
+                    let disclose = DiscloseRequest::sign(&self.sid, target, profiles, partial, &my.secret, skey);
+                    let results = self.query_disclose(&disclose)?;
+                    match check_heights(&results, self.config.height_tolerance) {
+                        Ok(_) => break (disclose, results),
+                        Err(e) => {
+                            if retries == DISCLOSE_RETRIES {
+                                return Err(Error::new(ErrorKind::Other, e))
+                            }
 
-                // select a random set of 2t + 1 peers
-                let mut rng = rand::thread_rng();
-                let mut peers = self.config.peers.clone();
-                peers.shuffle(&mut rng);
+                            // give time for the lagging peers to commit the next blocks
+                            retries += 1;
+                            thread::sleep(Duration::from_secs(1));
+                        }
+                    }
+                };
 
-                if peers.len() < min {
-                    return Err(Error::new(ErrorKind::Other, "Not enought peers to process disclosure!"))
-                }
+                let disclosed = reconstruct_pseudonyms(&disclose, &results, &self.config.peers_keys, self.config.threshold)
+                    .map_err(|e| Error::new(ErrorKind::Other, e))?;
 
-                let mut results = HashMap::<usize, DiscloseResult>::with_capacity(2*self.config.threshold + 1);
-                let selected = &peers[..min];
-                for sel in selected.iter() {
-                    let res = (self.query)(&sel, Request::Query(Query::QDiscloseRequest(disclose.clone())))?;
-                    match res {
-                        Response::QResult(res) => match res {
-                            QResult::QDiscloseResult(dr) => {
-                                let peer = self.config.peers.get(dr.sig.index).ok_or("Unexpected peer index!")
-                                    .map_err(|e| Error::new(ErrorKind::Other, e))?;
-                                
-                                dr.check(&disclose.sig.sig.encoded, profiles, &peer.pkey)
-                                    .map_err(|e| Error::new(ErrorKind::Other, e))?;
-
-                                if results.get(&dr.sig.index).is_some() {
-                                    // TODO: replace this with ignore or retry strategy?
-                                    return Err(Error::new(ErrorKind::Other, "Replaced response on key disclosure!"))
-                                }
-
-                                results.insert(dr.sig.index, dr);
-                            }
-                        },
-                        _ => return Err(Error::new(ErrorKind::Other, "Unexpected response on disclosure!"))
+                // the last disclosure of the same profiles replaces the previous one
+                let mut disclosures = Storage::disclosures(&self.home, &self.sid, &self.vault)?;
+                disclosures.retain(|(request, _)| request.target != disclose.target || request.profiles != disclose.profiles);
+                disclosures.push((disclose.clone(), results));
+                Storage::disclosed(&self.home, &self.sid, &disclosures, &self.vault)?;
+
+                for (key, pseudo) in disclosed.pseudonyms.iter() {
+                    if hashed {
+                        println!("STREAM {} -> {}", key, stream_id(pseudo));
+                    } else {
+                        println!("PSEUDO {} -> {}", key, pseudo.encode());
                     }
-                    
                 }
 
-                if results.len() < min {
-                    // TODO: try other peers?
-                    return Err(Error::new(ErrorKind::Other, "Not enought responses to process disclosure!"))
-                }
-                
-                // check and combine results to get pseudonyms
-                let mut pseudo_poly_shares = HashMap::<String, Vec<RistrettoShare>>::new();
-                let mut crypto_poly_shares = HashMap::<String, Vec<RistrettoShare>>::new();
-                for (n, dr) in results.into_iter() {
-                    for (typ, locs) in dr.keys.keys.into_iter() {
-                        for (loc, shares) in locs.into_iter() {
-                            for (i, rs) in shares.into_iter().enumerate() {
-                                let key = format!("{}-{}-{}", typ, loc, i);
-
-                                // collect pseudo shares
-                                let v_shares = pseudo_poly_shares.entry(key.clone()).or_insert_with(|| Vec::<RistrettoShare>::new());
-                                v_shares.push(RistrettoShare { i: (n + 1) as u32, Yi: rs.0 });
-
-                                if let Some(crypto) = rs.1 {
-                                    // collect crypto shares
-                                    let v_shares = crypto_poly_shares.entry(key).or_insert_with(|| Vec::<RistrettoShare>::new());
-                                    v_shares.push(RistrettoShare { i: (n + 1) as u32, Yi: crypto });
-                                }
-                            }
-                        }
-                    }
+                for (key, crypto) in disclosed.encryptions.iter() {
+                    println!("CRYPTO {} -> {}", key, crypto.encode());
                 }
 
-                // reconstruct pseudonyms
-                for (key, shares) in pseudo_poly_shares.iter() {
-                    let rpoly = RistrettoPolynomial::reconstruct(&shares);
-                    if rpoly.degree() != self.config.threshold {
-                        return Err(Error::new(ErrorKind::Other, "Incorrect set of pseudo shares!"))
-                    }
-
-                    let pseudo = rpoly.evaluate(&Scalar::zero());
-                    println!("PSEUDO {} -> {}", key, pseudo.encode());
+                // profiles without authorization (partial disclosure)
+                for typ in disclosed.denied.iter() {
+                    println!("DENIED {}", typ);
                 }
 
-                // reconstruct encryption secrets
-                for (key, shares) in crypto_poly_shares.iter() {
-                    let rpoly = RistrettoPolynomial::reconstruct(&shares);
-                    if rpoly.degree() != self.config.threshold {
-                        return Err(Error::new(ErrorKind::Other, "Incorrect set of crypto shares!"))
-                    }
+                // authorized profiles that were removed from the target
+                for typ in disclosed.absent.iter() {
+                    println!("ABSENT {}", typ);
+                }
 
-                    let crypto = rpoly.evaluate(&Scalar::zero());
-                    println!("CRYPTO {} -> {}", key, crypto.encode());
+                // locations that were moved to another profile server
+                for (pid, to) in disclosed.migrations.iter() {
+                    println!("MIGRATED {} -> {}", pid, to);
                 }
 
                 Ok(())
@@ -342,98 +676,521 @@ impl<F: Fn(&Peer, Commit) -> Result<()>, Q: Fn(&Peer, Request) -> Result<Respons
         }
     }
 
-    pub fn negotiate(&mut self, kid: &str) -> Result<()> {
-        self.check_pending()?;
-        
+    pub fn check_unlinkability(&self) -> Result<Vec<(String, String)>> {
         match &self.sto {
             None => Err(Error::new(ErrorKind::Other, "There is not subject in the store!")),
             Some(my) => {
-                let n = self.config.peers.len();
+                let mut linked = my.subject.linked_keys();
 
-                let skey = my.subject.keys.last().ok_or_else(|| Error::new(ErrorKind::Other, "Subject doesn't have a key!"))?;
-                let req = MasterKeyRequest::sign(&self.sid, kid, &self.config.peers_hash, &my.secret, skey);
+                // profile secrets must not be derivable from each other or from the subject secret
+                let secrets: Vec<(String, Scalar)> = my.profile_secrets.iter().map(|(pid, secret)| (pid.clone(), *secret)).collect();
+                linked.extend(linked_secrets(&secrets, &[Scalar::one(), my.secret]));
 
-                // set the results in ordered fashion
-                let mut votes = Vec::<MasterKeyVote>::with_capacity(n);
-                for peer in self.config.peers.iter() {
-                    let res = (self.query)(peer, Request::Negotiate(Negotiate::NMasterKeyRequest(req.clone())))?;
-                    match res {
-                        Response::Vote(vote) => match vote {
-                            Vote::VMasterKeyVote(vote) => {
-                                let peer = self.config.peers.get(vote.sig.index).ok_or("Unexpected peer index!")
-                                    .map_err(|e| Error::new(ErrorKind::Other, e))?;
-                                
-                                vote.check(&req.sig.id(), &kid, &self.config.peers_hash, self.config.peers.len(), &peer.pkey)
-                                    .map_err(|e| Error::new(ErrorKind::Other, e))?;
-
-                                if votes.get(vote.sig.index).is_some() {
-                                    // TODO: replace this with ignore or retry strategy?
-                                    return Err(Error::new(ErrorKind::Other, "Replaced response on key negotiation!"))
-                                }
-
-                                votes.insert(vote.sig.index, vote);
-                            }
-                        },
-                        _ => return Err(Error::new(ErrorKind::Other, "Unexpected response on key negotiation!"))
-                    }
+                Ok(linked)
+            }
+        }
+    }
+
+    pub fn master_key(&self, kid: &str) -> Result<Option<RistrettoPoint>> {
+        self.check_peers(1)?;
+
+        match &self.sto {
+            None => Err(Error::new(ErrorKind::Other, "There is not subject in the store!")),
+            Some(my) => {
+                let skey = my.subject.active_key().map_err(|e| Error::new(ErrorKind::Other, e))?;
+                let req = MasterKeyQuery::sign(&self.sid, kid, &my.secret, skey);
+
+                let sel = self.select().ok_or_else(|| Error::new(ErrorKind::Other, "No peer found to send request!"))?;
+                let res = (self.query)(sel, Request::Query(Query::QMasterKey(req)))?;
+                match res {
+                    Response::QResult(QResult::QMasterKeyResult(mkr)) => {
+                        // the public-key is only accepted with valid evidence
+                        mkr.check(kid, &self.config.peers_hash, &self.config.peers_keys, self.config.threshold)
+                            .map_err(|e| Error::new(ErrorKind::Other, e))?;
+
+                        Ok(mkr.public)
+                    },
+                    _ => Err(Error::new(ErrorKind::Other, "Unexpected response on master-key query!"))
                 }
+            }
+        }
+    }
 
-                // If all is OK, create MasterKey to commit
-                let mk = MasterKey::sign(&self.sid, &req.sig.id(), kid, &self.config.peers_hash, votes, &self.config.peers_keys, &my.secret, skey)
-                    .map_err(|e| Error::new(ErrorKind::Other, e))?;
+    // records of the stream, the base defaults to the pseudonym master-key
+    pub fn records(&self, pseudonym: &RistrettoPoint, base: Option<RistrettoPoint>, from_index: usize, limit: usize) -> Result<(usize, Vec<Record>)> {
+        self.query_records(pseudonym, base, from_index, None, limit)
+    }
+
+    // records of the stream after the record with the signature prev (or OPEN), the page position is returned by the peer
+    pub fn records_after(&self, pseudonym: &RistrettoPoint, base: Option<RistrettoPoint>, prev: &str, limit: usize) -> Result<(usize, Vec<Record>)> {
+        self.query_records(pseudonym, base, 0, Some(prev), limit)
+    }
 
-                // select a random peer
-                let selection = self.config.peers.choose(&mut rand::thread_rng());
+    fn query_records(&self, pseudonym: &RistrettoPoint, base: Option<RistrettoPoint>, from_index: usize, from_prev: Option<&str>, limit: usize) -> Result<(usize, Vec<Record>)> {
+        self.check_peers(1)?;
 
-                // process master-key commit
-                match selection {
-                    None => Err(Error::new(ErrorKind::Other, "No peer found to send request!")),
-                    Some(sel) => (self.commit)(&sel, Commit::Evidence(Evidence::EMasterKey(mk)))
+        let base = match base {
+            Some(base) => base,
+            None => self.master_key(PSEUDONYM_KEY)?
+                .ok_or_else(|| Error::new(ErrorKind::Other, "Pseudonym master-key not found!"))?
+        };
+
+        match &self.sto {
+            None => Err(Error::new(ErrorKind::Other, "There is not subject in the store!")),
+            Some(my) => {
+                let req = self.stream_query(my, pseudonym, &base, from_index, from_prev, limit)?;
+
+                let sel = self.select().ok_or_else(|| Error::new(ErrorKind::Other, "No peer found to send request!"))?;
+                let res = (self.query)(sel, Request::Query(Query::QRecordStream(req.clone())))?;
+                match res {
+                    Response::QResult(QResult::QRecordStream(rsr)) => {
+                        // the records must be signed by the pseudonym and chained
+                        rsr.check(&req, &self.config.peers_keys)
+                            .map_err(|e| Error::new(ErrorKind::Other, e))?;
+
+                        Ok((rsr.from_index, rsr.records))
+                    },
+                    _ => Err(Error::new(ErrorKind::Other, "Unexpected response on record stream query!"))
                 }
             }
         }
     }
 
-    fn check_pending(&self) -> Result<()> {
-        if self.upd.is_some() {
-            return Err(Error::new(ErrorKind::Other, "There is a pending synchronization in the log!"))
+    // The owner signs the query with the profile secret of the pseudonym, a reader presents the disclosure that revealed it.
+    fn stream_query(&self, my: &MySubject, pseudonym: &RistrettoPoint, base: &RistrettoPoint, from_index: usize, from_prev: Option<&str>, limit: usize) -> Result<RecordStreamQuery> {
+        let skey = my.subject.active_key().map_err(|e| Error::new(ErrorKind::Other, e))?;
+        if let Some(secret) = my.profile_secrets.values().find(|secret| *secret * base == *pseudonym) {
+            return Ok(match from_prev {
+                None => RecordStreamQuery::sign_owner(&self.sid, pseudonym, base, from_index, limit, secret, &my.secret, skey),
+                Some(prev) => RecordStreamQuery::sign_owner_after(&self.sid, pseudonym, base, prev, limit, secret, &my.secret, skey)
+            })
         }
 
-        if self.mrg.is_some() {
-            return Err(Error::new(ErrorKind::Other, "There is a pending synchronization in the log!"))
+        for (request, results) in Storage::disclosures(&self.home, &self.sid, &self.vault)?.into_iter() {
+            let disclosed = match reconstruct_pseudonyms(&request, &results, &self.config.peers_keys, self.config.threshold) {
+                Ok(disclosed) => disclosed,
+                Err(_) => continue
+            };
+
+            if disclosed.pseudonyms.values().any(|pseudo| pseudo == pseudonym) {
+                return Ok(match from_prev {
+                    None => RecordStreamQuery::sign_disclosed(&self.sid, pseudonym, base, from_index, limit, request, results, &my.secret, skey),
+                    Some(prev) => RecordStreamQuery::sign_disclosed_after(&self.sid, pseudonym, base, prev, limit, request, results, &my.secret, skey)
+                })
+            }
         }
 
-        Ok(())
+        Err(Error::new(ErrorKind::Other, "No disclosure found for the stream!"))
     }
 
-    // submit an existing update
-    fn submit(&mut self) -> Result<()> {
-        let update = self.upd.as_ref().ok_or_else(|| Error::new(ErrorKind::Other, "No update found to commit!"))?;
+    // all records of the profile-location stream (in chain order), and the position of the first one
+    pub fn profile_records(&self, typ: &str, lurl: &str) -> Result<(usize, Vec<Record>)> {
+        let (_, base, pseudonym) = self.profile_pseudonym(typ, lurl)?;
+        self.stream_records(&pseudonym, &base)
+    }
+
+    // append an owned record to the profile-location stream, returning the stream-id and the block height
+    pub fn push_record(&mut self, typ: &str, lurl: &str, rdata: RecordData) -> Result<(String, i64)> {
+        let res = self.commit_record(typ, lurl, Some(rdata));
+        let audited = res.as_ref().map(|_| ()).map_err(|e| Error::new(e.kind(), e.to_string()));
+        self.audit(&format!("Record({:?}, {:?})", typ, lurl), &audited)?;
+        res
+    }
+
+    // append the terminal record to the profile-location stream, no other record can be pushed after it
+    pub fn close_record(&mut self, typ: &str, lurl: &str) -> Result<(String, i64)> {
+        let res = self.commit_record(typ, lurl, None);
+        let audited = res.as_ref().map(|_| ()).map_err(|e| Error::new(e.kind(), e.to_string()));
+        self.audit(&format!("CloseRecord({:?}, {:?})", typ, lurl), &audited)?;
+        res
+    }
 
-        // select a random peer
-        let selection = self.config.peers.choose(&mut rand::thread_rng());
+    // without record data the stream is closed
+    fn commit_record(&self, typ: &str, lurl: &str, rdata: Option<RecordData>) -> Result<(String, i64)> {
+        self.check_peers(1)?;
 
-        // process sync message
-        match selection {
-            None => return Err(Error::new(ErrorKind::Other, "No peer found to request commit!")),
-            Some(sel) => (self.commit)(&sel, Commit::Value(update.msg.clone()))?
+        let (secret, base, pseudonym) = self.profile_pseudonym(typ, lurl)?;
+        let (_, tail) = self.records(&pseudonym, Some(base), TAIL, 1)?;
+        if tail.last().map(|last| last.is_closed()).unwrap_or(false) {
+            return Err(Error::new(ErrorKind::Other, "The stream is closed!"))
         }
 
-        self.merge()
+        // chained on the last record (the tail of the stream), or opening the stream
+        let prev = tail.last().map(|last| last.sig.encoded.clone()).unwrap_or_else(|| OPEN.into());
+        let record = match rdata {
+            Some(rdata) => Record::sign(&prev, RecordType::Owned, rdata, &base, &secret, &pseudonym),
+            None => Record::close(&prev, &base, &secret, &pseudonym)
+        };
+
+        let new_record = NewRecord { record, pseudonym, base, token: None };
+        let height = commit_failover(&self.candidates(), &self.commit, Commit::Value(Value::VNewRecord(new_record)))?;
+        Ok((stream_id(&pseudonym), height))
     }
 
-    // merge a submitted update
-    fn merge(&mut self) -> Result<()> {
-        let update = self.upd.take().ok_or_else(|| Error::new(ErrorKind::Other, "No update found to merge!"))?;
+    // The pseudonym of the profile-location under the pseudonym master-key, with the profile secret that signs the records.
+    // It's the same pseudonym reconstructed on a disclosure of the profile (the last key of the location).
+    fn profile_pseudonym(&self, typ: &str, lurl: &str) -> Result<(Scalar, RistrettoPoint, RistrettoPoint)> {
+        let my = self.sto.as_ref().ok_or_else(|| Error::new(ErrorKind::Other, "There is not subject in the store!"))?;
 
-        let merged = match self.sto.take() {
-            None => {
+        let pid = ProfileLocation::pid(typ, lurl);
+        let secret = *my.profile_secrets.get(&pid)
+            .ok_or_else(|| Error::new(ErrorKind::Other, format!("No profile key found for {:?}!", pid)))?;
+
+        // a type with its own base has a typed master-key, negotiated independently of the default one
+        let typed = match typed_kid(PSEUDONYM_KEY, typ) {
+            Ok(kid) => self.master_key(&kid)?,
+            Err(_) => None
+        };
+
+        let base = match typed {
+            Some(base) => base,
+            None => self.master_key(PSEUDONYM_KEY)?
+                .ok_or_else(|| Error::new(ErrorKind::Other, "Pseudonym master-key not found!"))?
+        };
+
+        Ok((secret, base, secret * base))
+    }
+
+    // page through the stream with the last record as the cursor, each page is checked to continue the chain of the cursor
+    fn stream_records(&self, pseudonym: &RistrettoPoint, base: &RistrettoPoint) -> Result<(usize, Vec<Record>)> {
+        let (first, mut records) = self.records(pseudonym, Some(*base), 0, RECORDS_PAGE)?;
+
+        let mut page_len = records.len();
+        while page_len == RECORDS_PAGE {
+            let cursor = records.last().map(|last| last.sig.encoded.clone()).unwrap_or_else(|| OPEN.into());
+            let (_, page) = self.records_after(pseudonym, Some(*base), &cursor, RECORDS_PAGE)?;
+
+            page_len = page.len();
+            records.extend(page);
+        }
+
+        Ok((first, records))
+    }
+
+    pub fn negotiate(&mut self, kid: &str) -> Result<()> {
+        let res = self.request_negotiate(kid);
+        self.audit(&format!("Negotiate({:?})", kid), &res)?;
+        res
+    }
+
+    fn request_negotiate(&mut self, kid: &str) -> Result<()> {
+        // all peers vote, and the evidence is only accepted for a federation with #peers >= 3 * t + 1
+        self.check_pending()?;
+        self.check_peers(3*self.config.threshold + 1)?;
+        
+        match &self.sto {
+            None => Err(Error::new(ErrorKind::Other, "There is not subject in the store!")),
+            Some(my) => {
+                let skey = my.subject.active_key().map_err(|e| Error::new(ErrorKind::Other, e))?;
+                let req = MasterKeyRequest::sign(&self.sid, kid, &self.config.peers_hash, &my.secret, skey);
+                let session = req.sig.id().to_string();
+                let votes = self.query_votes(Negotiate::NMasterKeyRequest(req.clone()))?;
+
+                let (mk, expected) = if votes.iter().any(|vote| matches!(vote, Vote::VPedersenKeyVote(_))) {
+                    // with Pedersen's commitments, the votes are committed before the peers reveal the public part of each secret
+                    let pk = self.commit_pedersen(my, votes, &session, kid, &req.peers)?;
+
+                    let reveal = MasterKeyReveal::sign(&self.sid, kid, &session, &my.secret, skey);
+                    let (mk, expected) = self.collect_votes(my, Negotiate::NMasterKeyReveal(reveal), &session, kid, &req.peers)?;
+                    pk.check_reveal(&mk).map_err(|e| Error::new(ErrorKind::Other, e))?;
+                    (mk, expected)
+                } else {
+                    self.sign_votes(my, feldman_votes(votes)?, &session, kid, &req.peers)?
+                };
+
+                // verify the evidence as the peers will, before it hits the chain
+                mk.check_aggregate(&expected)
+                    .and_then(|_| mk.check(&req.peers, &self.config.peers_keys, self.config.threshold, None))
+                    .map_err(|e| Error::new(ErrorKind::Other, e))?;
+
+                let (session, public) = (mk.session.clone(), mk.public());
+
+                // process master-key commit
+                let height = commit_failover(&self.candidates(), &self.commit, Commit::Evidence(Evidence::EMasterKey(mk)))?;
+
+                // record the committed key, replacing a previous negotiation of the same kid
+                let mut my = my.clone();
+                my.keys.retain(|item| item.kid != kid);
+                my.keys.push(NegotiatedKey { kid: kid.into(), session, public, height });
+
+                Storage::store(&self.home, &self.sid, SType::Stored, &my, &self.vault)?;
+                self.sto = Some(my);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn refresh(&mut self, kid: &str) -> Result<()> {
+        let res = self.request_refresh(kid);
+        self.audit(&format!("Refresh({:?})", kid), &res)?;
+        res
+    }
+
+    // rotate the peer shares of an existing master-key, the public-key doesn't change
+    fn request_refresh(&mut self, kid: &str) -> Result<()> {
+        // all peers vote, as in the negotiation
+        self.check_pending()?;
+        self.check_peers(3*self.config.threshold + 1)?;
+        
+        match &self.sto {
+            None => Err(Error::new(ErrorKind::Other, "There is not subject in the store!")),
+            Some(my) => {
+                let skey = my.subject.active_key().map_err(|e| Error::new(ErrorKind::Other, e))?;
+                let req = MasterKeyRefresh::sign(&self.sid, kid, &self.config.peers_hash, &my.secret, skey);
+                let (mk, expected) = self.collect_votes(my, Negotiate::NMasterKeyRefresh(req.clone()), &req.sig.id(), kid, &req.peers)?;
+
+                // the votes must share a zero secret, otherwise the peers reject the refresh
+                if expected != RistrettoPoint::default() {
+                    return Err(Error::new(ErrorKind::Other, "Refresh votes don't share a zero secret!"))
+                }
+
+                mk.check_aggregate(&expected)
+                    .and_then(|_| mk.check(&req.peers, &self.config.peers_keys, self.config.threshold, None))
+                    .map_err(|e| Error::new(ErrorKind::Other, e))?;
+
+                // process master-key refresh commit
+                commit_failover(&self.candidates(), &self.commit, Commit::Evidence(Evidence::EMasterKeyRefresh(mk)))?;
+                Ok(())
+            }
+        }
+    }
+
+    pub fn reshare(&mut self, kid: &str, threshold: usize, new_peers: &[RistrettoPoint]) -> Result<()> {
+        let res = self.request_reshare(kid, threshold, new_peers);
+        self.audit(&format!("Reshare({:?}, {:?}, {:?})", kid, threshold, new_peers.len()), &res)?;
+        res
+    }
+
+    // Reshare an existing master-key to a new peer-set (before the peer-set change), the public-key doesn't change.
+    // The votes of t + 1 share-holders are enough to reconstruct the shares of the new peers.
+    fn request_reshare(&mut self, kid: &str, threshold: usize, new_peers: &[RistrettoPoint]) -> Result<()> {
+        self.check_pending()?;
+        self.check_peers(self.config.threshold + 1)?;
+
+        let public = self.master_key(kid)?
+            .ok_or_else(|| Error::new(ErrorKind::Other, "Master-key not found!"))?;
+
+        match &self.sto {
+            None => Err(Error::new(ErrorKind::Other, "There is not subject in the store!")),
+            Some(my) => {
+                let skey = my.subject.active_key().map_err(|e| Error::new(ErrorKind::Other, e))?;
+                let req = MasterKeyReshare::sign(&self.sid, kid, &self.config.peers_hash, threshold, new_peers, &my.secret, skey);
+                req.check(&self.config.peers_hash).map_err(|e| Error::new(ErrorKind::Other, e))?;
+
+                let min = self.config.threshold + 1;
+                let votes = query_quorum(&self.config.peers, &self.query, Request::Negotiate(Negotiate::NMasterKeyReshare(req.clone())), min, None, |res| {
+                    match res {
+                        Response::Vote(Vote::VMasterKeyVote(vote)) => Ok((vote.sig.index, vote)),
+                        _ => Err(Error::new(ErrorKind::Other, "Unexpected response on key resharing!"))
+                    }
+                })?;
+
+                // verify the evidence as the peers will, the votes must reconstruct the same public-key
+                let reshared = MasterKey::reshare(&self.sid, &req.sig.id(), kid, threshold, new_peers, votes, &self.config.peers_keys, self.config.threshold, &public, &my.secret, skey)
+                    .map_err(|e| Error::new(ErrorKind::Other, e))?;
+
+                // process master-key resharing commit
+                commit_failover(&self.candidates(), &self.commit, Commit::Evidence(Evidence::EMasterKeyReshare(reshared)))?;
+                Ok(())
+            }
+        }
+    }
+
+    // votes from all peers (one per peer index), requested concurrently
+    fn query_votes(&self, neg: Negotiate) -> Result<Vec<Vote>> {
+        let n = self.config.peers.len();
+        query_quorum(&self.config.peers, &self.query, Request::Negotiate(neg), n, None, |res| {
+            match res {
+                Response::Vote(vote) => Ok((vote.sig().index, vote)),
+                _ => Err(Error::new(ErrorKind::Other, "Unexpected response on key negotiation!"))
+            }
+        })
+    }
+
+    // Collect and check the votes of all peers for the negotiation, returning the signed evidence and the public-key aggregated from the votes.
+    fn collect_votes(&self, my: &MySubject, neg: Negotiate, session: &str, kid: &str, peers_hash: &[u8]) -> Result<(MasterKey, RistrettoPoint)> {
+        let votes = feldman_votes(self.query_votes(neg)?)?;
+        self.sign_votes(my, votes, session, kid, peers_hash)
+    }
+
+    // all peers must vote under the peer-set of the request
+    fn check_divergent(&self, divergent: &[usize]) -> Result<()> {
+        if !divergent.is_empty() {
+            let hosts: Vec<&str> = divergent.iter().map(|i| self.config.peers[*i].host.as_str()).collect();
+            return Err(Error::new(ErrorKind::Other, format!("Peers with a different peer-set configuration: {:?}", hosts)))
+        }
+
+        Ok(())
+    }
+
+    // First phase of a negotiation with Pedersen's commitments, the votes of all peers are committed before the reveal.
+    fn commit_pedersen(&self, my: &MySubject, votes: Vec<Vote>, session: &str, kid: &str, peers_hash: &[u8]) -> Result<PedersenKey> {
+        let skey = my.subject.active_key().map_err(|e| Error::new(ErrorKind::Other, e))?;
+        let votes = votes.into_iter().map(|vote| match vote {
+            Vote::VPedersenKeyVote(vote) => Ok(vote),
+            _ => Err(Error::new(ErrorKind::Other, "Peers with a different VSS configuration!"))
+        }).collect::<Result<Vec<PedersenKeyVote>>>()?;
+
+        let divergent: Vec<usize> = votes.iter().filter(|vote| vote.peers != peers_hash).map(|vote| vote.sig.index).collect();
+        self.check_divergent(&divergent)?;
+
+        // verify the evidence as the peers will, before it hits the chain
+        let pk = PedersenKey::sign(&self.sid, session, kid, peers_hash, votes, &self.config.peers_keys, self.config.threshold, &my.secret, skey)
+            .map_err(|e| Error::new(ErrorKind::Other, e))?;
+
+        commit_failover(&self.candidates(), &self.commit, Commit::Evidence(Evidence::EPedersenKey(pk.clone())))?;
+        Ok(pk)
+    }
+
+    // Check the Feldman's votes of all peers, returning the signed evidence and the public-key aggregated from the votes.
+    fn sign_votes(&self, my: &MySubject, mut votes: Vec<MasterKeyVote>, session: &str, kid: &str, peers_hash: &[u8]) -> Result<(MasterKey, RistrettoPoint)> {
+        let n = self.config.peers.len();
+        let skey = my.subject.active_key().map_err(|e| Error::new(ErrorKind::Other, e))?;
+        self.check_divergent(&divergent_peers(&votes, peers_hash))?;
+
+        for vote in votes.iter() {
+            // the vote must be bound to the same session and peers-hash signed in the request
+            let peer = &self.config.peers[vote.sig.index];
+            vote.check(session, kid, peers_hash, n, self.config.threshold, &peer.pkey)
+                .map_err(|e| Error::new(ErrorKind::Other, e))?;
+        }
+
+        // set the votes in ordered fashion
+        votes.sort_by_key(|vote| vote.sig.index);
+
+        // If all is OK, create MasterKey to commit
+        let expected = aggregate_public(&votes);
+        let mk = MasterKey::sign(&self.sid, session, kid, peers_hash, votes, &self.config.peers_keys, self.config.threshold, &my.secret, skey)
+            .map_err(|e| Error::new(ErrorKind::Other, e))?;
+
+        Ok((mk, expected))
+    }
+
+    // master-keys negotiated by this subject
+    pub fn keys(&self) -> Result<&[NegotiatedKey]> {
+        match &self.sto {
+            None => Err(Error::new(ErrorKind::Other, "There is not subject in the store!")),
+            Some(my) => Ok(&my.keys)
+        }
+    }
+
+    // consents granted by this subject
+    pub fn authorizations(&self) -> Result<&Authorizations> {
+        match &self.sto {
+            None => Err(Error::new(ErrorKind::Other, "There is not subject in the store!")),
+            Some(my) => Ok(&my.auths)
+        }
+    }
+
+    // committed consents and revokes of this subject (with the purpose), in signing order
+    pub fn consents(&self) -> Result<Vec<Consent>> {
+        self.check_peers(1)?;
+
+        match &self.sto {
+            None => Err(Error::new(ErrorKind::Other, "There is not subject in the store!")),
+            Some(my) => {
+                let skey = my.subject.active_key().map_err(|e| Error::new(ErrorKind::Other, e))?;
+                let req = ConsentsQuery::sign(&self.sid, &my.secret, skey);
+
+                let sel = self.select().ok_or_else(|| Error::new(ErrorKind::Other, "No peer found to send request!"))?;
+                let res = (self.query)(sel, Request::Query(Query::QConsents(req.clone())))?;
+                match res {
+                    Response::QResult(QResult::QConsentsResult(cr)) => {
+                        cr.check(&req, &self.config.peers_keys)
+                            .map_err(|e| Error::new(ErrorKind::Other, e))?;
+
+                        Ok(cr.consents)
+                    },
+                    _ => Err(Error::new(ErrorKind::Other, "Unexpected response on consents query!"))
+                }
+            }
+        }
+    }
+
+    fn check_pending(&self) -> Result<()> {
+        if self.upd.is_some() {
+            return Err(Error::new(ErrorKind::Other, "There is a pending synchronization in the log!"))
+        }
+
+        if self.mrg.is_some() {
+            return Err(Error::new(ErrorKind::Other, "There is a pending synchronization in the log!"))
+        }
+
+        Ok(())
+    }
+
+    // audit entries only have the operation parameters, never the secrets
+    fn audit(&self, operation: &str, res: &Result<()>) -> Result<()> {
+        if !self.config.audit {
+            return Ok(())
+        }
+
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|t| t.as_secs()).unwrap_or(0);
+        let result = match res {
+            Ok(_) => "OK".to_string(),
+            Err(e) => format!("ERROR - {}", e)
+        };
+
+        Storage::audit(&self.home, &self.sid, &format!("{} - (sid = {:?}) {} -> {}", timestamp, self.sid, operation, result))
+    }
+
+    // select the pinned or a random peer
+    fn select(&self) -> Option<&Peer> {
+        match self.pinned {
+            Some(index) => self.config.peers.get(index),
+            None => self.config.peers.choose(&mut rand::thread_rng())
+        }
+    }
+
+    // the pinned peer, or the shuffled peers for the commit failover (the first one and up to max_retries others)
+    fn candidates(&self) -> Vec<&Peer> {
+        match self.pinned {
+            Some(index) => self.config.peers.get(index).into_iter().collect(),
+            None => {
+                let mut peers: Vec<&Peer> = self.config.peers.iter().collect();
+                peers.shuffle(&mut rand::thread_rng());
+                peers.truncate(self.config.max_retries + 1);
+                peers
+            }
+        }
+    }
+
+    // fail before writing to the log, otherwise the update is stuck without peers to submit to
+    fn check_peers(&self, min: usize) -> Result<()> {
+        if self.config.peers.is_empty() {
+            return Err(Error::new(ErrorKind::Other, "No peers configured in the federation!"))
+        }
+
+        if self.config.peers.len() < min {
+            return Err(Error::new(ErrorKind::Other, format!("Not enought peers to process the request, expecting at least {}!", min)))
+        }
+
+        Ok(())
+    }
+
+    // submit an existing update
+    fn submit(&mut self) -> Result<()> {
+        let update = self.upd.as_ref().ok_or_else(|| Error::new(ErrorKind::Other, "No update found to commit!"))?;
+
+        // process sync message
+        commit_failover(&self.candidates(), &self.commit, Commit::Value(update.msg.clone()))?;
+
+        self.merge()
+    }
+
+    // merge a submitted update
+    fn merge(&mut self) -> Result<()> {
+        let update = self.upd.take().ok_or_else(|| Error::new(ErrorKind::Other, "No update found to merge!"))?;
+
+        let merged = match self.sto.take() {
+            None => {
                 if let Value::VSubject(value) = update.msg {
                     MySubject {
                        secret: update.secret,
                        profile_secrets: update.profile_secrets,
                        subject: value,
-                       auths: Authorizations::new()
+                       auths: Authorizations::new(),
+                       keys: Vec::new()
                     }
                 } else {
                     return Err(Error::new(ErrorKind::Other, "There is not subject in the store!"))
@@ -455,6 +1212,15 @@ impl<F: Fn(&Peer, Commit) -> Result<()>, Q: Fn(&Peer, Request) -> Result<Respons
                         my.subject.merge(value);
                     },
 
+                    Value::VProfileConsent(value) => {
+                        my.profile_secrets.extend(update.profile_secrets);
+                        my.subject.merge(value.update);
+                        my.auths.authorize(&value.consent);
+                    },
+
+                    // the recovery is public, only the trustees keep the shares
+                    Value::VRecovery(_) => (),
+
                     _ => unreachable!()
                 }
 
@@ -463,7 +1229,7 @@ impl<F: Fn(&Peer, Commit) -> Result<()>, Q: Fn(&Peer, Request) -> Result<Respons
         };
 
         // write-ahead log
-        Storage::store(&self.home, &update.sid, SType::Merged, &merged)?;
+        Storage::store(&self.home, &update.sid, SType::Merged, &merged, &self.vault)?;
         self.mrg = Some(merged);
         self.upd = None;
 
@@ -474,7 +1240,7 @@ impl<F: Fn(&Peer, Commit) -> Result<()>, Q: Fn(&Peer, Request) -> Result<Respons
     // persistent a submitted and correctly merge update
     fn store(&mut self, sid: &str) -> Result<()> {
         if let Some(merged) = self.mrg.as_ref() {
-            Storage::store(&self.home, &sid, SType::Stored, merged)?;
+            Storage::store(&self.home, &sid, SType::Stored, merged, &self.vault)?;
             self.sto = self.mrg.take();
 
             Storage::clean(&self.home, &sid);
@@ -484,6 +1250,129 @@ impl<F: Fn(&Peer, Commit) -> Result<()>, Q: Fn(&Peer, Request) -> Result<Respons
     }
 }
 
+//-----------------------------------------------------------------------------------------------------------
+// Quorum
+//-----------------------------------------------------------------------------------------------------------
+// Fire the request to all peers concurrently, returning the first min accepted responses (in arrival order).
+// The threads of the remaining peers are detached, a slow or unreachable peer doesn't block the quorum.
+// Responses are accepted once per (signed) peer index, a duplicated or replayed response is ignored and the
+// remaining peers complete the quorum. It only fails when the pool of peers is exhausted.
+fn query_quorum<Q, T, A>(peers: &[Peer], query: &Arc<Q>, req: Request, min: usize, required: Option<&str>, accept: A) -> Result<Vec<T>>
+    where Q: Fn(&Peer, Request) -> Result<Response> + Send + Sync + 'static, A: Fn(Response) -> Result<(usize, T)>
+{
+    let (sender, receiver) = mpsc::channel::<(usize, Result<Response>)>();
+    for (i, peer) in peers.iter().enumerate() {
+        let (sender, query, peer, req) = (sender.clone(), query.clone(), peer.clone(), req.clone());
+        thread::spawn(move || {
+            // the receiver may be gone after the quorum
+            sender.send((i, query(&peer, req))).ok();
+        });
+    }
+
+    drop(sender);
+
+    // the required peer is always the first one of the quorum
+    let mut first: Option<T> = None;
+    let mut accepted = Vec::<T>::with_capacity(min);
+    let mut indexes = HashSet::<usize>::with_capacity(min);
+    let mut last_error: Option<Error> = None;
+    for (i, res) in receiver.iter() {
+        let host = peers[i].host.as_str();
+        let item = res.and_then(&accept).and_then(|(index, item)| match index < peers.len() {
+            true => Ok((index, item)),
+            false => Err(Error::new(ErrorKind::Other, "Unexpected peer index!"))
+        });
+
+        match item {
+            Ok((index, item)) => {
+                // a peer answering twice (or for another peer) doesn't count for the quorum
+                if !indexes.insert(index) {
+                    continue
+                }
+
+                match required == Some(host) {
+                    true => first = Some(item),
+                    false => accepted.push(item)
+                }
+            },
+            Err(e) => {
+                if required == Some(host) {
+                    return Err(Error::new(ErrorKind::Other, format!("Pinned peer {} - {}", host, e)))
+                }
+
+                last_error = Some(Error::new(ErrorKind::Other, format!("Peer {} - {}", host, e)))
+            }
+        }
+
+        let quorum = match required {
+            None => accepted.len() >= min,
+            Some(_) => first.is_some() && accepted.len() + 1 >= min
+        };
+
+        if quorum {
+            if let Some(first) = first.take() {
+                accepted.truncate(min - 1);
+                accepted.insert(0, first);
+            }
+
+            return Ok(accepted)
+        }
+    }
+
+    Err(last_error.unwrap_or_else(|| Error::new(ErrorKind::Other, "Not enough responses from the peers!")))
+}
+
+// votes with Feldman's commitments, negotiations with Pedersen's commitments only accept them after the reveal
+fn feldman_votes(votes: Vec<Vote>) -> Result<Vec<MasterKeyVote>> {
+    votes.into_iter().map(|vote| match vote {
+        Vote::VMasterKeyVote(vote) => Ok(vote),
+        _ => Err(Error::new(ErrorKind::Other, "Unexpected response on key negotiation!"))
+    }).collect()
+}
+
+// Commit to the first candidate, trying the next one only when the peer is unreachable (NotConnected).
+// A rejected transaction is not retried, the other peers would reject it as well (or it's already in the chain).
+fn commit_failover<F>(candidates: &[&Peer], commit: &F, msg: Commit) -> Result<i64>
+    where F: Fn(&Peer, Commit) -> Result<i64>
+{
+    let mut last_error: Option<Error> = None;
+    for peer in candidates.iter() {
+        match commit(peer, msg.clone()) {
+            Err(e) if e.kind() == ErrorKind::NotConnected => {
+                last_error = Some(Error::new(ErrorKind::NotConnected, format!("Peer {} - {}", peer.host, e)))
+            },
+            res => return res
+        }
+    }
+
+    Err(last_error.unwrap_or_else(|| Error::new(ErrorKind::Other, "No peer found to request commit!")))
+}
+
+//-----------------------------------------------------------------------------------------------------------
+// Operation (queued subject operations, these are signed only when processed)
+//-----------------------------------------------------------------------------------------------------------
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum Operation {
+    Evolve,
+    Reanchor,
+    Profile(String, String, bool),              // (typ, lurl, encrypted)
+    ProfileConsent(String, String, bool, String),   // (typ, lurl, encrypted, authorized)
+    Migrate(String, String, String),            // (typ, from, to)
+    Consent(String, Vec<String>),               // (authorized, profiles)
+    Revoke(String, Vec<String>),                // (authorized, profiles)
+    RevokeKey(usize, Option<i64>),              // (subject-key index, revoked as of the timestamp or now)
+    ConsentPurpose(String, Vec<String>, String) // (authorized, profiles, purpose), appended to keep the queued operations decodable
+}
+
+impl Operation {
+    pub fn consent(authorized: &str, profiles: &[String], purpose: Option<&str>) -> Self {
+        match purpose {
+            None => Operation::Consent(authorized.into(), profiles.to_vec()),
+            Some(purpose) => Operation::ConsentPurpose(authorized.into(), profiles.to_vec(), purpose.into())
+        }
+    }
+}
+
 //-----------------------------------------------------------------------------------------------------------
 // Update
 //-----------------------------------------------------------------------------------------------------------
@@ -504,6 +1393,16 @@ pub struct MySubject {
     secret: Scalar,                                                     // current subject-key secret
     profile_secrets: HashMap<String, Scalar>,         // current profile-key secrets <PID, Secret>
     
+    subject: Subject,
+    auths: Authorizations,
+    keys: Vec<NegotiatedKey>                                            // negotiated master-keys (only for the admin)
+}
+
+// MySubject of the previous store format, without the negotiated keys
+#[derive(Serialize, Deserialize)]
+struct LegacySubject {
+    secret: Scalar,
+    profile_secrets: HashMap<String, Scalar>,
     subject: Subject,
     auths: Authorizations
 }
@@ -526,6 +1425,1074 @@ impl Debug for MySubject {
             .field("profile_secrets", &p_secrets)
             .field("subject", &self.subject)
             .field("auths", &self.auths)
+            .field("keys", &self.keys)
+            .finish()
+    }
+}
+
+//-----------------------------------------------------------------------------------------------------------
+// SubjectView (redacted view of MySubject for scripting, never includes the secrets)
+//-----------------------------------------------------------------------------------------------------------
+#[derive(Serialize, Debug)]
+pub struct SubjectView {
+    pub sid: String,
+    pub keys: Vec<KeyView>,
+    pub profiles: Vec<ProfileView>,
+    pub authorizations: BTreeMap<String, Vec<String>>,                  // authorized profiles <target, [typ]>
+    pub master_keys: Vec<MasterKeyView>
+}
+
+#[derive(Serialize, Debug)]
+pub struct KeyView {
+    pub index: usize,
+    pub key: String,
+    pub fingerprint: String
+}
+
+#[derive(Serialize, Debug)]
+pub struct ProfileView {
+    pub typ: String,
+    pub locations: Vec<LocationView>
+}
+
+#[derive(Serialize, Debug)]
+pub struct LocationView {
+    pub lurl: String,
+    pub chain: Vec<ProfileKeyView>
+}
+
+#[derive(Serialize, Debug)]
+pub struct ProfileKeyView {
+    pub index: usize,
+    pub encrypted: bool,
+    pub pkey: String,
+    pub migrated_to: Option<String>
+}
+
+#[derive(Serialize, Debug)]
+pub struct MasterKeyView {
+    pub kid: String,
+    pub public: String,
+    pub fingerprint: String,
+    pub session: String,
+    pub height: i64
+}
+
+impl MySubject {
+    pub fn view(&self) -> SubjectView {
+        let keys = self.subject.keys.iter().map(|skey| KeyView {
+            index: skey.sig.index,
+            key: skey.key.encode(),
+            fingerprint: skey.fingerprint()
+        }).collect();
+
+        let profiles = self.subject.profiles.values().map(|profile| ProfileView {
+            typ: profile.typ.clone(),
+            locations: profile.locations.values().map(|loc| LocationView {
+                lurl: loc.lurl.clone(),
+                chain: loc.chain.iter().map(|pkey| ProfileKeyView {
+                    index: pkey.index,
+                    encrypted: pkey.encrypted,
+                    pkey: pkey.pkey.encode(),
+                    migrated_to: pkey.migrated_to.clone()
+                }).collect()
+            }).collect()
+        }).collect();
+
+        let authorizations = self.auths.iter()
+            .map(|(target, profiles)| (target.clone(), profiles.iter().cloned().collect()))
+            .collect();
+
+        let master_keys = self.keys.iter().map(|key| MasterKeyView {
+            kid: key.kid.clone(),
+            public: key.public.encode(),
+            fingerprint: fingerprint(&key.public),
+            session: key.session.clone(),
+            height: key.height
+        }).collect();
+
+        SubjectView { sid: self.subject.sid.clone(), keys, profiles, authorizations, master_keys }
+    }
+}
+
+//-----------------------------------------------------------------------------------------------------------
+// NegotiatedKey
+//-----------------------------------------------------------------------------------------------------------
+#[derive(Serialize, Deserialize, Clone)]
+pub struct NegotiatedKey {
+    pub kid: String,
+    pub session: String,                                                // signature id of the negotiation request
+    pub public: RistrettoPoint,                                         // derived master public-key
+    pub height: i64                                                     // block height of the committed evidence
+}
+
+impl Debug for NegotiatedKey {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> std::fmt::Result {
+        fmt.debug_struct("NegotiatedKey")
+            .field("kid", &self.kid)
+            .field("session", &self.session)
+            .field("public", &self.public.encode())
+            .field("fingerprint", &fingerprint(&self.public))
+            .field("height", &self.height)
             .finish()
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use log::LevelFilter;
+    use core_fpi::Constraints;
+    use core_fpi::governance::peers_hash;
+    use core_fpi::shares::{pedersen_h, Polynomial, RistrettoPolynomial, Share};
+    use core_fpi::records::RecordStreamResult;
+
+    fn peers(n: usize) -> Vec<Peer> {
+        (0..n).map(|i| Peer { host: format!("peer-{}", i), pkey: rnd_scalar() * G }).collect()
+    }
+
+    fn request() -> Request {
+        let sig_s = rnd_scalar();
+        let (_, skey) = Subject::new("s-id:shumy").evolve(sig_s);
+        Request::Query(Query::QMasterKey(MasterKeyQuery::sign("s-id:shumy", "p-master", &sig_s, &skey)))
+    }
+
+    // peer-1 replays the response of peer-0, the other peers answer with the own index
+    fn replay_query() -> Arc<impl Fn(&Peer, Request) -> Result<Response> + Send + Sync + 'static> {
+        let secret = rnd_scalar();
+        Arc::new(move |peer: &Peer, _: Request| {
+            let index: usize = peer.host["peer-".len()..].parse().unwrap();
+            let index = if index == 1 { 0 } else { index };
+
+            let res = MasterKeyResult::sign("p-master", None, None, &secret, &(secret * G), index);
+            Ok(Response::QResult(QResult::QMasterKeyResult(res)))
+        })
+    }
+
+    fn accept(res: Response) -> Result<(usize, usize)> {
+        match res {
+            Response::QResult(QResult::QMasterKeyResult(res)) => Ok((res.sig.index, res.sig.index)),
+            _ => Err(Error::new(ErrorKind::Other, "Unexpected response!"))
+        }
+    }
+
+    #[test]
+    fn test_quorum_duplicates() {
+        let peers = peers(4);
+        let query = replay_query();
+
+        // the duplicated index is ignored, the remaining peers complete the quorum
+        let mut indexes = query_quorum(&peers, &query, request(), 3, None, accept).unwrap();
+        indexes.sort();
+        assert!(indexes == vec![0, 2, 3]);
+
+        let indexes = query_quorum(&peers, &query, request(), 3, Some("peer-2"), accept).unwrap();
+        assert!(indexes.len() == 3 && indexes[0] == 2);
+
+        // only 3 distinct peers in the pool
+        let res = query_quorum(&peers, &query, request(), 4, None, accept);
+        assert!(res.err().unwrap().to_string() == "Not enough responses from the peers!");
+    }
+
+    #[test]
+    fn test_quorum_errors() {
+        let peers = peers(4);
+        let secret = rnd_scalar();
+        let query = Arc::new(move |peer: &Peer, _: Request| {
+            match peer.host.as_str() {
+                "peer-0" => Err(Error::new(ErrorKind::Other, "Connection refused")),
+                "peer-1" => {
+                    let res = MasterKeyResult::sign("p-master", None, None, &secret, &(secret * G), 7);
+                    Ok(Response::QResult(QResult::QMasterKeyResult(res)))
+                },
+                _ => {
+                    let index: usize = peer.host["peer-".len()..].parse().unwrap();
+                    let res = MasterKeyResult::sign("p-master", None, None, &secret, &(secret * G), index);
+                    Ok(Response::QResult(QResult::QMasterKeyResult(res)))
+                }
+            }
+        });
+
+        let indexes = query_quorum(&peers, &query, request(), 2, None, accept).unwrap();
+        assert!(indexes.len() == 2);
+
+        let res = query_quorum(&peers, &query, request(), 2, Some("peer-0"), accept);
+        assert!(res.err().unwrap().to_string() == "Pinned peer peer-0 - Connection refused");
+
+        let res = query_quorum(&peers, &query, request(), 2, Some("peer-1"), accept);
+        assert!(res.err().unwrap().to_string() == "Pinned peer peer-1 - Unexpected peer index!");
+
+        let res = query_quorum(&peers, &query, request(), 3, None, accept);
+        let err = res.err().unwrap().to_string();
+        assert!(err == "Peer peer-0 - Connection refused" || err == "Peer peer-1 - Unexpected peer index!");
+    }
+
+    #[test]
+    fn test_quorum_stragglers() {
+        let peers = peers(4);
+        let secret = rnd_scalar();
+        let query = Arc::new(move |peer: &Peer, _: Request| {
+            let index: usize = peer.host["peer-".len()..].parse().unwrap();
+            match index {
+                0 => return Err(Error::new(ErrorKind::Other, "Connection refused")),
+                3 => thread::sleep(Duration::from_secs(30)),
+                _ => ()
+            }
+
+            let res = MasterKeyResult::sign("p-master", None, None, &secret, &(secret * G), index);
+            Ok(Response::QResult(QResult::QMasterKeyResult(res)))
+        });
+
+        // the quorum doesn't wait for the slow peer
+        let start = std::time::Instant::now();
+        let mut indexes = query_quorum(&peers, &query, request(), 2, None, accept).unwrap();
+        indexes.sort();
+        assert!(indexes == vec![1, 2]);
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_commit_failover() {
+        let peers = peers(4);
+        let candidates: Vec<&Peer> = peers.iter().collect();
+        let msg = || Commit::Value(Value::VSubject(Subject::new("s-id:shumy")));
+
+        let tries = std::cell::RefCell::new(Vec::<String>::new());
+        let commit = |peer: &Peer, _: Commit| -> Result<i64> {
+            tries.borrow_mut().push(peer.host.clone());
+            match peer.host.as_str() {
+                "peer-0" | "peer-1" => Err(Error::new(ErrorKind::NotConnected, "Unable to commit to network!")),
+                "peer-2" => Ok(10),
+                _ => Err(Error::new(ErrorKind::Other, "Transaction error from network. On check: invalid"))
+            }
+        };
+
+        // unreachable peers are skipped
+        assert!(commit_failover(&candidates, &commit, msg()).unwrap() == 10);
+        assert!(*tries.borrow() == vec!["peer-0", "peer-1", "peer-2"]);
+
+        // rejections are not retried
+        tries.borrow_mut().clear();
+        let res = commit_failover(&[&peers[3], &peers[2]], &commit, msg());
+        assert!(res.err().unwrap().to_string() == "Transaction error from network. On check: invalid");
+        assert!(*tries.borrow() == vec!["peer-3"]);
+
+        // all candidates unreachable (or without retries)
+        let res = commit_failover(&candidates[..2], &commit, msg());
+        assert!(res.err().unwrap().to_string() == "Peer peer-1 - Unable to commit to network!");
+
+        let res = commit_failover(&candidates[..1], &commit, msg());
+        assert!(res.err().unwrap().to_string() == "Peer peer-0 - Unable to commit to network!");
+
+        let res = commit_failover(&[], &commit, msg());
+        assert!(res.err().unwrap().to_string() == "No peer found to request commit!");
+    }
+
+    // evidence of the pseudonym master-key, negotiated with random encryption keys between the peers
+    fn pseudonym_key(secrets: &[Scalar], pkeys: &[RistrettoPoint], peers_hash: &[u8], t: usize) -> MasterKey {
+        let n = pkeys.len();
+        let mut e_keys = vec![vec![Scalar::zero(); n]; n];
+        #[allow(clippy::needless_range_loop)]
+        for i in 0..n {
+            for j in i..n {
+                let e_ij = rnd_scalar();
+                e_keys[i][j] = e_ij;
+                e_keys[j][i] = e_ij;
+            }
+        }
+
+        let votes: Vec<MasterKeyVote> = (0..n).map(|i| {
+            let poly = Polynomial::rnd(rnd_scalar(), t);
+            let p_keys: Vec<RistrettoPoint> = e_keys[i].iter().map(|e_ij| e_ij * G).collect();
+            let e_shares: Vec<Share> = poly.shares(n).0.iter().zip(e_keys[i].iter()).map(|(y_j, e_ij)| y_j + e_ij).collect();
+            MasterKeyVote::sign("session", PSEUDONYM_KEY, peers_hash, e_shares, p_keys, &poly * &G, &secrets[i], &pkeys[i], i)
+        }).collect();
+
+        let sig_s = rnd_scalar();
+        let (_, skey) = Subject::new("s-id:admin").evolve(sig_s);
+        MasterKey::sign("s-id:admin", "session", PSEUDONYM_KEY, peers_hash, votes, pkeys, t, &sig_s, &skey).unwrap()
+    }
+
+    #[test]
+    fn test_push_records() {
+        let home = std::env::temp_dir().join(format!("fedpi-client-{}", core_fpi::uuid()));
+        std::fs::create_dir_all(&home).unwrap();
+        let home = home.to_str().unwrap().to_string();
+
+        let t = 1;
+        let secrets: Vec<Scalar> = (0..4).map(|_| rnd_scalar()).collect();
+        let pkeys: Vec<RistrettoPoint> = secrets.iter().map(|s| s * G).collect();
+        let mk = pseudonym_key(&secrets, &pkeys, &peers_hash(&pkeys), t);
+        let base = mk.public();
+
+        let peers = pkeys.iter().enumerate().map(|(i, pkey)| Peer { host: format!("peer-{}", i), pkey: *pkey }).collect();
+        let config = Config { network: None, log: LevelFilter::Off, audit: false, derived_keys: false, height_tolerance: 0, max_retries: 3,
+            threshold: t, peers, peers_hash: peers_hash(&pkeys), peers_keys: pkeys.clone() };
+
+        let sig_s = rnd_scalar();
+        let mut subject = Subject::new("s-id:shumy");
+        let (secret, skey) = subject.evolve(sig_s);
+        subject.keys.push(skey);
+
+        let mut profile_secrets = HashMap::new();
+        profile_secrets.insert(ProfileLocation::pid("HealthCare", "https://profile-url.org"), rnd_scalar());
+        let my = MySubject { secret, profile_secrets, subject: subject.clone(), auths: Authorizations::new(), keys: Vec::new() };
+
+        // the committed stream (the same for all peers) and the requested ranges
+        let stream = Arc::new(Mutex::new(Vec::<Record>::new()));
+        let ranges = Arc::new(Mutex::new(Vec::<(usize, usize)>::new()));
+
+        let (q_stream, q_ranges) = (stream.clone(), ranges.clone());
+        let query = move |peer: &Peer, req: Request| -> Result<Response> {
+            let index: usize = peer.host["peer-".len()..].parse().unwrap();
+            let res = match req {
+                Request::Query(Query::QMasterKey(req)) => {
+                    // there is no typed key for the profile, the default base is used
+                    let evidence = if req.kid == PSEUDONYM_KEY { Some(mk.clone()) } else { None };
+                    QResult::QMasterKeyResult(MasterKeyResult::sign(&req.kid, evidence, None, &secrets[index], &pkeys[index], index))
+                },
+                Request::Query(Query::QRecordStream(req)) => {
+                    req.authorize(&subject, &pkeys, t).map_err(|e| Error::new(ErrorKind::Other, e))?;
+                    q_ranges.lock().unwrap().push((req.from_index, req.limit));
+
+                    let records = q_stream.lock().unwrap();
+                    let from = match &req.from_prev {
+                        Some(prev) => records.iter().position(|record| record.sig.encoded == *prev).map(|pos| pos + 1).unwrap_or(0),
+                        None if req.from_index == TAIL => records.len().saturating_sub(req.limit),
+                        None => req.from_index.min(records.len())
+                    };
+                    let to = records.len().min(from + req.limit);
+                    QResult::QRecordStream(RecordStreamResult::sign(req.sig.id(), from, records[from..to].to_vec(), &secrets[index], &pkeys[index], index))
+                },
+                _ => return Err(Error::new(ErrorKind::Other, "Unexpected request!"))
+            };
+
+            Ok(Response::QResult(res))
+        };
+
+        let c_stream = stream.clone();
+        let commit = move |_: &Peer, msg: Commit| -> Result<i64> {
+            let mut records = c_stream.lock().unwrap();
+            match msg {
+                Commit::Value(Value::VNewRecord(new)) => {
+                    new.check(records.last(), &[base], false).map_err(|e| Error::new(ErrorKind::Other, e))?;
+                    records.push(new.record);
+                    Ok(records.len() as i64)
+                },
+                _ => Err(Error::new(ErrorKind::Other, "Unexpected commit!"))
+            }
+        };
+
+        let mut sm = SubjectManager::new(&home, "s-id:shumy", "passphrase", config, commit, query).unwrap();
+        sm.sto = Some(my);
+
+        let r_data = || RecordData::inline("JSON", b"{}".to_vec(), Vec::new());
+        let (stream1, height1) = sm.push_record("HealthCare", "https://profile-url.org", r_data()).unwrap();
+        let (stream2, height2) = sm.push_record("HealthCare", "https://profile-url.org", r_data()).unwrap();
+        assert!(stream1 == stream2 && height1 == 1 && height2 == 2);
+
+        // only the tail of the stream is queried to chain the new record
+        assert!(*ranges.lock().unwrap() == vec![(TAIL, 1), (TAIL, 1)]);
+
+        // the complete stream in chain order
+        let (first, records) = sm.profile_records("HealthCare", "https://profile-url.org").unwrap();
+        assert!(first == 0 && records.len() == 2);
+        assert!(records[0].prev == OPEN && records[1].prev == records[0].sig.encoded);
+        assert!(stream_id(&(sm.profile_pseudonym("HealthCare", "https://profile-url.org").unwrap().2)) == stream1);
+
+        // the page after a cursor record
+        let pseudonym = sm.profile_pseudonym("HealthCare", "https://profile-url.org").unwrap().2;
+        let (from, page) = sm.records_after(&pseudonym, Some(base), &records[0].sig.encoded, 10).unwrap();
+        assert!(from == 1 && page.len() == 1 && page[0].sig.encoded == records[1].sig.encoded);
+
+        // other streams are only queried with a disclosure
+        let res = sm.records(&(rnd_scalar() * base), Some(base), 0, 10);
+        assert!(res.err().unwrap().to_string() == "No disclosure found for the stream!");
+
+        // no profile key for the location
+        let res = sm.push_record("HealthCare", "https://other-url.org", r_data());
+        assert!(res.err().unwrap().to_string() == "No profile key found for \"HealthCare@https://other-url.org\"!");
+
+        // the closed stream doesn't accept more records
+        let (stream3, height3) = sm.close_record("HealthCare", "https://profile-url.org").unwrap();
+        assert!(stream3 == stream1 && height3 == 3);
+
+        let (_, records) = sm.profile_records("HealthCare", "https://profile-url.org").unwrap();
+        assert!(records.len() == 3 && records[2].is_closed() && records[2].prev == records[1].sig.encoded);
+
+        let res = sm.push_record("HealthCare", "https://profile-url.org", r_data());
+        assert!(res.err().unwrap().to_string() == "The stream is closed!");
+
+        let res = sm.close_record("HealthCare", "https://profile-url.org");
+        assert!(res.err().unwrap().to_string() == "The stream is closed!");
+
+        drop(sm);
+        std::fs::remove_dir_all(&home).ok();
+    }
+
+    #[test]
+    fn test_queue() {
+        let home = std::env::temp_dir().join(format!("fedpi-client-{}", core_fpi::uuid()));
+        std::fs::create_dir_all(&home).unwrap();
+        let home = home.to_str().unwrap().to_string();
+
+        let config = Config { network: None, log: LevelFilter::Off, audit: false, derived_keys: false, height_tolerance: 0, max_retries: 0,
+            threshold: 0, peers: peers(1), peers_hash: Vec::new(), peers_keys: Vec::new() };
+
+        let mut subject = Subject::new("s-id:shumy");
+        let (secret, skey) = subject.evolve(rnd_scalar());
+        subject.keys.push(skey);
+        let my = MySubject { secret, profile_secrets: HashMap::new(), subject, auths: Authorizations::new(), keys: Vec::new() };
+        Storage::store(&home, "s-id:shumy", SType::Stored, &my, &Vault::new("passphrase")).unwrap();
+
+        // the committed consents (in order), the peer is unreachable after the first commit
+        let committed = Arc::new(Mutex::new(Vec::<Consent>::new()));
+        let offline = Arc::new(Mutex::new(false));
+        let manager = |committed: Arc<Mutex<Vec<Consent>>>, offline: Arc<Mutex<bool>>| {
+            let commit = move |_: &Peer, msg: Commit| -> Result<i64> {
+                if *offline.lock().unwrap() {
+                    return Err(Error::new(ErrorKind::NotConnected, "Unable to commit to network!"))
+                }
+
+                let mut committed = committed.lock().unwrap();
+                match msg {
+                    Commit::Value(Value::VConsent(consent)) => committed.push(consent),
+                    _ => return Err(Error::new(ErrorKind::Other, "Unexpected commit!"))
+                }
+
+                *offline.lock().unwrap() = committed.len() == 1;
+                Ok(committed.len() as i64)
+            };
+
+            let query = |_: &Peer, _: Request| -> Result<Response> { Err(Error::new(ErrorKind::Other, "Unexpected query!")) };
+            SubjectManager::new(&home, "s-id:shumy", "passphrase", config.clone(), commit, query).unwrap()
+        };
+
+        let profiles = vec!["HealthCare".to_string()];
+        let mut sm = manager(committed.clone(), offline.clone());
+        sm.enqueue(Operation::consent("s-id:target-1", &profiles, None)).unwrap();
+        sm.enqueue(Operation::consent("s-id:target-2", &profiles, None)).unwrap();
+        sm.enqueue(Operation::Revoke("s-id:target-1".into(), profiles.clone())).unwrap();
+
+        // the second operation fails, its update stays in the log and the third one in the queue
+        let res = sm.flush();
+        assert!(res.err().unwrap().to_string() == "Peer peer-0 - Unable to commit to network!");
+        assert!(committed.lock().unwrap().len() == 1);
+        assert!(sm.upd.is_some() && sm.queue.len() == 1);
+        assert!(read(&select(&home, "s-id:shumy", SType::Updating)).is_some());
+
+        // new commands are rejected with the pending update, but still accepted in the queue
+        let res = sm.consent("s-id:target-3", &profiles, None);
+        assert!(res.err().unwrap().to_string() == "There is a pending synchronization in the log!");
+        sm.enqueue(Operation::consent("s-id:target-3", &profiles, None)).unwrap();
+        drop(sm);
+
+        // recovered from the files, the pending update is submitted before the queue
+        *offline.lock().unwrap() = false;
+        let mut sm = manager(committed.clone(), offline.clone());
+        assert!(sm.upd.is_some() && sm.queue.len() == 2);
+        sm.flush().unwrap();
+
+        let targets: Vec<(String, bool)> = committed.lock().unwrap().iter().map(|consent| (consent.target.clone(), matches!(consent.typ, ConsentType::Revoke))).collect();
+        assert!(targets == vec![
+            ("s-id:target-1".into(), false),
+            ("s-id:target-2".into(), false),
+            ("s-id:target-1".into(), true),
+            ("s-id:target-3".into(), false)
+        ]);
+
+        // each operation was merged and stored before the next one
+        let auths = &sm.sto.as_ref().unwrap().auths;
+        assert!(!auths.is_authorized("s-id:target-1", "HealthCare"));
+        assert!(auths.is_authorized("s-id:target-2", "HealthCare") && auths.is_authorized("s-id:target-3", "HealthCare"));
+        assert!(sm.upd.is_none() && sm.mrg.is_none() && sm.queue.is_empty());
+        assert!(read(&select(&home, "s-id:shumy", SType::Queued)).is_none());
+
+        drop(sm);
+        std::fs::remove_dir_all(&home).ok();
+    }
+
+    #[test]
+    fn test_empty_federation() {
+        let home = std::env::temp_dir().join(format!("fedpi-client-{}", core_fpi::uuid()));
+        std::fs::create_dir_all(&home).unwrap();
+        let home = home.to_str().unwrap().to_string();
+
+        let config = |threshold: usize, peers: Vec<Peer>| Config { network: None, log: LevelFilter::Off, audit: false, derived_keys: false, height_tolerance: 0, max_retries: 3,
+            threshold, peers, peers_hash: Vec::new(), peers_keys: Vec::new() };
+        let commit = |_: &Peer, _: Commit| -> Result<i64> { Err(Error::new(ErrorKind::Other, "Unexpected commit!")) };
+        let query = |_: &Peer, _: Request| -> Result<Response> { Err(Error::new(ErrorKind::Other, "Unexpected query!")) };
+        let no_peers = "No peers configured in the federation!";
+
+        // without a subject
+        let mut sm = SubjectManager::new(&home, "s-id:shumy", "passphrase", config(0, Vec::new()), commit, query).unwrap();
+        assert!(sm.create().err().unwrap().to_string() == no_peers);
+        assert!(sm.upd.is_none() && read(&select(&home, "s-id:shumy", SType::Updating)).is_none());
+
+        // with a subject, all mutating operations fail before the log
+        let mut subject = Subject::new("s-id:shumy");
+        let (secret, skey) = subject.evolve(rnd_scalar());
+        subject.keys.push(skey);
+        sm.sto = Some(MySubject { secret, profile_secrets: HashMap::new(), subject, auths: Authorizations::new(), keys: Vec::new() });
+
+        let profiles = vec!["HealthCare".to_string()];
+        assert!(sm.evolve().err().unwrap().to_string() == no_peers);
+        assert!(sm.profile("HealthCare", "https://profile-url.org", false).err().unwrap().to_string() == no_peers);
+        assert!(sm.consent("s-id:target", &profiles, None).err().unwrap().to_string() == no_peers);
+        assert!(sm.disclose("s-id:target", &profiles, false, false).err().unwrap().to_string() == no_peers);
+        assert!(sm.negotiate(PSEUDONYM_KEY).err().unwrap().to_string() == no_peers);
+
+        sm.enqueue(Operation::Evolve).unwrap();
+        assert!(sm.flush().err().unwrap().to_string() == no_peers);
+        assert!(sm.queue.len() == 1);
+
+        assert!(sm.upd.is_none() && read(&select(&home, "s-id:shumy", SType::Updating)).is_none());
+
+        // the negotiation requires the votes of a complete federation (#peers >= 3 * t + 1), not only a quorum
+        sm.config = config(1, peers(3));
+        let res = sm.negotiate(PSEUDONYM_KEY);
+        assert!(res.err().unwrap().to_string() == "Not enought peers to process the request, expecting at least 4!");
+        let res = sm.refresh(PSEUDONYM_KEY);
+        assert!(res.err().unwrap().to_string() == "Not enought peers to process the request, expecting at least 4!");
+
+        drop(sm);
+        std::fs::remove_dir_all(&home).ok();
+    }
+
+    #[test]
+    fn test_audit() {
+        let home = std::env::temp_dir().join(format!("fedpi-client-{}", core_fpi::uuid()));
+        std::fs::create_dir_all(&home).unwrap();
+        let home = home.to_str().unwrap().to_string();
+
+        let config = Config { network: None, log: LevelFilter::Off, audit: true, derived_keys: false, height_tolerance: 0, max_retries: 3,
+            threshold: 0, peers: peers(1), peers_hash: Vec::new(), peers_keys: Vec::new() };
+        let commit = |_: &Peer, msg: Commit| -> Result<i64> {
+            match msg {
+                Commit::Value(Value::VSubject(_)) => Ok(1),
+                _ => Err(Error::new(ErrorKind::Other, "Unexpected commit!"))
+            }
+        };
+        let query = |_: &Peer, _: Request| -> Result<Response> { Err(Error::new(ErrorKind::Other, "Unexpected query!")) };
+        let mut sm = SubjectManager::new(&home, "s-id:shumy", "passphrase", config, commit, query).unwrap();
+
+        let mut subject = Subject::new("s-id:shumy");
+        let (secret, skey) = subject.evolve(rnd_scalar());
+        subject.keys.push(skey);
+        sm.sto = Some(MySubject { secret, profile_secrets: HashMap::new(), subject, auths: Authorizations::new(), keys: Vec::new() });
+
+        sm.profile("HealthCare", "https://profile-url.org", false).unwrap();
+        let res = sm.consent("s-id:target", &["HealthCare".to_string()], None);
+        assert!(res.err().unwrap().to_string() == "Unexpected commit!");
+
+        // one entry per operation, with the parameters and the result
+        let audit = String::from_utf8(read(&select(&home, "s-id:shumy", SType::Audited)).unwrap()).unwrap();
+        let lines: Vec<&str> = audit.lines().collect();
+        assert!(lines.len() == 2);
+        assert!(lines[0].ends_with("- (sid = \"s-id:shumy\") Profile(\"HealthCare\", \"https://profile-url.org\", false) -> OK"));
+        assert!(lines[1].ends_with("- (sid = \"s-id:shumy\") Consent(\"s-id:target\", [\"HealthCare\"]) -> ERROR - Unexpected commit!"));
+
+        // the secrets are never included
+        let my = sm.sto.as_ref().unwrap();
+        let profile_secret = my.profile_secrets.get(&ProfileLocation::pid("HealthCare", "https://profile-url.org")).unwrap();
+        assert!(!audit.contains(&profile_secret.encode()) && !audit.contains(&my.secret.encode()));
+
+        drop(sm);
+        std::fs::remove_dir_all(&home).ok();
+    }
+
+    #[test]
+    fn test_pinned_peer() {
+        let home = std::env::temp_dir().join(format!("fedpi-client-{}", core_fpi::uuid()));
+        std::fs::create_dir_all(&home).unwrap();
+        let home = home.to_str().unwrap().to_string();
+
+        let secrets: Vec<Scalar> = (0..4).map(|_| rnd_scalar()).collect();
+        let pkeys: Vec<RistrettoPoint> = secrets.iter().map(|s| s * G).collect();
+        let peers = pkeys.iter().enumerate().map(|(i, pkey)| Peer { host: format!("http://peer-{}:26657", i), pkey: *pkey }).collect();
+        let config = Config { network: None, log: LevelFilter::Off, audit: false, derived_keys: false, height_tolerance: 0, max_retries: 3,
+            threshold: 1, peers, peers_hash: peers_hash(&pkeys), peers_keys: pkeys.clone() };
+
+        // the peers called by the transport, peer-2 is unreachable for commits
+        let called = Arc::new(Mutex::new(Vec::<String>::new()));
+        let (q_called, c_called) = (called.clone(), called.clone());
+        let query = move |peer: &Peer, req: Request| -> Result<Response> {
+            q_called.lock().unwrap().push(peer.host.clone());
+            let index: usize = peer.host["http://peer-".len()..][..1].parse().unwrap();
+            match req {
+                Request::Query(Query::QConsents(req)) => Ok(Response::QResult(QResult::QConsentsResult(ConsentsResult::sign(req.sig.id(), Vec::new(), &secrets[index], &pkeys[index], index)))),
+                _ => Err(Error::new(ErrorKind::Other, "Unexpected request!"))
+            }
+        };
+        let commit = move |peer: &Peer, _: Commit| -> Result<i64> {
+            c_called.lock().unwrap().push(peer.host.clone());
+            match peer.host.as_str() {
+                "http://peer-2:26657" => Err(Error::new(ErrorKind::NotConnected, "Unable to commit to network!")),
+                _ => Ok(1)
+            }
+        };
+
+        let mut sm = SubjectManager::new(&home, "s-id:shumy", "passphrase", config, commit, query).unwrap();
+        let mut subject = Subject::new("s-id:shumy");
+        let (secret, skey) = subject.evolve(rnd_scalar());
+        subject.keys.push(skey);
+        sm.sto = Some(MySubject { secret, profile_secrets: HashMap::new(), subject, auths: Authorizations::new(), keys: Vec::new() });
+
+        // selected by index or by host
+        assert!(sm.pin("4").err().unwrap().to_string() == "No peer found at index 4!");
+        assert!(sm.pin("http://other:26657").err().unwrap().to_string() == "No peer found for host \"http://other:26657\"!");
+        sm.pin("http://peer-1:26657/").unwrap();
+        assert!(sm.pinned == Some(1));
+        sm.pin("3").unwrap();
+        assert!(sm.pinned == Some(3));
+
+        // single-peer queries and commits only call the pinned peer
+        let profiles = vec!["HealthCare".to_string()];
+        for _ in 0..5 {
+            sm.consents().unwrap();
+            sm.consent("s-id:target", &profiles, None).unwrap();
+        }
+
+        assert!(called.lock().unwrap().iter().all(|host| host == "http://peer-3:26657"));
+        assert!(called.lock().unwrap().len() == 10);
+
+        // there is no failover from the pinned peer
+        called.lock().unwrap().clear();
+        sm.pin("2").unwrap();
+        let res = sm.consent("s-id:target", &profiles, None);
+        assert!(res.err().unwrap().to_string() == "Peer http://peer-2:26657 - Unable to commit to network!");
+        assert!(*called.lock().unwrap() == vec!["http://peer-2:26657"]);
+
+        drop(sm);
+        std::fs::remove_dir_all(&home).ok();
+    }
+
+    #[test]
+    fn test_revoke_key() {
+        let home = std::env::temp_dir().join(format!("fedpi-client-{}", core_fpi::uuid()));
+        std::fs::create_dir_all(&home).unwrap();
+        let home = home.to_str().unwrap().to_string();
+
+        let config = Config { network: None, log: LevelFilter::Off, audit: false, derived_keys: false, height_tolerance: 0, max_retries: 3,
+            threshold: 0, peers: Vec::new(), peers_hash: Vec::new(), peers_keys: Vec::new() };
+        let commit = |_: &Peer, _: Commit| -> Result<i64> { Err(Error::new(ErrorKind::Other, "Unexpected commit!")) };
+        let query = |_: &Peer, _: Request| -> Result<Response> { Err(Error::new(ErrorKind::Other, "Unexpected query!")) };
+        let mut sm = SubjectManager::new(&home, "s-id:shumy", "passphrase", config, commit, query).unwrap();
+
+        let mut subject = Subject::new("s-id:shumy");
+        let (secret, skey) = subject.evolve(rnd_scalar());
+        subject.keys.push(skey);
+        sm.sto = Some(MySubject { secret, profile_secrets: HashMap::new(), subject: subject.clone(), auths: Authorizations::new(), keys: Vec::new() });
+
+        // the active key is replaced, and revoked by the new key
+        sm.prepare(&Operation::RevokeKey(0, None)).unwrap();
+        match &sm.upd.as_ref().unwrap().msg {
+            Value::VSubject(update) => {
+                assert!(update.keys.len() == 1 && update.revocations.len() == 1 && update.revocations[0].index == 0);
+                assert!(update.verify(&subject, Duration::from_secs(5)) == Ok(()));
+                assert!(update.check(&Some(subject.clone())) == Ok(()));
+            },
+            _ => panic!("Unexpected update!")
+        }
+
+        // the evolution is signed by the revoked key
+        let res = sm.prepare(&Operation::RevokeKey(0, Some(0)));
+        assert!(res.err().unwrap().to_string() == "The active subject-key can only be revoked after the evolution that replaces it!");
+
+        drop(sm);
+        std::fs::remove_dir_all(&home).ok();
+    }
+
+    #[test]
+    fn test_consents() {
+        let home = std::env::temp_dir().join(format!("fedpi-client-{}", core_fpi::uuid()));
+        std::fs::create_dir_all(&home).unwrap();
+        let home = home.to_str().unwrap().to_string();
+
+        let secrets: Vec<Scalar> = (0..4).map(|_| rnd_scalar()).collect();
+        let pkeys: Vec<RistrettoPoint> = secrets.iter().map(|s| s * G).collect();
+        let peers = pkeys.iter().enumerate().map(|(i, pkey)| Peer { host: format!("peer-{}", i), pkey: *pkey }).collect();
+        let config = Config { network: None, log: LevelFilter::Off, audit: false, derived_keys: false, height_tolerance: 0, max_retries: 3,
+            threshold: 1, peers, peers_hash: peers_hash(&pkeys), peers_keys: pkeys.clone() };
+
+        let sig_s = rnd_scalar();
+        let mut subject = Subject::new("s-id:shumy");
+        let (secret, skey) = subject.evolve(sig_s);
+        subject.keys.push(skey.clone());
+        let my = MySubject { secret, profile_secrets: HashMap::new(), subject: subject.clone(), auths: Authorizations::new(), keys: Vec::new() };
+
+        // the committed consent, peer-3 answers with the consents of another subject
+        let profiles = vec!["HealthCare".to_string()];
+        let consent = Consent::sign_with_purpose("s-id:shumy", ConsentType::Consent, "s-id:target", &profiles, Some("treatment"), &sig_s, &skey);
+        let other = Consent::sign("s-id:other", ConsentType::Consent, "s-id:target", &profiles, &sig_s, &skey);
+        let query = move |peer: &Peer, req: Request| -> Result<Response> {
+            let index: usize = peer.host["peer-".len()..].parse().unwrap();
+            match req {
+                Request::Query(Query::QConsents(req)) => {
+                    req.verify(&subject, Duration::from_secs(5)).map_err(|e| Error::new(ErrorKind::Other, e))?;
+                    let consents = if index == 3 { vec![other.clone()] } else { vec![consent.clone()] };
+                    Ok(Response::QResult(QResult::QConsentsResult(ConsentsResult::sign(req.sig.id(), consents, &secrets[index], &pkeys[index], index))))
+                },
+                _ => Err(Error::new(ErrorKind::Other, "Unexpected request!"))
+            }
+        };
+
+        let commit = |_: &Peer, _: Commit| -> Result<i64> { Err(Error::new(ErrorKind::Other, "Unexpected commit!")) };
+        let mut sm = SubjectManager::new(&home, "s-id:shumy", "passphrase", config, commit, query).unwrap();
+        sm.sto = Some(my);
+
+        sm.pinned = Some(0);
+        let consents = sm.consents().unwrap();
+        assert!(consents.len() == 1 && consents[0].purpose == Some("treatment".into()));
+
+        sm.pinned = Some(3);
+        let res = sm.consents();
+        assert!(res.err().unwrap().to_string() == "Field Constraint - (consents, Expected the same subject)");
+        sm.pinned = None;
+
+        // the purpose selects the appended operation, consents without purpose keep the previous encoding
+        assert!(matches!(Operation::consent("s-id:target", &profiles, None), Operation::Consent(..)));
+        assert!(matches!(Operation::consent("s-id:target", &profiles, Some("treatment")), Operation::ConsentPurpose(..)));
+        sm.prepare(&Operation::consent("s-id:target", &profiles, Some("treatment"))).unwrap();
+        match &sm.upd.as_ref().unwrap().msg {
+            Value::VConsent(consent) => assert!(consent.purpose == Some("treatment".into())),
+            _ => panic!("Unexpected update!")
+        }
+
+        drop(sm);
+        std::fs::remove_dir_all(&home).ok();
+    }
+
+    #[test]
+    fn test_restore() {
+        let home = std::env::temp_dir().join(format!("fedpi-client-{}", core_fpi::uuid()));
+        std::fs::create_dir_all(&home).unwrap();
+        let home = home.to_str().unwrap().to_string();
+
+        let secrets: Vec<Scalar> = (0..4).map(|_| rnd_scalar()).collect();
+        let pkeys: Vec<RistrettoPoint> = secrets.iter().map(|s| s * G).collect();
+        let peers = pkeys.iter().enumerate().map(|(i, pkey)| Peer { host: format!("peer-{}", i), pkey: *pkey }).collect();
+        let config = Config { network: None, log: LevelFilter::Off, audit: false, derived_keys: false, height_tolerance: 0, max_retries: 3,
+            threshold: 1, peers, peers_hash: peers_hash(&pkeys), peers_keys: pkeys.clone() };
+
+        // the committed subject, with a derived and a random profile secret
+        let mut subject = Subject::new("s-id:shumy");
+        let (secret, skey) = subject.evolve(rnd_scalar());
+        subject.keys.push(skey.clone());
+
+        let mut derived = Profile::new("HealthCare");
+        derived.push(derived.derive("s-id:shumy", "https://a.org", false, &secret, &skey).1);
+        let mut evolved = Profile::new("Finance");
+        evolved.push(evolved.evolve("s-id:shumy", "https://b.org", false, &secret, &skey).1);
+        subject.push(derived).push(evolved);
+
+        let trustees = vec!["s-id:trustee-0".to_string(), "s-id:trustee-1".to_string(), "s-id:trustee-2".to_string()];
+        let (_, shares) = Recovery::setup("s-id:shumy", &trustees, 2, &secret, &skey).unwrap();
+        let shares: Vec<String> = shares.iter().map(|item| item.encode()).collect();
+
+        let profiles = vec!["HealthCare".to_string()];
+        let consent = Consent::sign("s-id:shumy", ConsentType::Consent, "s-id:target", &profiles, &secret, &skey);
+        let query = move |peer: &Peer, req: Request| -> Result<Response> {
+            let index: usize = peer.host["peer-".len()..].parse().unwrap();
+            match req {
+                Request::Query(Query::QSubject(req)) => {
+                    req.verify(&subject, Duration::from_secs(5)).map_err(|e| Error::new(ErrorKind::Other, e))?;
+                    Ok(Response::QResult(QResult::QSubjectResult(SubjectResult::sign(req.sig.id(), subject.clone(), &secrets[index], &pkeys[index], index))))
+                },
+                Request::Query(Query::QConsents(req)) => {
+                    req.verify(&subject, Duration::from_secs(5)).map_err(|e| Error::new(ErrorKind::Other, e))?;
+                    Ok(Response::QResult(QResult::QConsentsResult(ConsentsResult::sign(req.sig.id(), vec![consent.clone()], &secrets[index], &pkeys[index], index))))
+                },
+                _ => Err(Error::new(ErrorKind::Other, "Unexpected request!"))
+            }
+        };
+
+        let commit = |_: &Peer, _: Commit| -> Result<i64> { Err(Error::new(ErrorKind::Other, "Unexpected commit!")) };
+        let mut sm = SubjectManager::new(&home, "s-id:shumy", "passphrase", config.clone(), commit, query).unwrap();
+
+        assert!(sm.restore(&shares[..1]).unwrap_err().to_string() == "Not enough or invalid recovery shares!");
+        assert!(sm.sto.is_none());
+
+        // only the random profile secret is lost
+        let lost = sm.restore(&shares[1..]).unwrap();
+        assert!(lost == vec![ProfileLocation::pid("Finance", "https://b.org")]);
+
+        let my = sm.sto.as_ref().unwrap();
+        assert!(my.secret == secret && my.subject.active_key().unwrap().key == skey.key);
+        assert!(my.profile_secrets.len() == 1 && my.profile_secrets.contains_key(&ProfileLocation::pid("HealthCare", "https://a.org")));
+        assert!(my.auths.authorized_profiles("s-id:target").is_some());
+        assert!(sm.restore(&shares).unwrap_err().to_string() == "You already have a subject in the store!");
+
+        // the restored store is persisted
+        let commit = |_: &Peer, _: Commit| -> Result<i64> { Err(Error::new(ErrorKind::Other, "Unexpected commit!")) };
+        let query = |_: &Peer, _: Request| -> Result<Response> { Err(Error::new(ErrorKind::Other, "Unexpected query!")) };
+        let loaded = SubjectManager::new(&home, "s-id:shumy", "passphrase", config, commit, query).unwrap();
+        assert!(loaded.sto.as_ref().unwrap().secret == secret);
+
+        drop(sm);
+        drop(loaded);
+        std::fs::remove_dir_all(&home).ok();
+    }
+
+    #[test]
+    fn test_plaintext_migration() {
+        let home = std::env::temp_dir().join(format!("fedpi-client-{}", core_fpi::uuid()));
+        std::fs::create_dir_all(&home).unwrap();
+        let home = home.to_str().unwrap().to_string();
+
+        // a store written before the vault
+        let mut subject = Subject::new("s-id:shumy");
+        let (secret, skey) = subject.evolve(rnd_scalar());
+        subject.keys.push(skey);
+        let my = MySubject { secret, profile_secrets: HashMap::new(), subject, auths: Authorizations::new(), keys: Vec::new() };
+
+        let file = select(&home, "s-id:shumy", SType::Stored);
+        write(&file, serialize(&my).unwrap()).unwrap();
+
+        let vault = Vault::new("passphrase");
+        let (_, _, sto, _) = Storage::load(&home, "s-id:shumy", &vault).unwrap();
+        assert!(sto.unwrap().subject.sid == "s-id:shumy");
+
+        // sealed in place, and loaded again from the sealed file
+        assert!(Vault::is_sealed(&read(&file).unwrap()));
+        let (_, _, sto, _) = Storage::load(&home, "s-id:shumy", &vault).unwrap();
+        assert!(sto.unwrap().secret == secret);
+
+        let res = Storage::load(&home, "s-id:shumy", &Vault::new("wrong"));
+        assert!(res.err().unwrap().to_string() == "Unable to decrypt the store, incorrect passphrase?");
+
+        std::fs::remove_dir_all(&home).ok();
+    }
+
+    #[test]
+    fn test_legacy_subject() {
+        let home = std::env::temp_dir().join(format!("fedpi-client-{}", core_fpi::uuid()));
+        std::fs::create_dir_all(&home).unwrap();
+        let home = home.to_str().unwrap().to_string();
+
+        // a store written before the negotiated keys
+        let mut subject = Subject::new("s-id:shumy");
+        let (secret, skey) = subject.evolve(rnd_scalar());
+        subject.keys.push(skey);
+        let legacy = LegacySubject { secret, profile_secrets: HashMap::new(), subject, auths: Authorizations::new() };
+
+        let vault = Vault::new("passphrase");
+        let file = select(&home, "s-id:shumy", SType::Stored);
+        write(&file, vault.seal(&serialize(&legacy).unwrap()).unwrap()).unwrap();
+
+        let (_, _, sto, _) = Storage::load(&home, "s-id:shumy", &vault).unwrap();
+        let sto = sto.unwrap();
+        assert!(sto.secret == secret && sto.subject.sid == "s-id:shumy" && sto.keys.is_empty());
+
+        // an undecodable file is reported, not dropped
+        write(&file, vault.seal(b"corrupted").unwrap()).unwrap();
+        let res = Storage::load(&home, "s-id:shumy", &vault);
+        assert!(res.err().unwrap().to_string().starts_with(&format!("Unable to decode {:?}", file)));
+
+        let que = select(&home, "s-id:shumy", SType::Queued);
+        std::fs::remove_file(&file).unwrap();
+        write(&que, b"corrupted".to_vec()).unwrap();
+        let res = Storage::load(&home, "s-id:shumy", &vault);
+        assert!(res.err().unwrap().to_string().starts_with(&format!("Unable to decode {:?}", que)));
+
+        std::fs::remove_dir_all(&home).ok();
+    }
+
+    #[test]
+    fn test_view_redacted() {
+        let sig_s = rnd_scalar();
+        let mut subject = Subject::new("s-id:shumy");
+        let (secret, skey) = subject.evolve(sig_s);
+        subject.keys.push(skey.clone());
+
+        let mut profile_secrets = HashMap::new();
+        profile_secrets.insert("HealthCare-https://profile-url.org-0".to_string(), rnd_scalar());
+
+        let my = MySubject { secret, profile_secrets: profile_secrets.clone(), subject, auths: Authorizations::new(), keys: Vec::new() };
+        let json = serde_json::to_string(&my.view()).unwrap();
+
+        assert!(json.contains("\"sid\":\"s-id:shumy\""));
+        assert!(json.contains(&skey.key.encode()));
+        assert!(json.contains(&skey.fingerprint()));
+
+        // secrets are never in the view
+        assert!(!json.contains(&secret.encode()));
+        for secret in profile_secrets.values() {
+            assert!(!json.contains(&secret.encode()));
+        }
+    }
+
+    #[test]
+    fn test_negotiate_pedersen() {
+        let home = std::env::temp_dir().join(format!("fedpi-client-{}", core_fpi::uuid()));
+        std::fs::create_dir_all(&home).unwrap();
+        let home = home.to_str().unwrap().to_string();
+
+        let (n, t) = (4, 1);
+        let secrets: Vec<Scalar> = (0..n).map(|_| rnd_scalar()).collect();
+        let pkeys: Vec<RistrettoPoint> = secrets.iter().map(|s| s * G).collect();
+
+        // symmetric matrices of encryption and blinding keys between peers
+        let mut e_keys = vec![vec![Scalar::zero(); n]; n];
+        let mut f_keys = vec![vec![Scalar::zero(); n]; n];
+        #[allow(clippy::needless_range_loop)]
+        for i in 0..n {
+            for j in i..n {
+                let (e_ij, f_ij) = (rnd_scalar(), rnd_scalar());
+                e_keys[i][j] = e_ij; e_keys[j][i] = e_ij;
+                f_keys[i][j] = f_ij; f_keys[j][i] = f_ij;
+            }
+        }
+
+        let polys: Vec<(Polynomial, Polynomial)> = (0..n).map(|_| (Polynomial::rnd(rnd_scalar(), t), Polynomial::rnd(rnd_scalar(), t))).collect();
+        let y = polys.iter().fold(Scalar::zero(), |total, (ak, _)| total + ak.coefficients()[0]);
+
+        let committed = Arc::new(Mutex::new(Vec::<Commit>::new()));
+        let q_committed = committed.clone();
+        let q_pkeys = pkeys.clone();
+        let query = move |peer: &Peer, req: Request| -> Result<Response> {
+            let i: usize = peer.host["peer-".len()..].parse().unwrap();
+            let (ak, bk) = &polys[i];
+            let p_keys: Vec<RistrettoPoint> = e_keys[i].iter().map(|e_ij| e_ij * G).collect();
+            let e_shares: Vec<Share> = ak.shares(n).0.iter().zip(e_keys[i].iter()).map(|(y_j, e_ij)| y_j + e_ij).collect();
+
+            let vote = match req {
+                Request::Negotiate(Negotiate::NMasterKeyRequest(req)) => {
+                    let b_keys: Vec<RistrettoPoint> = f_keys[i].iter().map(|f_ij| f_ij * pedersen_h()).collect();
+                    let b_shares: Vec<Share> = bk.shares(n).0.iter().zip(f_keys[i].iter()).map(|(z_j, f_ij)| z_j + f_ij).collect();
+                    let commit = RistrettoPolynomial::pedersen(ak, bk).unwrap();
+                    Vote::VPedersenKeyVote(PedersenKeyVote::sign(req.sig.id(), &req.kid, &req.peers, e_shares, p_keys, commit, b_shares, b_keys, &secrets[i], &q_pkeys[i], i))
+                },
+                Request::Negotiate(Negotiate::NMasterKeyReveal(req)) => {
+                    // only revealed after the commit of the Pedersen's votes
+                    let pk = match q_committed.lock().unwrap().last() {
+                        Some(Commit::Evidence(Evidence::EPedersenKey(pk))) => pk.clone(),
+                        _ => return Err(Error::new(ErrorKind::Other, "Pedersen's evidence not found!"))
+                    };
+
+                    Vote::VMasterKeyVote(MasterKeyVote::sign(&req.session, &req.kid, &pk.votes[i].peers, e_shares, p_keys, ak * &G, &secrets[i], &q_pkeys[i], i))
+                },
+                _ => return Err(Error::new(ErrorKind::Other, "Unexpected request!"))
+            };
+
+            Ok(Response::Vote(vote))
+        };
+
+        let c_committed = committed.clone();
+        let commit = move |_: &Peer, msg: Commit| -> Result<i64> {
+            let mut committed = c_committed.lock().unwrap();
+            committed.push(msg);
+            Ok(committed.len() as i64)
+        };
+
+        let peers = pkeys.iter().enumerate().map(|(i, pkey)| Peer { host: format!("peer-{}", i), pkey: *pkey }).collect();
+        let config = Config { network: None, log: LevelFilter::Off, audit: false, derived_keys: false, height_tolerance: 0, max_retries: 3,
+            threshold: t, peers, peers_hash: peers_hash(&pkeys), peers_keys: pkeys.clone() };
+        let mut sm = SubjectManager::new(&home, "s-id:shumy", "passphrase", config, commit, query).unwrap();
+
+        let mut subject = Subject::new("s-id:shumy");
+        let (secret, skey) = subject.evolve(rnd_scalar());
+        subject.keys.push(skey);
+        sm.sto = Some(MySubject { secret, profile_secrets: HashMap::new(), subject, auths: Authorizations::new(), keys: Vec::new() });
+
+        sm.negotiate("kid").unwrap();
+
+        // the Pedersen's votes are committed before the MasterKey of the revealed votes
+        let committed = committed.lock().unwrap();
+        let (pk, mk) = match &committed[..] {
+            [Commit::Evidence(Evidence::EPedersenKey(pk)), Commit::Evidence(Evidence::EMasterKey(mk))] => (pk, mk),
+            _ => panic!("Expecting the Pedersen's votes before the MasterKey!")
+        };
+
+        assert!(pk.check_reveal(mk) == Ok(()));
+        assert!(mk.public() == y * G);
+
+        let keys = sm.keys().unwrap();
+        assert!(keys.len() == 1 && keys[0].public == y * G && keys[0].session == pk.session && keys[0].height == 2);
+
+        drop(sm);
+        std::fs::remove_dir_all(&home).ok();
+    }
+
+    #[test]
+    fn test_refresh() {
+        let home = std::env::temp_dir().join(format!("fedpi-client-{}", core_fpi::uuid()));
+        std::fs::create_dir_all(&home).unwrap();
+        let home = home.to_str().unwrap().to_string();
+
+        let (n, t) = (4, 1);
+        let secrets: Vec<Scalar> = (0..n).map(|_| rnd_scalar()).collect();
+        let pkeys: Vec<RistrettoPoint> = secrets.iter().map(|s| s * G).collect();
+
+        // symmetric matrix of encryption keys between peers
+        let mut e_keys = vec![vec![Scalar::zero(); n]; n];
+        #[allow(clippy::needless_range_loop)]
+        for i in 0..n {
+            for j in i..n {
+                let e_ij = rnd_scalar();
+                e_keys[i][j] = e_ij; e_keys[j][i] = e_ij;
+            }
+        }
+
+        // peer-0 may vote with a non-zero secret
+        let cheat = Arc::new(Mutex::new(false));
+        let q_cheat = cheat.clone();
+        let q_pkeys = pkeys.clone();
+        let query = move |peer: &Peer, req: Request| -> Result<Response> {
+            let i: usize = peer.host["peer-".len()..].parse().unwrap();
+            let req = match req {
+                Request::Negotiate(Negotiate::NMasterKeyRefresh(req)) => req,
+                _ => return Err(Error::new(ErrorKind::Other, "Unexpected request!"))
+            };
+
+            let secret = if i == 0 && *q_cheat.lock().unwrap() { rnd_scalar() } else { Scalar::zero() };
+            let poly = Polynomial::rnd(secret, t);
+            let p_keys: Vec<RistrettoPoint> = e_keys[i].iter().map(|e_ij| e_ij * G).collect();
+            let e_shares: Vec<Share> = poly.shares(n).0.iter().zip(e_keys[i].iter()).map(|(y_j, e_ij)| y_j + e_ij).collect();
+
+            let vote = MasterKeyVote::sign(req.sig.id(), &req.kid, &req.peers, e_shares, p_keys, &poly * &G, &secrets[i], &q_pkeys[i], i);
+            Ok(Response::Vote(Vote::VMasterKeyVote(vote)))
+        };
+
+        let committed = Arc::new(Mutex::new(Vec::<Commit>::new()));
+        let c_committed = committed.clone();
+        let commit = move |_: &Peer, msg: Commit| -> Result<i64> {
+            let mut committed = c_committed.lock().unwrap();
+            committed.push(msg);
+            Ok(committed.len() as i64)
+        };
+
+        let peers = pkeys.iter().enumerate().map(|(i, pkey)| Peer { host: format!("peer-{}", i), pkey: *pkey }).collect();
+        let config = Config { network: None, log: LevelFilter::Off, audit: false, derived_keys: false, height_tolerance: 0, max_retries: 3,
+            threshold: t, peers, peers_hash: peers_hash(&pkeys), peers_keys: pkeys.clone() };
+        let mut sm = SubjectManager::new(&home, "s-id:shumy", "passphrase", config, commit, query).unwrap();
+
+        let mut subject = Subject::new("s-id:shumy");
+        let (secret, skey) = subject.evolve(rnd_scalar());
+        subject.keys.push(skey);
+        sm.sto = Some(MySubject { secret, profile_secrets: HashMap::new(), subject, auths: Authorizations::new(), keys: Vec::new() });
+
+        // the refresh evidence is committed as the peers will verify it
+        sm.refresh("kid").unwrap();
+        {
+            let committed = committed.lock().unwrap();
+            match &committed[..] {
+                [Commit::Evidence(Evidence::EMasterKeyRefresh(mk))] => {
+                    assert!(mk.kid == "kid" && mk.votes.len() == n);
+                    assert!(mk.check_zero() == Ok(()));
+                    assert!(mk.check(&peers_hash(&pkeys), &pkeys, t, None) == Ok(()));
+                },
+                _ => panic!("Expecting a single MasterKeyRefresh!")
+            }
+        }
+
+        // votes of a non-zero secret are never committed
+        *cheat.lock().unwrap() = true;
+        assert!(sm.refresh("kid").unwrap_err().to_string() == "Refresh votes don't share a zero secret!");
+        assert!(committed.lock().unwrap().len() == 1);
+
+        drop(sm);
+        std::fs::remove_dir_all(&home).ok();
+    }
 }
\ No newline at end of file
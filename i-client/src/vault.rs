@@ -0,0 +1,129 @@
+use std::io::{Result, Error, ErrorKind};
+
+use argon2::Argon2;
+use chacha20poly1305::{XChaCha20Poly1305, Key, XNonce};
+use chacha20poly1305::aead::{Aead, KeyInit};
+use clear_on_drop::clear::Clear;
+
+const SALT_SIZE: usize = 16;
+const NONCE_SIZE: usize = 24;
+const FORMAT: &[u8] = b"fpi:vlt1";                 // marker of the sealed format, files without it are from the plaintext store
+
+//-----------------------------------------------------------------------------------------------------------
+// Vault (passphrase-based encryption of the stored secret material)
+//-----------------------------------------------------------------------------------------------------------
+// sealed format: <marker><salt><nonce><ciphertext + tag>, with a fresh salt and nonce for every write
+pub struct Vault {
+    passphrase: Vec<u8>
+}
+
+impl Drop for Vault {
+    fn drop(&mut self) {
+        self.passphrase.as_mut_slice().clear();
+    }
+}
+
+impl Vault {
+    pub fn new(passphrase: &str) -> Self {
+        Self { passphrase: passphrase.as_bytes().to_vec() }
+    }
+
+    pub fn seal(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let salt: [u8; SALT_SIZE] = rand::random();
+        let nonce: [u8; NONCE_SIZE] = rand::random();
+
+        let cipher = self.cipher(&salt)?;
+        let encrypted = cipher.encrypt(XNonce::from_slice(&nonce), data)
+            .map_err(|_| Error::new(ErrorKind::Other, "Unable to encrypt the store!"))?;
+
+        let mut sealed = Vec::with_capacity(FORMAT.len() + SALT_SIZE + NONCE_SIZE + encrypted.len());
+        sealed.extend_from_slice(FORMAT);
+        sealed.extend_from_slice(&salt);
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(&encrypted);
+
+        Ok(sealed)
+    }
+
+    pub fn open(&self, sealed: &[u8]) -> Result<Vec<u8>> {
+        if !Self::is_sealed(sealed) {
+            return Err(Error::new(ErrorKind::InvalidData, "Unsealed store, expecting the vault format!"))
+        }
+
+        let sealed = &sealed[FORMAT.len()..];
+        if sealed.len() < SALT_SIZE + NONCE_SIZE {
+            return Err(Error::new(ErrorKind::InvalidData, "Corrupted store, not enough data!"))
+        }
+
+        let (salt, rest) = sealed.split_at(SALT_SIZE);
+        let (nonce, encrypted) = rest.split_at(NONCE_SIZE);
+
+        // the AEAD tag fails for a wrong passphrase or tampered data, these can't be distinguished
+        let cipher = self.cipher(salt)?;
+        cipher.decrypt(XNonce::from_slice(nonce), encrypted)
+            .map_err(|_| Error::new(ErrorKind::InvalidData, "Unable to decrypt the store, incorrect passphrase?"))
+    }
+
+    // data written before the vault has no marker, it's plaintext that should be sealed
+    pub fn is_sealed(data: &[u8]) -> bool {
+        data.starts_with(FORMAT)
+    }
+
+    fn cipher(&self, salt: &[u8]) -> Result<XChaCha20Poly1305> {
+        let mut key = [0u8; 32];
+        Argon2::default().hash_password_into(&self.passphrase, salt, &mut key)
+            .map_err(|e| Error::new(ErrorKind::Other, format!("Unable to derive the store key - {}", e)))?;
+
+        let cipher = XChaCha20Poly1305::new(Key::from_slice(&key));
+        key.clear();
+
+        Ok(cipher)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seal_open() {
+        let vault = Vault::new("passphrase");
+        let data = b"secret material".to_vec();
+
+        let sealed = vault.seal(&data).unwrap();
+        assert!(Vault::is_sealed(&sealed));
+        assert!(vault.open(&sealed).unwrap() == data);
+
+        // a fresh salt and nonce for every write
+        let other = vault.seal(&data).unwrap();
+        assert!(other != sealed);
+        assert!(vault.open(&other).unwrap() == data);
+
+        // the empty data is also sealed
+        let empty = vault.seal(&[]).unwrap();
+        assert!(vault.open(&empty).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_open_errors() {
+        let vault = Vault::new("passphrase");
+        let sealed = vault.seal(b"secret material").unwrap();
+
+        let res = Vault::new("wrong").open(&sealed);
+        assert!(res.err().unwrap().to_string() == "Unable to decrypt the store, incorrect passphrase?");
+
+        let mut tampered = sealed.clone();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 1;
+        let res = vault.open(&tampered);
+        assert!(res.err().unwrap().to_string() == "Unable to decrypt the store, incorrect passphrase?");
+
+        let res = vault.open(&sealed[..FORMAT.len() + SALT_SIZE]);
+        assert!(res.err().unwrap().to_string() == "Corrupted store, not enough data!");
+
+        // plaintext from the previous store format
+        assert!(!Vault::is_sealed(b"secret material"));
+        let res = vault.open(b"secret material");
+        assert!(res.err().unwrap().to_string() == "Unsealed store, expecting the vault format!");
+    }
+}
\ No newline at end of file